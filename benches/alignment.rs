@@ -0,0 +1,159 @@
+//! Micro-benchmarks of the three pair HMM functions that realign the reads to the haplotypes: the
+//! forward algorithm (```forward```, the default), the numerically-stable forward algorithm in log
+//! space (```forward-logspace```, -S) and the max-scoring alignment (```viterbi```, -x). Each
+//! function is measured across read window lengths, across band widths and on windows made of
+//! homopolymer runs, with reads simulated from the windows by the pair HMM of
+//! ```longshot simulate```.
+//!
+//! These benchmarks only build with the ```benchmarks``` feature:
+//! ```cargo bench --features benchmarks```
+//!
+//! Criterion keeps the results of the last run in ```target/criterion``` and reports the change of
+//! each benchmark against them, so running the benchmarks before and after a change to the
+//! alignment code measures its effect.
+
+#[macro_use]
+extern crate criterion;
+extern crate longshot;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use longshot::{
+    forward_algorithm_non_numerically_stable, forward_algorithm_numerically_stable,
+    simulate_read_window, viterbi_max_scoring_alignment, AlignmentParameters, EmissionProbs,
+    LnAlignmentParameters, TransitionProbs,
+};
+
+/// the lengths of the haplotype windows, aligned with the default band width
+static WINDOW_LENGTHS: [usize; 3] = [100, 500, 2000];
+/// the minimum band widths (-B), for windows of ```BAND_WIDTH_WINDOW_LENGTH``` bases
+static BAND_WIDTHS: [usize; 4] = [10, 20, 50, 100];
+static BAND_WIDTH_WINDOW_LENGTH: usize = 500;
+/// the default minimum band width (-B)
+static DEFAULT_BAND_WIDTH: usize = 20;
+/// the random seed of the simulated windows, so that every run measures the same alignments
+static SEED: usize = 0;
+
+/// a haplotype window, a read simulated from it, and the alignment parameters for each base of
+/// the window
+struct Fixture {
+    read: Vec<u8>,
+    window: Vec<u8>,
+    params: Vec<AlignmentParameters>,
+    ln_params: Vec<LnAlignmentParameters>,
+}
+
+impl Fixture {
+    fn new(len: usize, homopolymers: bool) -> Fixture {
+        // an error model like that of PacBio CLR reads, where insertions are the most common error
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.879,
+                insertion_from_match: 0.1,
+                deletion_from_match: 0.021,
+                insertion_from_insertion: 0.4,
+                match_from_insertion: 0.6,
+                deletion_from_deletion: 0.25,
+                match_from_deletion: 0.75,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let (read, window) = simulate_read_window(len, &params, homopolymers, SEED);
+        Fixture {
+            params: vec![params; window.len()],
+            ln_params: vec![params.ln(); window.len()],
+            read: read,
+            window: window,
+        }
+    }
+}
+
+fn forward(f: &Fixture, band_width: usize) {
+    black_box(forward_algorithm_non_numerically_stable(
+        &f.read, &f.window, &f.params, band_width,
+    ));
+}
+
+fn forward_logspace(f: &Fixture, band_width: usize) {
+    black_box(forward_algorithm_numerically_stable(
+        &f.read,
+        &f.window,
+        &f.ln_params,
+        band_width,
+    ));
+}
+
+fn viterbi(f: &Fixture, band_width: usize) {
+    black_box(viterbi_max_scoring_alignment(
+        &f.read,
+        &f.window,
+        &f.ln_params,
+        band_width,
+    ));
+}
+
+/// the benchmarked alignment functions, named like their ```--realignment_model```
+static ALIGNMENT_FUNCTIONS: [(&str, fn(&Fixture, usize)); 3] = [
+    ("forward", forward),
+    ("forward-logspace", forward_logspace),
+    ("viterbi", viterbi),
+];
+
+/// benchmarks each alignment function on windows of each length in ```WINDOW_LENGTHS```, with
+/// random or homopolymer-rich windows
+fn bench_window_lengths(c: &mut Criterion, homopolymers: bool) {
+    let fixtures: Vec<Fixture> = WINDOW_LENGTHS
+        .iter()
+        .map(|&len| Fixture::new(len, homopolymers))
+        .collect();
+    let dimension = if homopolymers {
+        "homopolymer_window_length"
+    } else {
+        "window_length"
+    };
+    for &(name, align) in ALIGNMENT_FUNCTIONS.iter() {
+        let mut group = c.benchmark_group(format!("{}/{}", name, dimension));
+        for (len, fixture) in WINDOW_LENGTHS.iter().zip(fixtures.iter()) {
+            group.bench_with_input(BenchmarkId::from_parameter(len), fixture, |b, f| {
+                b.iter(|| align(f, DEFAULT_BAND_WIDTH))
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_random_windows(c: &mut Criterion) {
+    bench_window_lengths(c, false);
+}
+
+fn bench_homopolymer_windows(c: &mut Criterion) {
+    bench_window_lengths(c, true);
+}
+
+/// benchmarks each alignment function with each band width in ```BAND_WIDTHS```
+fn bench_band_widths(c: &mut Criterion) {
+    let fixture = Fixture::new(BAND_WIDTH_WINDOW_LENGTH, false);
+    for &(name, align) in ALIGNMENT_FUNCTIONS.iter() {
+        let mut group = c.benchmark_group(format!("{}/band_width", name));
+        for &band_width in BAND_WIDTHS.iter() {
+            group.bench_with_input(
+                BenchmarkId::from_parameter(band_width),
+                &band_width,
+                |b, &band_width| b.iter(|| align(&fixture, band_width)),
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_random_windows,
+    bench_homopolymer_windows,
+    bench_band_widths
+);
+criterion_main!(benches);
@@ -0,0 +1,442 @@
+//! This module contains the targeted ultra-deep mode, which reports alternate allele fractions
+//! instead of diploid genotypes.
+//!
+//! It is intended for panels sequenced to very high depth (1000x or more) where the goal is to
+//! find low-frequency variants. There is no maximum coverage cutoff, and sites are processed one
+//! at a time directly from the BAM pileup: each site that passes the allele count and fraction
+//! cutoffs is written to the output VCF immediately, so memory use does not grow with the
+//! number of sites or the read depth.
+//!
+//! For every alternate allele we report the exact allele fraction with a 95% Wilson score
+//! confidence interval, and a QUAL value that is the PHRED-scaled probability of observing at
+//! least that many alternate bases from sequencing errors alone (binomial upper tail, using the
+//! estimated substitution rate).
+
+use bio::stats::{LogProb, PHREDProb};
+use errors::*;
+use rust_htslib::bam;
+use rust_htslib::bam::pileup::Indel;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use util::*;
+
+/// z-score for a two-sided 95% confidence interval
+static Z_95: f64 = 1.959964;
+
+/// Calculates the Wilson score confidence interval for a binomial proportion
+///
+/// # Arguments
+/// - ```successes```: number of alternate allele observations
+/// - ```trials```: total number of observations (read depth)
+/// - ```z```: z-score for the desired confidence level (1.96 for 95%)
+///
+/// # Returns
+/// Returns a tuple ```(lower, upper)``` holding the bounds of the interval.
+/// If there are no trials the interval is ```(0.0, 1.0)```.
+pub fn wilson_score_interval(successes: usize, trials: usize, z: f64) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = z * z;
+    let denom = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denom;
+    let half_width = (z / denom) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+
+    let lower = (center - half_width).max(0.0);
+    let upper = (center + half_width).min(1.0);
+    (lower, upper)
+}
+
+/// Calculates the probability of observing ```k``` or more successes out of ```n``` trials with
+/// success probability ```p```, in log space.
+pub fn binomial_upper_tail(k: usize, n: usize, p: f64) -> LogProb {
+    if k == 0 {
+        return LogProb::ln_one();
+    }
+    if k > n || p <= 0.0 {
+        return LogProb::ln_zero();
+    }
+
+    let ln_p = p.ln();
+    let ln_q = (1.0 - p).ln();
+
+    // ln(n choose k), computed incrementally as a sum of logs
+    let mut ln_choose: f64 = 0.0;
+    for i in 0..k {
+        ln_choose += ((n - i) as f64).ln() - ((i + 1) as f64).ln();
+    }
+
+    let mut terms: Vec<LogProb> = Vec::with_capacity(n - k + 1);
+    for i in k..(n + 1) {
+        terms.push(LogProb(
+            ln_choose + (i as f64) * ln_p + ((n - i) as f64) * ln_q,
+        ));
+        // update ln(n choose i) to ln(n choose i+1)
+        if i < n {
+            ln_choose += ((n - i) as f64).ln() - ((i + 1) as f64).ln();
+        }
+    }
+
+    LogProb::ln_sum_exp(&terms)
+}
+
+/// Calculates ln(Gamma(x)) for ```x > 0``` with the Lanczos approximation (g = 7, n = 9)
+fn ln_gamma(x: f64) -> f64 {
+    static LANCZOS: [f64; 9] = [
+        0.9999999999998099,
+        676.5203681218851,
+        -1259.1392167224028,
+        771.3234287776531,
+        -176.6150291621406,
+        12.507343278686905,
+        -0.13857109526572012,
+        9.984369578019572e-6,
+        1.5056327351493116e-7,
+    ];
+    if x < 0.5 {
+        // reflection formula
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let mut sum = LANCZOS[0];
+    for (i, &c) in LANCZOS.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+// the continued fraction of the incomplete beta function, evaluated with the modified Lentz
+// method (Numerical Recipes, section 6.4)
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    let tiny = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < tiny {
+        d = tiny;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..10000 {
+        let m = m as f64;
+        // the even and odd steps of the recurrence
+        for &aa in &[
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + aa * d;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Calculates the regularized incomplete beta function I_x(a, b), the cumulative distribution
+/// function of the Beta(a, b) distribution at ```x```
+pub fn regularized_incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let ln_front =
+        ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln();
+    // the continued fraction converges quickly on this side of the mean, and the symmetry
+    // I_x(a, b) = 1 - I_(1-x)(b, a) is used on the other side
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - ln_front.exp() * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Calculates the equal-tailed credible interval of a Beta(a, b) distribution holding ```mass```
+/// of the probability (e.g. 0.95), by bisection on its cumulative distribution function.
+///
+/// # Returns
+/// Returns a tuple ```(lower, upper)``` holding the bounds of the interval
+pub fn beta_credible_interval(a: f64, b: f64, mass: f64) -> (f64, f64) {
+    let quantile = |q: f64| {
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..50 {
+            let mid = (lo + hi) / 2.0;
+            if regularized_incomplete_beta(a, b, mid) < q {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo + hi) / 2.0
+    };
+    let tail = (1.0 - mass) / 2.0;
+    (quantile(tail), quantile(1.0 - tail))
+}
+
+fn print_allele_fraction_vcf_header<W: Write>(
+    mut output_file: W,
+    filename: &std::path::Display,
+    sample_name: &str,
+    fasta_file: &FastaReader,
+) -> Result<()> {
+    writeln!(output_file, "##fileformat=VCFv4.2\n##source=Longshot v0.4.2")
+        .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
+
+    for sequence in fasta_file.index.sequences().iter() {
+        writeln!(
+            output_file,
+            "##contig=<ID={},length={}>",
+            sequence.name, sequence.len
+        )
+        .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
+    }
+
+    let headerstr = format!("##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth of reads passing MAPQ filter\">
+##INFO=<ID=AD,Number=R,Type=Integer,Description=\"Number of Observations of Each Allele\">
+##INFO=<ID=AF,Number=A,Type=Float,Description=\"Alternate Allele Fraction\">
+##INFO=<ID=AFLO,Number=A,Type=Float,Description=\"Lower bound of 95% Wilson score confidence interval for the Alternate Allele Fraction\">
+##INFO=<ID=AFHI,Number=A,Type=Float,Description=\"Upper bound of 95% Wilson score confidence interval for the Alternate Allele Fraction\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype (not called in ultra-deep mode)\">
+##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Number of Observations of Each Allele\">
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read depth\">
+##FORMAT=<ID=AF,Number=A,Type=Float,Description=\"Alternate Allele Fraction\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", sample_name);
+    writeln!(output_file, "{}", headerstr)
+        .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
+    Ok(())
+}
+
+/// Streams over the BAM pileup and writes the alternate allele fractions of every site with
+/// evidence for an alternate base to a VCF file
+///
+/// # Arguments
+/// -```bam_file```: the input BAM file name
+/// -```fasta_file```: the input FASTA file name
+/// -```interval```: optional genomic interval to report sites in
+/// -```output_vcf_file```: the output VCF file name
+/// -```sample_name```: the sample name for the VCF header
+/// -```min_mapq```: the minimum mapping quality to use a read
+/// -```min_alt_count```: the minimum number of observations of an alternate allele to report it
+/// -```min_alt_frac```: the minimum fraction of observations of an alternate allele to report it
+/// -```p_substitution```: the probability that a sequencing error produces a specific wrong base
+///                        (the estimated ```not_equal``` emission probability)
+/// -```depth_definition```: which reads overlapping a site count toward its depth, which is the
+///                          denominator of the allele fractions
+///
+/// # Returns
+/// Returns the number of sites written to the VCF.
+///
+/// # Errors
+/// - ```IndexedFastaOpenError```, ```IndexedFastaReadError```: errors reading the FASTA file
+/// - ```IndexedBamOpenError```, ```IndexedBamFetchError```, ```IndexedBamPileupReadError```,
+///   ```IndexedBamPileupQueryPositionError```: errors reading the BAM pileup
+/// - ```CreateFileError```, ```FileWriteError```: errors writing the output VCF
+pub fn call_allele_fractions(
+    bam_file: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    output_vcf_file: &String,
+    sample_name: &String,
+    min_mapq: u8,
+    min_alt_count: usize,
+    min_alt_frac: f64,
+    p_substitution: f64,
+    depth_definition: DepthDefinition,
+) -> Result<usize> {
+    let target_names = parse_target_names(&bam_file)?;
+    let bases = ['A', 'C', 'G', 'T'];
+
+    let fasta = open_fasta(fasta_file)?;
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+
+    let vcf_path = Path::new(output_vcf_file);
+    let vcf_display = vcf_path.display();
+    let mut file = File::create(&vcf_path)
+        .chain_err(|| ErrorKind::CreateFileError(vcf_display.to_string()))?;
+    print_allele_fraction_vcf_header(&mut file, &vcf_display, sample_name, &fasta)?;
+
+    let mut num_sites = 0;
+
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
+    let mut bam_ix =
+        open_indexed_bam(bam_file)?;
+
+    for iv in interval_lst {
+        bam_ix
+            .fetch(iv.tid as u32, iv.start_pos as u32, iv.end_pos as u32 + 1)
+            .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+
+        // htslib caps pileup depth at 8000 reads by default, so we lift the cap for deep panels
+        let mut bam_pileup = bam_ix.pileup();
+        bam_pileup.set_max_depth(i32::max_value() as u32);
+
+        for p in bam_pileup {
+            let pileup = p.chain_err(|| ErrorKind::IndexedBamPileupReadError)?;
+
+            if pileup.tid() != iv.tid || pileup.pos() < iv.start_pos || pileup.pos() > iv.end_pos
+            {
+                continue;
+            }
+
+            let pos: usize = pileup.pos() as usize;
+            let ref_base = ref_reader.window(&target_names[iv.tid as usize], pos, pos + 1)?[pos];
+            let ref_ix = match bases.iter().position(|&b| b == ref_base) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let mut counts = [0 as usize; 4];
+            let mut depth: usize = 0;
+
+            for alignment in pileup.alignments() {
+                let record = alignment.record();
+                if record.is_secondary()
+                    || record.is_quality_check_failed()
+                    || record.is_duplicate()
+                    || record.is_supplementary()
+                    || record.is_unmapped()
+                    || record.mapq() < min_mapq
+                {
+                    continue;
+                }
+
+                if depth_definition.counts(&alignment) {
+                    depth += 1;
+                }
+
+                if !alignment.is_del() && !alignment.is_refskip() {
+                    if let Indel::None = alignment.indel() {
+                        let qpos = alignment
+                            .qpos()
+                            .chain_err(|| ErrorKind::IndexedBamPileupQueryPositionError)?;
+                        match record.seq()[qpos] as char {
+                            'A' | 'a' => counts[0] += 1,
+                            'C' | 'c' => counts[1] += 1,
+                            'G' | 'g' => counts[2] += 1,
+                            'T' | 't' => counts[3] += 1,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            if depth == 0 {
+                continue;
+            }
+
+            // alternate alleles passing the cutoffs, in order of decreasing count
+            let mut alt_ixs: Vec<usize> = (0..4)
+                .filter(|&i| {
+                    i != ref_ix
+                        && counts[i] >= min_alt_count
+                        && counts[i] as f64 / depth as f64 >= min_alt_frac
+                })
+                .collect();
+            if alt_ixs.len() == 0 {
+                continue;
+            }
+            alt_ixs.sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+
+            // QUAL is based on the best supported alternate allele
+            let p_err = binomial_upper_tail(counts[alt_ixs[0]], depth, p_substitution);
+            let qual = (*PHREDProb::from(p_err)).min(MAX_VCF_QUAL);
+
+            let mut alt_strs: Vec<String> = vec![];
+            let mut ad_strs: Vec<String> = vec![counts[ref_ix].to_string()];
+            let mut af_strs: Vec<String> = vec![];
+            let mut aflo_strs: Vec<String> = vec![];
+            let mut afhi_strs: Vec<String> = vec![];
+            for &a in &alt_ixs {
+                let (lo, hi) = wilson_score_interval(counts[a], depth, Z_95);
+                alt_strs.push(bases[a].to_string());
+                ad_strs.push(counts[a].to_string());
+                af_strs.push(format!("{:.4}", counts[a] as f64 / depth as f64));
+                aflo_strs.push(format!("{:.4}", lo));
+                afhi_strs.push(format!("{:.4}", hi));
+            }
+
+            writeln!(
+                file,
+                "{}\t{}\t.\t{}\t{}\t{:.0}\tPASS\tDP={};AD={};AF={};AFLO={};AFHI={}\tGT:AD:DP:AF\t./.:{}:{}:{}",
+                target_names[tid],
+                pos + 1,
+                ref_base,
+                alt_strs.join(","),
+                qual + 0.4999, // round off to integer
+                depth,
+                ad_strs.join(","),
+                af_strs.join(","),
+                aflo_strs.join(","),
+                afhi_strs.join(","),
+                ad_strs.join(","),
+                depth,
+                af_strs.join(",")
+            )
+            .chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
+
+            num_sites += 1;
+        }
+    }
+
+    Ok(num_sites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::stats::Prob;
+
+    #[test]
+    fn test_wilson_score_interval() {
+        let (lo, hi) = wilson_score_interval(10, 1000, Z_95);
+        assert!((lo - 0.00544).abs() < 0.00001);
+        assert!((hi - 0.01831).abs() < 0.00001);
+
+        assert_eq!(wilson_score_interval(0, 0, Z_95), (0.0, 1.0));
+        let (lo, hi) = wilson_score_interval(0, 100, Z_95);
+        assert!(lo.abs() < 1e-12);
+        assert!((hi - 0.03699).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_binomial_upper_tail() {
+        // P(X >= 1) for X ~ Binomial(2, 0.5) is 0.75
+        let p = *Prob::from(binomial_upper_tail(1, 2, 0.5));
+        assert!((p - 0.75).abs() < 1e-10);
+        // P(X >= 3) for X ~ Binomial(3, 0.1) is 0.001
+        let p = *Prob::from(binomial_upper_tail(3, 3, 0.1));
+        assert!((p - 0.001).abs() < 1e-10);
+        assert_eq!(binomial_upper_tail(0, 10, 0.1), LogProb::ln_one());
+    }
+
+    #[test]
+    fn test_beta_credible_interval() {
+        assert!((regularized_incomplete_beta(20.0, 20.0, 0.5) - 0.5).abs() < 1e-10);
+        // the CDF of Beta(2, 1) is x^2
+        assert!((regularized_incomplete_beta(2.0, 1.0, 0.3) - 0.09).abs() < 1e-10);
+
+        let (lo, hi) = beta_credible_interval(1.0, 1.0, 0.95);
+        assert!((lo - 0.025).abs() < 1e-8);
+        assert!((hi - 0.975).abs() < 1e-8);
+        let (lo, hi) = beta_credible_interval(2.0, 1.0, 0.95);
+        assert!((lo - 0.025f64.sqrt()).abs() < 1e-8);
+        assert!((hi - 0.975f64.sqrt()).abs() < 1e-8);
+        // deep coverage: the interval is narrow around the allele fraction
+        let (lo, hi) = beta_credible_interval(51.0, 951.0, 0.95);
+        assert!(lo < 0.05 && hi > 0.05 && hi - lo < 0.03);
+    }
+}
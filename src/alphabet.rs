@@ -0,0 +1,225 @@
+//! The alphabet of the bases compared by the pair-HMM (```--alphabet```).
+//!
+//! With the default DNA alphabet, the bases are A, C, G and T, and any other base is an N, which
+//! only matches an N: the IUPAC ambiguity codes of the reference and of the reads are read as N.
+//! With the IUPAC alphabet, the ambiguity codes are kept, e.g. for the degenerate primers of an
+//! amplicon reference, U is read as T (RNA references), and a read base is scored against an
+//! ambiguous haplotype base with the mean emission probability over the bases that the code stands
+//! for. An ambiguous read base is scored as the sum over the bases it stands for, so that a read N
+//! doesn't favour any haplotype.
+//!
+//! The alphabet is set once for the run, like the counters of ```telemetry```, so that the
+//! alignment functions don't need to pass it around.
+
+use bio::stats::{LogProb, Prob};
+use realignment::WILDCARD_BASE;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use util::IUPAC_AMBIGUITY_CODES;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alphabet {
+    Dna,
+    Iupac,
+}
+
+pub static ALPHABET_NAMES: [&str; 2] = ["dna", "iupac"];
+
+static CURRENT_ALPHABET: AtomicUsize = AtomicUsize::new(0);
+
+// the codes of the bases in the alignment functions: A, C, G and T have the codes 0 to 3 of a
+// 2-bit encoding, then come N, WILDCARD_BASE and the ambiguity codes of IUPAC_AMBIGUITY_CODES
+pub const CODE_N: u8 = 4;
+pub const CODE_WILDCARD: u8 = 5;
+const CODE_FIRST_AMBIGUITY: u8 = 6;
+
+// the bases that each code stands for, as a bit mask of A (1), C (2), G (4) and T (8)
+const CODE_MASKS: [u8; 16] = [1, 2, 4, 8, 15, 0, 5, 10, 6, 9, 12, 3, 14, 13, 11, 7];
+
+/// How a read base is scored against a haplotype base
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BaseMatch {
+    /// with the emission probability of equal bases
+    Equal,
+    /// with the emission probability of different bases
+    NotEqual,
+    /// with this many times the probability of equal bases plus this many times the probability
+    /// of different bases, for an ambiguous base
+    Partial(f64, f64),
+}
+
+impl BaseMatch {
+    #[inline]
+    pub fn emission(&self, equal: f64, not_equal: f64) -> f64 {
+        match *self {
+            BaseMatch::Equal => equal,
+            BaseMatch::NotEqual => not_equal,
+            BaseMatch::Partial(a, b) => a * equal + b * not_equal,
+        }
+    }
+
+    #[inline]
+    pub fn ln_emission(&self, equal: LogProb, not_equal: LogProb) -> LogProb {
+        match *self {
+            BaseMatch::Equal => equal,
+            BaseMatch::NotEqual => not_equal,
+            BaseMatch::Partial(a, b) => {
+                LogProb::from(Prob(a * (*equal).exp() + b * (*not_equal).exp()))
+            }
+        }
+    }
+
+    /// Whether the bases can be equal, i.e. are not an edit of the edit distance
+    pub fn is_compatible(&self) -> bool {
+        match *self {
+            BaseMatch::Equal => true,
+            BaseMatch::NotEqual => false,
+            BaseMatch::Partial(a, _) => a > 0.0,
+        }
+    }
+}
+
+impl Alphabet {
+    pub fn from_name(name: &str) -> Option<Alphabet> {
+        match name {
+            "dna" => Some(Alphabet::Dna),
+            "iupac" => Some(Alphabet::Iupac),
+            _ => None,
+        }
+    }
+
+    /// The alphabet of the run
+    pub fn current() -> Alphabet {
+        match CURRENT_ALPHABET.load(Ordering::Relaxed) {
+            1 => Alphabet::Iupac,
+            _ => Alphabet::Dna,
+        }
+    }
+
+    /// Sets the alphabet of the run, before any sequence is read
+    pub fn set_current(&self) {
+        let ix = match *self {
+            Alphabet::Dna => 0,
+            Alphabet::Iupac => 1,
+        };
+        CURRENT_ALPHABET.store(ix, Ordering::Relaxed);
+    }
+
+    /// Returns the upper case base of a reference or read sequence in this alphabet, or None if
+    /// it isn't a base of it. Ambiguity codes are read as N in the DNA alphabet.
+    pub fn normalize(&self, base: char) -> Option<char> {
+        let c = base.to_ascii_uppercase();
+        match c {
+            'A' | 'C' | 'G' | 'T' | 'N' => Some(c),
+            'U' if *self == Alphabet::Iupac => Some('T'),
+            _ if IUPAC_AMBIGUITY_CODES.contains(&c) => match *self {
+                Alphabet::Dna => Some('N'),
+                Alphabet::Iupac => Some(c),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the 4-bit code of a base in the alignment functions (in upper or lower case), with
+    /// a code of its own for ```WILDCARD_BASE```. Any other byte is an N.
+    #[inline]
+    pub fn code(&self, base: u8) -> u8 {
+        match base {
+            b'A' | b'a' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' => 3,
+            b'U' | b'u' if *self == Alphabet::Iupac => 3,
+            WILDCARD_BASE => CODE_WILDCARD,
+            _ if *self == Alphabet::Iupac => IUPAC_AMBIGUITY_CODES
+                .iter()
+                .position(|&c| c == (base as char).to_ascii_uppercase())
+                .map_or(CODE_N, |ix| CODE_FIRST_AMBIGUITY + ix as u8),
+            _ => CODE_N,
+        }
+    }
+
+    /// How the read base with the code ```read_code``` is scored when it is aligned to the
+    /// haplotype base with the code ```hap_code```. Every read base matches a wildcard.
+    #[inline]
+    pub fn compare(&self, read_code: u8, hap_code: u8) -> BaseMatch {
+        if hap_code == CODE_WILDCARD
+            || (read_code == hap_code && (read_code < CODE_N || *self == Alphabet::Dna))
+        {
+            return BaseMatch::Equal;
+        }
+        if *self == Alphabet::Dna
+            || (read_code < CODE_N && hap_code < CODE_N)
+            || read_code == CODE_WILDCARD
+        {
+            return BaseMatch::NotEqual;
+        }
+        // the haplotype base is each of the bases of its code with the same probability, and the
+        // read base is any of the bases of its code
+        let read_mask = CODE_MASKS[read_code as usize];
+        let hap_mask = CODE_MASKS[hap_code as usize];
+        let n_shared = (read_mask & hap_mask).count_ones() as f64;
+        let n_read = read_mask.count_ones() as f64;
+        let n_hap = hap_mask.count_ones() as f64;
+        BaseMatch::Partial(n_shared / n_hap, (n_read * n_hap - n_shared) / n_hap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alphabet_codes() {
+        let codes = |alphabet: Alphabet, seq: &[u8]| -> Vec<u8> {
+            seq.iter().map(|&b| alphabet.code(b)).collect()
+        };
+        assert_eq!(
+            codes(Alphabet::Dna, b"ACGTNa*RUy"),
+            vec![0, 1, 2, 3, CODE_N, 0, CODE_WILDCARD, CODE_N, CODE_N, CODE_N]
+        );
+        assert_eq!(
+            codes(Alphabet::Iupac, b"ACGTNa*RUyX"),
+            vec![0, 1, 2, 3, CODE_N, 0, CODE_WILDCARD, 6, 3, 7, CODE_N]
+        );
+        assert_eq!(Alphabet::Dna.normalize('r'), Some('N'));
+        assert_eq!(Alphabet::Iupac.normalize('r'), Some('R'));
+        assert_eq!(Alphabet::Iupac.normalize('u'), Some('T'));
+        assert_eq!(Alphabet::Dna.normalize('U'), None);
+        assert_eq!(Alphabet::Iupac.normalize('-'), None);
+    }
+
+    #[test]
+    fn test_alphabet_compare() {
+        let dna =
+            |r: u8, h: u8| Alphabet::Dna.compare(Alphabet::Dna.code(r), Alphabet::Dna.code(h));
+        let iupac = |r: u8, h: u8| {
+            Alphabet::Iupac.compare(Alphabet::Iupac.code(r), Alphabet::Iupac.code(h))
+        };
+
+        // a read base matches the same base and a wildcard, and an N only matches an N
+        assert_eq!(dna(b'A', b'*'), BaseMatch::Equal);
+        assert_eq!(dna(b'N', b'N'), BaseMatch::Equal);
+        assert_eq!(dna(b'A', b'N'), BaseMatch::NotEqual);
+        assert_eq!(dna(b'*', b'A'), BaseMatch::NotEqual);
+        assert_eq!(dna(b'A', b'R'), BaseMatch::NotEqual);
+
+        assert_eq!(iupac(b'A', b'a'), BaseMatch::Equal);
+        assert_eq!(iupac(b'T', b'U'), BaseMatch::Equal);
+        assert_eq!(iupac(b'A', b'C'), BaseMatch::NotEqual);
+        assert_eq!(iupac(b'A', b'*'), BaseMatch::Equal);
+        // R is A or G
+        assert_eq!(iupac(b'A', b'R'), BaseMatch::Partial(0.5, 0.5));
+        assert_eq!(iupac(b'C', b'R'), BaseMatch::Partial(0.0, 1.0));
+        // a read N is scored the same against every haplotype base
+        assert_eq!(iupac(b'N', b'A'), BaseMatch::Partial(1.0, 3.0));
+        assert_eq!(iupac(b'N', b'R'), BaseMatch::Partial(1.0, 3.0));
+        assert_eq!(iupac(b'A', b'N'), BaseMatch::Partial(0.25, 0.75));
+
+        assert!((iupac(b'A', b'R').emission(0.97, 0.01) - 0.49).abs() < 1e-12);
+        let ln = iupac(b'N', b'A').ln_emission(LogProb(0.97f64.ln()), LogProb(0.01f64.ln()));
+        assert!((*ln).abs() < 1e-12);
+        assert!(iupac(b'A', b'R').is_compatible());
+        assert!(!iupac(b'C', b'R').is_compatible());
+        assert!(!dna(b'A', b'N').is_compatible());
+    }
+}
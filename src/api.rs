@@ -0,0 +1,490 @@
+//! Library interface to the haplotype fragment extraction: the realignment-based assignment of
+//! the read alleles at a given set of variants, without running the rest of the caller. The
+//! Pair-HMM that it is built on is also available on its own, to score a read window against any
+//! list of candidate haplotypes (```haplotype_posteriors```).
+//!
+//! ```ignore
+//! extern crate longshot;
+//! use longshot::{extract_fragments, FragmentOptions, Variant};
+//!
+//! let variants = vec![Variant {
+//!     chrom: "chr20".to_string(),
+//!     pos0: 1000000,
+//!     alleles: vec!["A".to_string(), "G".to_string()],
+//! }];
+//! let options = FragmentOptions::default();
+//! let fragments = extract_fragments("reads.bam", "ref.fa", &variants, &options)?;
+//! for frag in fragments {
+//!     for a in frag.alleles {
+//!         println!("{}\t{}\t{}\t{:.1}", frag.read_id, a.variant_ix, a.allele, a.qual);
+//!     }
+//! }
+//! ```
+
+use bio::stats::{LogProb, PHREDProb, Prob};
+use errors::*;
+use estimate_alignment_parameters::{estimate_alignment_parameters, read_alignment_parameters};
+use extract_fragments::{
+    ExtractFragmentParameters, MissingQualPolicy, ReadFilter, RefAmbiguity,
+    DEFAULT_MISSING_BASE_QUAL,
+};
+use hashbrown::HashMap;
+use presets::{Preset, PRESET_NAMES};
+use realignment::{
+    check_window_lengths, forward_algorithm_base_quals, AlignmentParameters, AlignmentType,
+    LnAlignmentParameters, MAX_WINDOW_LEN,
+};
+use std::u32;
+use util::{get_interval_lst, parse_region_string, parse_target_names};
+use variants_and_fragments::{Var, VarList};
+
+/// A variant to assign the read alleles of. The first allele is the reference allele.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant {
+    pub chrom: String,
+    /// 0-based position of the first base of the alleles
+    pub pos0: usize,
+    pub alleles: Vec<String>,
+}
+
+/// The allele of a read at one variant
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allele {
+    /// index of the variant in the list passed to ```extract_fragments```
+    pub variant_ix: usize,
+    /// index of the allele in the alleles of the variant
+    pub allele: u8,
+    /// PHRED-scaled probability that the allele call is an error
+    pub qual: f64,
+    /// whether the call comes from a reverse strand alignment
+    pub reverse_strand: bool,
+    /// 0-based position of the variant in the read sequence as stored in the BAM, if known
+    pub read_pos: Option<u32>,
+}
+
+/// The alleles of one read, in the order of the variants
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub read_id: String,
+    pub mapq: u8,
+    pub reverse_strand: bool,
+    pub alleles: Vec<Allele>,
+}
+
+/// Options of ```extract_fragments```. The defaults are the defaults of the corresponding command
+/// line options of longshot.
+#[derive(Clone, Debug)]
+pub struct FragmentOptions {
+    /// only use the reads in this region (chr or chr:start-stop, 1-based), instead of the whole BAM
+    pub region: Option<String>,
+    /// minimum mapping quality of the reads (```--min_mapq```)
+    pub min_mapq: u8,
+    /// length of the exact-matching anchors of the realignment windows (```--anchor_length```)
+    pub anchor_length: usize,
+    /// maximum number of variants realigned together (```--max_snvs```)
+    pub variant_cluster_max_size: usize,
+    /// maximum number of haplotypes aligned to a read per cluster (```--cluster_work_budget```)
+    pub cluster_work_budget: usize,
+    /// maximum extension of the realignment windows (```--max_window```)
+    pub max_window_padding: usize,
+    /// minimum distance between a variant and its anchors (```--window_flank```)
+    pub window_flank: usize,
+    /// only end the realignment windows at exact, unique anchors (```--exact_anchors```)
+    pub exact_anchors: bool,
+    /// merge realignment windows less than this far apart (```--window_merge_distance```)
+    pub window_merge_distance: usize,
+    /// maximum CIGAR indel length within a realignment window (```--max_cigar_indel```)
+    pub max_cigar_indel: usize,
+    /// band width of the realignment (```--band_width```)
+    pub band_width: usize,
+    /// alignment parameters written by ```longshot train``` or ```--write-params```. If None, the
+    /// parameters are estimated from the reads, as longshot does by default.
+    pub params_file: Option<String>,
+}
+
+impl Default for FragmentOptions {
+    fn default() -> FragmentOptions {
+        FragmentOptions {
+            region: None,
+            min_mapq: 20,
+            anchor_length: 6,
+            variant_cluster_max_size: 3,
+            cluster_work_budget: 64,
+            max_window_padding: 50,
+            window_flank: 0,
+            exact_anchors: false,
+            window_merge_distance: 0,
+            max_cigar_indel: 20,
+            band_width: 20,
+            params_file: None,
+        }
+    }
+}
+
+/// Converts the variants to a ```VarList```. Returns it together with, for each variant of the
+/// (sorted) list, the index of the variant in ```variants```.
+fn variants_to_varlist(
+    variants: &[Variant],
+    target_names: Vec<String>,
+) -> Result<(VarList, Vec<usize>)> {
+    let mut tids: HashMap<&str, u32> = HashMap::new();
+    for (tid, name) in target_names.iter().enumerate() {
+        tids.insert(name.as_str(), tid as u32);
+    }
+
+    let mut input_ix: HashMap<(u32, usize), usize> = HashMap::new();
+    let mut vars: Vec<Var> = vec![];
+    for (i, v) in variants.iter().enumerate() {
+        let tid = match tids.get(v.chrom.as_str()) {
+            Some(&tid) => tid,
+            None => bail!(
+                "Variant {}:{} is on a contig that is not in the BAM header.",
+                v.chrom,
+                v.pos0 + 1
+            ),
+        };
+        ensure!(
+            v.alleles.len() >= 2,
+            "Variant {}:{} must have a reference allele and at least one alternative allele.",
+            v.chrom,
+            v.pos0 + 1
+        );
+        if input_ix.insert((tid, v.pos0), i).is_some() {
+            bail!(
+                "Multiple variants at {}:{}. Merge them into one multiallelic variant.",
+                v.chrom,
+                v.pos0 + 1
+            );
+        }
+        vars.push(Var::new(tid, v.pos0, v.alleles.clone()));
+    }
+
+    let varlist = VarList::new(vars, target_names)?;
+    let order: Vec<usize> = varlist
+        .lst
+        .iter()
+        .map(|var| input_ix[&(var.tid, var.pos0)])
+        .collect();
+    Ok((varlist, order))
+}
+
+/// Assigns the alleles of the reads in ```bam_file``` at ```variants```, realigning each read to
+/// the haplotypes of the variants near it, exactly as longshot does before genotyping. Returns one
+/// ```Fragment``` per read with at least one allele call.
+pub fn extract_fragments(
+    bam_file: &str,
+    fasta_file: &str,
+    variants: &[Variant],
+    options: &FragmentOptions,
+) -> Result<Vec<Fragment>> {
+    ensure!(
+        options.max_window_padding <= MAX_WINDOW_LEN && options.window_flank <= MAX_WINDOW_LEN,
+        "The window padding and flank must be at most {} bases.",
+        MAX_WINDOW_LEN
+    );
+    let bam_file = bam_file.to_string();
+    let fasta_file = fasta_file.to_string();
+
+    let target_names = parse_target_names(&bam_file)?;
+    let (mut varlist, order) = variants_to_varlist(variants, target_names)?;
+
+    let interval = parse_region_string(options.region.as_ref().map(|r| r.as_str()), &bam_file)?;
+    let (align_params, strand_params, context_params, read_group_params) = match options.params_file
+    {
+        Some(ref params_file) => read_alignment_parameters(params_file)?,
+        None => estimate_alignment_parameters(
+            &bam_file,
+            &fasta_file,
+            &get_interval_lst(&bam_file, &interval)?,
+            options.min_mapq,
+            options.max_cigar_indel as u32,
+            None,
+            false,
+            false,
+        )?,
+    };
+
+    let extract_params = ExtractFragmentParameters {
+        read_filter: ReadFilter::new(options.min_mapq),
+        alignment_type: AlignmentType::ForwardAlgorithmNonNumericallyStable,
+        band_width: options.band_width,
+        anchor_length: options.anchor_length,
+        variant_cluster_max_size: options.variant_cluster_max_size,
+        cluster_work_budget: options.cluster_work_budget,
+        max_window_padding: options.max_window_padding,
+        window_flank: options.window_flank,
+        exact_anchors: options.exact_anchors,
+        window_merge_distance: options.window_merge_distance,
+        max_cigar_indel: options.max_cigar_indel,
+        store_read_id: true,
+        min_alignment_score: None,
+        min_aligned_frac: 0.0,
+        max_soft_clip: None,
+        soft_clip_window: 10,
+        min_soft_clip_cluster: None,
+        missing_qual_policy: MissingQualPolicy::Keep(DEFAULT_MISSING_BASE_QUAL),
+        low_memory: false,
+        spill_fragments: false,
+        max_depth: None,
+        max_realign_depth: None,
+        alignment_cache_size: 100000,
+        stitch_split_reads: false,
+        partial_reads: false,
+        pileup_only: false,
+        prefilter_edits: None,
+        repeat_band: false,
+        ref_ambiguity: RefAmbiguity::Mismatch,
+        strict: false,
+    };
+
+    let (flist, _, _) = ::extract_fragments::extract_fragments(
+        &bam_file,
+        &fasta_file,
+        &mut varlist,
+        &interval,
+        extract_params,
+        align_params,
+        &context_params,
+        &vec![],
+        &strand_params,
+        &read_group_params,
+    )
+    .chain_err(|| "Error generating haplotype fragments from BAM reads.")?;
+
+    Ok(flist
+        .into_iter()
+        .filter(|frag| !frag.calls.is_empty())
+        .map(|frag| Fragment {
+            read_id: frag.id.unwrap_or_default(),
+            mapq: frag.mapq,
+            reverse_strand: frag.reverse_strand,
+            alleles: frag
+                .calls
+                .iter()
+                .map(|call| Allele {
+                    variant_ix: order[call.var_ix],
+                    allele: call.allele,
+                    qual: *PHREDProb::from(call.qual),
+                    reverse_strand: call.reverse_strand,
+                    read_pos: if call.read_pos == u32::MAX {
+                        None
+                    } else {
+                        Some(call.read_pos)
+                    },
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Options of ```haplotype_posteriors```
+#[derive(Clone, Debug)]
+pub struct HaplotypeOptions {
+    /// technology preset (```--preset```) whose typical alignment parameters are used, if
+    /// ```params_file``` is None
+    pub preset: String,
+    /// alignment parameters written by ```longshot train``` or ```--write-params```
+    pub params_file: Option<String>,
+    /// band width of the alignment (```--band_width```)
+    pub band_width: usize,
+    /// prior probabilities of the haplotypes, in the order of the haplotypes. If None, the
+    /// haplotypes are equally likely.
+    pub priors: Option<Vec<f64>>,
+}
+
+impl Default for HaplotypeOptions {
+    fn default() -> HaplotypeOptions {
+        HaplotypeOptions {
+            preset: "pacbio-clr".to_string(),
+            params_file: None,
+            band_width: 20,
+            priors: None,
+        }
+    }
+}
+
+/// Scores read windows against candidate haplotypes with the alignment parameters of a
+/// ```HaplotypeOptions```, which are only read once for all of the reads.
+pub struct HaplotypeScorer {
+    params: AlignmentParameters,
+    band_width: usize,
+    ln_priors: Option<Vec<LogProb>>,
+}
+
+impl HaplotypeScorer {
+    pub fn new(options: &HaplotypeOptions) -> Result<HaplotypeScorer> {
+        let params = match options.params_file {
+            Some(ref params_file) => read_alignment_parameters(params_file)?.0,
+            None => match Preset::from_name(&options.preset) {
+                Some(preset) => preset.alignment_parameters(),
+                None => bail!(
+                    "Unknown preset {}, expected one of {}.",
+                    options.preset,
+                    PRESET_NAMES.join(", ")
+                ),
+            },
+        };
+        let ln_priors = match options.priors {
+            Some(ref priors) => {
+                ensure!(
+                    priors.iter().all(|&p| p >= 0.0 && p <= 1.0),
+                    "The haplotype priors must be probabilities between 0 and 1."
+                );
+                Some(priors.iter().map(|&p| LogProb::from(Prob(p))).collect())
+            }
+            None => None,
+        };
+        Ok(HaplotypeScorer {
+            params: params,
+            band_width: options.band_width,
+            ln_priors: ln_priors,
+        })
+    }
+
+    /// Returns the posterior probability of each haplotype given the read window ```read```,
+    /// whose bases are miscalled with the probabilities of the PHRED-scaled base qualities
+    /// ```quals``` (as stored in the BAM, without the offset of 33). A base quality of 255 is a
+    /// missing quality, and the base is scored with the alignment parameters alone.
+    pub fn posteriors(
+        &self,
+        read: &[u8],
+        quals: &[u8],
+        haplotypes: &[Vec<u8>],
+    ) -> Result<Vec<f64>> {
+        ensure!(!read.is_empty(), "The read window must not be empty.");
+        ensure!(
+            quals.len() == read.len(),
+            "The read window has {} bases but {} base qualities.",
+            read.len(),
+            quals.len()
+        );
+        ensure!(
+            !haplotypes.is_empty(),
+            "At least one candidate haplotype is needed."
+        );
+        ensure!(
+            haplotypes.iter().all(|hap| !hap.is_empty()),
+            "The candidate haplotypes must not be empty."
+        );
+        for hap in haplotypes {
+            check_window_lengths(read.len(), hap.len())?;
+        }
+        if let Some(ref ln_priors) = self.ln_priors {
+            ensure!(
+                ln_priors.len() == haplotypes.len(),
+                "There are {} haplotype priors for {} haplotypes.",
+                ln_priors.len(),
+                haplotypes.len()
+            );
+        }
+
+        let read: Vec<u8> = read.to_ascii_uppercase();
+        let base_errors: Vec<f64> = quals
+            .iter()
+            .map(|&q| match q {
+                255 => 0.0,
+                q => *Prob::from(PHREDProb(q as f64)),
+            })
+            .collect();
+
+        let ln_scores: Vec<LogProb> = haplotypes
+            .iter()
+            .enumerate()
+            .map(|(i, hap)| {
+                let hap = hap.to_ascii_uppercase();
+                let ln_params: Vec<LnAlignmentParameters> = vec![self.params.ln(); hap.len()];
+                let score = forward_algorithm_base_quals(
+                    &read,
+                    &base_errors,
+                    &hap,
+                    &ln_params,
+                    self.band_width,
+                );
+                match self.ln_priors {
+                    Some(ref ln_priors) => score + ln_priors[i],
+                    None => score,
+                }
+            })
+            .collect();
+
+        let total = LogProb::ln_sum_exp(&ln_scores);
+        ensure!(
+            total != LogProb::ln_zero(),
+            "The read window has a probability of zero under all of the haplotypes."
+        );
+        Ok(ln_scores.iter().map(|&s| (*s - *total).exp()).collect())
+    }
+}
+
+/// Returns the posterior probability of each of the candidate ```haplotypes``` given a read window
+/// and its base qualities, from the Pair-HMM likelihoods that longshot assigns read alleles with.
+/// This generalizes the scoring of the reference and alternative haplotypes of a variant to any
+/// number of haplotypes; use a ```HaplotypeScorer``` to score many reads with the same options.
+pub fn haplotype_posteriors(
+    read: &[u8],
+    quals: &[u8],
+    haplotypes: &[Vec<u8>],
+    options: &HaplotypeOptions,
+) -> Result<Vec<f64>> {
+    HaplotypeScorer::new(options)?.posteriors(read, quals, haplotypes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_to_varlist() {
+        let target_names = vec!["chr1".to_string(), "chr2".to_string()];
+        let var = |chrom: &str, pos0: usize| Variant {
+            chrom: chrom.to_string(),
+            pos0: pos0,
+            alleles: vec!["A".to_string(), "C".to_string()],
+        };
+
+        let variants = vec![var("chr2", 10), var("chr1", 50), var("chr1", 20)];
+        let (varlist, order) = variants_to_varlist(&variants, target_names.clone()).unwrap();
+        let positions: Vec<(u32, usize)> = varlist.lst.iter().map(|v| (v.tid, v.pos0)).collect();
+        assert_eq!(positions, vec![(0, 20), (0, 50), (1, 10)]);
+        assert_eq!(order, vec![2, 1, 0]);
+
+        assert!(variants_to_varlist(&[var("chr3", 10)], target_names.clone()).is_err());
+        assert!(variants_to_varlist(&[var("chr1", 10), var("chr1", 10)], target_names).is_err());
+    }
+
+    #[test]
+    fn test_haplotype_posteriors() {
+        let haps = vec![
+            b"ACGTTGCAAGTC".to_vec(),
+            b"ACGTTACAAGTC".to_vec(),
+            b"ACGTCAAGTC".to_vec(),
+        ];
+        let read = b"acgttacaagtc";
+        let options = HaplotypeOptions::default();
+
+        let posteriors = haplotype_posteriors(read, &[30; 12], &haps, &options).unwrap();
+        assert_eq!(posteriors.len(), 3);
+        assert!((posteriors.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(posteriors[1] > 0.9);
+
+        // a low quality base at the difference makes the haplotypes harder to tell apart
+        let mut quals = [30; 12];
+        quals[5] = 3;
+        let low_qual = haplotype_posteriors(read, &quals, &haps, &options).unwrap();
+        assert!(low_qual[1] < posteriors[1] && low_qual[0] > posteriors[0]);
+
+        // the priors are multiplied in
+        let mut with_priors = options.clone();
+        with_priors.priors = Some(vec![0.98, 0.01, 0.01]);
+        let prior_posteriors = haplotype_posteriors(read, &quals, &haps, &with_priors).unwrap();
+        assert!(prior_posteriors[0] > low_qual[0]);
+
+        assert!(haplotype_posteriors(read, &[30; 11], &haps, &options).is_err());
+        assert!(haplotype_posteriors(read, &[30; 12], &[], &options).is_err());
+        with_priors.priors = Some(vec![0.5, 0.5]);
+        assert!(haplotype_posteriors(read, &[30; 12], &haps, &with_priors).is_err());
+        let mut bad_preset = options.clone();
+        bad_preset.preset = "sanger".to_string();
+        assert!(haplotype_posteriors(read, &[30; 12], &haps, &bad_preset).is_err());
+    }
+}
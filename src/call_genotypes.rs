@@ -7,14 +7,20 @@
 // use declarations
 use bio::stats::{LogProb, PHREDProb, Prob};
 use chrono::prelude::*;
-use rand::{Rng, SeedableRng, StdRng};
+use hashbrown::HashMap;
 
+use allele_fractions::beta_credible_interval;
 use errors::*;
 use genotype_probs::*;
-use haplotype_assembly::{call_hapcut2, generate_flist_buffer};
+use haplotype_assembly::{
+    call_hapcut2, generate_flist_buffer, mapq_weighted_fragments, orient_phase_blocks, KnownPhase,
+    Phaser,
+};
+use methylation::{add_methylation_calls, MethylationMarkers};
 use print_output::*;
-use util::{DensityParameters, GenomicInterval, MAX_VCF_QUAL};
+use util::{position_hash, ContigParams, DensityParameters, GenomicInterval, MAX_VCF_QUAL};
 use variants_and_fragments::*;
+use wmec::wmec_phase;
 
 /// Takes a vector of fragments and returns a vector of "allele pileups"
 ///
@@ -42,6 +48,28 @@ fn generate_fragcall_pileup(flist: &Vec<Fragment>, n_var: usize) -> Vec<Vec<Frag
     pileup_lst
 }
 
+/// Mixes the miscall probability of every allele call with a random-allele model for mismapped
+/// reads, weighted by the probability that the read is mismapped given its MAPQ (see
+/// ```mismapping_adjusted_miscall```). The genotype likelihoods and the haplotype assembly then
+/// weigh the calls of reads with a low MAPQ (e.g. from segmental duplications) less, rather than
+/// giving them the full confidence of their realignment. Returns the number of calls that changed.
+pub fn apply_mismapping_probs(flist: &mut Vec<Fragment>, varlist: &VarList) -> usize {
+    let mut n_adjusted = 0;
+    for frag in flist.iter_mut() {
+        let p_mismap = mismapping_prob(frag.mapq);
+        if p_mismap <= 0.0 {
+            continue;
+        }
+        for call in frag.calls.iter_mut() {
+            let n_alleles = varlist.lst[call.var_ix].alleles.len();
+            call.qual = mismapping_adjusted_miscall(call.qual, p_mismap, n_alleles);
+            call.one_minus_qual = LogProb::ln_one_minus_exp(&call.qual);
+            n_adjusted += 1;
+        }
+    }
+    n_adjusted
+}
+
 /// Counts the number of each allele in an allele pileup
 ///
 /// # Arguments
@@ -71,7 +99,7 @@ fn count_alleles(
         if call.qual < ln_max_p_miscall {
             // allele call meets cutoff
             counts[call.allele as usize] += 1;
-            if flist[call.frag_ix as usize].reverse_strand {
+            if call.reverse_strand {
                 counts_reverse[call.allele as usize] += 1;
             } else {
                 counts_forward[call.allele as usize] += 1;
@@ -85,18 +113,175 @@ fn count_alleles(
     (counts, counts_forward, counts_reverse, count_amb) // return counts
 }
 
+/// Returns the median of a non-empty list of values (which is sorted in place)
+fn median(values: &mut Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Calculates the distribution of the mapping qualities of the reads supporting each allele in an
+/// allele pileup
+///
+/// # Arguments
+/// - pileup: an allele pileup for some variant site (represented as a vector of ```FragCalls```)
+/// - flist: the fragment list that the ```FragCalls``` index into
+/// - num_alleles: how many alleles does this variant site have (2 for biallelic, 3 for triallelic...)
+/// - max_p_miscall: the maximum probability of an allele miscall to count the allele, as in
+///                  ```count_alleles```
+///
+/// # Returns
+/// Returns a tuple containing ```(medians, mads)```, the median and the median absolute deviation
+/// of the MAPQ of the reads supporting each allele. Both are 0 for alleles without supporting reads.
+fn allele_mapq_stats(
+    pileup: &Vec<FragCall>,
+    flist: &Vec<Fragment>,
+    num_alleles: usize,
+    max_p_miscall: f64,
+) -> (Vec<f64>, Vec<f64>) {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut allele_mapqs: Vec<Vec<f64>> = vec![vec![]; num_alleles];
+    for call in pileup {
+        if call.qual < ln_max_p_miscall {
+            allele_mapqs[call.allele as usize].push(flist[call.frag_ix as usize].mapq as f64);
+        }
+    }
+
+    let mut medians: Vec<f64> = vec![0.0; num_alleles];
+    let mut mads: Vec<f64> = vec![0.0; num_alleles];
+    for (a, mapqs) in allele_mapqs.iter_mut().enumerate() {
+        if mapqs.is_empty() {
+            continue;
+        }
+        medians[a] = median(mapqs);
+        let mut deviations: Vec<f64> = mapqs.iter().map(|q| (q - medians[a]).abs()).collect();
+        mads[a] = median(&mut deviations);
+    }
+    (medians, mads)
+}
+
+/// Calculates the mean mapping quality, the mean allele quality and the realignment likelihood
+/// ratio of the allele calls of each allele in an allele pileup
+///
+/// # Arguments
+/// - pileup: an allele pileup for some variant site (represented as a vector of ```FragCalls```)
+/// - flist: the fragment list that the ```FragCalls``` index into
+/// - num_alleles: how many alleles does this variant site have (2 for biallelic, 3 for triallelic...)
+/// - max_p_miscall: the maximum probability of an allele miscall to count the allele, as in
+///                  ```count_alleles```
+///
+/// # Returns
+/// Returns a tuple containing ```(mapq_means, qual_means, llrs)``` for each allele:
+/// - ```mapq_means```: the mean MAPQ of the reads supporting the allele
+/// - ```qual_means```: the PHRED-scaled mean miscall probability of the calls of the allele
+/// - ```llrs```: the log10 likelihood ratio of the allele versus the reference allele summed over
+///   all calls (including ambiguous ones). A call of the allele with miscall probability ```q```
+///   adds ```log10((1-q)/q)```, a call of the reference allele subtracts it, and calls of other
+///   alleles are not counted. ```llrs[0]``` is 0.
+/// The means are 0 for alleles without supporting reads.
+fn allele_call_stats(
+    pileup: &Vec<FragCall>,
+    flist: &Vec<Fragment>,
+    num_alleles: usize,
+    max_p_miscall: f64,
+) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut mapq_sums: Vec<f64> = vec![0.0; num_alleles];
+    let mut qual_sums: Vec<LogProb> = vec![LogProb::ln_zero(); num_alleles];
+    let mut counts: Vec<usize> = vec![0; num_alleles];
+    let mut llrs: Vec<f64> = vec![0.0; num_alleles];
+
+    for call in pileup {
+        let a = call.allele as usize;
+        if call.qual < ln_max_p_miscall {
+            mapq_sums[a] += flist[call.frag_ix as usize].mapq as f64;
+            qual_sums[a] = LogProb::ln_add_exp(qual_sums[a], call.qual);
+            counts[a] += 1;
+        }
+
+        let llr = *(call.one_minus_qual - call.qual) / std::f64::consts::LN_10;
+        if !llr.is_finite() {
+            continue;
+        }
+        if a == 0 {
+            for b in 1..num_alleles {
+                llrs[b] -= llr;
+            }
+        } else {
+            llrs[a] += llr;
+        }
+    }
+
+    let mut mapq_means: Vec<f64> = vec![0.0; num_alleles];
+    let mut qual_means: Vec<f64> = vec![0.0; num_alleles];
+    for a in 0..num_alleles {
+        if counts[a] > 0 {
+            mapq_means[a] = mapq_sums[a] / counts[a] as f64;
+            qual_means[a] = *PHREDProb::from(qual_sums[a] - LogProb::from(Prob(counts[a] as f64)));
+        }
+    }
+    (mapq_means, qual_means, llrs)
+}
+
+/// The probability mass of the credible interval of the alternate allele fraction
+static ALT_FRAC_CI_MASS: f64 = 0.95;
+
+/// Estimates the alternate allele fraction of an allele pileup from the per-read allele
+/// posteriors, with a credible interval
+///
+/// Each call that passes ```max_p_miscall``` is an alternate allele observation with the
+/// posterior probability that the read carries an alternate allele: ```1-q``` for a call of an
+/// alternate allele with miscall probability ```q```, and ```q``` for a call of the reference
+/// allele. The expected alternate and reference counts give a Beta posterior of the allele
+/// fraction (with a uniform prior).
+///
+/// # Returns
+/// Returns a tuple ```(alt_frac, (lower, upper))``` with the expected fraction of the calls that
+/// are of an alternate allele and the equal-tailed 95% credible interval of the allele fraction.
+/// Without calls, the fraction is 0 and the interval is (0, 1).
+fn alt_allele_fraction(pileup: &Vec<FragCall>, max_p_miscall: f64) -> (f64, (f64, f64)) {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut alt = 0.0;
+    let mut total = 0.0;
+    for call in pileup {
+        if call.qual >= ln_max_p_miscall {
+            continue;
+        }
+        let p_miscall = *Prob::from(call.qual);
+        alt += if call.allele == 0 {
+            p_miscall
+        } else {
+            1.0 - p_miscall
+        };
+        total += 1.0;
+    }
+    if total == 0.0 {
+        return (0.0, (0.0, 1.0));
+    }
+    let ci = beta_credible_interval(alt + 1.0, total - alt + 1.0, ALT_FRAC_CI_MASS);
+    (alt / total, ci)
+}
+
 /// Calculates the posterior probabilities for a pileup-based genotyping calculation (without using
 /// haplotype information)
 ///
 /// # Arguments
 /// - pileup: an allele pileup for some variant site (represented as a vector of ```FragCalls```)
-/// - genotype_priors: a struct holding the genotype prior probabilities
+/// - genotype_priors: the genotype prior (see ```GenotypePrior```)
 /// - alleles: a vector of the alleles (as Strings) for this variant site. ```alleles[0]``` should
 ///            be the ref allele, and alleles must be in same order as the allele indices held in the
 ///            pileup ```FragCalls```.
 /// - max_p_miscall: the maximum probability of an allele miscall to count the allele (equivalent
 ///                  to the minimum allowed allele quality, but represented as a normal probability
 ///                  rather than PHRED-scaled)
+/// - ploidy: the ploidy of the site. At haploid sites (```ploidy == 1```) only the genotypes
+///           ```Genotype(a, a)``` are possible, and their likelihood is that of a single allele.
+/// - population_af: the population allele frequencies of the site, if it is a known site
+///                  (see ```PopulationAfPrior```)
 ///
 /// # Returns
 /// Returns a Result holding a ```GenotypeProbs``` struct.
@@ -107,9 +292,11 @@ fn count_alleles(
 /// - Can throw an error if attempts to query ```genotype_priors``` using an invalid genotype
 pub fn calculate_genotype_posteriors_no_haplotypes(
     pileup: &Vec<FragCall>,
-    genotype_priors: &GenotypePriors,
+    genotype_priors: &dyn GenotypePrior,
     alleles: &Vec<String>,
     max_p_miscall: f64,
+    ploidy: u8,
+    population_af: &Option<Vec<f64>>,
 ) -> Result<GenotypeProbs> {
     let ln_max_p_miscall: LogProb = LogProb::from(Prob(max_p_miscall));
     let ln_half: LogProb = LogProb::from(Prob(0.5)); // ln(0.5)
@@ -117,7 +304,7 @@ pub fn calculate_genotype_posteriors_no_haplotypes(
     // this probability matrix initially holds the genotype priors p(g),
     // and after the loop it holds P(data | g) * p(g)
     let mut probs: GenotypeProbs = genotype_priors
-        .get_all_priors(alleles)
+        .get_all_site_priors(alleles, ploidy, population_af)
         .chain_err(|| "Error getting all genotype priors while calculating genotypes.")?;
 
     for &call in pileup {
@@ -131,7 +318,7 @@ pub fn calculate_genotype_posteriors_no_haplotypes(
 
         // for each possible genotype (e.g. there are 4 possible genotypes for biallelic site)
         // update the genotype probabilities as we calculate P(data | g) * p(g)
-        for g in possible_genotypes(alleles) {
+        for g in possible_genotypes_with_ploidy(alleles, ploidy) {
             if g.0 == allele && g.1 == allele {
                 // both alleles of genotype match this allele observation
                 probs.ln_times_equals(g, p_call);
@@ -158,7 +345,7 @@ pub fn calculate_genotype_posteriors_no_haplotypes(
 /// #Arguments
 /// - flist: a vector of ```Fragment```s representing the list of haplotype fragments
 /// - varlist: a mutable ```VarList``` representing the information about variants (including current genotypes)
-/// - genotype_priors: a struct holding the genotype prior probabilities
+/// - genotype_priors: the genotype prior (see ```GenotypePrior```)
 /// - max_p_miscall: the maximum probability of an allele miscall to count the allele (equivalent
 ///                  to the minimum allowed allele quality, but represented as a normal probability
 ///                  rather than PHRED-scaled)
@@ -177,7 +364,7 @@ pub fn calculate_genotype_posteriors_no_haplotypes(
 pub fn call_genotypes_no_haplotypes(
     flist: &Vec<Fragment>,
     varlist: &mut VarList,
-    genotype_priors: &GenotypePriors,
+    genotype_priors: &dyn GenotypePrior,
     max_p_miscall: f64,
 ) -> Result<()> {
     // generate a list of allele pileups so we can iterate over them and use each pileup to calculate genotypes
@@ -196,19 +383,32 @@ pub fn call_genotypes_no_haplotypes(
             &genotype_priors,
             &var.alleles,
             max_p_miscall,
+            var.ploidy,
+            &var.population_af,
         )
             .chain_err(|| "Error calculating genotype posteriors for haplotype-free genotyping")?;
 
+        // the genotype likelihoods (for the PL field) are the posteriors without the priors
+        let priors: GenotypeProbs = genotype_priors
+            .get_all_site_priors(&var.alleles, var.ploidy, &var.population_af)
+            .chain_err(|| "Error getting all genotype priors while calculating genotypes.")?;
+
         // get the genotype with maximum genotype posterior
-        let (max_g, max_post) = posts.max_genotype_post(false, false);
+        let (max_g, _) = posts.max_genotype_post(false, false);
 
-        // convert genotype quality into a PHRED scaled value
-        let genotype_qual: f64 = *PHREDProb::from(LogProb::ln_one_minus_exp(&max_post));
+        // the genotype quality measures whether the genotype call is right, the variant quality
+        // (below) whether there is a variant at all
+        let genotype_qual: f64 = posts.genotype_qual(max_g, &var.possible_genotypes());
 
         // count the number of alleles (for annotating the VCF fields)
         let (allele_counts, counts_forward, counts_reverse, ambig_count) =
             count_alleles(&pileup, flist, var.alleles.len(), max_p_miscall);
         let allele_total: u16 = allele_counts.iter().sum::<u16>() + ambig_count;
+        let (mapq_median, mapq_mad) =
+            allele_mapq_stats(&pileup, flist, var.alleles.len(), max_p_miscall);
+        let (mapq_mean, allele_qual_mean, realignment_llr) =
+            allele_call_stats(&pileup, flist, var.alleles.len(), max_p_miscall);
+        let (alt_frac, alt_frac_ci) = alt_allele_fraction(&pileup, max_p_miscall);
 
         // UPDATE THE VARIANT FIELDS
         if var.dp < allele_total as usize {
@@ -216,15 +416,24 @@ pub fn call_genotypes_no_haplotypes(
             // in certain extreme cases the DP returned by samtools can be underestimated due to pileup max depth
         }
 
-        var.qual = *PHREDProb::from(posts.get(Genotype(0, 0)));
+        var.qual = posts.discovery_qual();
         // don't let the variant quality exceed upper bound
         var.qual = var.qual.min(MAX_VCF_QUAL);
 
         var.genotype = max_g;
+        var.genotype_post = posts.clone();
+        var.genotype_likelihoods = posts.likelihoods(&priors);
         var.allele_counts = allele_counts;
         var.allele_counts_forward = counts_forward;
         var.allele_counts_reverse = counts_reverse;
         var.ambiguous_count = ambig_count;
+        var.mapq_median = mapq_median;
+        var.mapq_mad = mapq_mad;
+        var.mapq_mean = mapq_mean;
+        var.allele_qual_mean = allele_qual_mean;
+        var.realignment_llr = realignment_llr;
+        var.alt_frac = alt_frac;
+        var.alt_frac_ci = alt_frac_ci;
         var.unphased_genotype = max_g;
         var.gq = genotype_qual;
         var.unphased_gq = genotype_qual;
@@ -237,13 +446,100 @@ pub fn call_genotypes_no_haplotypes(
     Ok(())
 }
 
+/// Annotates the variants of each realignment cluster with a cluster-level quality
+///
+/// The variants of a cluster are genotyped from the same realigned reads, so their errors are not
+/// independent and their individual QUALs overstate the confidence in the cluster as a whole.
+/// The cluster quality is the PHRED-scaled sum of the probabilities that each non-reference call
+/// in the cluster is wrong (P(0/0)), which is an upper bound on the probability that any of them is
+/// wrong that holds without assuming independence.
+///
+/// # Arguments
+/// - varlist: a mutable ```VarList``` of genotyped variants, with the cluster IDs assigned during
+///            fragment extraction. Variants without a cluster ID are left unchanged.
+pub fn calculate_cluster_quals(varlist: &mut VarList) {
+    // summed P(0/0) of the non-reference calls of each cluster, keyed on (tid, cluster ID)
+    let mut cluster_p_wrong: HashMap<(u32, usize), LogProb> = HashMap::new();
+    for var in &varlist.lst {
+        if let Some(cid) = var.cluster_id {
+            let p = cluster_p_wrong
+                .entry((var.tid, cid))
+                .or_insert(LogProb::ln_zero());
+            if var.genotype != Genotype(0, 0) {
+                *p = LogProb::ln_add_exp(*p, var.genotype_post.get(Genotype(0, 0)));
+            }
+        }
+    }
+
+    for var in &mut varlist.lst {
+        var.cluster_qual = match var.cluster_id {
+            Some(cid) => {
+                let p_wrong = cluster_p_wrong[&(var.tid, cid)];
+                if p_wrong == LogProb::ln_zero() {
+                    None // no variant was called in the cluster
+                } else {
+                    let p_wrong = if p_wrong > LogProb::ln_one() {
+                        LogProb::ln_one()
+                    } else {
+                        p_wrong
+                    };
+                    Some((*PHREDProb::from(p_wrong)).min(MAX_VCF_QUAL))
+                }
+            }
+            None => None,
+        };
+    }
+}
+
+/// Controls for the rounds of haplotype assembly and genotype refinement in
+/// ```call_genotypes_with_haplotypes```
+#[derive(Clone, Copy, Debug)]
+pub struct HapIterationParams {
+    /// stop when the relative change in log-likelihood between two rounds falls below this amount
+    pub ll_delta: f64,
+    /// the maximum number of rounds
+    pub max_rounds: usize,
+    /// if set, also stop when at most this many genotypes (ignoring phase) changed in a round
+    pub max_genotype_changes: Option<usize>,
+    /// refine the genotypes with the assembled haplotypes. If false, the genotypes from the pileup
+    /// are only phased, with a single round of haplotype assembly.
+    pub refine_genotypes: bool,
+    /// the haplotype assembly algorithm
+    pub phaser: Phaser,
+    /// with ```Phaser::Wmec```, the maximum number of fragments that span a variant
+    pub wmec_max_cov: usize,
+    /// weight the fragments by their mapping quality in haplotype assembly, see
+    /// ```mapq_weighted_fragments```
+    pub mapq_weighting: bool,
+}
+
+/// Diagnostics of one round of haplotype assembly and genotype refinement. The likelihoods are
+/// the total likelihoods of the reads given the haplotypes, including the genotype priors.
+#[derive(Clone, Debug)]
+pub struct HapIterationRound {
+    pub round: usize,
+    pub num_phased: usize,
+    pub likelihood_before_assembly: LogProb,
+    pub likelihood_after_assembly: LogProb,
+    pub likelihood_after_refinement: LogProb,
+    /// the number of variants whose genotype (ignoring phase) changed in the round
+    pub genotype_changes: usize,
+    /// the number of passes over the variants of the greedy genotype refinement
+    pub refinement_passes: usize,
+}
+
+// whether two genotypes are the same, ignoring phase
+fn same_unphased_genotype(a: Genotype, b: Genotype) -> bool {
+    (a.0 == b.0 && a.1 == b.1) || (a.0 == b.1 && a.1 == b.0)
+}
+
 /// Refines diploid genotypes for each variant in the ```VarList``` using a haplotype assembly approach.
 ///
 /// #Arguments
 /// - flist: a vector of ```Fragment```s representing the list of haplotype fragments
 /// - varlist: a mutable ```VarList``` representing the information about variants (including current genotypes)
 /// - interval: an optional ```GenomicInterval``` specifying the region where variants are being called.
-/// - genotype_priors: a struct holding the genotype prior probabilities
+/// - genotype_priors: the genotype prior (see ```GenotypePrior```)
 /// - variant_debug_directory: an optional string specifying a directory path where we are writing
 ///                            intermediate variant and fragment data for debugging purposes
 /// - program_step: this argument is only needed for naming the output files placed in the
@@ -257,17 +553,22 @@ pub fn call_genotypes_no_haplotypes(
 ///                  to the minimum allowed allele quality, but represented as a normal probability
 ///                  rather than PHRED-scaled)
 /// - sample_name: the sample name that each variant should be associated with
-/// - ll_delta: a parameter to control how quickly the likelihood algorithm converges.
-///             the algorithm terminates when ```abs((log10(l_new)-log10(l_old))/log10(l_old)) < ll_delta```,
+/// - iteration_params: controls when the iteration stops (see ```HapIterationParams```).
+///             It terminates when ```abs((log10(l_new)-log10(l_old))/log10(l_old)) < ll_delta```,
 ///             or in other words when the improvement in likelihood from one iteration to the next
-///             is small.
+///             is small, when at most ```max_genotype_changes``` genotypes changed, or after
+///             ```max_rounds``` rounds.
+/// - known_phase: an optional known phase of the variants (see ```known_phase_constraints```),
+///             added to each round of assembly as pseudo-fragments. The assembled blocks are
+///             oriented to match it.
+/// - contig_params: per-contig parameter overrides (used for the coverage filter in debug VCFs)
 ///
 /// # Returns
-/// Returns nothing. The function mutates each Var in the input VarList. The fields are updated
-///  using phase-aware genotyping calculation.
-/// - ```var.qual```: the variant quality
+/// Returns the diagnostics of each round. The function mutates each Var in the input VarList. The
+///  fields are updated using phase-aware genotyping calculation.
+/// - ```var.qual```: the variant (discovery) quality, see ```GenotypeProbs::discovery_qual```
 /// - ```var.genotype```: the genotype call
-/// - ```var.gq```: the genotype quality
+/// - ```var.gq```: the genotype quality, see ```GenotypeProbs::genotype_qual```
 /// - ```var.filter```: the variant filter field. All variants are set to ```PASS``, but if the
 ///                     variant debug directory is specified then the depth and variant density filters
 ///                     are applied so that intermediary/debug VCFs have the filters applied.
@@ -281,26 +582,35 @@ pub fn call_genotypes_with_haplotypes(
     flist: &mut Vec<Fragment>,
     varlist: &mut VarList,
     interval: &Option<GenomicInterval>,
-    genotype_priors: &GenotypePriors,
+    genotype_priors: &dyn GenotypePrior,
     variant_debug_directory: &Option<String>,
     program_step: usize,
     max_cov: u32,
     density_params: &DensityParameters,
     max_p_miscall: f64,
     sample_name: &String,
-    ll_delta: f64,
-) -> Result<()> {
+    iteration_params: &HapIterationParams,
+    methylation_markers: &Option<MethylationMarkers>,
+    known_phase: &Option<KnownPhase>,
+    contig_params: &Vec<ContigParams>,
+) -> Result<Vec<HapIterationRound>> {
     let n_var = varlist.lst.len();
     let pileup_lst = generate_fragcall_pileup(&flist, varlist.lst.len());
     assert_eq!(pileup_lst.len(), varlist.lst.len());
 
     let max_iterations: usize = 1000000;
     let ln_half = LogProb::from(Prob(0.5));
-    let mut rng: StdRng = StdRng::from_seed(&[0]);
     let print_time: fn() -> String = || Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
 
     let hap_ixs = vec![0, 1];
 
+    // haplotype of the methylation markers (if any) from the last round of HapCUT2 assembly
+    let n_markers = match methylation_markers {
+        &Some(ref markers) => markers.sites.len(),
+        &None => 0,
+    };
+    let mut marker_hap1: Vec<u8> = vec!['0' as u8; n_markers];
+
     // obtain var_frags. var_frags[i] contains a vector with the indices of fragments overlapping
     // the i-th variant
     let mut var_frags: Vec<Vec<u32>> = vec![vec![]; varlist.lst.len()];
@@ -312,11 +622,18 @@ pub fn call_genotypes_with_haplotypes(
     }
 
     let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let weighted_flist: Option<Vec<Fragment>> = if iteration_params.mapq_weighting {
+        Some(mapq_weighted_fragments(flist))
+    } else {
+        None
+    };
     let mut haps: Vec<Vec<u8>> = vec![vec![0u8; n_var]; 2];
     let mut prev_likelihood = LogProb::ln_zero();
+    let mut rounds: Vec<HapIterationRound> = vec![];
 
     // for all basic biallelic heterozygous variants
-    // randomly shuffle the phase of the variant
+    // randomly shuffle the phase of the variant (with a random number of its position, so that
+    // the initial phase doesn't depend on the other variants of the region)
     for i in 0..varlist.lst.len() {
         let var = &mut varlist.lst[i];
         if var.alleles.len() == 2
@@ -324,7 +641,7 @@ pub fn call_genotypes_with_haplotypes(
             && var.alleles[0].len() == 1
             && var.alleles[1].len() == 1
         {
-            if rng.next_f64() < 0.5 {
+            if position_hash(0, var.tid, var.pos0) % 2 == 0 {
                 var.genotype = Genotype(0, 1);
             } else {
                 var.genotype = Genotype(1, 0);
@@ -336,13 +653,15 @@ pub fn call_genotypes_with_haplotypes(
     // - perform HapCUT2 haplotype assembly over the variants currently called as heterozygous
     // - take variants in random order and greedily update genotypes, maximizing genotype
     //     likelihood using the haplotype information
-    for hapcut2_iter in 0..max_iterations {
+    for hapcut2_iter in 0..iteration_params.max_rounds {
         // print the haplotype assembly iteration
-        eprintln!(
+        info!(
             "{}    Round {} of haplotype assembly...",
             print_time(),
             hapcut2_iter + 1
         );
+        let round_start_genotypes: Vec<Genotype> =
+            varlist.lst.iter().map(|var| var.genotype).collect();
 
         // count how many variants meet the criteria for "phased"
         let mut num_phased = 0;
@@ -363,7 +682,13 @@ pub fn call_genotypes_with_haplotypes(
         for v in 0..varlist.lst.len() {
             let g = Genotype(haps[0][v], haps[1][v]);
             total_likelihood =
-                total_likelihood + genotype_priors.get_prior(&varlist.lst[v].alleles, g)?;
+                total_likelihood
+                    + genotype_priors.get_site_prior(
+                        &varlist.lst[v].alleles,
+                        g,
+                        varlist.lst[v].ploidy,
+                        &varlist.lst[v].population_af,
+                    )?;
         }
 
         // iterate over all the fragments and all the sites and calculate the read likelihood
@@ -389,7 +714,8 @@ pub fn call_genotypes_with_haplotypes(
                 total_likelihood + LogProb::ln_add_exp(ln_half + pr[0], ln_half + pr[1]);
         }
 
-        eprintln!("{}    (Before HapCUT2) Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+        info!("{}    (Before HapCUT2) Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+        let likelihood_before_assembly = total_likelihood;
 
         // generate buffers with contents equivalent to VCF and fragment file and
         // pass these off to HapCUT2 for haplotype assembly
@@ -402,6 +728,16 @@ pub fn call_genotypes_with_haplotypes(
         let mut var_phased: Vec<bool> = vec![false; varlist.lst.len()];
         let mut hap1: Vec<u8> = vec!['-' as u8; varlist.lst.len()];
 
+        // methylation markers are numbered after the variants in the HapCUT2 input.
+        // they take their haplotype from the previous round, and are always phased.
+        for h in marker_hap1.iter_mut() {
+            if *h != '0' as u8 && *h != '1' as u8 {
+                *h = '0' as u8;
+            }
+        }
+        hap1.extend(marker_hap1.iter());
+        let mut phase_variant: Vec<bool> = vec![true; n_var + n_markers];
+
         for (i, var) in varlist.lst.iter().enumerate() {
             // if the variant meets certain criteria (heterozygous, biallelic, not an indel)
             // set its bit to true in var_phased (so that it will be used in HapCUT2 assembly)
@@ -421,31 +757,85 @@ pub fn call_genotypes_with_haplotypes(
             }
         }
 
-        // similarly to the VCF buffer, generate a fragment buffer representing the fragment file
-        // this also gets passed off as input to HapCUT2
-        let frag_buffer = generate_flist_buffer(&flist, &var_phased, max_p_miscall, false)
-            .chain_err(|| "Error generating fragment list buffer.")?;
+        phase_variant[..n_var].copy_from_slice(&var_phased);
         // this phase_sets vector gets modified by HapCUT2 to hold the haplotype block (phase set)
         // information
         // phase_sets[i] will hold a specific integer that is like a haplotype block identifier
-        let mut phase_sets: Vec<i32> = vec![-1i32; varlist.lst.len()];
-
-        // ASSEMBLE HAPLOTYPES WITH HAPCUT2
-        // make an unsafe call to the HapCUT2 code which is linked statically via FFI
-        call_hapcut2(
-            &frag_buffer,
-            frag_buffer.len(),
-            varlist.lst.len(),
-            &mut hap1,
-            &mut phase_sets,
-        );
+        let mut phase_sets: Vec<i32> = vec![-1i32; n_var + n_markers];
+
+        // the fragments for haplotype assembly, with the calls of each read also wrong if the
+        // read is mismapped with mapq weighting
+        let assembly_flist: &Vec<Fragment> = match weighted_flist {
+            Some(ref weighted) => weighted,
+            None => &*flist,
+        };
+        match iteration_params.phaser {
+            Phaser::MaxCut => {
+                // similarly to the VCF buffer, generate a fragment buffer representing the
+                // fragment file. this also gets passed off as input to HapCUT2
+                let mut frag_buffer = match methylation_markers {
+                    &Some(ref markers) => generate_flist_buffer(
+                        &add_methylation_calls(assembly_flist, markers, n_var),
+                        &phase_variant,
+                        max_p_miscall,
+                        false,
+                    ),
+                    &None => {
+                        generate_flist_buffer(assembly_flist, &var_phased, max_p_miscall, false)
+                    }
+                }
+                .chain_err(|| "Error generating fragment list buffer.")?;
+                // the known phase sets are added as pseudo-fragments that link their variants
+                if let &Some(ref known) = known_phase {
+                    frag_buffer.extend(
+                        generate_flist_buffer(
+                            &known.fragments,
+                            &phase_variant,
+                            max_p_miscall,
+                            false,
+                        )
+                        .chain_err(|| "Error generating fragment list buffer.")?,
+                    );
+                }
+
+                // ASSEMBLE HAPLOTYPES WITH HAPCUT2
+                // make an unsafe call to the HapCUT2 code which is linked statically via FFI
+                call_hapcut2(
+                    &frag_buffer,
+                    frag_buffer.len(),
+                    n_var + n_markers,
+                    &mut hap1,
+                    &mut phase_sets,
+                );
+            }
+            Phaser::Wmec => {
+                // methylation markers are not supported, so the fragments are used as they are
+                let mut wmec_flist: Vec<&Fragment> = assembly_flist.iter().collect();
+                if let &Some(ref known) = known_phase {
+                    wmec_flist.extend(known.fragments.iter());
+                }
+                wmec_phase(
+                    &wmec_flist,
+                    &phase_variant,
+                    max_p_miscall,
+                    iteration_params.wmec_max_cov,
+                    &mut hap1,
+                    &mut phase_sets,
+                );
+            }
+        }
+        if let &Some(ref known) = known_phase {
+            orient_phase_blocks(&mut hap1, &phase_sets, &known.hap1_alleles);
+        }
+        marker_hap1 = hap1.split_off(n_var);
 
         // we want to convert the phase set ID given by HapCUT2 into the VCF standard type
         // it should be the variant position (on its chromosome) of the first phased variant in the block
         // we'll iterate over the phase set IDs given by HapCUT2 and figure out what the minimum
         // position for that phase set is, so we can use it as the PS flag value
         let m = <usize>::max_value();
-        let mut min_pos_ps: Vec<usize> = vec![m; varlist.lst.len()];
+        let mut min_pos_ps: Vec<usize> = vec![m; phase_sets.len()];
+        phase_sets.truncate(n_var);
 
         for (i, p) in phase_sets.iter().enumerate() {
             if p < &0 {
@@ -508,7 +898,13 @@ pub fn call_genotypes_with_haplotypes(
         for v in 0..varlist.lst.len() {
             let g = Genotype(haps[0][v], haps[1][v]);
             total_likelihood =
-                total_likelihood + genotype_priors.get_prior(&varlist.lst[v].alleles, g)?;
+                total_likelihood
+                    + genotype_priors.get_site_prior(
+                        &varlist.lst[v].alleles,
+                        g,
+                        varlist.lst[v].ploidy,
+                        &varlist.lst[v].population_af,
+                    )?;
         }
 
         // iterate over all the fragments and all the sites and calculate the read likelihood
@@ -532,7 +928,8 @@ pub fn call_genotypes_with_haplotypes(
                 total_likelihood + LogProb::ln_add_exp(ln_half + pr[0], ln_half + pr[1]);
         }
 
-        eprintln!("{}    (After HapCUT2)  Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+        info!("{}    (After HapCUT2)  Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+        let likelihood_after_assembly = total_likelihood;
 
         // p_read_hap[i][j] will contain P(R_j | H_i)
         // we will keep this saved and update it when the haplotypes change
@@ -557,13 +954,23 @@ pub fn call_genotypes_with_haplotypes(
         // GREEDY GENOTYPE OPTIMIZATION
 
         // loop over all variants repeatedly until the haplotype likelihoods stop changing
+        let mut refinement_passes = 0;
         for _ in 0..max_iterations {
+            if !iteration_params.refine_genotypes {
+                break;
+            }
+            refinement_passes += 1;
             let mut changed = false;
 
-            // loop over the set of variants v in random order
+            // loop over the set of variants v in random order. the order of two variants only
+            // depends on their positions, the round and the pass, so that it is the same
+            // whichever other variants are in the region
+            let pass_seed = ((hapcut2_iter as u64) << 32) | refinement_passes as u64;
             let mut ixvec: Vec<usize> = (0..varlist.lst.len()).collect();
+            ixvec.sort_by_key(|&v| {
+                position_hash(pass_seed, varlist.lst[v].tid, varlist.lst[v].pos0)
+            });
             let ixslice: &mut [usize] = ixvec.as_mut_slice();
-            rng.shuffle(ixslice);
 
             for v_r in ixslice {
                 let v = *v_r;
@@ -572,7 +979,8 @@ pub fn call_genotypes_with_haplotypes(
 
                 assert_eq!(v, var.ix);
 
-                let mut p_reads: GenotypeProbs = genotype_priors.get_all_priors(&var.alleles).chain_err(|| "Error getting all genotype priors while calculating haplotype-informed genotypes")?;
+                let priors: GenotypeProbs = genotype_priors.get_all_site_priors(&var.alleles, var.ploidy, &var.population_af).chain_err(|| "Error getting all genotype priors while calculating haplotype-informed genotypes")?;
+                let mut p_reads: GenotypeProbs = priors.clone();
 
                 // let (g1,g2) be the current genotype being considered to switch to
                 // then p_read_lst_genotype[g1][g2] contains a vector of tuples (frag_ix, p_read_h0, p_read_h1
@@ -661,6 +1069,7 @@ pub fn call_genotypes_with_haplotypes(
                 let (max_g, _) = posts.max_genotype_post(true, false);
 
                 var.genotype_post = posts.clone();
+                var.genotype_likelihoods = posts.likelihoods(&priors);
                 // TODO: should we reassign var.gq here?
 
                 // we need to track if any changes occured for termination
@@ -708,7 +1117,13 @@ pub fn call_genotypes_with_haplotypes(
         for v in 0..varlist.lst.len() {
             let g = Genotype(haps[0][v], haps[1][v]);
             total_likelihood =
-                total_likelihood + genotype_priors.get_prior(&varlist.lst[v].alleles, g)?;
+                total_likelihood
+                    + genotype_priors.get_site_prior(
+                        &varlist.lst[v].alleles,
+                        g,
+                        varlist.lst[v].ploidy,
+                        &varlist.lst[v].population_af,
+                    )?;
         }
 
         // iterate over all the fragments and all the sites and calculate the read likelihood
@@ -737,19 +1152,18 @@ pub fn call_genotypes_with_haplotypes(
             //let pileup = &pileup_lst[i];
             let var = &mut varlist.lst[i];
 
-            let (max_g, _) = var.genotype_post.max_genotype_post(true, false);
+            // without refinement, the genotype is the pileup genotype as phased by HapCUT2
+            let max_g = if iteration_params.refine_genotypes {
+                var.genotype_post.max_genotype_post(true, false).0
+            } else {
+                Genotype(haps[0][i], haps[1][i])
+            };
 
             // calculate the genotype quality for the max phased genotype
             // sum all of the genotypes that aren't max_g, or the flipped phase version of max_g
-            let mut non_max_post: Vec<LogProb> = vec![];
-            for g in var.possible_genotypes() {
-                if g != max_g && g != Genotype(max_g.1, max_g.0) {
-                    non_max_post.push(var.genotype_post.get(g));
-                }
-            }
-            let p_call_wrong: LogProb = LogProb::ln_sum_exp(&non_max_post);
-            //let p_call_wrong: LogProb = LogProb::ln_one_minus_exp(&max_post);
-            let genotype_qual: f64 = *PHREDProb::from(p_call_wrong);
+            let genotype_qual: f64 = var
+                .genotype_post
+                .genotype_qual(max_g, &var.possible_genotypes());
 
             //let (allele_counts, ambig_count) = count_alleles(&pileup, var.alleles.len(), max_p_miscall);
             //let allele_total: usize = allele_counts.iter().sum::<usize>() + ambig_count;
@@ -758,7 +1172,7 @@ pub fn call_genotypes_with_haplotypes(
             //    var.dp = allele_total;
             //}
 
-            var.qual = *PHREDProb::from(var.genotype_post.get(Genotype(0, 0)));
+            var.qual = var.genotype_post.discovery_qual();
             //var.allele_counts = allele_counts;
             //var.ambiguous_count = ambig_count;
             var.genotype = max_g;
@@ -794,21 +1208,52 @@ pub fn call_genotypes_with_haplotypes(
             max_cov,
             density_params,
             sample_name,
+            contig_params,
         )?;
 
-        eprintln!("{}    (After Greedy)   Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+        info!("{}    (After Greedy)   Total phased heterozygous SNVs: {}  Total likelihood (phred): {:.2}", print_time(), num_phased, *PHREDProb::from(total_likelihood));
+
+        let genotype_changes = varlist
+            .lst
+            .iter()
+            .zip(round_start_genotypes.iter())
+            .filter(|&(var, &g)| !same_unphased_genotype(var.genotype, g))
+            .count();
+        rounds.push(HapIterationRound {
+            round: hapcut2_iter + 1,
+            num_phased: num_phased,
+            likelihood_before_assembly: likelihood_before_assembly,
+            likelihood_after_assembly: likelihood_after_assembly,
+            likelihood_after_refinement: total_likelihood,
+            genotype_changes: genotype_changes,
+            refinement_passes: refinement_passes,
+        });
+
+        // without refinement the genotypes don't change, so another round gives the same phasing
+        if !iteration_params.refine_genotypes {
+            break;
+        }
 
         // convert logprob value to base 10
         let b10 = |x: LogProb| (*PHREDProb::from(x) / -10.0) as f64;
 
         // termination criteria for the likelihoods
-        if ((b10(total_likelihood) - b10(prev_likelihood)) / b10(prev_likelihood)) < ll_delta {
+        if ((b10(total_likelihood) - b10(prev_likelihood)) / b10(prev_likelihood))
+            < iteration_params.ll_delta
+        {
             break;
         }
 
+        // termination criteria for the genotype changes
+        if let Some(max_changes) = iteration_params.max_genotype_changes {
+            if genotype_changes <= max_changes {
+                break;
+            }
+        }
+
         prev_likelihood = total_likelihood; // save the current likelihood as the previous likelihood
     }
-    Ok(())
+    Ok(rounds)
 }
 
 #[cfg(test)]
@@ -825,7 +1270,10 @@ mod tests {
                 var_ix: v_ix,                              // index into variant list
                 allele: a,                                 // allele call
                 qual: LogProb::from(Prob(0.01)), // LogProb probability the call is an error
-                one_minus_qual: LogProb::from(Prob(0.99))
+                one_minus_qual: LogProb::from(Prob(0.99)),
+                reverse_strand: false,
+                read_pos: u32::MAX,
+                base_qual: 255,
             }
         };
         let p50 = LogProb::from(Prob(0.5));
@@ -841,6 +1289,10 @@ mod tests {
             calls: vec![f0v0, f0v1, f0v2, f0v3],
             p_read_hap: [p50, p50],
             reverse_strand: false,
+            mapq: 60,
+            error_rate: None,
+            hp_tag: None,
+            ps_tag: None,
         };
         // second fragment
         let f1v0 = fcall(1, 0, 0);
@@ -851,6 +1303,10 @@ mod tests {
             calls: vec![f1v0, f1v1, f1v2],
             p_read_hap: [p50, p50],
             reverse_strand: false,
+            mapq: 60,
+            error_rate: None,
+            hp_tag: None,
+            ps_tag: None,
         };
         // third fragment
         let f2v1 = fcall(2, 1, 1);
@@ -861,6 +1317,10 @@ mod tests {
             calls: vec![f2v1, f2v2, f2v3],
             p_read_hap: [p50, p50],
             reverse_strand: false,
+            mapq: 60,
+            error_rate: None,
+            hp_tag: None,
+            ps_tag: None,
         };
 
         // the fragment list looks like this (rows are fragments and columns are variant sites)
@@ -894,4 +1354,185 @@ mod tests {
             }
         }
     }
+
+    fn generate_genotype_priors() -> GenotypePriors {
+        GenotypePriors::new(
+            LogProb::from(Prob(0.0005)),
+            LogProb::from(Prob(0.001)),
+            LogProb::from(Prob(0.00005)),
+            LogProb::from(Prob(0.0001)),
+            2.0,
+        )
+        .unwrap()
+    }
+
+    fn prior(priors: &GenotypePriors, alleles: &Vec<String>, g: Genotype, ploidy: u8) -> f64 {
+        *Prob::from(priors.get_prior_with_ploidy(alleles, g, ploidy).unwrap())
+    }
+
+    #[test]
+    fn test_genotype_posteriors_no_haplotypes() {
+        // two reads with the ref allele and one with the alt allele, each with a 1% error rate
+        let flist = fragments_from_strings(&["0", "0", "1"], 0.01);
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+        let priors = generate_genotype_priors();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+
+        let posts =
+            calculate_genotype_posteriors_no_haplotypes(pileup, &priors, &alleles, 0.1, 2, &None).unwrap();
+
+        // P(reads | 0/0) = 0.99 * 0.99 * 0.01
+        // P(reads | 0/1) = (0.5 * 0.99 + 0.5 * 0.01)^3 = 0.125
+        // P(reads | 1/1) = 0.01 * 0.01 * 0.99
+        let p00 = prior(&priors, &alleles, Genotype(0, 0), 2) * 0.009801;
+        let p01 = prior(&priors, &alleles, Genotype(0, 1), 2) * 0.125;
+        let p10 = prior(&priors, &alleles, Genotype(1, 0), 2) * 0.125;
+        let p11 = prior(&priors, &alleles, Genotype(1, 1), 2) * 0.000099;
+        let total = p00 + p01 + p10 + p11;
+
+        for &(g, p) in &[
+            (Genotype(0, 0), p00),
+            (Genotype(0, 1), p01),
+            (Genotype(1, 0), p10),
+            (Genotype(1, 1), p11),
+        ] {
+            assert!((*Prob::from(posts.get(g)) - p / total).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_genotype_posteriors_no_haplotypes_quality_cutoff() {
+        // calls with an error probability above max_p_miscall are ignored, so the posteriors are
+        // equal to the priors
+        let flist = fragments_from_strings(&["1", "1"], 0.2);
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+        let priors = generate_genotype_priors();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+
+        let posts =
+            calculate_genotype_posteriors_no_haplotypes(pileup, &priors, &alleles, 0.1, 2, &None).unwrap();
+        let expected = priors.get_all_priors(&alleles).unwrap().normalize();
+
+        for g in possible_genotypes(&alleles) {
+            assert!((*Prob::from(posts.get(g)) - *Prob::from(expected.get(g))).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_genotype_posteriors_no_haplotypes_haploid() {
+        // at a haploid site, the same reads can only support genotype 0 or 1
+        let flist = fragments_from_strings(&["0", "0", "1"], 0.01);
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+        let priors = generate_genotype_priors();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+
+        let posts =
+            calculate_genotype_posteriors_no_haplotypes(pileup, &priors, &alleles, 0.1, 1, &None).unwrap();
+
+        let p0 = prior(&priors, &alleles, Genotype(0, 0), 1) * 0.009801;
+        let p1 = prior(&priors, &alleles, Genotype(1, 1), 1) * 0.000099;
+
+        assert_eq!(posts.get(Genotype(0, 1)), LogProb::ln_zero());
+        assert_eq!(posts.get(Genotype(1, 0)), LogProb::ln_zero());
+        assert!((*Prob::from(posts.get(Genotype(0, 0))) - p0 / (p0 + p1)).abs() < 1e-9);
+        assert!((*Prob::from(posts.get(Genotype(1, 1))) - p1 / (p0 + p1)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_allele_mapq_stats() {
+        let mut flist = fragments_from_strings(&["0", "0", "0", "1", "1", "-"], 0.01);
+        for (f, &mapq) in flist.iter_mut().zip([60, 50, 60, 10, 30, 60].iter()) {
+            f.mapq = mapq;
+        }
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+
+        let (medians, mads) = allele_mapq_stats(pileup, &flist, 3, 0.1);
+        assert_eq!(medians, vec![60.0, 20.0, 0.0]);
+        assert_eq!(mads, vec![0.0, 10.0, 0.0]);
+    }
+
+    #[test]
+    fn test_allele_call_stats() {
+        let mut flist = fragments_from_strings(&["0", "0", "1", "-"], 0.01);
+        for (f, &mapq) in flist.iter_mut().zip([60, 40, 20, 60].iter()) {
+            f.mapq = mapq;
+        }
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+
+        let (mapq_means, qual_means, llrs) = allele_call_stats(pileup, &flist, 3, 0.1);
+        assert_eq!(mapq_means, vec![50.0, 20.0, 0.0]);
+        assert!((qual_means[0] - 20.0).abs() < 1e-6);
+        assert!((qual_means[1] - 20.0).abs() < 1e-6);
+        assert_eq!(qual_means[2], 0.0);
+        // one call of allele 1 and two calls of the reference allele
+        let llr = (0.99f64 / 0.01).log10();
+        assert_eq!(llrs[0], 0.0);
+        assert!((llrs[1] + llr).abs() < 1e-6);
+        assert!((llrs[2] + 2.0 * llr).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_alt_allele_fraction() {
+        let flist = fragments_from_strings(&["0", "0", "1", "-"], 0.01);
+        let pileup = &generate_fragcall_pileup(&flist, 1)[0];
+
+        // the reference calls are alternate alleles with their miscall probability
+        let (alt_frac, (lower, upper)) = alt_allele_fraction(pileup, 0.1);
+        assert!((alt_frac - 1.01 / 3.0).abs() < 1e-9);
+        assert!(lower > 0.0 && lower < alt_frac && upper > alt_frac && upper < 1.0);
+        // the calls below the allele quality cutoff are not used
+        assert_eq!(alt_allele_fraction(pileup, 0.001), (0.0, (0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_apply_mismapping_probs() {
+        let mut flist = fragments_from_strings(&["11", "1-", "0-"], 0.01);
+        flist[0].mapq = 10;
+        flist[1].mapq = 60;
+        let varlist = VarList::new(
+            vec![
+                Var::new(0, 100, vec!["A".to_string(), "G".to_string()]),
+                Var::new(0, 200, vec!["C".to_string(), "T".to_string(), "G".to_string()]),
+            ],
+            vec!["chr1".to_string()],
+        )
+        .unwrap();
+
+        // fragments without a MAPQ are unchanged
+        assert_eq!(apply_mismapping_probs(&mut flist, &varlist), 3);
+        let expected = [0.9 * 0.01 + 0.05, 0.9 * 0.01 + 0.1 * 2.0 / 3.0];
+        for (call, &p) in flist[0].calls.iter().zip(expected.iter()) {
+            assert!((*Prob::from(call.qual) - p).abs() < 1e-9);
+            assert!((*Prob::from(call.one_minus_qual) - (1.0 - p)).abs() < 1e-9);
+        }
+        assert!((*Prob::from(flist[1].calls[0].qual) - (0.01 + 1e-6 * 0.49)).abs() < 1e-9);
+        assert_eq!(flist[2].calls[0].qual, LogProb::from(Prob(0.01)));
+    }
+
+    #[test]
+    fn test_calculate_cluster_quals() {
+        let mut lst: Vec<Var> = vec![];
+        for &(pos0, cluster_id, genotype, p00) in &[
+            (100, Some(101), Genotype(0, 1), 0.001),
+            (105, Some(101), Genotype(1, 1), 0.01),
+            (110, Some(101), Genotype(0, 0), 0.9),
+            (500, None, Genotype(0, 1), 0.001),
+        ] {
+            let mut var = Var::new(0, pos0, vec!["A".to_string(), "G".to_string()]);
+            var.cluster_id = cluster_id;
+            var.genotype = genotype;
+            var.genotype_post.set(Genotype(0, 0), LogProb::from(Prob(p00)));
+            lst.push(var);
+        }
+        let mut varlist = VarList::new(lst, vec!["chr1".to_string()]).unwrap();
+
+        calculate_cluster_quals(&mut varlist);
+
+        // the reference call does not add to the cluster's probability of a wrong call
+        let expected = -10.0 * (0.011f64).log10();
+        for var in &varlist.lst[0..3] {
+            assert!((var.cluster_qual.unwrap() - expected).abs() < 1e-6);
+        }
+        assert_eq!(varlist.lst[3].cluster_qual, None);
+    }
 }
@@ -3,14 +3,17 @@
 extern crate rust_htslib;
 
 use std::char;
+use std::collections::{BTreeMap, VecDeque};
 
-use bio::io::fasta;
 use bio::stats::{LogProb, Prob};
 use rust_htslib::bam;
 use rust_htslib::bam::pileup::Indel;
-use rust_htslib::bam::Read;
+use rust_htslib::bam::record::Cigar;
 
+use allele_fractions::binomial_upper_tail;
+use callable_regions::{callable_status, CallableRegions};
 use errors::*;
+use extract_fragments::ReadFilter;
 //use std::str;
 //use bio::alignment::Alignment;
 //use bio::alignment::pairwise::banded::*;
@@ -25,6 +28,403 @@ use variants_and_fragments::*;
 pub static VARLIST_CAPACITY: usize = 0; //1000000;
 static VERBOSE: bool = false; //true;
 
+/// The minimum number and fraction of alternate allele observations for a site to be considered a
+/// potential variant, as a function of the read depth at the site.
+#[derive(Clone, Debug)]
+pub struct CandidateThresholds {
+    /// ```min_alt_counts[d]``` is the minimum alternate allele count at depth ```d```. If this is
+    /// empty, ```min_alt_count``` and ```min_alt_frac``` are used at every depth.
+    min_alt_counts: Vec<usize>,
+    min_alt_count: usize,
+    min_alt_frac: f64,
+}
+
+impl CandidateThresholds {
+    /// Uses the same minimum alternate allele count and fraction at every depth
+    pub fn fixed(min_alt_count: usize, min_alt_frac: f64) -> CandidateThresholds {
+        CandidateThresholds {
+            min_alt_counts: vec![],
+            min_alt_count,
+            min_alt_frac,
+        }
+    }
+
+    /// Derives the minimum alternate allele count at each depth from the sequencing error model
+    ///
+    /// At depth ```d```, the probability that sequencing errors alone produce ```k``` or more
+    /// observations of one of the 3 non-reference bases is at most
+    /// ```3 * P(Binomial(d, p_miscall) >= k)```. The expected fraction of false candidates (FDR)
+    /// among sites with ```k``` or more alternate observations is then this probability divided by
+    /// itself plus ```variant_rate```, and the threshold is the smallest ```k``` (at least 2) for
+    /// which it is no more than ```max_fdr```.
+    ///
+    /// With a ```min_sensitivity```, the threshold is lowered (but not below 2) where it would
+    /// miss more heterozygous variants than that: a read from the alternate haplotype shows the
+    /// alternate base with probability ```1 - 3 * p_miscall```, so the alternate allele count of a
+    /// heterozygous variant at depth ```d``` is ```Binomial(d, (1 - 3 * p_miscall) / 2)```, and
+    /// the threshold is at most the largest ```k``` that this count reaches with probability
+    /// ```min_sensitivity```.
+    ///
+    /// # Arguments
+    /// -```p_miscall```: the probability that a sequencing error changes a base to one specific other base
+    /// -```variant_rate```: the prior probability that a site has a (non-reference) variant
+    /// -```max_fdr```: the maximum expected fraction of potential variants that are false
+    /// -```min_sensitivity```: the minimum fraction of heterozygous variants that pass the threshold
+    /// -```max_depth```: the maximum depth at which a site can be a potential variant
+    pub fn from_error_model(
+        p_miscall: f64,
+        variant_rate: f64,
+        max_fdr: f64,
+        min_sensitivity: Option<f64>,
+        max_depth: usize,
+    ) -> CandidateThresholds {
+        let ln_3 = LogProb((3.0 as f64).ln());
+        // p_false / (p_false + variant_rate) <= max_fdr  <=>  p_false <= variant_rate * max_fdr / (1 - max_fdr)
+        let max_p_false = LogProb::from(Prob(variant_rate * max_fdr / (1.0 - max_fdr)));
+        let p_het_alt = (1.0 - 3.0 * p_miscall).max(0.0) / 2.0;
+
+        let mut min_alt_counts: Vec<usize> = Vec::with_capacity(max_depth + 1);
+        let mut k: usize = 2;
+        let mut k_sensitive: usize = 0;
+        for d in 0..(max_depth + 1) {
+            // the thresholds never decrease with depth, so we continue from the previous values
+            while k <= d && ln_3 + binomial_upper_tail(k, d, p_miscall) > max_p_false {
+                k += 1;
+            }
+            match min_sensitivity {
+                Some(sensitivity) => {
+                    let ln_sensitivity = LogProb::from(Prob(sensitivity));
+                    while k_sensitive < d
+                        && binomial_upper_tail(k_sensitive + 1, d, p_het_alt) >= ln_sensitivity
+                    {
+                        k_sensitive += 1;
+                    }
+                    min_alt_counts.push(k.min(k_sensitive).max(2));
+                }
+                None => min_alt_counts.push(k),
+            }
+        }
+
+        CandidateThresholds {
+            min_alt_count: min_alt_counts[max_depth],
+            min_alt_counts,
+            min_alt_frac: 0.0,
+        }
+    }
+
+    /// Returns the thresholds to use on a contig with the given parameter overrides. An override
+    /// of the minimum alternate allele count replaces the counts for every depth, and an override
+    /// of the minimum fraction replaces the fraction.
+    pub fn for_contig(&self, contig_params: Option<&ContigParams>) -> CandidateThresholds {
+        let mut thresholds = self.clone();
+        if let Some(p) = contig_params {
+            if let Some(min_alt_count) = p.min_alt_count {
+                thresholds.min_alt_counts = vec![];
+                thresholds.min_alt_count = min_alt_count;
+            }
+            if let Some(min_alt_frac) = p.min_alt_frac {
+                thresholds.min_alt_frac = min_alt_frac;
+            }
+        }
+        thresholds
+    }
+
+    /// returns the tuple ```(min_alt_count, min_alt_frac)``` to use at a site with this read depth
+    pub fn get(&self, depth: usize) -> (usize, f64) {
+        if self.min_alt_counts.is_empty() {
+            (self.min_alt_count, self.min_alt_frac)
+        } else if depth < self.min_alt_counts.len() {
+            (self.min_alt_counts[depth], self.min_alt_frac)
+        } else {
+            (self.min_alt_count, self.min_alt_frac)
+        }
+    }
+
+    /// returns true if the alternate allele count passes the thresholds at this read depth
+    pub fn passes(&self, alt_count: usize, depth: usize) -> bool {
+        let (min_alt_count, min_alt_frac) = self.get(depth);
+        alt_count >= min_alt_count && (alt_count as f64) / (depth as f64) >= min_alt_frac
+    }
+
+    /// Prints the effective thresholds in depth strata (doubling in size, 1-1, 2-3, 4-7, ...)
+    pub fn print_depth_strata(&self) {
+        if self.min_alt_counts.is_empty() {
+            info!(
+                "{}all depths: min alt count {}, min alt fraction {:.3}",
+                SPACER, self.min_alt_count, self.min_alt_frac
+            );
+            return;
+        }
+        let max_depth = self.min_alt_counts.len() - 1;
+        let mut lo: usize = 1;
+        while lo <= max_depth {
+            let hi = (2 * lo - 1).min(max_depth);
+            info!(
+                "{}depth {}-{}: min alt count {}-{}, min alt fraction {:.3}-{:.3}",
+                SPACER,
+                lo,
+                hi,
+                self.min_alt_counts[lo],
+                self.min_alt_counts[hi],
+                self.min_alt_counts[hi] as f64 / hi as f64,
+                self.min_alt_counts[lo] as f64 / lo as f64,
+            );
+            lo = hi + 1;
+        }
+    }
+}
+
+/// The observations of the reads overlapping one reference position, as used to find potential
+/// variants there
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PileupColumn {
+    tid: u32,
+    pos: u32,
+    /// the number of reads overlapping the position, before any filters
+    n_reads: usize,
+    /// the number of reads passing the standard filters (not secondary, QC-failed, duplicate or
+    /// supplementary), at any mapping quality
+    passing_reads: usize,
+    /// the number of passing reads with a mapping quality of at least 10, 20, 30, 40 and 50
+    mq_counts: [usize; 5],
+    /// the number of passing reads with at least the minimum mapping quality that count toward the
+    /// read depth
+    depth: usize,
+    /// the number of each base (A,C,G,T,N) aligned to the position
+    counts: [usize; 5],
+    /// the number of each inserted sequence following the base aligned to the position
+    insertions: BTreeMap<Vec<u8>, usize>,
+    /// the number of deletions of each length following the base aligned to the position
+    deletions: BTreeMap<usize, usize>,
+}
+
+impl PileupColumn {
+    fn new(tid: u32, pos: u32) -> PileupColumn {
+        PileupColumn {
+            tid: tid,
+            pos: pos,
+            ..Default::default()
+        }
+    }
+
+    /// counts a read overlapping the position toward the totals (but not the bases)
+    fn add_read(&mut self, passing: bool, mapq: u8, counts_depth: bool) {
+        self.n_reads += 1;
+        if !passing {
+            return;
+        }
+        self.passing_reads += 1;
+        for (i, &cutoff) in [10, 20, 30, 40, 50].iter().enumerate() {
+            if mapq >= cutoff {
+                self.mq_counts[i] += 1;
+            }
+        }
+        if counts_depth {
+            self.depth += 1;
+        }
+    }
+}
+
+/// Returns the insertion or deletion that htslib reports on the last base of an aligned block,
+/// given the CIGAR operations after the block: a deletion or insertion right after it, or the
+/// insertions after a padding operation.
+fn following_indel(ops: &[Cigar]) -> Indel {
+    match ops.first() {
+        Some(&Cigar::Del(l)) => Indel::Del(l),
+        Some(&Cigar::Ins(l)) => Indel::Ins(l),
+        Some(&Cigar::Pad(_)) if ops.len() > 1 => {
+            let mut inserted = 0;
+            for op in &ops[1..] {
+                match *op {
+                    Cigar::Ins(l) => inserted += l,
+                    Cigar::Del(_)
+                    | Cigar::Match(_)
+                    | Cigar::RefSkip(_)
+                    | Cigar::Equal(_)
+                    | Cigar::Diff(_) => break,
+                    _ => {}
+                }
+            }
+            if inserted > 0 {
+                Indel::Ins(inserted)
+            } else {
+                Indel::None
+            }
+        }
+        _ => Indel::None,
+    }
+}
+
+/// A single streaming pass over the sorted reads of a fetched region, which replaces the htslib
+/// pileup for candidate discovery
+///
+/// Each read's CIGAR is walked once when it is added, adding its observations to a buffer of the
+/// columns of the positions it overlaps. The columns before the start of a read are complete when
+/// the read is added, since the reads are sorted. The htslib pileup instead revisits every
+/// overlapping read at every position, which is slow on high-depth data such as amplicons.
+///
+/// The observations are the same as the ones counted from the htslib pileup: every read except
+/// unmapped reads makes a column, only the reads that pass the ```ReadFilter``` are counted, the base on the last base of an aligned block is replaced by the
+/// insertion or deletion that follows it (see ```following_indel```), and deletions and reference
+/// skips count as gaps. Unlike the htslib pileup, which by default stops adding the reads that start
+/// at a position once 8000 reads overlap it, every read is counted.
+struct PileupColumns {
+    read_filter: ReadFilter,
+    max_indel_len: usize,
+    depth_definition: DepthDefinition,
+    /// the contig of the reads that were added
+    tid: i32,
+    /// the position of the first column in ```active```
+    start: u32,
+    /// the columns that a later read can still overlap, one for each position from ```start```
+    active: VecDeque<PileupColumn>,
+    /// the complete columns overlapped by at least one read, in order
+    complete: VecDeque<PileupColumn>,
+}
+
+impl PileupColumns {
+    fn new(
+        read_filter: &ReadFilter,
+        max_indel_len: usize,
+        depth_definition: DepthDefinition,
+    ) -> PileupColumns {
+        PileupColumns {
+            read_filter: *read_filter,
+            max_indel_len: max_indel_len,
+            depth_definition: depth_definition,
+            tid: -1,
+            start: 0,
+            active: VecDeque::new(),
+            complete: VecDeque::new(),
+        }
+    }
+
+    /// completes the active columns before ```pos```
+    fn complete_before(&mut self, pos: u32) {
+        while self.active.front().map_or(false, |c| c.pos < pos) {
+            if let Some(column) = self.active.pop_front() {
+                if column.n_reads > 0 {
+                    self.complete.push_back(column);
+                }
+            }
+        }
+        self.start = match self.active.front() {
+            Some(column) => column.pos,
+            None => pos,
+        };
+    }
+
+    /// the active column of ```pos```, which must not be before ```start```
+    fn column(&mut self, pos: u32) -> &mut PileupColumn {
+        let ix = (pos - self.start) as usize;
+        while self.active.len() <= ix {
+            let next_pos = self.start + self.active.len() as u32;
+            self.active
+                .push_back(PileupColumn::new(self.tid as u32, next_pos));
+        }
+        &mut self.active[ix]
+    }
+
+    /// Adds the observations of a read, which must not start before the reads added earlier
+    fn push(&mut self, record: &bam::Record) -> Result<()> {
+        // like the htslib pileup, only unmapped reads are skipped here
+        if record.tid() < 0 || record.is_unmapped() {
+            return Ok(());
+        }
+        let start = record.pos() as u32;
+        if record.tid() != self.tid {
+            self.complete_before(u32::max_value());
+            self.tid = record.tid();
+            self.start = start;
+        }
+        ensure!(
+            start >= self.start,
+            "The reads of the BAM file are not sorted by position: read {} starts before the previous read.",
+            String::from_utf8_lossy(record.qname())
+        );
+        self.complete_before(start);
+
+        // reads of any mapping quality are counted in the MAPQ statistics of a column
+        let passing = self.read_filter.check_any_mapq(record).is_none();
+        let mapq = record.mapq();
+        let used = passing && mapq >= self.read_filter.min_mapq;
+        let base_counts_depth = used && self.depth_definition.counts_gap(false);
+        let gap_counts_depth = used && self.depth_definition.counts_gap(true);
+        let max_indel_len = self.max_indel_len;
+
+        let seq = record.seq();
+        let cigar = record.cigar();
+        let ops: &[Cigar] = &cigar;
+        let mut ref_pos = start;
+        let mut qpos: usize = 0;
+        for (k, op) in ops.iter().enumerate() {
+            match *op {
+                Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                    let indel = following_indel(&ops[k + 1..]);
+                    for i in 0..l {
+                        let column = self.column(ref_pos + i);
+                        column.add_read(passing, mapq, base_counts_depth);
+                        if !used {
+                            continue;
+                        }
+                        let q = qpos + i as usize;
+                        let indel_here = if i + 1 == l { indel } else { Indel::None };
+                        match indel_here {
+                            Indel::None => {
+                                let b = match seq[q] as char {
+                                    'A' | 'a' => 0,
+                                    'C' | 'c' => 1,
+                                    'G' | 'g' => 2,
+                                    'T' | 't' => 3,
+                                    _ => 4,
+                                };
+                                column.counts[b] += 1;
+                            }
+                            Indel::Ins(n) if (n as usize) <= max_indel_len => {
+                                // the inserted bases follow the base aligned to this position
+                                if q + n as usize >= seq.len() {
+                                    continue;
+                                }
+                                let inserted: Vec<u8> =
+                                    ((q + 1)..(q + 1 + n as usize)).map(|j| seq[j]).collect();
+                                *column.insertions.entry(inserted).or_insert(0) += 1;
+                            }
+                            Indel::Del(n) if (n as usize) <= max_indel_len => {
+                                *column.deletions.entry(n as usize).or_insert(0) += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                    ref_pos += l;
+                    qpos += l as usize;
+                }
+                Cigar::Del(l) | Cigar::RefSkip(l) => {
+                    for i in 0..l {
+                        self.column(ref_pos + i)
+                            .add_read(passing, mapq, gap_counts_depth);
+                    }
+                    ref_pos += l;
+                }
+                Cigar::Ins(l) | Cigar::SoftClip(l) => {
+                    qpos += l as usize;
+                }
+                Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// completes all of the columns, after the last read
+    fn finish(&mut self) {
+        self.complete_before(u32::max_value());
+    }
+
+    /// the next complete column
+    fn pop(&mut self) -> Option<PileupColumn> {
+        self.complete.pop_front()
+    }
+}
+
 /// Calls potential SNV sites using a pileup-based genotyping calculation
 ///
 /// Potential SNVs are identified by performing a relatively standard pileup-based
@@ -33,14 +433,20 @@ static VERBOSE: bool = false; //true;
 /// in the genome, we perform a genotyping calculation and consider every site meeting a low threshold
 /// for variant evidence (by default, probability of non-reference genotype > 0.001) as a potential SNV
 ///
+/// The pileup columns are built in a single streaming pass over the sorted reads of each region
+/// (see ```PileupColumns```).
+///
 /// # Arguments
 /// -```bam_file```: the input BAM file name as a string
 /// -```fasta_file```: the input FASTA file name as a string
 /// -```interval```: optional struct holding the genomic interval to call variants in
-/// -```genotype_priors```: struct holding the genotype priors
+/// -```genotype_priors```: the genotype prior (see ```GenotypePrior```)
 /// -```min_coverage```: the minimum read coverage to consider a site as a potential variant
 /// -```max_coverage```: the maximum read coverage to consider a site as a potential variant
-/// -```min_mapq```: the minimum mapping quality to use a read in variant calling
+/// -```thresholds```: the minimum number and fraction of alternate allele observations for a
+///                     potential variant, depending on the read depth
+/// -```read_filter```: the flag, mapping quality and length filters of the reads to use in variant
+///                     calling, the same as for fragment extraction
 /// -```max_p_miscall```: the maximum probability of an allele miscall to count the allele (equivalent
 /////                     to the minimum allowed allele quality, but represented as a normal probability
 /////                     rather than PHRED-scaled)
@@ -52,6 +458,20 @@ static VERBOSE: bool = false; //true;
 ///                              genotype (by default 0.001). Any site with probability of
 ///                              non-reference genotype greater than this amount will be kept and
 ///                              considered as a potential SNV site.
+/// -```max_indel_len```: the maximum length of an insertion or deletion to consider as a potential
+///                       variant. Indel alleles are read from the pileup and evaluated at the base
+///                       preceding the indel (VCF convention). If this is 0, no indels are called.
+///                       If both an SNV and an indel pass at the same position, they are returned
+///                       as a single multiallelic variant.
+/// -```max_alt_alleles```: the maximum number of alternate SNV alleles at a site. The alternate
+///                         bases are considered in order of decreasing count, and every one that
+///                         passes the cutoffs on its own is kept, so that a site can be returned as
+///                         a multiallelic SNV.
+/// -```depth_definition```: which reads overlapping a site count toward its depth (DP) and the
+///                          coverage cutoffs
+/// -```contig_params```: per-contig overrides of ```max_coverage``` and ```thresholds```
+/// -```callable_regions```: if given, the status of every position of the intervals is added to it
+///                          (see ```CallableRegions```)
 ///
 /// # Returns
 /// Returns a result that wraps a VarList struct, representing the list of potential variants.
@@ -63,35 +483,37 @@ static VERBOSE: bool = false; //true;
 /// - ```IndexedFastaOpenError```: error opening the indexed FASTA file
 /// - ```IndexedBamOpenError```: error opening the indexed BAM file
 /// - ```IndexedBamFetchError```: error fetching a region from the indexed BAM file
-/// - ```IndexedBamPileupReadError```: error retrieving pileup from indexed BAM file
+/// - ```CorruptBamRecord```: error reading a record from the indexed BAM file
 /// - ```IndexedFastaReadError```: error reading an entry from the indexed FASTA file
-/// - ```IndexedBamPileupQueryPositionError```: error accessing the query position from the BAM pileup
+/// - The reads of the BAM file are not sorted by position
 /// - Error calculating genotype posteriors for reference genotype qual calculation (usually having to
 ///          do with accessing invalid genotypes)
 pub fn call_potential_snvs(
     bam_file: &String,
     fasta_file: &String,
     interval: &Option<GenomicInterval>,
-    genotype_priors: &GenotypePriors,
+    genotype_priors: &dyn GenotypePrior,
     min_coverage: u32,
     max_coverage: u32,
-    min_alt_count: usize,
-    min_alt_frac: f64,
-    min_mapq: u8,
+    thresholds: &CandidateThresholds,
+    read_filter: &ReadFilter,
     ln_align_params: LnAlignmentParameters,
     potential_snv_cutoff: LogProb,
+    max_indel_len: usize,
+    max_alt_alleles: usize,
+    depth_definition: DepthDefinition,
+    contig_params: &Vec<ContigParams>,
+    mut callable_regions: Option<&mut CallableRegions>,
 ) -> Result<VarList> {
     // the list of target (contig) names from the bam file
     let target_names = parse_target_names(&bam_file)?;
     let bases = ['A', 'C', 'G', 'T', 'N'];
 
-    let mut fasta = fasta::IndexedReader::from_file(&fasta_file)
-        .chain_err(|| ErrorKind::IndexedFastaOpenError)?;
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
 
     let mut varlist: Vec<Var> = Vec::with_capacity(VARLIST_CAPACITY);
 
     // pileup over all covered sites
-    let mut ref_seq: Vec<char> = vec![]; // this vector will be used to hold the reference sequence
     let mut prev_tid = 4294967295;
 
     let mut genotype_priors_table: [[(LogProb, LogProb, LogProb); 4]; 4] =
@@ -128,233 +550,231 @@ pub fn call_potential_snvs(
     // interval separately, which effectively just iterates over all the BAM entries.
     //
     // the reason for this strange design (instead of either fetching a region beforehand or not and
-    // then just iterating over all of ```bam_ix.pileup()```) is the following:
-    // if an indexed reader is used, and fetch is never called, pileup() hangs.
+    // then just iterating over all of the reads) is the following:
+    // if an indexed reader is used, and fetch is never called, pileup() hangs and accessing BAM
+    // records fails.
 
     let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
     let mut bam_ix =
-        bam::IndexedReader::from_path(bam_file).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+        open_indexed_bam(bam_file)?;
+
+    let mut progress = ProgressReporter::new(
+        "Potential SNV calling",
+        "reference bases",
+        interval_lst_len(&interval_lst),
+    );
+    let mut bases_done: usize = 0;
 
     // interval_lst has either a single genomic interval (if --region was specified) or a list of
     // genomic intervals for each chromosome covering the entire genome
     for iv in interval_lst {
+        let iv_bases_done = bases_done;
+        bases_done += (iv.end_pos - iv.start_pos + 1) as usize;
+
+        // the coverage cutoff and thresholds can be overridden for each contig
+        let max_coverage = contig_max_cov(contig_params, iv.tid, max_coverage);
+        let thresholds: &CandidateThresholds =
+            &thresholds.for_contig(contig_params.get(iv.tid as usize));
+
         bam_ix
             .fetch(iv.tid as u32, iv.start_pos as u32, iv.end_pos as u32 + 1)
             .chain_err(|| ErrorKind::IndexedBamFetchError)?;
-        let bam_pileup = bam_ix.pileup();
+        let mut columns = PileupColumns::new(read_filter, max_indel_len, depth_definition);
+        let mut record = bam::Record::new();
+        let mut more_reads = true;
+        if let Some(ref mut callable) = callable_regions {
+            callable.start_interval(&iv);
+        }
 
         // this variable is used to avoid having a variant inside a previous variant's deletion.
         let mut next_valid_pos = 0;
 
-        // iterate over base pileup
-        for p in bam_pileup {
-            let pileup = p.chain_err(|| ErrorKind::IndexedBamPileupReadError)?;
-
-            let tid: usize = pileup.tid() as usize;
-            let chrom: String = target_names[tid].clone();
-
-            // if we're on a different contig/chrom, we need to read in the sequence for that
-            // contig/chrom from the FASTA into the ref_seq vector
-            if tid != prev_tid {
-                let mut ref_seq_u8: Vec<u8> = vec![];
-                fasta
-                    .fetch_all(&chrom)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                fasta
-                    .read(&mut ref_seq_u8)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                ref_seq = dna_vec(&ref_seq_u8);
-                next_valid_pos = 0;
-            }
-
-            prev_tid = tid;
-
-            // this is specifically to avoid having a variant inside a previous variant's deletion.
-            if pileup.pos() < next_valid_pos {
-                continue;
+        // stream the reads, evaluating each pileup column as soon as no later read can overlap it
+        while more_reads {
+            more_reads = bam_ix.read(&mut record)?;
+            if more_reads {
+                columns.push(&record)?;
+            } else {
+                columns.finish();
             }
 
-            // ex pos = 10
-            // l_window = 6
-            // r_window = 15
-            // l..r = 6,7,8,9,10,11,12,13,14
-
-            let mut counts = [0 as usize; 5]; // A,C,G,T,N
+            while let Some(column) = columns.pop() {
+                progress.set(iv_bases_done + column.pos.saturating_sub(iv.start_pos) as usize);
 
-            // use a counter instead of pileup.depth() since that would include qc_fail bases, low mapq, etc.
-            let mut depth: usize = 0;
-            let pos: usize = pileup.pos() as usize;
-            let ref_allele = (ref_seq[pos] as char).to_ascii_uppercase();
+                let tid: usize = column.tid as usize;
+                let chrom: String = target_names[tid].clone();
 
-            if ref_allele == 'N' {
-                continue;
-            }
-
-            // the dna_vec conversion function should remove any non-ACGT bases
-            assert!(
-                ref_allele == 'A' || ref_allele == 'C' || ref_allele == 'G' || ref_allele == 'T'
-            );
+                if tid != prev_tid {
+                    next_valid_pos = 0;
+                }
 
-            let mut passing_reads: usize = 0; // total number of reads in this pileup at any mapq
-            let mut mq10: f64 = 0.0; // total number of reads in this pileup with mapq >= 10
-            let mut mq20: f64 = 0.0; // total number of reads in this pileup with mapq >= 20
-            let mut mq30: f64 = 0.0; // total number of reads in this pileup with mapq >= 30
-            let mut mq40: f64 = 0.0; // total number of reads in this pileup with mapq >= 40
-            let mut mq50: f64 = 0.0; // total number of reads in this pileup with mapq >= 50
+                prev_tid = tid;
 
-            // pileup the bases for a single position and count number of each base
-            for alignment in pileup.alignments() {
-                let record = alignment.record();
+                if let Some(ref mut callable) = callable_regions {
+                    if column.tid == iv.tid
+                        && column.pos >= iv.start_pos
+                        && column.pos <= iv.end_pos
+                    {
+                        let pos = column.pos as usize;
+                        callable.add_uncovered(pos, &mut ref_reader, &chrom)?;
+                        let ref_base = ref_reader.window(&chrom, pos, pos + 1)?[pos];
+                        callable.add(
+                            pos,
+                            callable_status(ref_base, column.depth, min_coverage, max_coverage),
+                        );
+                    }
+                }
 
-                // check that the read doesn't fail any standard filters
-                if record.is_secondary()
-                    || record.is_quality_check_failed()
-                    || record.is_duplicate()
-                    || record.is_supplementary()
-                {
+                // this is specifically to avoid having a variant inside a previous variant's deletion.
+                if column.pos < next_valid_pos {
                     continue;
                 }
 
-                // we're keeping track of how many reads meeting different MAPQ cutoff we see
-                // this information will be printed to the VCF and may help identify poor-mappability sites
-                // iterate counters if read meets a MAPQ cutoff
-                passing_reads += 1;
-                if record.mapq() >= 10 {
-                    mq10 += 1.0
-                };
-                if record.mapq() >= 20 {
-                    mq20 += 1.0
-                };
-                if record.mapq() >= 30 {
-                    mq30 += 1.0
-                };
-                if record.mapq() >= 40 {
-                    mq40 += 1.0
-                };
-                if record.mapq() >= 50 {
-                    mq50 += 1.0
-                };
+                let pos: usize = column.pos as usize;
+                // the reference from this position to the end of the longest deletion
+                let ref_seq = ref_reader.window(&chrom, pos, pos + max_indel_len + 2)?;
+                let ref_allele = (ref_seq[pos] as char).to_ascii_uppercase();
 
-                if record.is_unmapped() || record.mapq() < min_mapq {
+                if is_ambiguous_base(ref_allele) {
                     continue;
                 }
 
-                depth += 1; // depth counter does not consider unmapped low-mapq reads
+                // the dna_vec conversion function should remove any non-ACGT bases
+                assert!(
+                    ref_allele == 'A' || ref_allele == 'C' || ref_allele == 'G' || ref_allele == 'T'
+                );
+
+                // the depth counts only the reads passing the filters and the minimum mapping quality
+                let depth: usize = column.depth;
+                let counts = column.counts;
+                let passing_reads = column.passing_reads;
+
+                // counts of (ref allele, alt allele) pairs for indels beginning after this position
+                let mut indel_counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+                for (inserted, &count) in &column.insertions {
+                    let mut alt_allele: String = ref_allele.to_string();
+                    for &b in inserted {
+                        alt_allele.push((b as char).to_ascii_uppercase());
+                    }
+                    if has_non_acgt(&alt_allele) {
+                        continue;
+                    }
+                    *indel_counts
+                        .entry((ref_allele.to_string(), alt_allele))
+                        .or_insert(0) += count;
+                }
+                for (&l, &count) in &column.deletions {
+                    // the deleted reference bases follow this position
+                    if pos + l >= ref_seq.len() {
+                        continue;
+                    }
+                    let del_ref_allele: String = ref_seq[pos..(pos + l + 1)].iter().collect();
+                    if has_non_acgt(&del_ref_allele) {
+                        continue;
+                    }
+                    *indel_counts
+                        .entry((del_ref_allele, ref_allele.to_string()))
+                        .or_insert(0) += count;
+                }
 
-                // handle the base/indel observed on the read
-                if !alignment.is_del() && !alignment.is_refskip() {
-                    match alignment.indel() {
-                        Indel::None => {
-                            // read is NOT an indel (a base is observed)
+                // these values representing read mappability will be saved and written to the VCF later
+                let mq10_frac = column.mq_counts[0] as f64 / (passing_reads as f64);
+                let mq20_frac = column.mq_counts[1] as f64 / (passing_reads as f64);
+                let mq30_frac = column.mq_counts[2] as f64 / (passing_reads as f64);
+                let mq40_frac = column.mq_counts[3] as f64 / (passing_reads as f64);
+                let mq50_frac = column.mq_counts[4] as f64 / (passing_reads as f64);
 
-                            let base: char = alignment.record().seq()[alignment
-                                .qpos()
-                                .chain_err(|| ErrorKind::IndexedBamPileupQueryPositionError)?]
-                                as char;
+                if depth < min_coverage as usize {
+                    continue;
+                }
 
-                            let b = match base {
-                                'A' | 'a' => 0,
-                                'C' | 'c' => 1,
-                                'G' | 'g' => 2,
-                                'T' | 't' => 3,
-                                _ => 4
-                            };
+                if depth > max_coverage as usize {
+                    continue;
+                }
 
-                            counts[b] += 1;
-                        }
-                        _ => {}
+                // the most likely indel allele beginning after this position, if any
+                let potential_indel: Option<Vec<String>> = match call_potential_indel(
+                    &indel_counts,
+                    depth,
+                    genotype_priors,
+                    thresholds,
+                    ln_align_params,
+                )? {
+                    Some((indel_alleles, indel_qual)) if indel_qual > potential_snv_cutoff => {
+                        Some(indel_alleles)
+                    }
+                    _ => None,
+                };
+
+                let mut ref_count = 0;
+                let mut ref_allele_ix = 0;
+                for i in 0..4 {
+                    if bases[i] == ref_allele {
+                        ref_count = counts[i];
+                        ref_allele_ix = i;
                     }
                 }
-            }
 
-            // these values representing read mappability will be saved and written to the VCF later
-            let mq10_frac = mq10 / (passing_reads as f64);
-            let mq20_frac = mq20 / (passing_reads as f64);
-            let mq30_frac = mq30 / (passing_reads as f64);
-            let mq40_frac = mq40 / (passing_reads as f64);
-            let mq50_frac = mq50 / (passing_reads as f64);
+                // the alternate bases in order of decreasing count. Each one that meets the count
+                // cutoffs and the quality cutoff (against the reference allele alone) is kept, up to
+                // max_alt_alleles of them.
+                let mut alt_ixs: Vec<usize> = (0..4).filter(|&i| bases[i] != ref_allele).collect();
+                alt_ixs.sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+
+                let mut potential_snvs: Vec<char> = vec![];
+                for var_allele_ix in alt_ixs {
+                    let var_count = counts[var_allele_ix];
+                    if potential_snvs.len() >= max_alt_alleles
+                        || !thresholds.passes(var_count, depth)
+                    {
+                        break;
+                    }
 
-            if depth < min_coverage as usize {
-                continue;
-            }
+                    let snv_qual = potential_snv_qual(
+                        genotype_priors_table[ref_allele_ix][var_allele_ix],
+                        ref_count,
+                        var_count,
+                        ln_align_params,
+                    );
 
-            if depth > max_coverage as usize {
-                continue;
-            }
+                    // check if SNV meets our quality criteria for a potential SNV
+                    if snv_qual > potential_snv_cutoff {
+                        potential_snvs.push(bases[var_allele_ix]);
+                    }
+                }
 
-            let mut var_count = 0;
-            let mut ref_count = 0;
-            let mut var_allele = 'N';
-            let mut ref_allele_ix = 0;
-            let mut var_allele_ix = 0;
-
-            for i in 0..5 {
-                //base_cov += counts[i];
-                if bases[i] == ref_allele {
-                    ref_count = counts[i];
-                    ref_allele_ix = i;
+                // the SNV alleles and an indel at the same position are alternative alleles of a single
+                // multiallelic variant. The SNV alleles are padded with the reference bases spanned by
+                // the indel's reference allele, so that all of the alleles share one reference allele.
+                let mut alleles: Vec<String> = match potential_indel {
+                    Some(ref indel_alleles) => vec![indel_alleles[0].clone()],
+                    None => vec![ref_allele.to_string()],
+                };
+                for snv in potential_snvs {
+                    let mut snv_allele = snv.to_string();
+                    snv_allele.push_str(&alleles[0][1..]);
+                    alleles.push(snv_allele);
                 }
-                if counts[i] > var_count && bases[i] != ref_allele {
-                    var_count = counts[i];
-                    var_allele = bases[i];
-                    var_allele_ix = i;
+                if let Some(indel_alleles) = potential_indel {
+                    alleles.push(indel_alleles[1].clone());
+                }
+                if alleles.len() < 2 {
+                    continue;
                 }
-            }
-
-            let alt_frac: f64 = (var_count as f64) / (depth as f64);
-
-            if var_count < min_alt_count || alt_frac < min_alt_frac {
-                continue;
-            }
 
-            if var_allele == 'N' {
-                continue;
-            }
+                // we don't want potential variants that are inside a deletion, for instance.
+                next_valid_pos = (pos + alleles[0].len()) as u32;
 
-            // use a basic genotype likelihood calculation to call SNVs
-            // snv_qual is the LogProb probability of a non-reference base observation
-
-            let (prior_00, prior_01, prior_11) =
-                genotype_priors_table[ref_allele_ix][var_allele_ix];
-
-            // we dereference these so that they are f64 but in natural log space
-            // we want to be able to multiply them by some integer (raise to power),
-            // representing multiplying the independent probability that many times
-            let p_miscall = *ln_align_params.emission_probs.not_equal;
-            let p_call = *LogProb::ln_one_minus_exp(&ln_align_params.emission_probs.not_equal);
-            let ln_half = *LogProb::from(Prob(0.5)); // ln(0.5)
-            let ln_two = *LogProb::from(Prob(2.0)); // ln(2)
-            let p_het =
-                *LogProb::ln_add_exp(LogProb(ln_half + p_call), LogProb(ln_half + p_miscall));
-
-            // raise the probability of observing allele to the power of number of times we observed that allele
-            // fastest way of multiplying probabilities for independent events, where the
-            // probabilities are all the same (either quality score or 1 - quality score)
-            let p00 = LogProb(*prior_00 + p_call * ref_count as f64 + p_miscall * var_count as f64);
-            let p01 = LogProb(ln_two + *prior_01 + p_het * (ref_count + var_count) as f64);
-            let p11 = LogProb(*prior_11 + p_call * var_count as f64 + p_miscall * ref_count as f64);
-
-            // calculate the posterior probability of 0/0 genotype
-            let p_total = LogProb::ln_sum_exp(&[p00, p01, p11]);
-            //let post_00 = p00 - p_total;
-            let snv_qual = LogProb::ln_add_exp(p01, p11) - p_total; //LogProb::ln_one_minus_exp(&post_00);
-
-            next_valid_pos = (pos + 1) as u32;
-
-            // check if SNV meets our quality criteria for a potential SNV
-            // if it does, make a new variant and add it to the list of potential SNVs.
-            if snv_qual > potential_snv_cutoff && ref_allele != 'N' && var_allele != 'N' {
-                let tid: usize = pileup.tid() as usize;
                 let new_var = Var {
                     ix: 0,
                     // these will be set automatically,
                     tid: tid as u32,
                     pos0: pos,
-                    alleles: vec![ref_allele.to_string(), var_allele.to_string()],
+                    alleles: alleles.clone(),
                     dp: depth,
-                    allele_counts: vec![0, 0],
-                    allele_counts_forward: vec![0, 0],
-                    allele_counts_reverse: vec![0, 0],
+                    allele_counts: vec![0; alleles.len()],
+                    allele_counts_forward: vec![0; alleles.len()],
+                    allele_counts_reverse: vec![0; alleles.len()],
                     ambiguous_count: 0,
                     qual: 0.0,
                     filter: VarFilter::Pass,
@@ -363,32 +783,179 @@ pub fn call_potential_snvs(
                     gq: 0.0,
                     unphased_genotype: Genotype(0, 0),
                     unphased_gq: 0.0,
-                    genotype_post: GenotypeProbs::uniform(2),
+                    genotype_post: GenotypeProbs::uniform(alleles.len()),
+                    genotype_likelihoods: GenotypeProbs::uniform(alleles.len()),
                     phase_set: None,
                     strand_bias_pvalue: 0.0,
                     mec: 0,
                     mec_frac_variant: 0.0, // mec fraction for this variant
                     mec_frac_block: 0.0,   // mec fraction for this haplotype block
                     mean_allele_qual: 0.0,
+                    soft_clip_frac: 0.0,
                     dp_any_mq: passing_reads,
                     mq10_frac: mq10_frac,
                     mq20_frac: mq20_frac,
                     mq30_frac: mq30_frac,
                     mq40_frac: mq40_frac,
                     mq50_frac: mq50_frac,
+                    mapq_median: vec![0.0; alleles.len()],
+                    mapq_mad: vec![0.0; alleles.len()],
+                    mapq_mean: vec![0.0; alleles.len()],
+                    allele_qual_mean: vec![0.0; alleles.len()],
+                    realignment_llr: vec![0.0; alleles.len()],
+                    alt_frac: 0.0,
+                    alt_frac_ci: (0.0, 1.0),
+                    cluster_id: None,
+                    cluster_qual: None,
+                    cluster_split: false,
+                    realign_skipped: 0,
+                    de_novo_qual: None,
+                    somatic_qual: None,
+                    somatic_vaf: None,
+                    mosaic_qual: None,
+                    mosaic_vaf: None,
+                    annotations: vec![],
+                    genotype_concordance: None,
+                    phase_qual: None,
+                    ploidy: 2,
+                    population_af: None,
+                    merged_mnv: false,
+                    mnv_id: None,
+                    tandem_repeat: None,
+                    hap_counts: None,
+                    phased_no_ps: false,
                 };
 
-                // we don't want potential SNVs that are inside a deletion, for instance.
-                next_valid_pos = pileup.pos() + 1;
-
                 varlist.push(new_var);
             }
         }
+        if let Some(ref mut callable) = callable_regions {
+            callable.add_uncovered(iv.end_pos as usize + 1, &mut ref_reader, &iv.chrom)?;
+        }
     }
     // return the vector of Vars as a VarList struct
     Ok(VarList::new(varlist, target_names.clone())?)
 }
 
+/// Returns the (natural-log) probability of a non-reference genotype at a site with
+/// ```ref_count``` observations of the reference base and ```var_count``` observations of an
+/// alternate base, using a basic genotype likelihood calculation with the base substitution rate
+/// as the allele quality. ```priors``` holds the priors of the 0/0, 0/1 and 1/1 genotypes.
+fn potential_snv_qual(
+    priors: (LogProb, LogProb, LogProb),
+    ref_count: usize,
+    var_count: usize,
+    ln_align_params: LnAlignmentParameters,
+) -> LogProb {
+    let (prior_00, prior_01, prior_11) = priors;
+
+    // we dereference these so that they are f64 but in natural log space
+    // we want to be able to multiply them by some integer (raise to power),
+    // representing multiplying the independent probability that many times
+    let p_miscall = *ln_align_params.emission_probs.not_equal;
+    let p_call = *LogProb::ln_one_minus_exp(&ln_align_params.emission_probs.not_equal);
+    let ln_half = *LogProb::from(Prob(0.5)); // ln(0.5)
+    let ln_two = *LogProb::from(Prob(2.0)); // ln(2)
+    let p_het = *LogProb::ln_add_exp(LogProb(ln_half + p_call), LogProb(ln_half + p_miscall));
+
+    // raise the probability of observing allele to the power of number of times we observed that allele
+    // fastest way of multiplying probabilities for independent events, where the
+    // probabilities are all the same (either quality score or 1 - quality score)
+    let p00 = LogProb(*prior_00 + p_call * ref_count as f64 + p_miscall * var_count as f64);
+    let p01 = LogProb(ln_two + *prior_01 + p_het * (ref_count + var_count) as f64);
+    let p11 = LogProb(*prior_11 + p_call * var_count as f64 + p_miscall * ref_count as f64);
+
+    // calculate the posterior probability of a non-reference genotype
+    let p_total = LogProb::ln_sum_exp(&[p00, p01, p11]);
+    LogProb::ln_add_exp(p01, p11) - p_total
+}
+
+/// Evaluates the indel alleles observed at a single pileup position
+///
+/// The most frequently observed indel allele is scored with the same simple pileup genotype
+/// likelihood calculation used for potential SNVs. The probability that a read shows a spurious indel of
+/// length L is taken from the estimated pair-HMM gap open and gap extension probabilities.
+///
+/// # Arguments
+/// -```indel_counts```: map from (ref allele, alt allele) pairs to the number of reads observing them
+/// -```depth```: the number of reads passing filters at this position
+/// -```genotype_priors```: the genotype prior (see ```GenotypePrior```)
+/// -```thresholds```: the minimum number and fraction of reads observing the indel allele
+/// -```ln_align_params```: natural-log-scaled parameters for read alignment (Pair-HMM)
+///
+/// # Returns
+/// Returns ```None``` if no indel allele meets the count cutoffs, otherwise the alleles of the
+/// potential indel and the LogProb probability of a non-reference genotype
+fn call_potential_indel(
+    indel_counts: &BTreeMap<(String, String), usize>,
+    depth: usize,
+    genotype_priors: &dyn GenotypePrior,
+    thresholds: &CandidateThresholds,
+    ln_align_params: LnAlignmentParameters,
+) -> Result<Option<(Vec<String>, LogProb)>> {
+    let mut best: Option<(&(String, String), usize)> = None;
+    for (alleles, &count) in indel_counts.iter() {
+        match best {
+            Some((_, best_count)) if best_count >= count => {}
+            _ => {
+                best = Some((alleles, count));
+            }
+        }
+    }
+
+    let (&(ref ref_allele, ref alt_allele), var_count) = match best {
+        Some(b) => b,
+        None => {
+            return Ok(None);
+        }
+    };
+
+    if !thresholds.passes(var_count, depth) {
+        return Ok(None);
+    }
+
+    let alleles = vec![ref_allele.clone(), alt_allele.clone()];
+    let ref_count = if depth > var_count {
+        depth - var_count
+    } else {
+        0
+    };
+
+    let priors: GenotypeProbs = genotype_priors
+        .get_all_priors(&alleles)
+        .chain_err(|| "Error getting all genotype priors while calculating genotypes.")?;
+
+    // probability of a spurious indel of this length: gap open times gap extension for each extra base
+    let indel_len = if alt_allele.len() > ref_allele.len() {
+        alt_allele.len() - ref_allele.len()
+    } else {
+        ref_allele.len() - alt_allele.len()
+    };
+    let t = &ln_align_params.transition_probs;
+    let p_miscall = if alt_allele.len() > ref_allele.len() {
+        *t.insertion_from_match + *t.insertion_from_insertion * (indel_len - 1) as f64
+    } else {
+        *t.deletion_from_match + *t.deletion_from_deletion * (indel_len - 1) as f64
+    };
+    let p_call = *LogProb::ln_one_minus_exp(&LogProb(p_miscall));
+    let ln_half = *LogProb::from(Prob(0.5)); // ln(0.5)
+    let ln_two = *LogProb::from(Prob(2.0)); // ln(2)
+    let p_het = *LogProb::ln_add_exp(LogProb(ln_half + p_call), LogProb(ln_half + p_miscall));
+
+    let p00 = LogProb(
+        *priors.get(Genotype(0, 0)) + p_call * ref_count as f64 + p_miscall * var_count as f64,
+    );
+    let p01 = LogProb(ln_two + *priors.get(Genotype(0, 1)) + p_het * depth as f64);
+    let p11 = LogProb(
+        *priors.get(Genotype(1, 1)) + p_call * var_count as f64 + p_miscall * ref_count as f64,
+    );
+
+    let p_total = LogProb::ln_sum_exp(&[p00, p01, p11]);
+    let indel_qual = LogProb::ln_add_exp(p01, p11) - p_total;
+
+    Ok(Some((alleles, indel_qual)))
+}
+
 // alignment: a rust-bio alignment object where x is a read consensus window, and y is the window from the reference
 //
 // l_ref: the 0-indexed position on the reference of the start of the reference window
@@ -563,7 +1130,7 @@ pub fn call_potential_variants_poa(bam_file: &String,
     let target_names = parse_target_names(&bam_file);
 
     //let genotype_priors = estimate_genotype_priors();
-    let mut fasta = fasta::IndexedReader::from_file(&fasta_file).chain_err(|| ErrorKind::IndexedFastaOpenError)?;
+    let mut fasta = open_fasta(fasta_file)?;
 
     let mut varlist: Vec<Var> = Vec::with_capacity(VARLIST_CAPACITY);
 
@@ -578,7 +1145,7 @@ pub fn call_potential_variants_poa(bam_file: &String,
     // or a totally separate pileup from the unindexed file if not.
     let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval);
 
-    let mut bam_ix = bam::IndexedReader::from_path(bam_file).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+    let mut bam_ix = open_indexed_bam(bam_file)?;
 
     let alignment_type: i32 = 0;
     let match_score: i32 = 5;
@@ -822,3 +1389,300 @@ prev_tid = tid;
 Ok(VarList::new(varlist))
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use realignment::{AlignmentParameters, EmissionProbs, TransitionProbs};
+    use rust_htslib::bam::record::CigarString;
+
+    #[test]
+    fn test_candidate_thresholds_from_error_model() {
+        let thresholds = CandidateThresholds::from_error_model(0.005, 0.0015, 0.05, None, 100);
+        assert_eq!(thresholds.get(10), (3, 0.0));
+        assert_eq!(thresholds.get(20), (4, 0.0));
+        assert_eq!(thresholds.get(50), (5, 0.0));
+        assert_eq!(thresholds.get(100), (6, 0.0));
+        // depths beyond the table use the threshold for the maximum depth
+        assert_eq!(thresholds.get(1000), (6, 0.0));
+
+        assert!(thresholds.passes(5, 50));
+        assert!(!thresholds.passes(4, 50));
+
+        // a high error rate needs a high count, which misses heterozygous variants at low depth
+        let thresholds = CandidateThresholds::from_error_model(0.05, 0.0015, 0.05, None, 100);
+        let sensitive =
+            CandidateThresholds::from_error_model(0.05, 0.0015, 0.05, Some(0.95), 100);
+        for &d in &[5, 10, 20] {
+            assert!(sensitive.get(d).0 < thresholds.get(d).0);
+        }
+        assert_eq!(sensitive.get(3), (2, 0.0));
+        assert_eq!(sensitive.get(100), thresholds.get(100));
+    }
+
+    #[test]
+    fn test_candidate_thresholds_fixed() {
+        let thresholds = CandidateThresholds::fixed(3, 0.125);
+        assert!(thresholds.passes(3, 20));
+        assert!(!thresholds.passes(2, 10));
+        assert!(!thresholds.passes(3, 30));
+    }
+
+    #[test]
+    fn test_potential_snv_qual() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.879,
+                insertion_from_match: 0.1,
+                deletion_from_match: 0.021,
+                insertion_from_insertion: 0.4,
+                match_from_insertion: 0.6,
+                deletion_from_deletion: 0.25,
+                match_from_deletion: 0.75,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let priors = (
+            LogProb::from(Prob(0.9985)),
+            LogProb::from(Prob(0.001)),
+            LogProb::from(Prob(0.0005)),
+        );
+
+        // a heterozygous site, e.g. the second alternate allele of a triallelic site
+        let het = potential_snv_qual(priors, 10, 10, params.ln());
+        assert!(*Prob::from(het) > 0.999);
+        // a single alternate observation among many reference observations is an error
+        let err = potential_snv_qual(priors, 30, 1, params.ln());
+        assert!(*Prob::from(err) < 0.001);
+    }
+
+    fn make_record(
+        tid: i32,
+        pos: i32,
+        cigar: Vec<Cigar>,
+        seq: &[u8],
+        mapq: u8,
+        flags: u16,
+    ) -> bam::Record {
+        let mut record = bam::Record::new();
+        record.set(
+            b"read",
+            Some(&CigarString(cigar)),
+            seq,
+            &vec![30; seq.len()],
+        );
+        record.set_tid(tid);
+        record.set_pos(pos);
+        record.set_mapq(mapq);
+        record.set_flags(flags);
+        record
+    }
+
+    #[test]
+    fn test_following_indel() {
+        assert_eq!(
+            following_indel(&[Cigar::Del(2), Cigar::Match(3)]),
+            Indel::Del(2)
+        );
+        assert_eq!(
+            following_indel(&[Cigar::Ins(4), Cigar::Match(3)]),
+            Indel::Ins(4)
+        );
+        assert_eq!(
+            following_indel(&[
+                Cigar::Pad(1),
+                Cigar::Ins(2),
+                Cigar::Pad(1),
+                Cigar::Ins(1),
+                Cigar::Match(3),
+                Cigar::Ins(5)
+            ]),
+            Indel::Ins(3)
+        );
+        assert_eq!(following_indel(&[Cigar::SoftClip(3)]), Indel::None);
+        assert_eq!(following_indel(&[]), Indel::None);
+    }
+
+    #[test]
+    fn test_pileup_columns() {
+        for &depth_definition in &[DepthDefinition::All, DepthDefinition::GapExcluded] {
+            let reads = vec![
+                // ACG, TT inserted, GA, 1 base deleted, GC at positions 10-17
+                make_record(
+                    0,
+                    10,
+                    vec![
+                        Cigar::Match(3),
+                        Cigar::Ins(2),
+                        Cigar::Match(2),
+                        Cigar::Del(1),
+                        Cigar::Match(2),
+                    ],
+                    b"ACGTTGAGC",
+                    60,
+                    0,
+                ),
+                // a secondary alignment
+                make_record(0, 12, vec![Cigar::Match(4)], b"GGGG", 60, 0x100),
+                // below the minimum mapping quality
+                make_record(0, 12, vec![Cigar::Match(2)], b"GG", 5, 0),
+                // an unmapped read
+                make_record(0, 30, vec![Cigar::Match(2)], b"AA", 60, 0x4),
+                // soft-clipped, after a gap in the coverage
+                make_record(
+                    0,
+                    30,
+                    vec![Cigar::SoftClip(2), Cigar::Match(2)],
+                    b"NNCA",
+                    30,
+                    0x10,
+                ),
+            ];
+            let mut columns = PileupColumns::new(&ReadFilter::new(20), 10, depth_definition);
+            for read in &reads {
+                columns.push(read).unwrap();
+            }
+            columns.finish();
+            let mut col: Vec<PileupColumn> = vec![];
+            while let Some(column) = columns.pop() {
+                col.push(column);
+            }
+
+            let positions: Vec<u32> = col.iter().map(|c| c.pos).collect();
+            assert_eq!(positions, vec![10, 11, 12, 13, 14, 15, 16, 17, 30, 31]);
+
+            assert_eq!(col[0].counts, [1, 0, 0, 0, 0]);
+            assert_eq!(
+                (col[0].n_reads, col[0].passing_reads, col[0].depth),
+                (1, 1, 1)
+            );
+            assert_eq!(col[0].mq_counts, [1; 5]);
+
+            // the base before the insertion is replaced by the insertion
+            assert_eq!(col[2].counts, [0; 5]);
+            assert_eq!(col[2].insertions.get(&b"TT".to_vec()), Some(&1));
+            assert_eq!(
+                (col[2].n_reads, col[2].passing_reads, col[2].depth),
+                (3, 2, 1)
+            );
+            assert_eq!(col[2].mq_counts, [1; 5]);
+            assert_eq!(col[3].counts, [0, 0, 1, 0, 0]);
+
+            // and the base before the deletion by the deletion
+            assert_eq!(col[4].counts, [0; 5]);
+            assert_eq!(col[4].deletions.get(&1), Some(&1));
+            assert_eq!(col[5].n_reads, 2);
+            let gap_depth = if depth_definition == DepthDefinition::All {
+                1
+            } else {
+                0
+            };
+            assert_eq!(col[5].depth, gap_depth);
+            assert_eq!(col[7].counts, [0, 1, 0, 0, 0]);
+
+            assert_eq!(col[8].counts, [0, 1, 0, 0, 0]);
+            assert_eq!(col[9].counts, [1, 0, 0, 0, 0]);
+            assert_eq!(col[8].mq_counts, [1, 1, 1, 0, 0]);
+
+            // the reads must be sorted
+            let mut columns = PileupColumns::new(&ReadFilter::new(20), 10, depth_definition);
+            columns.push(&reads[4]).unwrap();
+            assert!(columns.push(&reads[0]).is_err());
+        }
+    }
+
+    /// the pileup columns of the fetched region, counted from the htslib pileup
+    fn htslib_columns(
+        bam_ix: &mut IndexedBamReader,
+        read_filter: &ReadFilter,
+        max_indel_len: usize,
+        depth_definition: DepthDefinition,
+    ) -> Vec<PileupColumn> {
+        let mut columns: Vec<PileupColumn> = vec![];
+        let mut bam_pileup = bam_ix.pileup();
+        bam_pileup.set_max_depth(i32::max_value() as u32);
+        for p in bam_pileup {
+            let pileup = p.unwrap();
+            let mut column = PileupColumn::new(pileup.tid(), pileup.pos());
+            for alignment in pileup.alignments() {
+                let record = alignment.record();
+                let passing = read_filter.check_any_mapq(&record).is_none();
+                let used = passing && record.mapq() >= read_filter.min_mapq;
+                column.add_read(
+                    passing,
+                    record.mapq(),
+                    used && depth_definition.counts(&alignment),
+                );
+                if !used || alignment.is_del() || alignment.is_refskip() {
+                    continue;
+                }
+                let seq = record.seq();
+                let qpos = alignment.qpos().unwrap();
+                match alignment.indel() {
+                    Indel::None => {
+                        let b = match seq[qpos] as char {
+                            'A' | 'a' => 0,
+                            'C' | 'c' => 1,
+                            'G' | 'g' => 2,
+                            'T' | 't' => 3,
+                            _ => 4,
+                        };
+                        column.counts[b] += 1;
+                    }
+                    Indel::Ins(l) if (l as usize) <= max_indel_len => {
+                        if qpos + l as usize >= seq.len() {
+                            continue;
+                        }
+                        let inserted: Vec<u8> = ((qpos + 1)..(qpos + 1 + l as usize))
+                            .map(|j| seq[j])
+                            .collect();
+                        *column.insertions.entry(inserted).or_insert(0) += 1;
+                    }
+                    Indel::Del(l) if (l as usize) <= max_indel_len => {
+                        *column.deletions.entry(l as usize).or_insert(0) += 1;
+                    }
+                    _ => {}
+                }
+            }
+            columns.push(column);
+        }
+        columns
+    }
+
+    #[test]
+    fn test_pileup_columns_match_htslib() {
+        let mut bam_ix = open_indexed_bam("example_data/pacbio_reads_30x.bam").unwrap();
+        for &(min_mapq, max_indel_len, depth_definition) in &[
+            (20, 50, DepthDefinition::All),
+            (0, 0, DepthDefinition::GapExcluded),
+        ] {
+            bam_ix.fetch(0, 50000, 60000).unwrap();
+            let read_filter = ReadFilter::new(min_mapq);
+            let expected =
+                htslib_columns(&mut bam_ix, &read_filter, max_indel_len, depth_definition);
+            assert!(expected.iter().any(|c| !c.insertions.is_empty()) || max_indel_len == 0);
+
+            bam_ix.fetch(0, 50000, 60000).unwrap();
+            let mut columns = PileupColumns::new(&read_filter, max_indel_len, depth_definition);
+            let mut record = bam::Record::new();
+            while bam_ix.read(&mut record).unwrap() {
+                columns.push(&record).unwrap();
+            }
+            columns.finish();
+            let mut streamed: Vec<PileupColumn> = vec![];
+            while let Some(column) = columns.pop() {
+                streamed.push(column);
+            }
+
+            assert_eq!(streamed.len(), expected.len());
+            for (s, e) in streamed.iter().zip(expected.iter()) {
+                assert_eq!(s, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Callable regions (```--out-callable```): where the caller actually looked for variants.
+//!
+//! The status of each position of the called regions is recorded during candidate discovery, from
+//! the effective read depth of its pileup column (the reads passing the filters and the minimum
+//! mapping quality) and the reference base. Positions that no read covers are ```NO_COVERAGE```
+//! (or ```REF_N``` if the reference is ambiguous there). The regions of the variants flagged by
+//! the variant density filter are marked as ```DENSE``` when the regions are written, since the
+//! filter is only applied after genotyping. Benchmarks (e.g. against the GIAB truth sets) can then
+//! be restricted to the callable regions.
+
+use errors::*;
+use util::{is_ambiguous_base, GenomicInterval, RefSeqReader};
+
+/// the number of reference bases read at a time when the positions without reads are classified
+static UNCOVERED_CHUNK: usize = 100000;
+
+/// Why the caller could or could not call variants at a position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallableStatus {
+    Callable,
+    /// no read passing the filters covers the position
+    NoCoverage,
+    /// the depth is below ```--min_cov```
+    LowCoverage,
+    /// the depth is above ```--max_cov```
+    ExcessiveCoverage,
+    /// the reference base is ambiguous (N or an IUPAC code)
+    RefN,
+    /// the position is in a region of variants flagged by the variant density filter
+    Dense,
+}
+
+impl CallableStatus {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CallableStatus::Callable => "CALLABLE",
+            CallableStatus::NoCoverage => "NO_COVERAGE",
+            CallableStatus::LowCoverage => "LOW_COVERAGE",
+            CallableStatus::ExcessiveCoverage => "EXCESSIVE_COVERAGE",
+            CallableStatus::RefN => "REF_N",
+            CallableStatus::Dense => "DENSE",
+        }
+    }
+}
+
+/// Returns the status of a position with the reference base ```ref_base``` and ```depth``` reads
+/// passing the filters.
+pub fn callable_status(
+    ref_base: char,
+    depth: usize,
+    min_coverage: u32,
+    max_coverage: u32,
+) -> CallableStatus {
+    if is_ambiguous_base(ref_base.to_ascii_uppercase()) {
+        CallableStatus::RefN
+    } else if depth == 0 {
+        CallableStatus::NoCoverage
+    } else if depth < min_coverage as usize {
+        CallableStatus::LowCoverage
+    } else if depth > max_coverage as usize {
+        CallableStatus::ExcessiveCoverage
+    } else {
+        CallableStatus::Callable
+    }
+}
+
+/// A run of positions with the same status
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallableRegion {
+    pub tid: u32,
+    /// 0-based start, and end (exclusive)
+    pub start: usize,
+    pub end: usize,
+    pub status: CallableStatus,
+}
+
+/// The status of every position of the called intervals, as runs of positions with the same
+/// status. The positions of each interval are added in increasing order.
+#[derive(Clone, Debug, Default)]
+pub struct CallableRegions {
+    regions: Vec<CallableRegion>,
+    /// the target ID of the current interval, and the next position that is expected in it
+    tid: u32,
+    next_pos: usize,
+}
+
+impl CallableRegions {
+    pub fn new() -> CallableRegions {
+        CallableRegions::default()
+    }
+
+    /// Starts a new interval. The positions before the first position added are filled in by
+    /// ```add_uncovered```.
+    pub fn start_interval(&mut self, iv: &GenomicInterval) {
+        self.tid = iv.tid;
+        self.next_pos = iv.start_pos as usize;
+    }
+
+    /// Sets the status of position ```pos``` of the current interval
+    pub fn add(&mut self, pos: usize, status: CallableStatus) {
+        let extend = match self.regions.last() {
+            Some(r) => r.tid == self.tid && r.end == pos && r.status == status,
+            None => false,
+        };
+        if extend {
+            self.regions.last_mut().unwrap().end = pos + 1;
+        } else {
+            self.regions.push(CallableRegion {
+                tid: self.tid,
+                start: pos,
+                end: pos + 1,
+                status: status,
+            });
+        }
+        self.next_pos = pos + 1;
+    }
+
+    /// Adds the positions of the current interval from the last position added up to ```end```
+    /// (exclusive), which no read covers: ```NO_COVERAGE```, or ```REF_N``` where the reference
+    /// base on ```chrom``` is ambiguous.
+    pub fn add_uncovered(
+        &mut self,
+        end: usize,
+        ref_reader: &mut RefSeqReader,
+        chrom: &str,
+    ) -> Result<()> {
+        while self.next_pos < end {
+            let chunk_start = self.next_pos;
+            let chunk_end = (chunk_start + UNCOVERED_CHUNK).min(end);
+            let statuses: Vec<CallableStatus> = {
+                let ref_seq = ref_reader.window(chrom, chunk_start, chunk_end)?;
+                (chunk_start..chunk_end)
+                    .map(|pos| callable_status(ref_seq[pos], 0, 0, 0))
+                    .collect()
+            };
+            for (i, status) in statuses.into_iter().enumerate() {
+                self.add(chunk_start + i, status);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the regions, with the positions in ```dense``` (target ID, 0-based start, and end
+    /// exclusive) marked as ```DENSE```. Positions with an ambiguous reference base stay
+    /// ```REF_N```.
+    pub fn regions(&self, dense: &[(u32, usize, usize)]) -> Vec<CallableRegion> {
+        let mut regions: Vec<CallableRegion> = self.regions.clone();
+        for &(tid, start, end) in dense {
+            let mut split: Vec<CallableRegion> = Vec::with_capacity(regions.len() + 2);
+            for r in regions {
+                if r.tid != tid
+                    || r.end <= start
+                    || r.start >= end
+                    || r.status == CallableStatus::RefN
+                {
+                    split.push(r);
+                    continue;
+                }
+                if r.start < start {
+                    split.push(CallableRegion {
+                        end: start,
+                        ..r.clone()
+                    });
+                }
+                split.push(CallableRegion {
+                    start: r.start.max(start),
+                    end: r.end.min(end),
+                    status: CallableStatus::Dense,
+                    ..r.clone()
+                });
+                if r.end > end {
+                    split.push(CallableRegion { start: end, ..r });
+                }
+            }
+            regions = split;
+        }
+
+        // the dense parts of adjacent regions are merged
+        let mut merged: Vec<CallableRegion> = Vec::with_capacity(regions.len());
+        for r in regions {
+            let extend = match merged.last() {
+                Some(m) => m.tid == r.tid && m.end == r.start && m.status == r.status,
+                None => false,
+            };
+            if extend {
+                merged.last_mut().unwrap().end = r.end;
+            } else {
+                merged.push(r);
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callable_status() {
+        assert_eq!(callable_status('A', 10, 5, 100), CallableStatus::Callable);
+        assert_eq!(callable_status('n', 10, 5, 100), CallableStatus::RefN);
+        assert_eq!(callable_status('R', 10, 5, 100), CallableStatus::RefN);
+        assert_eq!(callable_status('C', 0, 5, 100), CallableStatus::NoCoverage);
+        assert_eq!(callable_status('G', 3, 5, 100), CallableStatus::LowCoverage);
+        assert_eq!(
+            callable_status('T', 101, 5, 100),
+            CallableStatus::ExcessiveCoverage
+        );
+    }
+
+    #[test]
+    fn test_callable_regions() {
+        let mut callable = CallableRegions::new();
+        callable.start_interval(&GenomicInterval {
+            tid: 1,
+            chrom: "chr2".to_string(),
+            start_pos: 10,
+            end_pos: 29,
+        });
+        for pos in 10..20 {
+            callable.add(pos, CallableStatus::Callable);
+        }
+        callable.add(20, CallableStatus::RefN);
+        for pos in 21..30 {
+            callable.add(pos, CallableStatus::Callable);
+        }
+        let region = |start: usize, end: usize, status: CallableStatus| CallableRegion {
+            tid: 1,
+            start: start,
+            end: end,
+            status: status,
+        };
+        assert_eq!(
+            callable.regions(&[]),
+            vec![
+                region(10, 20, CallableStatus::Callable),
+                region(20, 21, CallableStatus::RefN),
+                region(21, 30, CallableStatus::Callable),
+            ]
+        );
+
+        // a dense region across the reference N, and one on another contig
+        assert_eq!(
+            callable.regions(&[(1, 15, 25), (0, 10, 30)]),
+            vec![
+                region(10, 15, CallableStatus::Callable),
+                region(15, 20, CallableStatus::Dense),
+                region(20, 21, CallableStatus::RefN),
+                region(21, 25, CallableStatus::Dense),
+                region(25, 30, CallableStatus::Callable),
+            ]
+        );
+        assert_eq!(
+            callable.regions(&[(1, 12, 14), (1, 14, 16)]),
+            vec![
+                region(10, 12, CallableStatus::Callable),
+                region(12, 16, CallableStatus::Dense),
+                region(16, 20, CallableStatus::Callable),
+                region(20, 21, CallableStatus::RefN),
+                region(21, 30, CallableStatus::Callable),
+            ]
+        );
+    }
+}
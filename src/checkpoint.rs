@@ -0,0 +1,206 @@
+//! Checkpoints of a run that calls its regions separately (```--stream_output``` or ```--bed```),
+//! so that an interrupted run (e.g. a preempted cluster job) can be resumed with ```--resume```.
+//!
+//! The checkpoint directory holds the alignment parameters of each sample, and a table with a line
+//! for every finished region that records the size of each output file after the region was
+//! written. A resumed run reads the alignment parameters instead of estimating them again,
+//! truncates the output files to their size after the last finished region (dropping the partial
+//! output of the interrupted region), and calls the remaining regions.
+
+use errors::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+use util::*;
+
+/// the name of the table of finished regions in the checkpoint directory
+static CHECKPOINT_FILE: &str = "checkpoint.tsv";
+
+fn region_name(iv: &GenomicInterval) -> String {
+    format!("{}:{}-{}", iv.chrom, iv.start_pos + 1, iv.end_pos + 1)
+}
+
+fn header_line(output_files: &Vec<String>) -> String {
+    format!("#region\t{}", output_files.join("\t"))
+}
+
+/// Parses a checkpoint table
+///
+/// # Returns
+/// Returns the finished regions with the size of each output file after the region was written.
+/// A last line that isn't terminated by a newline was being written when the run was interrupted,
+/// so it is ignored.
+///
+/// # Errors
+/// - if the output files of the table are not ```output_files```, i.e. the interrupted run was run
+///   with different outputs
+fn parse_checkpoint(contents: &str, output_files: &Vec<String>) -> Result<Vec<(String, Vec<u64>)>> {
+    let complete = match contents.rfind('\n') {
+        Some(i) => &contents[..i + 1],
+        None => "",
+    };
+    let mut lines = complete.lines();
+    ensure!(
+        lines.next() == Some(header_line(output_files).as_str()),
+        "The output files ({}) are not the output files of the interrupted run.",
+        output_files.join(", ")
+    );
+
+    let mut finished: Vec<(String, Vec<u64>)> = vec![];
+    for (line_num, line) in lines.enumerate() {
+        let cols: Vec<&str> = line.split('\t').collect();
+        let sizes: Vec<u64> = cols[1..].iter().flat_map(|s| s.parse::<u64>()).collect();
+        ensure!(
+            cols.len() == output_files.len() + 1 && sizes.len() == output_files.len(),
+            "Invalid line {} in checkpoint file.",
+            line_num + 2
+        );
+        finished.push((cols[0].to_string(), sizes));
+    }
+    Ok(finished)
+}
+
+pub struct Checkpoint {
+    dir: String,
+    /// the output files that are appended to after each region
+    output_files: Vec<String>,
+    /// the names of the finished regions
+    finished: Vec<String>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint in ```dir```, which is created if it doesn't exist
+    ///
+    /// # Errors
+    /// - if ```dir``` already has a checkpoint and ```force``` isn't set
+    pub fn create(dir: &String, output_files: &Vec<String>, force: bool) -> Result<Checkpoint> {
+        let checkpoint_file = Path::new(dir).join(CHECKPOINT_FILE);
+        ensure!(
+            !checkpoint_file.is_file() || force,
+            "Checkpoint directory {} already has a checkpoint. Rerun with --resume to resume the interrupted run, or with -F to start over.",
+            dir
+        );
+        fs::create_dir_all(dir).chain_err(|| "Error creating checkpoint directory.")?;
+        let mut file = File::create(&checkpoint_file)
+            .chain_err(|| ErrorKind::CreateFileError(checkpoint_file.display().to_string()))?;
+        writeln!(file, "{}", header_line(output_files))
+            .chain_err(|| ErrorKind::FileWriteError(checkpoint_file.display().to_string()))?;
+
+        Ok(Checkpoint {
+            dir: dir.clone(),
+            output_files: output_files.clone(),
+            finished: vec![],
+        })
+    }
+
+    /// Resumes the checkpoint in ```dir```, truncating the output files to their size after the
+    /// last finished region
+    pub fn resume(dir: &String, output_files: &Vec<String>) -> Result<Checkpoint> {
+        let checkpoint_file = Path::new(dir).join(CHECKPOINT_FILE);
+        let contents = fs::read_to_string(&checkpoint_file)
+            .chain_err(|| format!("Error reading checkpoint file in {}. Was the interrupted run started with --checkpoint_dir {}?", dir, dir))?;
+        let finished = parse_checkpoint(&contents, output_files)?;
+
+        if let Some(&(_, ref sizes)) = finished.last() {
+            for (output_file, &size) in output_files.iter().zip(sizes.iter()) {
+                if size == 0 && !Path::new(output_file).is_file() {
+                    continue;
+                }
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(output_file)
+                    .chain_err(|| ErrorKind::FileReadError(output_file.clone()))?;
+                let len = file
+                    .metadata()
+                    .chain_err(|| ErrorKind::FileReadError(output_file.clone()))?
+                    .len();
+                ensure!(
+                    len >= size,
+                    "Output file {} is shorter than at the last checkpoint.",
+                    output_file
+                );
+                file.set_len(size)
+                    .chain_err(|| ErrorKind::FileWriteError(output_file.clone()))?;
+            }
+        }
+        info!(
+            "{} Resuming the run from {}: {} region(s) were already finished.",
+            print_time(),
+            dir,
+            finished.len()
+        );
+
+        Ok(Checkpoint {
+            dir: dir.clone(),
+            output_files: output_files.clone(),
+            finished: finished.into_iter().map(|(name, _)| name).collect(),
+        })
+    }
+
+    /// Returns whether ```iv``` was finished before the run was interrupted
+    pub fn is_finished(&self, iv: &GenomicInterval) -> bool {
+        self.finished.contains(&region_name(iv))
+    }
+
+    /// The file that holds the alignment parameters of sample ```sample_ix```
+    pub fn params_file(&self, sample_ix: usize) -> String {
+        Path::new(&self.dir)
+            .join(format!("sample{}.params", sample_ix))
+            .display()
+            .to_string()
+    }
+
+    /// Records that ```iv``` is finished, with the current size of each output file
+    pub fn finish_region(&mut self, iv: &GenomicInterval) -> Result<()> {
+        let mut line = region_name(iv);
+        for output_file in &self.output_files {
+            // the outputs are flushed to disk before the region is recorded, so that they are
+            // complete up to the recorded size if the node goes down
+            if let Ok(f) = File::open(output_file) {
+                f.sync_all()
+                    .chain_err(|| ErrorKind::FileWriteError(output_file.clone()))?;
+            }
+            // an output may not have been written yet, e.g. if no variants were called
+            let size = fs::metadata(output_file).map(|m| m.len()).unwrap_or(0);
+            line.push_str(&format!("\t{}", size));
+        }
+
+        let checkpoint_file = Path::new(&self.dir).join(CHECKPOINT_FILE);
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&checkpoint_file)
+            .chain_err(|| ErrorKind::FileWriteError(checkpoint_file.display().to_string()))?;
+        writeln!(file, "{}", line)
+            .and_then(|_| file.sync_all())
+            .chain_err(|| ErrorKind::FileWriteError(checkpoint_file.display().to_string()))?;
+        self.finished.push(region_name(iv));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let output_files = vec!["out.vcf".to_string(), "out.sv.vcf".to_string()];
+        let contents =
+            "#region\tout.vcf\tout.sv.vcf\nchr1:1-1000\t2048\t512\nchr2:1-800\t4096\t700\nchr3:1-5";
+        let finished = parse_checkpoint(contents, &output_files).unwrap();
+        // the last line was interrupted
+        assert_eq!(
+            finished,
+            vec![
+                ("chr1:1-1000".to_string(), vec![2048, 512]),
+                ("chr2:1-800".to_string(), vec![4096, 700])
+            ]
+        );
+
+        // the interrupted run had different output files
+        assert!(parse_checkpoint(contents, &vec!["out.vcf".to_string()]).is_err());
+        assert!(
+            parse_checkpoint("#region\tout.vcf\tout.sv.vcf\nchr1\t10\n", &output_files).is_err()
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Whole-genome runs with several contigs called at the same time (```--threads```).
+//!
+//! Each contig is called by a separate longshot process, started with the options of this run
+//! plus ```--region <contig>```, which writes its calls and its log to a temporary directory next
+//! to the output VCF. The workers take the next contig from a shared queue as soon as they are
+//! done with the previous one, longest contigs first, so that a long chromosome isn't left to run
+//! on its own at the end. The VCF of each finished contig is compressed with bgzip and indexed,
+//! and when every contig is finished, they are merged as by ```longshot merge``` (see
+//! ```vcf_merge```).
+
+use errors::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use util::*;
+use vcf_merge::{compress_and_index_vcf, merge_vcfs};
+
+/// An option on the command line: the long name, the short name and whether it takes a value
+pub type CommandLineOption = (&'static str, Option<&'static str>, bool);
+
+/// The options of this run that the per-contig processes get a value of their own for
+pub static PER_CONTIG_OPTIONS: [CommandLineOption; 6] = [
+    ("--region", Some("-r"), true),
+    ("--out", Some("-o"), true),
+    ("--threads", None, true),
+    ("--contigs", None, true),
+    ("--scheduler", None, true),
+    ("--write-params", None, true),
+];
+
+/// The options of longshot whose values may start with a hyphen (```allow_hyphen_values```), so
+/// that the argument after them is their value even if it looks like an option
+pub static HYPHEN_VALUE_OPTIONS: [&str; 1] = ["--min_alignment_score"];
+
+/// Removes the given options (and their values) from the command line arguments, which are
+/// given without the program name. Options are recognized as ```--long value```,
+/// ```--long=value```, ```-s value``` and ```-svalue```, except for an argument that is the value
+/// of the option before it: the value of an option of ```hyphen_value_options``` is kept even if
+/// it starts like ```-s```.
+pub fn strip_options(
+    args: &[String],
+    options: &[CommandLineOption],
+    hyphen_value_options: &[&str],
+) -> Vec<String> {
+    let mut stripped: Vec<String> = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let a = &args[i];
+        let mut skip = 0;
+        // the value of the option before is kept as it is
+        let is_value = i > 0 && hyphen_value_options.contains(&args[i - 1].as_str());
+        for &(long, short, takes_value) in options.iter().filter(|_| !is_value) {
+            if a == long || short.map_or(false, |s| a == s) {
+                skip = if takes_value { 2 } else { 1 };
+            } else if takes_value
+                && (a.starts_with(&format!("{}=", long))
+                    || short.map_or(false, |s| a.starts_with(s) && !a.starts_with("--")))
+            {
+                skip = 1;
+            }
+        }
+        if skip == 0 {
+            stripped.push(a.clone());
+            i += 1;
+        } else {
+            i += skip;
+        }
+    }
+    stripped
+}
+
+/// Parses the contigs of ```--contigs```: either a comma-separated list of names, or a file
+/// with one name per line.
+///
+/// # Returns
+/// Returns the intervals of the whole contigs, in the order of the BAM header
+///
+/// # Errors
+/// Fails if a contig is not in the header of the BAM file.
+pub fn parse_contig_list(contigs: &str, bam_file: &String) -> Result<Vec<GenomicInterval>> {
+    let names: Vec<String> = if Path::new(contigs).is_file() {
+        let f = File::open(contigs).chain_err(|| format!("Could not open {}.", contigs))?;
+        let mut names = vec![];
+        for line in BufReader::new(f).lines() {
+            let line = line.chain_err(|| format!("Could not read {}.", contigs))?;
+            if !line.trim().is_empty() {
+                names.push(line.trim().to_string());
+            }
+        }
+        names
+    } else {
+        contigs
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+    ensure!(!names.is_empty(), "No contigs given with --contigs.");
+
+    let header_contigs = get_whole_genome_intervals(bam_file)?;
+    for name in &names {
+        ensure!(
+            header_contigs.iter().any(|iv| &iv.chrom == name),
+            "Contig {} of --contigs is not in the header of {}.",
+            name,
+            bam_file
+        );
+    }
+    Ok(header_contigs
+        .into_iter()
+        .filter(|iv| names.contains(&iv.chrom))
+        .collect())
+}
+
+/// Calls the variants of each contig in a separate longshot process, running up to ```threads```
+/// of them at a time, and merges their calls into ```output_vcf_file```. The VCF of each contig
+/// is compressed with bgzip and indexed when its process has finished.
+///
+/// # Arguments
+/// - ```child_args```: the command line options of the per-contig processes, except for
+///   ```--region``` and ```--out```
+/// - ```contigs```: the contigs to call, in the order of the BAM header
+/// - ```threads```: the number of contigs called at the same time
+/// - ```tmp_dir```: an empty directory for the VCFs and logs of the contigs. It is removed when
+///   all contigs were called successfully, and kept with the logs otherwise.
+///
+/// # Errors
+/// Fails if the process of a contig fails, after the processes that are already running have
+/// finished. No new contigs are started after the first failure.
+pub fn call_contigs_in_parallel(
+    child_args: &Vec<String>,
+    contigs: &Vec<GenomicInterval>,
+    threads: usize,
+    tmp_dir: &Path,
+    output_vcf_file: &str,
+) -> Result<()> {
+    let exe = env::current_exe().chain_err(|| "Could not find the longshot executable.")?;
+    let vcf_files: Vec<PathBuf> = (0..contigs.len())
+        .map(|i| tmp_dir.join(format!("contig_{}.vcf", i)))
+        .collect();
+    let vcf_gz_files: Vec<String> = vcf_files
+        .iter()
+        .map(|f| format!("{}.gz", f.display()))
+        .collect();
+
+    // the queue is popped from the end, so the longest contig is last
+    let mut queue: Vec<usize> = (0..contigs.len()).collect();
+    queue.sort_by_key(|&i| contigs[i].end_pos - contigs[i].start_pos);
+
+    let queue = Arc::new(Mutex::new(queue));
+    let errors: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+    let failed = Arc::new(AtomicBool::new(false));
+    let n_finished = Arc::new(AtomicUsize::new(0));
+    let jobs: Arc<Vec<(String, PathBuf, PathBuf, PathBuf)>> = Arc::new(
+        contigs
+            .iter()
+            .enumerate()
+            .map(|(i, iv)| {
+                (
+                    iv.chrom.clone(),
+                    vcf_files[i].clone(),
+                    PathBuf::from(&vcf_gz_files[i]),
+                    tmp_dir.join(format!("contig_{}.log", i)),
+                )
+            })
+            .collect(),
+    );
+
+    let mut workers = vec![];
+    for _ in 0..threads.min(contigs.len()) {
+        let (exe, child_args) = (exe.clone(), child_args.clone());
+        let (queue, errors, failed, n_finished, jobs) = (
+            queue.clone(),
+            errors.clone(),
+            failed.clone(),
+            n_finished.clone(),
+            jobs.clone(),
+        );
+        workers.push(thread::spawn(move || {
+            while !failed.load(Ordering::SeqCst) {
+                let i = match queue.lock().unwrap().pop() {
+                    Some(i) => i,
+                    None => break,
+                };
+                let (ref chrom, ref vcf_file, ref vcf_gz_file, ref log_file) = jobs[i];
+                info!("{} Calling variants on contig {}...", print_time(), chrom);
+                // the error messages of longshot are printed to stdout, so both go to the log
+                let status = File::create(log_file)
+                    .and_then(|log| Ok((log.try_clone()?, log)))
+                    .and_then(|(stdout, stderr)| {
+                        Command::new(&exe)
+                            .args(&child_args)
+                            .arg("--region")
+                            .arg(chrom)
+                            .arg("--out")
+                            .arg(vcf_file)
+                            .stdin(Stdio::null())
+                            .stdout(stdout)
+                            .stderr(stderr)
+                            .status()
+                    });
+                let error = match status {
+                    Ok(ref s) if s.success() => compress_and_index_vcf(vcf_file, vcf_gz_file)
+                        .err()
+                        .map(|e| format!("Could not index the VCF of contig {}: {}", chrom, e)),
+                    Ok(s) => Some(format!(
+                        "Calling contig {} failed ({}), see the log {}.",
+                        chrom,
+                        s,
+                        log_file.display()
+                    )),
+                    Err(e) => Some(format!("Could not run longshot on contig {}: {}", chrom, e)),
+                };
+                match error {
+                    None => {
+                        let n = n_finished.fetch_add(1, Ordering::SeqCst) + 1;
+                        info!(
+                            "{} Finished contig {} ({} of {}).",
+                            print_time(),
+                            chrom,
+                            n,
+                            jobs.len()
+                        );
+                    }
+                    Some(e) => {
+                        failed.store(true, Ordering::SeqCst);
+                        errors.lock().unwrap().push(e);
+                    }
+                }
+            }
+        }));
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            bail!(ErrorKind::InternalError("a worker thread panicked".to_string()));
+        }
+    }
+
+    let errors = errors.lock().unwrap();
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+
+    info!(
+        "{} Merging the calls of {} contigs...",
+        print_time(),
+        contigs.len()
+    );
+    merge_vcfs(&vcf_gz_files, output_vcf_file)?;
+    fs::remove_dir_all(tmp_dir)
+        .chain_err(|| format!("Could not remove the directory {}.", tmp_dir.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_strip_options() {
+        let args = to_args(&[
+            "--bam", "a.bam", "-r", "chr1", "--out=x.vcf", "-F", "--threads", "8", "-rchr2",
+            "--contigs", "chr1,chr2", "--scheduler", "windows", "-C", "50",
+        ]);
+        assert_eq!(
+            strip_options(&args, &PER_CONTIG_OPTIONS, &HYPHEN_VALUE_OPTIONS),
+            to_args(&["--bam", "a.bam", "-F", "-C", "50"])
+        );
+
+        // the value of an option that allows hyphen values is not taken for -r with an attached
+        // value, and the arguments after it are not shifted
+        let args = to_args(&["--sample_id", "-rat1", "-r", "chr1", "--bam", "a.bam"]);
+        assert_eq!(
+            strip_options(&args, &PER_CONTIG_OPTIONS, &["--sample_id"]),
+            to_args(&["--sample_id", "-rat1", "--bam", "a.bam"])
+        );
+        let args = to_args(&["--min_alignment_score", "-5", "-ochr1.vcf", "-r", "chr1"]);
+        assert_eq!(
+            strip_options(&args, &PER_CONTIG_OPTIONS, &HYPHEN_VALUE_OPTIONS),
+            to_args(&["--min_alignment_score", "-5"])
+        );
+
+        // flags don't take the next argument with them
+        let flags: [CommandLineOption; 2] = [
+            ("--auto_max_cov", Some("-A"), false),
+            ("--max_cov", Some("-C"), true),
+        ];
+        let args = to_args(&["-A", "--bam", "a.bam", "-C", "50", "--max_cov=60", "-c", "3"]);
+        assert_eq!(
+            strip_options(&args, &flags, &[]),
+            to_args(&["--bam", "a.bam", "-c", "3"])
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Debug output of the read realignment at a single site (```--debug-realign```).
+//!
+//! The reads that cover the site are realigned to each allele of the potential variants there, in
+//! the same realignment windows and with the same alignment parameters as during fragment
+//! extraction. The single most likely (Viterbi) alignment of each read window to each allele is
+//! written to a SAM file, with the per-allele log-likelihoods in custom tags, to show why a read
+//! supports the allele that it was called with.
+//!
+//! Each allele gets its own reference sequence in the SAM header, named
+//! ```<chrom>_<pos>_var<k>_allele<a>```: the reference around the site with the allele in place of
+//! the reference allele. The variants at the site are realigned one at a time, so the calls can
+//! differ from those of a variant that was realigned in a cluster with its neighbors.
+
+use bio::stats::{LogProb, Prob};
+use errors::*;
+use estimate_alignment_parameters::AlignmentState;
+use extract_fragments::{
+    check_read_filters, create_augmented_cigarlist, find_anchors, haplotype_window,
+    haplotype_window_bounds, score_haplotype_window, AnchorPositions, CigarPos,
+    ExtractFragmentParameters,
+};
+use realignment::{
+    AlignmentParameters, ContextAlignmentParameters, ReadGroupAlignmentParameters,
+    StrandAlignmentParameters, MAX_CHUNK_LEN,
+};
+use rust_htslib::bam;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use util::*;
+use variants_and_fragments::{Var, VarList};
+
+// a read window realigned to every allele of a variant
+struct ReadRealignment {
+    qname: String,
+    reverse: bool,
+    mapq: u8,
+    seq: String,
+    qual: String,
+    anchors: AnchorPositions,
+    read_len: usize,
+    // first reference position of the haplotype windows
+    hap_start: usize,
+    hap_end: usize,
+    // the score of the read window against each allele, as used for the allele call
+    scores: Vec<LogProb>,
+    // the Viterbi score and path of the read window against each allele
+    paths: Vec<(LogProb, Vec<AlignmentState>)>,
+}
+
+/// Formats an alignment path as a CIGAR string, with the read bases outside of the realignment
+/// window soft-clipped
+fn path_cigar(path: &Vec<AlignmentState>, left_clip: usize, right_clip: usize) -> String {
+    let mut ops: Vec<(usize, char)> = vec![];
+    if left_clip > 0 {
+        ops.push((left_clip, 'S'));
+    }
+    for state in path {
+        let op = match *state {
+            AlignmentState::Match => 'M',
+            AlignmentState::Insertion => 'I',
+            AlignmentState::Deletion => 'D',
+        };
+        if let Some(last) = ops.last_mut() {
+            if last.1 == op {
+                last.0 += 1;
+                continue;
+            }
+        }
+        ops.push((1, op));
+    }
+    if right_clip > 0 {
+        ops.push((right_clip, 'S'));
+    }
+    ops.iter()
+        .map(|&(len, op)| format!("{}{}", len, op))
+        .collect()
+}
+
+// the name of the SAM reference sequence of an allele
+fn allele_contig_name(var: &Var, chrom: &str, k: usize, allele: usize) -> String {
+    format!("{}_{}_var{}_allele{}", chrom, var.pos0 + 1, k, allele)
+}
+
+/// Realigns the reads that cover ```site``` to the alleles of the potential variants at the site,
+/// and writes the Viterbi alignments to a SAM file.
+///
+/// Each read gets one record per allele, at the position of its realignment window on the allele
+/// sequence. The record of the allele with the highest score is the primary alignment, and the
+/// others are secondary (flag 256). The custom tags are:
+/// - ```ZA:i```: the allele index of the record
+/// - ```ZV:f```: the log probability of the Viterbi alignment path of the record
+/// - ```ZL:B:f```: the log-likelihood of the read window given each allele, as used for the allele
+///                 call (the forward algorithm score unless ```--max_alignment``` is used)
+/// - ```ZP:f```: the posterior probability of the allele of the record, from ```ZL```
+///
+/// Reads that start or end within the realignment window are aligned with a free end during
+/// fragment extraction, which has no single alignment path, so they are not written.
+///
+/// # Errors
+/// Fails if the BAM, FASTA or output file can't be read or written.
+pub fn write_realignment_debug_sam(
+    bam_file: &String,
+    fasta_file: &String,
+    varlist: &VarList,
+    site: &GenomicInterval,
+    extract_params: ExtractFragmentParameters,
+    align_params: AlignmentParameters,
+    context_params: &Option<ContextAlignmentParameters>,
+    contig_params: &Vec<ContigParams>,
+    strand_params: &Option<StrandAlignmentParameters>,
+    read_group_params: &Option<ReadGroupAlignmentParameters>,
+    output_file: &String,
+) -> Result<()> {
+    let vars: Vec<Var> = varlist
+        .get_variants_range(site.clone())
+        .chain_err(|| "Error getting variants in range.")?
+        .into_iter()
+        .filter(|var| var.pos0 == site.start_pos as usize)
+        .collect();
+    if vars.is_empty() {
+        warn!(
+            "{} WARNING: No potential variant at {}:{} to write the realignments for.",
+            print_time(),
+            site.chrom,
+            site.start_pos + 1
+        );
+        return Ok(());
+    }
+
+    let t_names = parse_target_names(bam_file)?;
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+
+    let mut read_params = extract_params;
+    if let Some(band_width) = contig_params.get(site.tid as usize).and_then(|p| p.band_width) {
+        read_params.band_width = band_width;
+    }
+
+    let mut realignments: Vec<Vec<ReadRealignment>> = vars.iter().map(|_| vec![]).collect();
+    let mut n_free_end = 0;
+    let mut bam_ix = open_indexed_bam(bam_file)?;
+    // the reference window must hold the read and the realignment window (with its anchors)
+    let ref_margin = read_params.max_window_padding
+        + read_params.window_flank
+        + 2 * read_params.anchor_length
+        + 1;
+    bam_ix
+        .fetch(site.tid, site.start_pos, site.end_pos + 1)
+        .chain_err(|| "Error seeking BAM file while realigning reads at the debug site.")?;
+    for r in bam_ix.records() {
+        let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+        if check_read_filters(&record, &read_params).is_some() {
+            continue;
+        }
+
+        let read_align_params = match (strand_params, read_group_params) {
+            (&Some(ref sp), _) => sp.for_strand(record.is_reverse()),
+            (&None, &Some(ref rp)) => rp.for_read_group(
+                read_group(&record).as_ref().map(|rg| rg.as_str()),
+                align_params,
+            ),
+            (&None, &None) => align_params,
+        };
+        let ref_seq = ref_reader.window(
+            &site.chrom,
+            (record.pos() as usize).saturating_sub(ref_margin),
+            record.cigar().end_pos() as usize + ref_margin,
+        )?;
+        let read_seq: Vec<char> = dna_vec(&record.seq().as_bytes());
+        let cigarpos_list: Vec<CigarPos> =
+            create_augmented_cigarlist(record.pos() as u32, &record.cigar())
+                .chain_err(|| "Error creating augmented cigarlist.")?;
+        let qual: String = match record.qual().first() {
+            Some(&255) | None => "*".to_string(),
+            Some(_) => record.qual().iter().map(|&q| (q + 33) as char).collect(),
+        };
+
+        for (k, var) in vars.iter().enumerate() {
+            let anchors = match find_anchors(
+                &record,
+                &cigarpos_list,
+                site.clone(),
+                ref_seq,
+                &read_seq,
+                &t_names,
+                read_params,
+            )
+            .chain_err(|| "Error while finding anchor sequences.")?
+            {
+                Some(anchors) => anchors,
+                None => continue,
+            };
+            if anchors.free_left_end || anchors.free_right_end {
+                n_free_end += 1;
+                continue;
+            }
+
+            let var_cluster = vec![var.clone()];
+            let (hap_start, hap_end) =
+                haplotype_window_bounds(ref_seq, &var_cluster, &anchors, &read_params);
+            let read_window: Vec<u8> = read_seq
+                [(anchors.left_anchor_read as usize)..(anchors.right_anchor_read as usize) + 1]
+                .iter()
+                .map(|&c| c as u8)
+                .collect();
+            // the alignment paths are traced back through the whole dynamic programming matrix,
+            // which only fits in memory for windows that are aligned in one piece
+            ensure!(
+                read_window.len() <= MAX_CHUNK_LEN && hap_end + 1 - hap_start <= MAX_CHUNK_LEN,
+                "The realignment window of read {} at the debug site is longer than {} bases.",
+                u8_to_string(record.qname())?,
+                MAX_CHUNK_LEN
+            );
+
+            let mut scores: Vec<LogProb> = vec![];
+            let mut paths: Vec<(LogProb, Vec<AlignmentState>)> = vec![];
+            for a in 0..var.alleles.len() {
+                let hap_window =
+                    haplotype_window(ref_seq, &var_cluster, &vec![a as u8], hap_start, hap_end);
+                scores.push(score_haplotype_window(
+                    &read_window,
+                    &hap_window,
+                    &anchors,
+                    &read_params,
+                    read_align_params,
+                    context_params,
+                ));
+                let hap_params: Vec<AlignmentParameters> = match context_params {
+                    &Some(ref cp) => cp.for_sequence(&hap_window, read_align_params),
+                    &None => vec![read_align_params; hap_window.len()],
+                };
+                match read_params.alignment_type.model().traceback(
+                    &read_window,
+                    &hap_window,
+                    &hap_params,
+                ) {
+                    Some(path) => paths.push(path),
+                    None => bail!("Empty realignment window at the debug site."),
+                }
+            }
+
+            realignments[k].push(ReadRealignment {
+                qname: u8_to_string(record.qname())?,
+                reverse: record.is_reverse(),
+                mapq: record.mapq(),
+                seq: read_seq.iter().collect(),
+                qual: qual.clone(),
+                anchors: anchors,
+                read_len: read_seq.len(),
+                hap_start: hap_start,
+                hap_end: hap_end,
+                scores: scores,
+                paths: paths,
+            });
+        }
+    }
+
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file =
+        File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+    let mut lines: Vec<String> = vec!["@HD\tVN:1.6\tSO:unsorted".to_string()];
+
+    // the allele sequences span the haplotype windows of all reads
+    let mut contig_starts: Vec<usize> = vec![];
+    for (k, var) in vars.iter().enumerate() {
+        let start = realignments[k]
+            .iter()
+            .map(|r| r.hap_start)
+            .min()
+            .unwrap_or(var.pos0);
+        let end = realignments[k]
+            .iter()
+            .map(|r| r.hap_end)
+            .max()
+            .unwrap_or(var.pos0 + var.alleles[0].len() - 1);
+        let ref_seq = ref_reader.window(&site.chrom, start, end + var.alleles[0].len() + 1)?;
+        for a in 0..var.alleles.len() {
+            let seq: String =
+                haplotype_window(ref_seq, &vec![var.clone()], &vec![a as u8], start, end)
+                    .into_iter()
+                    .map(|c| c as char)
+                    .collect();
+            lines.push(format!(
+                "@SQ\tSN:{}\tLN:{}",
+                allele_contig_name(var, &site.chrom, k, a),
+                seq.len()
+            ));
+            lines.push(format!(
+                "@CO\t{}\t{}",
+                allele_contig_name(var, &site.chrom, k, a),
+                seq
+            ));
+        }
+        contig_starts.push(start);
+    }
+    lines.push("@PG\tID:longshot\tPN:longshot".to_string());
+
+    for (k, var) in vars.iter().enumerate() {
+        for r in &realignments[k] {
+            let total = LogProb::ln_sum_exp(&r.scores);
+            let best_allele = (0..r.scores.len())
+                .fold(0, |best, a| if r.scores[a] > r.scores[best] { a } else { best });
+            let scores: Vec<String> = r.scores.iter().map(|s| format!("{:.4}", **s)).collect();
+            let left_clip = r.anchors.left_anchor_read as usize;
+            let right_clip = r.read_len - r.anchors.right_anchor_read as usize - 1;
+
+            for (a, &(ref viterbi_score, ref path)) in r.paths.iter().enumerate() {
+                let mut flag = 0;
+                if r.reverse {
+                    flag |= 16;
+                }
+                if a != best_allele {
+                    flag |= 256;
+                }
+                lines.push(format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t*\t0\t0\t{}\t{}\tZA:i:{}\tZV:f:{:.4}\tZL:B:f,{}\tZP:f:{:.4}",
+                    r.qname,
+                    flag,
+                    allele_contig_name(var, &site.chrom, k, a),
+                    r.hap_start - contig_starts[k] + 1,
+                    r.mapq,
+                    path_cigar(path, left_clip, right_clip),
+                    r.seq,
+                    r.qual,
+                    a,
+                    **viterbi_score,
+                    scores.join(","),
+                    *Prob::from(r.scores[a] - total)
+                ));
+            }
+        }
+    }
+
+    for line in lines {
+        writeln!(file, "{}", line).chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    info!(
+        "{} Wrote the realignments of {} reads at {}:{} to {}.",
+        print_time(),
+        realignments.iter().map(|r| r.len()).max().unwrap_or(0),
+        site.chrom,
+        site.start_pos + 1,
+        output_file
+    );
+    if n_free_end > 0 {
+        info!(
+            "{} {} reads that start or end within the realignment window were not written.",
+            SPACER, n_free_end
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_cigar() {
+        let mut path = vec![AlignmentState::Match; 4];
+        path.push(AlignmentState::Insertion);
+        path.extend(vec![AlignmentState::Match; 3]);
+        path.push(AlignmentState::Deletion);
+        path.push(AlignmentState::Deletion);
+        path.push(AlignmentState::Match);
+        assert_eq!(path_cigar(&path, 0, 0), "4M1I3M2D1M");
+        assert_eq!(path_cigar(&path, 10, 2), "10S4M1I3M2D1M2S");
+    }
+}
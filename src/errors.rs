@@ -5,8 +5,9 @@
 error_chain! {
     errors {
         // BAM errors
-        BamOpenError {
-            description("Error opening BAM file.")
+        BamOpenError(f: String) {
+            description("Error opening BAM file")
+            display(x) -> ("{}: {}", x.description(), f)
         }
         BamWriterOpenError(f: String) {
             description("Error opening BAM file for writing")
@@ -19,9 +20,16 @@ error_chain! {
         BamHeaderTargetLenAccessError {
             description("Error accessing target len for a contig in bam header.")
         }
+        // a BAM record that can't be decoded, with the file, the fetched region and the name of
+        // the last read that was read successfully
+        CorruptBamRecord(f: String, region: String, previous_read: String) {
+            description("Corrupt or truncated BAM record")
+            display(x) -> ("{} in {}, region {}, after read {}", x.description(), f, region, previous_read)
+        }
         // Indexed BAM errors
-        IndexedBamOpenError {
-            description("Error opening indexed BAM file.")
+        IndexedBamOpenError(f: String) {
+            description("Error opening indexed BAM file (is it indexed?)")
+            display(x) -> ("{}: {}", x.description(), f)
         }
         IndexedBamReadError {
             description("Error reading indexed BAM file.")
@@ -29,6 +37,10 @@ error_chain! {
         IndexedBamFetchError {
             description("Error fetching region from indexed BAM file.")
         }
+        IndexedBamRegionFetchError(f: String, region: String) {
+            description("Error fetching region from indexed BAM file")
+            display(x) -> ("{} {}: {}", x.description(), f, region)
+        }
         IndexedBamRecordReadError {
             description("Error reading BAM record.")
         }
@@ -39,15 +51,21 @@ error_chain! {
             description("Error accessing query position for alignment in BAM pileup.")
         }
         // Indexed Fasta errors
-        IndexedFastaOpenError {
-            description("Error opening indexed FASTA file.")
+        IndexedFastaOpenError(f: String) {
+            description("Error opening indexed FASTA file")
+            display(x) -> ("{}: {}", x.description(), f)
         }
         IndexedFastaReadError {
             description("Error reading indexed FASTA file.")
         }
-        // Indexed BAM errors
-        BCFOpenError {
-            description("Error opening BCF file.")
+        IndexedFastaRegionReadError(region: String) {
+            description("Error reading region from indexed FASTA file")
+            display(x) -> ("{}: {}", x.description(), region)
+        }
+        // BCF errors
+        BCFOpenError(f: String) {
+            description("Error opening BCF file")
+            display(x) -> ("{}: {}", x.description(), f)
         }
         BCFReadError {
             description("Error reading BCF file.")
@@ -88,9 +106,123 @@ error_chain! {
             description("Couldn't create file")
             display(x) -> ("{}: {}", x.description(), filename)
         }
-        // File IO errors
+        FileReadError (filename: String){
+            description("Couldn't read file")
+            display(x) -> ("{}: {}", x.description(), filename)
+        }
+        // Read processing errors
+        ReadProcessingError(qname: String, region: String) {
+            description("Error processing read")
+            display(x) -> ("{} {} at {}", x.description(), qname, region)
+        }
+        // Internal errors
         NoneError {
             description("Option was None.")
         }
+        InternalError(msg: String) {
+            description("Internal error (this is a bug in longshot, please report it)")
+            display(x) -> ("{}: {}", x.description(), msg)
+        }
+    }
+}
+
+/// The exit code for an invalid command line or input file (a BAM, FASTA, VCF or BED file that
+/// can't be read or holds invalid data). Errors that are raised with ```bail!``` are checks of
+/// the options and input files, so they have this exit code unless they are caused by an error of
+/// another kind.
+pub static EXIT_INPUT_ERROR: i32 = 2;
+/// The exit code for an output file that can't be created or written
+pub static EXIT_OUTPUT_ERROR: i32 = 3;
+/// The exit code for an internal error, i.e. a bug, including panics
+pub static EXIT_INTERNAL_ERROR: i32 = 4;
+
+impl ErrorKind {
+    /// The exit code of an error of this kind, or None if it depends on the error that caused it
+    fn exit_code(&self) -> Option<i32> {
+        match *self {
+            ErrorKind::BamOpenError(..)
+            | ErrorKind::BamHeaderTargetLenAccessError
+            | ErrorKind::CorruptBamRecord(..)
+            | ErrorKind::IndexedBamOpenError(..)
+            | ErrorKind::IndexedBamReadError
+            | ErrorKind::IndexedBamFetchError
+            | ErrorKind::IndexedBamRegionFetchError(..)
+            | ErrorKind::IndexedBamRecordReadError
+            | ErrorKind::IndexedBamPileupReadError
+            | ErrorKind::IndexedBamPileupQueryPositionError
+            | ErrorKind::IndexedFastaOpenError(..)
+            | ErrorKind::IndexedFastaReadError
+            | ErrorKind::IndexedFastaRegionReadError(..)
+            | ErrorKind::BCFOpenError(..)
+            | ErrorKind::BCFReadError
+            | ErrorKind::UnexpectedCigarOperation(..)
+            | ErrorKind::UnsupportedCigarOperation(..)
+            | ErrorKind::FileReadError(..) => Some(EXIT_INPUT_ERROR),
+            ErrorKind::BamWriterOpenError(..)
+            | ErrorKind::BamRecordWriteError(..)
+            | ErrorKind::FileWriteError(..)
+            | ErrorKind::CreateFileError(..) => Some(EXIT_OUTPUT_ERROR),
+            ErrorKind::AnchorRangeOutsideRead
+            | ErrorKind::InvalidTransitionBase(..)
+            | ErrorKind::InvalidHaploidGenotype(..)
+            | ErrorKind::GenotypeNotInGenotypePriorsError(..)
+            | ErrorKind::NoneError
+            | ErrorKind::InternalError(..) => Some(EXIT_INTERNAL_ERROR),
+            _ => None,
+        }
+    }
+}
+
+/// The exit code of the process for an error: that of the innermost error of the chain with a
+/// kind that decides it (e.g. a corrupt BAM record that caused an error while extracting the
+/// fragments is an input error)
+pub fn exit_code(e: &Error) -> i32 {
+    let mut code = EXIT_INPUT_ERROR;
+    let mut link: Option<&Error> = Some(e);
+    while let Some(err) = link {
+        if let Some(c) = err.kind().exit_code() {
+            code = c;
+        }
+        link = err
+            .1
+            .next_error
+            .as_ref()
+            .and_then(|next| next.downcast_ref::<Error>());
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let options: Result<()> = Err("Invalid value for --min_cov.".into());
+        assert_eq!(exit_code(&options.unwrap_err()), EXIT_INPUT_ERROR);
+
+        let corrupt: Result<()> = Err(ErrorKind::CorruptBamRecord(
+            "a.bam".to_string(),
+            "chr1:1-1000".to_string(),
+            "read1".to_string(),
+        )
+        .into());
+        let e = corrupt
+            .chain_err(|| {
+                ErrorKind::ReadProcessingError("read2".to_string(), "chr1:10".to_string())
+            })
+            .chain_err(|| "Error extracting fragments.")
+            .unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_INPUT_ERROR);
+
+        let write: Result<()> = Err(ErrorKind::FileWriteError("out.vcf".to_string()).into());
+        assert_eq!(exit_code(&write.unwrap_err()), EXIT_OUTPUT_ERROR);
+
+        // the innermost error decides
+        let bug: Result<()> = Err(ErrorKind::AnchorRangeOutsideRead.into());
+        let e = bug
+            .chain_err(|| ErrorKind::IndexedBamRecordReadError)
+            .unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_INTERNAL_ERROR);
     }
 }
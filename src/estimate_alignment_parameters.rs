@@ -5,14 +5,16 @@
 //! Durbin, R., Eddy, S.R., Krogh, A. and Mitchison, G., 1998. Biological sequence analysis:
 //! probabilistic models of proteins and nucleic acids. Cambridge university press.
 
-use bio::io::fasta;
 use errors::*;
 use extract_fragments::{create_augmented_cigarlist, CigarPos};
+use hashbrown::HashMap;
 use realignment::*;
 use rust_htslib::bam;
 use rust_htslib::bam::record::Cigar;
 use rust_htslib::bam::record::CigarStringView;
-use rust_htslib::bam::Read;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
 use util::*;
 
 /// represents the 3 states for the sequence alignment Pair-HMM
@@ -34,7 +36,7 @@ pub struct StateTransition {
 ///
 /// the counts in this struct are used to keep track of the different alignment transitions that
 /// are observed in the BAM file. These counts can be used to help us directly estimate alignment parameters
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct TransitionCounts {
     match_from_match: usize,
     insertion_from_match: usize,
@@ -46,7 +48,7 @@ pub struct TransitionCounts {
 }
 
 /// represents counts of different emission events (aligned bases being equal or not equal) in the BAM
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct EmissionCounts {
     equal: usize,
     not_equal: usize,
@@ -55,7 +57,7 @@ pub struct EmissionCounts {
 /// a struct to hold counts of transition events and emission events from the BAM file
 ///
 /// together these counts represent all of the counts necessary to estimate the HMM parameters
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 struct AlignmentCounts {
     transition_counts: TransitionCounts,
     emission_counts: EmissionCounts,
@@ -88,6 +90,86 @@ impl TransitionCounts {
         }
     }
 
+    /// converts TransitionCounts to TransitionProbs, shrinking the probabilities towards
+    /// ```prior```
+    ///
+    /// The prior probabilities are added as ```weight``` pseudo-counts of transitions out of each
+    /// state, so that states with few observed transitions (e.g. in a rare sequence context) get
+    /// probabilities close to the prior.
+    fn to_probs_with_prior(&self, prior: &TransitionProbs, weight: f64) -> TransitionProbs {
+        let total_from_match: f64 = self.match_from_match as f64
+            + self.insertion_from_match as f64
+            + self.deletion_from_match as f64
+            + weight;
+        let total_from_insertion: f64 =
+            self.insertion_from_insertion as f64 + self.match_from_insertion as f64 + weight;
+        let total_from_deletion: f64 =
+            self.deletion_from_deletion as f64 + self.match_from_deletion as f64 + weight;
+        let shrink = |count: usize, p: f64, total: f64| (count as f64 + weight * p) / total;
+
+        TransitionProbs {
+            match_from_match: shrink(self.match_from_match, prior.match_from_match, total_from_match),
+            insertion_from_match: shrink(
+                self.insertion_from_match,
+                prior.insertion_from_match,
+                total_from_match,
+            ),
+            deletion_from_match: shrink(
+                self.deletion_from_match,
+                prior.deletion_from_match,
+                total_from_match,
+            ),
+            insertion_from_insertion: shrink(
+                self.insertion_from_insertion,
+                prior.insertion_from_insertion,
+                total_from_insertion,
+            ),
+            match_from_insertion: shrink(
+                self.match_from_insertion,
+                prior.match_from_insertion,
+                total_from_insertion,
+            ),
+            deletion_from_deletion: shrink(
+                self.deletion_from_deletion,
+                prior.deletion_from_deletion,
+                total_from_deletion,
+            ),
+            match_from_deletion: shrink(
+                self.match_from_deletion,
+                prior.match_from_deletion,
+                total_from_deletion,
+            ),
+        }
+    }
+
+    /// count a single transition from ```prev_state``` to ```current_state```
+    ///
+    /// MINIMAP2 sometimes goes directly from insertion <-> deletion, so these are counted as an
+    /// implicit transition through the match state (e.g. insertion -> match -> deletion).
+    fn count(&mut self, prev_state: AlignmentState, current_state: AlignmentState) {
+        match (prev_state, current_state) {
+            (AlignmentState::Match, AlignmentState::Match) => self.match_from_match += 1,
+            (AlignmentState::Insertion, AlignmentState::Match) => self.match_from_insertion += 1,
+            (AlignmentState::Deletion, AlignmentState::Match) => self.match_from_deletion += 1,
+            (AlignmentState::Match, AlignmentState::Insertion) => self.insertion_from_match += 1,
+            (AlignmentState::Insertion, AlignmentState::Insertion) => {
+                self.insertion_from_insertion += 1
+            }
+            (AlignmentState::Deletion, AlignmentState::Insertion) => {
+                self.match_from_deletion += 1;
+                self.insertion_from_match += 1;
+            }
+            (AlignmentState::Match, AlignmentState::Deletion) => self.deletion_from_match += 1,
+            (AlignmentState::Deletion, AlignmentState::Deletion) => {
+                self.deletion_from_deletion += 1
+            }
+            (AlignmentState::Insertion, AlignmentState::Deletion) => {
+                self.match_from_insertion += 1;
+                self.deletion_from_match += 1;
+            }
+        }
+    }
+
     /// add the corresponding counts inside two ```TransitionCount```s together
     ///
     /// this function allows us to create separate ```TransitionCount``` structs for each newly
@@ -101,6 +183,33 @@ impl TransitionCounts {
         self.deletion_from_deletion += other.deletion_from_deletion;
         self.match_from_deletion += other.match_from_deletion;
     }
+
+    /// the counts of ```self``` without the counts of ```other```, which were added to it
+    fn without(&self, other: &TransitionCounts) -> TransitionCounts {
+        TransitionCounts {
+            match_from_match: self.match_from_match - other.match_from_match,
+            insertion_from_match: self.insertion_from_match - other.insertion_from_match,
+            deletion_from_match: self.deletion_from_match - other.deletion_from_match,
+            insertion_from_insertion: self.insertion_from_insertion
+                - other.insertion_from_insertion,
+            match_from_insertion: self.match_from_insertion - other.match_from_insertion,
+            deletion_from_deletion: self.deletion_from_deletion - other.deletion_from_deletion,
+            match_from_deletion: self.match_from_deletion - other.match_from_deletion,
+        }
+    }
+
+    /// a count of 1 for every transition, so that no probability is divided by 0
+    fn pseudo_counts() -> TransitionCounts {
+        TransitionCounts {
+            match_from_match: 1,
+            insertion_from_match: 1,
+            deletion_from_match: 1,
+            insertion_from_insertion: 1,
+            match_from_insertion: 1,
+            deletion_from_deletion: 1,
+            match_from_deletion: 1,
+        }
+    }
 }
 
 impl EmissionCounts {
@@ -122,6 +231,20 @@ impl EmissionCounts {
         }
     }
 
+    /// converts EmissionCounts to EmissionProbs, shrinking the probabilities towards ```prior```
+    /// by adding the prior probabilities as ```weight``` pseudo-counts of aligned bases
+    fn to_probs_with_prior(&self, prior: &EmissionProbs, weight: f64) -> EmissionProbs {
+        let total: f64 = self.equal as f64 + self.not_equal as f64 + weight;
+
+        EmissionProbs {
+            equal: (self.equal as f64 + weight * prior.equal) / total,
+            // prior.not_equal is the probability of one of the 3 possible mismatched bases
+            not_equal: (self.not_equal as f64 + weight * prior.not_equal * 3.0) / total / 3.0,
+            insertion: 1.0,
+            deletion: 1.0,
+        }
+    }
+
     /// add the corresponding counts inside two ```EmissionCount```s together
     ///
     /// this function allows us to create separate ```EmissionCount``` structs for each newly
@@ -141,6 +264,317 @@ impl AlignmentCounts {
             emission_probs: self.emission_counts.to_probs(),
         }
     }
+
+    /// convert ```AlignmentCounts``` into ```AlignmentParameters```, shrinking the probabilities
+    /// towards ```prior``` with ```weight``` pseudo-counts
+    fn to_parameters_with_prior(
+        &self,
+        prior: &AlignmentParameters,
+        weight: f64,
+    ) -> AlignmentParameters {
+        AlignmentParameters {
+            transition_probs: self
+                .transition_counts
+                .to_probs_with_prior(&prior.transition_probs, weight),
+            emission_probs: self
+                .emission_counts
+                .to_probs_with_prior(&prior.emission_probs, weight),
+        }
+    }
+}
+
+/// the number of pseudo-counts of the genome-wide alignment parameters that are added to the
+/// counts of each read group, so that a read group with only a few reads in the estimation
+/// regions gets parameters close to the genome-wide ones.
+static READ_GROUP_PRIOR_WEIGHT: f64 = 1000.0;
+
+/// the number of pseudo-counts of the genome-wide alignment parameters that are added to the
+/// counts of each sequence context. Contexts with much fewer observed events than this get
+/// parameters close to the genome-wide ones.
+static CONTEXT_PRIOR_WEIGHT: f64 = 100.0;
+
+/// the number of reference bases read on either side of each read, which covers the k-mer
+/// contexts of the read's first and last bases (k is at most 9)
+static REF_CONTEXT_MARGIN: usize = 16;
+
+/// the upper bounds of the GC content bins that the training windows are stratified by. The last
+/// bin has no upper bound.
+static TRAINING_GC_BINS: [f64; 4] = [0.35, 0.45, 0.55, 0.65];
+
+/// the number of randomly sampled candidate windows that the training windows are chosen from,
+/// per training window
+static TRAINING_CANDIDATES_PER_WINDOW: usize = 4;
+
+/// the random seed for sampling the candidate training windows, so that repeated runs estimate the
+/// parameters from the same windows
+static TRAINING_WINDOW_SEED: usize = 0;
+
+/// the maximum fraction of ambiguous reference bases in a training window
+static TRAINING_MAX_N_FRAC: f64 = 0.1;
+
+/// the minimum number of estimation regions with reads for the confidence intervals of the
+/// transition probabilities and the convergence diagnostic
+static MIN_DIAGNOSTIC_REGIONS: usize = 10;
+
+/// the maximum relative difference between the transition probabilities estimated from the two
+/// halves of the estimation regions, for the estimates to be considered converged
+static CONVERGENCE_TOLERANCE: f64 = 0.1;
+
+/// the names of the transition probabilities, in the order of ```transition_prob_values```
+static TRANSITION_NAMES: [&str; 7] = [
+    "match -> match",
+    "match -> insertion",
+    "match -> deletion",
+    "deletion -> match",
+    "deletion -> deletion",
+    "insertion -> match",
+    "insertion -> insertion",
+];
+
+fn transition_prob_values(t: &TransitionProbs) -> [f64; 7] {
+    [
+        t.match_from_match,
+        t.insertion_from_match,
+        t.deletion_from_match,
+        t.match_from_deletion,
+        t.deletion_from_deletion,
+        t.match_from_insertion,
+        t.insertion_from_insertion,
+    ]
+}
+
+/// Returns the 95% confidence interval of each transition probability (in the order of
+/// ```TRANSITION_NAMES```), from a delete-one-region jackknife over the transition counts of each
+/// estimation region. The reads of a region share its sequence composition, so the regions rather
+/// than the reads are treated as the independent samples.
+fn transition_prob_intervals(region_counts: &[TransitionCounts]) -> Vec<(f64, f64)> {
+    let mut total = TransitionCounts::pseudo_counts();
+    for c in region_counts {
+        total.add(*c);
+    }
+    let estimate = transition_prob_values(&total.to_probs());
+    let n = region_counts.len() as f64;
+    let leave_one_out: Vec<[f64; 7]> = region_counts
+        .iter()
+        .map(|c| transition_prob_values(&total.without(c).to_probs()))
+        .collect();
+    (0..7)
+        .map(|k| {
+            let mean: f64 = leave_one_out.iter().map(|p| p[k]).sum::<f64>() / n;
+            let sum_sq: f64 = leave_one_out.iter().map(|p| (p[k] - mean).powi(2)).sum();
+            let half_width = 1.96 * ((n - 1.0) / n * sum_sq).sqrt();
+            (
+                (estimate[k] - half_width).max(0.0),
+                (estimate[k] + half_width).min(1.0),
+            )
+        })
+        .collect()
+}
+
+/// Returns the largest relative difference between the transition probabilities estimated from
+/// the even and the odd estimation regions. The estimates have converged if the two halves agree.
+fn split_half_difference(region_counts: &[TransitionCounts]) -> f64 {
+    let mut halves = [TransitionCounts::pseudo_counts(); 2];
+    for (i, c) in region_counts.iter().enumerate() {
+        halves[i % 2].add(*c);
+    }
+    let a = transition_prob_values(&halves[0].to_probs());
+    let b = transition_prob_values(&halves[1].to_probs());
+    (0..7)
+        .map(|k| (a[k] - b[k]).abs() / ((a[k] + b[k]) / 2.0))
+        .fold(0.0, f64::max)
+}
+
+/// Prints the confidence intervals of the transition probabilities and whether the estimates of
+/// the two halves of the estimation regions agree, i.e. whether the estimates have converged
+fn print_transition_diagnostics(region_counts: &[TransitionCounts]) {
+    info!(
+        "{} Transition Probability 95% Confidence Intervals (jackknife over {} regions):",
+        SPACER,
+        region_counts.len()
+    );
+    for (name, &(low, high)) in TRANSITION_NAMES
+        .iter()
+        .zip(transition_prob_intervals(region_counts).iter())
+    {
+        let label = format!("{}:", name);
+        info!("{} {:<25} {:.4}-{:.4}", SPACER, label, low, high);
+    }
+    info!("");
+
+    let difference = split_half_difference(region_counts);
+    if difference <= CONVERGENCE_TOLERANCE {
+        info!(
+            "{} The transition probabilities estimated from the two halves of the regions differ by at most {:.1}%.",
+            print_time(),
+            100.0 * difference
+        );
+    } else {
+        warn!(
+            "{} WARNING: The transition probabilities estimated from the two halves of the regions differ by up to {:.1}%, so the estimates may not have converged. Estimate them from more regions (e.g. more --param_windows).",
+            print_time(),
+            100.0 * difference
+        );
+    }
+}
+
+/// Chooses about ```n_windows``` of the candidate windows, stratified by their GC content: each
+/// GC bin (see ```TRAINING_GC_BINS```) gets a share of the windows in proportion to its number of
+/// candidates, and at least one window, and the windows of a bin are spaced evenly along its
+/// candidates (which are in genome order). Returns the windows sorted by position.
+fn select_stratified_windows(
+    candidates: Vec<(GenomicInterval, f64)>,
+    n_windows: usize,
+) -> Vec<GenomicInterval> {
+    let n_candidates = candidates.len();
+    let mut bins: Vec<Vec<GenomicInterval>> = vec![vec![]; TRAINING_GC_BINS.len() + 1];
+    for (window, gc) in candidates {
+        let bin = TRAINING_GC_BINS.iter().filter(|&&b| gc >= b).count();
+        bins[bin].push(window);
+    }
+
+    let mut windows: Vec<GenomicInterval> = vec![];
+    for bin in bins {
+        if bin.is_empty() {
+            continue;
+        }
+        let share = (n_windows as f64 * bin.len() as f64 / n_candidates as f64).round() as usize;
+        let share = share.max(1).min(bin.len());
+        for i in 0..share {
+            windows.push(bin[(2 * i + 1) * bin.len() / (2 * share)].clone());
+        }
+    }
+    windows.sort_by_key(|w| (w.tid, w.start_pos));
+    windows
+}
+
+/// Samples training windows of ```window_size``` bases from the intervals to estimate the
+/// alignment parameters from, instead of from all of the reads in the intervals, so that the
+/// estimates reflect the sequence composition of the whole genome rather than that of the first
+/// or the largest region. The candidate windows are sampled at random (with a fixed seed) from all
+/// of the windows of the intervals, which spreads them over the contigs in proportion to their
+/// length, and candidates with more than ```TRAINING_MAX_N_FRAC``` ambiguous reference bases are
+/// skipped. About ```n_windows``` of them are chosen, stratified by GC content (see
+/// ```select_stratified_windows```).
+///
+/// #Errors
+/// - ```IndexedFastaOpenError```: error opening the indexed FASTA file
+/// - ```IndexedFastaReadError```: error reading a record from the FASTA
+pub fn stratified_training_windows(
+    fasta_file: &String,
+    interval_lst: &Vec<GenomicInterval>,
+    n_windows: usize,
+    window_size: u32,
+) -> Result<Vec<GenomicInterval>> {
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+    let mut candidates: Vec<(GenomicInterval, f64)> = vec![];
+    for window in sample_windows(
+        interval_lst,
+        n_windows * TRAINING_CANDIDATES_PER_WINDOW,
+        window_size,
+        TRAINING_WINDOW_SEED,
+    ) {
+        let (start, end) = (window.start_pos as usize, window.end_pos as usize + 1);
+        let ref_seq = ref_reader.window(&window.chrom, start, end)?;
+        let mut n_gc = 0;
+        let mut n_acgt = 0;
+        for pos in start..end {
+            match ref_seq[pos].to_ascii_uppercase() {
+                'G' | 'C' => {
+                    n_gc += 1;
+                    n_acgt += 1;
+                }
+                'A' | 'T' => n_acgt += 1,
+                _ => {}
+            }
+        }
+        if n_acgt > 0 && (end - start - n_acgt) as f64 <= TRAINING_MAX_N_FRAC * (end - start) as f64
+        {
+            candidates.push((window, n_gc as f64 / n_acgt as f64));
+        }
+    }
+
+    let windows = select_stratified_windows(candidates, n_windows);
+    ensure!(
+        !windows.is_empty(),
+        "No training windows for alignment parameter estimation were found in the called regions."
+    );
+    let mut tids: Vec<u32> = windows.iter().map(|w| w.tid).collect();
+    tids.dedup();
+    info!(
+        "{} Estimating alignment parameters from {} windows of {} bp sampled across {} contigs, stratified by GC content.",
+        print_time(),
+        windows.len(),
+        window_size,
+        tids.len()
+    );
+    Ok(windows)
+}
+
+/// counts of alignment events (transitions and emissions) for each k-mer context of the
+/// reference, for estimating a context-specific error model
+///
+/// each event is counted in the context of the reference base at which it occurs: the aligned
+/// base for matches, the deleted base for deletions, and the base preceding the insertion for
+/// insertions. This matches the positions at which the context-specific parameters are used in
+/// realignment (see ```realignment::ContextAlignmentParameters```).
+pub struct ContextCounts {
+    k: usize,
+    transition_counts: Vec<TransitionCounts>,
+    emission_counts: Vec<EmissionCounts>,
+}
+
+impl ContextCounts {
+    pub fn new(k: usize) -> ContextCounts {
+        let n_contexts = 4usize.pow(k as u32);
+        ContextCounts {
+            k: k,
+            transition_counts: vec![TransitionCounts::default(); n_contexts],
+            emission_counts: vec![EmissionCounts::default(); n_contexts],
+        }
+    }
+
+    fn count_transition(
+        &mut self,
+        ref_seq: &RefSeqWindow,
+        ref_pos: usize,
+        prev_state: AlignmentState,
+        current_state: AlignmentState,
+    ) {
+        if let Some(ix) = kmer_context_index(&ref_seq.seq, ref_pos - ref_seq.start, self.k) {
+            self.transition_counts[ix].count(prev_state, current_state);
+        }
+    }
+
+    fn count_emission(&mut self, ref_seq: &RefSeqWindow, ref_pos: usize, equal: bool) {
+        if let Some(ix) = kmer_context_index(&ref_seq.seq, ref_pos - ref_seq.start, self.k) {
+            if equal {
+                self.emission_counts[ix].equal += 1;
+            } else {
+                self.emission_counts[ix].not_equal += 1;
+            }
+        }
+    }
+
+    /// convert the counts for each context into alignment parameters, shrunk towards the
+    /// genome-wide parameters ```prior```
+    fn to_parameters(&self, prior: &AlignmentParameters) -> ContextAlignmentParameters {
+        let params = self
+            .transition_counts
+            .iter()
+            .zip(self.emission_counts.iter())
+            .map(|(tc, ec)| AlignmentParameters {
+                transition_probs: tc
+                    .to_probs_with_prior(&prior.transition_probs, CONTEXT_PRIOR_WEIGHT),
+                emission_probs: ec.to_probs_with_prior(&prior.emission_probs, CONTEXT_PRIOR_WEIGHT),
+            })
+            .collect();
+
+        ContextAlignmentParameters {
+            k: self.k,
+            params: params,
+        }
+    }
 }
 
 //************************************************************************************************
@@ -166,12 +600,15 @@ impl AlignmentCounts {
 /// #Arguments
 /// -```cigarpos_list```: a vector of ```CigarPos``` which hold the CIGAR operations for the read,
 ///                       along with the reference and read positions where they occur
-/// -```ref_seq```: the reference sequence for the chromosome that the read is aligned to
+/// -```ref_seq```: a window of the reference sequence that holds the read's alignment, with at
+///                 least ```REF_CONTEXT_MARGIN``` bases on either side
 /// -```read_seq```: the sequence of the read from the BAM file
 /// -```max_cigar_indel```: the maximum length of a CIGAR operation in order to count it.
 ///                         this is meant to filter out large indels observed in the BAM alignment
 ///                         that are due to misalignment or structural variations instead of
 ///                         random sequencing error.
+/// -```context_counts```: if not None, the events are also counted for the k-mer context of the
+///                        reference at which they occur
 ///
 /// #Returns
 /// Returns a Result containing a TransitionCounts and an EmissionCounts.
@@ -184,25 +621,14 @@ impl AlignmentCounts {
 ///   been filtered out (Pad,Back,Softclip,Hardclip).
 pub fn count_alignment_events(
     cigarpos_list: &Vec<CigarPos>,
-    ref_seq: &Vec<char>,
+    ref_seq: &RefSeqWindow,
     read_seq: &Vec<char>,
     max_cigar_indel: u32,
+    context_counts: &mut Option<ContextCounts>,
 ) -> Result<(TransitionCounts, EmissionCounts)> {
     // initialize TransitionCounts and EmissionCounts with all counts set to 0
-    let mut transition_counts = TransitionCounts {
-        match_from_match: 0,
-        insertion_from_match: 0,
-        deletion_from_match: 0,
-        insertion_from_insertion: 0,
-        match_from_insertion: 0,
-        deletion_from_deletion: 0,
-        match_from_deletion: 0,
-    };
-
-    let mut emission_counts = EmissionCounts {
-        equal: 0,
-        not_equal: 0,
-    };
+    let mut transition_counts = TransitionCounts::default();
+    let mut emission_counts = EmissionCounts::default();
 
     // assume the initial state is MATCH
     let mut state: AlignmentState = AlignmentState::Match;
@@ -218,22 +644,15 @@ pub fn count_alignment_events(
 
                 // traverse over the length of the match operation
                 for _ in 0..l {
-                    if ref_pos >= ref_seq.len() - 1 || read_pos >= read_seq.len() - 1 {
+                    if ref_pos + 1 >= ref_seq.len() || read_pos + 1 >= read_seq.len() {
                         break; // break if we've reached the end of the read or reference
                     }
 
                     // we add the transition from the current state
                     // to the new state (which is match)
-                    match state {
-                        AlignmentState::Match => {
-                            transition_counts.match_from_match += 1;
-                        }
-                        AlignmentState::Deletion => {
-                            transition_counts.match_from_deletion += 1;
-                        }
-                        AlignmentState::Insertion => {
-                            transition_counts.match_from_insertion += 1;
-                        }
+                    transition_counts.count(state, AlignmentState::Match);
+                    if let Some(ref mut cc) = *context_counts {
+                        cc.count_transition(ref_seq, ref_pos, state, AlignmentState::Match);
                     }
 
                     // we have transitioned to a match so set the current state to match
@@ -241,12 +660,18 @@ pub fn count_alignment_events(
 
                     // check if the aligned bases match or mismatch and use these to iterate the
                     // emission counts (whether bases match or mismatch)
-                    if ref_seq[ref_pos] != 'N' && read_seq[read_pos] != 'N' {
-                        if ref_seq[ref_pos] == read_seq[read_pos] {
+                    if !is_ambiguous_base(ref_seq[ref_pos])
+                        && !is_ambiguous_base(read_seq[read_pos])
+                    {
+                        let equal = ref_seq[ref_pos] == read_seq[read_pos];
+                        if equal {
                             emission_counts.equal += 1;
                         } else {
                             emission_counts.not_equal += 1;
                         }
+                        if let Some(ref mut cc) = *context_counts {
+                            cc.count_emission(ref_seq, ref_pos, equal);
+                        }
                     }
 
                     // it's a match operation so both read and reference move forward one position
@@ -266,24 +691,22 @@ pub fn count_alignment_events(
 
                 // traverse over the length of the insertion operation
                 for _ in 0..l {
-                    if ref_pos >= ref_seq.len() - 1 || read_pos >= read_seq.len() - 1 {
+                    if ref_pos + 1 >= ref_seq.len() || read_pos + 1 >= read_seq.len() {
                         break; // break if we've reached the end of the read or reference
                     }
 
                     // we add the transition from the current state
                     // to the new state (which is insertion)
-                    match state {
-                        AlignmentState::Insertion => {
-                            transition_counts.insertion_from_insertion += 1;
-                        }
-                        AlignmentState::Match => {
-                            transition_counts.insertion_from_match += 1;
-                        }
-                        AlignmentState::Deletion => {
-                            // MINIMAP2 sometimes goes directly from insertion <-> deletion
-                            // we will just add an implicit deletion -> match -> insertion
-                            transition_counts.match_from_deletion += 1;
-                            transition_counts.insertion_from_match += 1;
+                    transition_counts.count(state, AlignmentState::Insertion);
+                    if let Some(ref mut cc) = *context_counts {
+                        // the insertion follows the reference base before ref_pos
+                        if ref_pos > 0 {
+                            cc.count_transition(
+                                ref_seq,
+                                ref_pos - 1,
+                                state,
+                                AlignmentState::Insertion,
+                            );
                         }
                     }
 
@@ -305,24 +728,14 @@ pub fn count_alignment_events(
 
                 // traverse over the length of the deletion operation
                 for _ in 0..l {
-                    if ref_pos >= ref_seq.len() - 1 || read_pos >= read_seq.len() - 1 {
+                    if ref_pos + 1 >= ref_seq.len() || read_pos + 1 >= read_seq.len() {
                         break; // break if we've reached the end of the read or reference
                     }
                     // we add the transition from the current state
                     // to the new state (which is deletion)
-                    match state {
-                        AlignmentState::Deletion => {
-                            transition_counts.deletion_from_deletion += 1;
-                        }
-                        AlignmentState::Match => {
-                            transition_counts.deletion_from_match += 1;
-                        }
-                        AlignmentState::Insertion => {
-                            // MINIMAP2 sometimes goes directly from insertion <-> deletion
-                            // we will just add an implicit Insertion -> match -> deletion
-                            transition_counts.match_from_insertion += 1;
-                            transition_counts.deletion_from_match += 1;
-                        }
+                    transition_counts.count(state, AlignmentState::Deletion);
+                    if let Some(ref mut cc) = *context_counts {
+                        cc.count_transition(ref_seq, ref_pos, state, AlignmentState::Deletion);
                     }
 
                     // we have transitioned to deletion so set the current state to deletion
@@ -352,18 +765,24 @@ pub fn count_alignment_events(
 /// #Arguments
 /// -```bam_file```: the input BAM file name
 /// -```fasta_file```: the input FASTA file name
-/// -```interval```: the (optional) GenomicInterval within which variants should be called
-///                  the reads that are used for estimating the alignment parameters are also
-///                  limited to this region.
+/// -```interval_lst```: the GenomicIntervals within which variants should be called (see
+///                      ```get_interval_lst```). the reads that are used for estimating the
+///                      alignment parameters are also limited to these regions.
 /// -```min_mapq```: the minimium mapping quality to use a read
 /// -```max_cigar_indel```: the maximum length of a CIGAR operation in order to count it.
 ///                         this is meant to filter out large indels observed in the BAM alignment
 ///                         that are due to misalignment or structural variations instead of
 ///                         random sequencing error.
+/// -```context_k```: if not None, also estimate alignment parameters for each reference k-mer
+///                   context of this length
+/// -```strand_specific```: also estimate alignment parameters for each strand
+/// -```read_group_specific```: also estimate alignment parameters for each read group (RG tag)
 ///
 /// #Returns
-/// Returns a result contain an ```AlignmentParameters``` struct. This struct contains the alignment
-/// parameters estimated from the BAM file.
+/// Returns a result containing an ```AlignmentParameters``` struct with the alignment parameters
+/// estimated from the BAM file, the ```StrandAlignmentParameters``` if ```strand_specific```,
+/// the ```ContextAlignmentParameters``` for each k-mer context if ```context_k``` is not None,
+/// and the ```ReadGroupAlignmentParameters``` if ```read_group_specific```.
 ///
 /// #Errors
 /// - ```IndexedFastaOpenError```: error opening the indexed FASTA file
@@ -375,16 +794,21 @@ pub fn count_alignment_events(
 pub fn estimate_alignment_parameters(
     bam_file: &String,
     fasta_file: &String,
-    interval: &Option<GenomicInterval>,
+    interval_lst: &Vec<GenomicInterval>,
     min_mapq: u8,
     max_cigar_indel: u32,
-) -> Result<AlignmentParameters> {
+    context_k: Option<usize>,
+    strand_specific: bool,
+    read_group_specific: bool,
+) -> Result<(
+    AlignmentParameters,
+    Option<StrandAlignmentParameters>,
+    Option<ContextAlignmentParameters>,
+    Option<ReadGroupAlignmentParameters>,
+)> {
     let t_names = parse_target_names(&bam_file)?;
 
-    let mut prev_tid = 4294967295; // huge value so that tid != prev_tid on first iter
-    let mut fasta = fasta::IndexedReader::from_file(fasta_file)
-        .chain_err(|| ErrorKind::IndexedFastaOpenError)?;
-    let mut ref_seq: Vec<char> = vec![];
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
 
     // initial transition and emission counts
     // set everything to 1 so that it's impossible to have e.g. divide by 0 errors
@@ -403,19 +827,48 @@ pub fn estimate_alignment_parameters(
         not_equal: 1,
     };
 
-    // interval_lst has either the single specified genomic region, or list of regions covering all chromosomes
-    // for more information about this design decision, see get_interval_lst implementation in util.rs
-    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
+    // counts for the reads on the forward [0] and reverse [1] strand, if the parameters are
+    // estimated separately for each strand
+    let mut strand_counts: [AlignmentCounts; 2] = [AlignmentCounts {
+        transition_counts: transition_counts,
+        emission_counts: emission_counts,
+    }; 2];
+
+    let mut context_counts: Option<ContextCounts> = context_k.map(ContextCounts::new);
+
+    // counts for the reads of each read group, if the parameters are estimated separately for
+    // each read group. reads without a read group are only counted genome-wide.
+    let mut read_group_counts: HashMap<String, AlignmentCounts> = HashMap::new();
+
+    // the transition counts of each region with reads, for the confidence intervals and the
+    // convergence diagnostic
+    let mut region_counts: Vec<TransitionCounts> = vec![];
+
     let mut bam_ix =
-        bam::IndexedReader::from_path(bam_file).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+        open_indexed_bam(bam_file)?;
+
+    let mut progress = ProgressReporter::new(
+        "Alignment parameter estimation",
+        "reference bases",
+        interval_lst_len(interval_lst),
+    );
+    let mut bases_done: usize = 0;
 
     for iv in interval_lst {
+        let iv_bases_done = bases_done;
+        bases_done += (iv.end_pos - iv.start_pos + 1) as usize;
+
         bam_ix
             .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
             .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+        let mut iv_counts = TransitionCounts::default();
+        let mut iv_reads: usize = 0;
 
         for r in bam_ix.records() {
             let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            progress.set(
+                iv_bases_done + (record.pos().max(0) as u32).saturating_sub(iv.start_pos) as usize,
+            );
 
             // check that the read doesn't fail any standard filters
             if record.mapq() < min_mapq
@@ -428,20 +881,13 @@ pub fn estimate_alignment_parameters(
                 continue;
             }
 
-            // if we're on a different contig/chrom than the previous BAM record, we need to read
-            // in the sequence for that contig/chrom from the FASTA into the ref_seq vector
-            let tid: usize = record.tid() as usize;
+            // the reference around the read
             let chrom: String = t_names[record.tid() as usize].clone();
-            if tid != prev_tid {
-                let mut ref_seq_u8: Vec<u8> = vec![];
-                fasta
-                    .fetch_all(&chrom)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                fasta
-                    .read(&mut ref_seq_u8)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                ref_seq = dna_vec(&ref_seq_u8);
-            }
+            let ref_seq = ref_reader.window(
+                &chrom,
+                (record.pos() as usize).saturating_sub(REF_CONTEXT_MARGIN),
+                record.cigar().end_pos() as usize + REF_CONTEXT_MARGIN,
+            )?;
 
             // create a vector of CigarPos from the BAM record
             // these contain the CIGAR operation as well as the reference and read positions
@@ -454,14 +900,39 @@ pub fn estimate_alignment_parameters(
 
             // count the emission and transition events directly from the record's CIGAR and sequences
             let (read_transition_counts, read_emission_counts) =
-                count_alignment_events(&cigarpos_list, &ref_seq, &read_seq, max_cigar_indel)
-                    .chain_err(|| "Error counting cigar alignment events.")?;
+                count_alignment_events(
+                    &cigarpos_list,
+                    ref_seq,
+                    &read_seq,
+                    max_cigar_indel,
+                    &mut context_counts,
+                )
+                .chain_err(|| "Error counting cigar alignment events.")?;
 
             // add emission and transition counts to the running total
             transition_counts.add(read_transition_counts);
             emission_counts.add(read_emission_counts);
-
-            prev_tid = tid;
+            iv_counts.add(read_transition_counts);
+            iv_reads += 1;
+            if strand_specific {
+                let strand = record.is_reverse() as usize;
+                strand_counts[strand]
+                    .transition_counts
+                    .add(read_transition_counts);
+                strand_counts[strand].emission_counts.add(read_emission_counts);
+            }
+            if read_group_specific {
+                if let Some(rg) = read_group(&record) {
+                    let counts = read_group_counts
+                        .entry(rg)
+                        .or_insert_with(AlignmentCounts::default);
+                    counts.transition_counts.add(read_transition_counts);
+                    counts.emission_counts.add(read_emission_counts);
+                }
+            }
+        }
+        if iv_reads > 0 {
+            region_counts.push(iv_counts);
         }
     }
 
@@ -475,58 +946,567 @@ pub fn estimate_alignment_parameters(
     let params = alignment_counts.to_parameters();
 
     // print the estimated alignment parameters to STDERR
-    eprintln!("{} Done estimating alignment parameters.", print_time());
-    eprintln!("");
+    info!("{} Done estimating alignment parameters.", print_time());
+    info!("");
 
-    eprintln!("{} Transition Probabilities:", SPACER);
-    eprintln!(
+    info!("{} Transition Probabilities:", SPACER);
+    info!(
         "{} match -> match:          {:.3}",
         SPACER, params.transition_probs.match_from_match
     );
-    eprintln!(
+    info!(
         "{} match -> insertion:      {:.3}",
         SPACER, params.transition_probs.insertion_from_match
     );
-    eprintln!(
+    info!(
         "{} match -> deletion:       {:.3}",
         SPACER, params.transition_probs.deletion_from_match
     );
-    eprintln!(
+    info!(
         "{} deletion -> match:       {:.3}",
         SPACER, params.transition_probs.match_from_deletion
     );
-    eprintln!(
+    info!(
         "{} deletion -> deletion:    {:.3}",
         SPACER, params.transition_probs.deletion_from_deletion
     );
-    eprintln!(
+    info!(
         "{} insertion -> match:      {:.3}",
         SPACER, params.transition_probs.match_from_insertion
     );
-    eprintln!(
+    info!(
         "{} insertion -> insertion:  {:.3}",
         SPACER, params.transition_probs.insertion_from_insertion
     );
-    eprintln!("");
+    info!("");
 
-    eprintln!("{} Emission Probabilities:", SPACER);
-    eprintln!(
+    info!("{} Emission Probabilities:", SPACER);
+    info!(
         "{} match (equal):           {:.3}",
         SPACER, params.emission_probs.equal
     );
-    eprintln!(
+    info!(
         "{} match (not equal):       {:.3}",
         SPACER, params.emission_probs.not_equal
     );
-    eprintln!(
+    info!(
         "{} insertion:               {:.3}",
         SPACER, params.emission_probs.insertion
     );
-    eprintln!(
+    info!(
         "{} deletion:                {:.3}",
         SPACER, params.emission_probs.deletion
     );
-    eprintln!("");
+    info!("");
+
+    if region_counts.len() >= MIN_DIAGNOSTIC_REGIONS {
+        print_transition_diagnostics(&region_counts);
+    }
+
+    let strand_params: Option<StrandAlignmentParameters> = if strand_specific {
+        let strand_params = StrandAlignmentParameters {
+            forward: strand_counts[0].to_parameters(),
+            reverse: strand_counts[1].to_parameters(),
+        };
+        info!("{} Strand-specific Alignment Parameters:", SPACER);
+        for &(strand, ref p) in &[
+            ("forward", strand_params.forward),
+            ("reverse", strand_params.reverse),
+        ] {
+            info!(
+                "{} {} strand: match (not equal) {:.3}, match -> insertion {:.3}, match -> deletion {:.3}",
+                SPACER,
+                strand,
+                p.emission_probs.not_equal,
+                p.transition_probs.insertion_from_match,
+                p.transition_probs.deletion_from_match
+            );
+        }
+        info!("");
+        Some(strand_params)
+    } else {
+        None
+    };
+
+    let context_params: Option<ContextAlignmentParameters> = match context_counts {
+        Some(ref cc) => {
+            let context_params = cc.to_parameters(&params);
+            print_context_extremes(&context_params);
+            Some(context_params)
+        }
+        None => None,
+    };
+
+    let read_group_params: Option<ReadGroupAlignmentParameters> = if read_group_specific {
+        if read_group_counts.is_empty() {
+            warn!(
+                "{} WARNING: No reads with a read group (RG tag) were found, so the same alignment parameters are used for all reads.",
+                print_time()
+            );
+        }
+        let mut read_groups: Vec<&String> = read_group_counts.keys().collect();
+        read_groups.sort();
+        info!("{} Read-group-specific Alignment Parameters:", SPACER);
+        let mut rg_params: HashMap<String, AlignmentParameters> = HashMap::new();
+        for rg in read_groups {
+            let p =
+                read_group_counts[rg].to_parameters_with_prior(&params, READ_GROUP_PRIOR_WEIGHT);
+            info!(
+                "{} read group {}: match (not equal) {:.3}, match -> insertion {:.3}, match -> deletion {:.3}",
+                SPACER,
+                rg,
+                p.emission_probs.not_equal,
+                p.transition_probs.insertion_from_match,
+                p.transition_probs.deletion_from_match
+            );
+            rg_params.insert(rg.clone(), p);
+        }
+        info!("");
+        Some(ReadGroupAlignmentParameters { params: rg_params })
+    } else {
+        None
+    };
+
+    Ok((params, strand_params, context_params, read_group_params))
+}
+
+/// Prints the sequence contexts with the lowest and highest probability of an error (leaving the
+/// match state) to STDERR
+fn print_context_extremes(context_params: &ContextAlignmentParameters) {
+    let k = context_params.k;
+    let kmer = |ix: usize| -> String {
+        (0..k)
+            .map(|i| ['A', 'C', 'G', 'T'][(ix >> (2 * (k - 1 - i))) & 3])
+            .collect()
+    };
+    // probability of an error at the center base of the context
+    let p_error = |p: &AlignmentParameters| {
+        1.0 - p.transition_probs.match_from_match * (1.0 - 3.0 * p.emission_probs.not_equal)
+    };
+
+    let mut order: Vec<usize> = (0..context_params.params.len()).collect();
+    order.sort_by(|&a, &b| {
+        p_error(&context_params.params[a])
+            .partial_cmp(&p_error(&context_params.params[b]))
+            .unwrap()
+    });
+
+    info!(
+        "{} Estimated alignment parameters for {} sequence contexts of length {}.",
+        print_time(),
+        context_params.params.len(),
+        k
+    );
+    let n_print = order.len().min(5);
+    info!("{} Contexts with the lowest error probability:", SPACER);
+    for &ix in &order[..n_print] {
+        info!(
+            "{} {}:   {:.3}",
+            SPACER,
+            kmer(ix),
+            p_error(&context_params.params[ix])
+        );
+    }
+    info!("{} Contexts with the highest error probability:", SPACER);
+    for &ix in order[order.len() - n_print..].iter().rev() {
+        info!(
+            "{} {}:   {:.3}",
+            SPACER,
+            kmer(ix),
+            p_error(&context_params.params[ix])
+        );
+    }
+    info!("");
+}
+
+/// the header line of an alignment parameters file, naming the columns of each parameter set
+static PARAMETER_FILE_HEADER: &str = "#set\tmatch_from_match\tinsertion_from_match\tdeletion_from_match\tinsertion_from_insertion\tmatch_from_insertion\tdeletion_from_deletion\tmatch_from_deletion\tequal\tnot_equal\tinsertion\tdeletion";
+
+fn format_parameter_set(name: &str, p: &AlignmentParameters) -> String {
+    let t = &p.transition_probs;
+    let e = &p.emission_probs;
+    let values: Vec<String> = [
+        t.match_from_match,
+        t.insertion_from_match,
+        t.deletion_from_match,
+        t.insertion_from_insertion,
+        t.match_from_insertion,
+        t.deletion_from_deletion,
+        t.match_from_deletion,
+        e.equal,
+        e.not_equal,
+        e.insertion,
+        e.deletion,
+    ]
+    .iter()
+    .map(|x| format!("{:e}", x))
+    .collect();
+    format!("{}\t{}", name, values.join("\t"))
+}
+
+fn parse_parameter_set(values: &[&str]) -> Option<AlignmentParameters> {
+    if values.len() != 11 {
+        return None;
+    }
+    let mut v: Vec<f64> = vec![];
+    for s in values {
+        match s.parse::<f64>() {
+            Ok(x) if x >= 0.0 && x <= 1.0 => v.push(x),
+            _ => return None,
+        }
+    }
+    Some(AlignmentParameters {
+        transition_probs: TransitionProbs {
+            match_from_match: v[0],
+            insertion_from_match: v[1],
+            deletion_from_match: v[2],
+            insertion_from_insertion: v[3],
+            match_from_insertion: v[4],
+            deletion_from_deletion: v[5],
+            match_from_deletion: v[6],
+        },
+        emission_probs: EmissionProbs {
+            equal: v[7],
+            not_equal: v[8],
+            insertion: v[9],
+            deletion: v[10],
+        },
+    })
+}
+
+/// the prefix of the names of the read-group-specific parameter sets, followed by the read group ID
+static READ_GROUP_SET_PREFIX: &str = "rg:";
+
+/// Formats estimated alignment parameters as a tab-separated table with one parameter set per
+/// line: the genome-wide parameters (```default```), the strand-specific parameters
+/// (```forward``` and ```reverse```), the parameters of each sequence context (named by the
+/// k-mer) and the parameters of each read group (named ```rg:<ID>```).
+/// Each set is a single line of 11 probabilities, so the table is written and read without a
+/// serialization dependency and can be compared or edited line by line.
+fn format_alignment_parameters(
+    params: &AlignmentParameters,
+    strand_params: &Option<StrandAlignmentParameters>,
+    context_params: &Option<ContextAlignmentParameters>,
+    read_group_params: &Option<ReadGroupAlignmentParameters>,
+) -> String {
+    let mut lines: Vec<String> = vec![
+        PARAMETER_FILE_HEADER.to_string(),
+        format_parameter_set("default", params),
+    ];
+    if let &Some(ref sp) = strand_params {
+        lines.push(format_parameter_set("forward", &sp.forward));
+        lines.push(format_parameter_set("reverse", &sp.reverse));
+    }
+    if let &Some(ref cp) = context_params {
+        for (ix, p) in cp.params.iter().enumerate() {
+            let kmer: String = (0..cp.k)
+                .map(|i| ['A', 'C', 'G', 'T'][(ix >> (2 * (cp.k - 1 - i))) & 3])
+                .collect();
+            lines.push(format_parameter_set(&kmer, p));
+        }
+    }
+    if let &Some(ref rp) = read_group_params {
+        let mut read_groups: Vec<&String> = rp.params.keys().collect();
+        read_groups.sort();
+        for rg in read_groups {
+            lines.push(format_parameter_set(
+                &format!("{}{}", READ_GROUP_SET_PREFIX, rg),
+                &rp.params[rg],
+            ));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Parses a table of alignment parameters written by ```format_alignment_parameters```
+fn parse_alignment_parameters(
+    contents: &str,
+) -> Result<(
+    AlignmentParameters,
+    Option<StrandAlignmentParameters>,
+    Option<ContextAlignmentParameters>,
+    Option<ReadGroupAlignmentParameters>,
+)> {
+    let mut params: Option<AlignmentParameters> = None;
+    let mut forward: Option<AlignmentParameters> = None;
+    let mut reverse: Option<AlignmentParameters> = None;
+    let mut context: Vec<(usize, AlignmentParameters)> = vec![];
+    let mut k: Option<usize> = None;
+    let mut read_group_sets: HashMap<String, AlignmentParameters> = HashMap::new();
 
-    Ok(params)
+    for (line_num, line) in contents.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        let p = match parse_parameter_set(&cols[1..]) {
+            Some(p) => p,
+            None => bail!(
+                "Invalid parameter set on line {} of alignment parameters file.",
+                line_num + 1
+            ),
+        };
+        match cols[0] {
+            "default" => params = Some(p),
+            "forward" => forward = Some(p),
+            "reverse" => reverse = Some(p),
+            name if name.starts_with(READ_GROUP_SET_PREFIX) => {
+                read_group_sets.insert(name[READ_GROUP_SET_PREFIX.len()..].to_string(), p);
+            }
+            kmer => {
+                let kmer_seq: Vec<char> = kmer.chars().collect();
+                let kmer_k = *k.get_or_insert(kmer_seq.len());
+                match kmer_context_index(&kmer_seq, kmer_k / 2, kmer_k) {
+                    Some(ix) if kmer_seq.len() == kmer_k && kmer_k % 2 == 1 => context.push((ix, p)),
+                    _ => bail!(
+                        "Invalid parameter set name {} on line {} of alignment parameters file.",
+                        kmer,
+                        line_num + 1
+                    ),
+                }
+            }
+        }
+    }
+
+    let params = params.chain_err(|| "Alignment parameters file has no default parameters.")?;
+    let strand_params = match (forward, reverse) {
+        (Some(f), Some(r)) => Some(StrandAlignmentParameters {
+            forward: f,
+            reverse: r,
+        }),
+        (None, None) => None,
+        _ => bail!("Alignment parameters file must have both forward and reverse parameters."),
+    };
+    let context_params = match k {
+        Some(k) => {
+            let mut cp = ContextAlignmentParameters {
+                k: k,
+                params: vec![params; 1 << (2 * k)],
+            };
+            ensure!(
+                context.len() == cp.params.len(),
+                "Alignment parameters file must have parameters for all {} contexts of length {}.",
+                cp.params.len(),
+                k
+            );
+            for (ix, p) in context {
+                cp.params[ix] = p;
+            }
+            Some(cp)
+        }
+        None => None,
+    };
+    let read_group_params = if read_group_sets.is_empty() {
+        None
+    } else {
+        Some(ReadGroupAlignmentParameters {
+            params: read_group_sets,
+        })
+    };
+
+    Ok((params, strand_params, context_params, read_group_params))
+}
+
+/// Writes estimated alignment parameters to a file, so that they can be reused with
+/// ```read_alignment_parameters``` instead of estimating them again
+pub fn write_alignment_parameters(
+    params_file: &String,
+    params: &AlignmentParameters,
+    strand_params: &Option<StrandAlignmentParameters>,
+    context_params: &Option<ContextAlignmentParameters>,
+    read_group_params: &Option<ReadGroupAlignmentParameters>,
+) -> Result<()> {
+    let mut file =
+        File::create(params_file).chain_err(|| ErrorKind::CreateFileError(params_file.clone()))?;
+    let contents =
+        format_alignment_parameters(params, strand_params, context_params, read_group_params);
+    file.write_all(contents.as_bytes())
+        .chain_err(|| ErrorKind::FileWriteError(params_file.clone()))?;
+    Ok(())
+}
+
+/// Reads alignment parameters written by ```write_alignment_parameters```
+pub fn read_alignment_parameters(
+    params_file: &String,
+) -> Result<(
+    AlignmentParameters,
+    Option<StrandAlignmentParameters>,
+    Option<ContextAlignmentParameters>,
+    Option<ReadGroupAlignmentParameters>,
+)> {
+    let contents = fs::read_to_string(params_file)
+        .chain_err(|| ErrorKind::FileReadError(params_file.clone()))?;
+    parse_alignment_parameters(&contents)
+        .chain_err(|| format!("Error reading alignment parameters file {}.", params_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::CigarString;
+
+    #[test]
+    fn test_count_alignment_events_without_md_tag() {
+        // minimap2 doesn't write MD tags by default: the mismatches are found by comparing the
+        // read to the reference along the CIGAR, whether it has M or =/X operations (--eqx)
+        let ref_seq = RefSeqWindow::whole_contig(dna_vec(b"ACGTACGTACGTACGTACGT"));
+        for cigar in &[
+            vec![Cigar::Match(16)],
+            vec![
+                Cigar::Equal(3),
+                Cigar::Diff(1),
+                Cigar::Equal(6),
+                Cigar::Diff(1),
+                Cigar::Equal(5),
+            ],
+        ] {
+            let mut record = bam::Record::new();
+            record.set(
+                b"read1",
+                Some(&CigarString(cigar.clone())),
+                b"GTAGGTACGTTCGTAC",
+                &[30; 16],
+            );
+            record.set_pos(2);
+            assert!(record.aux(b"MD").is_none());
+
+            let cigarpos_list = create_augmented_cigarlist(2, &record.cigar()).unwrap();
+            let read_seq = dna_vec(&record.seq().as_bytes());
+            let (transitions, emissions) =
+                count_alignment_events(&cigarpos_list, &ref_seq, &read_seq, 20, &mut None)
+                    .unwrap();
+            // the last base of the read is not counted
+            assert_eq!(transitions.match_from_match, 15);
+            assert_eq!(emissions.equal, 13);
+            assert_eq!(emissions.not_equal, 2);
+        }
+    }
+
+    #[test]
+    fn test_alignment_parameters_file_roundtrip() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.879,
+                insertion_from_match: 0.1,
+                deletion_from_match: 0.021,
+                insertion_from_insertion: 0.4,
+                match_from_insertion: 0.6,
+                deletion_from_deletion: 0.25,
+                match_from_deletion: 0.75,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.99,
+                not_equal: 0.01 / 3.0,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let mut reverse = params;
+        reverse.emission_probs.not_equal = 0.005;
+        let strand_params = Some(StrandAlignmentParameters {
+            forward: params,
+            reverse: reverse,
+        });
+        let mut context_params = ContextAlignmentParameters {
+            k: 1,
+            params: vec![params; 4],
+        };
+        context_params.params[2].transition_probs.match_from_match = 0.5;
+
+        let mut rg_params: HashMap<String, AlignmentParameters> = HashMap::new();
+        let mut hifi = params;
+        hifi.transition_probs.match_from_match = 0.99;
+        rg_params.insert("hifi_1".to_string(), hifi);
+        let read_group_params = Some(ReadGroupAlignmentParameters { params: rg_params });
+
+        let contents = format_alignment_parameters(
+            &params,
+            &strand_params,
+            &Some(context_params),
+            &read_group_params,
+        );
+        let (p, sp, cp, rp) = parse_alignment_parameters(&contents).unwrap();
+        assert_eq!(p.emission_probs.not_equal, 0.01 / 3.0);
+        assert_eq!(sp.unwrap().reverse.emission_probs.not_equal, 0.005);
+        let cp = cp.unwrap();
+        assert_eq!(cp.k, 1);
+        assert_eq!(cp.params[2].transition_probs.match_from_match, 0.5);
+        assert_eq!(cp.params[3].transition_probs.match_from_match, 0.879);
+        let rp = rp.unwrap();
+        assert_eq!(
+            rp.for_read_group(Some("hifi_1"), p)
+                .transition_probs
+                .match_from_match,
+            0.99
+        );
+        assert_eq!(
+            rp.for_read_group(Some("ont_1"), p)
+                .transition_probs
+                .match_from_match,
+            0.879
+        );
+
+        // missing contexts and unknown names are rejected
+        let lines: Vec<&str> = contents.lines().collect();
+        assert!(parse_alignment_parameters(&lines[..lines.len() - 1].join("\n")).is_err());
+        assert!(parse_alignment_parameters(&contents.replace("forward", "ACN")).is_err());
+    }
+
+    #[test]
+    fn test_select_stratified_windows() {
+        let window = |tid: u32, start: u32| GenomicInterval {
+            tid: tid,
+            chrom: format!("chr{}", tid + 1),
+            start_pos: start,
+            end_pos: start + 999,
+        };
+        // 20 windows of average GC content, 4 GC-rich windows and 1 AT-rich window
+        let mut candidates: Vec<(GenomicInterval, f64)> =
+            (0..20).map(|i| (window(i % 2, i * 1000), 0.4)).collect();
+        candidates.extend((0..4).map(|i| (window(2, i * 1000), 0.7)));
+        candidates.push((window(2, 50000), 0.2));
+
+        // the bins get 4, 1 and (at least) 1 windows
+        let windows = select_stratified_windows(candidates.clone(), 5);
+        let positions: Vec<(u32, u32)> = windows.iter().map(|w| (w.tid, w.start_pos)).collect();
+        assert_eq!(positions.len(), 6);
+        let mut sorted = positions.clone();
+        sorted.sort();
+        assert_eq!(positions, sorted);
+        assert!(positions.contains(&(2, 50000)));
+        assert_eq!(positions.iter().filter(|p| p.0 == 2).count(), 2);
+        // the windows of average GC content are spread over both contigs
+        assert_eq!(positions.iter().filter(|p| p.0 == 0).count(), 2);
+
+        // all of the candidates if there are too few
+        assert_eq!(select_stratified_windows(candidates, 100).len(), 25);
+    }
+
+    #[test]
+    fn test_transition_diagnostics() {
+        let counts = |mm: usize, im: usize, dm: usize| TransitionCounts {
+            match_from_match: mm,
+            insertion_from_match: im,
+            deletion_from_match: dm,
+            insertion_from_insertion: im / 4,
+            match_from_insertion: im,
+            deletion_from_deletion: dm / 4,
+            match_from_deletion: dm,
+        };
+
+        // regions with the same error rates give narrow intervals and agreeing halves
+        let uniform: Vec<TransitionCounts> = (0..20).map(|_| counts(9000, 400, 600)).collect();
+        let intervals = transition_prob_intervals(&uniform);
+        assert_eq!(intervals.len(), 7);
+        assert!((intervals[1].0 - 0.04).abs() < 0.001 && (intervals[1].1 - 0.04).abs() < 0.001);
+        assert!(split_half_difference(&uniform) < 0.01);
+
+        // the insertion rate differs between the even and odd regions
+        let biased: Vec<TransitionCounts> = (0..20)
+            .map(|i| counts(9000, if i % 2 == 0 { 200 } else { 800 }, 600))
+            .collect();
+        let biased_intervals = transition_prob_intervals(&biased);
+        assert!(biased_intervals[1].1 - biased_intervals[1].0 > intervals[1].1 - intervals[1].0);
+        assert!(split_half_difference(&biased) > CONVERGENCE_TOLERANCE);
+    }
 }
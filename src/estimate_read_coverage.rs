@@ -10,7 +10,43 @@ extern crate rust_htslib;
 use errors::*;
 use rust_htslib::bam;
 use rust_htslib::bam::Read;
-use util::{get_interval_lst, print_time, GenomicInterval};
+use util::{
+    get_interval_lst, open_bam, open_indexed_bam, print_time, DepthDefinition, GenomicInterval,
+    IndexedBamReader,
+};
+
+/// Whether a read counts toward the coverage (reads that fail the basic QC filters don't)
+fn counts_toward_coverage(record: &bam::Record) -> bool {
+    !(record.is_unmapped()
+        || record.is_secondary()
+        || record.is_quality_check_failed()
+        || record.is_duplicate()
+        || record.is_supplementary())
+}
+
+/// Calculates the mean coverage of a small interval, e.g. of one of the windows that the coverage
+/// of a contig is sampled in. Unlike ```calculate_mean_coverage```, nothing is printed.
+pub fn interval_mean_coverage(
+    bam_ix: &mut IndexedBamReader,
+    iv: &GenomicInterval,
+    depth_definition: DepthDefinition,
+) -> Result<f64> {
+    bam_ix
+        .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+        .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+    let mut total_read_bases: usize = 0;
+    for p in bam_ix.pileup() {
+        let pileup = p.chain_err(|| ErrorKind::IndexedBamPileupReadError)?;
+        if pileup.tid() != iv.tid || pileup.pos() < iv.start_pos || pileup.pos() > iv.end_pos {
+            continue;
+        }
+        total_read_bases += pileup
+            .alignments()
+            .filter(|a| counts_toward_coverage(&a.record()) && depth_definition.counts(a))
+            .count();
+    }
+    Ok(total_read_bases as f64 / (iv.end_pos - iv.start_pos + 1) as f64)
+}
 
 /// Calculates the mean coverage of the input BAM file over the input region
 ///
@@ -19,6 +55,7 @@ use util::{get_interval_lst, print_time, GenomicInterval};
 /// -```interval```: the (optional) GenomicInterval within which variants should be called
 ///                  the reads that are used for estimating the alignment parameters are also
 ///                  limited to this region.
+/// -```depth_definition```: which reads overlapping a position count toward its coverage
 ///
 /// #Returns
 /// Returns a result containing the mean coverage.
@@ -33,13 +70,14 @@ use util::{get_interval_lst, print_time, GenomicInterval};
 pub fn calculate_mean_coverage(
     bam_file: &String,
     interval: &Option<GenomicInterval>,
+    depth_definition: DepthDefinition,
 ) -> Result<f64> {
     // currently, we open up a separate BAM (bam) and indexed BAM (bam_ix) handle
     // we do this because it's illegal to borrow from bam_ix both mutably to do pileup and
     // immutably to access stuff from the header
     // I am pretty sure that recent versions of Rust-htslib implement clone() for the bam header
     // which might make this unneccessary
-    let bam = bam::Reader::from_path(bam_file).chain_err(|| ErrorKind::BamOpenError)?;
+    let bam = open_bam(bam_file)?;
 
     // count variables and etc
     let mut prev_tid = 4294967295;
@@ -52,7 +90,7 @@ pub fn calculate_mean_coverage(
     let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
 
     let mut bam_ix =
-        bam::IndexedReader::from_path(bam_file).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+        open_indexed_bam(bam_file)?;
 
     for iv in interval_lst {
         bam_ix
@@ -84,20 +122,15 @@ pub fn calculate_mean_coverage(
 
             // pileup the bases for a single position and count number of each base
             for alignment in pileup.alignments() {
-                let record = alignment.record();
-
                 // filter out some read QC failures
-                if record.is_unmapped()
-                    || record.is_secondary()
-                    || record.is_quality_check_failed()
-                    || record.is_duplicate()
-                    || record.is_supplementary()
-                {
+                if !counts_toward_coverage(&alignment.record()) {
                     continue;
                 }
 
                 // increment depth count
-                depth += 1;
+                if depth_definition.counts(&alignment) {
+                    depth += 1;
+                }
             }
 
             bam_covered_positions += 1;
@@ -115,7 +148,7 @@ pub fn calculate_mean_coverage(
         &None => {
             // output a warning if the number of covered bases is significantly less than the ref positions
             if total_bam_ref_positions / 2 > bam_covered_positions {
-                eprintln!("{} WARNING: Max coverage calculation is highly likely to be incorrect. The number of reference \
+                warn!("{} WARNING: Max coverage calculation is highly likely to be incorrect. The number of reference \
                               bases covered by the bam file ({}) differs significantly from the expected number of positions in the \
                               reference ({}). If you are using a bam file that only covers part of the genome, please specify \
                               this region exactly with the --region argument so the number of reference bases is known. \
@@ -128,12 +161,12 @@ pub fn calculate_mean_coverage(
     };
 
     // print the total reference positions and number of observed bases in BAM file
-    eprintln!(
+    info!(
         "{} Total reference positions: {}",
         print_time(),
         total_ref_positions
     );
-    eprintln!("{} Total bases in bam: {}", print_time(), total_read_bases);
+    info!("{} Total bases in bam: {}", print_time(), total_read_bases);
 
     Ok(total_read_bases as f64 / total_ref_positions as f64)
 }
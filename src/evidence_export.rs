@@ -0,0 +1,400 @@
+//! Export of the per-read evidence at the candidate sites to a NumPy ```.npz``` archive, e.g. to
+//! train a model that re-scores longshot's calls from its realignment results instead of the
+//! summaries in the VCF (```--out-evidence```).
+//!
+//! The evidence of a site is a matrix with a row per read that has an allele call at the site and
+//! a column per feature (see ```EVIDENCE_FEATURE_NAMES```). The matrices of all sites are stacked
+//! into the ```features``` array, and the rows of site ```i``` are
+//! ```features[site_offsets[i]:site_offsets[i + 1]]```. The other ```site_*``` arrays describe the
+//! sites, in the order of the output VCF. The archive is an uncompressed ZIP file of ```.npy```
+//! arrays (as written by ```numpy.savez```), so it can be read with ```numpy.load```.
+
+use errors::*;
+use haplotype_assembly::haplotype_posteriors;
+use std::f32;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::u32;
+use util::*;
+use variants_and_fragments::*;
+
+/// The columns of the ```features``` array. ```allele``` is the index of the allele called in the
+/// read (0 for the reference allele), ```ln_p_allele``` and ```ln_p_miscall``` are the natural
+/// log-probabilities that the call is right and wrong, ```read_pos``` is the 0-based position of
+/// the variant in the read sequence as stored in the BAM file, and ```ln_p_hap1``` and
+/// ```ln_p_hap2``` are the log posterior probabilities that the read comes from each haplotype.
+/// The MAPQ, base quality and read position are NaN if they are not known.
+pub static EVIDENCE_FEATURE_NAMES: [&str; 9] = [
+    "allele",
+    "ln_p_allele",
+    "ln_p_miscall",
+    "mapq",
+    "base_qual",
+    "reverse_strand",
+    "read_pos",
+    "ln_p_hap1",
+    "ln_p_hap2",
+];
+
+/// The per-read evidence of the candidate sites, collected region by region and written to an
+/// ```.npz``` archive at the end of the run
+pub struct EvidenceTensor {
+    features: Vec<f32>,
+    site_offsets: Vec<i64>,
+    site_contig: Vec<i32>,
+    site_pos: Vec<i64>,
+    site_ref: Vec<String>,
+    site_alt: Vec<String>,
+    site_qual: Vec<f32>,
+    site_genotype: Vec<i32>,
+    contigs: Vec<String>,
+}
+
+impl EvidenceTensor {
+    pub fn new() -> EvidenceTensor {
+        EvidenceTensor {
+            features: vec![],
+            site_offsets: vec![0],
+            site_contig: vec![],
+            site_pos: vec![],
+            site_ref: vec![],
+            site_alt: vec![],
+            site_qual: vec![],
+            site_genotype: vec![],
+            contigs: vec![],
+        }
+    }
+
+    /// the number of sites
+    pub fn n_sites(&self) -> usize {
+        self.site_pos.len()
+    }
+
+    /// the number of rows of the ```features``` array (allele calls)
+    pub fn n_rows(&self) -> usize {
+        self.features.len() / EVIDENCE_FEATURE_NAMES.len()
+    }
+
+    /// Adds the variants of ```varlist``` in ```interval``` (all of them if None), with the allele
+    /// calls of the fragments in ```flist```. The fragments should be genotyped and assigned to
+    /// haplotypes.
+    pub fn add_sites(
+        &mut self,
+        flist: &Vec<Fragment>,
+        varlist: &VarList,
+        interval: &Option<GenomicInterval>,
+    ) {
+        if self.contigs.is_empty() {
+            self.contigs = varlist.target_names.clone();
+        }
+
+        // the rows of the calls of each variant, in the order of the fragments
+        let mut var_rows: Vec<Vec<[f32; 9]>> = vec![vec![]; varlist.lst.len()];
+        let known = |is_known: bool, value: f32| if is_known { value } else { f32::NAN };
+        for f in flist {
+            let (p_hap1, p_hap2) = haplotype_posteriors(f);
+            for call in &f.calls {
+                var_rows[call.var_ix].push([
+                    call.allele as f32,
+                    *call.one_minus_qual as f32,
+                    *call.qual as f32,
+                    known(f.mapq != 255, f.mapq as f32),
+                    known(call.base_qual != 255, call.base_qual as f32),
+                    if call.reverse_strand { 1.0 } else { 0.0 },
+                    known(call.read_pos != u32::MAX, call.read_pos as f32),
+                    *p_hap1 as f32,
+                    *p_hap2 as f32,
+                ]);
+            }
+        }
+
+        for (var, rows) in varlist.lst.iter().zip(var_rows.iter()) {
+            if let &Some(ref iv) = interval {
+                if var.tid != iv.tid
+                    || var.pos0 < iv.start_pos as usize
+                    || var.pos0 > iv.end_pos as usize
+                {
+                    continue;
+                }
+            }
+            for row in rows {
+                self.features.extend_from_slice(row);
+            }
+            let n_rows = self.n_rows() as i64;
+            self.site_offsets.push(n_rows);
+            self.site_contig.push(var.tid as i32);
+            self.site_pos.push(var.pos0 as i64 + 1);
+            self.site_ref.push(var.alleles[0].clone());
+            self.site_alt.push(var.alleles[1..].join(","));
+            self.site_qual.push(var.qual as f32);
+            self.site_genotype.push(var.genotype.0 as i32);
+            self.site_genotype.push(var.genotype.1 as i32);
+        }
+    }
+
+    /// Writes the evidence to an ```.npz``` archive.
+    ///
+    /// # Errors
+    /// Fails if the file can't be written, or if an array is larger than 4 GB (the limit of ZIP
+    /// files without the ZIP64 extensions).
+    pub fn write_npz(&self, output_file: &String) -> Result<()> {
+        let n_features = EVIDENCE_FEATURE_NAMES.len();
+        let feature_names: Vec<String> =
+            EVIDENCE_FEATURE_NAMES.iter().map(|s| s.to_string()).collect();
+        let arrays: Vec<(&str, Vec<u8>)> = vec![
+            ("features", npy_f32(&self.features, &[self.n_rows(), n_features])),
+            ("feature_names", npy_str(&feature_names)),
+            ("site_offsets", npy_i64(&self.site_offsets)),
+            ("site_contig", npy_i32(&self.site_contig, &[self.n_sites()])),
+            ("site_pos", npy_i64(&self.site_pos)),
+            ("site_ref", npy_str(&self.site_ref)),
+            ("site_alt", npy_str(&self.site_alt)),
+            ("site_qual", npy_f32(&self.site_qual, &[self.n_sites()])),
+            ("site_genotype", npy_i32(&self.site_genotype, &[self.n_sites(), 2])),
+            ("contigs", npy_str(&self.contigs)),
+        ];
+
+        let path = Path::new(output_file);
+        let display = path.display();
+        let mut file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+        let mut zip: Vec<u8> = vec![];
+        write_stored_zip(&mut zip, &arrays)?;
+        file.write_all(&zip)
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Returns the header of a ```.npy``` file (format version 1.0) for an array of ```shape``` with
+/// the NumPy type ```descr```. The header is padded so that the data starts at a multiple of 64
+/// bytes.
+fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let shape_str = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!(
+            "({})",
+            shape.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(", ")
+        ),
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape_str
+    );
+    // magic string (6 bytes), version (2 bytes), header length (2 bytes), dict and newline
+    while (10 + dict.len() + 1) % 64 != 0 {
+        dict.push(' ');
+    }
+    dict.push('\n');
+
+    let mut header: Vec<u8> = b"\x93NUMPY\x01\x00".to_vec();
+    header.push((dict.len() & 0xff) as u8);
+    header.push((dict.len() >> 8) as u8);
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+fn npy_f32(values: &Vec<f32>, shape: &[usize]) -> Vec<u8> {
+    let mut npy = npy_header("<f4", shape);
+    for v in values {
+        npy.extend_from_slice(&v.to_bits().to_le_bytes());
+    }
+    npy
+}
+
+fn npy_i32(values: &Vec<i32>, shape: &[usize]) -> Vec<u8> {
+    let mut npy = npy_header("<i4", shape);
+    for v in values {
+        npy.extend_from_slice(&v.to_le_bytes());
+    }
+    npy
+}
+
+fn npy_i64(values: &Vec<i64>) -> Vec<u8> {
+    let mut npy = npy_header("<i8", &[values.len()]);
+    for v in values {
+        npy.extend_from_slice(&v.to_le_bytes());
+    }
+    npy
+}
+
+/// a 1-dimensional array of fixed-width unicode strings (NumPy type ```<U```), as wide as the
+/// longest string
+fn npy_str(values: &Vec<String>) -> Vec<u8> {
+    let width = values.iter().map(|s| s.chars().count()).max().unwrap_or(0).max(1);
+    let mut npy = npy_header(&format!("<U{}", width), &[values.len()]);
+    for s in values {
+        let mut n = 0;
+        for c in s.chars() {
+            npy.extend_from_slice(&(c as u32).to_le_bytes());
+            n += 1;
+        }
+        for _ in n..width {
+            npy.extend_from_slice(&[0u8; 4]);
+        }
+    }
+    npy
+}
+
+/// the CRC-32 checksum (as in ZIP and gzip files) of ```data```
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for i in 0..256 {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[i] = c;
+    }
+    let mut crc = u32::MAX;
+    for &b in data {
+        crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Writes a ZIP archive with the ```.npy``` arrays, stored without compression, with the file
+/// names ```<name>.npy```.
+fn write_stored_zip(out: &mut Vec<u8>, arrays: &Vec<(&str, Vec<u8>)>) -> Result<()> {
+    // the date of the files, 1980-01-01 (the earliest date of the ZIP format)
+    let dos_date: u16 = (1 << 5) | 1;
+    let u16_le = |out: &mut Vec<u8>, v: u16| out.extend_from_slice(&v.to_le_bytes());
+    let u32_le = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&v.to_le_bytes());
+    let to_u32 = |n: usize| -> Result<u32> {
+        ensure!(
+            n <= u32::MAX as usize,
+            "The evidence archive is larger than 4 GB. Export a smaller region."
+        );
+        Ok(n as u32)
+    };
+
+    let mut central_dir: Vec<u8> = vec![];
+    for &(name, ref data) in arrays {
+        let file_name = format!("{}.npy", name);
+        let crc = crc32(data);
+        let size = to_u32(data.len())?;
+        let offset = to_u32(out.len())?;
+
+        u32_le(out, 0x0403_4b50);
+        u16_le(out, 20); // version needed to extract
+        u16_le(out, 0); // flags
+        u16_le(out, 0); // compression method: stored
+        u16_le(out, 0); // time
+        u16_le(out, dos_date);
+        u32_le(out, crc);
+        u32_le(out, size); // compressed size
+        u32_le(out, size); // uncompressed size
+        u16_le(out, file_name.len() as u16);
+        u16_le(out, 0); // extra field length
+        out.extend_from_slice(file_name.as_bytes());
+        out.extend_from_slice(data);
+
+        u32_le(&mut central_dir, 0x0201_4b50);
+        u16_le(&mut central_dir, 20); // version made by
+        u16_le(&mut central_dir, 20); // version needed to extract
+        u16_le(&mut central_dir, 0);
+        u16_le(&mut central_dir, 0);
+        u16_le(&mut central_dir, 0);
+        u16_le(&mut central_dir, dos_date);
+        u32_le(&mut central_dir, crc);
+        u32_le(&mut central_dir, size);
+        u32_le(&mut central_dir, size);
+        u16_le(&mut central_dir, file_name.len() as u16);
+        u16_le(&mut central_dir, 0); // extra field length
+        u16_le(&mut central_dir, 0); // comment length
+        u16_le(&mut central_dir, 0); // disk number
+        u16_le(&mut central_dir, 0); // internal attributes
+        u32_le(&mut central_dir, 0); // external attributes
+        u32_le(&mut central_dir, offset);
+        central_dir.extend_from_slice(file_name.as_bytes());
+    }
+
+    let central_dir_offset = to_u32(out.len())?;
+    let central_dir_size = to_u32(central_dir.len())?;
+    out.extend_from_slice(&central_dir);
+    u32_le(out, 0x0605_4b50);
+    u16_le(out, 0); // disk number
+    u16_le(out, 0); // disk with the central directory
+    u16_le(out, arrays.len() as u16);
+    u16_le(out, arrays.len() as u16);
+    u32_le(out, central_dir_size);
+    u32_le(out, central_dir_offset);
+    u16_le(out, 0); // comment length
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genotype_probs::Genotype;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_npy_header() {
+        let header = npy_header("<f4", &[3, 9]);
+        assert_eq!(header.len() % 64, 0);
+        assert_eq!(&header[..8], b"\x93NUMPY\x01\x00");
+        let dict = String::from_utf8(header[10..].to_vec()).unwrap();
+        assert!(dict.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 9), }"));
+        assert!(dict.ends_with(" \n"));
+
+        let npy = npy_str(&vec!["AC".to_string(), "G".to_string()]);
+        let data = &npy[npy_header("<U2", &[2]).len()..];
+        assert_eq!(data, &[65, 0, 0, 0, 67, 0, 0, 0, 71, 0, 0, 0, 0, 0, 0, 0][..]);
+    }
+
+    #[test]
+    fn test_add_sites() {
+        let mut flist = fragments_from_strings(&["01", "1-", "-0"], 0.01);
+        flist[0].mapq = 60;
+        flist[0].calls[1].reverse_strand = true;
+        flist[0].calls[1].read_pos = 120;
+        flist[0].calls[1].base_qual = 30;
+        let mut vars = vec![
+            Var::new(0, 100, vec!["A".to_string(), "G".to_string()]),
+            Var::new(0, 200, vec!["C".to_string(), "T".to_string(), "G".to_string()]),
+        ];
+        vars[0].genotype = Genotype(0, 1);
+        vars[0].qual = 50.0;
+        let varlist = VarList::new(vars, vec!["chr1".to_string()]).unwrap();
+
+        let mut evidence = EvidenceTensor::new();
+        evidence.add_sites(&flist, &varlist, &None);
+        assert_eq!(evidence.n_sites(), 2);
+        assert_eq!(evidence.n_rows(), 4);
+        assert_eq!(evidence.site_offsets, vec![0, 2, 4]);
+        assert_eq!(evidence.site_pos, vec![101, 201]);
+        assert_eq!(evidence.site_alt, vec!["G".to_string(), "T,G".to_string()]);
+        assert_eq!(evidence.site_genotype, vec![0, 1, 0, 0]);
+
+        // the second call of the first read
+        let row = &evidence.features[2 * 9..3 * 9];
+        assert_eq!(row[0], 1.0);
+        assert!((row[1] - 0.99f32.ln()).abs() < 1e-6);
+        assert!((row[2] - 0.01f32.ln()).abs() < 1e-5);
+        assert_eq!(&row[3..7], &[60.0, 30.0, 1.0, 120.0][..]);
+        assert!((row[7] - 0.5f32.ln()).abs() < 1e-3);
+
+        // a call without a known base quality and read position
+        let row = &evidence.features[3 * 9..4 * 9];
+        assert!(row[4].is_nan() && row[6].is_nan());
+
+        // only the sites in the interval are added
+        let mut evidence = EvidenceTensor::new();
+        let interval = Some(GenomicInterval {
+            tid: 0,
+            chrom: "chr1".to_string(),
+            start_pos: 150,
+            end_pos: 250,
+        });
+        evidence.add_sites(&flist, &varlist, &interval);
+        assert_eq!(evidence.site_offsets, vec![0, 2]);
+        assert_eq!(evidence.site_pos, vec![201]);
+    }
+}
@@ -22,18 +22,26 @@
 //!     containing ```FragCall``` structs for each call.
 
 // use declarations
-use bio::io::fasta;
 use bio::pattern_matching::bndm;
 use bio::stats::{LogProb, PHREDProb, Prob};
 use errors::*;
+use rand::{Rng, SeedableRng, StdRng};
 use realignment::*;
+use telemetry::count_alignment_cache;
+use soft_clip_clusters::{find_soft_clip_clusters, ClippedEnd, SoftClipCluster};
 use rust_htslib::bam;
 use rust_htslib::bam::record::Cigar;
 use rust_htslib::bam::record::CigarStringView;
 use rust_htslib::bam::record::Record;
-use rust_htslib::bam::Read;
 use util::*;
 use variants_and_fragments::*;
+use estimate_alignment_parameters::AlignmentState;
+use fragment_store::FragmentSpill;
+use hashbrown::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::mem;
+use std::f64;
 use std::u32;
 use std::usize;
 
@@ -44,9 +52,11 @@ static IGNORE_INDEL_ONLY_CLUSTERS: bool = false;
 /// to pass all of the parameters between functions in this module
 #[derive(Clone, Copy)]
 pub struct ExtractFragmentParameters {
-    /// minimum mapping quality to use (extract haplotype information for) a read
-    pub min_mapq: u8,
-    /// type of alignment algorithm to use (viterbi, forward algorithm, numerically stable forward algorithm)
+    /// the flag, mapping quality and length filters for the reads to use (extract haplotype
+    /// information for)
+    pub read_filter: ReadFilter,
+    /// type of alignment algorithm to use (viterbi, forward algorithm, numerically stable forward
+    /// algorithm, forward algorithm on the run-length compressed sequences)
     pub alignment_type: AlignmentType, //
     /// band width for the alignment algorithm
     pub band_width: usize,
@@ -57,16 +67,1063 @@ pub struct ExtractFragmentParameters {
     /// are considered in combination (a "short-haplotype") during variant realignment but it is
     /// computationally infeasible to consider too many together.
     pub variant_cluster_max_size: usize,
+    /// maximum number of short-haplotypes that are aligned to a read for a variant cluster (the
+    /// product of the numbers of alleles of its variants). Larger clusters are split where the
+    /// realignment windows of adjacent variants overlap the least. If 0, clusters are simply cut
+    /// after variant_cluster_max_size variants.
+    pub cluster_work_budget: usize,
     /// the maximum distance in bp to the left or right of a variant (or short-haplotype) that the
     /// realignment window can be expanded to.
     pub max_window_padding: usize,
+    /// the minimum number of bases between a variant and the anchors of its realignment window.
+    /// the window is padded by up to this many bases on either side (within the read) before the
+    /// anchors are searched for.
+    pub window_flank: usize,
+    /// only end the realignment windows at anchors that match the read exactly and are unique.
+    /// otherwise a window without such an anchor ends at max_window_padding bases from the
+    /// variant. variants without exact anchors are skipped for the read.
+    pub exact_anchors: bool,
+    /// the realignment windows of the variants on a read that overlap, or are at most this many
+    /// bases apart, are merged, and the read is realigned once to the short-haplotypes of all of
+    /// their variants
+    pub window_merge_distance: usize,
     /// the maximum allowed size of a CIGAR indel in order to use a realignment window.
     /// if a CIGAR indel is encountered that exceeds this size while forming the window,
     /// the allele site is thrown out for that read.
     pub max_cigar_indel: usize,
     /// whether or not to store the read id.
     /// we store the read ID if we'll be separating reads by haplotype and otherwise we don't
-    pub store_read_id: bool
+    pub store_read_id: bool,
+    /// minimum value of the AS (alignment score) tag to use a read.
+    /// reads without an AS tag are not removed by this filter.
+    pub min_alignment_score: Option<i64>,
+    /// minimum fraction of the read's bases (including soft and hard clipped bases) that
+    /// must be aligned to the reference in order to use the read
+    pub min_aligned_frac: f64,
+    /// maximum total number of soft-clipped bases (both ends combined) allowed to use a read
+    pub max_soft_clip: Option<usize>,
+    /// maximum distance in bp between a variant and the soft-clipped end of a read, for the read
+    /// to be counted as soft-clipped at the variant (for the soft-clip fraction annotation), and
+    /// between the clipped read ends of a soft-clip cluster
+    pub soft_clip_window: usize,
+    /// if not None, the soft-clipped read ends are clustered, and clusters with at least this many
+    /// clipped ends are returned as putative structural variant or misassembly loci
+    pub min_soft_clip_cluster: Option<usize>,
+    /// what to do with reads whose base quality string is absent ('*' in the SAM QUAL field)
+    pub missing_qual_policy: MissingQualPolicy,
+    /// reduce memory usage by discarding the reads without any allele calls (unless
+    /// ```store_read_id``` is set)
+    pub low_memory: bool,
+    /// write the fragments to a temporary file during read realignment instead of keeping them in
+    /// memory (see ```FragmentSpill```)
+    pub spill_fragments: bool,
+    /// if not None, the maximum number of reads starting in each window of ```DOWNSAMPLE_WINDOW```
+    /// bases that are used. the reads of windows with more reads are downsampled.
+    pub max_depth: Option<usize>,
+    /// if not None, the maximum number of reads that are realigned to each variant. at variants
+    /// with more reads, the most informative reads are chosen (see
+    /// ```select_informative_reads```), and the others are skipped for the variant.
+    pub max_realign_depth: Option<usize>,
+    /// maximum number of read/haplotype alignment scores to keep in the alignment cache (0 to
+    /// disable the cache)
+    pub alignment_cache_size: usize,
+    /// merge the fragments of the alignments of the same read (e.g. the primary and supplementary
+    /// alignments of a read split across a structural variant) on the same contig and strand
+    pub stitch_split_reads: bool,
+    /// use the reads that start or end within the realignment window of a variant, by aligning
+    /// them to the haplotypes with a free end (see ```forward_algorithm_free_end```). Otherwise the
+    /// variants are skipped for these reads.
+    pub partial_reads: bool,
+    /// take the allele calls of the reads directly from their BAM alignments (see
+    /// ```extract_pileup_fragment```) instead of realigning them to the haplotypes
+    pub pileup_only: bool,
+    /// if not None, a read window whose edit distance to one short-haplotype is lower by at least
+    /// this many edits than its edit distance to every other short-haplotype is scored with the
+    /// edit distances (see ```EditDistance```) instead of the realignment model
+    pub prefilter_edits: Option<usize>,
+    /// widen the alignment band of the variant clusters whose realignment window contains a
+    /// tandem repeat (see ```repeat_band_width```)
+    pub repeat_band: bool,
+    /// how to realign the reads in windows with ambiguous reference bases
+    pub ref_ambiguity: RefAmbiguity,
+    /// stop with an error at the first read with a malformed or unsupported CIGAR string (see
+    /// ```cigar_problem```) instead of skipping the read with a warning
+    pub strict: bool,
+}
+
+/// the longest period (repeat unit length) of the tandem repeats that widen the alignment band
+static MAX_REPEAT_PERIOD: usize = 6;
+/// the minimum length of the tandem repeats that widen the alignment band
+static MIN_REPEAT_LEN: usize = 10;
+
+/// the length of the windows in which reads are downsampled to ```max_depth``` reads
+static DOWNSAMPLE_WINDOW: u32 = 1000;
+/// the random seed for downsampling, combined with the position of each window so that the same
+/// reads are chosen regardless of the region that is called
+static DOWNSAMPLE_SEED: usize = 0;
+
+/// the base quality assumed for the bases of reads without base qualities
+/// (```--missing_base_qual_value```), unless a preset sets another one
+pub static DEFAULT_MISSING_BASE_QUAL: u8 = 20;
+
+/// How to handle reads without base qualities (a '*' QUAL field, as emitted by some instruments
+/// and basecallers). The allele qualities are estimated by realigning the read with the
+/// estimated error model, so base qualities are not needed for realignment and these reads can be
+/// kept as is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingQualPolicy {
+    /// use the read like any other, assuming this base quality for each of its bases where base
+    /// qualities are used (the allele calls of the pileup-only mode and the base quality of each
+    /// allele call)
+    Keep(u8),
+    /// remove the read during fragment extraction
+    Reject,
+}
+
+impl MissingQualPolicy {
+    /// the base quality assumed for the bases of a read without base qualities
+    pub fn base_qual(&self) -> u8 {
+        match *self {
+            MissingQualPolicy::Keep(qual) => qual,
+            // the reads are removed, so their base qualities are never used
+            MissingQualPolicy::Reject => 0,
+        }
+    }
+}
+
+/// How to realign the reads in windows that contain ambiguous reference bases (N, to which the
+/// IUPAC ambiguity codes are also converted when the reference is read, unless they are kept with
+/// ```--alphabet iupac```). Candidate variants are never called at the ambiguous bases themselves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefAmbiguity {
+    /// score the ambiguous bases like any other base, i.e. as a mismatch to every read base (or by
+    /// the bases they stand for with ```--alphabet iupac```)
+    Mismatch,
+    /// the ambiguous bases match any read base (see ```WILDCARD_BASE```)
+    Wildcard,
+    /// make no allele calls for the variant clusters whose realignment window contains an
+    /// ambiguous base
+    Skip,
+}
+
+pub static REF_AMBIGUITY_NAMES: [&str; 3] = ["mismatch", "wildcard", "skip"];
+
+impl RefAmbiguity {
+    pub fn from_name(name: &str) -> Option<RefAmbiguity> {
+        match name {
+            "mismatch" => Some(RefAmbiguity::Mismatch),
+            "wildcard" => Some(RefAmbiguity::Wildcard),
+            "skip" => Some(RefAmbiguity::Skip),
+            _ => None,
+        }
+    }
+
+    /// sets the ambiguous bases of a haplotype or reference window to ```WILDCARD_BASE``` in the
+    /// wildcard mode
+    pub fn mask(&self, window: &mut Vec<u8>) {
+        if *self == RefAmbiguity::Wildcard {
+            for c in window.iter_mut() {
+                if is_ambiguous_base(*c as char) {
+                    *c = WILDCARD_BASE;
+                }
+            }
+        }
+    }
+}
+
+/// The reason that a BAM record was filtered out and not used for fragment extraction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadFilterReason {
+    QualityCheckFailed,
+    Duplicate,
+    Secondary,
+    Unmapped,
+    LowMapq,
+    Supplementary,
+    LowAlignmentScore,
+    LowAlignedFraction,
+    ExcessSoftClip,
+    MissingBaseQual,
+    Downsampled,
+    ReadLength,
+    SamFlags,
+    MalformedCigar,
+    MissingSequence,
+}
+
+/// The flag, mapping quality and length filters that decide which reads are used for fragment
+/// extraction. Unmapped reads are always removed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadFilter {
+    /// minimum mapping quality to use (extract haplotype information for) a read
+    pub min_mapq: u8,
+    /// minimum length of the read sequence (excluding hard-clipped bases)
+    pub min_read_length: usize,
+    /// if not None, the maximum length of the read sequence (excluding hard-clipped bases)
+    pub max_read_length: Option<usize>,
+    /// use secondary alignments (flag 0x100)
+    pub keep_secondary: bool,
+    /// use supplementary alignments (flag 0x800)
+    pub keep_supplementary: bool,
+    /// use reads marked as PCR or optical duplicates (flag 0x400)
+    pub keep_duplicates: bool,
+    /// use reads that failed the platform or vendor quality checks (flag 0x200)
+    pub keep_qc_fail: bool,
+    /// only use reads with all of these SAM flag bits set
+    pub required_flags: u16,
+    /// only use reads with none of these SAM flag bits set
+    pub forbidden_flags: u16,
+}
+
+impl ReadFilter {
+    /// Creates the default read filter with the given minimum mapping quality: secondary,
+    /// supplementary, duplicate and QC-failed reads are removed, and there are no length or
+    /// additional flag requirements.
+    pub fn new(min_mapq: u8) -> ReadFilter {
+        ReadFilter {
+            min_mapq: min_mapq,
+            min_read_length: 0,
+            max_read_length: None,
+            keep_secondary: false,
+            keep_supplementary: false,
+            keep_duplicates: false,
+            keep_qc_fail: false,
+            required_flags: 0,
+            forbidden_flags: 0,
+        }
+    }
+
+    /// Checks a BAM record against the filter
+    ///
+    /// # Returns
+    /// ```None``` if the read passes the filter, otherwise the first ```ReadFilterReason``` that
+    /// it failed
+    pub fn check(&self, record: &Record) -> Option<ReadFilterReason> {
+        if !self.keep_qc_fail && record.is_quality_check_failed() {
+            return Some(ReadFilterReason::QualityCheckFailed);
+        }
+        if !self.keep_duplicates && record.is_duplicate() {
+            return Some(ReadFilterReason::Duplicate);
+        }
+        if !self.keep_secondary && record.is_secondary() {
+            return Some(ReadFilterReason::Secondary);
+        }
+        if record.is_unmapped() {
+            return Some(ReadFilterReason::Unmapped);
+        }
+        if record.mapq() < self.min_mapq {
+            return Some(ReadFilterReason::LowMapq);
+        }
+        if !self.keep_supplementary && record.is_supplementary() {
+            return Some(ReadFilterReason::Supplementary);
+        }
+        let flags = record.flags();
+        if flags & self.required_flags != self.required_flags || flags & self.forbidden_flags != 0
+        {
+            return Some(ReadFilterReason::SamFlags);
+        }
+        let read_len = record.seq().len();
+        // secondary alignments are usually written without their sequence (SEQ '*'), and a read
+        // without a sequence can't be realigned
+        if read_len == 0 {
+            return Some(ReadFilterReason::MissingSequence);
+        }
+        if read_len < self.min_read_length
+            || self.max_read_length.map_or(false, |max_len| read_len > max_len)
+        {
+            return Some(ReadFilterReason::ReadLength);
+        }
+        None
+    }
+
+    /// Checks a BAM record against every part of the filter except the minimum mapping quality,
+    /// e.g. to count the reads of any mapping quality in the MAPQ statistics of a site
+    pub fn check_any_mapq(&self, record: &Record) -> Option<ReadFilterReason> {
+        ReadFilter {
+            min_mapq: 0,
+            ..*self
+        }
+        .check(record)
+    }
+}
+
+/// the width (PHRED) of the bins of ```RealignmentConcordance```
+static CONCORDANCE_BIN_WIDTH: f64 = 0.1;
+/// the number of bins of ```RealignmentConcordance```. Larger score differences are counted in
+/// the last bin.
+static CONCORDANCE_BINS: usize = 10000;
+/// the upper bounds (PHRED) of the bins of the realignment score differences in the stats report
+pub static CONCORDANCE_REPORT_BOUNDS: [f64; 7] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// The distribution of the per-read difference between the score of the best haplotypes and the
+/// score of the read's BAM alignment in the realignment windows (PHRED-scaled), as a histogram
+/// with bins of ```CONCORDANCE_BIN_WIDTH```, so that the distributions of regions and samples can
+/// be added up. Most reads should have a small difference; a long tail points to reads with
+/// mapping problems or to mis-estimated alignment parameters.
+#[derive(Clone, Debug, Default)]
+pub struct RealignmentConcordance {
+    counts: Vec<usize>,
+    /// the number of reads
+    pub reads: usize,
+    /// the largest score difference
+    pub max: f64,
+}
+
+impl RealignmentConcordance {
+    /// counts the score difference of a read. Negative differences are counted in the first bin.
+    pub fn add(&mut self, diff: f64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; CONCORDANCE_BINS];
+        }
+        let bin = ((diff / CONCORDANCE_BIN_WIDTH).max(0.0) as usize).min(CONCORDANCE_BINS - 1);
+        self.counts[bin] += 1;
+        self.max = if self.reads == 0 {
+            diff
+        } else {
+            self.max.max(diff)
+        };
+        self.reads += 1;
+    }
+
+    /// adds the reads of another distribution, e.g. of another region or sample
+    pub fn add_distribution(&mut self, other: &RealignmentConcordance) {
+        if other.reads == 0 {
+            return;
+        }
+        if self.counts.is_empty() {
+            self.counts = vec![0; CONCORDANCE_BINS];
+        }
+        for (c, o) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *c += o;
+        }
+        self.max = if self.reads == 0 {
+            other.max
+        } else {
+            self.max.max(other.max)
+        };
+        self.reads += other.reads;
+    }
+
+    /// the quantile ```q``` of the score differences, rounded up to the end of its bin (and at
+    /// most the largest difference)
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        let rank = ((self.reads - 1) as f64 * q).round() as usize;
+        let mut seen = 0;
+        for (bin, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return ((bin + 1) as f64 * CONCORDANCE_BIN_WIDTH).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// the number of reads with a score difference below each of the ```bounds``` (which must
+    /// be multiples of ```CONCORDANCE_BIN_WIDTH```), and with a larger difference
+    pub fn histogram(&self, bounds: &[f64]) -> Vec<usize> {
+        let mut hist = vec![0; bounds.len() + 1];
+        for (bin, &count) in self.counts.iter().enumerate() {
+            let bin_start = bin as f64 * CONCORDANCE_BIN_WIDTH;
+            // the small tolerance keeps the rounding errors of the bin starts out of the next bin
+            let ix = bounds
+                .iter()
+                .position(|&b| bin_start < b - CONCORDANCE_BIN_WIDTH / 2.0)
+                .unwrap_or(bounds.len());
+            hist[ix] += count;
+        }
+        hist
+    }
+
+    /// Prints the quantiles of the score differences
+    pub fn print(&self) {
+        if self.reads == 0 {
+            return;
+        }
+        info!(
+            "{} Realignment score difference of {} reads (best haplotype vs. BAM alignment, PHRED-scaled):",
+            print_time(),
+            self.reads
+        );
+        let quantile = |q: f64| self.quantile(q);
+        info!("{} median:                 {:.1}", SPACER, quantile(0.5));
+        info!("{} 90th percentile:        {:.1}", SPACER, quantile(0.9));
+        info!("{} 99th percentile:        {:.1}", SPACER, quantile(0.99));
+        info!("{} maximum:                {:.2}", SPACER, self.max);
+    }
+}
+
+/// Counters for the number of BAM records seen during fragment extraction and the number removed
+/// by each read filter, and the realignment concordance of the realigned reads
+#[derive(Clone, Debug, Default)]
+pub struct ReadFilterCounts {
+    pub total: usize,
+    pub quality_check_failed: usize,
+    pub duplicate: usize,
+    pub secondary: usize,
+    pub unmapped: usize,
+    pub low_mapq: usize,
+    pub supplementary: usize,
+    pub low_alignment_score: usize,
+    pub low_aligned_frac: usize,
+    pub excess_soft_clip: usize,
+    pub missing_base_qual: usize,
+    pub downsampled: usize,
+    pub read_length: usize,
+    pub sam_flags: usize,
+    pub malformed_cigar: usize,
+    pub missing_sequence: usize,
+    /// reads without base qualities that were kept (with ```MissingQualPolicy::Keep```)
+    pub missing_base_qual_kept: usize,
+    /// the score differences of the realigned reads
+    pub realignment_concordance: RealignmentConcordance,
+}
+
+impl ReadFilterCounts {
+    pub fn new() -> ReadFilterCounts {
+        ReadFilterCounts::default()
+    }
+
+    /// count a single read, incrementing the counter for the filter that removed it (if any)
+    pub fn add(&mut self, filter: Option<ReadFilterReason>) {
+        self.total += 1;
+        match filter {
+            Some(ReadFilterReason::QualityCheckFailed) => self.quality_check_failed += 1,
+            Some(ReadFilterReason::Duplicate) => self.duplicate += 1,
+            Some(ReadFilterReason::Secondary) => self.secondary += 1,
+            Some(ReadFilterReason::Unmapped) => self.unmapped += 1,
+            Some(ReadFilterReason::LowMapq) => self.low_mapq += 1,
+            Some(ReadFilterReason::Supplementary) => self.supplementary += 1,
+            Some(ReadFilterReason::LowAlignmentScore) => self.low_alignment_score += 1,
+            Some(ReadFilterReason::LowAlignedFraction) => self.low_aligned_frac += 1,
+            Some(ReadFilterReason::ExcessSoftClip) => self.excess_soft_clip += 1,
+            Some(ReadFilterReason::MissingBaseQual) => self.missing_base_qual += 1,
+            Some(ReadFilterReason::Downsampled) => self.downsampled += 1,
+            Some(ReadFilterReason::ReadLength) => self.read_length += 1,
+            Some(ReadFilterReason::SamFlags) => self.sam_flags += 1,
+            Some(ReadFilterReason::MalformedCigar) => self.malformed_cigar += 1,
+            Some(ReadFilterReason::MissingSequence) => self.missing_sequence += 1,
+            None => {}
+        }
+    }
+
+    /// add the counts of another set of reads, e.g. of another region or sample
+    pub fn add_counts(&mut self, other: &ReadFilterCounts) {
+        self.total += other.total;
+        self.quality_check_failed += other.quality_check_failed;
+        self.duplicate += other.duplicate;
+        self.secondary += other.secondary;
+        self.unmapped += other.unmapped;
+        self.low_mapq += other.low_mapq;
+        self.supplementary += other.supplementary;
+        self.low_alignment_score += other.low_alignment_score;
+        self.low_aligned_frac += other.low_aligned_frac;
+        self.excess_soft_clip += other.excess_soft_clip;
+        self.missing_base_qual += other.missing_base_qual;
+        self.downsampled += other.downsampled;
+        self.read_length += other.read_length;
+        self.sam_flags += other.sam_flags;
+        self.malformed_cigar += other.malformed_cigar;
+        self.missing_sequence += other.missing_sequence;
+        self.missing_base_qual_kept += other.missing_base_qual_kept;
+        self.realignment_concordance
+            .add_distribution(&other.realignment_concordance);
+    }
+
+    /// the number of reads removed by each filter, named by the filter
+    pub fn reasons(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("quality_check_failed", self.quality_check_failed),
+            ("duplicate", self.duplicate),
+            ("secondary", self.secondary),
+            ("unmapped", self.unmapped),
+            ("low_mapq", self.low_mapq),
+            ("supplementary", self.supplementary),
+            ("low_alignment_score", self.low_alignment_score),
+            ("low_aligned_frac", self.low_aligned_frac),
+            ("excess_soft_clip", self.excess_soft_clip),
+            ("missing_base_qual", self.missing_base_qual),
+            ("downsampled", self.downsampled),
+            ("read_length", self.read_length),
+            ("sam_flags", self.sam_flags),
+            ("malformed_cigar", self.malformed_cigar),
+            ("missing_sequence", self.missing_sequence),
+        ]
+    }
+
+    /// total number of reads removed by any filter
+    pub fn filtered(&self) -> usize {
+        self.quality_check_failed
+            + self.duplicate
+            + self.secondary
+            + self.unmapped
+            + self.low_mapq
+            + self.supplementary
+            + self.low_alignment_score
+            + self.low_aligned_frac
+            + self.excess_soft_clip
+            + self.missing_base_qual
+            + self.downsampled
+            + self.read_length
+            + self.sam_flags
+            + self.malformed_cigar
+            + self.missing_sequence
+    }
+
+    pub fn print(&self) {
+        info!(
+            "{} {} of {} reads filtered out during fragment extraction:",
+            print_time(),
+            self.filtered(),
+            self.total
+        );
+        info!("{} QC fail:                {}", SPACER, self.quality_check_failed);
+        info!("{} duplicate:              {}", SPACER, self.duplicate);
+        info!("{} secondary:              {}", SPACER, self.secondary);
+        info!("{} unmapped:               {}", SPACER, self.unmapped);
+        info!("{} low MAPQ:               {}", SPACER, self.low_mapq);
+        info!("{} supplementary:          {}", SPACER, self.supplementary);
+        info!("{} low alignment score:    {}", SPACER, self.low_alignment_score);
+        info!("{} low aligned fraction:   {}", SPACER, self.low_aligned_frac);
+        info!("{} excess soft-clipping:   {}", SPACER, self.excess_soft_clip);
+        info!("{} missing base qualities: {}", SPACER, self.missing_base_qual);
+        info!("{} read length:            {}", SPACER, self.read_length);
+        info!("{} SAM flags:              {}", SPACER, self.sam_flags);
+        if self.malformed_cigar > 0 {
+            info!(
+                "{} malformed CIGAR:        {}",
+                SPACER, self.malformed_cigar
+            );
+        }
+        if self.missing_sequence > 0 {
+            info!(
+                "{} missing sequence:       {}",
+                SPACER, self.missing_sequence
+            );
+        }
+        if self.downsampled > 0 {
+            info!("{} downsampled:            {}", SPACER, self.downsampled);
+        }
+        if self.missing_base_qual_kept > 0 {
+            info!(
+                "{} {} reads without base qualities were kept (--missing_base_qual keep).",
+                print_time(),
+                self.missing_base_qual_kept
+            );
+        }
+    }
+}
+
+/// the upper bounds of the read length strata for downsampling. The bins are wider for longer
+/// reads, so that the few ultralong reads of a window are kept in proportion.
+static DOWNSAMPLE_LENGTH_BINS: [usize; 6] = [1000, 5000, 10000, 25000, 50000, 100000];
+/// the upper bounds of the mean base quality strata for downsampling
+static DOWNSAMPLE_QUAL_BINS: [f64; 2] = [10.0, 20.0];
+
+/// The downsampling stratum of a read, from its length and its mean base quality. Reads without
+/// base qualities have a stratum of their own for each length.
+fn downsample_stratum(record: &Record) -> usize {
+    let len = record.seq().len();
+    let length_bin = DOWNSAMPLE_LENGTH_BINS
+        .iter()
+        .position(|&b| len < b)
+        .unwrap_or(DOWNSAMPLE_LENGTH_BINS.len());
+    let n_qual_bins = DOWNSAMPLE_QUAL_BINS.len() + 2;
+    let qual_bin = if len == 0 || has_missing_base_qual(record) {
+        n_qual_bins - 1
+    } else {
+        let mean_qual =
+            record.qual().iter().map(|&q| q as f64).sum::<f64>() / record.qual().len() as f64;
+        DOWNSAMPLE_QUAL_BINS
+            .iter()
+            .position(|&b| mean_qual < b)
+            .unwrap_or(DOWNSAMPLE_QUAL_BINS.len())
+    };
+    length_bin * n_qual_bins + qual_bin
+}
+
+/// Chooses ```max_depth``` of the reads with the given strata, so that each stratum keeps its share
+/// of the reads. Each stratum gets the integer part of its proportional share, and the remaining
+/// reads go to the strata with the largest remainders (ties to the lower stratum). The reads of each
+/// stratum are chosen at random.
+///
+/// # Returns
+/// The indices of the chosen reads, in increasing order
+fn stratified_sample(strata: &[usize], max_depth: usize, rng: &mut StdRng) -> Vec<usize> {
+    if strata.len() <= max_depth {
+        return (0..strata.len()).collect();
+    }
+    let mut members: Vec<(usize, Vec<usize>)> = vec![];
+    for (i, &s) in strata.iter().enumerate() {
+        match members.iter().position(|&(stratum, _)| stratum == s) {
+            Some(ix) => members[ix].1.push(i),
+            None => members.push((s, vec![i])),
+        }
+    }
+    members.sort_by_key(|&(stratum, _)| stratum);
+
+    let n = strata.len();
+    let mut quota: Vec<usize> = members
+        .iter()
+        .map(|&(_, ref m)| m.len() * max_depth / n)
+        .collect();
+    let mut by_remainder: Vec<usize> = (0..members.len()).collect();
+    by_remainder.sort_by_key(|&i| (n - members[i].1.len() * max_depth % n, i));
+    let assigned: usize = quota.iter().sum();
+    for &i in by_remainder.iter().take(max_depth - assigned) {
+        quota[i] += 1;
+    }
+
+    let mut chosen: Vec<usize> = vec![];
+    for (&mut (_, ref mut m), &q) in members.iter_mut().zip(quota.iter()) {
+        // partial Fisher-Yates shuffle
+        for j in 0..q {
+            let k = rng.gen_range(j, m.len());
+            m.swap(j, k);
+        }
+        chosen.extend_from_slice(&m[..q]);
+    }
+    chosen.sort();
+    chosen
+}
+
+/// Chooses the reads to use in an interval when the reads are downsampled to ```max_depth``` reads
+/// per window
+///
+/// The reads that pass the read filters are grouped into windows of ```DOWNSAMPLE_WINDOW``` bases
+/// by their start position, and ```max_depth``` reads are chosen from each window. The reads are
+/// sampled within strata of read length and mean base quality (see ```stratified_sample```), so
+/// that the retained reads have the same length and quality distributions as all of the reads,
+/// and the ultralong reads that link the most variants are not pruned by chance. The random
+/// generator of each window is seeded with its position, so reruns choose the same reads.
+///
+/// # Returns
+/// Returns a result containing a flag for each BAM record in the interval (in the order they are
+/// fetched), which is true if the record is kept
+fn choose_downsampled_reads(
+    bam_ix: &mut IndexedBamReader,
+    iv: &GenomicInterval,
+    extract_params: &ExtractFragmentParameters,
+    max_depth: usize,
+) -> Result<Vec<bool>> {
+    bam_ix
+        .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+        .chain_err(|| "Error seeking BAM file while downsampling reads.")?;
+
+    let mut keep: Vec<bool> = vec![];
+    let mut window: Option<(i32, u32)> = None;
+    // the indices and strata of the reads in the current window
+    let mut window_reads: Vec<usize> = vec![];
+    let mut window_strata: Vec<usize> = vec![];
+
+    let choose = |keep: &mut Vec<bool>, window: (i32, u32), reads: &[usize], strata: &[usize]| {
+        let mut rng: StdRng =
+            StdRng::from_seed(&[DOWNSAMPLE_SEED, window.0 as usize, window.1 as usize]);
+        for ix in stratified_sample(strata, max_depth, &mut rng) {
+            keep[reads[ix]] = true;
+        }
+    };
+
+    for r in bam_ix.records() {
+        let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+        let read_ix = keep.len();
+        keep.push(false);
+        if check_read_filters(&record, extract_params).is_some() {
+            continue;
+        }
+
+        let read_window = (record.tid(), record.pos().max(0) as u32 / DOWNSAMPLE_WINDOW);
+        if window != Some(read_window) {
+            if let Some(w) = window {
+                choose(&mut keep, w, &window_reads, &window_strata);
+            }
+            window_reads.clear();
+            window_strata.clear();
+            window = Some(read_window);
+        }
+        window_reads.push(read_ix);
+        window_strata.push(downsample_stratum(&record));
+    }
+    if let Some(w) = window {
+        choose(&mut keep, w, &window_reads, &window_strata);
+    }
+
+    Ok(keep)
+}
+
+/// a read overlapping a variant, ranked by its informativeness for the realignment depth cap
+struct DepthCapRead {
+    /// the index of the BAM record in the interval (in the order they are fetched)
+    read_ix: usize,
+    reverse: bool,
+    mapq: u8,
+    /// the number of aligned bases of the read on the shorter side of the variant
+    span: usize,
+}
+
+/// Chooses ```max_reads``` of the reads overlapping a variant to realign to it, by their
+/// informativeness. The reads that span the largest possible realignment window
+/// (```full_span``` bases on both sides of the variant) are preferred, then the reads with the
+/// highest mapping quality, then the reads with the longest span. The reads are taken from the
+/// two strands in turn, so that the retained reads are balanced between the strands as far as
+/// possible, and ties are broken by the order of the reads in the BAM file.
+///
+/// # Returns
+/// The indices of the chosen reads (```read_ix```)
+fn select_informative_reads(
+    mut reads: Vec<DepthCapRead>,
+    full_span: usize,
+    max_reads: usize,
+) -> Vec<usize> {
+    if reads.len() <= max_reads {
+        return reads.iter().map(|r| r.read_ix).collect();
+    }
+    reads.sort_by_key(|r| {
+        (
+            usize::MAX - r.span.min(full_span),
+            u8::MAX - r.mapq,
+            usize::MAX - r.span,
+            r.read_ix,
+        )
+    });
+    let (reverse, forward): (Vec<DepthCapRead>, Vec<DepthCapRead>) =
+        reads.into_iter().partition(|r| r.reverse);
+
+    let mut chosen: Vec<usize> = vec![];
+    let (mut f, mut r) = (0, 0);
+    while chosen.len() < max_reads {
+        if r >= reverse.len() || (f < forward.len() && f <= r) {
+            chosen.push(forward[f].read_ix);
+            f += 1;
+        } else {
+            chosen.push(reverse[r].read_ix);
+            r += 1;
+        }
+    }
+    chosen
+}
+
+/// Chooses the reads to realign to each variant in an interval when the number of reads realigned
+/// to a variant is capped at ```max_reads``` (see ```select_informative_reads```)
+///
+/// The reads are counted after the read filters and the downsampling (```keep_reads```), like the
+/// reads that are realigned. The number of reads skipped at each variant is added to its
+/// ```realign_skipped``` count.
+///
+/// # Returns
+/// Returns a result containing the indices of the variants that each read (by its index in the
+/// interval, in the order the records are fetched) is not realigned to. Reads that are realigned
+/// to all of their variants are not included.
+fn choose_depth_capped_reads(
+    bam_ix: &mut IndexedBamReader,
+    iv: &GenomicInterval,
+    varlist: &mut VarList,
+    extract_params: &ExtractFragmentParameters,
+    keep_reads: &Option<Vec<bool>>,
+    max_reads: usize,
+) -> Result<HashMap<usize, Vec<usize>>> {
+    bam_ix
+        .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+        .chain_err(|| "Error seeking BAM file while capping the realignment depth.")?;
+
+    // a read spanning this many bases on both sides of a variant covers its largest realignment
+    // window
+    let full_span = extract_params.max_window_padding
+        + extract_params.window_flank
+        + extract_params.anchor_length;
+    let mut var_reads: HashMap<usize, Vec<DepthCapRead>> = HashMap::new();
+
+    for (read_ix, r) in bam_ix.records().enumerate() {
+        let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+        if check_read_filters(&record, extract_params).is_some()
+            || keep_reads.as_ref().map_or(false, |k| !k[read_ix])
+            || cigar_problem(&record.cigar(), record.seq().len()).is_some()
+        {
+            continue;
+        }
+
+        let start_pos = record.pos() as usize;
+        let end_pos = record.cigar().end_pos() as usize - 1;
+        let interval = GenomicInterval {
+            tid: record.tid() as u32,
+            chrom: iv.chrom.clone(),
+            start_pos: start_pos as u32,
+            end_pos: end_pos as u32,
+        };
+        for var in varlist.variants_in_range(&interval)? {
+            // the aligned bases on the shorter side of the variant
+            let left = var.pos0.saturating_sub(start_pos);
+            let right = end_pos.saturating_sub(var.pos0 + var.alleles[0].len() - 1);
+            let read = DepthCapRead {
+                read_ix: read_ix,
+                reverse: record.is_reverse(),
+                mapq: record.mapq(),
+                span: left.min(right),
+            };
+            var_reads.entry(var.ix).or_insert_with(Vec::new).push(read);
+        }
+    }
+
+    let mut skipped: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (var_ix, reads) in var_reads {
+        if reads.len() <= max_reads {
+            continue;
+        }
+        let all_reads: Vec<usize> = reads.iter().map(|r| r.read_ix).collect();
+        let mut chosen = select_informative_reads(reads, full_span, max_reads);
+        chosen.sort();
+        varlist.lst[var_ix].realign_skipped += all_reads.len() - max_reads;
+        for read_ix in all_reads {
+            if chosen.binary_search(&read_ix).is_err() {
+                skipped.entry(read_ix).or_insert_with(Vec::new).push(var_ix);
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+/// Checks whether a read's base quality string is absent. htslib stores a '*' QUAL field as a
+/// quality of 0xff for every base.
+pub fn has_missing_base_qual(record: &Record) -> bool {
+    let qual = record.qual();
+    qual.len() > 0 && qual[0] == 0xff
+}
+
+/// Counts the clipped and aligned bases of a CIGAR string
+///
+/// # Returns
+/// a tuple ```(soft_clipped, hard_clipped, aligned)``` where aligned is the number of read bases
+/// consumed by M, =, X and I operations
+pub fn count_clipped_and_aligned_bases(cigar: &CigarStringView) -> (usize, usize, usize) {
+    let mut soft_clipped = 0;
+    let mut hard_clipped = 0;
+    let mut aligned = 0;
+
+    for c in cigar.iter() {
+        match c {
+            &Cigar::SoftClip(l) => soft_clipped += l as usize,
+            &Cigar::HardClip(l) => hard_clipped += l as usize,
+            &Cigar::Match(l) | &Cigar::Equal(l) | &Cigar::Diff(l) | &Cigar::Ins(l) => {
+                aligned += l as usize
+            }
+            &Cigar::Del(_) | &Cigar::RefSkip(_) | &Cigar::Pad(_) => {}
+        }
+    }
+
+    (soft_clipped, hard_clipped, aligned)
+}
+
+/// Finds the reference positions at which a read alignment is soft-clipped
+///
+/// # Arguments
+/// - ```start_pos```: 0-based reference position of the first aligned base
+/// - ```end_pos```: 0-based reference position of the last aligned base
+/// - ```cigar```: the CIGAR string of the alignment
+///
+/// # Returns
+/// a tuple ```(left, right)``` holding ```Some(start_pos)``` if the alignment starts with a soft
+/// clip (possibly after a hard clip) and ```Some(end_pos)``` if it ends with one
+pub fn soft_clip_positions(
+    start_pos: usize,
+    end_pos: usize,
+    cigar: &CigarStringView,
+) -> (Option<usize>, Option<usize>) {
+    let is_soft_clip = |c: &Cigar| match c {
+        &Cigar::SoftClip(_) => true,
+        _ => false,
+    };
+    let is_hard_clip = |c: &Cigar| match c {
+        &Cigar::HardClip(_) => true,
+        _ => false,
+    };
+
+    let left = match cigar.iter().find(|c| !is_hard_clip(c)) {
+        Some(c) if is_soft_clip(c) => Some(start_pos),
+        _ => None,
+    };
+    let right = match cigar.iter().rev().find(|c| !is_hard_clip(c)) {
+        Some(c) if is_soft_clip(c) => Some(end_pos),
+        _ => None,
+    };
+
+    (left, right)
+}
+
+/// the number of reads with a malformed CIGAR string that are reported with a warning in each
+/// call of ```extract_fragments```. The rest are only counted.
+static MAX_CIGAR_WARNINGS: usize = 10;
+
+/// Checks that a CIGAR string describes an alignment that fragment extraction can handle
+///
+/// Every operation must have a nonzero length, and there must be at least one aligned (M, = or X)
+/// base. Hard clips may only be the first or last operation, and insertions, deletions, reference
+/// skips and soft clips other than those at the ends must lie between aligned bases. Padded
+/// alignments (P) are not supported. The CIGAR must also describe as many bases as the read
+/// sequence has, unless the sequence is absent.
+///
+/// # Arguments
+/// - ```cigar```: the CIGAR string of the alignment
+/// - ```read_len```: the length of the read sequence (0 if it is absent)
+///
+/// # Returns
+/// ```None``` if the CIGAR can be used, otherwise a description of its problem
+pub fn cigar_problem(cigar: &CigarStringView, read_len: usize) -> Option<String> {
+    let is_aligned = |c: &Cigar| match c {
+        &Cigar::Match(_) | &Cigar::Equal(_) | &Cigar::Diff(_) => true,
+        _ => false,
+    };
+    let first_aligned = match cigar.iter().position(|c| is_aligned(c)) {
+        Some(i) => i,
+        None => return Some("no aligned (M, = or X) bases".to_owned()),
+    };
+    let last_aligned = cigar
+        .iter()
+        .rposition(|c| is_aligned(c))
+        .unwrap_or(first_aligned);
+    let outside_alignment = |i: usize| i < first_aligned || i > last_aligned;
+
+    let mut query_len = 0;
+    for (i, c) in cigar.iter().enumerate() {
+        if c.len() == 0 {
+            return Some(format!("zero-length '{}' operation", c.char()));
+        }
+        match c {
+            &Cigar::HardClip(_) if i > 0 && i < cigar.len() - 1 => {
+                return Some("'hard clip' (H) in between operations".to_owned());
+            }
+            &Cigar::SoftClip(_) if !outside_alignment(i) => {
+                return Some("'soft clip' (S) in between aligned bases".to_owned());
+            }
+            &Cigar::Pad(_) => {
+                return Some("'padding' (P) operation of a padded alignment".to_owned());
+            }
+            &Cigar::Ins(_) if outside_alignment(i) => {
+                return Some(
+                    "'insertion' (I) before the first or after the last aligned base".to_owned(),
+                );
+            }
+            &Cigar::Del(_) | &Cigar::RefSkip(_) if outside_alignment(i) => {
+                return Some(format!(
+                    "'{}' operation before the first or after the last aligned base",
+                    c.char()
+                ));
+            }
+            _ => {}
+        }
+        match c {
+            &Cigar::Match(l)
+            | &Cigar::Equal(l)
+            | &Cigar::Diff(l)
+            | &Cigar::Ins(l)
+            | &Cigar::SoftClip(l) => query_len += l as usize,
+            _ => {}
+        }
+    }
+
+    if read_len > 0 && query_len != read_len {
+        return Some(format!(
+            "the CIGAR describes {} read bases, but the read has {}",
+            query_len, read_len
+        ));
+    }
+    None
+}
+
+/// Checks the CIGAR string of a read that passed the read filters (see ```cigar_problem```)
+///
+/// # Returns
+/// ```Some(ReadFilterReason::MalformedCigar)``` if the CIGAR is malformed, with a warning for the
+/// first ```MAX_CIGAR_WARNINGS``` such reads (```n_malformed``` is the number seen so far)
+///
+/// # Errors
+/// - ```UnexpectedCigarOperation```: the CIGAR is malformed and ```strict``` is set
+fn check_read_cigar(
+    record: &Record,
+    t_names: &Vec<String>,
+    strict: bool,
+    n_malformed: usize,
+) -> Result<Option<ReadFilterReason>> {
+    let problem = match cigar_problem(&record.cigar(), record.seq().len()) {
+        Some(problem) => problem,
+        None => return Ok(None),
+    };
+    let qname = String::from_utf8_lossy(record.qname()).into_owned();
+    let region = format!("{}:{}", t_names[record.tid() as usize], record.pos() + 1);
+    if strict {
+        return Err(Error::from(ErrorKind::UnexpectedCigarOperation(problem)))
+            .chain_err(|| ErrorKind::ReadProcessingError(qname, region));
+    }
+    if n_malformed < MAX_CIGAR_WARNINGS {
+        warn!(
+            "{} WARNING: Skipping read {} at {} with a malformed CIGAR: {}.{}",
+            print_time(),
+            qname,
+            region,
+            problem,
+            if n_malformed + 1 == MAX_CIGAR_WARNINGS {
+                " Further reads with malformed CIGARs are only counted (use --strict to stop at them)."
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(Some(ReadFilterReason::MalformedCigar))
+}
+
+/// Checks a BAM record against the read filters
+///
+/// # Arguments
+/// - ```record```: the BAM record to check
+/// - ```extract_params```: parameters holding the filter thresholds
+///
+/// # Returns
+/// ```None``` if the read passes all filters, otherwise the first ```ReadFilterReason``` that it
+/// failed
+pub fn check_read_filters(
+    record: &Record,
+    extract_params: &ExtractFragmentParameters,
+) -> Option<ReadFilterReason> {
+    if let Some(reason) = extract_params.read_filter.check(record) {
+        return Some(reason);
+    }
+
+    if let Some(min_as) = extract_params.min_alignment_score {
+        if let Some(bam::record::Aux::Integer(score)) = record.aux(b"AS") {
+            if score < min_as {
+                return Some(ReadFilterReason::LowAlignmentScore);
+            }
+        }
+    }
+
+    if extract_params.min_aligned_frac > 0.0 || extract_params.max_soft_clip.is_some() {
+        let (soft_clipped, hard_clipped, aligned) =
+            count_clipped_and_aligned_bases(&record.cigar());
+
+        if extract_params.min_aligned_frac > 0.0 {
+            let read_len = record.seq().len() + hard_clipped;
+            if read_len > 0
+                && (aligned as f64 / read_len as f64) < extract_params.min_aligned_frac
+            {
+                return Some(ReadFilterReason::LowAlignedFraction);
+            }
+        }
+
+        if let Some(max_sc) = extract_params.max_soft_clip {
+            if soft_clipped > max_sc {
+                return Some(ReadFilterReason::ExcessSoftClip);
+            }
+        }
+    }
+
+    if extract_params.missing_qual_policy == MissingQualPolicy::Reject
+        && has_missing_base_qual(record)
+    {
+        return Some(ReadFilterReason::MissingBaseQual);
+    }
+
+    None
 }
 
 /// an extension of the rust-htslib cigar representation that has the cigar operation and length as
@@ -219,6 +1276,38 @@ pub struct AnchorPositions {
     pub left_anchor_read: u32,
     /// the position of the right anchor on the read. This should be the rightmost base of the right anchor sequence.
     pub right_anchor_read: u32,
+    /// the read starts within the window: the left anchor is the first aligned base of the read,
+    /// and the read is aligned to the haplotypes with a free left end
+    pub free_left_end: bool,
+    /// the read ends within the window: the right anchor is the last aligned base of the read,
+    /// and the read is aligned to the haplotypes with a free right end
+    pub free_right_end: bool,
+}
+
+/// the reference and read positions of the first aligned (matched) base of a read
+fn first_aligned_base(cigarpos_list: &Vec<CigarPos>) -> Option<(u32, u32)> {
+    for cigarpos in cigarpos_list {
+        match cigarpos.cig {
+            Cigar::Match(l) | Cigar::Diff(l) | Cigar::Equal(l) if l > 0 => {
+                return Some((cigarpos.ref_pos, cigarpos.read_pos));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// the reference and read positions of the last aligned (matched) base of a read
+fn last_aligned_base(cigarpos_list: &Vec<CigarPos>) -> Option<(u32, u32)> {
+    for cigarpos in cigarpos_list.iter().rev() {
+        match cigarpos.cig {
+            Cigar::Match(l) | Cigar::Diff(l) | Cigar::Equal(l) if l > 0 => {
+                return Some((cigarpos.ref_pos + l - 1, cigarpos.read_pos + l - 1));
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
 /// Given a BAM record, and the position (or interval) of a variant (or variant cluster),
@@ -238,7 +1327,7 @@ pub fn find_anchors(
     bam_record: &Record,
     cigarpos_list: &Vec<CigarPos>,
     var_interval: GenomicInterval,
-    ref_seq: &Vec<char>,
+    ref_seq: &RefSeqWindow,
     read_seq: &Vec<char>,
     target_names: &Vec<String>,
     extract_params: ExtractFragmentParameters,
@@ -248,23 +1337,6 @@ pub fn find_anchors(
 
     let anchor_length = extract_params.anchor_length as u32;
 
-    let l_max = if var_interval.start_pos as usize >= max_window_padding {
-        var_interval.start_pos as usize - max_window_padding
-    } else {
-        0
-    };
-
-    let r_max = if var_interval.end_pos as usize + max_window_padding < ref_seq.len() {
-        var_interval.end_pos as usize + max_window_padding
-    } else {
-        ref_seq.len() - 1
-    };
-    let mut ref_seq_max_window: Vec<u8> = vec![];
-
-    for c in ref_seq[l_max..r_max + 1].iter() {
-        ref_seq_max_window.push(*c as u8);
-    }
-
     if VERBOSE {
         eprintln!(
             "Finding anchors for variant at {} {} {}:",
@@ -279,11 +1351,11 @@ pub fn find_anchors(
                 .end_pos()
         || (var_interval.end_pos as i32) < bam_record.pos()
     {
-        eprintln!(
+        info!(
             "var_interval: {}\t{}\t{}",
             var_interval.chrom, var_interval.start_pos, var_interval.end_pos
         );
-        eprintln!(
+        info!(
             "bam_record:   {}\t{}\t{}",
             target_names[bam_record.tid() as usize],
             bam_record.pos(),
@@ -295,6 +1367,36 @@ pub fn find_anchors(
         bail!(ErrorKind::AnchorRangeOutsideRead);
     }
 
+    // the flanks are only added within the aligned part of the read, and the maximum window is
+    // measured from them
+    let flank = extract_params.window_flank as u32;
+    let var_interval = GenomicInterval {
+        start_pos: var_interval
+            .start_pos
+            .saturating_sub(flank)
+            .max(bam_record.pos() as u32)
+            .min(var_interval.start_pos),
+        end_pos: (var_interval.end_pos.saturating_add(flank))
+            .min(bam_record.cigar().end_pos() as u32 - 1)
+            .max(var_interval.end_pos),
+        ..var_interval
+    };
+
+    let l_max = if var_interval.start_pos as usize >= max_window_padding {
+        var_interval.start_pos as usize - max_window_padding
+    } else {
+        0
+    };
+
+    let r_max = (var_interval.end_pos as usize)
+        .saturating_add(max_window_padding)
+        .min(ref_seq.len() - 1);
+    let mut ref_seq_max_window: Vec<u8> = vec![];
+
+    for c in ref_seq[l_max..r_max + 1].iter() {
+        ref_seq_max_window.push(*c as u8);
+    }
+
     let mut left_anchor_ref: u32 = 0;
     let mut right_anchor_ref: u32 = 0;
     let mut left_anchor_read: u32 = 0;
@@ -404,7 +1506,14 @@ pub fn find_anchors(
                         let bndm = bndm::BNDM::new(&pattern);
                         let occ: Vec<usize> = bndm.find_all(&ref_seq_max_window).collect();
 
-                        if (anchor_match && occ.len() == 1) || l_anc <= l_max {
+                        if anchor_match && occ.len() == 1 {
+                            found_anchor_left = true;
+                            break;
+                        }
+                        if l_anc <= l_max {
+                            if extract_params.exact_anchors {
+                                return Ok(None); // no exact anchor within the maximum window
+                            }
                             found_anchor_left = true;
                             break;
                         }
@@ -444,8 +1553,22 @@ pub fn find_anchors(
         eprintln!("");
     }
 
+    // the read starts before a left anchor was found. with partial reads, the window starts at
+    // the first aligned base of the read instead
+    let mut free_left_end = false;
     if !found_anchor_left {
-        return Ok(None); // failed to find a left anchor
+        match first_aligned_base(cigarpos_list) {
+            Some((ref_pos, read_pos))
+                if extract_params.partial_reads && ref_pos <= var_interval.start_pos =>
+            {
+                left_anchor_ref = ref_pos;
+                left_anchor_read = read_pos;
+                free_left_end = true;
+            }
+            _ => {
+                return Ok(None); // failed to find a left anchor
+            }
+        }
     }
 
     //println!("**************************************************");
@@ -514,7 +1637,14 @@ pub fn find_anchors(
                         let bndm = bndm::BNDM::new(&pattern);
                         let occ: Vec<usize> = bndm.find_all(&ref_seq_max_window).collect();
 
-                        if (anchor_match && occ.len() == 1) || r_anc >= r_max {
+                        if anchor_match && occ.len() == 1 {
+                            found_anchor_right = true;
+                            break;
+                        }
+                        if r_anc >= r_max {
+                            if extract_params.exact_anchors {
+                                return Ok(None); // no exact anchor within the maximum window
+                            }
                             found_anchor_right = true;
                             break;
                         }
@@ -550,8 +1680,24 @@ pub fn find_anchors(
         eprintln!("");
     }
 
+    // likewise if the read ends before a right anchor was found. a read that starts and ends
+    // within the window can't be anchored at all
+    let mut free_right_end = false;
     if !found_anchor_right {
-        return Ok(None); // failed to find a right anchor
+        match last_aligned_base(cigarpos_list) {
+            Some((ref_pos, read_pos))
+                if extract_params.partial_reads
+                    && !free_left_end
+                    && ref_pos >= var_interval.end_pos =>
+            {
+                right_anchor_ref = ref_pos;
+                right_anchor_read = read_pos;
+                free_right_end = true;
+            }
+            _ => {
+                return Ok(None); // failed to find a right anchor
+            }
+        }
     }
 
     // return none if any of the anchors are out of bounds
@@ -567,6 +1713,8 @@ pub fn find_anchors(
         right_anchor_ref: right_anchor_ref,
         left_anchor_read: left_anchor_read,
         right_anchor_read: right_anchor_read,
+        free_left_end: free_left_end,
+        free_right_end: free_right_end,
     }))
 }
 
@@ -641,25 +1789,422 @@ fn generate_haps(var_cluster: &Vec<Var>) -> Vec<Vec<u8>> {
     generate_haps_k_onward(var_cluster, 0)
 }
 
+/// Returns the states of the read's BAM alignment (from its CIGAR) between the anchors of a
+/// realignment window, or None if the CIGAR doesn't describe a path between the anchors (e.g. it
+/// soft-clips bases inside the window).
+fn anchored_alignment_path(
+    cigarpos_list: &Vec<CigarPos>,
+    anchors: &AnchorPositions,
+) -> Option<Vec<AlignmentState>> {
+    let in_ref = |r: u32| r >= anchors.left_anchor_ref && r <= anchors.right_anchor_ref;
+    let in_read = |q: u32| q >= anchors.left_anchor_read && q <= anchors.right_anchor_read;
+    let mut path: Vec<AlignmentState> = vec![];
+    let (mut n_ref, mut n_read) = (0, 0);
+
+    for cigarpos in cigarpos_list {
+        match cigarpos.cig {
+            Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                for k in 0..l {
+                    if in_ref(cigarpos.ref_pos + k) {
+                        path.push(AlignmentState::Match);
+                        n_ref += 1;
+                        n_read += 1;
+                    }
+                }
+            }
+            Cigar::Ins(l) => {
+                for k in 0..l {
+                    if in_read(cigarpos.read_pos + k) {
+                        path.push(AlignmentState::Insertion);
+                        n_read += 1;
+                    }
+                }
+            }
+            Cigar::Del(l) | Cigar::RefSkip(l) => {
+                for k in 0..l {
+                    if in_ref(cigarpos.ref_pos + k) {
+                        path.push(AlignmentState::Deletion);
+                        n_ref += 1;
+                    }
+                }
+            }
+            Cigar::SoftClip(_) | Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+
+    if n_ref == anchors.right_anchor_ref - anchors.left_anchor_ref + 1
+        && n_read == anchors.right_anchor_read - anchors.left_anchor_read + 1
+        && path.first() == Some(&AlignmentState::Match)
+    {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Scores a read window that starts or ends within the realignment window against a haplotype
+/// window, with the forward algorithm and a free end. A read that starts within the window is
+/// aligned with the reversed sequences, so that its start is the free end.
+fn partial_read_score(
+    read_window: &[u8],
+    hap_window: &[u8],
+    hap_params: &Vec<AlignmentParameters>,
+    free_left_end: bool,
+) -> LogProb {
+    let mut ln_params: Vec<LnAlignmentParameters> = hap_params.iter().map(|p| p.ln()).collect();
+    if free_left_end {
+        ln_params.reverse();
+        let read_rev: Vec<u8> = read_window.iter().rev().cloned().collect();
+        let hap_rev: Vec<u8> = hap_window.iter().rev().cloned().collect();
+        forward_algorithm_free_end(&read_rev, &hap_rev, &ln_params)
+    } else {
+        forward_algorithm_free_end(read_window, hap_window, &ln_params)
+    }
+}
+
+type AlignmentCacheKey = (Vec<u8>, Vec<u8>, u64);
+
+/// A bounded cache of the pair-HMM scores of read windows against haplotype windows. Reads with the
+/// same sequence in a realignment window (common for accurate reads, e.g. PacBio HiFi) then only
+/// need to be aligned to each short-haplotype once.
+///
+/// The cache keeps two generations of entries: when the current generation is full it replaces
+/// the previous one, and entries of the previous generation are moved back to the current one when
+/// they are used. Entries that were not used for a whole generation are dropped, so this behaves
+/// like an LRU cache without the bookkeeping of one.
+pub struct AlignmentCache {
+    generation_size: usize,
+    current: HashMap<AlignmentCacheKey, LogProb>,
+    previous: HashMap<AlignmentCacheKey, LogProb>,
+    pub lookups: usize,
+    pub hits: usize,
+    /// number of read windows that were scored by the edit distance pre-filter instead of being
+    /// aligned (```prefilter_edits```)
+    pub prefiltered: usize,
+}
+
+impl AlignmentCache {
+    /// Creates a cache that holds at most ```capacity``` scores. A capacity of 0 disables the cache.
+    pub fn new(capacity: usize) -> AlignmentCache {
+        AlignmentCache {
+            generation_size: capacity / 2,
+            current: HashMap::new(),
+            previous: HashMap::new(),
+            lookups: 0,
+            hits: 0,
+            prefiltered: 0,
+        }
+    }
+
+    /// The key of an alignment: the read window, the haplotype window, and a hash of the other
+    /// things the score depends on (the alignment parameters, algorithm, band width and free ends
+    /// of the alignment). The context-specific parameters are fixed for a run, and determined by
+    /// the haplotype window.
+    fn key(
+        read_window: &[u8],
+        hap_window: &[u8],
+        align_params: &AlignmentParameters,
+        extract_params: &ExtractFragmentParameters,
+        anchors: &AnchorPositions,
+    ) -> AlignmentCacheKey {
+        let t = align_params.transition_probs;
+        let e = align_params.emission_probs;
+        let mut hasher = DefaultHasher::new();
+        for p in &[
+            t.match_from_match,
+            t.insertion_from_match,
+            t.deletion_from_match,
+            t.insertion_from_insertion,
+            t.match_from_insertion,
+            t.deletion_from_deletion,
+            t.match_from_deletion,
+            e.equal,
+            e.not_equal,
+            e.insertion,
+            e.deletion,
+        ] {
+            hasher.write_u64(p.to_bits());
+        }
+        hasher.write_usize(extract_params.alignment_type as usize);
+        hasher.write_usize(extract_params.band_width);
+        hasher.write_u8(anchors.free_left_end as u8);
+        hasher.write_u8(anchors.free_right_end as u8);
+
+        (
+            read_window.to_vec(),
+            hap_window.to_vec(),
+            hasher.finish(),
+        )
+    }
+
+    fn get(&mut self, key: &AlignmentCacheKey) -> Option<LogProb> {
+        if self.generation_size == 0 {
+            return None;
+        }
+        self.lookups += 1;
+        if let Some(&score) = self.current.get(key) {
+            self.hits += 1;
+            return Some(score);
+        }
+        match self.previous.remove(key) {
+            Some(score) => {
+                self.hits += 1;
+                self.insert(key.clone(), score);
+                Some(score)
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: AlignmentCacheKey, score: LogProb) {
+        if self.generation_size == 0 {
+            return;
+        }
+        if self.current.len() >= self.generation_size {
+            self.previous = mem::replace(&mut self.current, HashMap::new());
+        }
+        self.current.insert(key, score);
+    }
+
+    /// Prints the fraction of the alignments that were found in the cache, and the number of read
+    /// windows that the pre-filter scored without aligning them.
+    pub fn print(&self) {
+        if self.prefiltered > 0 {
+            info!(
+                "{} {} read windows were scored by their edit distances without realignment.",
+                print_time(),
+                self.prefiltered
+            );
+        }
+        if self.lookups == 0 {
+            return;
+        }
+        info!(
+            "{} {} of {} read/haplotype alignments ({:.2}%) were found in the alignment cache.",
+            print_time(),
+            self.hits,
+            self.lookups,
+            100.0 * self.hits as f64 / self.lookups as f64
+        );
+    }
+}
+
+/// Returns the first and last reference position (0-based, inclusive) of the haplotype windows that
+/// a read window between ```anchors``` is aligned to. A read that starts or ends within the window
+/// is aligned with a free end, to haplotypes that extend past the end of the read far enough for
+/// it to end anywhere on them.
+pub fn haplotype_window_bounds(
+    ref_seq: &RefSeqWindow,
+    var_cluster: &Vec<Var>,
+    anchors: &AnchorPositions,
+    extract_params: &ExtractFragmentParameters,
+) -> (usize, usize) {
+    let free_end_padding = if anchors.free_left_end || anchors.free_right_end {
+        let max_len_diff = |var: &Var| {
+            let ref_len = var.alleles[0].len() as i64;
+            var.alleles
+                .iter()
+                .map(|a| (a.len() as i64 - ref_len).abs() as usize)
+                .max()
+                .unwrap_or(0)
+        };
+        extract_params.anchor_length + var_cluster.iter().map(max_len_diff).sum::<usize>()
+    } else {
+        0
+    };
+    let hap_start: usize = if anchors.free_left_end {
+        (anchors.left_anchor_ref as usize)
+            .saturating_sub(free_end_padding)
+            .max(ref_seq.start)
+    } else {
+        anchors.left_anchor_ref as usize
+    };
+    let hap_end: usize = if anchors.free_right_end {
+        (anchors.right_anchor_ref as usize + free_end_padding)
+            .min(ref_seq.start + ref_seq.seq.len() - 1)
+    } else {
+        anchors.right_anchor_ref as usize
+    };
+    (hap_start, hap_end)
+}
+
+/// Returns the sequence of the short-haplotype ```hap``` (an allele index for each variant of the
+/// cluster) from ```hap_start``` to ```hap_end``` on the reference.
+pub fn haplotype_window(
+    ref_seq: &RefSeqWindow,
+    var_cluster: &Vec<Var>,
+    hap: &Vec<u8>,
+    hap_start: usize,
+    hap_end: usize,
+) -> Vec<u8> {
+    let mut hap_window: Vec<u8> = Vec::with_capacity(hap_end - hap_start + 10);
+    let mut i: usize = hap_start;
+    for var in 0..var_cluster.len() {
+        while i < var_cluster[var].pos0 {
+            hap_window.push(ref_seq[i] as u8);
+            i += 1;
+        }
+
+        for c in var_cluster[var].alleles[hap[var] as usize].bytes() {
+            hap_window.push(c);
+        }
+
+        i += var_cluster[var].alleles[0].len();
+    }
+
+    while i <= hap_end {
+        hap_window.push(ref_seq[i] as u8);
+        i += 1;
+    }
+    hap_window
+}
+
+/// Returns the (period, length) of the longest tandem repeat in ```seq``` with a period of at most
+/// ```max_period``` bases and at least two copies of the repeat unit, or None if there is no such
+/// repeat. The repeat may end with a partial copy of the unit. Of repeats of the same length, the
+/// one with the shortest period is returned (e.g. a homopolymer rather than a dinucleotide repeat).
+pub fn longest_tandem_repeat(seq: &[char], max_period: usize) -> Option<(usize, usize)> {
+    let mut longest: Option<(usize, usize)> = None;
+    for period in 1..(max_period + 1) {
+        // the number of consecutive bases equal to the base one period before them
+        let mut run: usize = 0;
+        for i in period..seq.len() {
+            if seq[i] == seq[i - period] && !is_ambiguous_base(seq[i]) {
+                run += 1;
+                let len = run + period;
+                if len >= 2 * period && longest.map_or(true, |(_, l)| len > l) {
+                    longest = Some((period, len));
+                }
+            } else {
+                run = 0;
+            }
+        }
+    }
+    longest
+}
+
+/// Returns the band width for aligning reads to the haplotypes of a variant cluster whose
+/// haplotype windows span ```ref_window``` on the reference. If the window contains a tandem repeat
+/// of at least ```MIN_REPEAT_LEN``` bases, the extra or missing copies of the repeat unit in a read
+/// can be placed anywhere in the repeat, so the band is widened to the length of the repeat and
+/// two more copies of its unit. Otherwise (and without ```repeat_band```) the band width of
+/// ```extract_params``` is used.
+pub fn repeat_band_width(ref_window: &[char], extract_params: &ExtractFragmentParameters) -> usize {
+    if !extract_params.repeat_band {
+        return extract_params.band_width;
+    }
+    match longest_tandem_repeat(ref_window, MAX_REPEAT_PERIOD) {
+        Some((period, len)) if len >= MIN_REPEAT_LEN => {
+            extract_params.band_width.max(len + 2 * period)
+        }
+        _ => extract_params.band_width,
+    }
+}
+
+/// Scores a read window against a haplotype window with the realignment model of
+/// ```extract_params``` (see ```RealignmentModel```), or with a free end if the read starts or
+/// ends within the window. Windows longer than ```MAX_CHUNK_LEN``` are aligned in chunks.
+pub fn score_haplotype_window(
+    read_window: &[u8],
+    hap_window: &[u8],
+    anchors: &AnchorPositions,
+    extract_params: &ExtractFragmentParameters,
+    align_params: AlignmentParameters,
+    context_params: &Option<ContextAlignmentParameters>,
+) -> LogProb {
+    // the alignment parameters at each position of hap_window, which depend on the
+    // sequence context if a context-specific error model was estimated
+    let hap_params: Vec<AlignmentParameters> = match context_params {
+        &Some(ref cp) => cp.for_sequence(hap_window, align_params),
+        &None => vec![align_params; hap_window.len()],
+    };
+
+    if anchors.free_left_end || anchors.free_right_end {
+        partial_read_score(read_window, hap_window, &hap_params, anchors.free_left_end)
+    } else if read_window.len() > MAX_CHUNK_LEN || hap_window.len() > MAX_CHUNK_LEN {
+        chunked_score(
+            extract_params.alignment_type.model(),
+            read_window,
+            hap_window,
+            &hap_params,
+            extract_params.band_width,
+            MAX_CHUNK_LEN,
+        )
+    } else {
+        extract_params.alignment_type.model().score(
+            read_window,
+            hap_window,
+            &hap_params,
+            extract_params.band_width,
+        )
+    }
+}
+
+/// The edit distances of a read window to the short-haplotype windows of a variant cluster, if
+/// they leave no doubt about the short-haplotype of the read: its edit distance to one of them is
+/// lower by at least ```prefilter_edits``` than its edit distance to every other. Accurate reads
+/// (e.g. PacBio HiFi) mostly match one short-haplotype exactly, and their allele calls are then
+/// confident whatever the realignment model, so they are scored by their edit distances and only
+/// the ambiguous read windows are realigned. Read windows with a free end are always realigned.
+fn prefilter_edit_distances(
+    read_window: &[u8],
+    hap_windows: &Vec<Vec<u8>>,
+    anchors: &AnchorPositions,
+    extract_params: &ExtractFragmentParameters,
+) -> Option<Vec<usize>> {
+    let margin = extract_params.prefilter_edits?;
+    if anchors.free_left_end || anchors.free_right_end || hap_windows.len() < 2 {
+        return None;
+    }
+    let distances: Vec<usize> = hap_windows
+        .iter()
+        .map(|w| banded_edit_distance(read_window, w, extract_params.band_width))
+        .collect();
+    let best = *distances.iter().min()?;
+    if distances.iter().filter(|&&d| d < best + margin).count() == 1 {
+        Some(distances)
+    } else {
+        None
+    }
+}
+
 ///
 fn extract_var_cluster(
     read_seq: &Vec<char>,
-    ref_seq: &Vec<char>,
+    ref_seq: &RefSeqWindow,
     var_cluster: Vec<Var>,
     anchors: AnchorPositions,
     extract_params: ExtractFragmentParameters,
     align_params: AlignmentParameters,
-) -> Vec<FragCall> {
+    context_params: &Option<ContextAlignmentParameters>,
+    alignment_cache: &mut AlignmentCache,
+) -> (Vec<FragCall>, LogProb) {
     let mut calls: Vec<FragCall> = vec![];
 
     //let ref_window = ref_seq[(anchors.left_anchor_ref as usize)..
     //(anchors.right_anchor_ref as usize) + 1]
     //        .to_vec();
-    let window_capacity = (anchors.right_anchor_ref - anchors.left_anchor_ref + 10) as usize;
+    let (hap_start, hap_end) =
+        haplotype_window_bounds(ref_seq, &var_cluster, &anchors, &extract_params);
+    // the reads are aligned with a wider band in tandem repeats. extract_params is a copy, so
+    // the wider band is also used for the alignment cache keys and the pre-filter
+    let mut extract_params = extract_params;
+    extract_params.band_width = repeat_band_width(&ref_seq[hap_start..hap_end + 1], &extract_params);
+
+        let max_len_diff = |var: &Var| {
+            let ref_len = var.alleles[0].len() as i64;
+            var.alleles
+                .iter()
+                .map(|a| (a.len() as i64 - ref_len).abs() as usize)
+                .max()
+                .unwrap_or(0)
+        };
 
-    let read_window: Vec<char> = read_seq
+    let read_window: Vec<u8> = read_seq
         [(anchors.left_anchor_read as usize)..(anchors.right_anchor_read as usize) + 1]
-        .to_vec();
+        .iter()
+        .map(|&c| c as u8)
+        .collect();
 
     let mut max_score: LogProb = LogProb::ln_zero();
     let mut max_hap: Vec<u8> = vec![0u8; var_cluster.len()];
@@ -684,58 +2229,60 @@ fn extract_var_cluster(
             }
             eprintln!("");
         }
-        let read_seq_str: String = read_window.clone().into_iter().collect();
+        let read_seq_str = String::from_utf8_lossy(&read_window);
         eprintln!("read: {}", read_seq_str);
     }
 
     let haps = generate_haps(&var_cluster);
+    let hap_windows: Vec<Vec<u8>> = haps
+        .iter()
+        .map(|hap| {
+            let mut hap_window = haplotype_window(ref_seq, &var_cluster, hap, hap_start, hap_end);
+            extract_params.ref_ambiguity.mask(&mut hap_window);
+            hap_window
+        })
+        .collect();
+    let prefilter_distances: Option<Vec<usize>> =
+        prefilter_edit_distances(&read_window, &hap_windows, &anchors, &extract_params);
+    if prefilter_distances.is_some() {
+        alignment_cache.prefiltered += 1;
+    }
 
-    for ref hap in haps {
+    for (h, hap) in haps.iter().enumerate() {
         assert!(hap.len() > 0);
-        let mut hap_window: Vec<char> = Vec::with_capacity(window_capacity);
-        let mut i: usize = anchors.left_anchor_ref as usize;
-        for var in 0..n_vars {
-            while i < var_cluster[var].pos0 {
-                hap_window.push(ref_seq[i]);
-                i += 1;
-            }
-
-            for c in var_cluster[var].alleles[hap[var] as usize].chars() {
-                hap_window.push(c);
-            }
-
-            i += var_cluster[var].alleles[0].len();
-        }
+        let hap_window: &Vec<u8> = &hap_windows[h];
 
-        while i <= anchors.right_anchor_ref as usize {
-            hap_window.push(ref_seq[i]);
-            i += 1;
-        }
-
-        // we now want to score hap_window
-        let score: LogProb = match extract_params.alignment_type {
-            AlignmentType::ForwardAlgorithmNumericallyStable => {
-                forward_algorithm_numerically_stable(
-                    &read_window,
-                    &hap_window,
-                    align_params.ln(),
-                    extract_params.band_width,
-                )
-            }
-            AlignmentType::ForwardAlgorithmNonNumericallyStable => {
-                forward_algorithm_non_numerically_stable(
+        let score: LogProb = match prefilter_distances {
+            Some(ref distances) => edit_distance_score(
+                distances[h],
+                read_window.len(),
+                &vec![align_params; hap_window.len()],
+            ),
+            None => {
+                // we now want to score hap_window, unless the same alignment was done before
+                let cache_key = AlignmentCache::key(
                     &read_window,
-                    &hap_window,
-                    align_params,
-                    extract_params.band_width,
-                )
+                    hap_window,
+                    &align_params,
+                    &extract_params,
+                    &anchors,
+                );
+                match alignment_cache.get(&cache_key) {
+                    Some(score) => score,
+                    None => {
+                        let score: LogProb = score_haplotype_window(
+                            &read_window,
+                            hap_window,
+                            &anchors,
+                            &extract_params,
+                            align_params,
+                            context_params,
+                        );
+                        alignment_cache.insert(cache_key, score);
+                        score
+                    }
+                }
             }
-            AlignmentType::ViterbiMaxScoringAlignment => viterbi_max_scoring_alignment(
-                &read_window,
-                &hap_window,
-                align_params.ln(),
-                extract_params.band_width,
-            ),
         };
 
         assert!(score > LogProb::ln_zero());
@@ -745,7 +2292,7 @@ fn extract_var_cluster(
                 LogProb::ln_add_exp(allele_scores[var][hap[var] as usize], score);
         }
         if VERBOSE {
-            let hap_seq_str: String = hap_window.into_iter().collect();
+            let hap_seq_str = String::from_utf8_lossy(hap_window);
             eprintln!(
                 "hap:{:?} {} PHRED: {}",
                 hap,
@@ -785,35 +2332,248 @@ fn extract_var_cluster(
             for allele in &var_cluster[v].alleles {
                 eprint!(" {}", allele);
             }
-
-            eprint!("; allele = {};", best_allele);
-            eprintln!(" qual = {};", *Prob::from(qual));
+
+            eprint!("; allele = {};", best_allele);
+            eprintln!(" qual = {};", *Prob::from(qual));
+        }
+
+        calls.push(FragCall {
+            frag_ix: usize::MAX, // this will be assigned a correct value soon after all fragments extracted
+            var_ix: var_cluster[v as usize].ix,
+            allele: best_allele,
+            qual: qual,
+            one_minus_qual: LogProb::ln_one_minus_exp(&qual),
+            reverse_strand: false, // set with the fragment's strand in extract_fragment
+            read_pos: u32::MAX,    // set from the read's BAM alignment in extract_fragment
+            base_qual: 255,
+        });
+    }
+
+    if VERBOSE {
+        eprintln!("--------------------------------------");
+    }
+
+    (calls, max_score)
+}
+
+/// Splits a group of variants whose realignment windows overlap into clusters that are aligned
+/// together, and appends each cluster to ```cluster_lst``` with anchors that cover the whole cluster
+/// (the first-left and last-right anchor positions).
+///
+/// A group with more than ```variant_cluster_max_size``` variants, or with more short-haplotypes than
+/// ```cluster_work_budget```, is split between the two adjacent variants whose realignment windows
+/// overlap by the fewest bases (the point where the read connects them the least), and the halves
+/// are split further until they fit. The variants on both sides of a split are marked in
+/// ```cluster_split```. If the budget is 0, the group is cut after every ```variant_cluster_max_size```
+/// variants instead.
+/// Groups the variants of a read (in order of position, with the anchors of their realignment
+/// windows) into the variants that are realigned together: a window that overlaps the window of
+/// the previous variant, or starts less than ```merge_distance``` bases after it ends, is merged
+/// with it.
+fn group_var_windows(
+    var_anchor_lst: Vec<(Var, AnchorPositions)>,
+    merge_distance: usize,
+) -> Vec<Vec<(Var, AnchorPositions)>> {
+    let mut var_groups: Vec<Vec<(Var, AnchorPositions)>> = vec![];
+    for (var, anc) in var_anchor_lst {
+        let overlaps = match var_groups.last() {
+            Some(group) => {
+                (anc.left_anchor_ref as usize)
+                    < group[group.len() - 1].1.right_anchor_ref as usize + merge_distance
+            }
+            None => false,
+        };
+        match var_groups.last_mut() {
+            Some(group) if overlaps => group.push((var, anc)),
+            _ => var_groups.push(vec![(var, anc)]),
+        }
+    }
+    var_groups
+}
+
+fn split_var_cluster(
+    group: &[(Var, AnchorPositions)],
+    extract_params: ExtractFragmentParameters,
+    cluster_split: &mut Vec<bool>,
+    cluster_lst: &mut Vec<(AnchorPositions, Vec<Var>)>,
+) {
+    let l = group.len();
+    let budget = extract_params.cluster_work_budget;
+    let n_haps = group
+        .iter()
+        .fold(1usize, |n, &(ref var, _)| n.saturating_mul(var.alleles.len()));
+
+    if l > 1 && (l > extract_params.variant_cluster_max_size || (budget > 0 && n_haps > budget)) {
+        let split = if budget == 0 {
+            extract_params.variant_cluster_max_size
+        } else {
+            (1..l)
+                .min_by_key(|&i| group[i - 1].1.right_anchor_ref - group[i].1.left_anchor_ref)
+                .unwrap_or(l / 2)
+        };
+        cluster_split[group[split - 1].0.ix] = true;
+        cluster_split[group[split].0.ix] = true;
+        split_var_cluster(&group[..split], extract_params, cluster_split, cluster_lst);
+        split_var_cluster(&group[split..], extract_params, cluster_split, cluster_lst);
+        return;
+    }
+
+    // sequence anchor that covers the whole cluster of variants
+    let combined_anchor = AnchorPositions {
+        left_anchor_ref: group[0].1.left_anchor_ref,
+        right_anchor_ref: group[l - 1].1.right_anchor_ref,
+        left_anchor_read: group[0].1.left_anchor_read,
+        right_anchor_read: group[l - 1].1.right_anchor_read,
+        free_left_end: group[0].1.free_left_end,
+        free_right_end: group[l - 1].1.free_right_end,
+    };
+    cluster_lst.push((
+        combined_anchor,
+        group.iter().map(|&(ref var, _)| var.clone()).collect(),
+    ));
+}
+
+/// Merges the allele calls of ```other``` into ```frag```, where both come from alignments of the
+/// same read. If both alignments call the same variant, the more confident call is kept, or the
+/// variant is dropped if they call different alleles.
+fn merge_fragment_calls(frag: &mut Fragment, other: Fragment) {
+    let mut calls: Vec<FragCall> = frag.calls.drain(..).chain(other.calls.into_iter()).collect();
+    calls.sort_by_key(|c| c.var_ix);
+
+    let mut i = 0;
+    while i < calls.len() {
+        let mut j = i + 1;
+        while j < calls.len() && calls[j].var_ix == calls[i].var_ix {
+            j += 1;
+        }
+        let group = &calls[i..j];
+        if group.iter().all(|c| c.allele == group[0].allele) {
+            let mut best = group[0];
+            for c in group.iter() {
+                if c.qual < best.qual {
+                    best = *c;
+                }
+            }
+            frag.calls.push(best);
+        }
+        i = j;
+    }
+
+    frag.mapq = frag.mapq.min(other.mapq);
+    if frag.error_rate.is_none() {
+        frag.error_rate = other.error_rate;
+    }
+}
+
+/// Stitches the fragments of the alignments of the same read (by read ID) on the same contig into a
+/// single fragment, so that haplotype assembly can link the variants on both sides of a reference
+/// gap or a structural variant that split the read into a primary and supplementary alignments.
+/// The alignments may be on either strand (e.g. the inverted part of a read split by an inversion);
+/// each call keeps the strand of the alignment it came from. Fragments without a read ID or without
+/// calls are kept as they are.
+///
+/// # Returns
+/// The stitched fragments (in the order of the first alignment of each read) and the number of
+/// fragments that were merged into another one
+fn stitch_split_read_fragments(flist: Vec<Fragment>, varlist: &VarList) -> (Vec<Fragment>, usize) {
+    let mut stitched: Vec<Fragment> = vec![];
+    let mut read_fragment: HashMap<(String, u32), usize> = HashMap::new();
+    let mut merged = 0;
+    for frag in flist {
+        let key = match (&frag.id, frag.calls.first()) {
+            (&Some(ref id), Some(call)) => (id.clone(), varlist.lst[call.var_ix].tid),
+            _ => {
+                stitched.push(frag);
+                continue;
+            }
+        };
+        let existing = read_fragment.get(&key).cloned();
+        match existing {
+            Some(ix) => {
+                merge_fragment_calls(&mut stitched[ix], frag);
+                merged += 1;
+            }
+            None => {
+                read_fragment.insert(key, stitched.len());
+                stitched.push(frag);
+            }
         }
+    }
+    (stitched, merged)
+}
 
-        calls.push(FragCall {
-            frag_ix: usize::MAX, // this will be assigned a correct value soon after all fragments extracted
-            var_ix: var_cluster[v as usize].ix,
-            allele: best_allele,
-            qual: qual,
-            one_minus_qual: LogProb::ln_one_minus_exp(&qual)
-        });
+/// Returns the position in the read (0-based, in the sequence as stored in the BAM record) of the
+/// base aligned to the reference position ```ref_pos``` in the read's BAM alignment, or None if
+/// the position is deleted, skipped or outside the alignment
+fn read_position(cigarpos_list: &Vec<CigarPos>, ref_pos: usize) -> Option<usize> {
+    for cp in cigarpos_list {
+        let start = cp.ref_pos as usize;
+        match cp.cig {
+            Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                if ref_pos >= start && ref_pos < start + l as usize {
+                    return Some(cp.read_pos as usize + ref_pos - start);
+                }
+            }
+            Cigar::Del(l) | Cigar::RefSkip(l) => {
+                if ref_pos >= start && ref_pos < start + l as usize {
+                    return None;
+                }
+            }
+            _ => {}
+        }
     }
+    None
+}
 
-    if VERBOSE {
-        eprintln!("--------------------------------------");
+/// Sets ```read_pos``` and ```base_qual``` of an allele call from the read's BAM alignment at the
+/// variant position ```pos0```
+fn set_call_read_position(
+    call: &mut FragCall,
+    bam_record: &Record,
+    cigarpos_list: &Vec<CigarPos>,
+    pos0: usize,
+    missing_qual_policy: MissingQualPolicy,
+) {
+    if let Some(read_pos) = read_position(cigarpos_list, pos0) {
+        call.read_pos = read_pos as u32;
+        call.base_qual = match bam_record.qual().get(read_pos) {
+            Some(&255) => missing_qual_policy.base_qual(),
+            Some(&qual) => qual,
+            None => 255,
+        };
     }
+}
 
-    calls
+/// Returns the root of the variant cluster that the variant ```ix``` belongs to, in a union-find
+/// forest of the variants that were realigned together
+fn find_cluster_root(cluster_parent: &mut Vec<usize>, ix: usize) -> usize {
+    let mut root = ix;
+    while cluster_parent[root] != root {
+        root = cluster_parent[root];
+    }
+    // compress the path so that later lookups are fast
+    let mut i = ix;
+    while cluster_parent[i] != root {
+        let next = cluster_parent[i];
+        cluster_parent[i] = root;
+        i = next;
+    }
+    root
 }
 
 pub fn extract_fragment(
     bam_record: &Record,
     cigarpos_list: &Vec<CigarPos>,
-    vars: Vec<Var>,
-    ref_seq: &Vec<char>,
+    vars: &[Var],
+    ref_seq: &RefSeqWindow,
     target_names: &Vec<String>,
     extract_params: ExtractFragmentParameters,
     align_params: AlignmentParameters,
+    context_params: &Option<ContextAlignmentParameters>,
+    realignment_concordance: &mut RealignmentConcordance,
+    cluster_parent: &mut Vec<usize>,
+    cluster_split: &mut Vec<bool>,
+    alignment_cache: &mut AlignmentCache,
 ) -> Result<Option<Fragment>> {
     // TODO assert that every single variant in vars is on the same chromosome
     let id: String = u8_to_string(bam_record.qname())?;
@@ -828,21 +2588,29 @@ pub fn extract_fragment(
     //};
 
     let mut fragment = Fragment {
-        id: Some(id),
+        id: if extract_params.store_read_id {
+            Some(id)
+        } else {
+            None
+        },
         calls: vec![],
         // ln(0.5) stored as f16 for compactness
         p_read_hap: [LogProb::from(Prob(0.5)),
                      LogProb::from(Prob(0.5))],
-        reverse_strand: bam_record.is_reverse()
+        reverse_strand: bam_record.is_reverse(),
+        mapq: bam_record.mapq(),
+        error_rate: None,
+        hp_tag: match bam_record.aux(b"HP") {
+            Some(bam::record::Aux::Integer(hp)) if hp == 1 || hp == 2 => Some(hp as u8),
+            _ => None,
+        },
+        ps_tag: match bam_record.aux(b"PS") {
+            Some(bam::record::Aux::Integer(ps)) => Some(ps as i32),
+            _ => None,
+        },
     };
 
-    if bam_record.is_quality_check_failed()
-        || bam_record.is_duplicate()
-        || bam_record.is_secondary()
-        || bam_record.is_unmapped()
-        || bam_record.mapq() < extract_params.min_mapq
-        || bam_record.is_supplementary()
-    {
+    if check_read_filters(bam_record, &extract_params).is_some() {
         return Ok(None);
     }
 
@@ -851,12 +2619,23 @@ pub fn extract_fragment(
     let mut var_anchor_lst: Vec<(Var, AnchorPositions)> = vec![];
 
     // populate a list with tuples of each variant, and anchor sequences for its alignment
-    for ref var in vars {
+    let read_end = bam_record.cigar().end_pos() as usize - 1;
+    for var in vars {
+        // the realignment window of a variant in an annotated tandem repeat covers the whole
+        // repeat (or the part of it that the read is aligned to), so that the extra or missing
+        // copies of the repeat unit in the read can be aligned anywhere in the repeat
+        let (start_pos, end_pos) = match var.tandem_repeat {
+            Some((start, end)) => (
+                start.max(bam_record.pos() as usize).min(var.pos0),
+                end.min(read_end).max(var.pos0),
+            ),
+            None => (var.pos0, var.pos0),
+        };
         let var_interval = GenomicInterval {
             tid: var.tid as u32,
             chrom: target_names[var.tid as usize].clone(),
-            start_pos: var.pos0 as u32,
-            end_pos: var.pos0 as u32,
+            start_pos: start_pos as u32,
+            end_pos: end_pos as u32,
         };
         match find_anchors(
             &bam_record,
@@ -878,67 +2657,279 @@ pub fn extract_fragment(
 
     // now that we have anchors for each var the read covers,
     // group the variants into clusters to align together if adjacent anchors overlap
-    // we generate anchors for the whole cluster by taking the first-left and last-right anchor pos of the cluster
-    {
-        // populate cluster_lst with variant clusters
-        let mut var_cluster: Vec<Var> = vec![];
-        let mut var_anchors: Vec<AnchorPositions> = vec![];
-        let mut l;
-
-        // generate clusters of SNVs that should be considered
-        for (var, anc) in var_anchor_lst {
-            l = var_cluster.len();
-            if l == 0
-                || (anc.left_anchor_ref < var_anchors[l - 1].right_anchor_ref
-                    && l < extract_params.variant_cluster_max_size)
-            {
-                var_cluster.push(var);
-                var_anchors.push(anc);
-            } else {
-                // sequence anchor that covers the whole cluster of variants
-                let combined_anchor = AnchorPositions {
-                    left_anchor_ref: var_anchors[0].left_anchor_ref,
-                    right_anchor_ref: var_anchors[l - 1].right_anchor_ref,
-                    left_anchor_read: var_anchors[0].left_anchor_read,
-                    right_anchor_read: var_anchors[l - 1].right_anchor_read,
-                };
+    let var_groups = group_var_windows(var_anchor_lst, extract_params.window_merge_distance);
 
-                cluster_lst.push((combined_anchor, var_cluster.clone()));
+    // groups that are too large to align together are split into smaller clusters
+    for group in var_groups {
+        split_var_cluster(&group, extract_params, cluster_split, &mut cluster_lst);
+    }
 
-                var_cluster.clear();
-                var_anchors.clear();
-                var_cluster.push(var);
-                var_anchors.push(anc);
-            }
-        }
+    // now extract alleles for the variant cluster
 
-        l = var_cluster.len();
+    // difference between the scores of the best haplotypes and of the read's BAM alignment to the
+    // reference, summed over the realignment windows
+    let mut score_diff: Option<LogProb> = None;
+    // score of the best haplotypes and number of read bases, summed over the realignment windows
+    let mut best_score: LogProb = LogProb::ln_one();
+    let mut window_bases: usize = 0;
 
-        if l > 0 {
-            // sequence anchor that covers the whole cluster of variants
-            let combined_anchor = AnchorPositions {
-                left_anchor_ref: var_anchors[0].left_anchor_ref,
-                right_anchor_ref: var_anchors[l - 1].right_anchor_ref,
-                left_anchor_read: var_anchors[0].left_anchor_read,
-                right_anchor_read: var_anchors[l - 1].right_anchor_read,
-            };
+    for (anchors, var_cluster) in cluster_lst {
+        // a cluster of variants that the read starts and ends within can't be anchored
+        if anchors.free_left_end && anchors.free_right_end {
+            continue;
+        }
+        let read_window_len = (anchors.right_anchor_read - anchors.left_anchor_read + 1) as usize;
+        let ref_window_len = (anchors.right_anchor_ref - anchors.left_anchor_ref + 1) as usize;
+        check_window_lengths(read_window_len, ref_window_len)
+            .chain_err(|| "Realignment window is too long.")?;
+        // the free end of a partial read is aligned without a band, so only short windows of
+        // partial reads are realigned
+        if (anchors.free_left_end || anchors.free_right_end)
+            && read_window_len.max(ref_window_len) > MAX_CHUNK_LEN
+        {
+            continue;
+        }
+        if extract_params.ref_ambiguity == RefAmbiguity::Skip
+            && ref_seq[(anchors.left_anchor_ref as usize)..(anchors.right_anchor_ref as usize) + 1]
+                .iter()
+                .any(|&c| is_ambiguous_base(c))
+        {
+            continue;
+        }
+
+        // score the BAM alignment in the window with the same pair-HMM parameters. the score of a
+        // free-end alignment isn't comparable, so partial reads are left out
+        let path = if anchors.free_left_end || anchors.free_right_end {
+            None
+        } else {
+            anchored_alignment_path(cigarpos_list, &anchors)
+        };
+        let path_score: Option<LogProb> = match path {
+            Some(path) => {
+                let read_window: Vec<u8> = read_seq[(anchors.left_anchor_read as usize)
+                    ..(anchors.right_anchor_read as usize) + 1]
+                    .iter()
+                    .map(|&c| c as u8)
+                    .collect();
+                let mut ref_window: Vec<u8> = ref_seq
+                    [(anchors.left_anchor_ref as usize)..(anchors.right_anchor_ref as usize) + 1]
+                    .iter()
+                    .map(|&c| c as u8)
+                    .collect();
+                extract_params.ref_ambiguity.mask(&mut ref_window);
+                let ref_params: Vec<LnAlignmentParameters> = match context_params {
+                    &Some(ref cp) => cp.for_sequence(&ref_window, align_params),
+                    &None => vec![align_params; ref_window.len()],
+                }
+                .iter()
+                .map(|p| p.ln())
+                .collect();
+                alignment_path_score(&read_window, &ref_window, &path, &ref_params)
+            }
+            None => None,
+        };
 
-            cluster_lst.push((combined_anchor, var_cluster.clone()));
+        // variants that are realigned together in any read belong to the same cluster
+        for var in var_cluster.iter().skip(1) {
+            let root_a = find_cluster_root(cluster_parent, var_cluster[0].ix);
+            let root_b = find_cluster_root(cluster_parent, var.ix);
+            cluster_parent[root_a.max(root_b)] = root_a.min(root_b);
         }
-    }
 
-    // now extract alleles for the variant cluster
+        window_bases += read_window_len;
 
-    for (anchors, var_cluster) in cluster_lst {
-        // extract the calls for the fragment
-        for call in extract_var_cluster(
+        // extract the calls for the fragment. the calls are in the order of the variants
+        let var_positions: Vec<usize> = var_cluster.iter().map(|var| var.pos0).collect();
+        let (calls, max_score) = extract_var_cluster(
             &read_seq,
             ref_seq,
             var_cluster,
             anchors,
             extract_params,
             align_params,
+            context_params,
+            alignment_cache,
+        );
+        for (mut call, &pos0) in calls.into_iter().zip(var_positions.iter()) {
+            call.reverse_strand = fragment.reverse_strand;
+            set_call_read_position(
+                &mut call,
+                bam_record,
+                cigarpos_list,
+                pos0,
+                extract_params.missing_qual_policy,
+            );
+            fragment.calls.push(call);
+        }
+        best_score = best_score + max_score;
+
+        if let Some(path_score) = path_score {
+            score_diff = Some(score_diff.unwrap_or(LogProb::ln_one()) + max_score - path_score);
+        }
+    }
+
+    if let Some(diff) = score_diff {
+        // PHRED-scaled
+        realignment_concordance.add(*diff * 10.0 / f64::consts::LN_10);
+    }
+
+    // the effective error rate is one minus the geometric mean of the per-base likelihood of the
+    // read under its best haplotypes
+    if window_bases > 0 {
+        fragment.error_rate = Some((1.0 - (*best_score / window_bases as f64).exp()) as f32);
+    }
+
+    Ok(Some(fragment))
+}
+
+/// the maximum probability that an allele call from the pileup is an error. a call that is at
+/// least as likely to be wrong as right (e.g. from a read with MAPQ 0) carries no information.
+static PILEUP_MAX_P_MISCALL: f64 = 0.5;
+
+/// Returns the sequence of a read over the reference positions ```start``` to ```end```
+/// (0-based, exclusive), with the bases that are inserted after any of these positions if
+/// ```with_insertions```, and the lowest base quality of that sequence.
+///
+/// Returns None if the read does not align over the whole range (or past it, with
+/// ```with_insertions```), or if the first position of the range is deleted or skipped in the
+/// read.
+fn pileup_allele_seq(
+    cigarpos_list: &Vec<CigarPos>,
+    read_seq: &[u8],
+    read_quals: &[u8],
+    start: usize,
+    end: usize,
+    with_insertions: bool,
+) -> Option<(Vec<u8>, u8)> {
+    let mut seq: Vec<u8> = vec![];
+    let mut min_qual = u8::max_value();
+    // the number of positions of the range that are aligned to a base or deleted
+    let mut covered = 0;
+    let mut aligned_end = 0;
+
+    for cp in cigarpos_list {
+        let (ref_pos, read_pos) = (cp.ref_pos as usize, cp.read_pos as usize);
+        match cp.cig {
+            Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                let (s, e) = (ref_pos.max(start), (ref_pos + l as usize).min(end));
+                for p in s..e {
+                    seq.push(read_seq[read_pos + p - ref_pos]);
+                    min_qual = min_qual.min(read_quals[read_pos + p - ref_pos]);
+                }
+                covered += e.saturating_sub(s);
+                aligned_end = ref_pos + l as usize;
+            }
+            Cigar::Del(l) => {
+                if start >= ref_pos && start < ref_pos + l as usize {
+                    return None;
+                }
+                covered += (ref_pos + l as usize).min(end).saturating_sub(ref_pos.max(start));
+                aligned_end = ref_pos + l as usize;
+            }
+            Cigar::RefSkip(l) => {
+                if ref_pos < end && ref_pos + l as usize > start {
+                    return None;
+                }
+            }
+            // the inserted bases follow the reference position before ref_pos
+            Cigar::Ins(l) if with_insertions && ref_pos > start && ref_pos <= end => {
+                for i in read_pos..read_pos + l as usize {
+                    seq.push(read_seq[i]);
+                    min_qual = min_qual.min(read_quals[i]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if covered < end - start || (with_insertions && aligned_end <= end) {
+        return None;
+    }
+    Some((seq, min_qual))
+}
+
+/// Extracts the haplotype fragment of a read directly from the read's alignment (the pileup),
+/// without realigning it to the haplotypes of the variants.
+///
+/// The allele of a variant is the read's sequence over the reference allele (with the inserted
+/// bases, for indels), so an indel is only seen if the BAM alignment places it exactly like the
+/// variant. The probability that the call is wrong combines the lowest base quality of the allele
+/// and the read's mapping quality: ```1 - (1 - p_base) (1 - p_map)```.
+///
+/// # Arguments
+/// - ```bam_record```: the read
+/// - ```cigarpos_list```: the augmented cigar list of the read (see ```create_augmented_cigarlist```)
+/// - ```vars```: the variants that the read overlaps
+/// - ```extract_params```: the parameters for the fragment extraction; only the read filters
+///   and ```store_read_id``` are used
+///
+/// # Returns
+/// Returns the fragment with the allele calls of the read, or None if the read is filtered out
+pub fn extract_pileup_fragment(
+    bam_record: &Record,
+    cigarpos_list: &Vec<CigarPos>,
+    vars: &[Var],
+    extract_params: ExtractFragmentParameters,
+) -> Result<Option<Fragment>> {
+    if check_read_filters(bam_record, &extract_params).is_some() {
+        return Ok(None);
+    }
+    let id: String = u8_to_string(bam_record.qname())?;
+    let mut fragment = Fragment::new(
+        if extract_params.store_read_id {
+            Some(id)
+        } else {
+            None
+        },
+        vec![],
+    );
+    fragment.reverse_strand = bam_record.is_reverse();
+    fragment.mapq = bam_record.mapq();
+
+    let read_seq: Vec<u8> = bam_record.seq().as_bytes().to_ascii_uppercase();
+    let missing_base_qual = extract_params.missing_qual_policy.base_qual();
+    let read_quals: Vec<u8> = bam_record
+        .qual()
+        .iter()
+        .map(|&q| if q == 255 { missing_base_qual } else { q })
+        .collect();
+    // a MAPQ of 255 means that the mapping quality is not available
+    let p_map = if bam_record.mapq() == 255 {
+        0.0
+    } else {
+        10.0f64.powf(-(bam_record.mapq() as f64) / 10.0)
+    };
+
+    for var in vars {
+        let ref_len = var.alleles[0].len();
+        let with_insertions = var.alleles.iter().any(|a| a.len() != ref_len);
+        let (seq, qual) = match pileup_allele_seq(
+            cigarpos_list,
+            &read_seq,
+            &read_quals,
+            var.pos0,
+            var.pos0 + ref_len,
+            with_insertions,
         ) {
+            Some(s) => s,
+            None => continue,
+        };
+        let allele = var
+            .alleles
+            .iter()
+            .position(|a| a.as_bytes().eq_ignore_ascii_case(&seq));
+        if let Some(allele) = allele {
+            let p_base = 10.0f64.powf(-(qual as f64) / 10.0);
+            let p_miscall = (1.0 - (1.0 - p_base) * (1.0 - p_map)).min(PILEUP_MAX_P_MISCALL);
+            let mut call = FragCall::new(0, var.ix, allele as u8, p_miscall);
+            call.reverse_strand = fragment.reverse_strand;
+            set_call_read_position(
+                &mut call,
+                bam_record,
+                cigarpos_list,
+                var.pos0,
+                extract_params.missing_qual_policy,
+            );
             fragment.calls.push(call);
         }
     }
@@ -946,6 +2937,9 @@ pub fn extract_fragment(
     Ok(Some(fragment))
 }
 
+/// Realigns the reads to the variants of ```varlist``` (or, with ```pileup_only```, takes their
+/// alleles from their BAM alignments) and returns their haplotype fragments, with the number of
+/// reads removed by each read filter and the soft-clip clusters (with ```min_soft_clip_cluster```)
 pub fn extract_fragments(
     bam_file: &String,
     fastafile_name: &String,
@@ -953,65 +2947,133 @@ pub fn extract_fragments(
     interval: &Option<GenomicInterval>,
     extract_params: ExtractFragmentParameters,
     align_params: AlignmentParameters,
-) -> Result<Vec<Fragment>> {
+    context_params: &Option<ContextAlignmentParameters>,
+    contig_params: &Vec<ContigParams>,
+    strand_params: &Option<StrandAlignmentParameters>,
+    read_group_params: &Option<ReadGroupAlignmentParameters>,
+) -> Result<(Vec<Fragment>, ReadFilterCounts, Vec<SoftClipCluster>)> {
     let t_names = parse_target_names(&bam_file)?;
 
-    let mut prev_tid = 4294967295; // huge value so that tid != prev_tid on first iter
-    let mut fasta = fasta::IndexedReader::from_file(fastafile_name)
-        .chain_err(|| ErrorKind::IndexedFastaOpenError)?;
-    let mut ref_seq: Vec<char> = vec![];
+    let mut ref_reader = RefSeqReader::open(fastafile_name)?;
 
     let mut flist: Vec<Fragment> = vec![];
+    let mut spill: Option<FragmentSpill> = if extract_params.spill_fragments {
+        Some(FragmentSpill::create()?)
+    } else {
+        None
+    };
+
+    // scores of the read windows that were already aligned to each haplotype window
+    let mut alignment_cache = AlignmentCache::new(extract_params.alignment_cache_size);
+
+    // union-find forest of the variants that were realigned together in the same cluster
+    let mut cluster_parent: Vec<usize> = (0..varlist.lst.len()).collect();
+    // variants next to a point where a variant cluster was split in any read
+    let mut cluster_split: Vec<bool> = vec![false; varlist.lst.len()];
+
+    // number of reads overlapping each variant, and the number of those that are soft-clipped
+    // within extract_params.soft_clip_window of it
+    let mut var_overlap_reads: Vec<usize> = vec![0; varlist.lst.len()];
+    let mut var_clipped_reads: Vec<usize> = vec![0; varlist.lst.len()];
+    // the soft-clipped ends of the reads, if they are clustered
+    let mut clipped_ends: Vec<ClippedEnd> = vec![];
 
     // TODO: this uses a lot of duplicate code, need to figure out a better solution.
     let mut complete = 0;
+    let mut filter_counts = ReadFilterCounts::new();
+    let mut progress = ProgressReporter::new("Read realignment", "variants", varlist.lst.len());
 
     let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
     let mut bam_ix =
-        bam::IndexedReader::from_path(bam_file).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+        open_indexed_bam(bam_file)?;
+    // the reference window must hold the read and the largest realignment window (with its
+    // anchors) around any variant on the read
+    let ref_margin = extract_params.max_window_padding
+        + extract_params.window_flank
+        + 2 * extract_params.anchor_length
+        + 1;
 
     for iv in interval_lst {
+        // with a maximum depth, the reads to use are chosen in a first pass over the interval
+        let keep_reads: Option<Vec<bool>> = match extract_params.max_depth {
+            Some(max_depth) => Some(choose_downsampled_reads(
+                &mut bam_ix,
+                &iv,
+                &extract_params,
+                max_depth,
+            )?),
+            None => None,
+        };
+        // with a realignment depth cap, the variants that each read is not realigned to are also
+        // chosen in a first pass
+        let skipped_vars: HashMap<usize, Vec<usize>> = match extract_params.max_realign_depth {
+            Some(max_reads) => choose_depth_capped_reads(
+                &mut bam_ix,
+                &iv,
+                varlist,
+                &extract_params,
+                &keep_reads,
+                max_reads,
+            )?,
+            None => HashMap::new(),
+        };
+
         bam_ix
             .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
             .chain_err(|| "Error seeking BAM file while extracting fragments.")?;
 
-        for (_, r) in bam_ix.records().enumerate() {
+        for (read_ix, r) in bam_ix.records().enumerate() {
             let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
 
-            if record.is_quality_check_failed()
-                || record.is_duplicate()
-                || record.is_secondary()
-                || record.is_unmapped()
-                || record.mapq() < extract_params.min_mapq
-                || record.is_supplementary()
-            {
+            let mut read_filter = check_read_filters(&record, &extract_params);
+            if read_filter.is_none() && keep_reads.as_ref().map_or(false, |k| !k[read_ix]) {
+                read_filter = Some(ReadFilterReason::Downsampled);
+            }
+            if read_filter.is_none() {
+                read_filter = check_read_cigar(
+                    &record,
+                    &t_names,
+                    extract_params.strict,
+                    filter_counts.malformed_cigar,
+                )?;
+            }
+            filter_counts.add(read_filter);
+            if read_filter.is_some() {
                 continue;
             }
+            if has_missing_base_qual(&record) {
+                filter_counts.missing_base_qual_kept += 1;
+            }
 
             let tid: usize = record.tid() as usize;
             let chrom: String = t_names[tid].clone();
 
-            if tid != prev_tid {
-                let mut ref_seq_u8: Vec<u8> = vec![];
-                fasta
-                    .fetch_all(&chrom)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                fasta
-                    .read(&mut ref_seq_u8)
-                    .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                ref_seq = dna_vec(&ref_seq_u8);
-            }
-
             let start_pos = record.pos();
             let end_pos = record
                 .cigar()
                 .end_pos()
                 - 1;
+            // names the read and its alignment in the errors of the read
+            let read_error = || {
+                ErrorKind::ReadProcessingError(
+                    String::from_utf8_lossy(record.qname()).into_owned(),
+                    format!("{}:{}-{}", t_names[tid], start_pos + 1, end_pos + 1),
+                )
+            };
+
+            let ref_seq = ref_reader
+                .window(
+                    &chrom,
+                    (start_pos as usize).saturating_sub(ref_margin),
+                    end_pos as usize + ref_margin + 1,
+                )
+                .chain_err(&read_error)?;
 
             let bam_cig: CigarStringView = record.cigar();
             let cigarpos_list: Vec<CigarPos> =
                 create_augmented_cigarlist(start_pos as u32, &bam_cig)
-                    .chain_err(|| "Error creating augmented cigarlist.")?;
+                    .chain_err(|| "Error creating augmented cigarlist.")
+                    .chain_err(&read_error)?;
 
             let interval = GenomicInterval {
                 tid: tid as u32,
@@ -1021,46 +3083,166 @@ pub fn extract_fragments(
             };
 
             // get the list of variants that overlap this read
-            let read_vars = varlist
-                .get_variants_range(interval)
+            let read_vars: &[Var] = varlist
+                .variants_in_range(&interval)
                 .chain_err(|| "Error getting variants in range.")?;
 
+            // the soft-clipped bases of the read overhang the alignment, so variants within the
+            // window past a clipped end are also counted as overlapped by the read
+            let window = extract_params.soft_clip_window;
+            let (left_clip, right_clip) =
+                soft_clip_positions(start_pos as usize, end_pos as usize, &bam_cig);
+            if extract_params.min_soft_clip_cluster.is_some() {
+                if let Some(p) = left_clip {
+                    clipped_ends.push((interval.tid, p, true));
+                }
+                if let Some(p) = right_clip {
+                    clipped_ends.push((interval.tid, p, false));
+                }
+            }
+            let clip_vars: Option<&[Var]> = if left_clip.is_some() || right_clip.is_some() {
+                let clip_interval = GenomicInterval {
+                    tid: interval.tid,
+                    chrom: interval.chrom.clone(),
+                    start_pos: match left_clip {
+                        Some(p) if p >= window => (p - window) as u32,
+                        Some(_) => 0,
+                        None => interval.start_pos,
+                    },
+                    end_pos: match right_clip {
+                        Some(p) => (p + window) as u32,
+                        None => interval.end_pos,
+                    },
+                };
+                Some(
+                    varlist
+                        .variants_in_range(&clip_interval)
+                        .chain_err(|| "Error getting variants in range.")?,
+                )
+            } else {
+                None
+            };
+            for var in clip_vars.unwrap_or(read_vars) {
+                var_overlap_reads[var.ix] += 1;
+                let near_clip = |clip: Option<usize>| match clip {
+                    Some(p) => (var.pos0 as isize - p as isize).abs() as usize <= window,
+                    None => false,
+                };
+                if near_clip(left_clip) || near_clip(right_clip) {
+                    var_clipped_reads[var.ix] += 1;
+                }
+            }
+
             // print the percentage of variants processed every 10%
             if read_vars.len() > 0
                 && ((read_vars[0].ix as f64 / varlist.lst.len() as f64) * 10.0) as usize > complete
             {
                 complete = ((read_vars[0].ix as f64 / varlist.lst.len() as f64) * 10.0) as usize;
                 if complete < 10 {
-                    eprintln!(
+                    info!(
                         "{}    {}% of variants processed...",
                         print_time(),
                         complete * 10
                     );
                 }
             }
+            if read_vars.len() > 0 {
+                progress.set(read_vars[0].ix);
+            }
 
-            let frag = extract_fragment(
-                &record,
-                &cigarpos_list,
-                read_vars,
-                &ref_seq,
-                &t_names,
-                extract_params,
-                align_params,
-            )
-            .chain_err(|| "Error extracting fragment from read.")?;
+            // the realignment band width may be overridden for this contig
+            let mut read_params = extract_params;
+            if let Some(band_width) = contig_params.get(tid).and_then(|p| p.band_width) {
+                read_params.band_width = band_width;
+            }
+            // with a strand- or read-group-specific error model, use the parameters for the
+            // read's strand or read group
+            let read_align_params = match (strand_params, read_group_params) {
+                (&Some(ref sp), _) => sp.for_strand(record.is_reverse()),
+                (&None, &Some(ref rp)) => rp.for_read_group(
+                    read_group(&record).as_ref().map(|rg| rg.as_str()),
+                    align_params,
+                ),
+                (&None, &None) => align_params,
+            };
 
-            match frag {
-                Some(some_frag) => {
-                    flist.push(some_frag);
+            // with a realignment depth cap, the read is only realigned to the variants that it
+            // was chosen for
+            let capped_vars: Vec<Var>;
+            let realign_vars: &[Var] = match skipped_vars.get(&read_ix) {
+                Some(skipped) => {
+                    capped_vars = read_vars
+                        .iter()
+                        .filter(|var| !skipped.contains(&var.ix))
+                        .cloned()
+                        .collect();
+                    &capped_vars
                 }
-                None => {}
+                None => read_vars,
+            };
+
+            let frag = if extract_params.pileup_only {
+                extract_pileup_fragment(&record, &cigarpos_list, realign_vars, read_params)
+            } else {
+                extract_fragment(
+                    &record,
+                    &cigarpos_list,
+                    realign_vars,
+                    ref_seq,
+                    &t_names,
+                    read_params,
+                    read_align_params,
+                    context_params,
+                    &mut filter_counts.realignment_concordance,
+                    &mut cluster_parent,
+                    &mut cluster_split,
+                    &mut alignment_cache,
+                )
             }
+            .chain_err(|| "Error extracting fragment from read.")
+            .chain_err(&read_error)?;
 
-            prev_tid = tid;
+            match frag {
+                // the reads without calls are only dropped if no output needs their read IDs
+                Some(ref some_frag)
+                    if extract_params.low_memory
+                        && !extract_params.store_read_id
+                        && some_frag.calls.is_empty() => {}
+                Some(some_frag) => match spill {
+                    Some(ref mut spill) => spill.push(&some_frag)?,
+                    None => flist.push(some_frag),
+                },
+                None => {}
+            }
         }
     }
-    eprintln!("{}    100% of variants processed.", print_time());
+    info!("{}    100% of variants processed.", print_time());
+    if let Some(spill) = spill {
+        info!(
+            "{} Reading {} fragments back from the spill file...",
+            print_time(),
+            spill.len()
+        );
+        flist = spill.into_fragments()?;
+    }
+    filter_counts.print();
+    filter_counts.realignment_concordance.print();
+    alignment_cache.print();
+    count_alignment_cache(
+        alignment_cache.lookups,
+        alignment_cache.hits,
+        alignment_cache.prefiltered,
+    );
+
+    if extract_params.stitch_split_reads {
+        let (stitched, merged) = stitch_split_read_fragments(flist, varlist);
+        flist = stitched;
+        info!(
+            "{} Stitched {} alignments to other alignments of the same reads.",
+            print_time(),
+            merged
+        );
+    }
 
     // label every fragment call with its index in the fragment list.
     for i in 0..flist.len() {
@@ -1083,12 +3265,51 @@ pub fn extract_fragments(
         }
     }
 
+    // the variants of a cluster are labeled with the position of its first variant. the root of
+    // each cluster is its first variant, because clusters are always linked to the smaller index
+    let mut cluster_sizes: Vec<usize> = vec![0; varlist.lst.len()];
+    let cluster_roots: Vec<usize> = (0..varlist.lst.len())
+        .map(|i| find_cluster_root(&mut cluster_parent, i))
+        .collect();
+    for &root in &cluster_roots {
+        cluster_sizes[root] += 1;
+    }
+    let root_positions: Vec<usize> = varlist.lst.iter().map(|var| var.pos0 + 1).collect();
+
     for (i, ref mut var) in varlist.lst.iter_mut().enumerate() {
+        var.cluster_id = if cluster_sizes[cluster_roots[i]] > 1 {
+            Some(root_positions[cluster_roots[i]])
+        } else {
+            None
+        };
+        var.cluster_split = cluster_split[i];
         let q = var_qual_sum[i] - LogProb::from(Prob(var_num_alleles[i] as f64)); // q is LogProb of mean allele qual
         var.mean_allele_qual = *PHREDProb::from(q);
+        var.soft_clip_frac = if var_overlap_reads[i] > 0 {
+            var_clipped_reads[i] as f64 / var_overlap_reads[i] as f64
+        } else {
+            0.0
+        };
     }
 
-    Ok(flist)
+    let clusters = match extract_params.min_soft_clip_cluster {
+        Some(min_reads) => {
+            let clusters = find_soft_clip_clusters(
+                &mut clipped_ends,
+                extract_params.soft_clip_window,
+                min_reads,
+            );
+            info!(
+                "{} Found {} clusters of soft-clipped reads.",
+                print_time(),
+                clusters.len()
+            );
+            clusters
+        }
+        None => vec![],
+    };
+
+    Ok((flist, filter_counts, clusters))
 }
 
 //************************************************************************************************
@@ -1099,6 +3320,7 @@ pub fn extract_fragments(
 mod tests {
     use super::*;
     use genotype_probs::*;
+    use rust_htslib::bam::record::CigarString;
 
     /*
     #[test]
@@ -1164,6 +3386,7 @@ mod tests {
             genotype: Genotype(0, 1),
             gq: 0.0,
             mean_allele_qual: 0.0,
+            soft_clip_frac: 0.0,
             mec: 0,
             strand_bias_pvalue: 0.0,
             mec_frac_block: 0.0,
@@ -1174,13 +3397,63 @@ mod tests {
             mq30_frac: 1.0,
             mq40_frac: 1.0,
             mq50_frac: 1.0,
+            mapq_median: vec![60.0; 2],
+            mapq_mad: vec![0.0; 2],
+            mapq_mean: vec![0.0; 2],
+            allele_qual_mean: vec![0.0; 2],
+            realignment_llr: vec![0.0; 2],
+            alt_frac: 0.0,
+            alt_frac_ci: (0.0, 1.0),
+            cluster_id: None,
+            cluster_qual: None,
+            cluster_split: false,
+            realign_skipped: 0,
+            de_novo_qual: None,
+            somatic_qual: None,
+            somatic_vaf: None,
+            mosaic_qual: None,
+            mosaic_vaf: None,
+            annotations: vec![],
+            genotype_concordance: None,
+            phase_qual: None,
+            ploidy: 2,
+            population_af: None,
+            merged_mnv: false,
+            mnv_id: None,
+            tandem_repeat: None,
+            hap_counts: None,
+            phased_no_ps: false,
             unphased_genotype: Genotype(0, 1),
             unphased_gq: 0.0,
             genotype_post: GenotypeProbs::uniform(2),
+            genotype_likelihoods: GenotypeProbs::uniform(2),
             phase_set: None,
         }
     }
 
+    #[test]
+    fn test_realignment_concordance() {
+        let mut rc = RealignmentConcordance::default();
+        assert_eq!(rc.quantile(0.5), 0.0);
+        for &diff in &[0.02, 0.5, 0.55, 1.5, 3.0, 7.0, 250.0] {
+            rc.add(diff);
+        }
+        assert_eq!(rc.reads, 7);
+        assert_eq!(rc.max, 250.0);
+        // the median (1.5) is rounded up to the end of its bin
+        assert!((rc.quantile(0.5) - 1.6).abs() < 1e-9);
+        assert_eq!(rc.quantile(1.0), 250.0);
+        assert_eq!(rc.histogram(&[1.0, 5.0, 100.0]), vec![3, 2, 1, 1]);
+
+        // negative differences are counted in the first bin
+        let mut other = RealignmentConcordance::default();
+        other.add(-2.0);
+        rc.add_distribution(&other);
+        assert_eq!(rc.reads, 8);
+        assert_eq!(rc.histogram(&[1.0]), vec![4, 4]);
+        assert_eq!(rc.max, 250.0);
+    }
+
     #[test]
     fn test_generate_haplotypes_basic() {
         let mut lst1: Vec<Var> = vec![];
@@ -1318,4 +3591,601 @@ mod tests {
         exp.sort();
         assert_eq!(haps, exp);
     }
+
+    #[test]
+    fn test_count_clipped_and_aligned_bases() {
+        let cigar = CigarString(vec![
+            Cigar::HardClip(5),
+            Cigar::SoftClip(10),
+            Cigar::Match(50),
+            Cigar::Ins(3),
+            Cigar::Del(4),
+            Cigar::Equal(20),
+            Cigar::Diff(1),
+            Cigar::SoftClip(7),
+        ])
+        .into_view(0);
+
+        assert_eq!(count_clipped_and_aligned_bases(&cigar), (17, 5, 74));
+    }
+
+    #[test]
+    fn test_soft_clip_positions() {
+        let cigar = CigarString(vec![
+            Cigar::HardClip(5),
+            Cigar::SoftClip(10),
+            Cigar::Match(50),
+            Cigar::Del(4),
+            Cigar::Match(20),
+            Cigar::HardClip(7),
+        ])
+        .into_view(100);
+
+        assert_eq!(soft_clip_positions(100, 173, &cigar), (Some(100), None));
+
+        let cigar = CigarString(vec![Cigar::Match(50), Cigar::SoftClip(3)]).into_view(100);
+
+        assert_eq!(soft_clip_positions(100, 149, &cigar), (None, Some(149)));
+    }
+
+    #[test]
+    fn test_cigar_problem() {
+        let problem = |ops: Vec<Cigar>, read_len: usize| {
+            cigar_problem(&CigarString(ops).into_view(100), read_len)
+        };
+
+        assert_eq!(
+            problem(
+                vec![
+                    Cigar::HardClip(5),
+                    Cigar::SoftClip(10),
+                    Cigar::Match(50),
+                    Cigar::Ins(3),
+                    Cigar::Del(4),
+                    Cigar::Equal(20),
+                    Cigar::SoftClip(7),
+                ],
+                90
+            ),
+            None
+        );
+        // the sequence is absent
+        assert_eq!(problem(vec![Cigar::Match(50)], 0), None);
+
+        let malformed: Vec<(Vec<Cigar>, usize)> = vec![
+            (vec![Cigar::Match(50)], 60),
+            (vec![Cigar::SoftClip(10), Cigar::HardClip(5)], 10),
+            (
+                vec![Cigar::Match(20), Cigar::Match(0), Cigar::Match(30)],
+                50,
+            ),
+            (
+                vec![Cigar::Match(20), Cigar::HardClip(5), Cigar::Match(30)],
+                50,
+            ),
+            (
+                vec![Cigar::Match(20), Cigar::SoftClip(5), Cigar::Match(30)],
+                55,
+            ),
+            (vec![Cigar::Match(20), Cigar::Pad(2), Cigar::Match(30)], 50),
+            (vec![Cigar::Ins(5), Cigar::Match(50)], 55),
+            (
+                vec![Cigar::Match(50), Cigar::Ins(5), Cigar::SoftClip(5)],
+                60,
+            ),
+            (vec![Cigar::Del(5), Cigar::Match(50)], 50),
+            (vec![Cigar::Match(50), Cigar::RefSkip(100)], 50),
+        ];
+        for (ops, read_len) in malformed {
+            assert!(problem(ops, read_len).is_some());
+        }
+    }
+
+    #[test]
+    fn test_read_filter() {
+        let mut record = Record::new();
+        record.set(
+            b"read1",
+            Some(&CigarString(vec![Cigar::Match(8)])),
+            b"ACGTACGT",
+            &[30; 8],
+        );
+        record.set_mapq(60);
+        record.set_flags(0x800); // supplementary
+
+        let mut filter = ReadFilter::new(20);
+        assert_eq!(
+            filter.check(&record),
+            Some(ReadFilterReason::Supplementary)
+        );
+
+        filter.keep_supplementary = true;
+        assert_eq!(filter.check(&record), None);
+
+        filter.min_read_length = 10;
+        assert_eq!(filter.check(&record), Some(ReadFilterReason::ReadLength));
+
+        filter.min_read_length = 0;
+        filter.forbidden_flags = 0x10;
+        filter.required_flags = 0x10;
+        assert_eq!(filter.check(&record), Some(ReadFilterReason::SamFlags));
+
+        record.set_flags(0x810); // supplementary, reverse strand
+        filter.forbidden_flags = 0;
+        assert_eq!(filter.check(&record), None);
+
+        record.set_mapq(10);
+        assert_eq!(filter.check(&record), Some(ReadFilterReason::LowMapq));
+
+        // a secondary alignment written by minimap2 without its sequence (SEQ '*')
+        let mut secondary = Record::new();
+        secondary.set(
+            b"read2",
+            Some(&CigarString(vec![Cigar::Match(8)])),
+            b"",
+            &[],
+        );
+        secondary.set_mapq(60);
+        secondary.set_flags(0x100);
+        let mut filter = ReadFilter::new(20);
+        assert_eq!(filter.check(&secondary), Some(ReadFilterReason::Secondary));
+        filter.keep_secondary = true;
+        assert_eq!(
+            filter.check(&secondary),
+            Some(ReadFilterReason::MissingSequence)
+        );
+    }
+
+    #[test]
+    fn test_split_var_cluster() {
+        let mut extract_params = ExtractFragmentParameters {
+            read_filter: ReadFilter::new(20),
+            alignment_type: AlignmentType::ForwardAlgorithmNumericallyStable,
+            band_width: 20,
+            anchor_length: 6,
+            variant_cluster_max_size: 3,
+            cluster_work_budget: 4,
+            max_window_padding: 50,
+            window_flank: 0,
+            exact_anchors: false,
+            window_merge_distance: 0,
+            max_cigar_indel: 20,
+            store_read_id: false,
+            min_alignment_score: None,
+            min_aligned_frac: 0.0,
+            max_soft_clip: None,
+            soft_clip_window: 0,
+            min_soft_clip_cluster: None,
+            missing_qual_policy: MissingQualPolicy::Keep(DEFAULT_MISSING_BASE_QUAL),
+            low_memory: false,
+            spill_fragments: false,
+            max_depth: None,
+            max_realign_depth: None,
+            alignment_cache_size: 0,
+            stitch_split_reads: false,
+            partial_reads: false,
+            pileup_only: false,
+            prefilter_edits: None,
+            repeat_band: false,
+            ref_ambiguity: RefAmbiguity::Mismatch,
+            strict: false,
+        };
+        let anchors = |left: u32, right: u32| AnchorPositions {
+            left_anchor_ref: left,
+            right_anchor_ref: right,
+            left_anchor_read: left,
+            right_anchor_read: right,
+            free_left_end: false,
+            free_right_end: false,
+        };
+        let alleles = vec!["A".to_string(), "G".to_string()];
+        // the windows of the 2nd and 3rd variants overlap by 2 bases, the others by 10 or more
+        let group: Vec<(Var, AnchorPositions)> = vec![
+            (generate_var2(0, 0, 100, alleles.clone()), anchors(80, 120)),
+            (generate_var2(1, 0, 105, alleles.clone()), anchors(85, 125)),
+            (generate_var2(2, 0, 140, alleles.clone()), anchors(123, 160)),
+            (generate_var2(3, 0, 145, alleles.clone()), anchors(130, 165)),
+        ];
+
+        let mut cluster_split = vec![false; 4];
+        let mut cluster_lst = vec![];
+        split_var_cluster(&group, extract_params, &mut cluster_split, &mut cluster_lst);
+        assert_eq!(cluster_lst.len(), 2);
+        let window = |a: &AnchorPositions| (a.left_anchor_ref, a.right_anchor_ref);
+        assert_eq!(window(&cluster_lst[0].0), (80, 125));
+        assert_eq!(window(&cluster_lst[1].0), (123, 165));
+        assert_eq!(cluster_lst[0].1.iter().map(|v| v.ix).collect::<Vec<usize>>(), vec![0, 1]);
+        assert_eq!(cluster_lst[1].1.iter().map(|v| v.ix).collect::<Vec<usize>>(), vec![2, 3]);
+        assert_eq!(cluster_split, vec![false, true, true, false]);
+
+        // without a budget, the group is cut after variant_cluster_max_size variants
+        extract_params.cluster_work_budget = 0;
+        let mut cluster_split = vec![false; 4];
+        let mut cluster_lst = vec![];
+        split_var_cluster(&group, extract_params, &mut cluster_split, &mut cluster_lst);
+        assert_eq!(cluster_lst.len(), 2);
+        assert_eq!(cluster_lst[0].1.len(), 3);
+        assert_eq!(cluster_lst[1].1.len(), 1);
+        assert_eq!(cluster_split, vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn test_group_var_windows() {
+        let anchors = |left: u32, right: u32| AnchorPositions {
+            left_anchor_ref: left,
+            right_anchor_ref: right,
+            left_anchor_read: left,
+            right_anchor_read: right,
+            free_left_end: false,
+            free_right_end: false,
+        };
+        let alleles = vec!["A".to_string(), "G".to_string()];
+        // the first two windows overlap, the third starts 5 bases after the second one ends
+        let var_anchor_lst = || {
+            vec![
+                (generate_var2(0, 0, 100, alleles.clone()), anchors(80, 120)),
+                (generate_var2(1, 0, 110, alleles.clone()), anchors(90, 130)),
+                (generate_var2(2, 0, 150, alleles.clone()), anchors(135, 170)),
+            ]
+        };
+        let group_sizes = |groups: Vec<Vec<(Var, AnchorPositions)>>| {
+            groups.iter().map(|g| g.len()).collect::<Vec<usize>>()
+        };
+        assert_eq!(
+            group_sizes(group_var_windows(var_anchor_lst(), 0)),
+            vec![2, 1]
+        );
+        assert_eq!(
+            group_sizes(group_var_windows(var_anchor_lst(), 5)),
+            vec![2, 1]
+        );
+        assert_eq!(group_sizes(group_var_windows(var_anchor_lst(), 6)), vec![3]);
+        assert!(group_var_windows(vec![], 10).is_empty());
+    }
+
+    #[test]
+    fn test_partial_read_score() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let seq = |s: &str| s.bytes().collect::<Vec<u8>>();
+        let ref_hap = seq("ACGTTGCAAGCTAGGCATCGATTACG");
+        let alt_hap = seq("ACGTTACAAGCTAGGCATCGATTACG");
+        let hap_params = vec![params; ref_hap.len()];
+
+        // a read that starts after the variant supports neither allele
+        let read = seq("GCTAGGCATCGATTACG");
+        let ref_score = partial_read_score(&read, &ref_hap, &hap_params, true);
+        let alt_score = partial_read_score(&read, &alt_hap, &hap_params, true);
+        assert!((*ref_score - *alt_score).abs() < 0.01);
+
+        // a read that starts just before the variant supports the reference allele
+        let read = seq("TTGCAAGCTAGGCATCGATTACG");
+        let ref_score = partial_read_score(&read, &ref_hap, &hap_params, true);
+        let alt_score = partial_read_score(&read, &alt_hap, &hap_params, true);
+        assert!(*ref_score - *alt_score > 2.0);
+    }
+
+    #[test]
+    fn test_alignment_cache() {
+        let key = |s: &str| (s.as_bytes().to_vec(), b"ACGT".to_vec(), 0u64);
+        let mut cache = AlignmentCache::new(4);
+        cache.insert(key("A"), LogProb(-1.0));
+        cache.insert(key("C"), LogProb(-2.0));
+        // the first generation is full, so it becomes the previous generation
+        cache.insert(key("G"), LogProb(-3.0));
+        assert_eq!(cache.get(&key("A")), Some(LogProb(-1.0)));
+        // "C" was not used in this generation, and is dropped when the next one starts
+        cache.insert(key("T"), LogProb(-4.0));
+        assert_eq!(cache.get(&key("C")), None);
+        assert_eq!(cache.get(&key("A")), Some(LogProb(-1.0)));
+        assert_eq!(cache.get(&key("G")), Some(LogProb(-3.0)));
+        assert_eq!((cache.hits, cache.lookups), (3, 4));
+
+        // a cache of size 0 stores nothing
+        let mut cache = AlignmentCache::new(0);
+        cache.insert(key("A"), LogProb(-1.0));
+        assert_eq!(cache.get(&key("A")), None);
+        assert_eq!(cache.lookups, 0);
+    }
+
+    #[test]
+    fn test_prefilter_edit_distances() {
+        let mut extract_params = ExtractFragmentParameters {
+            read_filter: ReadFilter::new(20),
+            alignment_type: AlignmentType::ForwardAlgorithmNonNumericallyStable,
+            band_width: 20,
+            anchor_length: 6,
+            variant_cluster_max_size: 3,
+            cluster_work_budget: 0,
+            max_window_padding: 50,
+            window_flank: 0,
+            exact_anchors: false,
+            window_merge_distance: 0,
+            max_cigar_indel: 20,
+            store_read_id: false,
+            min_alignment_score: None,
+            min_aligned_frac: 0.0,
+            max_soft_clip: None,
+            soft_clip_window: 0,
+            min_soft_clip_cluster: None,
+            missing_qual_policy: MissingQualPolicy::Keep(DEFAULT_MISSING_BASE_QUAL),
+            low_memory: false,
+            spill_fragments: false,
+            max_depth: None,
+            max_realign_depth: None,
+            alignment_cache_size: 0,
+            stitch_split_reads: false,
+            partial_reads: false,
+            pileup_only: false,
+            prefilter_edits: Some(2),
+            repeat_band: false,
+            ref_ambiguity: RefAmbiguity::Mismatch,
+            strict: false,
+        };
+        let mut anchors = AnchorPositions {
+            left_anchor_ref: 0,
+            right_anchor_ref: 20,
+            left_anchor_read: 0,
+            right_anchor_read: 20,
+            free_left_end: false,
+            free_right_end: false,
+        };
+        let seq = |s: &str| -> Vec<u8> { s.bytes().collect() };
+        let hap_windows = vec![seq("ACGTTGCAAGCTAGGCATGCA"), seq("ACGTTGCAAGCGAGGCATGCA")];
+
+        // the read has the alternate allele and a sequencing error, so it is one edit closer to
+        // the alternate haplotype
+        let read = seq("ACGTTGCAGCGAGGCATGCA");
+        assert_eq!(
+            prefilter_edit_distances(&read, &hap_windows, &anchors, &extract_params),
+            None
+        );
+        extract_params.prefilter_edits = Some(1);
+        assert_eq!(
+            prefilter_edit_distances(&read, &hap_windows, &anchors, &extract_params),
+            Some(vec![2, 1])
+        );
+        // a read that is as close to both haplotypes is always realigned
+        let read = seq("ACGTTGCAAGCAAGGCATGCA");
+        assert_eq!(
+            prefilter_edit_distances(&read, &hap_windows, &anchors, &extract_params),
+            None
+        );
+
+        // partial reads and disabled pre-filters are always realigned
+        let read = seq("ACGTTGCAAGCGAGGCATGCA");
+        anchors.free_right_end = true;
+        assert_eq!(
+            prefilter_edit_distances(&read, &hap_windows, &anchors, &extract_params),
+            None
+        );
+        anchors.free_right_end = false;
+        extract_params.prefilter_edits = None;
+        assert_eq!(
+            prefilter_edit_distances(&read, &hap_windows, &anchors, &extract_params),
+            None
+        );
+    }
+
+    #[test]
+    fn test_longest_tandem_repeat() {
+        let seq = |s: &str| -> Vec<char> { s.chars().collect() };
+        assert_eq!(longest_tandem_repeat(&seq("ACGTACGA"), 4), None);
+        assert_eq!(longest_tandem_repeat(&seq("ACGTACGT"), 4), Some((4, 8)));
+        assert_eq!(longest_tandem_repeat(&seq("ACGTACGT"), 3), None);
+        // a homopolymer is preferred to a dinucleotide repeat of the same length
+        assert_eq!(longest_tandem_repeat(&seq("GAAAAAAC"), 6), Some((1, 6)));
+        assert_eq!(longest_tandem_repeat(&seq("GACACACACT"), 6), Some((2, 8)));
+        // the repeat may end with a partial copy of the unit
+        assert_eq!(longest_tandem_repeat(&seq("TCAGCAGCAGCAT"), 6), Some((3, 11)));
+        assert_eq!(longest_tandem_repeat(&seq("ANNNNNNA"), 6), None);
+    }
+
+    #[test]
+    fn test_stitch_split_read_fragments() {
+        let lst = vec![
+            generate_var2(0, 0, 100, vec!["A".to_string(), "G".to_string()]),
+            generate_var2(1, 0, 200, vec!["C".to_string(), "T".to_string()]),
+            generate_var2(2, 0, 90000, vec!["G".to_string(), "A".to_string()]),
+            generate_var2(3, 1, 100, vec!["T".to_string(), "C".to_string()]),
+        ];
+        let varlist = VarList::new(lst, vec!["chr1".to_string(), "chr2".to_string()]).unwrap();
+        let fragment = |id: &str, calls: Vec<(usize, u8, f64)>| {
+            Fragment::new(
+                Some(id.to_string()),
+                calls
+                    .iter()
+                    .map(|&(v, a, p)| FragCall::new(0, v, a, p))
+                    .collect(),
+            )
+        };
+        // the inverted part of r3, aligned to the reverse strand
+        let mut inverted = fragment("r3", vec![(2, 0, 0.1)]);
+        inverted.reverse_strand = true;
+        inverted.calls[0].reverse_strand = true;
+        let flist = vec![
+            fragment("r1", vec![(0, 1, 0.1), (1, 0, 0.1)]),
+            fragment("r2", vec![(0, 0, 0.1)]),
+            fragment("r3", vec![(1, 1, 0.1)]),
+            // supplementary alignment of r1, overlapping the primary at variant 1
+            fragment("r1", vec![(1, 0, 0.01), (2, 1, 0.1)]),
+            // an alignment of r2 on another contig, and one that disagrees with the first
+            fragment("r2", vec![(3, 1, 0.1)]),
+            fragment("r2", vec![(0, 1, 0.1)]),
+            inverted,
+        ];
+        let (stitched, merged) = stitch_split_read_fragments(flist, &varlist);
+        assert_eq!(merged, 3);
+        let calls = |f: &Fragment| {
+            f.calls
+                .iter()
+                .map(|c| (c.var_ix, c.allele))
+                .collect::<Vec<(usize, u8)>>()
+        };
+        assert_eq!(stitched.len(), 4);
+        assert_eq!(calls(&stitched[0]), vec![(0, 1), (1, 0), (2, 1)]);
+        assert!(stitched[0].calls[1].qual < LogProb::from(Prob(0.05)));
+        assert_eq!(calls(&stitched[1]), vec![]);
+        // the alignments of r3 on opposite strands are stitched, keeping the strand of each call
+        assert_eq!(calls(&stitched[2]), vec![(1, 1), (2, 0)]);
+        assert!(!stitched[2].reverse_strand);
+        assert_eq!(
+            stitched[2].calls.iter().map(|c| c.reverse_strand).collect::<Vec<bool>>(),
+            vec![false, true]
+        );
+        assert_eq!(calls(&stitched[3]), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn test_stratified_sample() {
+        let strata = vec![0, 0, 0, 0, 0, 7, 7, 7, 3, 3];
+        let mut rng = StdRng::from_seed(&[DOWNSAMPLE_SEED]);
+        // shares of 2.5, 1 and 1.5 reads: the remaining read goes to the lowest stratum with the
+        // largest remainder
+        let chosen = stratified_sample(&strata, 5, &mut rng);
+        let count = |s: usize| chosen.iter().filter(|&&i| strata[i] == s).count();
+        assert_eq!((count(0), count(3), count(7)), (3, 1, 1));
+        assert!(chosen.windows(2).all(|w| w[0] < w[1]));
+        // all reads are kept if there are not more than max_depth
+        assert_eq!(stratified_sample(&strata, 10, &mut rng), (0..10).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_select_informative_reads() {
+        let read = |read_ix: usize, reverse: bool, mapq: u8, span: usize| DepthCapRead {
+            read_ix: read_ix,
+            reverse: reverse,
+            mapq: mapq,
+            span: span,
+        };
+        let reads = || {
+            vec![
+                read(0, false, 60, 5),
+                read(1, false, 60, 500),
+                read(2, false, 20, 800),
+                read(3, false, 60, 300),
+                read(4, true, 60, 20),
+                read(5, true, 60, 200),
+                read(6, false, 60, 1000),
+            ]
+        };
+
+        // the reads spanning the full window, by MAPQ and span, alternating between the strands
+        assert_eq!(select_informative_reads(reads(), 250, 3), vec![6, 5, 1]);
+        assert_eq!(select_informative_reads(reads(), 250, 4), vec![6, 5, 1, 4]);
+        assert_eq!(
+            select_informative_reads(reads(), 250, 6),
+            vec![6, 5, 1, 4, 3, 2]
+        );
+        // all reads are kept if there are not more than max_reads
+        assert_eq!(select_informative_reads(reads(), 250, 7).len(), 7);
+    }
+
+    #[test]
+    fn test_extract_pileup_fragment() {
+        let mut record = Record::new();
+        let mut quals = [30; 13];
+        quals[12] = 10;
+        record.set(
+            b"read1",
+            Some(&CigarString(vec![
+                Cigar::Match(4),
+                Cigar::Ins(1),
+                Cigar::Match(4),
+                Cigar::Del(2),
+                Cigar::Match(4),
+            ])),
+            b"ACGTGACGTTTTT",
+            &quals,
+        );
+        record.set_pos(100);
+        record.set_mapq(60);
+        let cigarpos_list = create_augmented_cigarlist(100, &record.cigar()).unwrap();
+
+        let alleles = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let vars: Vec<Var> = vec![
+            (101, alleles(&["C", "T"])),
+            (102, alleles(&["A", "G"])),
+            (103, alleles(&["T", "TG"])),
+            (107, alleles(&["TAA", "T"])),
+            // the read's alignment starts a deletion at the variant
+            (108, alleles(&["AA", "A"])),
+            // the read has a base that is neither allele
+            (110, alleles(&["A", "C"])),
+            (113, alleles(&["T", "C"])),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(ix, (pos0, a))| {
+            let mut var = Var::new(0, pos0, a);
+            var.ix = ix;
+            var
+        })
+        .collect();
+
+        let mut extract_params = ExtractFragmentParameters {
+            read_filter: ReadFilter::new(20),
+            alignment_type: AlignmentType::ForwardAlgorithmNonNumericallyStable,
+            band_width: 20,
+            anchor_length: 6,
+            variant_cluster_max_size: 3,
+            cluster_work_budget: 0,
+            max_window_padding: 50,
+            window_flank: 0,
+            exact_anchors: false,
+            window_merge_distance: 0,
+            max_cigar_indel: 20,
+            store_read_id: true,
+            min_alignment_score: None,
+            min_aligned_frac: 0.0,
+            max_soft_clip: None,
+            soft_clip_window: 0,
+            min_soft_clip_cluster: None,
+            missing_qual_policy: MissingQualPolicy::Keep(DEFAULT_MISSING_BASE_QUAL),
+            low_memory: false,
+            spill_fragments: false,
+            max_depth: None,
+            max_realign_depth: None,
+            alignment_cache_size: 0,
+            stitch_split_reads: false,
+            partial_reads: false,
+            pileup_only: true,
+            prefilter_edits: None,
+            repeat_band: false,
+            ref_ambiguity: RefAmbiguity::Mismatch,
+            strict: false,
+        };
+        let frag = extract_pileup_fragment(&record, &cigarpos_list, &vars, extract_params)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frag.id, Some("read1".to_string()));
+        let calls: Vec<(usize, u8)> = frag.calls.iter().map(|c| (c.var_ix, c.allele)).collect();
+        assert_eq!(calls, vec![(0, 0), (1, 1), (2, 1), (3, 1), (6, 0)]);
+
+        // Q30 base and MAPQ 60
+        let p_miscall = 1.0 - (1.0 - 0.001) * (1.0 - 0.000001);
+        assert!((*Prob::from(frag.calls[0].qual) - p_miscall).abs() < 1e-9);
+        // Q10 base
+        assert!((*Prob::from(frag.calls[4].qual) - 0.1).abs() < 1e-3);
+
+        extract_params.read_filter.min_mapq = 61;
+        assert!(extract_pileup_fragment(&record, &cigarpos_list, &vars, extract_params)
+            .unwrap()
+            .is_none());
+    }
 }
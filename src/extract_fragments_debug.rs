@@ -223,7 +223,7 @@ pub fn extract_fragments_debug(
             {
                 complete = ((read_vars[0].ix as f64 / varlist.lst.len() as f64) * 10.0) as usize;
                 if complete < 10 {
-                    eprintln!(
+                    info!(
                         "{}    {}% of variants processed...",
                         print_time(),
                         complete * 10
@@ -247,7 +247,7 @@ pub fn extract_fragments_debug(
             prev_tid = tid;
         }
     }
-    eprintln!("{}    100% of variants processed.", print_time());
+    info!("{}    100% of variants processed.", print_time());
 
     let mut total_ops = 0;
     let mut total_ref_len = 0;
@@ -291,30 +291,30 @@ pub fn extract_fragments_debug(
         region_variants[region_ix] += var_cluster.len();
         region_cluster_count[region_ix] += 1;
     }
-    eprintln!("\n----------------------------------------------------------------");
-    eprintln!("RESULTS FOR ALLELE REALIGNMENT DEBUG ANALYSIS:");
+    info!("\n----------------------------------------------------------------");
+    info!("RESULTS FOR ALLELE REALIGNMENT DEBUG ANALYSIS:");
 
     let total_mean_read_window_len = total_read_len as f64 / (total_cluster_lst.len() as f64);
     let total_mean_ref_window_len = total_ref_len as f64 / (total_cluster_lst.len() as f64);
     let mean_variants_per_cluster = total_variants as f64 / (total_cluster_lst.len() as f64);
 
-    eprintln!("Total realignment operations:  {}", total_ops);
-    eprintln!(
+    info!("Total realignment operations:  {}", total_ops);
+    info!(
         "Total mean read window length: {:.2}",
         total_mean_read_window_len
     );
-    eprintln!(
+    info!(
         "Total mean ref window length:  {:.2}",
         total_mean_ref_window_len
     );
-    eprintln!(
+    info!(
         "Mean variants per cluster:     {:.2}",
         mean_variants_per_cluster
     );
 
-    eprintln!("\n----------------------------------------------------------------");
-    eprintln!("RESULTS PER REGION:");
-    eprintln!("REGION\tMEAN_VARIANTS_PER_CLUSTER\tMEAN_READ_WINDOW_LEN\tMEAN_REF_WINDOW_LEN\tOPS_COUNT\tOPS_ENRICHMENT");
+    info!("\n----------------------------------------------------------------");
+    info!("RESULTS PER REGION:");
+    info!("REGION\tMEAN_VARIANTS_PER_CLUSTER\tMEAN_READ_WINDOW_LEN\tMEAN_REF_WINDOW_LEN\tOPS_COUNT\tOPS_ENRICHMENT");
 
     let mean_ops_per_region = total_ops as f64 / (region_op_counts.len() as f64);
 
@@ -328,7 +328,7 @@ pub fn extract_fragments_debug(
             region_variants[i] as f64 / (region_cluster_count[i] as f64);
         let mean_read_window_len = region_read_len_sum[i] as f64 / (region_cluster_count[i] as f64);
         let mean_ref_window_len = region_ref_len_sum[i] as f64 / (region_cluster_count[i] as f64);
-        eprintln!(
+        info!(
             "{}:{}-{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{:.2}",
             chrom,
             region_start,
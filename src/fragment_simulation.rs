@@ -0,0 +1,231 @@
+//! Simulation of haplotype fragment matrices with known haplotypes, for testing and comparing
+//! phasing algorithms.
+//!
+//! Every simulated variant is a heterozygous biallelic SNV. Each fragment covers a run of
+//! consecutive variants, with a length drawn from a ```ReadLengthDistribution```, and reads the
+//! alleles of one of the two haplotypes with independent allele call errors. A fragment can also
+//! switch to the other haplotype between two variants (e.g. a chimeric read), which gives phasing
+//! algorithms a switch structure to recover from.
+
+use genotype_probs::Genotype;
+use rand::{Rng, SeedableRng, StdRng};
+use variants_and_fragments::{FragCall, Fragment, Var, VarList};
+
+/// The distance between two simulated variants
+pub static SIMULATED_VARIANT_SPACING: usize = 100;
+
+/// Distribution of the number of variants covered by a simulated fragment
+#[derive(Debug, Clone, Copy)]
+pub enum ReadLengthDistribution {
+    /// every fragment covers the same number of variants
+    Fixed(usize),
+    /// uniform between a minimum and a maximum number of variants (inclusive)
+    Uniform(usize, usize),
+    /// geometric with the given mean number of variants, a long-tailed distribution similar to the
+    /// read lengths of single molecule sequencing
+    Geometric(f64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentSimulationParameters {
+    pub num_variants: usize,
+    pub num_fragments: usize,
+    pub read_length: ReadLengthDistribution,
+    /// probability that an allele call is an error
+    pub error_rate: f64,
+    /// probability that a fragment switches to the other haplotype between two variants
+    pub switch_rate: f64,
+    pub seed: usize,
+}
+
+impl FragmentSimulationParameters {
+    /// Creates the parameters for a simulation of ```num_fragments``` fragments over
+    /// ```num_variants``` variants, with fragments covering 2 to 10 variants, a 5% allele call
+    /// error rate and no haplotype switches
+    pub fn new(num_variants: usize, num_fragments: usize) -> FragmentSimulationParameters {
+        FragmentSimulationParameters {
+            num_variants: num_variants,
+            num_fragments: num_fragments,
+            read_length: ReadLengthDistribution::Uniform(2, 10),
+            error_rate: 0.05,
+            switch_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A simulated fragment matrix and the truth it was simulated from
+pub struct SimulatedFragments {
+    /// the fragments, ordered by their first variant
+    pub flist: Vec<Fragment>,
+    /// ```haplotypes[h][v]``` is the allele of haplotype ```h``` at variant ```v```
+    pub haplotypes: [Vec<u8>; 2],
+    /// ```call_haplotypes[f][i]``` is the haplotype that ```flist[f].calls[i]``` was read from
+    pub call_haplotypes: Vec<Vec<usize>>,
+}
+
+impl SimulatedFragments {
+    /// Returns the simulated variants as heterozygous SNVs on a contig ```sim```, with the true
+    /// genotypes, e.g. to run the phasing and phasing quality functions that take a ```VarList```
+    pub fn varlist(&self) -> VarList {
+        let lst: Vec<Var> = (0..self.haplotypes[0].len())
+            .map(|v| {
+                let mut var = Var::new(
+                    0,
+                    v * SIMULATED_VARIANT_SPACING,
+                    vec!["A".to_string(), "G".to_string()],
+                );
+                var.genotype = Genotype(self.haplotypes[0][v], self.haplotypes[1][v]);
+                var
+            })
+            .collect();
+        VarList::new(lst, vec!["sim".to_string()]).unwrap()
+    }
+
+    /// Compares a phased haplotype to the truth
+    ///
+    /// # Arguments
+    /// - ```var_ixs```: the phased variants, in increasing order
+    /// - ```hap```: ```hap[j]``` is the allele of the phased haplotype at variant ```var_ixs[j]```
+    ///
+    /// # Returns
+    /// Returns whether the phased allele of each variant matches the first true haplotype, which
+    /// can be passed to ```count_switch_flip_errors```
+    pub fn orientations(&self, var_ixs: &[usize], hap: &[u8]) -> Vec<bool> {
+        var_ixs
+            .iter()
+            .zip(hap.iter())
+            .map(|(&v, &allele)| allele == self.haplotypes[0][v])
+            .collect()
+    }
+}
+
+fn sample_read_length(rng: &mut StdRng, read_length: ReadLengthDistribution) -> usize {
+    let len = match read_length {
+        ReadLengthDistribution::Fixed(len) => len,
+        ReadLengthDistribution::Uniform(min_len, max_len) => {
+            assert!(min_len <= max_len);
+            rng.gen_range(min_len, max_len + 1)
+        }
+        ReadLengthDistribution::Geometric(mean) => {
+            if mean <= 1.0 {
+                1
+            } else {
+                // inverse transform sampling of the number of variants after the first
+                let u: f64 = 1.0 - rng.gen::<f64>();
+                1 + (u.ln() / (1.0 - 1.0 / mean).ln()).floor() as usize
+            }
+        }
+    };
+    len.max(1)
+}
+
+/// Simulates a fragment matrix
+///
+/// # Arguments
+/// - ```params```: the simulation parameters. The simulation is deterministic for a given
+///   ```params.seed```.
+///
+/// # Returns
+/// Returns the fragments, the true haplotypes and the haplotype of every allele call. Allele calls
+/// have ```params.error_rate``` as their miscall probability.
+pub fn simulate_fragments(params: &FragmentSimulationParameters) -> SimulatedFragments {
+    assert!(params.num_variants > 0);
+    let mut rng: StdRng = StdRng::from_seed(&[params.seed]);
+
+    let hap0: Vec<u8> = (0..params.num_variants)
+        .map(|_| if rng.gen::<f64>() < 0.5 { 0 } else { 1 })
+        .collect();
+    let hap1: Vec<u8> = hap0.iter().map(|&a| 1 - a).collect();
+    let haplotypes = [hap0, hap1];
+
+    // (first variant, calls, haplotype of each call) of each fragment
+    let mut fragments: Vec<(usize, Vec<(usize, u8)>, Vec<usize>)> = vec![];
+    for _ in 0..params.num_fragments {
+        let start = rng.gen_range(0, params.num_variants);
+        let len = sample_read_length(&mut rng, params.read_length);
+        let end = (start + len).min(params.num_variants);
+
+        let mut h: usize = if rng.gen::<f64>() < 0.5 { 0 } else { 1 };
+        let mut calls: Vec<(usize, u8)> = vec![];
+        let mut call_haps: Vec<usize> = vec![];
+        for v in start..end {
+            if v > start && rng.gen::<f64>() < params.switch_rate {
+                h = 1 - h;
+            }
+            let mut allele = haplotypes[h][v];
+            if rng.gen::<f64>() < params.error_rate {
+                allele = 1 - allele;
+            }
+            calls.push((v, allele));
+            call_haps.push(h);
+        }
+        fragments.push((start, calls, call_haps));
+    }
+    fragments.sort_by_key(|&(start, _, _)| start);
+
+    let mut flist: Vec<Fragment> = vec![];
+    let mut call_haplotypes: Vec<Vec<usize>> = vec![];
+    for (f, (_, calls, call_haps)) in fragments.into_iter().enumerate() {
+        let calls: Vec<FragCall> = calls
+            .into_iter()
+            .map(|(v, allele)| FragCall::new(f, v, allele, params.error_rate))
+            .collect();
+        flist.push(Fragment::new(Some(format!("sim{}", f)), calls));
+        call_haplotypes.push(call_haps);
+    }
+
+    SimulatedFragments {
+        flist: flist,
+        haplotypes: haplotypes,
+        call_haplotypes: call_haplotypes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phasing_evaluation::count_switch_flip_errors;
+    use polyploid_phasing::phase_polyploid;
+
+    #[test]
+    fn test_simulate_fragments() {
+        let mut params = FragmentSimulationParameters::new(30, 50);
+        params.error_rate = 0.0;
+        params.read_length = ReadLengthDistribution::Uniform(3, 5);
+        let sim = simulate_fragments(&params);
+
+        assert_eq!(sim.flist.len(), 50);
+        for (f, frag) in sim.flist.iter().enumerate() {
+            // fragments cover consecutive variants and match their haplotype without errors
+            assert!(frag.calls.len() >= 1 && frag.calls.len() <= 5);
+            let h = sim.call_haplotypes[f][0];
+            for (i, call) in frag.calls.iter().enumerate() {
+                assert_eq!(call.frag_ix, f);
+                assert_eq!(call.var_ix, frag.calls[0].var_ix + i);
+                assert_eq!(call.allele, sim.haplotypes[h][call.var_ix]);
+                assert_eq!(sim.call_haplotypes[f][i], h);
+            }
+        }
+
+        // the same seed gives the same fragments
+        let sim2 = simulate_fragments(&params);
+        assert_eq!(sim.haplotypes, sim2.haplotypes);
+        assert_eq!(sim.call_haplotypes, sim2.call_haplotypes);
+    }
+
+    #[test]
+    fn test_phase_simulated_fragments() {
+        let mut params = FragmentSimulationParameters::new(40, 200);
+        params.read_length = ReadLengthDistribution::Fixed(8);
+        params.error_rate = 0.02;
+        let sim = simulate_fragments(&params);
+
+        let phase_variant = vec![true; params.num_variants];
+        let blocks = phase_polyploid(&sim.flist, &phase_variant, 2, 0.1, 5);
+
+        assert_eq!(blocks.len(), 1);
+        let orientations = sim.orientations(&blocks[0].var_ixs, &blocks[0].haps[0]);
+        assert_eq!(count_switch_flip_errors(&orientations), (0, 0));
+    }
+}
@@ -0,0 +1,278 @@
+//! Disk-backed storage of the haplotype fragments during read realignment (```--spill_fragments```).
+//!
+//! The fragments are appended to a temporary file in a compact binary encoding as they are
+//! extracted, instead of growing a list of fragments in memory next to the working memory of the
+//! realignment. When all reads are realigned, the file is memory-mapped and the fragments are
+//! decoded into a list of exactly the needed size, without the spare capacity (and the copies when
+//! it is reallocated) of a list that grows one fragment at a time.
+//!
+//! Each fragment is encoded as its read ID (a 4-byte length, ```u32::MAX``` for no ID, followed by
+//! the bytes), its haplotype log-probabilities (2 x f64), a byte of flags (reverse strand, has an
+//! error rate, has a PS tag), its mapping quality, its error rate (f32), its HP tag (0 for none),
+//! its PS tag (i32) and its number of calls (u32), followed by its calls. Each call is encoded as its variant index (u32), allele, a byte of flags (reverse
+//! strand), base quality, read position (u32) and its log-probabilities of being wrong and right
+//! (2 x f64), which keeps the qualities exact. All numbers are little-endian.
+
+use bio::stats::LogProb;
+use errors::*;
+use memmap::Mmap;
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use variants_and_fragments::{FragCall, Fragment};
+
+/// the number of spill files created by this process, to give each a unique name
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// the encoded size of a call in bytes
+const CALL_BYTES: usize = 27;
+
+fn encode_fragment(frag: &Fragment, buf: &mut Vec<u8>) {
+    match frag.id {
+        Some(ref id) => {
+            buf.extend_from_slice(&(id.len() as u32).to_le_bytes());
+            buf.extend_from_slice(id.as_bytes());
+        }
+        None => buf.extend_from_slice(&u32::max_value().to_le_bytes()),
+    }
+    buf.extend_from_slice(&frag.p_read_hap[0].to_bits().to_le_bytes());
+    buf.extend_from_slice(&frag.p_read_hap[1].to_bits().to_le_bytes());
+    buf.push(
+        frag.reverse_strand as u8
+            | (frag.error_rate.is_some() as u8) << 1
+            | (frag.ps_tag.is_some() as u8) << 2,
+    );
+    buf.push(frag.mapq);
+    buf.extend_from_slice(&frag.error_rate.unwrap_or(0.0).to_bits().to_le_bytes());
+    buf.push(frag.hp_tag.unwrap_or(0));
+    buf.extend_from_slice(&frag.ps_tag.unwrap_or(0).to_le_bytes());
+    buf.extend_from_slice(&(frag.calls.len() as u32).to_le_bytes());
+    for call in &frag.calls {
+        buf.extend_from_slice(&(call.var_ix as u32).to_le_bytes());
+        buf.push(call.allele);
+        buf.push(call.reverse_strand as u8);
+        buf.push(call.base_qual);
+        buf.extend_from_slice(&call.read_pos.to_le_bytes());
+        buf.extend_from_slice(&call.qual.to_bits().to_le_bytes());
+        buf.extend_from_slice(&call.one_minus_qual.to_bits().to_le_bytes());
+    }
+}
+
+/// Reads little-endian numbers from an encoded fragment
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(
+            self.pos + n <= self.data.len(),
+            "Truncated fragment in the fragment spill file."
+        );
+        let b = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(f64::from_bits(u64::from_le_bytes(b)))
+    }
+
+    fn fragment(&mut self) -> Result<Fragment> {
+        let id_len = self.u32()?;
+        let id = if id_len == u32::max_value() {
+            None
+        } else {
+            Some(
+                String::from_utf8(self.bytes(id_len as usize)?.to_vec())
+                    .chain_err(|| "Invalid read ID in the fragment spill file.")?,
+            )
+        };
+        let p_read_hap = [LogProb(self.f64()?), LogProb(self.f64()?)];
+        let flags = self.u8()?;
+        let mapq = self.u8()?;
+        let error_rate = f32::from_bits(self.u32()?);
+        let hp_tag = self.u8()?;
+        let ps_tag = self.u32()? as i32;
+        let n_calls = self.u32()? as usize;
+        ensure!(
+            self.pos + n_calls * CALL_BYTES <= self.data.len(),
+            "Truncated fragment in the fragment spill file."
+        );
+        let mut calls: Vec<FragCall> = Vec::with_capacity(n_calls);
+        for _ in 0..n_calls {
+            let var_ix = self.u32()? as usize;
+            let allele = self.u8()?;
+            let reverse_strand = self.u8()? != 0;
+            let base_qual = self.u8()?;
+            let read_pos = self.u32()?;
+            calls.push(FragCall {
+                frag_ix: 0,
+                var_ix: var_ix,
+                allele: allele,
+                qual: LogProb(self.f64()?),
+                one_minus_qual: LogProb(self.f64()?),
+                reverse_strand: reverse_strand,
+                read_pos: read_pos,
+                base_qual: base_qual,
+            });
+        }
+        Ok(Fragment {
+            id: id,
+            calls: calls,
+            p_read_hap: p_read_hap,
+            reverse_strand: flags & 1 != 0,
+            mapq: mapq,
+            error_rate: if flags & 2 != 0 { Some(error_rate) } else { None },
+            hp_tag: if hp_tag != 0 { Some(hp_tag) } else { None },
+            ps_tag: if flags & 4 != 0 { Some(ps_tag) } else { None },
+        })
+    }
+}
+
+/// A temporary file that fragments are appended to, see the module documentation
+pub struct FragmentSpill {
+    path: PathBuf,
+    file: BufWriter<File>,
+    buf: Vec<u8>,
+    n_fragments: usize,
+}
+
+impl FragmentSpill {
+    /// Creates a spill file in the temporary directory (```$TMPDIR``` or ```/tmp```)
+    pub fn create() -> Result<FragmentSpill> {
+        let path = env::temp_dir().join(format!(
+            "longshot_fragments_{}_{}.bin",
+            process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::SeqCst)
+        ));
+        let display = path.display().to_string();
+        let file = File::create(&path).chain_err(|| ErrorKind::CreateFileError(display))?;
+        Ok(FragmentSpill {
+            path: path,
+            file: BufWriter::new(file),
+            buf: vec![],
+            n_fragments: 0,
+        })
+    }
+
+    pub fn push(&mut self, frag: &Fragment) -> Result<()> {
+        self.buf.clear();
+        encode_fragment(frag, &mut self.buf);
+        let path = &self.path;
+        self.file
+            .write_all(&self.buf)
+            .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+        self.n_fragments += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.n_fragments
+    }
+
+    /// Reads the fragments back from the memory-mapped file, in the order they were added. The
+    /// file is removed when the spill is dropped.
+    pub fn into_fragments(mut self) -> Result<Vec<Fragment>> {
+        let display = self.path.display().to_string();
+        self.file
+            .flush()
+            .chain_err(|| ErrorKind::FileWriteError(display.clone()))?;
+        let mut flist: Vec<Fragment> = Vec::with_capacity(self.n_fragments);
+        if self.n_fragments == 0 {
+            return Ok(flist);
+        }
+        let file = File::open(&self.path).chain_err(|| ErrorKind::FileReadError(display.clone()))?;
+        let mmap =
+            unsafe { Mmap::map(&file) }.chain_err(|| ErrorKind::FileReadError(display.clone()))?;
+        let mut decoder = Decoder {
+            data: &mmap[..],
+            pos: 0,
+        };
+        for _ in 0..self.n_fragments {
+            flist.push(decoder.fragment()?);
+        }
+        Ok(flist)
+    }
+}
+
+impl Drop for FragmentSpill {
+    fn drop(&mut self) {
+        // the spill file is temporary, and an error removing it doesn't affect the results
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::stats::Prob;
+    use variants_and_fragments::fragments_from_strings;
+
+    #[test]
+    fn test_fragment_spill() {
+        let mut flist = fragments_from_strings(&["01-1", "", "-10"], 0.01);
+        flist[0].mapq = 60;
+        flist[0].error_rate = Some(0.05);
+        flist[0].hp_tag = Some(2);
+        flist[0].ps_tag = Some(-5);
+        flist[2].hp_tag = Some(1);
+        flist[0].calls[1].reverse_strand = true;
+        flist[0].calls[1].read_pos = 1234;
+        flist[0].calls[1].base_qual = 30;
+        flist[1].id = None;
+        flist[2].reverse_strand = true;
+        flist[2].p_read_hap = [LogProb::from(Prob(0.9)), LogProb::from(Prob(0.1))];
+
+        let mut spill = FragmentSpill::create().unwrap();
+        let path = spill.path.clone();
+        for frag in &flist {
+            spill.push(frag).unwrap();
+        }
+        assert_eq!(spill.len(), 3);
+        let decoded = spill.into_fragments().unwrap();
+        assert!(!path.exists());
+
+        assert_eq!(decoded.len(), flist.len());
+        for (d, f) in decoded.iter().zip(flist.iter()) {
+            assert_eq!(d.id, f.id);
+            assert_eq!(d.p_read_hap, f.p_read_hap);
+            assert_eq!(d.reverse_strand, f.reverse_strand);
+            assert_eq!(d.mapq, f.mapq);
+            assert_eq!(d.error_rate, f.error_rate);
+            assert_eq!(d.hp_tag, f.hp_tag);
+            assert_eq!(d.ps_tag, f.ps_tag);
+            assert_eq!(d.calls.len(), f.calls.len());
+            assert_eq!(d.calls.capacity(), f.calls.len());
+            for (dc, fc) in d.calls.iter().zip(f.calls.iter()) {
+                assert_eq!(
+                    (dc.var_ix, dc.allele, dc.reverse_strand, dc.read_pos, dc.base_qual),
+                    (fc.var_ix, fc.allele, fc.reverse_strand, fc.read_pos, fc.base_qual)
+                );
+                assert_eq!(dc.qual, fc.qual);
+                assert_eq!(dc.one_minus_qual, fc.one_minus_qual);
+            }
+        }
+
+        // an empty spill file
+        let spill = FragmentSpill::create().unwrap();
+        assert!(spill.into_fragments().unwrap().is_empty());
+    }
+}
@@ -98,6 +98,41 @@ impl GenotypeProbs {
         (Genotype(max_i as u8, max_j as u8), max_post)
     }
 
+    /// The discovery quality of a site (QUAL in the VCF): the PHRED-scaled probability that the
+    /// genotype is homozygous reference. It measures the evidence that there is a non-reference
+    /// allele at the site, regardless of which genotype is called.
+    pub fn discovery_qual(&self) -> f64 {
+        *PHREDProb::from(self.get(Genotype(0, 0)))
+    }
+
+    /// The genotype quality of the genotype call ```g``` (GQ in the VCF): the PHRED-scaled
+    /// probability that the genotype is any of the ```possible``` genotypes other than ```g``` (in
+    /// either phase). It measures the evidence that the called genotype is right, e.g. a confident
+    /// variant site can have a low GQ if it is unclear whether it is heterozygous or homozygous.
+    pub fn genotype_qual(&self, g: Genotype, possible: &[Genotype]) -> f64 {
+        let p_call_wrong: Vec<LogProb> = possible
+            .iter()
+            .filter(|&&other| other != g && other != Genotype(g.1, g.0))
+            .map(|&other| self.get(other))
+            .collect();
+        *PHREDProb::from(LogProb::ln_sum_exp(&p_call_wrong))
+    }
+
+    /// Divides the genotype priors out of posterior probabilities, which gives the genotype
+    /// likelihoods P(data | G) normalized to sum to 1. Genotypes with a prior of zero (e.g.
+    /// heterozygous genotypes at haploid sites) get a likelihood of zero.
+    pub fn likelihoods(&self, priors: &GenotypeProbs) -> GenotypeProbs {
+        let mut likelihoods = GenotypeProbs::zeros(self.n_alleles());
+        for i in 0..self.n_alleles() {
+            for j in 0..self.n_alleles() {
+                if priors.tab[i][j] != LogProb::ln_zero() {
+                    likelihoods.tab[i][j] = self.tab[i][j] - priors.tab[i][j];
+                }
+            }
+        }
+        likelihoods.normalize()
+    }
+
     pub fn sum(&self) -> LogProb {
         let mut total: LogProb = LogProb::ln_zero();
         for row in &self.tab {
@@ -211,9 +246,340 @@ pub fn possible_genotypes(alleles: &Vec<String>) -> Vec<Genotype> {
     return genotypes;
 }
 
+/// returns the possible genotypes for a site with the given ploidy. A haploid genotype with
+/// allele ```a``` is represented as ```Genotype(a, a)```, so only these are possible if ```ploidy == 1```.
+pub fn possible_genotypes_with_ploidy(alleles: &Vec<String>, ploidy: u8) -> Vec<Genotype> {
+    if ploidy == 1 {
+        (0..alleles.len())
+            .map(|a| Genotype(a as u8, a as u8))
+            .collect()
+    } else {
+        possible_genotypes(alleles)
+    }
+}
+
+/// The probability that a read with mapping quality ```mapq``` is mismapped. MAPQ 0 (the MAPQ of
+/// fragments without one) and 255 (unavailable in SAM) count as correctly mapped.
+pub fn mismapping_prob(mapq: u8) -> f64 {
+    if mapq == 0 || mapq == 255 {
+        0.0
+    } else {
+        10.0f64.powf(-(mapq as f64) / 10.0)
+    }
+}
+
+/// Mixes the probability ```p_miscall``` that an allele call is wrong with a random-allele model
+/// for mismapped reads: a read that is mismapped (with probability ```p_mismap```), e.g. from
+/// another copy of a segmental duplication, shows each of the ```n_alleles``` alleles with the
+/// same probability. The call is then wrong with probability
+///     (1 - m) * q + m * (n - 1) / n
+/// so that a read that is likely mismapped carries little evidence for any genotype.
+pub fn mismapping_adjusted_miscall(p_miscall: LogProb, p_mismap: f64, n_alleles: usize) -> LogProb {
+    if p_mismap <= 0.0 {
+        return p_miscall;
+    }
+    let p_random_miscall = (n_alleles as f64 - 1.0) / n_alleles as f64;
+    LogProb::ln_add_exp(
+        LogProb::from(Prob(1.0 - p_mismap)) + p_miscall,
+        LogProb::from(Prob(p_mismap * p_random_miscall)),
+    )
+}
+
+/// Genotype posteriors for the members of a parent-offspring trio after applying Mendelian
+/// inheritance priors
+pub struct TrioPosteriors {
+    pub child: GenotypeProbs,
+    pub father: GenotypeProbs,
+    pub mother: GenotypeProbs,
+    /// posterior probability that the child carries an allele that was not inherited from either parent
+    pub de_novo: LogProb,
+}
+
+/// Genotype posteriors for the members of a family (two parents and their children) after
+/// applying Mendelian inheritance priors
+pub struct FamilyPosteriors {
+    pub children: Vec<GenotypeProbs>,
+    pub father: GenotypeProbs,
+    pub mother: GenotypeProbs,
+    /// for each child, the posterior probability that it carries an allele that was not inherited
+    /// from either parent
+    pub de_novo: Vec<LogProb>,
+}
+
+/// sums the phased genotype probabilities into unphased genotype probabilities.
+/// ```unphased[i][j]``` for ```i <= j``` holds the total probability of the unordered genotype.
+fn unphased_probs(probs: &GenotypeProbs) -> Vec<Vec<LogProb>> {
+    let n = probs.n_alleles();
+    let mut unphased = vec![vec![LogProb::ln_zero(); n]; n];
+    for i in 0..n {
+        for j in i..n {
+            unphased[i][j] = if i == j {
+                probs.tab[i][i]
+            } else {
+                LogProb::ln_add_exp(probs.tab[i][j], probs.tab[j][i])
+            };
+        }
+    }
+    unphased
+}
+
+/// The probability that a parent with unphased genotype ```(p0, p1)``` transmits allele ```a```,
+/// allowing for a de novo mutation (to any allele with equal probability) at rate ```de_novo_rate```
+fn transmission_prob(a: usize, p0: usize, p1: usize, n_alleles: usize, de_novo_rate: f64) -> f64 {
+    let mut inherit = 0.0;
+    if a == p0 {
+        inherit += 0.5;
+    }
+    if a == p1 {
+        inherit += 0.5;
+    }
+    (1.0 - de_novo_rate) * inherit + de_novo_rate / n_alleles as f64
+}
+
+/// Calculates the genotype posteriors of a trio under a Mendelian inheritance model
+///
+/// This is ```calculate_family_posteriors``` for a family with a single child.
+///
+/// # Arguments
+/// - ```child_post```, ```father_post```, ```mother_post```: the normalized (phased) genotype posteriors
+///   of each sample at the same site
+/// - ```child_prior```: the (phased) genotype priors that were used to calculate ```child_post```
+/// - ```de_novo_rate```: the per-allele probability of a de novo mutation
+pub fn calculate_trio_posteriors(
+    child_post: &GenotypeProbs,
+    father_post: &GenotypeProbs,
+    mother_post: &GenotypeProbs,
+    child_prior: &GenotypeProbs,
+    de_novo_rate: f64,
+) -> TrioPosteriors {
+    let mut family = calculate_family_posteriors(
+        &[child_post],
+        &[child_prior],
+        father_post,
+        mother_post,
+        de_novo_rate,
+    );
+
+    TrioPosteriors {
+        child: family.children.remove(0),
+        father: family.father,
+        mother: family.mother,
+        de_novo: family.de_novo[0],
+    }
+}
+
+/// Calculates the genotype posteriors of two parents and their children under a Mendelian
+/// inheritance model
+///
+/// The genotype posteriors from each sample are treated as independent, with each child's
+/// population prior replaced by the probability of inheriting its genotype from the parents.
+/// The children are conditionally independent given the parents' genotypes, so the parents'
+/// priors enter the joint posterior once no matter how many children they have.
+/// The joint posterior is marginalized for each sample, and each sample's phased genotype
+/// probabilities are rescaled so that their unphased sums match the new marginal posteriors
+/// (this keeps any phase information from haplotype assembly).
+///
+/// # Arguments
+/// - ```child_posts```: the normalized (phased) genotype posteriors of each child
+/// - ```child_priors```: the (phased) genotype priors that were used to calculate each of ```child_posts```
+/// - ```father_post```, ```mother_post```: the normalized (phased) genotype posteriors of the parents
+///   at the same site
+/// - ```de_novo_rate```: the per-allele probability of a de novo mutation
+pub fn calculate_family_posteriors(
+    child_posts: &[&GenotypeProbs],
+    child_priors: &[&GenotypeProbs],
+    father_post: &GenotypeProbs,
+    mother_post: &GenotypeProbs,
+    de_novo_rate: f64,
+) -> FamilyPosteriors {
+    let n = father_post.n_alleles();
+    let n_children = child_posts.len();
+    assert!(mother_post.n_alleles() == n);
+    assert!(child_priors.len() == n_children);
+    assert!(child_posts.iter().all(|p| p.n_alleles() == n));
+
+    // the unordered genotypes (a, b) with a <= b
+    let mut genotypes: Vec<(usize, usize)> = vec![];
+    for a in 0..n {
+        for b in a..n {
+            genotypes.push((a, b));
+        }
+    }
+
+    let father_u = unphased_probs(father_post);
+    let mother_u = unphased_probs(mother_post);
+    let child_us: Vec<Vec<Vec<LogProb>>> = child_posts.iter().map(|p| unphased_probs(p)).collect();
+    // P(data_c | g_c) is proportional to post(g_c) / prior(g_c)
+    let child_liks: Vec<Vec<Vec<LogProb>>> = child_us
+        .iter()
+        .zip(child_priors.iter())
+        .map(|(child_u, prior)| {
+            let prior_u = unphased_probs(prior);
+            let mut lik = vec![vec![LogProb::ln_zero(); n]; n];
+            for &(c0, c1) in &genotypes {
+                lik[c0][c1] = child_u[c0][c1] - prior_u[c0][c1];
+            }
+            lik
+        })
+        .collect();
+
+    let mut child_margs = vec![vec![vec![LogProb::ln_zero(); n]; n]; n_children];
+    let mut father_marg = vec![vec![LogProb::ln_zero(); n]; n];
+    let mut mother_marg = vec![vec![LogProb::ln_zero(); n]; n];
+    let mut de_novo_terms: Vec<Vec<LogProb>> = vec![vec![]; n_children];
+    let mut all_terms: Vec<LogProb> = vec![];
+
+    for &(f0, f1) in &genotypes {
+        for &(m0, m1) in &genotypes {
+            // the terms P(g_c | parents) * P(data_c | g_c) of each child's genotypes, and their sum
+            let mut child_terms: Vec<Vec<LogProb>> = vec![];
+            let mut child_sums: Vec<LogProb> = vec![];
+            for lik in &child_liks {
+                let terms: Vec<LogProb> = genotypes
+                    .iter()
+                    .map(|&(c0, c1)| {
+                        // probability of the unordered child genotype given the parents
+                        let p_trans = if c0 == c1 {
+                            transmission_prob(c0, f0, f1, n, de_novo_rate)
+                                * transmission_prob(c0, m0, m1, n, de_novo_rate)
+                        } else {
+                            transmission_prob(c0, f0, f1, n, de_novo_rate)
+                                * transmission_prob(c1, m0, m1, n, de_novo_rate)
+                                + transmission_prob(c1, f0, f1, n, de_novo_rate)
+                                    * transmission_prob(c0, m0, m1, n, de_novo_rate)
+                        };
+                        lik[c0][c1] + LogProb::from(Prob(p_trans))
+                    })
+                    .collect();
+                child_sums.push(LogProb::ln_sum_exp(&terms));
+                child_terms.push(terms);
+            }
+
+            let parents = father_u[f0][f1] + mother_u[m0][m1];
+            let joint = child_sums.iter().fold(parents, |acc, &s| acc + s);
+            father_marg[f0][f1] = LogProb::ln_add_exp(father_marg[f0][f1], joint);
+            mother_marg[m0][m1] = LogProb::ln_add_exp(mother_marg[m0][m1], joint);
+            all_terms.push(joint);
+
+            for c in 0..n_children {
+                // the joint probability with every other child's genotype summed out
+                let others = (0..n_children)
+                    .filter(|&o| o != c)
+                    .fold(parents, |acc, o| acc + child_sums[o]);
+
+                for (&(c0, c1), &term) in genotypes.iter().zip(child_terms[c].iter()) {
+                    let p = others + term;
+                    child_margs[c][c0][c1] = LogProb::ln_add_exp(child_margs[c][c0][c1], p);
+
+                    // the child has an allele that neither parent could have transmitted
+                    let from_father = |a: usize| a == f0 || a == f1;
+                    let from_mother = |a: usize| a == m0 || a == m1;
+                    let mendelian = (from_father(c0) && from_mother(c1))
+                        || (from_father(c1) && from_mother(c0));
+                    if !mendelian {
+                        de_novo_terms[c].push(p);
+                    }
+                }
+            }
+        }
+    }
+
+    let total = LogProb::ln_sum_exp(&all_terms);
+    let de_novo = de_novo_terms
+        .iter()
+        .map(|terms| {
+            if terms.len() > 0 {
+                LogProb::ln_sum_exp(terms) - total
+            } else {
+                LogProb::ln_zero()
+            }
+        })
+        .collect();
+
+    // rescale the phased genotype probabilities so that they sum to the new unphased marginals
+    let rescale = |post: &GenotypeProbs, old_u: &Vec<Vec<LogProb>>, new_u: &Vec<Vec<LogProb>>| {
+        let mut new_post = GenotypeProbs::zeros(n);
+        for i in 0..n {
+            for j in 0..n {
+                let (a, b) = if i <= j { (i, j) } else { (j, i) };
+                let new_marg = new_u[a][b] - total;
+                new_post.tab[i][j] = if old_u[a][b] == LogProb::ln_zero() {
+                    // no phase information to keep, split the probability evenly
+                    if i == j {
+                        new_marg
+                    } else {
+                        new_marg + LogProb::from(Prob(0.5))
+                    }
+                } else {
+                    post.tab[i][j] - old_u[a][b] + new_marg
+                };
+            }
+        }
+        new_post
+    };
+
+    FamilyPosteriors {
+        children: (0..n_children)
+            .map(|c| rescale(child_posts[c], &child_us[c], &child_margs[c]))
+            .collect(),
+        father: rescale(father_post, &father_u, &father_marg),
+        mother: rescale(mother_post, &mother_u, &mother_marg),
+        de_novo: de_novo,
+    }
+}
+
+/// The names of the built-in genotype priors, for ```--genotype_prior```
+pub static GENOTYPE_PRIOR_NAMES: [&str; 3] = ["het-rate", "population-af", "uniform"];
+
+/// A prior on the genotype of a variant site, used by the genotyping (```call_genotypes```,
+/// ```call_potential_snvs```) and the pedigree priors.
+///
+/// Longshot selects the prior with ```--genotype_prior```; library users can implement their own.
+/// Only ```get_site_prior``` is required: the prior probability of the (phased) genotype at a site
+/// with the given alleles (the 0-th allele being the reference) and ploidy, and the population
+/// allele frequencies of the alleles if the site is known. Haploid genotypes are represented as
+/// ```Genotype(a, a)```, and any other genotype must have probability zero at a haploid site.
+pub trait GenotypePrior {
+    fn get_site_prior(
+        &self,
+        alleles: &Vec<String>,
+        genotype: Genotype,
+        ploidy: u8,
+        population_af: &Option<Vec<f64>>,
+    ) -> Result<LogProb>;
+
+    /// the priors of all the possible genotypes at a site
+    fn get_all_site_priors(
+        &self,
+        alleles: &Vec<String>,
+        ploidy: u8,
+        population_af: &Option<Vec<f64>>,
+    ) -> Result<GenotypeProbs> {
+        let mut priors = GenotypeProbs::zeros(alleles.len());
+
+        for g in possible_genotypes_with_ploidy(alleles, ploidy) {
+            priors.set(
+                g,
+                self.get_site_prior(alleles, g, ploidy, population_af)
+                    .chain_err(|| "Error while accessing genotype prior in get_all_site_priors()")?,
+            );
+        }
+
+        Ok(priors)
+    }
+
+    /// the priors of all the genotypes at a diploid site without population allele frequencies
+    fn get_all_priors(&self, alleles: &Vec<String>) -> Result<GenotypeProbs> {
+        self.get_all_site_priors(alleles, 2, &None)
+    }
+}
+
+/// The default genotype prior (```--genotype_prior het-rate```), from the rates of heterozygous
+/// and homozygous SNVs and indels, and the transition/transversion ratio
 #[derive(Clone)]
 pub struct GenotypePriors {
     priors_dict: HashMap<(char, (char, char)), LogProb>, // (ref_allele, (allele1, allele2)) -> P(G)
+    haploid_priors_dict: HashMap<(char, char), LogProb>, // (ref_allele, allele) -> P(G) for haploid sites
 }
 
 impl GenotypePriors {
@@ -362,14 +728,15 @@ impl GenotypePriors {
             }
         }
 	/*
-        eprintln!("{} GENOTYPE PRIORS:", SPACER);
-        eprintln!("{} REF G1/G2 PROB", SPACER);
+        info!("{} GENOTYPE PRIORS:", SPACER);
+        info!("{} REF G1/G2 PROB", SPACER);
         for (&(ref ra, (ref g1, ref g2)), &p) in &diploid_genotype_priors {
-            eprintln!("{} {} {}/{} {}", SPACER, ra, g1, g2, *Prob::from(p));
+            info!("{} {} {}/{} {}", SPACER, ra, g1, g2, *Prob::from(p));
         }*/
 
         Ok(GenotypePriors {
             priors_dict: diploid_genotype_priors,
+            haploid_priors_dict: haploid_genotype_priors,
         })
     }
 
@@ -381,6 +748,57 @@ impl GenotypePriors {
     // TODO: currently MNPs are going to be assigned the prior of the first SNV in the MNP
     // not ideal, but should suffice for the time being
     pub fn get_prior(&self, alleles: &Vec<String>, genotype: Genotype) -> Result<LogProb> {
+        let (ra, g0, g1) = self.prior_key(alleles, genotype)?;
+
+        let ln_half = LogProb::from(Prob(0.5));
+
+        match self.priors_dict.get(&(ra, (g0, g1))) {
+            Some(p) => {
+                if g0 != g1 {
+                    return Ok(ln_half + *p);
+                } else {
+                    return Ok(*p);
+                }
+            }
+            None => match self.priors_dict.get(&(ra, (g1, g0))) {
+                Some(p) => {
+                    if g0 != g1 {
+                        return Ok(ln_half + *p);
+                    } else {
+                        return Ok(*p);
+                    }
+                }
+                None => bail!(ErrorKind::GenotypeNotInGenotypePriorsError(ra, g0, g1)),
+            },
+        }
+    }
+
+    // returns the prior probability of a genotype at a site with the given ploidy.
+    // haploid genotypes are represented as Genotype(a, a), and any other genotype has
+    // probability zero at a haploid site.
+    pub fn get_prior_with_ploidy(
+        &self,
+        alleles: &Vec<String>,
+        genotype: Genotype,
+        ploidy: u8,
+    ) -> Result<LogProb> {
+        if ploidy != 1 {
+            return self.get_prior(alleles, genotype);
+        }
+        if genotype.0 != genotype.1 {
+            return Ok(LogProb::ln_zero());
+        }
+
+        let (ra, g0, _) = self.prior_key(alleles, genotype)?;
+        match self.haploid_priors_dict.get(&(ra, g0)) {
+            Some(p) => Ok(*p),
+            None => bail!(ErrorKind::InvalidHaploidGenotype(ra, g0)),
+        }
+    }
+
+    // converts a genotype into the key (ref_allele, allele1, allele2) used to look up its prior,
+    // where insertion and deletion alleles are represented as 'I' and 'D'
+    fn prior_key(&self, alleles: &Vec<String>, genotype: Genotype) -> Result<(char, char, char)> {
         let nth_char: fn(&String, usize) -> Result<char> = |s: &String, i: usize| {
             s.chars()
                 .nth(i)
@@ -425,41 +843,112 @@ impl GenotypePriors {
             }
         }
 
-        let ln_half = LogProb::from(Prob(0.5));
+        Ok((ra, g0, g1))
+    }
 
-        match self.priors_dict.get(&(ra, (g0, g1))) {
-            Some(p) => {
-                if g0 != g1 {
-                    return Ok(ln_half + *p);
-                } else {
-                    return Ok(*p);
-                }
-            }
-            None => match self.priors_dict.get(&(ra, (g1, g0))) {
-                Some(p) => {
-                    if g0 != g1 {
-                        return Ok(ln_half + *p);
-                    } else {
-                        return Ok(*p);
-                    }
-                }
-                None => bail!(ErrorKind::GenotypeNotInGenotypePriorsError(ra, g0, g1)),
-            },
+    pub fn get_all_priors_with_ploidy(
+        &self,
+        alleles: &Vec<String>,
+        ploidy: u8,
+    ) -> Result<GenotypeProbs> {
+        let mut priors = GenotypeProbs::zeros(alleles.len());
+
+        for g in possible_genotypes_with_ploidy(alleles, ploidy) {
+            priors.set(
+                g,
+                self.get_prior_with_ploidy(alleles, g, ploidy)
+                    .chain_err(|| "Error while accessing genotype prior in get_all_priors_with_ploidy()")?,
+            );
         }
+
+        Ok(priors)
     }
+}
 
-    pub fn get_all_priors(&self, alleles: &Vec<String>) -> Result<GenotypeProbs> {
-        let mut priors = GenotypeProbs::zeros(alleles.len());
+impl GenotypePrior for GenotypePriors {
+    // the population allele frequencies are ignored (see ```PopulationAfPrior```)
+    fn get_site_prior(
+        &self,
+        alleles: &Vec<String>,
+        genotype: Genotype,
+        ploidy: u8,
+        _population_af: &Option<Vec<f64>>,
+    ) -> Result<LogProb> {
+        self.get_prior_with_ploidy(alleles, genotype, ploidy)
+    }
+}
+
+/// A genotype prior that mixes another prior with the Hardy-Weinberg genotype frequencies at
+/// sites with population allele frequencies (```--genotype_prior population-af```):
+///     (1 - w) * P(G) + w * f(a0) * f(a1)
+/// where w is the population allele frequency weight (between 0.0 and 1.0). For haploid sites the
+/// Hardy-Weinberg frequency of Genotype(a, a) is f(a). Sites that are not known keep the prior of
+/// ```base```.
+pub struct PopulationAfPrior {
+    pub base: Box<dyn GenotypePrior>,
+    pub weight: f64,
+}
+
+impl GenotypePrior for PopulationAfPrior {
+    fn get_site_prior(
+        &self,
+        alleles: &Vec<String>,
+        genotype: Genotype,
+        ploidy: u8,
+        population_af: &Option<Vec<f64>>,
+    ) -> Result<LogProb> {
+        let prior = self
+            .base
+            .get_site_prior(alleles, genotype, ploidy, population_af)?;
 
-        for g0 in 0..alleles.len() {
-            for g1 in 0..alleles.len() {
-                priors.tab[g0][g1] = self
-                    .get_prior(alleles, Genotype(g0 as u8, g1 as u8))
-                    .chain_err(|| "Error while accessing genotype prior in get_all_priors()")?;
+        let afs = match *population_af {
+            Some(ref afs) if self.weight > 0.0 => afs,
+            _ => {
+                return Ok(prior);
             }
+        };
+        if ploidy == 1 && genotype.0 != genotype.1 {
+            return Ok(prior);
         }
 
-        Ok(priors)
+        let hwe_freq = if ploidy == 1 {
+            afs[genotype.0 as usize]
+        } else {
+            afs[genotype.0 as usize] * afs[genotype.1 as usize]
+        };
+        Ok(LogProb::ln_add_exp(
+            LogProb::from(Prob(1.0 - self.weight)) + prior,
+            LogProb::from(Prob(self.weight * hwe_freq)),
+        ))
+    }
+}
+
+/// A flat genotype prior (```--genotype_prior uniform```): every unphased genotype that is
+/// possible at the site has the same prior, and it is split evenly between the two phasings of a
+/// heterozygous genotype. The genotype posteriors are then proportional to the likelihoods.
+pub struct UniformPrior;
+
+impl GenotypePrior for UniformPrior {
+    fn get_site_prior(
+        &self,
+        alleles: &Vec<String>,
+        genotype: Genotype,
+        ploidy: u8,
+        _population_af: &Option<Vec<f64>>,
+    ) -> Result<LogProb> {
+        let n_alleles = alleles.len() as f64;
+        if ploidy == 1 {
+            if genotype.0 != genotype.1 {
+                return Ok(LogProb::ln_zero());
+            }
+            return Ok(LogProb::from(Prob(1.0 / n_alleles)));
+        }
+        let n_unphased = n_alleles * (n_alleles + 1.0) / 2.0;
+        if genotype.0 == genotype.1 {
+            Ok(LogProb::from(Prob(1.0 / n_unphased)))
+        } else {
+            Ok(LogProb::from(Prob(0.5 / n_unphased)))
+        }
     }
 }
 
@@ -480,9 +969,248 @@ mod tests {
         }
     }
 
-    //#[test]
-    //fn test_max_prob (){
-    //
-    //}
+    fn assert_prob_eq(p: LogProb, expected: f64) {
+        assert!((*Prob::from(p) - expected).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_max_prob() {
+        let (g, p) = generate_genotype_probs1().max_prob();
+        assert_eq!(g, Genotype(0, 1));
+        assert_prob_eq(p, 0.997);
+    }
+
+    #[test]
+    fn test_max_genotype_post() {
+        let probs = GenotypeProbs {
+            tab: vec![vec![lp(0.1), lp(0.3)], vec![lp(0.2), lp(0.4)]],
+        };
+        // unphased, 0/1 and 1/0 are added: 0.3 + 0.2 = 0.5 > 0.4
+        let (g, p) = probs.max_genotype_post(false, false);
+        assert_eq!(g, Genotype(0, 1));
+        assert_prob_eq(p, 0.5);
+        // phased, 1|1 is the most likely
+        let (g, p) = probs.max_genotype_post(true, false);
+        assert_eq!(g, Genotype(1, 1));
+        assert_prob_eq(p, 0.4);
+
+        let probs = GenotypeProbs {
+            tab: vec![vec![lp(0.7), lp(0.05)], vec![lp(0.05), lp(0.2)]],
+        };
+        let (g, _) = probs.max_genotype_post(false, false);
+        assert_eq!(g, Genotype(0, 0));
+        // forcing a non-reference genotype
+        let (g, p) = probs.max_genotype_post(false, true);
+        assert_eq!(g, Genotype(1, 1));
+        assert_prob_eq(p, 0.2);
+    }
+
+    #[test]
+    fn test_discovery_and_genotype_qual() {
+        // confidently variant, but uncertain whether heterozygous or homozygous
+        let probs = GenotypeProbs {
+            tab: vec![vec![lp(0.001), lp(0.25)], vec![lp(0.25), lp(0.499)]],
+        };
+        let possible = possible_genotypes(&vec!["A".to_string(), "C".to_string()]);
+        assert!((probs.discovery_qual() - 30.0).abs() < 1e-6);
+        // P(call wrong) = P(0/0) + P(1/1) = 0.5
+        let gq = probs.genotype_qual(Genotype(0, 1), &possible);
+        assert!((gq - *PHREDProb::from(Prob(0.5))).abs() < 1e-6);
+        // P(call wrong) = P(0/0) + P(0/1) + P(1/0) = 0.501
+        let gq = probs.genotype_qual(Genotype(1, 1), &possible);
+        assert!((gq - *PHREDProb::from(Prob(0.501))).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sum_and_normalize() {
+        let mut probs = GenotypeProbs {
+            tab: vec![vec![lp(0.01), lp(0.03)], vec![lp(0.02), lp(0.04)]],
+        };
+        assert_prob_eq(probs.sum(), 0.1);
+        // genotypes containing allele 1: 0.03 + 0.02 + 0.04
+        assert_prob_eq(probs.sum_genotypes_with_allele(1), 0.09);
+        assert_prob_eq(probs.sum_genotypes_with_allele(0), 0.06);
+
+        let norm = probs.normalize();
+        assert_prob_eq(norm.get(Genotype(0, 0)), 0.1);
+        assert_prob_eq(norm.get(Genotype(0, 1)), 0.3);
+        assert_prob_eq(norm.get(Genotype(1, 0)), 0.2);
+        assert_prob_eq(norm.get(Genotype(1, 1)), 0.4);
+    }
+
+    #[test]
+    fn test_diploid_priors() {
+        let priors = GenotypePriors::new(
+            LogProb::from(Prob(0.0005)),
+            LogProb::from(Prob(0.001)),
+            LogProb::from(Prob(0.00005)),
+            LogProb::from(Prob(0.0001)),
+            2.0,
+        )
+        .unwrap();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+        let p = priors.get_all_priors(&alleles).unwrap();
+
+        // A->G is a transition, which is ts_tv / (ts_tv + 2) = 1/2 of SNVs. The heterozygous
+        // prior is the product of the haploid priors of A and G, split evenly between the two
+        // phased genotypes 0|1 and 1|0.
+        let het = (1.0 - 0.0011) * (0.001 * 0.5);
+        assert_prob_eq(p.get(Genotype(0, 1)), het * 0.5);
+        assert_prob_eq(p.get(Genotype(1, 0)), het * 0.5);
+        assert_prob_eq(p.get(Genotype(1, 1)), 0.0005 * 0.5);
+        assert_prob_eq(p.get(Genotype(0, 0)), 1.0 - 0.00165);
+    }
+
+    #[test]
+    fn test_haploid_priors() {
+        let priors = GenotypePriors::new(
+            LogProb::from(Prob(0.0005)),
+            LogProb::from(Prob(0.001)),
+            LogProb::from(Prob(0.00005)),
+            LogProb::from(Prob(0.00001)),
+            0.5,
+        )
+        .unwrap();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+        let haploid = priors.get_all_priors_with_ploidy(&alleles, 1).unwrap();
+
+        // heterozygous genotypes are impossible at a haploid site
+        assert_eq!(haploid.get(Genotype(0, 1)), LogProb::ln_zero());
+        assert_eq!(haploid.get(Genotype(1, 0)), LogProb::ln_zero());
+        // A->G is a transition, with probability het_snv_rate * ts / (ts + 2)
+        assert!((*Prob::from(haploid.get(Genotype(1, 1))) - 0.001 * 0.2).abs() < 1e-10);
+        assert!((*Prob::from(haploid.get(Genotype(0, 0))) - (1.0 - 0.00101)).abs() < 1e-10);
+
+        // diploid priors are unchanged
+        let diploid = priors.get_all_priors_with_ploidy(&alleles, 2).unwrap();
+        let expected = priors.get_all_priors(&alleles).unwrap();
+        for g in possible_genotypes(&alleles) {
+            assert_eq!(diploid.get(g), expected.get(g));
+        }
+    }
+
+    #[test]
+    fn test_population_af_priors() {
+        let het_rate_priors = GenotypePriors::new(
+            LogProb::from(Prob(0.0005)),
+            LogProb::from(Prob(0.001)),
+            LogProb::from(Prob(0.00005)),
+            LogProb::from(Prob(0.0001)),
+            2.0,
+        )
+        .unwrap();
+        let alleles = vec!["A".to_string(), "G".to_string()];
+        let afs = Some(vec![0.6, 0.4]);
+        let default = het_rate_priors.get_all_priors_with_ploidy(&alleles, 2).unwrap();
+        let haploid = het_rate_priors.get_all_priors_with_ploidy(&alleles, 1).unwrap();
+
+        // the het-rate prior ignores the population allele frequencies
+        let p = het_rate_priors.get_all_site_priors(&alleles, 2, &afs).unwrap();
+        for g in possible_genotypes(&alleles) {
+            assert_prob_eq(p.get(g), *Prob::from(default.get(g)));
+        }
+
+        let priors = PopulationAfPrior {
+            base: Box::new(het_rate_priors),
+            weight: 0.5,
+        };
+        let p = priors.get_all_site_priors(&alleles, 2, &afs).unwrap();
+        let mix = |g: Genotype, hwe: f64| 0.5 * *Prob::from(default.get(g)) + 0.5 * hwe;
+        assert_prob_eq(p.get(Genotype(0, 0)), mix(Genotype(0, 0), 0.36));
+        assert_prob_eq(p.get(Genotype(0, 1)), mix(Genotype(0, 1), 0.24));
+        assert_prob_eq(p.get(Genotype(1, 0)), mix(Genotype(1, 0), 0.24));
+        assert_prob_eq(p.get(Genotype(1, 1)), mix(Genotype(1, 1), 0.16));
+        // sites that are not known keep the default priors
+        let p = priors.get_all_site_priors(&alleles, 2, &None).unwrap();
+        assert_prob_eq(
+            p.get(Genotype(1, 1)),
+            *Prob::from(default.get(Genotype(1, 1))),
+        );
+
+        // haploid sites use the allele frequencies directly
+        let p = priors.get_all_site_priors(&alleles, 1, &afs).unwrap();
+        let hap_mix = |g: Genotype, hwe: f64| 0.5 * *Prob::from(haploid.get(g)) + 0.5 * hwe;
+        assert_prob_eq(p.get(Genotype(0, 0)), hap_mix(Genotype(0, 0), 0.6));
+        assert_prob_eq(p.get(Genotype(1, 1)), hap_mix(Genotype(1, 1), 0.4));
+        assert_eq!(p.get(Genotype(0, 1)), LogProb::ln_zero());
+    }
+
+    #[test]
+    fn test_mismapping_adjusted_miscall() {
+        assert_eq!(mismapping_prob(0), 0.0);
+        assert_eq!(mismapping_prob(255), 0.0);
+        assert!((mismapping_prob(20) - 0.01).abs() < 1e-12);
+
+        let q = lp(0.001);
+        assert_eq!(mismapping_adjusted_miscall(q, 0.0, 2), q);
+        // a read mismapped with probability 0.1 shows the wrong one of 2 alleles half of the time
+        assert_prob_eq(mismapping_adjusted_miscall(q, 0.1, 2), 0.9 * 0.001 + 0.05);
+        assert_prob_eq(mismapping_adjusted_miscall(q, 0.1, 3), 0.9 * 0.001 + 0.1 * 2.0 / 3.0);
+        // a read that is surely mismapped carries no evidence for a biallelic site
+        assert_prob_eq(mismapping_adjusted_miscall(q, 1.0, 2), 0.5);
+    }
+
+    #[test]
+    fn test_uniform_priors() {
+        let alleles = vec!["A".to_string(), "G".to_string(), "T".to_string()];
+
+        // 6 unphased genotypes, the heterozygous ones split between their two phasings
+        let p = UniformPrior.get_all_priors(&alleles).unwrap();
+        assert_prob_eq(p.get(Genotype(0, 0)), 1.0 / 6.0);
+        assert_prob_eq(p.get(Genotype(2, 2)), 1.0 / 6.0);
+        assert_prob_eq(p.get(Genotype(0, 2)), 1.0 / 12.0);
+        assert_prob_eq(p.get(Genotype(2, 1)), 1.0 / 12.0);
+        p.assert_approx_normalized();
+
+        let p = UniformPrior.get_all_site_priors(&alleles, 1, &None).unwrap();
+        assert_prob_eq(p.get(Genotype(1, 1)), 1.0 / 3.0);
+        assert_eq!(p.get(Genotype(0, 1)), LogProb::ln_zero());
+        p.assert_approx_normalized();
+    }
+
+    fn biallelic_probs(p00: f64, p01: f64, p11: f64) -> GenotypeProbs {
+        GenotypeProbs {
+            tab: vec![
+                vec![lp(p00), lp(p01 / 2.0)],
+                vec![lp(p01 / 2.0), lp(p11)],
+            ],
+        }
+    }
+
+    #[test]
+    fn test_trio_posteriors_mendelian() {
+        // both parents confidently homozygous reference, child weakly heterozygous
+        let father = biallelic_probs(0.99998, 0.00002, 0.0);
+        let mother = biallelic_probs(0.99998, 0.00002, 0.0);
+        let child = biallelic_probs(0.4, 0.55, 0.05);
+        let child_prior = biallelic_probs(0.998, 0.001, 0.001);
+
+        let trio = calculate_trio_posteriors(&child, &father, &mother, &child_prior, 1e-8);
+
+        trio.child.assert_approx_normalized();
+        trio.father.assert_approx_normalized();
+        trio.mother.assert_approx_normalized();
+
+        // the child's heterozygous genotype is now very unlikely, since it would require a de novo mutation
+        let (g, _) = trio.child.max_genotype_post(false, false);
+        assert_eq!(g, Genotype(0, 0));
+        assert!(trio.de_novo < lp(0.01));
+    }
+
+    #[test]
+    fn test_trio_posteriors_de_novo() {
+        // both parents confidently homozygous reference, child confidently heterozygous
+        let father = biallelic_probs(1.0 - 2e-10, 1e-10, 1e-10);
+        let mother = biallelic_probs(1.0 - 2e-10, 1e-10, 1e-10);
+        let child_prior = biallelic_probs(0.998, 0.001, 0.001);
+        // posteriors for a child with strong evidence of heterozygosity
+        let child = biallelic_probs(1e-30, 1.0 - 2e-30, 1e-30);
+
+        let trio = calculate_trio_posteriors(&child, &father, &mother, &child_prior, 1e-8);
+
+        let (g, _) = trio.child.max_genotype_post(false, false);
+        assert_eq!(g, Genotype(0, 1));
+        assert!(trio.de_novo > lp(0.95));
+    }
 
 }
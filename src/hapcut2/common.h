@@ -51,6 +51,7 @@ struct BLOCK {
     int* slist; // ordered list of variants in this connected component
     int lastvar; // index of the first and last variants in this connected component
     int iters_since_improvement;
+    unsigned short rng_state[3]; // random number generator state (erand48) of the max-cut iterations of this component
 };
 
 struct edge {
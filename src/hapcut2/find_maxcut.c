@@ -234,7 +234,7 @@ float compute_goodcut(struct SNPfrags* snpfrag, char* hap, int* slist, struct BL
 
 
     // edge contraction algorithm: merge vertices until only two nodes left or total edge weight of graph is negative
-    int startnode = (int) (drand48() * N);
+    int startnode = (int) (erand48(component->rng_state) * N);
     if (startnode == N) startnode--;
     int secondnode = -1; // root of 2nd cluster initially not there
     // chose a positive edge to initialize the two clusters and run this algorithm $O(m)$ times for each block
@@ -268,8 +268,8 @@ float compute_goodcut(struct SNPfrags* snpfrag, char* hap, int* slist, struct BL
             secondnode = edgelist[iter].t;
             if (DEBUG) fprintf(stdout, " edge sel %d %d %f \n", startnode, secondnode, edgelist[iter].w);
         } else {
-            if (drand48() < 0.5) {
-                i = (int) (drand48() * totaledges - 0.0001);
+            if (erand48(component->rng_state) < 0.5) {
+                i = (int) (erand48(component->rng_state) * totaledges - 0.0001);
                 j = 0;
                 while (i >= snpfrag[slist[j]].tedges) {
                     i -= snpfrag[slist[j]].tedges;
@@ -280,7 +280,7 @@ float compute_goodcut(struct SNPfrags* snpfrag, char* hap, int* slist, struct BL
                 if (snpfrag[slist[j]].telist[i].w >= 1) continue;
             } else {
                 // find node with high MEC score, initialize as startnode
-                j = (int) (drand48() * N);
+                j = (int) (erand48(component->rng_state) * N);
                 if (j >= N) j = N - 1;
                 startnode = slist[j];
                 secondnode = -1;
@@ -334,7 +334,7 @@ float compute_goodcut(struct SNPfrags* snpfrag, char* hap, int* slist, struct BL
             } else if (secondnode < 0) secondnode = slist[snp_add];
             else // score is 0
             {
-                if (drand48() < 0.5) snpfrag[slist[snp_add]].parent = startnode;
+                if (erand48(component->rng_state) < 0.5) snpfrag[slist[snp_add]].parent = startnode;
                 else snpfrag[slist[snp_add]].parent = secondnode;
             }
             V--;
@@ -91,8 +91,14 @@ int hapcut2(char** fragmentbuffer, int fragments, int snps, char* HAP1, int* PS)
 
     slist = (int*) malloc(sizeof (int)*snps);
 
+    // each component has its own random number stream, seeded as by srand48(1), so that its
+    // phasing does not depend on the other components (e.g. on whether the contigs are phased
+    // together or in separate runs)
     for (k = 0; k < components; k++){
         clist[k].iters_since_improvement = 0;
+        clist[k].rng_state[0] = 0x330E;
+        clist[k].rng_state[1] = 1;
+        clist[k].rng_state[2] = 0;
     }
 
     // RUN THE MAX_CUT ALGORITHM ITERATIVELY TO IMPROVE LIKELIHOOD
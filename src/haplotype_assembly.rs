@@ -1,67 +1,120 @@
 //! Contains functions related to haplotype assembly, including a FFI wrapper function for HapCUT2,
 //! MEC criteria, haplotype read separation, etc.
+use bio::alphabets::dna;
 use bio::stats::{LogProb, PHREDProb, Prob};
 use errors::*;
-use hashbrown::HashMap;
+use genotype_probs::Genotype;
+use hashbrown::{HashMap, HashSet};
+use phasing_evaluation::TruthPhase;
 use rust_htslib::bam;
-use rust_htslib::bam::Read;
 use std::char::from_digit;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
 use util::*;
 use variants_and_fragments::*;
 
+/// The posterior probabilities that a fragment comes from haplotype 1 and haplotype 2.
+pub fn haplotype_posteriors(f: &Fragment) -> (LogProb, LogProb) {
+    // we store p_read_hap as ln-scaled f16s to save space. need to convert back.
+    let p_read_hap0 = LogProb(f64::from(f.p_read_hap[0]));
+    let p_read_hap1 = LogProb(f64::from(f.p_read_hap[1]));
+
+    let total: LogProb = LogProb::ln_add_exp(p_read_hap0, p_read_hap1);
+    (p_read_hap0 - total, p_read_hap1 - total)
+}
+
+/// The phase set of a fragment: the phase set with the most phased heterozygous variants that the
+/// fragment has a confident allele call for, or ```None``` if it has no such calls.
+fn fragment_phase_set(f: &Fragment, varlist: &VarList, ln_max_p_miscall: LogProb) -> Option<usize> {
+    let mut fragment_phase_sets = HashMap::new();
+    for call in f.calls.iter() {
+        let var = &varlist.lst[call.var_ix];
+
+        if var.genotype.0 != var.genotype.1
+            && var.phase_set.is_some()
+            && call.qual < ln_max_p_miscall
+        {
+            *fragment_phase_sets.entry(var.phase_set.unwrap()).or_insert(0) += 1;
+        }
+    }
+
+    let mut fps = None;
+    let mut max_count = 0;
+    for (&ps, &count) in fragment_phase_sets.iter() {
+        if count > max_count {
+            max_count = count;
+            fps = Some(ps);
+        }
+    }
+    fps
+}
+
+/// Assigns fragments (reads) to haplotypes based on their haplotype likelihoods
+///
+/// # Returns
+/// Returns a tuple ```(h1, h2, margins, error_rates, contigs)```. ```h1``` and ```h2``` map the read IDs
+/// assigned to haplotype 1 and 2 to the phase set they belong to. ```margins``` maps the read ID of
+/// every read that covers a phased heterozygous variant (whether or not it was assigned) to the
+/// PHRED-scaled log-likelihood margin between its best and second-best haplotype assignment.
+/// ```error_rates``` maps the read ID of every realigned read to its effective error rate.
+/// ```contigs``` maps the read ID of every read in ```margins``` to the TID of the contig of its
+/// phase set, since phase set IDs are only unique within a contig.
 pub fn separate_fragments_by_haplotype(
     flist: &Vec<Fragment>,
     varlist: &VarList,
     threshold: LogProb,
     max_p_miscall: f64,
-) -> Result<(HashMap<String, usize>, HashMap<String, usize>)> {
+) -> Result<(
+    HashMap<String, usize>,
+    HashMap<String, usize>,
+    HashMap<String, f64>,
+    HashMap<String, f64>,
+    HashMap<String, u32>,
+)> {
     //println!("Statistics for haplotype-separated reads (filtered reads only)");
     let mut h1 = HashMap::new();
     let mut h2 = HashMap::new();
+    let mut margins = HashMap::new();
+    let mut error_rates = HashMap::new();
+    let mut contigs = HashMap::new();
 
     let mut h1_count = 0;
     let mut h2_count = 0;
     let mut unassigned_count = 0;
     let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
     for ref f in flist {
-        // we store p_read_hap as ln-scaled f16s to save space. need to convert back.
-        let p_read_hap0 = LogProb(f64::from(f.p_read_hap[0]));
-        let p_read_hap1 = LogProb(f64::from(f.p_read_hap[1]));
-
-        let total: LogProb = LogProb::ln_add_exp(p_read_hap0, p_read_hap1);
-        let p_read_hap0: LogProb = p_read_hap0 - total;
-        let p_read_hap1: LogProb = p_read_hap1 - total;
+        let (p_read_hap0, p_read_hap1) = haplotype_posteriors(f);
 
-        if p_read_hap0 <= threshold && p_read_hap1 <= threshold {
-            unassigned_count += 1;
-            continue;
-        }
         if f.id.is_none() {
             bail!("Fragment without read ID found while separating reads by haplotype.");
         }
 
-        let mut fragment_phase_sets = HashMap::new();
-        for call in f.calls.iter() {
-            let var = &varlist.lst[call.var_ix];
-
-            if var.genotype.0 != var.genotype.1
-                && var.phase_set.is_some()
-                && call.qual < ln_max_p_miscall
-            {
-                *fragment_phase_sets.entry(var.phase_set.unwrap()).or_insert(0) += 1;
-            }
-        }
-        if fragment_phase_sets.is_empty() {
-            continue;
+        if let Some(error_rate) = f.error_rate {
+            error_rates.insert(f.id.clone().unwrap(), error_rate as f64);
         }
 
-        let mut fps = 0;
-        let mut max_count = 0;
-        for (&ps, &count) in fragment_phase_sets.iter() {
-            if count > max_count {
-                max_count = count;
-                fps = ps;
+        let fps = match fragment_phase_set(f, varlist, ln_max_p_miscall) {
+            Some(ps) => ps,
+            None => {
+                if p_read_hap0 <= threshold && p_read_hap1 <= threshold {
+                    unassigned_count += 1;
+                }
+                continue;
             }
+        };
+
+        // log-likelihood margin between the best and second-best haplotype, PHRED-scaled
+        let margin: f64 = 10.0 * (*p_read_hap0 - *p_read_hap1).abs() / 10.0_f64.ln();
+        margins.insert(f.id.clone().unwrap(), margin);
+        // the calls of a fragment all come from the contig of its (primary) alignment
+        contigs.insert(f.id.clone().unwrap(), varlist.lst[f.calls[0].var_ix].tid);
+
+        if p_read_hap0 <= threshold && p_read_hap1 <= threshold {
+            unassigned_count += 1;
+            continue;
         }
 
         if p_read_hap0 > threshold {
@@ -80,47 +133,63 @@ pub fn separate_fragments_by_haplotype(
     let h2_percent: f64 = 100.0 * h2_count as f64 / total;
     let unassigned_percent: f64 = 100.0 * unassigned_count as f64 / total;
 
-    eprintln!(
+    info!(
         "{}     {} reads ({:.2}%) assigned to haplotype 1",
         print_time(),
         h1_count,
         h1_percent
     );
-    eprintln!(
+    info!(
         "{}     {} reads ({:.2}%) assigned to haplotype 2",
         print_time(),
         h2_count,
         h2_percent
     );
-    eprintln!(
+    info!(
         "{}     {} reads ({:.2}%) unassigned.",
         print_time(),
         unassigned_count,
         unassigned_percent
     );
 
-    Ok((h1, h2))
+    Ok((h1, h2, margins, error_rates, contigs))
 }
 
 // tag reads with haplotype and write to output bam file
+// reads with a haplotype likelihood margin are also tagged with it (HM:f), whether or not they
+// were assigned to a haplotype.
+// reads that were realigned are tagged with their effective error rate against their best
+// haplotypes (ER:f), which downstream consensus and polishing tools can use to weight them.
+// the tags are assigned per molecule (read name), so alignments that were not used for phasing
+// but come from the same molecule as an assigned read (secondary and supplementary alignments,
+// low MAPQ alignments, and mates) are tagged as well, even if they don't overlap any variant.
+// the haplotype tags (HP, PS and HM) are only added to alignments on the contig of the read's
+// phase set, since a phase set says nothing about the haplotype of an alignment elsewhere.
+// QC-failed, duplicate and unmapped reads are written without tags.
 pub fn separate_bam_reads_by_haplotype<P: AsRef<std::path::Path>>(
     bamfile_name: &String,
     interval: &Option<GenomicInterval>,
     out_bam_file: P,
     h1: &HashMap<String, usize>,
     h2: &HashMap<String, usize>,
+    margins: &HashMap<String, f64>,
+    error_rates: &HashMap<String, f64>,
+    contigs: &HashMap<String, u32>,
     min_mapq: u8,
 ) -> Result<()> {
     let interval_lst: Vec<GenomicInterval> = get_interval_lst(bamfile_name, interval)
         .chain_err(|| "Error getting genomic interval list.")?;
 
     let mut bam_ix =
-        bam::IndexedReader::from_path(bamfile_name).chain_err(|| ErrorKind::IndexedBamOpenError)?;
+        open_indexed_bam(bamfile_name)?;
 
     let header = bam::Header::from_template(&bam_ix.header());
     let mut out_bam = bam::Writer::from_path(&out_bam_file, &header, bam::Format::BAM)
         .chain_err(|| ErrorKind::BamWriterOpenError(out_bam_file.as_ref().display().to_string()))?;
 
+    let mut tagged_count = 0;
+    let mut linked_count = 0;
+
     for iv in interval_lst {
         bam_ix
             .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
@@ -130,29 +199,45 @@ pub fn separate_bam_reads_by_haplotype<P: AsRef<std::path::Path>>(
             let mut record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
             record.remove_aux(b"HP"); // remove HP tag before setting it
             record.remove_aux(b"PS"); // remove PS tag as well
+            record.remove_aux(b"HM"); // and any haplotype margin tag
+            record.remove_aux(b"ER"); // and error rate tag
 
             let qname = u8_to_string(record.qname())?;
-            if record.is_quality_check_failed()
-                || record.is_duplicate()
-                || record.is_secondary()
-                || record.is_unmapped()
-                || record.mapq() < min_mapq
-                || record.is_supplementary()
-            {
+            if record.is_quality_check_failed() || record.is_duplicate() || record.is_unmapped() {
                 out_bam
                     .write(&record)
                     .chain_err(|| ErrorKind::BamRecordWriteError(qname))?;
                 continue; // write filtered reads to bam file and continue
             }
 
-            if h1.contains_key(&qname) {
-                record.push_aux(b"HP", &bam::record::Aux::Integer(1));
-                record.push_aux(b"PS",
-                    &bam::record::Aux::Integer(*h1.get(&qname).unwrap() as i64));
-            } else if h2.contains_key(&qname) {
-                record.push_aux(b"HP", &bam::record::Aux::Integer(2));
-                record.push_aux(b"PS",
-                    &bam::record::Aux::Integer(*h2.get(&qname).unwrap() as i64));
+            // alignments that were filtered out of phasing can still be linked to an assigned read,
+            // if they are on the contig of its phase set
+            let same_contig = contigs.get(&qname) == Some(&(record.tid() as u32));
+            let linked = record.is_secondary()
+                || record.is_supplementary()
+                || record.mapq() < min_mapq;
+            if same_contig && (h1.contains_key(&qname) || h2.contains_key(&qname)) {
+                if linked {
+                    linked_count += 1;
+                } else {
+                    tagged_count += 1;
+                }
+            }
+
+            if same_contig {
+                if let Some(&ps) = h1.get(&qname) {
+                    record.push_aux(b"HP", &bam::record::Aux::Integer(1));
+                    record.push_aux(b"PS", &bam::record::Aux::Integer(ps as i64));
+                } else if let Some(&ps) = h2.get(&qname) {
+                    record.push_aux(b"HP", &bam::record::Aux::Integer(2));
+                    record.push_aux(b"PS", &bam::record::Aux::Integer(ps as i64));
+                }
+                if let Some(margin) = margins.get(&qname) {
+                    record.push_aux(b"HM", &bam::record::Aux::Float(*margin));
+                }
+            }
+            if let Some(error_rate) = error_rates.get(&qname) {
+                record.push_aux(b"ER", &bam::record::Aux::Float(*error_rate));
             }
             out_bam
                 .write(&record)
@@ -160,6 +245,261 @@ pub fn separate_bam_reads_by_haplotype<P: AsRef<std::path::Path>>(
         }
     }
 
+    info!(
+        "{}     {} alignments tagged with a haplotype, including {} secondary, supplementary or low MAPQ alignments linked by read name.",
+        print_time(),
+        tagged_count + linked_count,
+        linked_count
+    );
+
+    Ok(())
+}
+
+/// Builds a bedGraph coverage track from reads that are added in sorted order, by sweeping over
+/// the read start and end positions
+struct CoverageTrack {
+    lines: Vec<String>,
+    chrom: String,
+    ends: BinaryHeap<Reverse<u32>>, // end positions (exclusive) of the reads covering pos
+    pos: u32,
+    // the last segment (start, end, depth), which is extended if the next one has the same depth
+    pending: Option<(u32, u32, usize)>,
+}
+
+impl CoverageTrack {
+    fn new() -> CoverageTrack {
+        CoverageTrack {
+            lines: vec![],
+            chrom: String::new(),
+            ends: BinaryHeap::new(),
+            pos: 0,
+            pending: None,
+        }
+    }
+
+    fn emit(&mut self, start: u32, end: u32, depth: usize) {
+        if start >= end || depth == 0 {
+            return;
+        }
+        if let Some((p_start, p_end, p_depth)) = self.pending {
+            if p_end == start && p_depth == depth {
+                self.pending = Some((p_start, end, depth));
+                return;
+            }
+        }
+        self.flush_pending();
+        self.pending = Some((start, end, depth));
+    }
+
+    fn flush_pending(&mut self) {
+        if let Some((start, end, depth)) = self.pending.take() {
+            self.lines
+                .push(format!("{}\t{}\t{}\t{}", self.chrom, start, end, depth));
+        }
+    }
+
+    // emits the coverage up to position 'to', removing the reads that end before it
+    fn advance(&mut self, to: u32) {
+        while let Some(&Reverse(end)) = self.ends.peek() {
+            if end > to {
+                break;
+            }
+            let (pos, depth) = (self.pos, self.ends.len());
+            self.emit(pos, end, depth);
+            self.pos = end;
+            self.ends.pop();
+        }
+        let (pos, depth) = (self.pos, self.ends.len());
+        self.emit(pos, to, depth);
+        self.pos = to;
+    }
+
+    fn add_read(&mut self, chrom: &str, start: u32, end: u32) {
+        if chrom != self.chrom {
+            self.advance(u32::max_value());
+            self.flush_pending();
+            self.chrom = chrom.to_string();
+            self.pos = start;
+        }
+        self.advance(start);
+        self.ends.push(Reverse(end));
+    }
+
+    fn finish(&mut self) -> Vec<String> {
+        self.advance(u32::max_value());
+        self.flush_pending();
+        self.lines.drain(..).collect()
+    }
+}
+
+/// Writes bedGraph tracks of the read depth of each haplotype, computed from the reads assigned
+/// to haplotype 1 and 2. Regions where one haplotype has much lower coverage than the other point
+/// to haplotype dropout, e.g. from a deletion or from allelic mapping bias.
+///
+/// The tracks are written to ```<out_prefix>.hap1.bedgraph``` and ```<out_prefix>.hap2.bedgraph```.
+/// The reference span of the primary alignments with at least ```min_mapq``` is counted, and
+/// positions without any coverage are omitted.
+pub fn write_haplotype_coverage(
+    bamfile_name: &String,
+    interval: &Option<GenomicInterval>,
+    out_prefix: &str,
+    h1: &HashMap<String, usize>,
+    h2: &HashMap<String, usize>,
+    min_mapq: u8,
+) -> Result<()> {
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bamfile_name, interval)
+        .chain_err(|| "Error getting genomic interval list.")?;
+    let target_names = parse_target_names(bamfile_name)?;
+    let mut bam_ix =
+        open_indexed_bam(bamfile_name)?;
+
+    let mut tracks = [CoverageTrack::new(), CoverageTrack::new()];
+    for iv in interval_lst {
+        bam_ix
+            .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+
+        for r in bam_ix.records() {
+            let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if record.is_quality_check_failed()
+                || record.is_duplicate()
+                || record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.mapq() < min_mapq
+            {
+                continue;
+            }
+            let qname = u8_to_string(record.qname())?;
+            let hap = if h1.contains_key(&qname) {
+                0
+            } else if h2.contains_key(&qname) {
+                1
+            } else {
+                continue;
+            };
+            // clip the reads to the interval, so that overlapping intervals are not counted twice
+            let start = (record.pos().max(0) as u32).max(iv.start_pos);
+            let end = (record.cigar().end_pos().max(0) as u32).min(iv.end_pos + 1);
+            if start < end {
+                tracks[hap].add_read(&target_names[iv.tid as usize], start, end);
+            }
+        }
+    }
+
+    for (h, track) in tracks.iter_mut().enumerate() {
+        let path_str = format!("{}.hap{}.bedgraph", out_prefix, h + 1);
+        let mut file = File::create(&path_str)
+            .chain_err(|| ErrorKind::CreateFileError(path_str.clone()))?;
+        writeln!(
+            file,
+            "track type=bedGraph name=\"haplotype {} coverage\" description=\"Depth of the reads assigned to haplotype {}\"",
+            h + 1,
+            h + 1
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path_str.clone()))?;
+        for line in track.finish() {
+            writeln!(file, "{}", line).chain_err(|| ErrorKind::FileWriteError(path_str.clone()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the reads assigned to haplotype 1, to haplotype 2 and to neither to the bgzip-compressed
+/// FASTQ files ```<out_prefix>.hap1.fastq.gz```, ```<out_prefix>.hap2.fastq.gz``` and
+/// ```<out_prefix>.unassigned.fastq.gz```, e.g. for haplotype-resolved de novo assembly.
+///
+/// Each read is written once, from its primary alignment, in its original orientation (reads
+/// aligned to the reverse strand are reverse-complemented back) and with its base qualities.
+/// Bases that are hard-clipped from the primary alignment are not in the BAM record, so they are
+/// missing from the FASTQ, and reads without base qualities get the lowest quality ('!'). The
+/// reads assigned to a haplotype have their phase set in the comment of the name line
+/// (```PS:i:<ps>```).
+pub fn write_haplotype_fastq(
+    bamfile_name: &String,
+    interval: &Option<GenomicInterval>,
+    out_prefix: &str,
+    h1: &HashMap<String, usize>,
+    h2: &HashMap<String, usize>,
+) -> Result<()> {
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bamfile_name, interval)
+        .chain_err(|| "Error getting genomic interval list.")?;
+    let mut bam_ix = open_indexed_bam(bamfile_name)?;
+
+    let paths: Vec<String> = ["hap1", "hap2", "unassigned"]
+        .iter()
+        .map(|name| format!("{}.{}.fastq.gz", out_prefix, name))
+        .collect();
+    let mut files: Vec<BgzfWriter> = paths
+        .iter()
+        .map(|path| BgzfWriter::create(path))
+        .collect::<Result<Vec<BgzfWriter>>>()?;
+    let mut counts = [0usize; 3];
+
+    for iv in interval_lst {
+        bam_ix
+            .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+
+        for r in bam_ix.records() {
+            let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if record.is_quality_check_failed()
+                || record.is_duplicate()
+                || record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+            {
+                continue;
+            }
+            let qname = u8_to_string(record.qname())?;
+            let (h, phase_set) = match (h1.get(&qname), h2.get(&qname)) {
+                (Some(&ps), _) => (0, Some(ps)),
+                (None, Some(&ps)) => (1, Some(ps)),
+                (None, None) => (2, None),
+            };
+
+            let mut seq: Vec<u8> = record.seq().as_bytes();
+            let mut qual: Vec<u8> = match record.qual().first() {
+                Some(&255) | None => vec![b'!'; seq.len()],
+                Some(_) => record.qual().iter().map(|&q| q + 33).collect(),
+            };
+            if record.is_reverse() {
+                seq = dna::revcomp(&seq);
+                qual.reverse();
+            }
+
+            let mut entry: Vec<u8> = format!("@{}", qname).into_bytes();
+            if let Some(ps) = phase_set {
+                entry.extend_from_slice(format!("\tPS:i:{}", ps).as_bytes());
+            }
+            entry.push(b'\n');
+            entry.extend_from_slice(&seq);
+            entry.extend_from_slice(b"\n+\n");
+            entry.extend_from_slice(&qual);
+            entry.push(b'\n');
+            files[h]
+                .write_all(&entry)
+                .chain_err(|| ErrorKind::FileWriteError(paths[h].clone()))?;
+            counts[h] += 1;
+        }
+    }
+
+    for (file, path) in files.iter_mut().zip(paths.iter()) {
+        file.flush()
+            .chain_err(|| ErrorKind::FileWriteError(path.clone()))?;
+    }
+    info!(
+        "{}     {} reads written to {}, {} to {} and {} unassigned reads to {}.",
+        print_time(),
+        counts[0],
+        paths[0],
+        counts[1],
+        paths[1],
+        counts[2],
+        paths[2]
+    );
+
     Ok(())
 }
 
@@ -272,6 +612,265 @@ pub fn generate_flist_buffer(
     Ok(buffer)
 }
 
+/// Writes the haplotype fragments to a file in the HapCUT2 fragment format
+///
+/// Each line describes a fragment with at least 2 allele calls: the number of blocks of
+/// consecutive variants, the fragment ID, and for each block the (1-based) index of its first
+/// variant followed by the alleles, and finally the quality of each allele call as a PHRED+33
+/// character. The variants are numbered in the order in which they are written to the output
+/// VCF (in ```interval```, and only non-reference genotypes unless
+/// ```print_reference_genotype``` is set), so that the file can be used together with the VCF by
+/// HapCUT2 or other tools, or combined with fragments from other technologies (e.g. Hi-C).
+/// Multiallelic variants are numbered but their allele calls are not written, since the format
+/// only supports alleles 0 and 1.
+///
+/// # Arguments
+/// - ```flist```: the haplotype fragments
+/// - ```varlist```: the genotyped variants
+/// - ```interval```: the region that variants are called in
+/// - ```print_reference_genotype```: whether variants with genotype 0/0 are written to the VCF
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a call to be written
+/// - ```output_file```: path of the fragment file to write
+///
+/// # Errors
+/// - ```CreateFileError```: error creating the fragment file
+/// - ```FileWriteError```: error writing to the fragment file
+pub fn print_fragment_file(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    print_reference_genotype: bool,
+    max_p_miscall: f64,
+    output_file: &String,
+) -> Result<()> {
+    // index of each variant in the output VCF, if it is written there
+    let mut vcf_ix: Vec<Option<usize>> = vec![None; varlist.lst.len()];
+    let mut phase_variant: Vec<bool> = vec![];
+    for (i, var) in varlist.lst.iter().enumerate() {
+        if let &Some(ref iv) = interval {
+            if var.tid != iv.tid
+                || var.pos0 < iv.start_pos as usize
+                || var.pos0 > iv.end_pos as usize
+            {
+                continue;
+            }
+        }
+        if !print_reference_genotype && var.genotype == Genotype(0, 0) {
+            continue;
+        }
+        vcf_ix[i] = Some(phase_variant.len());
+        phase_variant.push(var.alleles.len() == 2);
+    }
+
+    let vcf_flist: Vec<Fragment> = flist
+        .iter()
+        .map(|frag| {
+            let mut f = frag.clone();
+            f.calls = frag
+                .calls
+                .iter()
+                .filter_map(|c| {
+                    vcf_ix[c.var_ix].map(|ix| FragCall {
+                        var_ix: ix,
+                        ..*c
+                    })
+                })
+                .collect();
+            f
+        })
+        .collect();
+
+    let fragment_buffer = generate_flist_buffer(&vcf_flist, &phase_variant, max_p_miscall, false)
+        .chain_err(|| "Error generating fragment list buffer.")?;
+
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file =
+        File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+    for mut line_u8 in fragment_buffer {
+        // remove the null terminator used for HapCUT2
+        line_u8.pop();
+        writeln!(file, "{}", u8_to_string(&line_u8)?)
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the allele calls of the reads to a TSV file, one line per read and variant in the
+/// region, e.g. as raw evidence to train variant filters or to review discordant calls. The
+/// columns are the read name, the variant (chromosome, 1-based position, reference and alternate
+/// alleles), the allele called in the read, the log10 likelihood ratio of that allele against the
+/// other alleles, the haplotype the read is assigned to (1 or 2, as for the haplotype-tagged BAM,
+/// or . if unassigned) and its phase set.
+///
+/// The file is created with a header line, or the lines are appended to it if ```append``` is true.
+pub fn print_read_matrix(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    threshold: LogProb,
+    max_p_miscall: f64,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file = if append {
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?
+    } else {
+        let mut file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+        writeln!(file, "#read\tchrom\tpos\tref\talt\tallele\tllr\thap\tphase_set")
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        file
+    };
+
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    for f in flist {
+        let read_id = match f.id {
+            Some(ref id) => id,
+            None => bail!("Fragment without read ID found while writing the read allele matrix."),
+        };
+
+        let (p_read_hap0, p_read_hap1) = haplotype_posteriors(f);
+        let (hap, phase_set) = match fragment_phase_set(f, varlist, ln_max_p_miscall) {
+            Some(ps) if p_read_hap0 > threshold => ("1".to_string(), ps.to_string()),
+            Some(ps) if p_read_hap1 > threshold => ("2".to_string(), ps.to_string()),
+            _ => (".".to_string(), ".".to_string()),
+        };
+
+        for call in &f.calls {
+            let var = &varlist.lst[call.var_ix];
+            if let &Some(ref iv) = interval {
+                if var.tid != iv.tid
+                    || var.pos0 < iv.start_pos as usize
+                    || var.pos0 > iv.end_pos as usize
+                {
+                    continue;
+                }
+            }
+            let llr = (*call.one_minus_qual - *call.qual) / 10.0_f64.ln();
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}\t{}",
+                read_id,
+                varlist.target_names[var.tid as usize],
+                var.pos0 + 1,
+                var.alleles[0],
+                var.alleles[1..].join(","),
+                var.alleles[call.allele as usize],
+                llr,
+                hap,
+                phase_set
+            )
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The haplotype assignment of a read, for ```print_read_haplotypes```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadHaplotype {
+    pub read_id: String,
+    pub tid: u32,
+    pub phase_set: usize,
+    /// the haplotype the read is assigned to (1 or 2), or None if neither haplotype is more likely
+    /// than the threshold
+    pub hap: Option<u8>,
+    /// the posterior probability of the more likely haplotype
+    pub posterior: f64,
+}
+
+/// Returns the haplotype assignment of every read with a confident allele call at a phased
+/// heterozygous variant. The reads are assigned to a haplotype as for the haplotype-separated BAM
+/// files (see ```separate_fragments_by_haplotype```), but the posterior probability of the more
+/// likely haplotype is also returned for the reads that aren't assigned.
+pub fn read_haplotype_assignments(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    threshold: LogProb,
+    max_p_miscall: f64,
+) -> Result<Vec<ReadHaplotype>> {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut assignments: Vec<ReadHaplotype> = vec![];
+    for f in flist {
+        let read_id = match f.id {
+            Some(ref id) => id.clone(),
+            None => bail!("Fragment without read ID found while assigning reads to haplotypes."),
+        };
+        let phase_set = match fragment_phase_set(f, varlist, ln_max_p_miscall) {
+            Some(ps) => ps,
+            None => continue,
+        };
+        let (p_read_hap0, p_read_hap1) = haplotype_posteriors(f);
+        let hap = if p_read_hap0 > threshold {
+            Some(1)
+        } else if p_read_hap1 > threshold {
+            Some(2)
+        } else {
+            None
+        };
+        assignments.push(ReadHaplotype {
+            read_id: read_id,
+            tid: varlist.lst[f.calls[0].var_ix].tid,
+            phase_set: phase_set,
+            hap: hap,
+            posterior: (*p_read_hap0).max(*p_read_hap1).exp(),
+        });
+    }
+    Ok(assignments)
+}
+
+/// Writes the haplotype assignment of each read (see ```read_haplotype_assignments```) to a
+/// tab-separated file: the read name, the chromosome and phase set of the read, the haplotype it
+/// is assigned to (1 or 2, or . if unassigned) and the posterior probability of its more likely
+/// haplotype, so that ambiguous reads can be dropped with a stricter cutoff downstream.
+///
+/// The file is created with a header line, or the lines are appended to it if ```append``` is true.
+pub fn print_read_haplotypes(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    threshold: LogProb,
+    max_p_miscall: f64,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file = if append {
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?
+    } else {
+        let mut file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+        writeln!(file, "#read\tchrom\tphase_set\thap\tposterior")
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        file
+    };
+
+    for read in read_haplotype_assignments(flist, varlist, threshold, max_p_miscall)? {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{:.6}",
+            read.read_id,
+            varlist.target_names[read.tid as usize],
+            read.phase_set,
+            read.hap.map_or(".".to_string(), |h| h.to_string()),
+            read.posterior
+        )
+        .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+    Ok(())
+}
+
 extern "C" {
     fn hapcut2(
         fragmentbuffer: *const *const u8,
@@ -306,6 +905,265 @@ pub fn call_hapcut2(
     }
 }
 
+/// Weights the fragments by their mapping quality for haplotype assembly: each allele call is
+/// also wrong if the read is mismapped (e.g. a read from another copy of a segmental
+/// duplication), so that a read with a low mapping quality has less weight in the haplotype
+/// likelihood than a uniquely mapped read with the same alleles. The probability that a call is
+/// wrong becomes ```p + m - p * m```, where ```p``` is the probability that the allele call is
+/// wrong and ```m``` the probability that the read is mismapped. Fragments without a mapping
+/// quality (0 or 255) are not changed.
+pub fn mapq_weighted_fragments(flist: &Vec<Fragment>) -> Vec<Fragment> {
+    flist
+        .iter()
+        .map(|f| {
+            let mut f = f.clone();
+            if f.mapq == 0 || f.mapq == 255 {
+                return f;
+            }
+            let ln_mapped = LogProb::from(PHREDProb(f.mapq as f64)).ln_one_minus_exp();
+            for call in f.calls.iter_mut() {
+                // P(correct) = P(correct call) * P(correctly mapped)
+                call.one_minus_qual = call.one_minus_qual + ln_mapped;
+                call.qual = call.one_minus_qual.ln_one_minus_exp();
+            }
+            f
+        })
+        .collect()
+}
+
+/// The haplotype assembly algorithm, for ```--phaser```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phaser {
+    /// the max-cut heuristic of HapCUT2
+    MaxCut,
+    /// the exact weighted minimum error correction dynamic program, see ```wmec_phase```
+    Wmec,
+}
+
+/// The names of the haplotype assembly algorithms, for ```--phaser```, in the order of ```Phaser```
+pub static PHASER_NAMES: [&str; 2] = ["maxcut", "wmec"];
+
+impl Phaser {
+    pub fn from_name(name: &str) -> Option<Phaser> {
+        match name {
+            "maxcut" => Some(Phaser::MaxCut),
+            "wmec" => Some(Phaser::Wmec),
+            _ => None,
+        }
+    }
+}
+
+/// The known phase of the variants of a region (e.g. from trio or statistical phasing), used as
+/// soft constraints in haplotype assembly
+pub struct KnownPhase {
+    /// a pseudo-fragment for each known phase set, with a call of the allele on the first known
+    /// haplotype at each of its variants
+    pub fragments: Vec<Fragment>,
+    /// for each variant, the allele on the first known haplotype if its phase is known
+    pub hap1_alleles: Vec<Option<u8>>,
+}
+
+/// Matches the biallelic variants of a region to the phased heterozygous variants of a phased VCF
+///
+/// The variants of each known phase set become a pseudo-fragment for HapCUT2, like a read that
+/// covers the whole phase set. Each of its calls is wrong with probability ```1 - trust```, so
+/// that read evidence against the known phase can still override it.
+///
+/// # Arguments
+/// - ```varlist```: the variants of the region
+/// - ```known```: the phased alleles by (tid, 0-based position), see ```parse_truth_vcf```
+/// - ```trust```: the probability that the known phase of a variant is correct
+pub fn known_phase_constraints(
+    varlist: &VarList,
+    known: &HashMap<(u32, usize), TruthPhase>,
+    trust: f64,
+) -> KnownPhase {
+    let mut hap1_alleles: Vec<Option<u8>> = vec![None; varlist.lst.len()];
+    let mut phase_set_calls: HashMap<(u32, Option<i32>), Vec<FragCall>> = HashMap::new();
+    for (i, var) in varlist.lst.iter().enumerate() {
+        if var.alleles.len() != 2 {
+            continue;
+        }
+        let k = match known.get(&(var.tid, var.pos0)) {
+            Some(k) => k,
+            None => {
+                continue;
+            }
+        };
+        let allele = if k.haps == (var.alleles[0].clone(), var.alleles[1].clone()) {
+            0
+        } else if k.haps == (var.alleles[1].clone(), var.alleles[0].clone()) {
+            1
+        } else {
+            continue;
+        };
+        hap1_alleles[i] = Some(allele);
+        phase_set_calls
+            .entry((var.tid, k.phase_set))
+            .or_insert(vec![])
+            .push(FragCall::new(0, i, allele, 1.0 - trust));
+    }
+
+    let mut phase_sets: Vec<(u32, Option<i32>)> = phase_set_calls.keys().cloned().collect();
+    phase_sets.sort();
+    let mut fragments: Vec<Fragment> = vec![];
+    for (frag_ix, key) in phase_sets.iter().enumerate() {
+        let mut calls = phase_set_calls.remove(key).unwrap();
+        if calls.len() < 2 {
+            continue;
+        }
+        for call in calls.iter_mut() {
+            call.frag_ix = frag_ix;
+        }
+        let id = match key.1 {
+            Some(ps) => format!("known_phase_{}_{}", key.0, ps),
+            None => format!("known_phase_{}", key.0),
+        };
+        fragments.push(Fragment::new(Some(id), calls));
+    }
+
+    KnownPhase {
+        fragments,
+        hap1_alleles,
+    }
+}
+
+/// Builds soft phase constraints from the haplotype tags of the reads in the input BAM (e.g. from a
+/// previous haplotagging run or from strand-seq), like ```known_phase_constraints``` does from a
+/// phased VCF
+///
+/// The reads with the same phase set (PS) tag are combined into a pseudo-fragment for HapCUT2. At
+/// each biallelic variant, its allele on the first haplotype is the majority of the alleles of the
+/// HP:1 reads and the other alleles of the HP:2 reads, and variants without a majority are left
+/// out. Reads with an HP tag but without a PS tag are phased across the whole chromosome. Reads
+/// without an HP tag don't contribute to the constraints.
+///
+/// # Arguments
+/// - ```flist```: the fragments of the region, with the HP and PS tags of their reads
+/// - ```varlist```: the variants of the region
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a call to be used
+/// - ```trust```: the probability that the tagged phase of a variant is correct
+pub fn tagged_read_constraints(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    max_p_miscall: f64,
+    trust: f64,
+) -> KnownPhase {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    // (tid, PS tag) -> variant -> number of reads with allele 0 and 1 on the first haplotype
+    let mut votes: HashMap<(u32, Option<i32>), HashMap<usize, [usize; 2]>> = HashMap::new();
+    for f in flist {
+        let hp = match f.hp_tag {
+            Some(hp) => hp,
+            None => {
+                continue;
+            }
+        };
+        for call in &f.calls {
+            let var = &varlist.lst[call.var_ix];
+            if var.alleles.len() != 2 || call.qual >= ln_max_p_miscall {
+                continue;
+            }
+            let allele = if hp == 1 { call.allele } else { 1 - call.allele };
+            votes
+                .entry((var.tid, f.ps_tag))
+                .or_insert(HashMap::new())
+                .entry(call.var_ix)
+                .or_insert([0, 0])[allele as usize] += 1;
+        }
+    }
+
+    let mut phase_sets: Vec<(u32, Option<i32>)> = votes.keys().cloned().collect();
+    phase_sets.sort();
+    let mut hap1_alleles: Vec<Option<u8>> = vec![None; varlist.lst.len()];
+    let mut fragments: Vec<Fragment> = vec![];
+    for key in phase_sets {
+        let mut var_votes: Vec<(usize, [usize; 2])> =
+            votes[&key].iter().map(|(&v, &c)| (v, c)).collect();
+        var_votes.sort();
+        let mut calls: Vec<FragCall> = vec![];
+        for (var_ix, counts) in var_votes {
+            if counts[0] == counts[1] {
+                continue;
+            }
+            let allele = if counts[1] > counts[0] { 1 } else { 0 };
+            if hap1_alleles[var_ix].is_none() {
+                hap1_alleles[var_ix] = Some(allele);
+            }
+            calls.push(FragCall::new(fragments.len(), var_ix, allele, 1.0 - trust));
+        }
+        if calls.len() < 2 {
+            continue;
+        }
+        let id = match key.1 {
+            Some(ps) => format!("hp_tags_{}_{}", key.0, ps),
+            None => format!("hp_tags_{}", key.0),
+        };
+        fragments.push(Fragment::new(Some(id), calls));
+    }
+
+    KnownPhase {
+        fragments,
+        hap1_alleles,
+    }
+}
+
+/// Orients the phase blocks assembled by HapCUT2 to a known phase, so that the first haplotype of
+/// the output matches the first known haplotype: the haplotypes of a block are swapped if its
+/// first haplotype has the allele of the second known haplotype at more variants than that of
+/// the first.
+///
+/// # Arguments
+/// - ```hap1```: the first haplotype from HapCUT2 (```'0'```, ```'1'``` or ```'-'``` for each
+///   variant or marker)
+/// - ```phase_sets```: the block of each variant or marker from HapCUT2 (negative if unphased)
+/// - ```hap1_alleles```: the allele on the first known haplotype of each variant, if known
+///   (markers numbered after the variants have no known phase)
+///
+/// # Returns
+/// Returns the number of blocks that were swapped
+pub fn orient_phase_blocks(
+    hap1: &mut Vec<u8>,
+    phase_sets: &Vec<i32>,
+    hap1_alleles: &Vec<Option<u8>>,
+) -> usize {
+    // block -> (variants that agree with the known phase, variants that disagree)
+    let mut votes: HashMap<i32, (usize, usize)> = HashMap::new();
+    for (i, &allele) in hap1_alleles.iter().enumerate() {
+        if phase_sets[i] < 0 {
+            continue;
+        }
+        let h = match hap1[i] as char {
+            '0' => 0,
+            '1' => 1,
+            _ => {
+                continue;
+            }
+        };
+        if let Some(a) = allele {
+            let v = votes.entry(phase_sets[i]).or_insert((0, 0));
+            if h == a {
+                v.0 += 1;
+            } else {
+                v.1 += 1;
+            }
+        }
+    }
+
+    for i in 0..hap1.len() {
+        if let Some(&(agree, disagree)) = votes.get(&phase_sets[i]) {
+            if disagree > agree {
+                hap1[i] = match hap1[i] as char {
+                    '0' => '1' as u8,
+                    '1' => '0' as u8,
+                    _ => hap1[i],
+                };
+            }
+        }
+    }
+    votes.values().filter(|&&(agree, disagree)| disagree > agree).count()
+}
+
 pub fn calculate_mec(
     flist: &Vec<Fragment>,
     varlist: &mut VarList,
@@ -391,3 +1249,871 @@ pub fn calculate_mec(
 
     Ok(())
 }
+
+/// Calculates the phasing quality (```phase_qual```, the PHQ field) of each phased heterozygous
+/// variant
+///
+/// A variant that is called heterozygous but whose alleles do not follow the haplotypes of the
+/// reads (e.g. a systematic sequencing error, or a paralogous sequence variant) can still have a
+/// high QUAL. For each read covering a phased heterozygous variant, the read is assigned to the
+/// haplotypes using its calls at the other phased variants of the same block. The likelihood of
+/// the reads' alleles at the variant given those assignments is compared to the likelihood if the
+/// alleles were unlinked to the haplotypes (each read equally likely to carry either allele).
+/// Reads that contradict the phase (which also add to the variant's MEC) lower the score, and
+/// reads that consistently support both haplotypes raise it. Variants near the edge of a block are
+/// linked to the rest of the block by fewer reads, and so get a lower score.
+///
+/// # Arguments
+/// - ```flist```: the fragments
+/// - ```varlist```: the phased variants. ```phase_qual``` is set to the PHRED-scaled probability
+///   that the alleles are unlinked (with equal priors on linked and unlinked) for each phased
+///   heterozygous variant, and to ```None``` for the other variants.
+/// - ```max_p_miscall```: allele calls with a higher probability of error are not used
+pub fn calculate_phasing_quality(flist: &Vec<Fragment>, varlist: &mut VarList, max_p_miscall: f64) {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let ln_half = LogProb::from(Prob(0.5));
+    let is_phased_het = |var: &Var| var.phase_set.is_some() && var.genotype.0 != var.genotype.1;
+
+    // log likelihoods of the reads' alleles at each variant if linked to the haplotypes, or unlinked
+    let mut ll_linked: Vec<LogProb> = vec![LogProb::ln_one(); varlist.lst.len()];
+    let mut ll_unlinked: Vec<LogProb> = vec![LogProb::ln_one(); varlist.lst.len()];
+
+    for frag in flist {
+        let calls: Vec<&FragCall> = frag
+            .calls
+            .iter()
+            .filter(|c| c.qual < ln_max_p_miscall && is_phased_het(&varlist.lst[c.var_ix]))
+            .collect();
+
+        for call in &calls {
+            let var = &varlist.lst[call.var_ix];
+
+            // haplotype likelihoods of the read from its other calls in the same block
+            let mut p_hap: Vec<LogProb> = vec![ln_half, ln_half];
+            let mut linked = false;
+            for other in &calls {
+                let other_var = &varlist.lst[other.var_ix];
+                if other.var_ix == call.var_ix || other_var.phase_set != var.phase_set {
+                    continue;
+                }
+                linked = true;
+                for hap_ix in 0..2 {
+                    let hap_allele = if hap_ix == 0 {
+                        other_var.genotype.0
+                    } else {
+                        other_var.genotype.1
+                    };
+                    p_hap[hap_ix] = p_hap[hap_ix] + if other.allele == hap_allele {
+                        other.one_minus_qual
+                    } else {
+                        other.qual
+                    };
+                }
+            }
+            if !linked {
+                continue;
+            }
+            let p_read = LogProb::ln_add_exp(p_hap[0], p_hap[1]);
+
+            let p_allele = |hap_allele: u8| {
+                if call.allele == hap_allele {
+                    call.one_minus_qual
+                } else {
+                    call.qual
+                }
+            };
+            let (p_allele0, p_allele1) = (p_allele(var.genotype.0), p_allele(var.genotype.1));
+
+            ll_linked[call.var_ix] = ll_linked[call.var_ix]
+                + LogProb::ln_add_exp(
+                    p_hap[0] - p_read + p_allele0,
+                    p_hap[1] - p_read + p_allele1,
+                );
+            ll_unlinked[call.var_ix] = ll_unlinked[call.var_ix]
+                + LogProb::ln_add_exp(ln_half + p_allele0, ln_half + p_allele1);
+        }
+    }
+
+    for (i, var) in varlist.lst.iter_mut().enumerate() {
+        var.phase_qual = if is_phased_het(var) {
+            let p_unlinked = ll_unlinked[i] - LogProb::ln_add_exp(ll_linked[i], ll_unlinked[i]);
+            Some((*PHREDProb::from(p_unlinked)).min(MAX_VCF_QUAL))
+        } else {
+            None
+        };
+    }
+}
+
+/// The confidence of the phase between two consecutive phased heterozygous variants of a block
+#[derive(Clone, Debug)]
+pub struct PhaseJunction {
+    /// the phase set of the block, before any split
+    pub phase_set: usize,
+    /// the index of the variant on the left of the junction
+    pub left_ix: usize,
+    /// the index of the variant on the right of the junction
+    pub right_ix: usize,
+    /// the number of reads with allele calls on both sides of the junction
+    pub reads: usize,
+    /// PHRED-scaled probability that the phase is switched at the junction
+    pub qual: f64,
+    /// whether the block was split at the junction
+    pub split: bool,
+}
+
+/// Scores the junctions between consecutive phased heterozygous variants of each phase block
+///
+/// Each read with confident calls on both sides of a junction is assigned to the haplotypes
+/// separately on the left and on the right. The likelihood of the reads with the phase kept (the
+/// same haplotype on both sides) is compared to the likelihood with the phase switched, i.e. with
+/// the haplotypes of all variants on the right flipped. A junction that no read spans has a
+/// quality of 3 (equal likelihoods), even if the variants were joined into a block through other
+/// evidence (e.g. a known phase).
+///
+/// # Returns
+/// Returns the junctions of each block in order, with the blocks ordered by chromosome and phase
+/// set.
+pub fn phase_junctions(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    max_p_miscall: f64,
+) -> Vec<PhaseJunction> {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let is_phased_het = |var: &Var| var.phase_set.is_some() && var.genotype.0 != var.genotype.1;
+
+    // the phased heterozygous variants of each block in order, and the position of each variant
+    // in its block
+    let mut blocks: HashMap<(u32, usize), Vec<usize>> = HashMap::new();
+    let mut block_pos: Vec<usize> = vec![0; varlist.lst.len()];
+    for (i, var) in varlist.lst.iter().enumerate() {
+        if is_phased_het(var) {
+            let block = blocks
+                .entry((var.tid, var.phase_set.unwrap()))
+                .or_insert(vec![]);
+            block_pos[i] = block.len();
+            block.push(i);
+        }
+    }
+
+    // log likelihoods of the reads spanning the junction to the right of each variant, with the
+    // phase kept and switched
+    let mut ll_keep: Vec<LogProb> = vec![LogProb::ln_one(); varlist.lst.len()];
+    let mut ll_switch: Vec<LogProb> = vec![LogProb::ln_one(); varlist.lst.len()];
+    let mut n_reads: Vec<usize> = vec![0; varlist.lst.len()];
+
+    for frag in flist {
+        // (block, position in block, ln P(call | haplotype 1), ln P(call | haplotype 2))
+        let mut calls: Vec<((u32, usize), usize, LogProb, LogProb)> = frag
+            .calls
+            .iter()
+            .filter(|c| c.qual < ln_max_p_miscall && is_phased_het(&varlist.lst[c.var_ix]))
+            .map(|c| {
+                let var = &varlist.lst[c.var_ix];
+                let p_allele = |hap_allele: u8| {
+                    if c.allele == hap_allele {
+                        c.one_minus_qual
+                    } else {
+                        c.qual
+                    }
+                };
+                (
+                    (var.tid, var.phase_set.unwrap()),
+                    block_pos[c.var_ix],
+                    p_allele(var.genotype.0),
+                    p_allele(var.genotype.1),
+                )
+            })
+            .collect();
+        calls.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        let mut start = 0;
+        while start < calls.len() {
+            let mut end = start + 1;
+            while end < calls.len() && calls[end].0 == calls[start].0 {
+                end += 1;
+            }
+            let block_calls = &calls[start..end];
+            start = end;
+
+            let block = &blocks[&block_calls[0].0];
+            let total0 = LogProb(block_calls.iter().map(|c| *c.2).sum());
+            let total1 = LogProb(block_calls.iter().map(|c| *c.3).sum());
+            let (mut left0, mut left1) = (LogProb::ln_one(), LogProb::ln_one());
+            for k in 0..block_calls.len() - 1 {
+                left0 = left0 + block_calls[k].2;
+                left1 = left1 + block_calls[k].3;
+                let (right0, right1) = (total0 - left0, total1 - left1);
+                let keep = LogProb::ln_add_exp(left0 + right0, left1 + right1);
+                let switch = LogProb::ln_add_exp(left0 + right1, left1 + right0);
+                // the read spans all junctions between this call and the next
+                for p in block_calls[k].1..block_calls[k + 1].1 {
+                    ll_keep[block[p]] = ll_keep[block[p]] + keep;
+                    ll_switch[block[p]] = ll_switch[block[p]] + switch;
+                    n_reads[block[p]] += 1;
+                }
+            }
+        }
+    }
+
+    let mut block_keys: Vec<(u32, usize)> = blocks.keys().cloned().collect();
+    block_keys.sort();
+    let mut junctions: Vec<PhaseJunction> = vec![];
+    for key in block_keys {
+        let block = &blocks[&key];
+        for p in 0..block.len() - 1 {
+            let i = block[p];
+            let p_switch = ll_switch[i] - LogProb::ln_add_exp(ll_keep[i], ll_switch[i]);
+            junctions.push(PhaseJunction {
+                phase_set: key.1,
+                left_ix: i,
+                right_ix: block[p + 1],
+                reads: n_reads[i],
+                qual: (*PHREDProb::from(p_switch)).min(MAX_VCF_QUAL),
+                split: false,
+            });
+        }
+    }
+    junctions
+}
+
+/// Splits the phase blocks at the junctions with a quality below ```min_qual``` (see
+/// ```phase_junctions```). The variants to the right of a split get the position of the first of
+/// them as their phase set, like HapCUT2 blocks, and ```split``` is set for the junction.
+///
+/// # Returns
+/// Returns the number of junctions where a block was split
+pub fn split_phase_blocks(
+    varlist: &mut VarList,
+    junctions: &mut Vec<PhaseJunction>,
+    min_qual: f64,
+) -> usize {
+    let mut n_split = 0;
+    // the phase set of the current part of the block, after the splits so far
+    let mut current: Option<(u32, usize, usize)> = None;
+    for junction in junctions.iter_mut() {
+        let tid = varlist.lst[junction.left_ix].tid;
+        let mut phase_set = match current {
+            Some((t, ps, new_ps)) if t == tid && ps == junction.phase_set => new_ps,
+            _ => junction.phase_set,
+        };
+        if junction.qual < min_qual {
+            junction.split = true;
+            n_split += 1;
+            phase_set = varlist.lst[junction.right_ix].pos0 + 1;
+        }
+        varlist.lst[junction.right_ix].phase_set = Some(phase_set);
+        current = Some((tid, junction.phase_set, phase_set));
+    }
+    n_split
+}
+
+/// Writes the phase junctions (see ```phase_junctions```) to a TSV file, with a line per junction
+/// whose left variant is in ```interval```: the chromosome, the positions of the variants on
+/// either side, the phase set of the block before any split, the number of reads that span the
+/// junction, its quality and whether the block was split there.
+///
+/// The file is created with a header line, or the lines are appended to it if ```append``` is true.
+pub fn print_phase_junctions(
+    junctions: &Vec<PhaseJunction>,
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file = if append {
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?
+    } else {
+        let mut file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+        writeln!(file, "#chrom\tleft_pos\tright_pos\tphase_set\treads\tqual\tsplit")
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        file
+    };
+
+    for junction in junctions {
+        let left = &varlist.lst[junction.left_ix];
+        if let &Some(ref iv) = interval {
+            if left.tid != iv.tid
+                || left.pos0 < iv.start_pos as usize
+                || left.pos0 > iv.end_pos as usize
+            {
+                continue;
+            }
+        }
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{:.2}\t{}",
+            varlist.target_names[left.tid as usize],
+            left.pos0 + 1,
+            varlist.lst[junction.right_ix].pos0 + 1,
+            junction.phase_set,
+            junction.reads,
+            junction.qual,
+            if junction.split { "yes" } else { "no" }
+        )
+        .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Counts the reads assigned to each haplotype at each phased heterozygous variant
+/// (```hap_counts```, the HDP and HAD fields)
+///
+/// The reads are assigned to the haplotypes as for ```separate_fragments_by_haplotype```
+/// (```--out_bam```): a read is assigned to haplotype 1 or 2 if its posterior probability for that
+/// haplotype is above ```threshold```, and it is only counted at the variants in its own phase set.
+/// The depth counts every assigned read that has an allele call at the variant, and the allele
+/// counts only its unambiguous calls, like AD.
+///
+/// # Arguments
+/// - ```flist```: the fragments, with their final haplotype posteriors
+/// - ```varlist```: the phased variants. ```hap_counts``` is set for each phased heterozygous
+///   variant, and to ```None``` for the other variants.
+/// - ```threshold```: the minimum posterior probability to assign a read to a haplotype
+/// - ```max_p_miscall```: allele calls with a higher probability of error are ambiguous
+pub fn count_haplotype_alleles(
+    flist: &Vec<Fragment>,
+    varlist: &mut VarList,
+    threshold: LogProb,
+    max_p_miscall: f64,
+) {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+
+    for var in varlist.lst.iter_mut() {
+        var.hap_counts = if var.phase_set.is_some() && var.genotype.0 != var.genotype.1 {
+            Some(HaplotypeCounts::new(var.alleles.len()))
+        } else {
+            None
+        };
+    }
+
+    for f in flist {
+        let (p_read_hap0, p_read_hap1) = haplotype_posteriors(f);
+        let hap_ix = if p_read_hap0 > threshold {
+            0
+        } else if p_read_hap1 > threshold {
+            1
+        } else {
+            continue;
+        };
+        let fps = match fragment_phase_set(f, varlist, ln_max_p_miscall) {
+            Some(ps) => ps,
+            None => continue,
+        };
+
+        for call in f.calls.iter() {
+            let var = &mut varlist.lst[call.var_ix];
+            if var.phase_set != Some(fps) {
+                continue;
+            }
+            if let Some(ref mut counts) = var.hap_counts {
+                counts.depth[hap_ix] += 1;
+                if call.qual < ln_max_p_miscall {
+                    counts.allele_counts[hap_ix][call.allele as usize] += 1;
+                }
+            }
+        }
+    }
+}
+
+/// What happens to the heterozygous variants that are the only variant of their phase block
+/// (```--singleton_phase```)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SingletonPhase {
+    /// keep the phased genotype and the phase set
+    Keep,
+    /// write an unphased genotype
+    Unphase,
+    /// keep the phased genotype, but without a phase set
+    DropPhaseSet,
+    /// join the variant to a phase block linked to it by the reads, or unphase it
+    Rescue,
+}
+
+/// The names of the singleton phase block modes, for ```--singleton_phase```, in the order of
+/// ```SingletonPhase```
+pub static SINGLETON_PHASE_NAMES: [&str; 4] = ["keep", "unphase", "drop-ps", "rescue"];
+
+impl SingletonPhase {
+    pub fn from_name(name: &str) -> Option<SingletonPhase> {
+        match name {
+            "keep" => Some(SingletonPhase::Keep),
+            "unphase" => Some(SingletonPhase::Unphase),
+            "drop-ps" => Some(SingletonPhase::DropPhaseSet),
+            "rescue" => Some(SingletonPhase::Rescue),
+            _ => None,
+        }
+    }
+}
+
+/// Finds the phase block that the reads link a singleton variant to, and the phase of the
+/// variant in it
+///
+/// Each read with a call at the variant is assigned to the haplotypes of a phase block using its
+/// confident calls at the phased heterozygous variants of the block, as for
+/// ```calculate_phasing_quality```. The likelihood of the reads' alleles at the variant is
+/// compared between the two phases of its genotype, for each block that the reads link it to. All
+/// of the calls at the variant are used, including the ambiguous calls that HapCUT2 does not see.
+///
+/// # Returns
+/// Returns ```(phase_set, flip, qual)``` for the block where the best phase is the most confident:
+/// ```flip``` is true if the genotype should be flipped, and ```qual``` is the PHRED-scaled
+/// probability that the phase is wrong. Returns ```None``` if no read links the variant to a
+/// block.
+fn rescue_singleton(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    var_ix: usize,
+    singleton_blocks: &HashSet<(u32, usize)>,
+    ln_max_p_miscall: LogProb,
+) -> Option<(usize, bool, f64)> {
+    let ln_half = LogProb::from(Prob(0.5));
+    let var = &varlist.lst[var_ix];
+    let is_block_het = |v: &Var| {
+        v.genotype.0 != v.genotype.1
+            && match v.phase_set {
+                Some(ps) => !singleton_blocks.contains(&(v.tid, ps)),
+                None => false,
+            }
+    };
+
+    // phase set -> log likelihoods of the reads' alleles at the variant with its genotype as it
+    // is, and flipped
+    let mut ll: HashMap<usize, (LogProb, LogProb)> = HashMap::new();
+    for frag in flist {
+        let call = match frag.calls.iter().find(|c| c.var_ix == var_ix) {
+            Some(call) => call,
+            None => continue,
+        };
+
+        // haplotype likelihoods of the read in each block, from its confident calls
+        let mut p_hap: HashMap<usize, (LogProb, LogProb)> = HashMap::new();
+        for other in frag.calls.iter() {
+            let other_var = &varlist.lst[other.var_ix];
+            if other.qual >= ln_max_p_miscall || !is_block_het(other_var) {
+                continue;
+            }
+            let p = p_hap
+                .entry(other_var.phase_set.unwrap())
+                .or_insert((ln_half, ln_half));
+            let p_allele = |hap_allele: u8| {
+                if other.allele == hap_allele {
+                    other.one_minus_qual
+                } else {
+                    other.qual
+                }
+            };
+            p.0 = p.0 + p_allele(other_var.genotype.0);
+            p.1 = p.1 + p_allele(other_var.genotype.1);
+        }
+
+        let p_allele = |hap_allele: u8| {
+            if call.allele == hap_allele {
+                call.one_minus_qual
+            } else {
+                call.qual
+            }
+        };
+        let (p_allele0, p_allele1) = (p_allele(var.genotype.0), p_allele(var.genotype.1));
+        for (&ps, &(p_hap0, p_hap1)) in p_hap.iter() {
+            let p_read = LogProb::ln_add_exp(p_hap0, p_hap1);
+            let (p_hap0, p_hap1) = (p_hap0 - p_read, p_hap1 - p_read);
+            let l = ll
+                .entry(ps)
+                .or_insert((LogProb::ln_one(), LogProb::ln_one()));
+            l.0 = l.0 + LogProb::ln_add_exp(p_hap0 + p_allele0, p_hap1 + p_allele1);
+            l.1 = l.1 + LogProb::ln_add_exp(p_hap0 + p_allele1, p_hap1 + p_allele0);
+        }
+    }
+
+    let mut best: Option<(usize, bool, f64)> = None;
+    for (&ps, &(ll_keep, ll_flip)) in ll.iter() {
+        let total = LogProb::ln_add_exp(ll_keep, ll_flip);
+        let flip = ll_flip > ll_keep;
+        let p_wrong = if flip { ll_keep } else { ll_flip } - total;
+        let qual = (*PHREDProb::from(p_wrong)).min(MAX_VCF_QUAL);
+        // ties go to the first phase block, so that the result does not depend on hash order
+        let better = match best {
+            Some((best_ps, _, best_qual)) => {
+                qual > best_qual || (qual == best_qual && ps < best_ps)
+            }
+            None => true,
+        };
+        if better {
+            best = Some((ps, flip, qual));
+        }
+    }
+    best
+}
+
+/// Handles the heterozygous variants that are the only variant of their phase block
+///
+/// HapCUT2 gives every heterozygous variant that it phases a phase set, even if no read links it
+/// to another variant, and such a "block" only confuses downstream tools. Depending on ```mode```,
+/// the variants of singleton blocks are written with an unphased genotype, written phased but
+/// without a phase set (```phased_no_ps```), or rescued: joined to the phase block that the reads
+/// link them to if their phase in it has a PHRED-scaled quality of at least ```min_rescue_qual```
+/// (see ```rescue_singleton```), and unphased otherwise.
+///
+/// # Returns
+/// Returns ```(singletons, rescued)```, the number of singleton variants and the number of them
+/// that were joined to another phase block.
+pub fn resolve_singleton_phase_blocks(
+    flist: &Vec<Fragment>,
+    varlist: &mut VarList,
+    mode: SingletonPhase,
+    min_rescue_qual: f64,
+    max_p_miscall: f64,
+) -> (usize, usize) {
+    // (tid, phase set) -> number of variants in the phase block
+    let mut block_sizes: HashMap<(u32, usize), usize> = HashMap::new();
+    for var in varlist.lst.iter() {
+        if let Some(ps) = var.phase_set {
+            *block_sizes.entry((var.tid, ps)).or_insert(0) += 1;
+        }
+    }
+    let singleton_blocks: HashSet<(u32, usize)> = block_sizes
+        .into_iter()
+        .filter(|&(_, n)| n == 1)
+        .map(|(block, _)| block)
+        .collect();
+    let singletons: Vec<usize> = varlist
+        .lst
+        .iter()
+        .enumerate()
+        .filter(|&(_, var)| match var.phase_set {
+            Some(ps) => singleton_blocks.contains(&(var.tid, ps)),
+            None => false,
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    if mode == SingletonPhase::Keep {
+        return (singletons.len(), 0);
+    }
+
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut rescued = 0;
+    for &i in singletons.iter() {
+        let rescue = if mode == SingletonPhase::Rescue {
+            rescue_singleton(flist, varlist, i, &singleton_blocks, ln_max_p_miscall)
+                .filter(|&(_, _, qual)| qual >= min_rescue_qual)
+        } else {
+            None
+        };
+
+        let var = &mut varlist.lst[i];
+        match rescue {
+            Some((ps, flip, _)) => {
+                rescued += 1;
+                var.phase_set = Some(ps);
+                if flip {
+                    var.genotype = Genotype(var.genotype.1, var.genotype.0);
+                    let n = var.genotype_post.n_alleles();
+                    for a in 0..n {
+                        for b in 0..a {
+                            let p = var.genotype_post.tab[a][b];
+                            var.genotype_post.tab[a][b] = var.genotype_post.tab[b][a];
+                            var.genotype_post.tab[b][a] = p;
+                        }
+                    }
+                }
+            }
+            None => {
+                var.phase_set = None;
+                var.phased_no_ps = mode == SingletonPhase::DropPhaseSet;
+            }
+        }
+    }
+    (singletons.len(), rescued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phased_varlist(n: usize) -> VarList {
+        let mut lst: Vec<Var> = vec![];
+        for i in 0..n {
+            let mut var = Var::new(0, i * 100, vec!["A".to_string(), "G".to_string()]);
+            var.genotype = Genotype(0, 1);
+            var.phase_set = Some(1);
+            lst.push(var);
+        }
+        VarList::new(lst, vec!["chr1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_calculate_phasing_quality() {
+        let mut varlist = phased_varlist(6);
+        varlist.lst[5].genotype = Genotype(0, 0);
+        varlist.lst[5].phase_set = None;
+
+        // variants 0-2 follow the haplotypes, variant 3 is unlinked to them, and variant 4 is
+        // linked to the rest of the block by a single read
+        let flist = fragments_from_strings(
+            &[
+                "000", "000", "000", "111", "111", "111", "0000", "0001", "1110", "1111",
+                "--1-1", "----00",
+            ],
+            0.01,
+        );
+        calculate_phasing_quality(&flist, &mut varlist, 0.1);
+
+        let phq: Vec<f64> = varlist.lst[0..5]
+            .iter()
+            .map(|v| v.phase_qual.unwrap())
+            .collect();
+        assert!(phq[0] > 20.0 && phq[1] > 20.0 && phq[2] > 20.0);
+        assert!(phq[3] < 1.0);
+        assert!(phq[4] > phq[3] && phq[4] < 10.0);
+        assert_eq!(varlist.lst[5].phase_qual, None);
+    }
+
+    #[test]
+    fn test_coverage_track() {
+        let mut track = CoverageTrack::new();
+        track.add_read("chr1", 100, 200);
+        track.add_read("chr1", 150, 200);
+        track.add_read("chr1", 200, 250);
+        track.add_read("chr1", 300, 400);
+        track.add_read("chr2", 0, 50);
+
+        assert_eq!(
+            track.finish(),
+            vec![
+                "chr1\t100\t150\t1",
+                "chr1\t150\t200\t2",
+                "chr1\t200\t250\t1",
+                "chr1\t300\t400\t1",
+                "chr2\t0\t50\t1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_known_phase_constraints() {
+        let mut varlist = phased_varlist(4);
+        varlist.lst[3].alleles = vec!["A".to_string(), "T".to_string()];
+        let mut known: HashMap<(u32, usize), TruthPhase> = HashMap::new();
+        let phase = |h0: &str, h1: &str, ps: i32| TruthPhase {
+            haps: (h0.to_string(), h1.to_string()),
+            phase_set: Some(ps),
+        };
+        known.insert((0, 0), phase("A", "G", 1));
+        known.insert((0, 100), phase("G", "A", 1));
+        // a single variant of a phase set, and a variant with other alleles
+        known.insert((0, 200), phase("G", "A", 2));
+        known.insert((0, 300), phase("A", "C", 1));
+
+        let constraints = known_phase_constraints(&varlist, &known, 0.99);
+        assert_eq!(constraints.hap1_alleles, vec![Some(0), Some(1), Some(1), None]);
+        assert_eq!(constraints.fragments.len(), 1);
+        let calls = &constraints.fragments[0].calls;
+        assert_eq!(calls.len(), 2);
+        assert_eq!((calls[0].var_ix, calls[0].allele), (0, 0));
+        assert_eq!((calls[1].var_ix, calls[1].allele), (1, 1));
+        assert!((*Prob::from(calls[0].qual) - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tagged_read_constraints() {
+        let varlist = phased_varlist(4);
+        let mut flist =
+            fragments_from_strings(&["011-", "100-", "-1--", "---0", "---0", "01--"], 0.01);
+        // reads 0-2 are in phase set 7 and read 2 disagrees with the others, reads 3 and 4 are
+        // in phase set 9 and disagree at variant 3, and read 5 is untagged
+        for (f, &(hp, ps)) in flist.iter_mut().zip(&[(1, 7), (2, 7), (2, 7), (1, 9), (2, 9)]) {
+            f.hp_tag = Some(hp);
+            f.ps_tag = Some(ps);
+        }
+
+        let constraints = tagged_read_constraints(&flist, &varlist, 0.1, 0.99);
+        assert_eq!(constraints.hap1_alleles, vec![Some(0), Some(1), Some(1), None]);
+        assert_eq!(constraints.fragments.len(), 1);
+        let calls: Vec<(usize, u8)> = constraints.fragments[0]
+            .calls
+            .iter()
+            .map(|c| (c.var_ix, c.allele))
+            .collect();
+        assert_eq!(calls, vec![(0, 0), (1, 1), (2, 1)]);
+        assert!((*Prob::from(constraints.fragments[0].calls[0].qual) - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mapq_weighted_fragments() {
+        let mut flist = fragments_from_strings(&["01", "01", "01"], 0.01);
+        flist[0].mapq = 60;
+        flist[1].mapq = 10;
+        let weighted = mapq_weighted_fragments(&flist);
+        let p_wrong = |f: &Fragment| *Prob::from(f.calls[1].qual);
+        assert!((p_wrong(&weighted[0]) - 0.01).abs() < 1e-5);
+        assert!((p_wrong(&weighted[1]) - (0.01 + 0.1 - 0.001)).abs() < 1e-9);
+        assert!((*Prob::from(weighted[1].calls[1].one_minus_qual) - 0.99 * 0.9).abs() < 1e-9);
+        // unknown mapping quality
+        assert_eq!(p_wrong(&weighted[2]), p_wrong(&flist[2]));
+    }
+
+    #[test]
+    fn test_phase_junctions() {
+        let mut varlist = phased_varlist(5);
+        // the reads support the phase between variants 0 to 2 and switch it between variants 2
+        // and 3, and no read spans the junction between variants 3 and 4
+        let flist = fragments_from_strings(
+            &["00---", "00---", "11---", "-00--", "-11--", "--01-", "--10-"],
+            0.01,
+        );
+        let mut junctions = phase_junctions(&flist, &varlist, 0.1);
+        let summary: Vec<(usize, usize, usize)> = junctions
+            .iter()
+            .map(|j| (j.left_ix, j.right_ix, j.reads))
+            .collect();
+        assert_eq!(summary, vec![(0, 1, 3), (1, 2, 2), (2, 3, 2), (3, 4, 0)]);
+        assert!(junctions[0].qual > 30.0 && junctions[1].qual > 20.0);
+        assert!(junctions[2].qual < 1.0);
+        assert!((junctions[3].qual - 3.01).abs() < 0.01);
+
+        assert_eq!(split_phase_blocks(&mut varlist, &mut junctions, 10.0), 2);
+        let phase_sets: Vec<Option<usize>> = varlist.lst.iter().map(|v| v.phase_set).collect();
+        assert_eq!(phase_sets, vec![Some(1), Some(1), Some(1), Some(301), Some(401)]);
+        let split: Vec<bool> = junctions.iter().map(|j| j.split).collect();
+        assert_eq!(split, vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn test_orient_phase_blocks() {
+        // two blocks and an unphased variant. the first block disagrees with the known phase at
+        // two of its three variants, the second block only at a variant with unknown phase
+        let mut hap1: Vec<u8> = b"101-10".to_vec();
+        let phase_sets = vec![0, 0, 0, -1, 4, 4];
+        let hap1_alleles = vec![Some(0), Some(1), Some(1), Some(1), None, Some(0)];
+        assert_eq!(orient_phase_blocks(&mut hap1, &phase_sets, &hap1_alleles), 1);
+        assert_eq!(hap1, b"010-10".to_vec());
+    }
+
+    #[test]
+    fn test_count_haplotype_alleles() {
+        let mut varlist = phased_varlist(3);
+        varlist.lst[2].genotype = Genotype(0, 0);
+        varlist.lst[2].phase_set = None;
+
+        let mut flist = fragments_from_strings(&["000", "111", "10", "11"], 0.01);
+        // the last read has an ambiguous call at variant 1
+        flist.push(Fragment::new(
+            Some("f4".to_string()),
+            vec![FragCall::new(4, 0, 1, 0.01), FragCall::new(4, 1, 1, 0.3)],
+        ));
+        let hap0 = [LogProb::from(Prob(0.99)), LogProb::from(Prob(0.01))];
+        let hap1 = [hap0[1], hap0[0]];
+        // the fourth read is not assigned to either haplotype
+        let unassigned = [hap0[1], hap0[1]];
+        for (f, p_read_hap) in flist.iter_mut().zip(&[hap0, hap1, hap0, unassigned, hap1]) {
+            f.p_read_hap = *p_read_hap;
+        }
+
+        count_haplotype_alleles(&flist, &mut varlist, LogProb::from(Prob(0.9)), 0.1);
+
+        let counts = varlist.lst[0].hap_counts.clone().unwrap();
+        assert_eq!(counts.depth, [2, 2]);
+        assert_eq!(counts.allele_counts, [vec![1, 1], vec![0, 2]]);
+        let counts = varlist.lst[1].hap_counts.clone().unwrap();
+        assert_eq!(counts.depth, [2, 2]);
+        assert_eq!(counts.allele_counts, [vec![2, 0], vec![0, 1]]);
+        assert_eq!(varlist.lst[2].hap_counts, None);
+    }
+
+    #[test]
+    fn test_read_haplotype_assignments() {
+        let mut varlist = phased_varlist(3);
+        varlist.lst[2].genotype = Genotype(0, 0);
+        varlist.lst[2].phase_set = None;
+
+        // the last read only covers the homozygous variant
+        let mut flist = fragments_from_strings(&["00", "11", "10", "--0"], 0.01);
+        let hap0 = [LogProb::from(Prob(0.99)), LogProb::from(Prob(0.01))];
+        let hap1 = [hap0[1], hap0[0]];
+        let ambiguous = [LogProb::from(Prob(0.3)), LogProb::from(Prob(0.7))];
+        for (f, p_read_hap) in flist.iter_mut().zip(&[hap0, hap1, ambiguous, hap0]) {
+            f.p_read_hap = *p_read_hap;
+        }
+
+        let assignments =
+            read_haplotype_assignments(&flist, &varlist, LogProb::from(Prob(0.9)), 0.1).unwrap();
+        let haps: Vec<(&str, usize, Option<u8>)> = assignments
+            .iter()
+            .map(|r| (r.read_id.as_str(), r.phase_set, r.hap))
+            .collect();
+        assert_eq!(
+            haps,
+            vec![("f0", 1, Some(1)), ("f1", 1, Some(2)), ("f2", 1, None)]
+        );
+        assert!((assignments[0].posterior - 0.99).abs() < 1e-9);
+        assert!((assignments[2].posterior - 0.7).abs() < 1e-9);
+
+        // with a lower threshold, the ambiguous read is assigned to its more likely haplotype
+        let assignments =
+            read_haplotype_assignments(&flist, &varlist, LogProb::from(Prob(0.6)), 0.1).unwrap();
+        assert_eq!(assignments[2].hap, Some(2));
+    }
+
+    #[test]
+    fn test_resolve_singleton_phase_blocks() {
+        // a phase block of two variants, a singleton variant that the reads link to the block,
+        // and a singleton variant that no read links to another variant
+        let singleton_varlist = || {
+            let mut varlist = phased_varlist(4);
+            varlist.lst[2].phase_set = Some(201);
+            varlist.lst[3].phase_set = Some(301);
+            varlist
+        };
+        let flist = fragments_from_strings(&["001", "001", "110", "110", "---1"], 0.01);
+
+        let mut varlist = singleton_varlist();
+        let resolved =
+            resolve_singleton_phase_blocks(&flist, &mut varlist, SingletonPhase::Keep, 20.0, 0.1);
+        assert_eq!(resolved, (2, 0));
+        assert_eq!(varlist.lst[2].phase_set, Some(201));
+
+        let mut varlist = singleton_varlist();
+        resolve_singleton_phase_blocks(&flist, &mut varlist, SingletonPhase::Unphase, 20.0, 0.1);
+        assert_eq!(varlist.lst[1].phase_set, Some(1));
+        assert_eq!(varlist.lst[2].phase_set, None);
+        assert_eq!(varlist.lst[3].phase_set, None);
+        assert!(!varlist.lst[2].phased_no_ps);
+
+        let mut varlist = singleton_varlist();
+        let mode = SingletonPhase::DropPhaseSet;
+        resolve_singleton_phase_blocks(&flist, &mut varlist, mode, 20.0, 0.1);
+        assert_eq!(varlist.lst[2].phase_set, None);
+        assert!(varlist.lst[2].phased_no_ps);
+        assert!(!varlist.lst[1].phased_no_ps);
+
+        // the rescued variant is on the other haplotype than its genotype says
+        let mut varlist = singleton_varlist();
+        let resolved =
+            resolve_singleton_phase_blocks(&flist, &mut varlist, SingletonPhase::Rescue, 20.0, 0.1);
+        assert_eq!(resolved, (2, 1));
+        assert_eq!(varlist.lst[2].phase_set, Some(1));
+        assert_eq!(varlist.lst[2].genotype, Genotype(1, 0));
+        assert_eq!(varlist.lst[3].phase_set, None);
+        assert!(!varlist.lst[3].phased_no_ps);
+
+        // too few reads for the minimum quality
+        let mut varlist = singleton_varlist();
+        let resolved = resolve_singleton_phase_blocks(
+            &flist,
+            &mut varlist,
+            SingletonPhase::Rescue,
+            100.0,
+            0.1,
+        );
+        assert_eq!(resolved, (2, 0));
+        assert_eq!(varlist.lst[2].phase_set, None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Longshot library
+//! Exposes the realignment-based extraction of haplotype fragments (the alleles of each read at a
+//! set of variants) to other Rust tools, see the ```api``` module, and the ```VcfRecord``` builder
+//! used to write the VCF records of the longshot outputs.
+//! With the ```python``` feature (```cargo build --release --features python```), it also exposes
+//! the Pair-HMM realignment and the genotype likelihood calculation to Python, so that methods can
+//! be prototyped against the exact models used by the longshot binary.
+//! With the ```benchmarks``` feature, it also exposes the alignment functions and the simulated
+//! fixtures that the micro-benchmarks in ```benches/``` measure.
+
+#![allow(dead_code)]
+// `error_chain!` can recurse deeply
+#![recursion_limit = "1024"]
+
+// external crates
+extern crate bio;
+extern crate chrono;
+extern crate clap;
+extern crate core;
+extern crate rand;
+extern crate rust_htslib;
+#[macro_use]
+extern crate error_chain;
+extern crate fishers_exact;
+extern crate hashbrown;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "python")]
+extern crate pyo3;
+extern crate memmap;
+extern crate url;
+
+// import modules
+mod allele_fractions;
+mod alphabet;
+mod api;
+mod call_genotypes;
+mod call_potential_snvs;
+mod callable_regions;
+mod checkpoint;
+mod debug_realign;
+mod errors;
+mod estimate_alignment_parameters;
+mod estimate_read_coverage;
+mod extract_fragments;
+mod fragment_simulation;
+mod fragment_store;
+mod genotype_probs;
+mod haplotype_assembly;
+mod liftover;
+mod local_assembly;
+mod methylation;
+mod mnv;
+mod mosaic;
+mod pedigree;
+mod phasing_evaluation;
+mod polyploid_phasing;
+mod population_af;
+mod presets;
+mod print_output;
+#[cfg(feature = "python")]
+mod python;
+mod read_simulation;
+mod realignment;
+mod region_queue;
+mod run_stats;
+mod site_filters;
+mod soft_clip_clusters;
+mod somatic;
+mod sv_breakpoints;
+mod telemetry;
+mod util;
+mod variants_and_fragments;
+mod vcf_annotation;
+mod wmec;
+
+pub use api::{
+    extract_fragments, haplotype_posteriors, Allele, Fragment, FragmentOptions, HaplotypeOptions,
+    HaplotypeScorer, Variant,
+};
+pub use errors::{Error, ErrorKind, Result};
+pub use print_output::VcfRecord;
+
+#[cfg(feature = "benchmarks")]
+pub use read_simulation::simulate_read_window;
+#[cfg(feature = "benchmarks")]
+pub use realignment::{
+    forward_algorithm_non_numerically_stable, forward_algorithm_numerically_stable,
+    viterbi_max_scoring_alignment, AlignmentParameters, EmissionProbs, LnAlignmentParameters,
+    TransitionProbs,
+};
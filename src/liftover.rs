@@ -0,0 +1,466 @@
+//! Lifting the phased output VCF over to another reference assembly with a UCSC chain file.
+//!
+//! Each variant is mapped through the highest-scoring chain with an aligned block that contains
+//! its reference allele. Generic liftover tools map every record independently, so a phase block
+//! can end up spanning a rearrangement between the assemblies, where the order of the variants
+//! (and the meaning of the phase set) is broken. Here, a phase block is only kept together while
+//! its consecutive variants map through the same chain, which is collinear by construction. When
+//! the chain changes, a new phase set is started; a single variant left in its own phase set is
+//! unphased. Phase sets are renamed to the lifted position of their first variant.
+//!
+//! SNVs on chains to the reverse strand are reverse-complemented. Other variants on
+//! reverse-strand chains can't be lifted without the new reference sequence (to left-align and
+//! re-anchor them), so they are dropped together with the variants that don't map.
+
+use errors::*;
+use hashbrown::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use util::*;
+
+/// An ungapped aligned block of a chain: ```[t_start, t_end)``` on the source reference is
+/// aligned to ```[q_start, q_start + t_end - t_start)``` on the target reference (on the strand
+/// of the chain).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChainBlock {
+    t_start: u64,
+    t_end: u64,
+    q_start: u64,
+    chain_ix: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Chain {
+    score: f64,
+    q_name: String,
+    q_size: u64,
+    reverse: bool,
+}
+
+/// The alignment of a source reference to a target reference from a UCSC chain file
+pub struct ChainMap {
+    chains: Vec<Chain>,
+    /// the blocks on each source contig, sorted by start position
+    blocks: HashMap<String, Vec<ChainBlock>>,
+    /// the length of the longest block on each source contig, to bound the search for blocks
+    max_block_len: HashMap<String, u64>,
+    /// the target contigs and their lengths, in the order of the chain file
+    pub target_contigs: Vec<(String, u64)>,
+}
+
+impl ChainMap {
+    /// Parses a chain file, e.g. hg19ToHg38.over.chain
+    pub fn parse<R: BufRead>(reader: R) -> Result<ChainMap> {
+        let mut chains: Vec<Chain> = vec![];
+        let mut blocks: HashMap<String, Vec<ChainBlock>> = HashMap::new();
+        let mut target_contigs: Vec<(String, u64)> = vec![];
+        let mut target_contig_seen: HashMap<String, bool> = HashMap::new();
+
+        // the current chain: source contig, and the positions of the next block
+        let mut t_name = String::new();
+        let mut t_pos: u64 = 0;
+        let mut q_pos: u64 = 0;
+        let mut in_chain = false;
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.chain_err(|| "Error reading chain file.")?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+            let parse = |s: &str| -> Result<u64> {
+                s.parse::<u64>()
+                    .chain_err(|| format!("Invalid number {} on line {} of chain file.", s, i + 1))
+            };
+
+            if fields[0] == "chain" {
+                ensure!(
+                    fields.len() >= 12,
+                    "Invalid chain header on line {} of chain file.",
+                    i + 1
+                );
+                ensure!(
+                    fields[4] == "+",
+                    "Chains on the reverse strand of the source reference are not supported (line {} of chain file).",
+                    i + 1
+                );
+                let score: f64 = fields[1]
+                    .parse()
+                    .chain_err(|| format!("Invalid chain score on line {} of chain file.", i + 1))?;
+                t_name = fields[2].to_string();
+                t_pos = parse(fields[5])?;
+                q_pos = parse(fields[10])?;
+                let q_name = fields[7].to_string();
+                let q_size = parse(fields[8])?;
+                if !target_contig_seen.contains_key(&q_name) {
+                    target_contig_seen.insert(q_name.clone(), true);
+                    target_contigs.push((q_name.clone(), q_size));
+                }
+                chains.push(Chain {
+                    score: score,
+                    q_name: q_name,
+                    q_size: q_size,
+                    reverse: fields[9] == "-",
+                });
+                in_chain = true;
+            } else {
+                ensure!(
+                    in_chain && (fields.len() == 1 || fields.len() == 3),
+                    "Invalid alignment block on line {} of chain file.",
+                    i + 1
+                );
+                let size = parse(fields[0])?;
+                blocks
+                    .entry(t_name.clone())
+                    .or_insert(vec![])
+                    .push(ChainBlock {
+                        t_start: t_pos,
+                        t_end: t_pos + size,
+                        q_start: q_pos,
+                        chain_ix: chains.len() - 1,
+                    });
+                if fields.len() == 3 {
+                    t_pos += size + parse(fields[1])?;
+                    q_pos += size + parse(fields[2])?;
+                } else {
+                    in_chain = false;
+                }
+            }
+        }
+
+        let mut max_block_len: HashMap<String, u64> = HashMap::new();
+        for (contig, contig_blocks) in blocks.iter_mut() {
+            contig_blocks.sort_by_key(|b| b.t_start);
+            let max_len = contig_blocks
+                .iter()
+                .map(|b| b.t_end - b.t_start)
+                .max()
+                .unwrap_or(0);
+            max_block_len.insert(contig.clone(), max_len);
+        }
+
+        Ok(ChainMap {
+            chains: chains,
+            blocks: blocks,
+            max_block_len: max_block_len,
+            target_contigs: target_contigs,
+        })
+    }
+
+    /// Maps the source interval of ```len``` bases starting at ```pos0``` (0-based) to the target
+    /// reference through the best chain with a block that contains all of it.
+    ///
+    /// # Returns
+    /// ```(chain_ix, target contig, target start on the forward strand, reverse strand)```
+    fn lift(&self, chrom: &str, pos0: u64, len: u64) -> Option<(usize, &str, u64, bool)> {
+        let contig_blocks = self.blocks.get(chrom)?;
+        let max_len = self.max_block_len[chrom];
+
+        // blocks that start after pos0 can't contain it, and neither can blocks that start more
+        // than max_len bases before it
+        let mut ix = match contig_blocks.binary_search_by(|b| {
+            if b.t_start <= pos0 {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        }) {
+            Ok(ix) | Err(ix) => ix,
+        };
+        let mut best: Option<&ChainBlock> = None;
+        while ix > 0 {
+            ix -= 1;
+            let block = &contig_blocks[ix];
+            if block.t_start + max_len <= pos0 {
+                break;
+            }
+            if pos0 + len <= block.t_end {
+                let better = match best {
+                    Some(b) => self.chains[block.chain_ix].score > self.chains[b.chain_ix].score,
+                    None => true,
+                };
+                if better {
+                    best = Some(block);
+                }
+            }
+        }
+
+        best.map(|block| {
+            let chain = &self.chains[block.chain_ix];
+            let q = block.q_start + (pos0 - block.t_start);
+            // on the reverse strand, the chain coordinates are on the reverse complement
+            let q_forward = if chain.reverse {
+                chain.q_size - q - len
+            } else {
+                q
+            };
+            (block.chain_ix, chain.q_name.as_str(), q_forward, chain.reverse)
+        })
+    }
+}
+
+fn reverse_complement(allele: &str) -> String {
+    allele
+        .chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            'a' => 't',
+            'c' => 'g',
+            'g' => 'c',
+            't' => 'a',
+            _ => c,
+        })
+        .collect()
+}
+
+/// A lifted VCF record, as its tab-separated fields
+struct LiftedRecord {
+    fields: Vec<String>,
+    chain_ix: usize,
+    contig_ix: usize,
+    pos: u64,
+}
+
+/// Lifts the records of a VCF (as lines, without the header) over to the target reference and
+/// fixes up the phase sets of every sample as described in the module documentation. The lifted
+/// records are sorted by target position.
+///
+/// # Returns
+/// The lifted records and the number of records that could not be lifted.
+fn lift_records(chain_map: &ChainMap, lines: &[String]) -> (Vec<String>, usize) {
+    let contig_ix: HashMap<&str, usize> = chain_map
+        .target_contigs
+        .iter()
+        .enumerate()
+        .map(|(i, &(ref name, _))| (name.as_str(), i))
+        .collect();
+
+    let mut records: Vec<LiftedRecord> = vec![];
+    let mut unlifted = 0;
+    for line in lines {
+        let mut fields: Vec<String> = line.split('\t').map(|f| f.to_string()).collect();
+        let pos0: u64 = match fields.get(1).and_then(|p| p.parse::<u64>().ok()) {
+            Some(p) if p > 0 && fields.len() >= 8 => p - 1,
+            _ => {
+                unlifted += 1;
+                continue;
+            }
+        };
+        let lifted = chain_map.lift(&fields[0], pos0, fields[3].len() as u64);
+        let (chain_ix, q_name, q_pos0, reverse) = match lifted {
+            Some(l) => l,
+            None => {
+                unlifted += 1;
+                continue;
+            }
+        };
+        if reverse {
+            let alts: Vec<&str> = fields[4].split(',').collect();
+            if fields[3].len() != 1 || alts.iter().any(|a| a.len() != 1) {
+                unlifted += 1;
+                continue;
+            }
+            let alts: Vec<String> = alts.iter().map(|a| reverse_complement(a)).collect();
+            fields[3] = reverse_complement(&fields[3]);
+            fields[4] = alts.join(",");
+        }
+        fields[0] = q_name.to_string();
+        fields[1] = (q_pos0 + 1).to_string();
+        records.push(LiftedRecord {
+            fields: fields,
+            chain_ix: chain_ix,
+            contig_ix: contig_ix[q_name],
+            pos: q_pos0 + 1,
+        });
+    }
+
+    // the phase sets of each sample are split where consecutive variants map through different
+    // chains, for each sample
+    let n_samples = records
+        .iter()
+        .map(|r| r.fields.len().saturating_sub(9))
+        .max()
+        .unwrap_or(0);
+    for s in 0..n_samples {
+        let mut run_of_record: Vec<Option<usize>> = vec![None; records.len()];
+        let mut run_members: Vec<Vec<usize>> = vec![];
+        // the current run and its chain of each (old) phase set
+        let mut current_run: HashMap<String, (usize, usize)> = HashMap::new();
+
+        for (i, record) in records.iter().enumerate() {
+            let format: Vec<&str> = record.fields[8].split(':').collect();
+            let values: Vec<&str> = match record.fields.get(9 + s) {
+                Some(v) => v.split(':').collect(),
+                None => continue,
+            };
+            let gt = format.iter().position(|&f| f == "GT").and_then(|ix| values.get(ix));
+            let ps = format.iter().position(|&f| f == "PS").and_then(|ix| values.get(ix));
+            match (gt, ps) {
+                (Some(gt), Some(&ps)) if gt.contains('|') && ps != "." => {
+                    let run = match current_run.get(ps) {
+                        Some(&(run, chain_ix)) if chain_ix == record.chain_ix => run,
+                        _ => {
+                            run_members.push(vec![]);
+                            run_members.len() - 1
+                        }
+                    };
+                    current_run.insert(ps.to_string(), (run, record.chain_ix));
+                    run_of_record[i] = Some(run);
+                    run_members[run].push(i);
+                }
+                _ => {}
+            }
+        }
+
+        for i in 0..records.len() {
+            let run = match run_of_record[i] {
+                Some(run) => run,
+                None => continue,
+            };
+            let members = &run_members[run];
+            let new_ps: Option<u64> = if members.len() > 1 {
+                members.iter().map(|&m| records[m].pos).min()
+            } else {
+                None
+            };
+
+            let record = &mut records[i];
+            let format: Vec<String> = record.fields[8].split(':').map(|f| f.to_string()).collect();
+            let mut values: Vec<String> =
+                record.fields[9 + s].split(':').map(|v| v.to_string()).collect();
+            for (f, v) in format.iter().zip(values.iter_mut()) {
+                match (f.as_str(), new_ps) {
+                    ("PS", Some(ps)) => *v = ps.to_string(),
+                    ("PS", None) => *v = ".".to_string(),
+                    ("GT", None) => *v = v.replace("|", "/"),
+                    _ => {}
+                }
+            }
+            record.fields[9 + s] = values.join(":");
+        }
+    }
+
+    records.sort_by_key(|r| (r.contig_ix, r.pos));
+    (
+        records.into_iter().map(|r| r.fields.join("\t")).collect(),
+        unlifted,
+    )
+}
+
+/// Lifts the VCF written by Longshot (```input_vcf```) over to another reference assembly with a
+/// chain file, and writes it to ```output_vcf```. The contig header lines are replaced with the
+/// contigs of the target reference.
+pub fn liftover_vcf(input_vcf: &String, chain_file: &String, output_vcf: &String) -> Result<()> {
+    let chain_map = ChainMap::parse(BufReader::new(
+        File::open(chain_file).chain_err(|| ErrorKind::FileReadError(chain_file.clone()))?,
+    ))
+    .chain_err(|| format!("Error parsing chain file {}.", chain_file))?;
+
+    let mut header: Vec<String> = vec![];
+    let mut lines: Vec<String> = vec![];
+    let input = BufReader::new(
+        File::open(input_vcf).chain_err(|| ErrorKind::FileReadError(input_vcf.clone()))?,
+    );
+    for line in input.lines() {
+        let line = line.chain_err(|| ErrorKind::FileReadError(input_vcf.clone()))?;
+        if line.starts_with('#') {
+            header.push(line);
+        } else if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+
+    let (lifted, unlifted) = lift_records(&chain_map, &lines);
+
+    let path = Path::new(output_vcf);
+    let display = path.display();
+    let mut file =
+        File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+    let mut wrote_contigs = false;
+    for line in &header {
+        if line.starts_with("##contig=") {
+            continue;
+        }
+        // the target contigs go where the source contigs were, before the INFO lines
+        if !wrote_contigs && (line.starts_with("##INFO") || line.starts_with("#CHROM")) {
+            for &(ref name, len) in &chain_map.target_contigs {
+                writeln!(file, "##contig=<ID={},length={}>", name, len)
+                    .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+            }
+            writeln!(file, "##liftover=<chain=\"{}\">", chain_file)
+                .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+            wrote_contigs = true;
+        }
+        writeln!(file, "{}", line).chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+    for line in &lifted {
+        writeln!(file, "{}", line).chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    info!(
+        "{} Lifted {} of {} variants over to the target reference.",
+        print_time(),
+        lifted.len(),
+        lifted.len() + unlifted
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liftover() {
+        // chr1 100-200 maps to chrA 1000-1100 (chain 1), chr1 200-300 maps to the reverse strand
+        // of chrB (chain 2, length 500)
+        let chains = "chain 1000 chr1 1000 + 100 200 chrA 5000 + 1000 1100 1\n\
+                      60 0 10\n\
+                      40\n\
+                      \n\
+                      chain 500 chr1 1000 + 200 300 chrB 500 - 100 200 2\n\
+                      100\n";
+        let chain_map = ChainMap::parse(chains.as_bytes()).unwrap();
+        assert_eq!(
+            chain_map.target_contigs,
+            vec![("chrA".to_string(), 5000), ("chrB".to_string(), 500)]
+        );
+        assert_eq!(chain_map.lift("chr1", 110, 1), Some((0, "chrA", 1010, false)));
+        // after the 10 bp gap on the target
+        assert_eq!(chain_map.lift("chr1", 170, 1), Some((0, "chrA", 1080, false)));
+        // spans the end of the first block
+        assert_eq!(chain_map.lift("chr1", 159, 2), None);
+        // reverse strand: position 100 + 10 on the reverse complement of chrB
+        assert_eq!(chain_map.lift("chr1", 210, 1), Some((1, "chrB", 389, true)));
+        assert_eq!(chain_map.lift("chr2", 110, 1), None);
+
+        let record = |pos: usize, gt: &str, ps: &str| {
+            format!("chr1\t{}\t.\tA\tG\t30\tPASS\tDP=20\tGT:GQ:PS\t{}:30:{}", pos, gt, ps)
+        };
+        let lines = vec![
+            record(111, "0|1", "111"),
+            record(171, "1|0", "111"),
+            record(211, "0|1", "111"),
+            record(251, "0/1", "."),
+            record(500, "0|1", "111"),
+        ];
+        let (lifted, unlifted) = lift_records(&chain_map, &lines);
+        assert_eq!(unlifted, 1);
+        assert_eq!(
+            lifted,
+            vec![
+                "chrA\t1011\t.\tA\tG\t30\tPASS\tDP=20\tGT:GQ:PS\t0|1:30:1011",
+                "chrA\t1081\t.\tA\tG\t30\tPASS\tDP=20\tGT:GQ:PS\t1|0:30:1011",
+                // the phase block continues on another chain, where its only variant is unphased
+                "chrB\t350\t.\tT\tC\t30\tPASS\tDP=20\tGT:GQ:PS\t0/1:30:.",
+                "chrB\t390\t.\tT\tC\t30\tPASS\tDP=20\tGT:GQ:PS\t0/1:30:.",
+            ]
+        );
+    }
+}
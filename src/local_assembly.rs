@@ -0,0 +1,540 @@
+//! Finds potential variants by local assembly of the reads, as an alternative to counting the
+//! alleles of each pileup column (see ```call_potential_snvs```).
+//!
+//! Windows of the genome with evidence for any difference from the reference (mismatches,
+//! insertions or deletions) are found from the pileup. In each window, the reads that span the
+//! window are assembled into a small de Bruijn graph, and every path through the graph from the
+//! first to the last reference k-mer is a candidate haplotype. The candidate haplotypes are
+//! aligned to the reference window to obtain the potential variants. Because the alleles come from
+//! whole haplotypes rather than single columns, this recovers clustered variants and small complex
+//! events whose observations are spread over several pileup columns by alignment differences.
+
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::AlignmentOperation;
+use call_potential_snvs::CandidateThresholds;
+use errors::*;
+use extract_fragments::ReadFilter;
+use hashbrown::{HashMap, HashSet};
+use rust_htslib::bam;
+use rust_htslib::bam::pileup::Indel;
+use rust_htslib::bam::record::{Cigar, Record};
+use util::*;
+use variants_and_fragments::*;
+
+/// number of reference bases added on each side of an active site to form an assembly window
+static WINDOW_PADDING: usize = 25;
+/// windows longer than this (e.g. long stretches of active sites) are not assembled
+static MAX_WINDOW_LEN: usize = 300;
+/// k-mer lengths to try for the de Bruijn graph. The smallest one for which the k-mers of the
+/// reference window are unique is used.
+static KMER_SIZES: [usize; 4] = [11, 15, 21, 31];
+/// a k-mer must occur in this fraction of the reads spanning the window to be part of the graph
+static MIN_KMER_FRAC: f64 = 0.1;
+/// a k-mer must occur in at least this many of the reads spanning the window
+static MIN_KMER_COUNT: usize = 3;
+/// windows with more paths through the graph than this are too complex to assemble
+static MAX_PATHS: usize = 32;
+/// maximum number of graph extension steps when searching for the paths of a window
+static MAX_SEARCH_STEPS: usize = 10000;
+/// alleles longer than this are not used as potential variants (as for potential variants VCFs)
+static MAX_ALLELE_LEN: usize = 50;
+
+/// Returns the bases of a read aligned to the reference positions ```l``` to ```r```
+/// (inclusive), or ```None``` if the read does not have a base aligned to both ```l``` and ```r```
+fn read_segment(record: &Record, l: usize, r: usize) -> Option<Vec<u8>> {
+    let mut ref_pos = record.pos() as usize;
+    let mut read_pos: usize = 0;
+    let mut start: Option<usize> = None;
+    let mut end: Option<usize> = None;
+
+    for op in record.cigar().iter() {
+        match *op {
+            Cigar::Match(n) | Cigar::Equal(n) | Cigar::Diff(n) => {
+                let n = n as usize;
+                if l >= ref_pos && l < ref_pos + n {
+                    start = Some(read_pos + l - ref_pos);
+                }
+                if r >= ref_pos && r < ref_pos + n {
+                    end = Some(read_pos + r - ref_pos);
+                }
+                ref_pos += n;
+                read_pos += n;
+            }
+            Cigar::Ins(n) | Cigar::SoftClip(n) => {
+                read_pos += n as usize;
+            }
+            Cigar::Del(n) | Cigar::RefSkip(n) => {
+                ref_pos += n as usize;
+            }
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+
+    match (start, end) {
+        (Some(s), Some(e)) if s <= e => Some(
+            record.seq().as_bytes()[s..e + 1]
+                .iter()
+                .map(|b| b.to_ascii_uppercase())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn has_unique_kmers(seq: &[u8], k: usize) -> bool {
+    let mut seen: HashSet<&[u8]> = HashSet::new();
+    seq.windows(k).all(|kmer| seen.insert(kmer))
+}
+
+/// Extends ```path``` through the de Bruijn graph in every possible way, adding each path that
+/// reaches ```sink``` to ```paths```. Returns false if the search was abandoned because there are
+/// too many paths, or it took too many steps.
+fn extend_paths(
+    kmers: &HashSet<&[u8]>,
+    k: usize,
+    sink: &[u8],
+    max_len: usize,
+    path: &mut Vec<u8>,
+    on_path: &mut HashSet<Vec<u8>>,
+    paths: &mut Vec<Vec<u8>>,
+    steps: &mut usize,
+) -> bool {
+    *steps += 1;
+    if *steps > MAX_SEARCH_STEPS {
+        return false;
+    }
+
+    let last: Vec<u8> = path[path.len() - k..].to_vec();
+    if &last[..] == sink {
+        paths.push(path.clone());
+        return paths.len() <= MAX_PATHS;
+    }
+    if path.len() >= max_len {
+        return true;
+    }
+
+    for &base in b"ACGT" {
+        let mut next: Vec<u8> = last[1..].to_vec();
+        next.push(base);
+        if !kmers.contains(&next[..]) || on_path.contains(&next) {
+            continue;
+        }
+        path.push(base);
+        on_path.insert(next.clone());
+        let complete = extend_paths(kmers, k, sink, max_len, path, on_path, paths, steps);
+        path.pop();
+        on_path.remove(&next);
+        if !complete {
+            return false;
+        }
+    }
+    true
+}
+
+/// Assembles the reads spanning a window into candidate haplotypes
+///
+/// # Arguments
+/// - ```ref_window```: the reference sequence of the window
+/// - ```segments```: the bases of each read aligned to the window (see ```read_segment```)
+///
+/// # Returns
+/// Returns the sequences of the non-reference paths from the first to the last k-mer of the
+/// reference window, through the k-mers that occur in enough reads. No haplotypes are returned if
+/// the window is repetitive (no k-mer length gives unique reference k-mers) or too complex.
+fn assemble_haplotypes(ref_window: &[u8], segments: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let k = match KMER_SIZES
+        .iter()
+        .find(|&&k| k < ref_window.len() && has_unique_kmers(ref_window, k))
+    {
+        Some(&k) => k,
+        None => return vec![],
+    };
+
+    let min_count = MIN_KMER_COUNT.max((MIN_KMER_FRAC * segments.len() as f64).ceil() as usize);
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for segment in segments {
+        // each k-mer is counted once per read
+        let mut seen: HashSet<&[u8]> = HashSet::new();
+        for kmer in segment.windows(k) {
+            if seen.insert(kmer) {
+                *counts.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut kmers: HashSet<&[u8]> = counts
+        .iter()
+        .filter(|&(_, &count)| count >= min_count)
+        .map(|(&kmer, _)| kmer)
+        .collect();
+    for kmer in ref_window.windows(k) {
+        kmers.insert(kmer);
+    }
+
+    let source: &[u8] = &ref_window[..k];
+    let sink: &[u8] = &ref_window[ref_window.len() - k..];
+    let mut path: Vec<u8> = source.to_vec();
+    let mut on_path: HashSet<Vec<u8>> = HashSet::new();
+    on_path.insert(source.to_vec());
+    let mut paths: Vec<Vec<u8>> = vec![];
+    let mut steps: usize = 0;
+
+    let complete = extend_paths(
+        &kmers,
+        k,
+        sink,
+        ref_window.len() + MAX_ALLELE_LEN,
+        &mut path,
+        &mut on_path,
+        &mut paths,
+        &mut steps,
+    );
+    if !complete {
+        return vec![];
+    }
+
+    paths.into_iter().filter(|p| &p[..] != ref_window).collect()
+}
+
+/// Aligns a haplotype to the reference window and returns its differences from the reference
+///
+/// # Returns
+/// Returns ```(offset, ref allele, alt allele)``` for each variant, where ```offset``` is the
+/// position of the variant in the window. Substitutions are returned as separate SNVs, so that
+/// they can be phased. Insertions and deletions, together with any substitutions next to them,
+/// are returned as a single (possibly complex) variant that includes the preceding reference base.
+fn haplotype_variants(ref_window: &[u8], haplotype: &[u8]) -> Vec<(usize, String, String)> {
+    let score = |a: u8, b: u8| if a == b { 2i32 } else { -4i32 };
+    let mut aligner = Aligner::new(-6, -1, &score);
+    let alignment = aligner.global(haplotype, ref_window);
+
+    let to_string = |s: &[u8]| s.iter().map(|&b| b as char).collect::<String>();
+    let mut variants: Vec<(usize, String, String)> = vec![];
+    let mut x: usize = 0; // position in the haplotype
+    let mut y: usize = 0; // position in the reference window
+    // the haplotype and reference positions where the current run of differences started
+    let mut event: Option<(usize, usize)> = None;
+
+    for op in &alignment.operations {
+        match *op {
+            AlignmentOperation::Match => {
+                if let Some((xs, ys)) = event {
+                    if x - xs == y - ys {
+                        for i in 0..(x - xs) {
+                            variants.push((
+                                ys + i,
+                                to_string(&ref_window[ys + i..ys + i + 1]),
+                                to_string(&haplotype[xs + i..xs + i + 1]),
+                            ));
+                        }
+                    } else if xs > 0 && ys > 0 {
+                        variants.push((
+                            ys - 1,
+                            to_string(&ref_window[ys - 1..y]),
+                            to_string(&haplotype[xs - 1..x]),
+                        ));
+                    }
+                    event = None;
+                }
+                x += 1;
+                y += 1;
+            }
+            AlignmentOperation::Subst | AlignmentOperation::Ins | AlignmentOperation::Del => {
+                if event.is_none() {
+                    event = Some((x, y));
+                }
+                match *op {
+                    AlignmentOperation::Subst => {
+                        x += 1;
+                        y += 1;
+                    }
+                    AlignmentOperation::Ins => x += 1,
+                    _ => y += 1,
+                }
+            }
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+    }
+
+    variants
+}
+
+/// Finds potential variants by local assembly of the reads in windows with evidence for variation
+///
+/// # Arguments
+/// -```bam_file```: the input BAM file name
+/// -```fasta_file```: the input FASTA file name
+/// -```interval```: optional genomic interval to call variants in
+/// -```min_coverage```: the minimum read depth of an active site
+/// -```max_coverage```: the maximum read depth of an active site
+/// -```thresholds```: a site is active if the number of reads that differ from the reference
+///                    there (by a mismatch, or an insertion or deletion) passes these thresholds
+/// -```read_filter```: the flag, mapping quality and length filters of the reads to use, the same
+///                     as for fragment extraction
+/// -```max_indel_len```: the maximum change in length of an indel or complex variant. If this
+///                       is 0, only SNVs are returned.
+/// -```depth_definition```: which reads overlapping a site count toward its depth
+/// -```contig_params```: per-contig overrides of ```max_coverage``` and ```thresholds```
+///
+/// # Returns
+/// Returns a result containing the potential variants. Variants at the same position (e.g. from
+/// the two haplotypes of a window) are merged into multiallelic variants.
+///
+/// # Errors
+/// - ```IndexedFastaOpenError```, ```IndexedFastaReadError```: errors reading the FASTA file
+/// - ```IndexedBamOpenError```, ```IndexedBamFetchError```, ```IndexedBamPileupReadError```,
+///   ```IndexedBamRecordReadError```: errors reading the BAM file
+pub fn call_potential_variants_assembly(
+    bam_file: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    min_coverage: u32,
+    max_coverage: u32,
+    thresholds: &CandidateThresholds,
+    read_filter: &ReadFilter,
+    max_indel_len: usize,
+    depth_definition: DepthDefinition,
+    contig_params: &Vec<ContigParams>,
+) -> Result<VarList> {
+    let target_names = parse_target_names(&bam_file)?;
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+
+    let mut vars: Vec<Var> = vec![];
+    let mut n_windows: usize = 0;
+    let mut n_skipped_windows: usize = 0;
+
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
+    let mut bam_ix = open_indexed_bam(bam_file)?;
+
+    let mut progress = ProgressReporter::new(
+        "Local assembly",
+        "reference bases",
+        interval_lst_len(&interval_lst),
+    );
+    let mut bases_done: usize = 0;
+
+    for iv in interval_lst {
+        let iv_bases_done = bases_done;
+        bases_done += (iv.end_pos - iv.start_pos + 1) as usize;
+
+        // the coverage cutoff and thresholds can be overridden for each contig
+        let max_coverage = contig_max_cov(contig_params, iv.tid, max_coverage);
+        let thresholds: &CandidateThresholds =
+            &thresholds.for_contig(contig_params.get(iv.tid as usize));
+
+        let chrom = &target_names[iv.tid as usize];
+        let contig_len = ref_reader.contig_len(chrom)?;
+
+        // find the active sites, where enough reads differ from the reference
+        let mut active_sites: Vec<usize> = vec![];
+        bam_ix
+            .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+
+        for p in bam_ix.pileup() {
+            let pileup = p.chain_err(|| ErrorKind::IndexedBamPileupReadError)?;
+            progress.set(iv_bases_done + pileup.pos().saturating_sub(iv.start_pos) as usize);
+            if pileup.tid() != iv.tid || pileup.pos() < iv.start_pos || pileup.pos() > iv.end_pos
+            {
+                continue;
+            }
+            let pos: usize = pileup.pos() as usize;
+            let ref_base = ref_reader.window(chrom, pos, pos + 1)?[pos];
+            if is_ambiguous_base(ref_base) {
+                continue;
+            }
+
+            let mut depth: usize = 0;
+            let mut non_ref: usize = 0;
+            for alignment in pileup.alignments() {
+                let record = alignment.record();
+                if read_filter.check(&record).is_some() {
+                    continue;
+                }
+
+                if depth_definition.counts(&alignment) {
+                    depth += 1;
+                }
+
+                if alignment.is_del() {
+                    non_ref += 1;
+                } else if !alignment.is_refskip() {
+                    let qpos: usize = alignment
+                        .qpos()
+                        .chain_err(|| ErrorKind::IndexedBamPileupQueryPositionError)?;
+                    let base = (record.seq()[qpos] as char).to_ascii_uppercase();
+                    match alignment.indel() {
+                        Indel::Ins(_) | Indel::Del(_) => non_ref += 1,
+                        Indel::None if base != ref_base => non_ref += 1,
+                        _ => {}
+                    }
+                }
+            }
+
+            if depth >= min_coverage as usize
+                && depth <= max_coverage as usize
+                && thresholds.passes(non_ref, depth)
+            {
+                active_sites.push(pos);
+            }
+        }
+
+        // pad and merge the active sites into windows
+        let mut windows: Vec<(usize, usize)> = vec![];
+        for pos in active_sites {
+            let l = if pos >= WINDOW_PADDING {
+                pos - WINDOW_PADDING
+            } else {
+                0
+            };
+            let r = (pos + WINDOW_PADDING).min(contig_len - 1);
+            let merged = match windows.last_mut() {
+                Some(w) if l <= w.1 => {
+                    w.1 = w.1.max(r);
+                    true
+                }
+                _ => false,
+            };
+            if !merged {
+                windows.push((l, r));
+            }
+        }
+
+        for (l, r) in windows {
+            n_windows += 1;
+            let ref_window: Vec<u8> = ref_reader.window(chrom, l, r + 1)?[l..r + 1]
+                .iter()
+                .map(|&c| c as u8)
+                .collect();
+            if ref_window.len() > MAX_WINDOW_LEN
+                || ref_window.iter().any(|&c| is_ambiguous_base(c as char))
+            {
+                n_skipped_windows += 1;
+                continue;
+            }
+
+            // the bases of the reads spanning the window, and mapping quality statistics
+            let mut segments: Vec<Vec<u8>> = vec![];
+            let mut passing_reads: usize = 0;
+            let mut mq_counts = [0 as usize; 5]; // reads with MAPQ >= 10, 20, 30, 40, 50
+            bam_ix
+                .fetch(iv.tid, l as u32, r as u32 + 1)
+                .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+            for rec in bam_ix.records() {
+                let record = rec.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+                if read_filter.check_any_mapq(&record).is_some() {
+                    continue;
+                }
+                passing_reads += 1;
+                for (i, count) in mq_counts.iter_mut().enumerate() {
+                    if record.mapq() as usize >= 10 * (i + 1) {
+                        *count += 1;
+                    }
+                }
+                if record.mapq() < read_filter.min_mapq {
+                    continue;
+                }
+                if let Some(segment) = read_segment(&record, l, r) {
+                    segments.push(segment);
+                }
+            }
+            if segments.len() < min_coverage as usize {
+                n_skipped_windows += 1;
+                continue;
+            }
+
+            for haplotype in assemble_haplotypes(&ref_window, &segments) {
+                for (offset, ref_allele, alt_allele) in haplotype_variants(&ref_window, &haplotype)
+                {
+                    let len_diff = if ref_allele.len() > alt_allele.len() {
+                        ref_allele.len() - alt_allele.len()
+                    } else {
+                        alt_allele.len() - ref_allele.len()
+                    };
+                    let pos0 = l + offset;
+                    if len_diff > max_indel_len
+                        || (max_indel_len == 0 && ref_allele.len() > 1)
+                        || ref_allele.len() > MAX_ALLELE_LEN
+                        || alt_allele.len() > MAX_ALLELE_LEN
+                        || has_non_acgt(&alt_allele)
+                        || pos0 < iv.start_pos as usize
+                        || pos0 > iv.end_pos as usize
+                    {
+                        continue;
+                    }
+
+                    let mut var = Var::new(iv.tid, pos0, vec![ref_allele, alt_allele]);
+                    var.dp = segments.len();
+                    var.dp_any_mq = passing_reads;
+                    var.mq10_frac = mq_counts[0] as f64 / passing_reads as f64;
+                    var.mq20_frac = mq_counts[1] as f64 / passing_reads as f64;
+                    var.mq30_frac = mq_counts[2] as f64 / passing_reads as f64;
+                    var.mq40_frac = mq_counts[3] as f64 / passing_reads as f64;
+                    var.mq50_frac = mq_counts[4] as f64 / passing_reads as f64;
+                    vars.push(var);
+                }
+            }
+        }
+    }
+
+    info!(
+        "{} Assembled {} of {} active windows.",
+        SPACER,
+        n_windows - n_skipped_windows,
+        n_windows
+    );
+
+    vars.sort();
+    vars.dedup_by(|a, b| a.tid == b.tid && a.pos0 == b.pos0 && a.alleles == b.alleles);
+    let mut varlist = VarList::new(vars, target_names)?;
+    varlist
+        .merge_same_position()
+        .chain_err(|| "Error merging assembled variants at the same position.")?;
+    Ok(varlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haplotype_variants() {
+        let ref_window = b"ACGTTGCAAGTCCATGACGT";
+        // two SNVs next to each other, and a 2 bp deletion
+        let haplotype = b"ACGTTGGTAGTCCGACGT";
+        let vars = haplotype_variants(ref_window, haplotype);
+        assert_eq!(
+            vars,
+            vec![
+                (6, "C".to_string(), "G".to_string()),
+                (7, "A".to_string(), "T".to_string()),
+                (12, "CAT".to_string(), "C".to_string()),
+            ]
+        );
+        assert!(haplotype_variants(ref_window, ref_window).is_empty());
+    }
+
+    #[test]
+    fn test_assemble_haplotypes() {
+        let ref_window = b"TTAGCCATGCAGGTACAATCGGACTTGACCATAGGTCAGTTCAGC".to_vec();
+        let mut alt_window = ref_window.clone();
+        alt_window[20] = b'A'; // SNV
+        alt_window.insert(30, b'G'); // insertion
+
+        // half of the reads on each haplotype, and one read with a sequencing error
+        let mut segments: Vec<Vec<u8>> = vec![];
+        for _ in 0..5 {
+            segments.push(ref_window.clone());
+            segments.push(alt_window.clone());
+        }
+        let mut error_read = ref_window.clone();
+        error_read[10] = b'T';
+        segments.push(error_read);
+
+        let haplotypes = assemble_haplotypes(&ref_window, &segments);
+        assert_eq!(haplotypes, vec![alt_window.clone()]);
+
+        // the variant k-mers are in too few reads
+        assert!(assemble_haplotypes(&ref_window, &segments[8..].to_vec()).is_empty());
+    }
+}
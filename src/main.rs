@@ -17,46 +17,144 @@ extern crate rust_htslib;
 extern crate error_chain;
 extern crate fishers_exact;
 extern crate hashbrown;
+#[macro_use]
+extern crate log;
+extern crate memmap;
+extern crate url;
 
 // import modules
+mod allele_fractions;
+mod alphabet;
 mod call_genotypes;
 mod call_potential_snvs;
+mod callable_regions;
+mod checkpoint;
+mod contig_scheduler;
+mod debug_realign;
 mod errors;
 mod estimate_alignment_parameters;
 mod estimate_read_coverage;
+mod evidence_export;
 mod extract_fragments; //mod extract_fragments_debug;
+mod fragment_simulation;
+mod fragment_store;
 mod genotype_probs;
 mod haplotype_assembly;
+mod liftover;
+mod local_assembly;
+mod methylation;
+mod mnv;
+mod mosaic;
+mod parquet_output;
+mod pedigree;
+mod phasing_evaluation;
+mod polyploid_phasing;
+mod population_af;
+mod presets;
 mod print_output;
+mod quality_calibration;
+mod read_simulation;
 mod realignment;
+mod region_queue;
+mod region_scheduler;
+mod run_stats;
+mod sex_chromosomes;
+mod site_filters;
+mod stdin_bam;
+mod soft_clip_clusters;
+mod somatic;
+mod subcommands;
+mod sv_breakpoints;
+mod telemetry;
 mod util;
 mod variants_and_fragments;
+mod vcf_annotation;
+mod vcf_merge;
+mod wmec;
 //mod spoa;
 
 // use declarations
 use bio::stats::{LogProb, PHREDProb, Prob};
-use bio::io::fasta::IndexedReader;
 use call_genotypes::*;
+use call_potential_snvs::CandidateThresholds;
+use callable_regions::CallableRegions;
+use checkpoint::Checkpoint;
+use contig_scheduler::{call_contigs_in_parallel, parse_contig_list, strip_options, CommandLineOption, HYPHEN_VALUE_OPTIONS, PER_CONTIG_OPTIONS};
+use allele_fractions::call_allele_fractions;
+use alphabet::{Alphabet, ALPHABET_NAMES};
 use clap::{App, Arg};
+use debug_realign::write_realignment_debug_sam;
 use errors::*;
-use estimate_alignment_parameters::estimate_alignment_parameters;
+use estimate_alignment_parameters::{
+    estimate_alignment_parameters, read_alignment_parameters, stratified_training_windows,
+    write_alignment_parameters,
+};
 use estimate_read_coverage::calculate_mean_coverage;
-use extract_fragments::ExtractFragmentParameters;
+use evidence_export::EvidenceTensor;
+use extract_fragments::{
+    ExtractFragmentParameters, MissingQualPolicy, ReadFilter, RefAmbiguity, REF_AMBIGUITY_NAMES,
+};
 use fishers_exact::fishers_exact;
-use genotype_probs::{Genotype, GenotypePriors};
+use genotype_probs::{
+    Genotype, GenotypePrior, GenotypePriors, PopulationAfPrior, UniformPrior, GENOTYPE_PRIOR_NAMES,
+};
 use haplotype_assembly::*;
-use print_output::{print_variant_debug, print_vcf, print_vcf_header};
-use realignment::AlignmentType;
+use hashbrown::HashMap;
+use liftover::liftover_vcf;
+use methylation::find_methylation_markers;
+use mnv::merge_phased_mnvs;
+use mosaic::{
+    call_mosaic_variants, estimate_noise_rate, mosaic_allele_fraction_likelihoods,
+    MosaicParameters,
+};
+use parquet_output::ParquetCallWriter;
+use pedigree::{apply_pedigree_priors, parse_pedigree, Trio};
+use phasing_evaluation::{evaluate_phasing, parse_truth_vcf, print_phasing_report};
+use polyploid_phasing::{phase_polyploid, print_polyploid_blocks};
+use population_af::{annotate_population_afs, check_population_af_vcf};
+use presets::{apply_preset, resolve_auto_preset, Preset, PRESET_ARG_VALUES, PRESET_NAMES};
+use print_output::{
+    add_vcf_header_lines, output_info_ids, print_block_consensus, print_callable_regions,
+    print_candidates_vcf, print_consensus_fasta, print_dense_regions, print_hap_iteration_stats,
+    print_soft_clip_clusters, print_variant_debug, print_vcf, print_vcf_header,
+    print_vcf_multisample, CallSummary,
+};
+use quality_calibration::{parse_truth_genotypes, CalibrationCalls, QualityCalibration};
+use read_simulation::{write_simulated_dataset, ReadSimulationParameters};
+use realignment::{
+    AlignmentParameters, AlignmentType, ContextAlignmentParameters, ReadGroupAlignmentParameters,
+    StrandAlignmentParameters, MAX_WINDOW_LEN, REALIGNMENT_MODEL_NAMES,
+};
+use region_queue::RegionQueue;
+use stdin_bam::{replace_stdin_bam, spool_stdin_bam, SpooledBam, STDIN_BAM};
+use region_scheduler::{call_windows_in_parallel, SchedulerMode, SCHEDULER_NAMES};
+use run_stats::RunStats;
+use sex_chromosomes::{Sex, SexChromosomes};
+use site_filters::{
+    apply_site_filters, AlleleBalanceFilter, HomopolymerFilter, MaxDepthFilter, MinDepthFilter,
+    SiteFilter, StrandBiasFilter, TandemRepeatFilter,
+};
+use soft_clip_clusters::{flag_soft_clip_cluster_snvs, soft_clip_cluster_snvs, SoftClipCluster};
+use somatic::{call_somatic_variants, tumor_allele_fraction_likelihoods, SomaticParameters};
+use subcommands::{apply_subcommand, Subcommand};
+use sv_breakpoints::{call_sv_breakpoints, genotype_external_svs, parse_sv_vcf, ExternalSv};
 use std::fs::create_dir;
+use std::fs::metadata;
 use std::fs::remove_dir_all;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use util::*;
 use util::{
     parse_flag, parse_positive_f64, parse_prob_into_logprob, parse_u32, parse_u8, parse_usize,
 };
-use variants_and_fragments::{parse_vcf_potential_variants, VarFilter};
+use variants_and_fragments::{
+    flag_reference_calls, parse_vcf_potential_variants, Var, VarFilter, VarList,
+};
+use vcf_annotation::{annotate_variants, AnnotationVcf};
+use vcf_merge::merge_vcfs;
 
 //use haplotype_assembly::separate_reads_by_haplotype;
 //use realignment::{AlignmentParameters, TransitionProbs, EmissionProbs};
@@ -67,7 +165,28 @@ use variants_and_fragments::{parse_vcf_potential_variants, VarFilter};
 /// It execute the run() function (which contains the entirety of the program logic)
 /// If the program fails, grab the chain of errors incurred and print them with nonzero exit code (and any backtrace)
 fn main() {
-    if let Err(ref e) = run() {
+    // a panic is a bug, so it exits with the code of an internal error rather than the code of
+    // an input error
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_panic_hook(info);
+        println!("error: internal error (this is a bug in longshot, please report it)");
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }));
+
+    // the messages are printed at the default level until --log_level is parsed
+    init_logging(log::LevelFilter::Info);
+
+    // `longshot simulate` writes a simulated dataset and `longshot merge` merges VCF files instead
+    // of calling variants, and the other subcommands are rewritten into the options of the main
+    // command
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(|a| a.as_str()) {
+        Some("simulate") => run_simulate(),
+        Some("merge") => run_merge(),
+        _ => apply_subcommand(args).and_then(|(args, subcommand)| run(args, subcommand)),
+    };
+    if let Err(ref e) = result {
         println!("error: {}", e);
         for e in e.iter().skip(1) {
             println!("caused by: {}", e);
@@ -75,8 +194,249 @@ fn main() {
         if let Some(backtrace) = e.backtrace() {
             println!("backtrace: {:?}", backtrace);
         }
-        std::process::exit(1);
+        std::process::exit(exit_code(e));
+    }
+}
+
+/// The value of ```--log_level``` in the command line (```info``` if it isn't given), which is
+/// looked up before the other options are parsed
+fn log_level_arg(args: &[String]) -> String {
+    for (i, arg) in args.iter().enumerate() {
+        if arg == "--log_level" {
+            if let Some(level) = args.get(i + 1) {
+                return level.clone();
+            }
+        } else if arg.starts_with("--log_level=") {
+            return arg["--log_level=".len()..].to_string();
+        }
+    }
+    "info".to_string()
+}
+
+/// The run function of the ```simulate``` subcommand
+///
+/// Simulates a reference, phased SNVs and indels, and long reads with the error model of a preset
+/// or of an alignment parameters file, and writes them as a reference FASTA, an indexed BAM file and
+/// a truth VCF to the output directory (see ```read_simulation```).
+fn run_simulate() -> Result<()> {
+    info!("");
+
+    let input_args = App::new("longshot simulate: simulate a reference, phased variants and long reads")
+        .arg(Arg::with_name("Output directory")
+                .short("o")
+                .long("out_dir")
+                .value_name("dir")
+                .help("Write the reference (ref.fa), the reads (reads.bam) and the planted variants (truth.vcf) to this directory. It is created if it doesn't exist.")
+                .display_order(10)
+                .required(true)
+                .takes_value(true))
+        .arg(Arg::with_name("Reference length")
+                .long("ref_len")
+                .value_name("int")
+                .help("Length of the simulated reference.")
+                .display_order(20)
+                .default_value("100000"))
+        .arg(Arg::with_name("Coverage")
+                .long("coverage")
+                .value_name("float")
+                .help("Mean read depth.")
+                .display_order(30)
+                .default_value("30"))
+        .arg(Arg::with_name("Read length")
+                .long("read_len")
+                .value_name("int")
+                .help("Mean read length. The read lengths are uniform between half and 1.5 times the mean.")
+                .display_order(40)
+                .default_value("10000"))
+        .arg(Arg::with_name("SNV rate")
+                .long("snv_rate")
+                .value_name("float")
+                .help("Probability that a reference position has a planted SNV.")
+                .display_order(50)
+                .default_value("0.001"))
+        .arg(Arg::with_name("Indel rate")
+                .long("indel_rate")
+                .value_name("float")
+                .help("Probability that a reference position has a planted insertion or deletion.")
+                .display_order(60)
+                .default_value("0.0001"))
+        .arg(Arg::with_name("Max indel length")
+                .long("max_indel_len")
+                .value_name("int")
+                .help("The planted indels are 1 to this many bases long.")
+                .display_order(70)
+                .default_value("5"))
+        .arg(Arg::with_name("Homozygous fraction")
+                .long("hom_frac")
+                .value_name("float")
+                .help("Fraction of the planted variants that are homozygous. The heterozygous variants are on either haplotype at random, and the truth VCF is phased.")
+                .display_order(80)
+                .default_value("0.3"))
+        .arg(Arg::with_name("Preset")
+                .long("preset")
+                .value_name("name")
+                .help("Simulate the sequencing errors with the typical alignment parameters of this technology.")
+                .display_order(90)
+                .possible_values(&PRESET_NAMES)
+                .default_value("pacbio-clr"))
+        .arg(Arg::with_name("Read alignment parameters")
+                .long("read-params")
+                .value_name("path")
+                .help("Simulate the sequencing errors with the alignment parameters in this file (written by longshot with --write-params) instead of those of the preset.")
+                .display_order(100))
+        .arg(Arg::with_name("Random seed")
+                .long("seed")
+                .value_name("int")
+                .help("Seed of the random number generator. The same seed and options simulate the same dataset.")
+                .display_order(110)
+                .default_value("0"))
+        .arg(Arg::with_name("Force overwrite")
+                .short("F")
+                .long("force_overwrite")
+                .help("If the output files exist, overwrite them.")
+                .display_order(120))
+        .get_matches_from(std::env::args().skip(1));
+
+    let out_dir = PathBuf::from(
+        input_args
+            .value_of("Output directory")
+            .chain_err(|| "Output directory not defined.")?,
+    );
+    let ref_len = parse_usize(&input_args, "Reference length")?;
+    let coverage = parse_positive_f64(&input_args, "Coverage")?;
+    let mean_read_len = parse_usize(&input_args, "Read length")?;
+    let max_indel_len = parse_usize(&input_args, "Max indel length")?;
+    let seed = parse_usize(&input_args, "Random seed")?;
+    let force_overwrite = parse_flag(&input_args, "Force overwrite")?;
+    let mut rates: Vec<f64> = vec![];
+    for &arg_name in &["SNV rate", "Indel rate", "Homozygous fraction"] {
+        let rate = parse_nonnegative_f64(&input_args, arg_name)?;
+        ensure!(
+            rate <= 1.0,
+            format!("{} must be a float between 0.0 and 1.0!", arg_name)
+        );
+        rates.push(rate);
+    }
+    ensure!(
+        ref_len >= 1000,
+        "The simulated reference must be at least 1000 bases long."
+    );
+    ensure!(
+        mean_read_len >= 100,
+        "The mean read length must be at least 100 bases."
+    );
+
+    let alignment_params = match input_args.value_of("Read alignment parameters") {
+        Some(params_file) => read_alignment_parameters(&params_file.to_string())?.0,
+        None => {
+            let name = input_args
+                .value_of("Preset")
+                .chain_err(|| "Preset not defined.")?;
+            Preset::from_name(name)
+                .chain_err(|| format!("Unknown preset {}.", name))?
+                .alignment_parameters()
+        }
+    };
+
+    if out_dir.exists() {
+        for file_name in &["ref.fa", "reads.bam", "truth.vcf"] {
+            ensure!(
+                !out_dir.join(file_name).exists() || force_overwrite,
+                "Simulated {} already exists in {}. Rerun with -F option to force overwrite.",
+                file_name,
+                out_dir.display()
+            );
+        }
+    } else {
+        create_dir(&out_dir).chain_err(|| {
+            ErrorKind::CreateFileError(out_dir.to_string_lossy().to_string())
+        })?;
+    }
+
+    let params = ReadSimulationParameters {
+        ref_len: ref_len,
+        snv_rate: rates[0],
+        indel_rate: rates[1],
+        max_indel_len: max_indel_len,
+        hom_frac: rates[2],
+        coverage: coverage,
+        mean_read_len: mean_read_len,
+        alignment_params: alignment_params,
+        seed: seed,
+    };
+    write_simulated_dataset(&params, &out_dir)
+}
+
+/// The run function of the ```merge``` subcommand
+///
+/// Merges VCF files of separate regions, e.g. of the contigs called by the jobs of a cluster, into
+/// a single VCF or BCF with consistent phase sets (see ```vcf_merge```).
+fn run_merge() -> Result<()> {
+    info!("");
+
+    let input_args = App::new("longshot merge: merge the VCF files of separate regions")
+        .arg(Arg::with_name("Output file")
+                .short("o")
+                .long("out")
+                .value_name("path")
+                .help("Write the merged calls to this file: a bgzip-compressed VCF with a tabix index if it ends with .gz, a BCF with a CSI index if it ends with .bcf, and a VCF otherwise.")
+                .display_order(10)
+                .required(true)
+                .takes_value(true))
+        .arg(Arg::with_name("Force overwrite")
+                .short("F")
+                .long("force_overwrite")
+                .help("If the output file exists, overwrite it.")
+                .display_order(20))
+        .arg(Arg::with_name("Input VCFs")
+                .value_name("VCF")
+                .help("The VCF files to merge (uncompressed or compressed with bgzip), in any order. They must have the same samples, each must be sorted, and no two files can have records at the same position.")
+                .required(true)
+                .multiple(true))
+        .get_matches_from(std::env::args().skip(1));
+
+    let output_file = input_args
+        .value_of("Output file")
+        .chain_err(|| "Output file not defined.")?
+        .to_string();
+    let force_overwrite = parse_flag(&input_args, "Force overwrite")?;
+    let input_files: Vec<String> = input_args
+        .values_of("Input VCFs")
+        .chain_err(|| "Input VCFs not defined.")?
+        .map(|f| f.to_string())
+        .collect();
+    ensure!(
+        !Path::new(&output_file).exists() || force_overwrite,
+        "Output file {} already exists. Rerun with -F option to force overwrite.",
+        output_file
+    );
+    for input_file in &input_files {
+        ensure!(
+            Path::new(input_file).is_file(),
+            "VCF file {} does not exist.",
+            input_file
+        );
+        ensure!(
+            input_file != &output_file,
+            "VCF file {} is both an input and the output.",
+            input_file
+        );
     }
+
+    info!(
+        "{} Merging {} VCF files into {}...",
+        print_time(),
+        input_files.len(),
+        output_file
+    );
+    let summary = merge_vcfs(&input_files, &output_file)?;
+    info!(
+        "{} Wrote {} records, with {} phase sets renumbered.",
+        print_time(),
+        summary.n_records,
+        summary.n_renumbered_phase_sets
+    );
+    Ok(())
 }
 
 /// The run function
@@ -98,12 +458,19 @@ fn main() {
 /// - if various command-line arguments are specified multiple times or with invalid values
 /// - if a file/directory already exists and -F option isn't set (e.g. vcf output or vcf debug directory)
 /// - input bam file isn't indexed
-fn run() -> Result<()> {
+fn run(args: Vec<String>, subcommand: Subcommand) -> Result<()> {
     /***********************************************************************************************/
     // READ COMMAND LINE ARGUMENTS
     /***********************************************************************************************/
 
-    eprintln!("");
+    // the log level is set first, so that it also applies to the messages of the preset detection
+    init_logging(parse_log_level(&log_level_arg(&args))?);
+
+    info!("");
+
+    // a technology preset sets the options that aren't given on the command line, and with
+    // --preset auto it is chosen from the platform of the reads
+    let (args, preset) = apply_preset(resolve_auto_preset(args)?);
 
     let input_args = App::new("Longshot: variant caller (SNVs) for long-read sequencing data")
         //.version(crate_version!())
@@ -113,15 +480,17 @@ fn run() -> Result<()> {
                 .short("b")
                 .long("bam")
                 .value_name("BAM")
-                .help("sorted, indexed BAM file with error-prone reads")
+                .help("sorted, indexed BAM file with error-prone reads, or an http:// or ftp:// URL of one (with the index at the same URL plus .bai), or - for a coordinate-sorted BAM file without an index on standard input (e.g. from samtools sort), which is copied to a temporary indexed file in $TMPDIR for the run. Specify multiple times (e.g. -b s1.bam -b s2.bam) to call variants in multiple samples: the candidate sites are shared, each sample is genotyped and phased separately, and the calls are written to one multi-sample VCF. Several BAM files of the same sample (e.g. of different flowcells) can be given as a comma-separated list (e.g. -b fc1.bam,fc2.bam), and their reads are merged on the fly as if the files had been merged with samtools merge.")
                 .display_order(10)
                 .required(true)
+                .multiple(true)
+                .number_of_values(1)
                 .takes_value(true))
         .arg(Arg::with_name("Input FASTA")
                 .short("f")
                 .long("ref")
                 .value_name("FASTA")
-                .help("indexed FASTA reference that BAM file is aligned to")
+                .help("indexed FASTA reference that BAM file is aligned to. It can be compressed with bgzip (indexed with both .fai and .gzi).")
                 .display_order(20)
                 .required(true)
                 .takes_value(true))
@@ -131,7 +500,7 @@ fn run() -> Result<()> {
                 .value_name("VCF")
                 .help("output VCF file with called variants.")
                 .display_order(30)
-                .required(true)
+                .required(subcommand.writes_vcf())
                 .takes_value(true))
         .arg(Arg::with_name("Region")
                 .short("r")
@@ -141,19 +510,171 @@ fn run() -> Result<()> {
                 .display_order(40)
                 //.required(true)
                 .takes_value(true))
+        .arg(Arg::with_name("Target BED")
+                .long("bed")
+                .value_name("BED")
+                .help("BED file of target regions in which to call variants, e.g. the targets of a panel or of an adaptive sampling run. Candidate discovery, read realignment, genotyping and alignment parameter estimation are restricted to these regions. Overlapping regions are merged and each region is phased separately. Cannot be used with --region, --stream_output, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir.")
+                .display_order(41)
+                .takes_value(true))
+        .arg(Arg::with_name("Target BED padding")
+                .long("bed_padding")
+                .value_name("int")
+                .help("Extend each region of the --bed file by this many bases on both sides.")
+                .display_order(42)
+                .default_value("0"))
+        .arg(Arg::with_name("Preset")
+                .long("preset")
+                .value_name("string")
+                .help("Use the defaults of a sequencing technology: ont-r10, ont-r9, pacbio-hifi or pacbio-clr, or auto to choose the preset from the platform of the first BAM file. The platform is detected from the read groups (PL and PM tags) and programs of the BAM header and the error rate (NM tags) and base qualities of its first reads, and the chosen preset is logged; give a preset by name to override it. The preset sets --band_width, --max_window, --max_snvs, --candidate_fdr and --missing_base_qual_value (and --max_indel_len for pacbio-hifi), which can be overridden individually, and uses typical alignment parameters of the technology instead of estimating them from the reads (unless --read-params, --context_error_model, --strand_error_model or --rg_error_model is given).")
+                .display_order(43)
+                .possible_values(&PRESET_ARG_VALUES)
+                .takes_value(true))
         .arg(Arg::with_name("Potential Variants VCF")
             .short("v")
             .long("potential_variants")
             .value_name("VCF")
-            .help("Genotype and phase the variants in this VCF instead of using pileup method to find variants. NOTES: VCF must be gzipped and tabix indexed or contain contig information. Use with caution because excessive false potential variants can lead to inaccurate results. Every variant is used and only the allele fields are considered -- Genotypes, filters, qualities etc are ignored. Indel variants will be genotyped but not phased. Triallelic variants and structural variants are currently not supported.")
+            .help("Genotype and phase the variants in this VCF instead of using pileup method to find variants. NOTES: VCF must be gzipped and tabix indexed or contain contig information. Use with caution because excessive false potential variants can lead to inaccurate results. Every variant is used and only the allele fields are considered -- Genotypes, filters, qualities etc are ignored. Indel and MNV variants will be genotyped but not phased. Multiallelic variants, and separate records at the same position, are genotyped as a single multiallelic site. Structural variants are currently not supported.")
+            .display_order(45)
+            .takes_value(true))
+        .arg(Arg::with_name("Candidate BAM")
+            .long("candidate_bam")
+            .value_name("BAM")
+            .help("Find the potential variants in the reads of this BAM file instead of the reads of --bam, e.g. in accurate short reads (Illumina) aligned to the same reference, whose low error rate finds the candidate SNVs cleanly. The candidates are genotyped and phased with the reads of --bam as usual. The error model of the pileup of these reads is estimated from their alignments, and their max coverage is estimated separately with -A.")
+            .display_order(45)
+            .takes_value(true))
+        .arg(Arg::with_name("Candidates output")
+            .long("candidates-out")
+            .value_name("VCF")
+            .help("Write the candidate variants found by the discovery stage (pileup, --local_assembly or --candidate_bam) to a sites-only VCF, with the contigs of the reference in its header. The VCF can be given to --candidates-in to skip the discovery stage in reruns with different genotyping and phasing options, e.g. in parameter sweeps. Cannot be used with --potential_variants or --candidates-in.")
+            .display_order(45)
+            .takes_value(true))
+        .arg(Arg::with_name("Candidates input")
+            .long("candidates-in")
+            .value_name("VCF")
+            .help("Genotype and phase the candidate variants of a --candidates-out VCF instead of finding them again. The candidates of the regions being called are used as if they had just been found, so the output is the same as that of a run with discovery (and the same discovery options) that changes only the genotyping and phasing options. The BAM files must be aligned to the same reference as in the run that wrote the VCF. Cannot be used with --potential_variants, --local_assembly or --candidate_bam.")
             .display_order(45)
             .takes_value(true))
+        .arg(Arg::with_name("Genotype only")
+            .long("genotype_only")
+            .help("Force-genotype every record of the --potential_variants VCF (SNVs, indels and MNVs) by realigning the reads to the alleles, and write every site to the output VCF even if its genotype is homozygous reference. The output then has the same sites for every sample genotyped with the same input VCF. Records with symbolic or non-ACGT alleles, or alleles longer than 50 bp, are skipped with a warning.")
+            .display_order(46))
         .arg(Arg::with_name("Bam Output")
             .short("O")
             .long("out_bam")
             .value_name("BAM")
-            .help("Write new bam file with haplotype tags (HP:i:1 and HP:i:2) for reads assigned to each haplotype, any existing HP and PS tags are removed")
+            .help("Write new bam file with haplotype tags (HP:i:1 and HP:i:2) for reads assigned to each haplotype, any existing HP and PS tags are removed. Reads covering phased variants are also tagged with the PHRED-scaled log-likelihood margin between their best and second-best haplotype assignment (HM:f). Reads used for realignment are tagged with their effective per-base error rate against their best haplotypes (ER:f), e.g. to weight reads for consensus or polishing. Secondary, supplementary and low MAPQ alignments of an assigned read are tagged with the same haplotype, even if they don't overlap a variant, if they are on the contig of its phase block.")
+            .display_order(50))
+        .arg(Arg::with_name("Haplotype coverage")
+            .long("hap_coverage")
+            .value_name("prefix")
+            .help("Write bedGraph tracks of the read depth of each haplotype to <prefix>.hap1.bedgraph and <prefix>.hap2.bedgraph, counting the primary alignments assigned to each haplotype as for --out_bam. Regions where one haplotype has much lower coverage than the other point to haplotype dropout (e.g. deletions or allelic mapping bias).")
+            .display_order(50))
+        .arg(Arg::with_name("Haplotype FASTQ")
+            .long("hap_fastq")
+            .value_name("prefix")
+            .help("Write the reads assigned to each haplotype (as for --out_bam) and the unassigned reads to <prefix>.hap1.fastq.gz, <prefix>.hap2.fastq.gz and <prefix>.unassigned.fastq.gz, e.g. for haplotype-resolved de novo assembly. Each read is written once, from its primary alignment, in its original orientation and with its base qualities.")
             .display_order(50))
+        .arg(Arg::with_name("Consensus FASTA")
+            .long("consensus")
+            .value_name("FASTA")
+            .help("Write the consensus sequence of each region (or contig) with the variant calls applied to a FASTA file, e.g. for amplicon workflows that need sequences rather than a VCF. Regions with phased heterozygous variants get a sequence for each haplotype (<sample>_<region>_hap1 and _hap2), other regions a single sequence in which heterozygous variants are written as IUPAC ambiguity codes. Unphased heterozygous indels and filtered variants are not applied.")
+            .display_order(51))
+        .arg(Arg::with_name("Block consensus")
+            .long("block_consensus")
+            .value_name("prefix")
+            .help("Write the two haplotype sequences of each phase block, with the phased variants of the block applied to the reference, to <prefix>.fa (<sample>_<chrom>:<start>-<end>_PS<phase set>_hap1 and _hap2), and the coordinates of the blocks with the sequence names and the number of phased variants to <prefix>.bed. A quick pseudo-assembly of phased loci such as HLA genes. Variants of other phase blocks within a block are written as unphased, i.e. as IUPAC ambiguity codes for SNVs. Filtered variants are not applied.")
+            .display_order(51))
+        .arg(Arg::with_name("Fragments Output")
+            .long("out-fragments")
+            .value_name("path")
+            .help("Write the haplotype fragments (the allele observed at each variant by each read) to a file in the HapCUT2 fragment format, with per-allele quality values. Variants are numbered in the order of the output VCF, so the file can be used with the VCF to phase with external tools or combined with other fragment files (e.g. from Hi-C). Fragments are written after genotyping, for a single sample with ploidy 1 or 2.")
+            .display_order(51))
+        .arg(Arg::with_name("Read matrix output")
+            .long("out-read-matrix")
+            .value_name("path")
+            .help("Write the allele calls of the reads to a TSV file with a line per read and variant: the read name, the variant (chromosome, position, reference and alternate alleles), the allele observed in the read, the log10 likelihood ratio of that allele against the other alleles, and the haplotype and phase set the read is assigned to (as for --out_bam). Useful as raw evidence to train variant filters or to review discordant calls. Supports a single BAM file with ploidy 1 or 2.")
+            .display_order(52))
+        .arg(Arg::with_name("Read haplotypes output")
+            .long("out-read-haps")
+            .value_name("path")
+            .help("Write the haplotype assignment of each read to a TSV file with a line per read that has an allele call at a phased heterozygous variant: the read name, the chromosome, the phase set (PS), the haplotype the read is assigned to (HP: 1 or 2, or . if neither haplotype passes --hap_assignment_qual or --hap_tag_posterior), and the posterior probability of the more likely haplotype. Allele-specific analyses can drop ambiguous reads by their posterior. Supports a single BAM file with ploidy 1 or 2, and can't be used with --no_haps.")
+            .display_order(52))
+        .arg(Arg::with_name("Junctions output")
+            .long("out-junctions")
+            .value_name("path")
+            .help("Write the confidence of the phase between each pair of consecutive phased heterozygous variants of a phase block to a TSV file: the chromosome, the positions of the two variants, the phase set, the number of reads with allele calls on both sides, the PHRED-scaled probability that the phase is switched between them, and whether the block was split there (see --min_junction_qual). Supports a single BAM file with ploidy 1 or 2.")
+            .display_order(52))
+        .arg(Arg::with_name("Evidence output")
+            .long("out-evidence")
+            .value_name("NPZ")
+            .help("Write the per-read evidence of each candidate site to a NumPy .npz archive, e.g. to train a model that re-scores the calls: a matrix with a row per read with an allele call at the site and the features allele, ln_p_allele, ln_p_miscall, mapq, base_qual, reverse_strand, read_pos, ln_p_hap1 and ln_p_hap2 (the log-probabilities that the call is right and wrong, the read's base quality and position at the variant, and the log posterior probabilities of its haplotypes), and the position, alleles, QUAL and genotype of each site. The evidence is kept in memory until the end of the run, so it is meant for training regions rather than whole genomes. Supports a single BAM file with ploidy 1 or 2, and cannot be used with --checkpoint_dir.")
+            .display_order(52))
+        .arg(Arg::with_name("Parquet output")
+            .long("out-parquet")
+            .value_name("path")
+            .help("Write the final calls to an Apache Parquet file with a row per variant and sample, for cohort analytics without parsing the VCF (e.g. with Spark, Polars or DuckDB). The columns are chrom, pos, ref, alt, sample, gt, gq, qual, filter, dp, ad, ref_count, alt_count and phase_set (null for unphased calls), with the values of the VCF fields. Supports ploidy 1 or 2, and cannot be used with --checkpoint_dir.")
+            .display_order(52))
+        .arg(Arg::with_name("Dense regions output")
+            .long("out-dense-regions")
+            .value_name("BED")
+            .help("Write the regions of variants flagged by the variant density filter (dn, see --density_params) to a BED file, with the filters of the variants as a reason code (e.g. dn;dp if some of them also exceed the maximum coverage), the number of variants, how many of them are heterozygous and homozygous alternate, and the sample name. Such regions are often mapping artifacts (e.g. collapsed repeats) or real divergent haplotypes.")
+            .display_order(52))
+        .arg(Arg::with_name("Callable regions output")
+            .long("out-callable")
+            .value_name("BED")
+            .help("Write the callable and uncallable regions of each sample to a BED file, with a status for each region: CALLABLE, NO_COVERAGE (no reads), LOW_COVERAGE (fewer reads than --min_cov), EXCESSIVE_COVERAGE (more reads than --max_cov), REF_N (ambiguous reference base) or DENSE (variants flagged by the variant density filter), and the sample name (only the tumor in --somatic mode, and the candidate BAM instead of the samples with --candidate_bam). The depth is the effective depth of candidate discovery, i.e. the reads passing the filters and --min_mapq, counted as for --depth_definition. Use it to restrict benchmarks (e.g. against GIAB) to the regions where longshot could call variants. Cannot be used with --potential_variants, --candidates-in or --local_assembly.")
+            .display_order(52))
+        .arg(Arg::with_name("Soft clip clusters output")
+            .long("out-soft-clip-clusters")
+            .value_name("BED")
+            .requires("Soft clip cluster")
+            .help("Write the clusters of soft-clipped reads found with --soft_clip_cluster to a BED file, as putative structural variant or misassembly loci: the region, the number of clipped read ends, how many of them are clipped before and after the breakpoint (an insertion has both), the number of SNV candidates that were suppressed, and the sample name.")
+            .display_order(52))
+        .arg(Arg::with_name("Haplotype iteration stats")
+            .long("hap_iteration_stats")
+            .value_name("path")
+            .help("Write the diagnostics of each round of haplotype assembly and genotype refinement to a tab-separated file: the region and sample, the number of phased heterozygous SNVs, the total likelihood (PHRED-scaled) before and after haplotype assembly and after genotype refinement, the number of genotypes that changed, and the number of passes of the genotype refinement.")
+            .display_order(58))
+        .arg(Arg::with_name("Stats output")
+            .long("stats-out")
+            .value_name("JSON")
+            .help("Write statistics of the run to a JSON file for QC: the reads used and filtered by reason, the distribution of the realignment score differences of the reads (best haplotypes vs. BAM alignment, PHRED-scaled; a long tail points to mapping problems or mis-estimated alignment parameters), the candidate variants considered, called and filtered by reason, the calls, the phasing metrics (phase block N50, fraction of heterozygous SNVs phased and the span of each phase block), the running time and work of each stage (alignments, dynamic programming cells computed and alignment cache hits) and the effective parameters (including the defaults, the values set by --preset and the alignment parameters of each sample).")
+            .display_order(52))
+        .arg(Arg::with_name("Liftover chain")
+            .long("liftover_chain")
+            .value_name("chain")
+            .requires("Liftover output")
+            .help("Lift the output VCF over to another reference assembly with a UCSC chain file (e.g. hg19ToHg38.over.chain), and write it to --liftover_out. Unlike generic liftover tools, phase blocks are only kept together while their variants map through the same chain: a phase block that spans a rearrangement between the assemblies is split, its phase sets are renamed to the lifted position of their first variant, and variants left alone in a phase set are unphased. Variants that don't map, and indels on chains to the reverse strand, are dropped.")
+            .display_order(53))
+        .arg(Arg::with_name("Liftover output")
+            .long("liftover_out")
+            .value_name("VCF")
+            .requires("Liftover chain")
+            .help("Output VCF file for the variants lifted over with --liftover_chain.")
+            .display_order(54))
+        .arg(Arg::with_name("SV output")
+            .long("sv_vcf")
+            .value_name("VCF")
+            .help("Write the breakpoints of large deletions and insertions to a separate VCF, as symbolic <DEL> and <INS> records. They are found from long deletions and insertions in the CIGAR strings and from split reads (supplementary alignments on the same contig and strand, from the SA tag), and are genotyped from the supporting reads and the reads spanning the breakpoint. Where both haplotypes of a phase block have assigned reads at the breakpoint, the genotype is phased with the surrounding SNVs. Supports a single BAM file with ploidy 1 or 2.")
+            .display_order(55))
+        .arg(Arg::with_name("Min SV length")
+            .long("min_sv_len")
+            .value_name("int")
+            .default_value("50")
+            .help("Minimum length of the deletions and insertions written to --sv_vcf.")
+            .display_order(56))
+        .arg(Arg::with_name("Min SV support")
+            .long("min_sv_support")
+            .value_name("int")
+            .default_value("3")
+            .help("Minimum number of reads supporting a deletion or insertion to write it to --sv_vcf.")
+            .display_order(57))
+        .arg(Arg::with_name("Genotype SV VCF")
+            .long("genotype_sv")
+            .value_name("VCF")
+            .requires("SV output")
+            .help("Genotype and phase the deletions and insertions of a VCF from a structural variant caller (e.g. cuteSV or Sniffles) instead of finding breakpoints, and write them to --sv_vcf with their ID, REF and ALT. A read supports an event if it has a matching deletion or insertion in its CIGAR string or between its split alignments, or else if it is more likely when realigned to the alternate haplotype than to the reference haplotype around the event (for events of up to 5 kb with a known sequence). The events are phased with the surrounding SNVs like the breakpoints of --sv_vcf, and need --min_sv_support supporting reads to be non-reference. Other records (inversions, duplications, breakends) are skipped.")
+            .display_order(57))
         .arg(Arg::with_name("Auto max coverage")
             .short("A")
             .long("auto_max_cov")
@@ -173,6 +694,24 @@ fn run() -> Result<()> {
                 .help("Maximum coverage (of reads passing filters) to consider position as a potential SNV.")
                 .display_order(80)
                 .default_value("8000"))
+        .arg(Arg::with_name("Depth definition")
+                .long("depth_definition")
+                .value_name("all|gap_excluded")
+                .help("Which reads count toward the read depth of a site, for the DP field, --min_cov, --max_cov and --auto_max_cov. With 'all', every read spanning the site counts, including reads with a deletion there. With 'gap_excluded', only reads with a base aligned to the site count, which matches the default of samtools depth. Clipped bases are never counted.")
+                .display_order(81)
+                .default_value("all"))
+        .arg(Arg::with_name("Max depth")
+                .long("max-depth")
+                .value_name("int")
+                .help("Downsample the reads to at most this many reads starting in each 1 kb window before realigning them, e.g. for amplicon data with a very high coverage. The reads are chosen at random with a fixed seed, so reruns use the same reads, and are sampled within strata of read length and mean base quality, so that the kept reads have the same length and quality distributions as all of the reads (in particular, the few ultralong reads that link the most variants are kept in proportion). The candidate variants are still found using all of the reads, so --max_cov must be high enough for the full coverage.")
+                .display_order(82)
+                .takes_value(true))
+        .arg(Arg::with_name("Max realign depth")
+                .long("max_realign_depth")
+                .value_name("int")
+                .help("Realign at most this many reads to each variant. At variants with more reads (e.g. in amplicon data), the most informative reads are kept: the reads that span the largest possible realignment window on both sides of the variant first, then the reads with the highest mapping quality and the longest span, taken from the two strands in turn so that the kept reads are balanced between the strands. The number of reads skipped at a variant is written to the INFO field RSKIP.")
+                .display_order(82)
+                .takes_value(true))
         .arg(Arg::with_name("Min mapq")
                 .short("q")
                 .long("min_mapq")
@@ -180,6 +719,105 @@ fn run() -> Result<()> {
                 .help("Minimum mapping quality to use a read.")
                 .display_order(90)
                 .default_value("20"))
+        .arg(Arg::with_name("Min read length")
+                .long("min_read_length")
+                .value_name("int")
+                .help("Minimum length of the read sequence (excluding hard-clipped bases) to use a read for candidate discovery and realignment.")
+                .display_order(90)
+                .default_value("0"))
+        .arg(Arg::with_name("Max read length")
+                .long("max_read_length")
+                .value_name("int")
+                .help("Maximum length of the read sequence (excluding hard-clipped bases) to use a read for candidate discovery and realignment.")
+                .display_order(90)
+                .takes_value(true))
+        .arg(Arg::with_name("Keep secondary")
+                .long("keep_secondary")
+                .help("Use secondary alignments (flag 0x100) for candidate discovery and realignment. Secondary alignments written without their sequence (SEQ '*', as by minimap2) are still skipped.")
+                .display_order(90))
+        .arg(Arg::with_name("Keep supplementary")
+                .long("keep_supplementary")
+                .help("Use supplementary alignments (flag 0x800) for candidate discovery and realignment, e.g. the parts of long reads split across a structural variant.")
+                .display_order(90))
+        .arg(Arg::with_name("Stitch split reads")
+                .long("stitch_split_reads")
+                .help("Use supplementary alignments, and merge the allele calls of all alignments of a read on the same contig (on either strand) into one haplotype fragment, so that phase blocks can be linked across reference gaps and large structural variants, including inversions, that split ultra-long reads. Each allele call keeps the strand of its alignment for the strand bias counts. Where the alignments overlap, the more confident allele call is kept, or the variant is skipped if they disagree.")
+                .display_order(90))
+        .arg(Arg::with_name("Partial reads")
+                .long("partial_reads")
+                .help("Use the reads that start or end within the realignment window of a variant (e.g. reads ending inside a cluster of variants), by aligning them to the haplotypes with a free end instead of skipping the variant. The read then only counts as evidence for the alleles it covers.")
+                .display_order(90))
+        .arg(Arg::with_name("MAPQ likelihood")
+                .long("mapq_likelihood")
+                .help("Mix the allele calls of each read with a model in which the read is mismapped and supports a random allele, weighted by the mismapping probability of its mapping quality, so that reads from segmental duplications and other paralogous regions carry less evidence. Reads with mapping quality 255 (unknown) are not adjusted.")
+                .display_order(90))
+        .arg(Arg::with_name("Keep duplicates")
+                .long("keep_duplicates")
+                .help("Use reads marked as PCR or optical duplicates (flag 0x400) for candidate discovery and realignment.")
+                .display_order(90))
+        .arg(Arg::with_name("Keep QC fail")
+                .long("keep_qc_fail")
+                .help("Use reads that failed the platform or vendor quality checks (flag 0x200) for candidate discovery and realignment.")
+                .display_order(90))
+        .arg(Arg::with_name("Required flags")
+                .long("require_flags")
+                .value_name("int")
+                .help("Only use reads with all of these SAM flag bits set for candidate discovery and realignment (decimal or hexadecimal with a 0x prefix, like samtools view -f).")
+                .display_order(90)
+                .default_value("0"))
+        .arg(Arg::with_name("Excluded flags")
+                .long("exclude_flags")
+                .value_name("int")
+                .help("Only use reads with none of these SAM flag bits set for candidate discovery and realignment (decimal or hexadecimal with a 0x prefix, like samtools view -F).")
+                .display_order(90)
+                .default_value("0"))
+        .arg(Arg::with_name("Min alignment score")
+                .long("min_alignment_score")
+                .value_name("int")
+                .help("Minimum alignment score (AS tag) to use a read. Reads without an AS tag are not filtered.")
+                .display_order(91)
+                .allow_hyphen_values(true)
+                .takes_value(true))
+        .arg(Arg::with_name("Min aligned fraction")
+                .long("min_aligned_frac")
+                .value_name("float")
+                .help("Minimum fraction of the read length (including clipped bases) that must be aligned to the reference to use a read.")
+                .display_order(93)
+                .default_value("0.0"))
+        .arg(Arg::with_name("Max soft clip")
+                .long("max_soft_clip")
+                .value_name("int")
+                .help("Maximum total number of soft-clipped bases allowed to use a read.")
+                .display_order(95)
+                .takes_value(true))
+        .arg(Arg::with_name("Missing base quality policy")
+                .long("missing_base_qual")
+                .value_name("keep|reject")
+                .help("How to handle reads without base qualities (a '*' QUAL field). Longshot estimates allele qualities by realigning the reads, which does not use base qualities, so these reads are kept by default, with the base quality of --missing_base_qual_value where base qualities are used (the allele calls of --fast and the base qualities of the allele calls in --out-evidence). With 'reject', they are not used for genotyping and phasing. The number of these reads is reported after fragment extraction, and the policy is written to the parameters of --stats-out.")
+                .display_order(95)
+                .default_value("keep"))
+        .arg(Arg::with_name("Missing base quality value")
+                .long("missing_base_qual_value")
+                .value_name("int")
+                .help("Base quality assumed for the bases of reads without base qualities with --missing_base_qual keep. Set by the preset to the typical base quality of the technology: 20 for ont-r10, 10 for ont-r9, 30 for pacbio-hifi and 10 for pacbio-clr.")
+                .display_order(95)
+                .default_value("20"))
+        .arg(Arg::with_name("Strict")
+                .long("strict")
+                .help("Stop with an error at the first read with a malformed or unsupported CIGAR string (e.g. a hard clip between other operations, a padded alignment, a zero-length operation, an insertion or deletion before the first or after the last aligned base, or a CIGAR that doesn't match the length of the read sequence), e.g. to validate a BAM file. By default, these reads are skipped with a warning and counted with the filtered reads.")
+                .display_order(95))
+        .arg(Arg::with_name("Soft clip window")
+                .long("soft_clip_window")
+                .value_name("int")
+                .help("Annotate each variant with the fraction of the overlapping reads that are soft-clipped within this many bases of it (SCF in the INFO field). High values indicate a structural variant breakpoint or a reference error near the variant. Also the maximum distance between consecutive clipped read ends of a soft-clip cluster (see --soft_clip_cluster).")
+                .display_order(96)
+                .default_value("10"))
+        .arg(Arg::with_name("Soft clip cluster")
+                .long("soft_clip_cluster")
+                .value_name("int")
+                .help("Find clusters of at least this many soft-clipped read ends, where consecutive clipped ends are at most --soft_clip_window bases apart. Such recurrent breakpoints mark putative structural variants (e.g. insertions too long for the aligner) or misassemblies of the reference, where the aligned bases are often misaligned: the SNV candidates in a cluster (padded by --soft_clip_window) are not used for genotyping and phasing, and get the FILTER flag sc. Off by default.")
+                .display_order(96)
+                .takes_value(true))
         .arg(Arg::with_name("Min allele quality")
             .short("a")
             .long("min_allele_qual")
@@ -194,6 +832,11 @@ fn run() -> Result<()> {
             .help("Minimum quality (Phred-scaled) of read->haplotype assignment (for read separation).")
             .display_order(94)
             .default_value("20.0"))
+        .arg(Arg::with_name("Haplotype tag posterior")
+            .long("hap_tag_posterior")
+            .value_name("float")
+            .help("Minimum posterior probability of the haplotype of a read for the read to be assigned to it (tagged with HP in --out_bam and --out-read-haps, and counted in --hap_coverage, --hap_fastq and the HDP/HAD fields). The same threshold as --hap_assignment_qual, given as a probability, e.g. 0.99 for a quality of 20. Must be at least 0.5 and less than 1.")
+            .display_order(94))
         .arg(Arg::with_name("Potential SNV Cutoff")
             .long("potential_snv_cutoff")
             .short("Q")
@@ -205,16 +848,25 @@ fn run() -> Result<()> {
             .long("min_alt_count")
             .short("e")
             .value_name("int")
-            .help("Require a potential SNV to have at least this many alternate allele observations.")
-            .display_order(97)
-            .default_value("3"))
+            .help("Require a potential SNV to have at least this many alternate allele observations. By default (if neither this option nor --min_alt_frac is set), the minimum count at each read depth is derived from the estimated error rate instead (see --candidate_fdr). If only --min_alt_frac is set, the minimum count is 3.")
+            .display_order(97))
         .arg(Arg::with_name("Potential SNV Min Alt Fraction")
             .long("min_alt_frac")
             .short("E")
             .value_name("float")
-            .help("Require a potential SNV to have at least this fraction of alternate allele observations.")
-            .display_order(98)
-            .default_value("0.125"))
+            .help("Require a potential SNV to have at least this fraction of alternate allele observations. By default (if neither this option nor --min_alt_count is set), there is no minimum fraction and the minimum count at each read depth is derived from the estimated error rate instead (see --candidate_fdr). If only --min_alt_count is set, the minimum fraction is 0.125.")
+            .display_order(98))
+        .arg(Arg::with_name("Candidate FDR")
+            .long("candidate_fdr")
+            .value_name("float")
+            .help("When deriving the potential SNV thresholds from the estimated error rate, use the smallest alternate allele count at each read depth for which the expected fraction of potential SNVs caused by sequencing errors is at most this amount.")
+            .display_order(101)
+            .default_value("0.05"))
+        .arg(Arg::with_name("Candidate sensitivity")
+            .long("candidate_sensitivity")
+            .value_name("float")
+            .help("When deriving the potential SNV thresholds from the estimated error rate, lower the alternate allele count at each read depth (but not below 2) where a heterozygous SNV would pass it with a probability less than this amount. With high error rates, the counts for --candidate_fdr can miss many variants at low depth. Off by default.")
+            .display_order(101))
         .arg(Arg::with_name("Haplotype Convergence Delta")
             .long("hap_converge_delta")
             .short("L")
@@ -222,6 +874,56 @@ fn run() -> Result<()> {
             .help("Terminate the haplotype/genotype iteration when the relative change in log-likelihood falls below this amount. Setting a larger value results in faster termination but potentially less accurate results.")
             .display_order(99)
             .default_value(&"0.0001"))
+        .arg(Arg::with_name("Haplotype max rounds")
+            .long("hap_max_rounds")
+            .value_name("int")
+            .help("Maximum number of rounds of haplotype assembly and haplotype-informed genotype refinement.")
+            .display_order(102)
+            .default_value("100"))
+        .arg(Arg::with_name("Haplotype converge changes")
+            .long("hap_converge_changes")
+            .value_name("int")
+            .help("Also terminate the haplotype/genotype iteration when at most this many genotypes (ignoring phase) changed in a round.")
+            .display_order(103))
+        .arg(Arg::with_name("No genotype refinement")
+            .long("no_genotype_refinement")
+            .help("Don't refine the genotypes with the assembled haplotypes: the genotypes from the read pileup are phased with a single round of haplotype assembly. Faster, but less accurate in regions where the pileup genotypes are uncertain.")
+            .display_order(104))
+        .arg(Arg::with_name("Phaser")
+            .long("phaser")
+            .value_name("algorithm")
+            .possible_values(&PHASER_NAMES)
+            .help("Haplotype assembly algorithm: maxcut (the max-cut heuristic of HapCUT2) or wmec (an exact weighted minimum error correction dynamic program, as in WhatsHap). wmec finds the optimal haplotypes for the reads that it uses, but its runtime and memory grow exponentially with the coverage, so the reads are first selected such that at most --wmec_max_cov of them span each variant. It is mainly useful for small regions such as targeted panels, and cannot be combined with --methylation_phasing.")
+            .display_order(104)
+            .default_value("maxcut"))
+        .arg(Arg::with_name("wMEC max coverage")
+            .long("wmec_max_cov")
+            .value_name("int")
+            .help("Maximum number of reads that span a variant with --phaser wmec (between 1 and 20). Reads with the most variants are preferred.")
+            .display_order(104)
+            .default_value("15"))
+        .arg(Arg::with_name("MAPQ weighted phasing")
+            .long("mapq_weighted_phasing")
+            .help("Weight the reads by their mapping quality in haplotype assembly: each allele call of a read is also counted as wrong with the probability that the read is mismapped, so that reads with a low mapping quality (e.g. from segmental duplications) are less likely to cause switch errors in otherwise well-supported phase blocks. The allele calls used for genotyping are not changed.")
+            .display_order(104))
+        .arg(Arg::with_name("Min junction quality")
+            .long("min_junction_qual")
+            .value_name("float")
+            .help("Split the phase blocks between consecutive phased heterozygous variants where the PHRED-scaled probability that the phase is switched (from the reads with allele calls on both sides, see --out-junctions) is below this value, e.g. 10. A shorter block is better than a long block with a hidden switch error. Off by default.")
+            .display_order(104))
+        .arg(Arg::with_name("Singleton phase")
+            .long("singleton_phase")
+            .value_name("mode")
+            .possible_values(&SINGLETON_PHASE_NAMES)
+            .help("What to do with the heterozygous variants that are the only variant of their phase block, i.e. no read links them to another phased variant: keep (write them phased with their own phase set, the default), unphase (write an unphased genotype), drop-ps (write the phased genotype without a PS) or rescue (join them to the phase block that the reads covering them are assigned to, using all of their allele calls at the variant, if the phase has at least --singleton_rescue_qual; otherwise unphase them).")
+            .display_order(104)
+            .default_value("keep"))
+        .arg(Arg::with_name("Singleton rescue quality")
+            .long("singleton_rescue_qual")
+            .value_name("float")
+            .help("Minimum quality (Phred-scaled) of the phase of a rescued singleton variant (--singleton_phase rescue).")
+            .display_order(104)
+            .default_value("20.0"))
         .arg(Arg::with_name("Anchor length")
                 .short("l")
                 .long("anchor_length")
@@ -236,6 +938,36 @@ fn run() -> Result<()> {
                 .help("Cut off variant clusters after this many variants. 2^m haplotypes must be aligned against per read for a variant cluster of size m.")
                 .display_order(130)
                 .default_value("3"))
+        .arg(Arg::with_name("Cluster work budget")
+                .long("cluster_work_budget")
+                .value_name("int")
+                .help("Maximum number of short-haplotypes aligned per read for a variant cluster (the product of the numbers of alleles of its variants). Clusters that exceed it, or that have more than --max_snvs variants, are split between the adjacent variants whose realignment windows overlap the least, and the variants next to the split are marked with the CSPLIT INFO flag. Set to 0 to cut clusters after every --max_snvs variants instead.")
+                .display_order(131)
+                .default_value("64"))
+        .arg(Arg::with_name("Max indel length")
+                .long("max_indel_len")
+                .value_name("int")
+                .help("Call potential insertions and deletions up to this length using the read pileup, and genotype them with pair-HMM realignment. Indels are genotyped but not phased. Set to 0 to call SNVs only.")
+                .display_order(131)
+                .default_value("0"))
+        .arg(Arg::with_name("Max alt alleles")
+                .long("max_alt_alleles")
+                .value_name("int")
+                .help("Maximum number of alternate SNV alleles at a potential variant site. The alternate bases are considered in order of decreasing count, and each one that passes the potential SNV cutoffs on its own is kept, so that triallelic and quadallelic sites are genotyped over all of their alleles and written as multiallelic VCF records. Multiallelic sites are genotyped but not phased. Must be between 1 and 3; the default of 3 keeps every alternate base that passes the cutoffs. Set to 1 to keep only the most frequent alternate base, as versions before multiallelic calling did.")
+                .display_order(131)
+                .default_value("3"))
+        .arg(Arg::with_name("Local assembly")
+                .long("local_assembly")
+                .help("Find potential variants by local assembly instead of counting alleles in pileup columns. Windows where enough reads differ from the reference (by the --min_alt_count and --min_alt_frac thresholds) are assembled into a small de Bruijn graph, and the variants come from the paths through the graph. This recovers clustered variants and small complex events that are spread over several pileup columns. Indels and complex variants are only found with a --max_indel_len greater than 0.")
+                .display_order(132))
+        .arg(Arg::with_name("Merge MNVs")
+                .long("merge_mnvs")
+                .help("Merge phased variants that are adjacent on the same haplotype (each one starting right after the reference allele of the previous one) into a single MNV or complex record with the INFO flag MNV, so that e.g. two SNVs in one codon are annotated as one amino acid change. Homozygous variants are merged with the phased variants next to them. Supports a single BAM file.")
+                .display_order(133))
+        .arg(Arg::with_name("Keep MNV components")
+                .long("keep_mnv_components")
+                .help("With --merge_mnvs, also write the merged variants, with the position of their MNV record in the INFO field MID.")
+                .display_order(134))
         /*.arg(Arg::with_name("Use POA")
             .short("p")
             .long("poa")
@@ -245,9 +977,25 @@ fn run() -> Result<()> {
                 .short("W")
                 .long("max_window")
                 .value_name("int")
-                .help("Maximum \"padding\" bases on either side of variant realignment window")
+                .help("Maximum \"padding\" bases on either side of variant realignment window. Realignment windows longer than 100 kb (e.g. of ultra-long reads) are aligned in chunks of 100 kb, and windows longer than 10 Mb are an error. Must be at most 10000000.")
                 .display_order(150)
                 .default_value("50"))
+        .arg(Arg::with_name("Window flank")
+                .long("window_flank")
+                .value_name("int")
+                .help("Minimum number of bases between a variant and the anchors of its realignment window. The window is padded by this many bases on either side of the variant (within the aligned part of the read) before the anchors are searched for, and --max_window is measured from the padded window. Larger flanks help to realign reads with errors next to the variant, at the cost of longer alignments.")
+                .display_order(150)
+                .default_value("0"))
+        .arg(Arg::with_name("Exact anchors")
+                .long("exact_anchors")
+                .help("Only end realignment windows at anchors that match the read exactly and are unique within the maximum window. By default, a window without such an anchor within --max_window bases of the variant ends there anyway; with this flag, the variant is skipped for the read instead.")
+                .display_order(150))
+        .arg(Arg::with_name("Window merge distance")
+                .long("window_merge_distance")
+                .value_name("int")
+                .help("Merge the realignment windows of nearby variants on a read when a window starts fewer than this many bases after the end of the previous one, so that the read is realigned once to the short-haplotypes of all of their variants rather than once per window. Overlapping windows are always merged. Merged windows are still split by --max_snvs and --cluster_work_budget.")
+                .display_order(150)
+                .default_value("0"))
         .arg(Arg::with_name("Max CIGAR indel")
                 .short("I")
                 .long("max_cigar_indel")
@@ -255,11 +1003,69 @@ fn run() -> Result<()> {
                 .default_value("20")
                 .help("Throw away a read-variant during allelotyping if there is a CIGAR indel (I/D/N) longer than this amount in its window.")
                 .display_order(151))
+        .arg(Arg::with_name("Context error model")
+                .long("context_error_model")
+                .value_name("int")
+                .help("Estimate the alignment parameters separately for each reference sequence context (k-mer) of this length, centered on the aligned base, and use the parameters of each context when realigning the reads. Sequencing errors of nanopore reads depend strongly on the sequence context (e.g. homopolymers). Must be odd and at most 9. Set to 0 to use a single set of parameters.")
+                .display_order(152)
+                .default_value("0"))
+        .arg(Arg::with_name("Strand error model")
+                .long("strand_error_model")
+                .help("Estimate the alignment parameters separately for the reads aligned to the forward and reverse strand, and realign each read with the parameters of its strand. Useful when the error profile differs between strands (e.g. nanopore reads).")
+                .display_order(153))
+        .arg(Arg::with_name("Read group error model")
+                .long("rg_error_model")
+                .help("Estimate the alignment parameters separately for each read group (RG tag), and realign each read with the parameters of its read group. Useful for BAM files that mix reads from different flowcells or sequencing chemistries (e.g. nanopore and HiFi reads). Reads without a read group use the parameters of all reads.")
+                .display_order(153))
+        .arg(Arg::with_name("Parameter windows")
+                .long("param_windows")
+                .value_name("int")
+                .help("Estimate the alignment parameters from about this many windows sampled across the called regions instead of from all of their reads. The windows are sampled at random (with a fixed seed) in proportion to the length of each contig and stratified by their GC content, so that the parameters are not biased by the sequence composition of one part of the genome, and windows with more than 10% ambiguous reference bases are skipped. With at least 10 windows (or regions) with reads, 95% confidence intervals of the transition probabilities and a convergence diagnostic (the difference between the estimates of two halves of the windows) are printed.")
+                .display_order(153))
+        .arg(Arg::with_name("Parameter window size")
+                .long("param_window_size")
+                .value_name("int")
+                .default_value("10000")
+                .help("Length (bp) of the windows of --param_windows.")
+                .display_order(153))
+        .arg(Arg::with_name("Write alignment parameters")
+                .long("write-params")
+                .value_name("path")
+                .help("Write the estimated alignment parameters (including the --strand_error_model, --context_error_model and --rg_error_model parameters) to this file, to reuse them with --read-params.")
+                .display_order(154))
+        .arg(Arg::with_name("Read alignment parameters")
+                .long("read-params")
+                .value_name("path")
+                .help("Read the alignment parameters from a file written with --write-params instead of estimating them from the reads, e.g. for samples sequenced with the same chemistry. The same parameters are used for every sample.")
+                .display_order(155))
+        .arg(Arg::with_name("Pileup only")
+            .long("fast")
+            .help("Skip the realignment of the reads and take the allele of each read at each variant directly from its BAM alignment, weighted by the base qualities of the allele and the mapping quality of the read. Much faster than the pair HMM realignment and often enough for accurate reads (PacBio HiFi) or for quick QC passes, but indels are only seen in the reads whose alignments place them exactly like the variant.")
+            .display_order(160))
         .arg(Arg::with_name("Numerically stable alignment")
             .short("S")
             .long("stable_alignment")
             .help("Use numerically-stable (logspace) pair HMM forward algorithm. Is significantly slower but may be more accurate. Tests have shown this not to be necessary for highly error prone reads (PacBio CLR).")
             .display_order(161))
+        .arg(Arg::with_name("Reduced precision alignment")
+            .long("reduced_precision")
+            .help("Use the numerically-stable (logspace) pair HMM forward algorithm (as -S) with 32-bit instead of 64-bit floats. Halves the memory traffic of the alignments, which dominates the running time on deep long-read data. The log-likelihoods differ from the 64-bit ones by less than 0.001 for realignment windows of a few hundred bases.")
+            .display_order(162))
+        .arg(Arg::with_name("Run-length alignment")
+            .long("rle_alignment")
+            .help("Realign the reads in run-length space: the read and haplotype windows are compressed into homopolymer runs, which are aligned with the numerically-stable pair HMM forward algorithm, and the differences of the run lengths of the aligned runs are scored separately. Recommended for Oxford Nanopore reads, whose errors are mostly wrong homopolymer lengths. The compressed windows are also shorter, which speeds up the realignment. Reads that start or end within a realignment window (--partial_reads) are still aligned base by base.")
+            .display_order(163))
+        .arg(Arg::with_name("Realignment model")
+            .long("realignment_model")
+            .value_name("name")
+            .possible_values(&REALIGNMENT_MODEL_NAMES)
+            .help("Model that scores the reads against the haplotypes of each variant cluster, e.g. to benchmark the models against each other: forward (the pair HMM forward algorithm, the default), forward-logspace (as -S), forward-f32 (as --reduced_precision), forward-rle (as --rle_alignment), viterbi (as -x) or edit-distance (the edit distance of the read to each haplotype, scored with the mean error rate of the alignment parameters). Cannot be combined with -S, -x, --reduced_precision or --rle_alignment.")
+            .display_order(163))
+        .arg(Arg::with_name("Prefilter edits")
+            .long("prefilter_edits")
+            .value_name("int")
+            .help("Before realigning a read to the haplotypes of a variant cluster, compute its banded edit distance to each haplotype. If one haplotype is closer than every other by at least this many edits, score the read by its edit distances instead of realigning it, and only realign the ambiguous reads. Much faster on accurate reads (PacBio HiFi), most of which match one haplotype exactly. The pre-filtered reads usually get the same allele calls, with qualities from the edit distances (see --realignment_model edit-distance). Reads that start or end within the realignment window are always realigned.")
+            .display_order(163))
         .arg(Arg::with_name("Force overwrite")
             .short("F")
             .long("force_overwrite")
@@ -276,22 +1082,82 @@ fn run() -> Result<()> {
                 .help("Minimum width of alignment band. Band will increase in size if sequences are different lengths.")
                 .display_order(170)
                 .default_value("20"))
+        .arg(Arg::with_name("Repeat band")
+                .long("repeat_band")
+                .help("Widen the alignment band (-B) for the variant clusters whose realignment window contains a tandem repeat of at least 10 bp with a repeat unit of up to 6 bp, to the length of the repeat plus two copies of its unit. A fixed band width can otherwise cut off the alignments of reads with extra or missing copies of the repeat unit in long STRs. With this option, a smaller -B can be used for the rest of the genome.")
+                .display_order(170))
+        .arg(Arg::with_name("Reference ambiguity")
+                .long("ref_ambiguity")
+                .value_name("mode")
+                .possible_values(&REF_AMBIGUITY_NAMES)
+                .help("How to realign the reads in windows that contain ambiguous reference bases (N or an IUPAC ambiguity code such as R or Y, which are read as N unless --alphabet iupac is given): mismatch (score them as a mismatch to every read base, or by the bases they stand for with --alphabet iupac), wildcard (let them match any read base) or skip (make no allele calls for the variants whose realignment window contains one). Variants are never called at the ambiguous bases themselves. Draft and non-model references often have many of these bases, which otherwise bias the realignment against every allele near them.")
+                .display_order(170)
+                .default_value("mismatch"))
+        .arg(Arg::with_name("Alphabet")
+                .long("alphabet")
+                .value_name("name")
+                .possible_values(&ALPHABET_NAMES)
+                .help("The bases that the reads are realigned with. dna: A, C, G and T, with any other base read as N, which only matches an N. iupac: the IUPAC ambiguity codes (e.g. the degenerate primers of an amplicon reference) are kept in the reference and the reads, and a read base is scored against an ambiguous base with the mean emission probability over the bases it stands for, while a read N is scored the same against every base. U is read as T (e.g. for RNA virus references), and lower case (soft-masked) bases as upper case. Variants are never called at ambiguous reference bases.")
+                .display_order(170)
+                .default_value("dna"))
+        .arg(Arg::with_name("Alignment cache size")
+                .long("alignment_cache")
+                .value_name("int")
+                .help("Keep the scores of up to this many read/haplotype alignments, so that reads with the same sequence in a realignment window (e.g. PacBio HiFi reads) are only aligned to each haplotype once. The least recently used scores are dropped first. Each score takes about twice the realignment window length in bytes. Set to 0 to disable the cache.")
+                .display_order(171)
+                .default_value("100000"))
         .arg(Arg::with_name("Density parameters")
             .short("D")
             .long("density_params")
             .value_name("string")
             .help("Parameters to flag a variant as part of a \"dense cluster\". Format <n>:<l>:<gq>. \
-                     If there are at least n variants within l base pairs with genotype quality >=gq, \
+                     If there are at least n variants within l base pairs with variant quality (QUAL) >=gq, \
                      then these variants are flagged as \"dn\"")
             .display_order(172)
             .default_value("10:500:50"))
+        .arg(Arg::with_name("Density count")
+            .long("density_count")
+            .value_name("int|auto")
+            .help("Flag the variants of a dense cluster if there are more than this many other variants within --density_window bp (overrides n of --density_params). With 'auto', the count is raised for divergent samples (e.g. non-human samples with a high heterozygosity) to the number of variants that a window exceeds with a probability of at most 1e-6 at the density of the variant calls of each region, and is never lower than n of --density_params.")
+            .display_order(172))
+        .arg(Arg::with_name("Density window")
+            .long("density_window")
+            .value_name("int")
+            .help("Window size (bp) of the variant density filter (overrides l of --density_params).")
+            .display_order(172))
+        .arg(Arg::with_name("Density quality")
+            .long("density_qual")
+            .value_name("float")
+            .help("Only count the variants with at least this quality in the variant density filter (overrides gq of --density_params). See --density_score.")
+            .display_order(172))
+        .arg(Arg::with_name("Density score")
+            .long("density_score")
+            .value_name("name")
+            .possible_values(&DENSITY_SCORE_NAMES)
+            .help("Quality of the variants that is compared to --density_qual: qual (the variant quality QUAL, i.e. the confidence that there is a variant) or gq (the genotype quality GQ, i.e. the confidence in the genotype call).")
+            .display_order(172)
+            .default_value("qual"))
+        .arg(Arg::with_name("Neighbor window")
+            .long("neighbor_window")
+            .value_name("int")
+            .help("Annotate each variant with the number of other variant calls (non-reference genotypes) within this many base pairs on either side (NW), in addition to the distance to the nearest other call (ND). Clusters of nearby calls are a common sign of artifacts in long-read data.")
+            .display_order(173)
+            .default_value("100"))
         .arg(Arg::with_name("Sample ID")
             .short("s")
             .long("sample_id")
             .value_name("string")
-            .help("Specify a sample ID to write to the output VCF")
+            .help("Specify a sample ID to write to the output VCF. For multiple BAM files, specify once per BAM file in the same order (by default the SM tag of each BAM file's read group is used).")
             .display_order(174)
+            .multiple(true)
+            .number_of_values(1)
             .default_value(&"SAMPLE"))
+        .arg(Arg::with_name("Samples")
+            .long("samples")
+            .value_name("list")
+            .help("Call the samples of a single multi-sample BAM file (e.g. of a barcoded nanopore run) whose reads are distinguished by the sample (SM tag) of their read groups: a comma-separated list of sample names, or 'all' for every sample in the BAM header. Several samples are called as with multiple BAM files, each with the reads of its own read groups only. Reads without a read group are not used.")
+            .display_order(174)
+            .takes_value(true))
         .arg(Arg::with_name("Homozygous SNV Rate")
             .long("hom_snv_rate")
             .value_name("float")
@@ -309,14 +1175,12 @@ fn run() -> Result<()> {
             .value_name("float")
             .help("Specify the homozygous Indel Rate for genotype prior estimation")
             .display_order(180)
-            .hidden(true)
             .default_value(&"0.00005"))
         .arg(Arg::with_name("Heterozygous Indel Rate")
             .long("het_indel_rate")
             .value_name("float")
             .help("Specify the heterozygous Indel Rate for genotype prior estimation")
             .display_order(182)
-            .hidden(true)
             .default_value(&"0.00001"))
         .arg(Arg::with_name("ts/tv Ratio")
             .long("ts_tv_ratio")
@@ -324,6 +1188,31 @@ fn run() -> Result<()> {
             .help("Specify the transition/transversion rate for genotype grior estimation")
             .display_order(184)
             .default_value(&"0.5"))
+        .arg(Arg::with_name("Genotype prior")
+            .long("genotype_prior")
+            .value_name("name")
+            .possible_values(&GENOTYPE_PRIOR_NAMES)
+            .help("Prior on the genotypes: het-rate (from --hom_snv_rate, --het_snv_rate, --hom_indel_rate, --het_indel_rate and --ts_tv_ratio), population-af (the het-rate prior mixed with the Hardy-Weinberg genotype frequencies at the sites in --population_af_vcf) or uniform (every genotype is equally likely, so the genotypes follow the read evidence alone). The default is population-af with --population_af_vcf and het-rate otherwise.")
+            .display_order(184))
+        .arg(Arg::with_name("Population AF VCF")
+            .long("population_af_vcf")
+            .value_name("VCF")
+            .help("Bgzipped and indexed VCF of known variant sites with population allele frequencies (e.g. gnomAD). At candidate variants that match a known allele, the genotype prior is mixed with the Hardy-Weinberg genotype frequencies (see --population_af_weight).")
+            .display_order(184))
+        .arg(Arg::with_name("Population AF tag")
+            .long("population_af_tag")
+            .value_name("string")
+            .requires("Population AF VCF")
+            .help("INFO field of --population_af_vcf holding the allele frequency of each ALT allele.")
+            .display_order(184)
+            .default_value("AF"))
+        .arg(Arg::with_name("Population AF weight")
+            .long("population_af_weight")
+            .value_name("float")
+            .requires("Population AF VCF")
+            .help("Weight of the Hardy-Weinberg genotype frequencies in the genotype prior at known sites, between 0.0 (use the default priors) and 1.0 (use only the population allele frequencies).")
+            .display_order(184)
+            .default_value("0.5"))
         .arg(Arg::with_name("Strand Bias P-value cutoff")
             .short("P")
             .long("strand_bias_pvalue_cutoff")
@@ -331,602 +1220,3817 @@ fn run() -> Result<()> {
             .help("Remove a variant if the allele observations are biased toward one strand (forward or reverse) according to Fisher's exact test. Use this cutoff for the two-tailed P-value.")
             .display_order(185)
             .default_value(&"0.01"))
+        .arg(Arg::with_name("Filter min depth")
+            .long("filter_min_depth")
+            .value_name("int")
+            .help("Set the FILTER flag ldp on variant calls with a read depth (DP) below this value.")
+            .display_order(211))
+        .arg(Arg::with_name("Filter max depth")
+            .long("filter_max_depth")
+            .value_name("int")
+            .help("Set the FILTER flag dp on variant calls with a read depth (DP) above this value, in addition to the maximum coverage cutoff.")
+            .display_order(212))
+        .arg(Arg::with_name("Filter allele balance")
+            .long("filter_allele_balance")
+            .value_name("min:max")
+            .help("Set the FILTER flag ab on heterozygous calls whose allele balance (the fraction of the reads supporting the called alleles that support the alternate allele) is outside of this range, e.g. 0.2:0.8.")
+            .display_order(213))
+        .arg(Arg::with_name("Filter strand bias")
+            .long("filter_strand_bias")
+            .value_name("float")
+            .help("Set the FILTER flag sb on variant calls with a PHRED-scaled strand bias p-value (FS) above this value. Unlike --strand_bias_pvalue_cutoff, the genotype is kept.")
+            .display_order(214))
+        .arg(Arg::with_name("Filter homopolymer")
+            .long("filter_homopolymer")
+            .value_name("int")
+            .help("Set the FILTER flag hp on variant calls next to a homopolymer run of at least this many bases on the reference.")
+            .display_order(215))
+        .arg(Arg::with_name("Filter tandem repeat")
+            .long("filter_tandem_repeat")
+            .value_name("float")
+            .help("Set the FILTER flag tr on variant calls in the tandem repeats of --tandem_repeats with a QUAL below this, e.g. to require more confident calls in repeats than elsewhere.")
+            .display_order(215))
+        .arg(Arg::with_name("Threads")
+            .long("threads")
+            .value_name("int")
+            .help("Whole-genome mode: call the contigs of the BAM header (or of --contigs) in separate longshot processes, running this many at a time, and merge their calls into the output VCF in the order of the header. The alignment parameters are estimated once for the whole genome with a single BAM file, and separately for each contig with multiple BAM files. The VCF of each contig is compressed with bgzip and indexed, and kept with its log in <VCF>.contigs until the run is finished. Cannot be used with --region, --bed, --stream_output, --quick-look, --region_queue, --checkpoint_dir, --potential_variants, --candidates-in, --candidates-out, --ultra_deep, --debug-realign, --fit_calibration or the options for additional outputs (e.g. --out_bam, --stats-out).")
+            .display_order(216))
+        .arg(Arg::with_name("Contigs")
+            .long("contigs")
+            .value_name("list")
+            .help("The contigs to call in whole-genome mode (see --threads, which defaults to 1 with this option): a comma-separated list of names or a file with one name per line.")
+            .display_order(217))
+        .arg(Arg::with_name("Scheduler")
+            .long("scheduler")
+            .value_name("mode")
+            .help("How the work of whole-genome mode (--threads, --contigs) is shared between the processes. contigs: a process for each contig, longest contigs first. windows: the contigs are split into windows of about equal work (estimated from the depth and read length of a few sampled windows of each contig), and each process calls a batch of windows, taken from the windows of its thread or stolen from the thread with the most work left, so that the threads finish at about the same time when the coverage is uneven. The phase blocks of the windows mode end at the boundaries of the windows, unless neighbouring windows are called in the same batch.")
+            .possible_values(&SCHEDULER_NAMES)
+            .display_order(217)
+            .default_value("contigs"))
+        .arg(Arg::with_name("Calibration")
+            .long("calibration")
+            .value_name("path")
+            .help("Replace the QUAL and GQ of the calls with the calibrated qualities of a calibration file written with --fit_calibration, fitted to a sample sequenced with the same technology.")
+            .display_order(218))
+        .arg(Arg::with_name("Fit calibration")
+            .long("fit_calibration")
+            .value_name("path")
+            .help("Compare the calls to the --calibration_truth VCF, fit a monotone mapping from the raw QUAL and GQ to the observed error rates (isotonic regression), and write it to this calibration file for --calibration. The truth VCF must be normalized like the longshot output (left-aligned, MNVs decomposed).")
+            .display_order(219))
+        .arg(Arg::with_name("Calibration truth VCF")
+            .long("calibration_truth")
+            .value_name("VCF")
+            .help("VCF of the true genotypes to fit the --fit_calibration to. With multiple samples in the truth VCF, samples are matched by name.")
+            .display_order(220))
+        .arg(Arg::with_name("Calibration regions")
+            .long("calibration_regions")
+            .value_name("BED")
+            .help("BED file of the confident regions of the --calibration_truth VCF. Only the calls in these regions are used to fit the calibration.")
+            .display_order(221))
+        .arg(Arg::with_name("Ultra-deep mode")
+                .long("ultra_deep")
+                .help("Targeted ultra-deep mode for low-frequency variants in very high depth data (e.g. panels with 1000x+ coverage). Sites are streamed directly from the pileup with no maximum coverage cutoff, and alternate allele fractions with 95% confidence intervals are reported instead of diploid genotypes.")
+                .display_order(186))
+        .arg(Arg::with_name("Ultra-deep min allele fraction")
+                .long("ultra_deep_min_af")
+                .value_name("float")
+                .help("In ultra-deep mode, report an alternate allele if its fraction of observations is at least this amount.")
+                .display_order(187)
+                .default_value("0.01"))
+        .arg(Arg::with_name("Pedigree")
+                .long("pedigree")
+                .value_name("PED")
+                .help("PED file describing the relationships between the samples. For each mother/father/child trio among the input BAM files, genotypes are refined using Mendelian inheritance priors and de novo candidates in the child are flagged in the INFO field (DN, DQ).")
+                .display_order(188))
+        .arg(Arg::with_name("De novo rate")
+                .long("de_novo_rate")
+                .value_name("float")
+                .help("Per-allele probability of a de novo mutation, used with --pedigree.")
+                .display_order(189)
+                .default_value("0.00000001"))
         .arg(Arg::with_name("No haplotypes")
                 .short("n")
                 .long("no_haps")
                 .help("Don't call HapCUT2 to phase variants.")
                 .display_order(190))
+        .arg(Arg::with_name("Ploidy")
+                .long("ploidy")
+                .value_name("int")
+                .help("Ploidy of the sample. With ploidy 1, haploid genotypes are called. With a ploidy greater than 2, HapCUT2 is not used: reads are instead clustered into this many haplotypes within each block, and the phased blocks are written to the --polyploid_blocks file. Genotypes in the VCF are the unphased pileup-based calls.")
+                .display_order(191)
+                .default_value("2"))
+        .arg(Arg::with_name("Polyploid blocks")
+                .long("polyploid_blocks")
+                .value_name("path")
+                .help("Output file for the haplotype blocks phased with --ploidy greater than 2, with the MEC and read assignment uncertainty of each block.")
+                .display_order(192))
+        .arg(Arg::with_name("Ploidy regions")
+                .long("ploidy_bed")
+                .value_name("BED")
+                .help("BED file of regions with a ploidy other than --ploidy, with the ploidy in the 4th column (e.g. 1 for chrX/chrY outside the PARs in males and for chrM, 0 to skip a region such as chrY in females). Ploidy 1 regions are called with haploid genotypes.")
+                .display_order(197))
+        .arg(Arg::with_name("Tandem repeats")
+                .long("tandem_repeats")
+                .value_name("BED")
+                .help("BED file of tandem repeats (e.g. the simple repeats of Tandem Repeats Finder or an STR catalog). Variants in the repeats are marked with the TR INFO field, and their realignment window is expanded to the whole repeat (or the part of it that each read is aligned to), so that the extra or missing copies of the repeat unit in the reads can be aligned anywhere in the repeat. See also --filter_tandem_repeat.")
+                .display_order(197))
+        .arg(Arg::with_name("Sex")
+                .long("sex")
+                .value_name("male|female|auto")
+                .help("Sex of the sample. In males, chrX and chrY are called with haploid genotypes outside the pseudoautosomal regions (PARs). In females, chrY is skipped. The PARs of chrY are skipped in both. With 'auto', the sex is inferred from the coverage of chrX relative to the autosomes. Regions of --ploidy_bed take precedence.")
+                .display_order(197)
+                .possible_values(&["male", "female", "auto"]))
+        .arg(Arg::with_name("PAR BED")
+                .long("par_bed")
+                .value_name("BED")
+                .help("BED file of the PARs of chrX and chrY for --sex. By default, the PARs of GRCh38 or GRCh37 are used, which are recognized by the length of chrX.")
+                .display_order(197))
+        .arg(Arg::with_name("Normal BAM")
+                .long("normal_bam")
+                .value_name("BAM")
+                .help("Somatic mode: sorted, indexed BAM file of the matched normal sample (the --bam file is the tumor). Candidate variants are found in the tumor and genotyped in both samples, and each variant is annotated with a somatic quality (SQ) and somatic allele fraction (SVAF). Lower --min_alt_frac to find low-fraction somatic candidates.")
+                .display_order(193))
+        .arg(Arg::with_name("Normal sample ID")
+                .long("normal_sample_id")
+                .value_name("string")
+                .help("Sample ID of the matched normal sample in the output VCF.")
+                .display_order(194)
+                .default_value("NORMAL"))
+        .arg(Arg::with_name("Tumor purity")
+                .long("purity")
+                .value_name("float")
+                .help("Somatic mode: fraction of the cells in the tumor sample that are tumor cells.")
+                .display_order(195)
+                .default_value("1.0"))
+        .arg(Arg::with_name("Somatic rate")
+                .long("somatic_rate")
+                .value_name("float")
+                .help("Somatic mode: prior probability of a somatic mutation at a site.")
+                .display_order(196)
+                .default_value("0.000001"))
+        .arg(Arg::with_name("Mosaic")
+                .long("mosaic")
+                .help("Mosaic mode: relax the diploid genotype model to call variants at allele fractions below that of a heterozygous variant, e.g. for somatic mosaicism or mitochondrial heteroplasmy. Each site that is called homozygous reference is annotated with a mosaic quality (MOSQ) and allele fraction (MOSVAF), from the per-read allele likelihoods and a read noise rate learned from the homozygous sites of the sample, and is called 0/1 if it is mosaic with at least --mosaic_min_posterior. Lower --min_alt_frac to find low-fraction candidates.")
+                .display_order(196))
+        .arg(Arg::with_name("Mosaic min VAF")
+                .long("mosaic_min_vaf")
+                .value_name("float")
+                .help("Mosaic mode: minimum alternate allele fraction of a mosaic variant.")
+                .display_order(196)
+                .default_value("0.02"))
+        .arg(Arg::with_name("Mosaic min posterior")
+                .long("mosaic_min_posterior")
+                .value_name("float")
+                .help("Mosaic mode: minimum posterior probability that a variant is mosaic to call it.")
+                .display_order(196)
+                .default_value("0.9"))
+        .arg(Arg::with_name("Truth VCF")
+                .long("truth_vcf")
+                .value_name("VCF")
+                .help("Phased VCF of true haplotypes (e.g. phased using a trio) to evaluate the phasing against. The switch error rate, flip error rate and phase block N50 of each sample are written to the --phasing_report file. With multiple samples in the truth VCF, samples are matched by name.")
+                .display_order(198))
+        .arg(Arg::with_name("Phasing report")
+                .long("phasing_report")
+                .value_name("path")
+                .help("Output file for the phasing accuracy report when using --truth_vcf.")
+                .display_order(199))
+        .arg(Arg::with_name("Annotation VCF")
+                .long("annotation_vcf")
+                .value_name("VCF")
+                .help("Indexed VCF of annotated variants (e.g. ClinVar or a previous callset) whose INFO fields are copied to the matching output records: a variant matches a record at the same position with one of its alternate alleles, and the values of per-allele fields are reordered to the alleles of the variant. If the VCF has genotypes, the genotype of each sample is compared to them (samples are matched by name, unless the VCF has a single sample) and written to the GTCONC INFO field: concordant, discordant, or phase_discordant for a variant whose phase is switched relative to the previous compared variant of its phase block. Sites that are not in the VCF are compared as homozygous reference.")
+                .display_order(199))
+        .arg(Arg::with_name("Annotation fields")
+                .long("annotation_fields")
+                .value_name("list")
+                .help("Comma separated list of the INFO fields of --annotation_vcf to copy, each optionally renamed in the output with ID:NEW_ID (e.g. CLNSIG,DP:PREV_DP). By default, every INFO field is copied except for those that have the ID of a field of the output VCF.")
+                .display_order(199))
+        .arg(Arg::with_name("Methylation phasing")
+                .long("methylation_phasing")
+                .help("Use CpG methylation from the MM/ML base modification tags of the reads as additional markers in haplotype assembly. CpG sites where the reads are split between methylated and unmethylated are phased together with the SNVs, which can link phase blocks across regions with few heterozygous SNVs (e.g. at imprinted loci). The markers are not written to the VCF.")
+                .display_order(200))
+        .arg(Arg::with_name("Phase VCF")
+                .long("phase_vcf")
+                .value_name("VCF")
+                .help("Phased VCF (e.g. phased using a trio, or statistically phased with a reference panel) used as soft constraints in haplotype assembly. The phased heterozygous variants of each phase set (PS) of the VCF that match a called variant are linked like a read that covers all of them, with each allele wrong with probability 1 - --phase_trust, so the reads can still override the known phase. This can join phase blocks that no read spans, and each phase block is oriented so that its first haplotype matches the first haplotype of the VCF. With multiple samples in the VCF, samples are matched by name.")
+                .display_order(200))
+        .arg(Arg::with_name("Use HP tags")
+                .long("use_hp_tags")
+                .conflicts_with("Phase VCF")
+                .help("Use the haplotype tags (HP and PS) of the reads in the input BAM, e.g. from a previous haplotagging run or from strand-seq, as soft constraints in haplotype assembly. The tagged reads of each phase set are combined like a read that covers all of their variants, with the allele of each variant wrong with probability 1 - --phase_trust, so the reads can still override the upstream phase. Untagged reads are phased as usual, and each phase block is oriented so that its first haplotype matches HP:1.")
+                .display_order(200))
+        .arg(Arg::with_name("Phase trust")
+                .long("phase_trust")
+                .value_name("float")
+                .help("Probability that the phase of a variant in the --phase_vcf, or of the tagged reads with --use_hp_tags, is correct. Must be greater than 0.5, and the miscall probability 1 - --phase_trust must be below that of --min_allele_qual.")
+                .display_order(200)
+                .default_value("0.99"))
+        .arg(Arg::with_name("Low memory")
+                .long("low_memory")
+                .help("Reduce memory usage, e.g. to call variants in a whole chromosome on a laptop. Turns on --spill_fragments, and unless a read-level output is written (--out_bam, --hap_coverage, --hap_fastq, --out-fragments, --out-read-matrix, --out-read-haps, --sv_vcf, --stitch_split_reads or --methylation_phasing), reads without any allele calls are discarded and read names are not kept; with such an output, all reads and their names are kept so that the output is complete. The region is not split into smaller chunks (call a whole genome one chromosome at a time with -r) and the allele calls are not stored with a reduced precision. The results are the same as without this option.")
+                .display_order(201))
+        .arg(Arg::with_name("Spill fragments")
+                .long("spill_fragments")
+                .help("Write the haplotype fragments (the allele calls of the reads) to a temporary file in $TMPDIR while the reads are realigned, instead of keeping them in memory, and read them back from the memory-mapped file when all reads are realigned. This lowers the peak memory of read realignment for very deep or ultra-long reads (e.g. a whole chromosome of high-coverage ONT ultra-long reads); the genotyping and phasing of a region still need all of its fragments in memory. The results are the same as without this option.")
+                .display_order(201))
+        .arg(Arg::with_name("Contig parameters")
+                .long("contig_params")
+                .value_name("TSV")
+                .help("Tab-separated file of per-contig parameter overrides, with a header line of '#contig' followed by any of the columns max_cov, min_alt_count, min_alt_frac and band_width, and one line per contig (e.g. to allow a higher coverage on chrM). Use '.' for parameters that are not overridden.")
+                .display_order(202))
+        .arg(Arg::with_name("Progress")
+                .long("progress")
+                .help("Print progress reports with the amount of work done and the estimated remaining time during the long-running stages (parameter estimation, candidate discovery and read realignment). The running time of each stage is printed at the end in any case.")
+                .display_order(203))
+        .arg(Arg::with_name("Log level")
+                .long("log_level")
+                .value_name("level")
+                .help("Which messages to print to stderr: info (the progress of the run, its statistics and the warnings), warn (only the warnings) or off (none). Errors are always printed.")
+                .display_order(203)
+                .possible_values(&LOG_LEVEL_NAMES)
+                .default_value("info"))
+        .arg(Arg::with_name("Stream output")
+                .long("stream_output")
+                .help("Call variants one window of --stream_window bases at a time and append the calls of each window to the output VCF as soon as it is finished, instead of keeping the calls of the whole genome in memory until the end. The phase blocks end at the window boundaries; with --stream_window 0, whole contigs are called at a time and the output is the same as without this option, since phase blocks never span contigs. Cannot be used with --region, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir.")
+                .display_order(204))
+        .arg(Arg::with_name("Stream window")
+                .long("stream_window")
+                .value_name("int")
+                .help("Length (bp) of the windows of --stream_output, whose calls are written as soon as each window is finished. Set to 0 to call whole contigs.")
+                .display_order(204)
+                .default_value("10000000"))
+        .arg(Arg::with_name("Quick look")
+                .long("quick-look")
+                .value_name("int")
+                .help("Call only this many windows of 100 kb, sampled at random (with a fixed seed) from the genome, --region or --bed regions, and print a summary of the calls. Useful to check the input files and parameters in a few minutes before a whole-genome run. Set to 0 to call the whole region. Cannot be used with --stream_output, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir.")
+                .display_order(205)
+                .default_value("0"))
+        .arg(Arg::with_name("Dry run")
+                .long("dry-run")
+                .help("Check the inputs and print the planned work without calling any variants: the BAM and FASTA files must be indexed and have the same contigs, the region, BED and parameter files are parsed, and the output directories must be writable. Prints the regions that would be called, the main settings and an estimate of the size of the outputs. Useful to catch input problems before submitting a long cluster job.")
+                .display_order(206))
+        .arg(Arg::with_name("Region queue")
+                .long("region_queue")
+                .value_name("FILE")
+                .help("Interactive mode: watch this file for requested regions (one <chrom> or <chrom:start-stop> per line, appended while longshot is running) and call each region as soon as it appears, appending its calls to the output VCF. The BAM files, reference and alignment parameters are only loaded once, so loci can be reviewed on demand. Lines starting with '#' are ignored, invalid regions are skipped with a warning and a line END ends the session. Cannot be used with --region, --bed, --stream_output, --quick-look, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir.")
+                .display_order(207))
+        .arg(Arg::with_name("Checkpoint directory")
+                .long("checkpoint_dir")
+                .value_name("path")
+                .help("Record the alignment parameters and every finished region in this directory, so that an interrupted run (e.g. a preempted cluster job) can be resumed with --resume. Requires --stream_output or --bed, whose regions are called and written separately.")
+                .display_order(208))
+        .arg(Arg::with_name("Resume")
+                .long("resume")
+                .help("Resume an interrupted run from its --checkpoint_dir: the outputs are truncated to the last finished region, the alignment parameters are read from the checkpoint instead of being estimated again, and only the remaining regions are called. Must be run with the same options as the interrupted run.")
+                .display_order(209))
         .arg(Arg::with_name("print reference_genotypes")
                 //.short("G")
                 .long("output-ref")
                 .help("print reference genotypes (non-variant), use this option only in combination with -v option.")
                 .display_order(210))
+        .arg(Arg::with_name("Emit all")
+                .long("emit-all")
+                .help("Write a record for every evaluated candidate site, not only for the variant calls: the sites with a homozygous reference genotype are written with the FILTER rc, and with --merge_mnvs the merged variants are kept as with --keep_mnv_components. The FILTER, QUAL, GQ and PL of each site show why it was not called, e.g. to compare against a truth set or to debug a loss of sensitivity.")
+                .display_order(211))
+        .arg(Arg::with_name("Debug realign")
+            .long("debug-realign")
+            .value_name("chrom:pos")
+            .help("Write the Viterbi alignments of the reads at this site (1-based) against each allele of the potential variants there to a SAM file (see --debug-realign-out), with the per-allele log-likelihoods of each read in custom tags. Supports a single BAM file.")
+            .display_order(231))
+        .arg(Arg::with_name("Debug realign output")
+            .long("debug-realign-out")
+            .value_name("SAM")
+            .help("Output file for the read alignments of --debug-realign.")
+            .display_order(232)
+            .default_value("realign_debug.sam"))
         .arg(Arg::with_name("Variant debug directory")
             .short("d")
             .long("variant_debug_dir")
             .value_name("path")
             .help("write out current information about variants at each step of algorithm to files in this directory")
             .display_order(230))
-        .get_matches();
+        .get_matches_from(args);
 
     // parse the input arguments and throw errors if inputs are invalid
-    let bamfile_name = input_args
-        .value_of("Input BAM or CRAM")
+    let mut bam_files: Vec<String> = input_args
+        .values_of("Input BAM or CRAM")
         .chain_err(|| "Input BAM file not defined.")?
-        .to_string();
+        .map(|b| b.to_string())
+        .collect();
+    // a BAM file on standard input is copied to an indexed temporary file, which is removed when
+    // the run ends
+    ensure!(
+        bam_files.iter().filter(|b| b.as_str() == STDIN_BAM).count() <= 1
+            && bam_files
+                .iter()
+                .all(|b| b.as_str() == STDIN_BAM || !merged_bam_files(b).contains(&STDIN_BAM)),
+        "Standard input (--bam -) can only be given once, and not in a list of BAM files."
+    );
+    let stdin_bam: Option<SpooledBam> = if bam_files.iter().any(|b| b.as_str() == STDIN_BAM) {
+        Some(spool_stdin_bam()?)
+    } else {
+        None
+    };
+    if let Some(ref spooled) = stdin_bam {
+        for b in bam_files.iter_mut() {
+            if b.as_str() == STDIN_BAM {
+                *b = spooled.name();
+            }
+        }
+    }
+    // the first BAM file is used for parsing the region and contig names
+    let bamfile_name = bam_files[0].clone();
     let fasta_file = input_args
         .value_of("Input FASTA")
         .chain_err(|| "Input FASTA file not defined.")?
         .to_string();
-    let output_vcf_file = input_args
-        .value_of("Output VCF")
-        .chain_err(|| "Output VCF file not defined.")?
-        .to_string();
+    // longshot train writes no VCF
+    let output_vcf_file = match input_args.value_of("Output VCF") {
+        Some(f) => f.to_string(),
+        None if !subcommand.writes_vcf() => String::new(),
+        None => bail!("Output VCF file not defined."),
+    };
     let interval: Option<GenomicInterval> =
         parse_region_string(input_args.value_of("Region"), &bamfile_name)?;
     let out_bam: Option<&str> = input_args.value_of("Bam Output");
-    let force = parse_flag(&input_args, "Force overwrite")?;
+    let hap_coverage: Option<&str> = input_args.value_of("Haplotype coverage");
+    let hap_fastq: Option<&str> = input_args.value_of("Haplotype FASTQ");
+    let consensus_fasta: Option<String> = input_args
+        .value_of("Consensus FASTA")
+        .map(|s| s.to_string());
+    let block_consensus: Option<String> = input_args
+        .value_of("Block consensus")
+        .map(|s| s.to_string());
+    let out_fragments: Option<String> = input_args
+        .value_of("Fragments Output")
+        .map(|s| s.to_string());
+    let out_read_matrix: Option<String> = input_args
+        .value_of("Read matrix output")
+        .map(|s| s.to_string());
+    let out_read_haps: Option<String> = input_args
+        .value_of("Read haplotypes output")
+        .map(|s| s.to_string());
+    let out_junctions: Option<String> = input_args
+        .value_of("Junctions output")
+        .map(|s| s.to_string());
+    let out_evidence: Option<String> = input_args
+        .value_of("Evidence output")
+        .map(|s| s.to_string());
+    let out_parquet: Option<String> = input_args
+        .value_of("Parquet output")
+        .map(|s| s.to_string());
+    let liftover_chain: Option<String> = input_args
+        .value_of("Liftover chain")
+        .map(|s| s.to_string());
+    let liftover_vcf_file: Option<String> = input_args
+        .value_of("Liftover output")
+        .map(|s| s.to_string());
+    let out_dense_regions: Option<String> = input_args
+        .value_of("Dense regions output")
+        .map(|s| s.to_string());
+    let out_soft_clip_clusters: Option<String> = input_args
+        .value_of("Soft clip clusters output")
+        .map(|s| s.to_string());
+    let out_callable: Option<String> = input_args
+        .value_of("Callable regions output")
+        .map(|s| s.to_string());
+    let sv_vcf: Option<String> = input_args.value_of("SV output").map(|s| s.to_string());
+    let genotype_sv_vcf: Option<String> = input_args
+        .value_of("Genotype SV VCF")
+        .map(|s| s.to_string());
+    let debug_realign_site: Option<GenomicInterval> = match input_args.value_of("Debug realign") {
+        Some(site) => Some(parse_site_string(site, &bamfile_name)?),
+        None => None,
+    };
+    let debug_realign_out: String = input_args
+        .value_of("Debug realign output")
+        .chain_err(|| "Debug realign output not defined.")?
+        .to_string();
+    let stats_out: Option<String> = input_args
+        .value_of("Stats output")
+        .map(|s| s.to_string());
+    let min_sv_len: usize = parse_usize(&input_args, "Min SV length")?;
+    let min_sv_support: usize = parse_usize(&input_args, "Min SV support")?;
+    ensure!(min_sv_support > 0, "Min SV support must be at least 1.");
+    let force_overwrite = parse_flag(&input_args, "Force overwrite")?;
+    let resume = parse_flag(&input_args, "Resume")?;
+    // a resumed run continues the outputs of the interrupted run, so they are expected to exist
+    let force = force_overwrite || resume;
+    let dry_run = parse_flag(&input_args, "Dry run")?;
     let no_haps = parse_flag(&input_args, "No haplotypes")?;
+    let methylation_phasing = parse_flag(&input_args, "Methylation phasing")?;
+    let ultra_deep = parse_flag(&input_args, "Ultra-deep mode")?;
+    let ultra_deep_min_af: f64 =
+        parse_nonnegative_f64(&input_args, "Ultra-deep min allele fraction")?;
     let output_refgenotypes = parse_flag(&input_args, "print reference_genotypes")?; // added 09/04/2020
+    let emit_all = parse_flag(&input_args, "Emit all")?;
     let mut output_rg: bool = false;
-    if output_refgenotypes
+    if output_refgenotypes || emit_all
     {
        output_rg = true;
     }
     let min_mapq: u8 = parse_u8(&input_args, "Min mapq")?;
+    let stitch_split_reads = parse_flag(&input_args, "Stitch split reads")?;
+    let partial_reads = parse_flag(&input_args, "Partial reads")?;
+    let mapq_likelihood = parse_flag(&input_args, "MAPQ likelihood")?;
+    let read_filter = ReadFilter {
+        min_read_length: parse_usize(&input_args, "Min read length")?,
+        max_read_length: match input_args.value_of("Max read length") {
+            Some(_) => Some(parse_usize(&input_args, "Max read length")?),
+            None => None,
+        },
+        keep_secondary: parse_flag(&input_args, "Keep secondary")?,
+        keep_supplementary: parse_flag(&input_args, "Keep supplementary")? || stitch_split_reads,
+        keep_duplicates: parse_flag(&input_args, "Keep duplicates")?,
+        keep_qc_fail: parse_flag(&input_args, "Keep QC fail")?,
+        required_flags: parse_sam_flags(&input_args, "Required flags")?,
+        forbidden_flags: parse_sam_flags(&input_args, "Excluded flags")?,
+        ..ReadFilter::new(min_mapq)
+    };
+    let min_alignment_score: Option<i64> = match input_args.value_of("Min alignment score") {
+        Some(s) => Some(
+            s.parse::<i64>()
+                .chain_err(|| "Min alignment score must be an integer!")?,
+        ),
+        None => None,
+    };
+    let min_aligned_frac: f64 = parse_nonnegative_f64(&input_args, "Min aligned fraction")?;
+    let max_soft_clip: Option<usize> = match input_args.value_of("Max soft clip") {
+        Some(s) => Some(
+            s.parse::<usize>()
+                .chain_err(|| "Max soft clip must be a positive integer!")?,
+        ),
+        None => None,
+    };
+    let missing_base_qual: u8 = parse_u8(&input_args, "Missing base quality value")?;
+    ensure!(
+        missing_base_qual < 255,
+        "--missing_base_qual_value must be less than 255, which marks a missing base quality."
+    );
+    let missing_qual_policy: MissingQualPolicy =
+        match input_args.value_of("Missing base quality policy") {
+            Some("keep") | None => MissingQualPolicy::Keep(missing_base_qual),
+            Some("reject") => MissingQualPolicy::Reject,
+            Some(p) => bail!("Invalid missing base quality policy {}. Must be 'keep' or 'reject'.", p),
+        };
+    let strict = parse_flag(&input_args, "Strict")?;
+    let soft_clip_window: usize = parse_usize(&input_args, "Soft clip window")?;
+    let min_soft_clip_cluster: Option<usize> = match input_args.value_of("Soft clip cluster") {
+        Some(_) => {
+            let min_reads = parse_usize(&input_args, "Soft clip cluster")?;
+            ensure!(min_reads >= 1, "--soft_clip_cluster must be at least 1.");
+            Some(min_reads)
+        }
+        None => None,
+    };
+    let prefilter_edits: Option<usize> = match input_args.value_of("Prefilter edits") {
+        Some(_) => {
+            let edits = parse_usize(&input_args, "Prefilter edits")?;
+            ensure!(edits >= 1, "--prefilter_edits must be at least 1.");
+            Some(edits)
+        }
+        None => None,
+    };
     let anchor_length: usize = parse_usize(&input_args, "Anchor length")?;
     let variant_cluster_max_size: usize = parse_usize(&input_args, "Variant cluster max size")?;
+    let cluster_work_budget: usize = parse_usize(&input_args, "Cluster work budget")?;
+    let max_indel_len: usize = parse_usize(&input_args, "Max indel length")?;
+    let max_alt_alleles: usize = parse_usize(&input_args, "Max alt alleles")?;
+    if max_alt_alleles < 1 || max_alt_alleles > 3 {
+        bail!("Max alt alleles must be between 1 and 3.");
+    }
     let max_window_padding: usize = parse_usize(&input_args, "Max window padding")?;
+    let window_flank: usize = parse_usize(&input_args, "Window flank")?;
+    ensure!(
+        max_window_padding <= MAX_WINDOW_LEN && window_flank <= MAX_WINDOW_LEN,
+        "--max_window and --window_flank must be at most {} bases.",
+        MAX_WINDOW_LEN
+    );
+    let exact_anchors: bool = parse_flag(&input_args, "Exact anchors")?;
+    let window_merge_distance: usize = parse_usize(&input_args, "Window merge distance")?;
     let max_cigar_indel: usize = parse_usize(&input_args, "Max CIGAR indel")?;
-    let min_allele_qual: f64 = parse_nonnegative_f64(&input_args, "Min allele quality")?;
-    let strand_bias_pvalue_cutoff: f64 =
-        parse_nonnegative_f64(&input_args, "Strand Bias P-value cutoff")?;
-    let hap_assignment_qual: f64 =
-        parse_nonnegative_f64(&input_args, "Haplotype assignment quality")?;
-    let ll_delta: f64 = parse_positive_f64(&input_args, "Haplotype Convergence Delta")?;
-    let potential_snv_cutoff_phred = parse_positive_f64(&input_args, "Potential SNV Cutoff")?;
-    let potential_snv_min_alt_count: usize =
-        parse_usize(&input_args, "Potential SNV Min Alt Count")?;
-    let potential_snv_min_alt_frac: f64 =
-        parse_nonnegative_f64(&input_args, "Potential SNV Min Alt Fraction")?;
-    let hom_snv_rate: LogProb = parse_prob_into_logprob(&input_args, "Homozygous SNV Rate")?;
-    let het_snv_rate: LogProb = parse_prob_into_logprob(&input_args, "Heterozygous SNV Rate")?;
-    let hom_indel_rate: LogProb = parse_prob_into_logprob(&input_args, "Homozygous Indel Rate")?;
-    let het_indel_rate: LogProb = parse_prob_into_logprob(&input_args, "Heterozygous Indel Rate")?;
-    let sample_name: String = input_args
-        .value_of(&"Sample ID")
-        .chain_err(|| "Sample ID not defined.")?
-        .to_string();
-    let potential_variants_file: Option<&str> = input_args.value_of("Potential Variants VCF");
-
-    // sanity checks on values that aren't covered by parsing functions
+    let context_k: Option<usize> = match parse_usize(&input_args, "Context error model")? {
+        0 => None,
+        k => {
+            ensure!(
+                k % 2 == 1 && k <= 9,
+                "The --context_error_model k-mer length must be odd and at most 9."
+            );
+            Some(k)
+        }
+    };
+    let strand_error_model = parse_flag(&input_args, "Strand error model")?;
+    let read_group_error_model = parse_flag(&input_args, "Read group error model")?;
+    let write_params_file: Option<String> = input_args
+        .value_of("Write alignment parameters")
+        .map(|s| s.to_string());
+    let read_params_file: Option<String> = input_args
+        .value_of("Read alignment parameters")
+        .map(|s| s.to_string());
+    let param_windows: Option<usize> = match input_args.value_of("Parameter windows") {
+        Some(_) => {
+            let n = parse_usize(&input_args, "Parameter windows")?;
+            ensure!(n >= 1, "--param_windows must be at least 1.");
+            Some(n)
+        }
+        None => None,
+    };
+    let param_window_size: u32 = parse_u32(&input_args, "Parameter window size")?;
+    ensure!(param_window_size >= 1, "--param_window_size must be at least 1.");
     ensure!(
-        ll_delta < 1.0,
-        format!("Haplotype Convergence Delta must be less than 1.0!")
+        param_windows.is_none() || read_params_file.is_none(),
+        "--param_windows cannot be used with --read-params, which skips the estimation of the alignment parameters."
     );
-
-    // manipulations to get some of the option values into forms we want
-    let max_p_miscall: f64 = *Prob::from(PHREDProb(min_allele_qual));
-    let hap_max_p_misassign: f64 = *Prob::from(PHREDProb(hap_assignment_qual));
-    let potential_snv_cutoff: LogProb = LogProb::from(PHREDProb(potential_snv_cutoff_phred));
-
-    // if VCF file exists, throw error unless --force_overwrite option is set
-    let vcf = Path::new(&output_vcf_file);
     ensure!(
-        !vcf.is_file() || force,
-        "Variant output file already exists. Rerun with -F option to force overwrite."
+        read_params_file.is_none()
+            || (context_k.is_none() && !strand_error_model && !read_group_error_model),
+        "--context_error_model, --strand_error_model and --rg_error_model cannot be used with --read-params, because the parameters file determines the error model."
     );
-    if let Some(filename) = out_bam {
-        ensure!(
-            !Path::new(filename).is_file() || force,
-            "Output bam file already exists. Rerun with -F option to force overwrite."
+    ensure!(
+        !read_group_error_model || (context_k.is_none() && !strand_error_model),
+        "--rg_error_model cannot be combined with --context_error_model or --strand_error_model."
+    );
+    if let Some(p) = preset {
+        let options: Vec<String> = p
+            .options()
+            .iter()
+            .map(|&(long, _, value)| format!("{} {}", long, value))
+            .collect();
+        info!(
+            "{} Using the {} preset (defaults: {}).",
+            print_time(),
+            p.name(),
+            options.join(", ")
         );
     }
+    ensure!(
+        write_params_file.is_none() || bam_files.len() == 1,
+        "--write-params can only be used with a single BAM file, because the parameters are estimated separately for each sample."
+    );
+    let min_allele_qual: f64 = parse_nonnegative_f64(&input_args, "Min allele quality")?;
 
+    // the hard filters on the variant calls, which are all off by default
+    let mut site_filters: Vec<Box<dyn SiteFilter>> = vec![];
+    if input_args.is_present("Filter min depth") {
+        site_filters.push(Box::new(MinDepthFilter {
+            min_depth: parse_usize(&input_args, "Filter min depth")?,
+        }));
+    }
+    if input_args.is_present("Filter max depth") {
+        site_filters.push(Box::new(MaxDepthFilter {
+            max_depth: parse_usize(&input_args, "Filter max depth")?,
+        }));
+    }
+    if let Some(range) = input_args.value_of("Filter allele balance") {
+        let bounds: Vec<f64> = range
+            .split(':')
+            .map(|x| x.parse::<f64>())
+            .collect::<std::result::Result<Vec<f64>, _>>()
+            .chain_err(|| "Format for --filter_allele_balance should be <min>:<max>.")?;
+        ensure!(
+            bounds.len() == 2 && bounds[0] >= 0.0 && bounds[0] <= bounds[1] && bounds[1] <= 1.0,
+            "Format for --filter_allele_balance should be <min>:<max>, with 0 <= min <= max <= 1."
+        );
+        site_filters.push(Box::new(AlleleBalanceFilter {
+            min: bounds[0],
+            max: bounds[1],
+        }));
+    }
+    if input_args.is_present("Filter strand bias") {
+        site_filters.push(Box::new(StrandBiasFilter {
+            max_phred: parse_nonnegative_f64(&input_args, "Filter strand bias")?,
+        }));
+    }
+    if input_args.is_present("Filter homopolymer") {
+        let min_len = parse_usize(&input_args, "Filter homopolymer")?;
+        ensure!(
+            min_len >= 1,
+            "The --filter_homopolymer run length must be at least 1."
+        );
+        site_filters.push(Box::new(HomopolymerFilter { min_len: min_len }));
+    }
+    if input_args.is_present("Filter tandem repeat") {
+        ensure!(
+            input_args.is_present("Tandem repeats"),
+            "The --filter_tandem_repeat option requires --tandem_repeats."
+        );
+        site_filters.push(Box::new(TandemRepeatFilter {
+            min_qual: parse_nonnegative_f64(&input_args, "Filter tandem repeat")?,
+        }));
+    }
+    for filter in &site_filters {
+        info!(
+            "{} Variant calls {} get the FILTER flag {}.",
+            print_time(),
+            filter.description(),
+            filter.flag()
+        );
+    }
+    let strand_bias_pvalue_cutoff: f64 =
+        parse_nonnegative_f64(&input_args, "Strand Bias P-value cutoff")?;
+    let hap_assignment_qual: f64 =
+        parse_nonnegative_f64(&input_args, "Haplotype assignment quality")?;
+    let hap_tag_posterior: Option<f64> = if input_args.is_present("Haplotype tag posterior") {
+        ensure!(
+            input_args.occurrences_of("Haplotype assignment quality") == 0,
+            "--hap_tag_posterior and --hap_assignment_qual cannot be used together."
+        );
+        let posterior = parse_positive_f64(&input_args, "Haplotype tag posterior")?;
+        ensure!(
+            posterior >= 0.5 && posterior < 1.0,
+            "--hap_tag_posterior must be at least 0.5 and less than 1."
+        );
+        Some(posterior)
+    } else {
+        None
+    };
+    let ll_delta: f64 = parse_positive_f64(&input_args, "Haplotype Convergence Delta")?;
+    let hap_max_rounds: usize = parse_usize(&input_args, "Haplotype max rounds")?;
+    let hap_converge_changes: Option<usize> =
+        if input_args.is_present("Haplotype converge changes") {
+            Some(parse_usize(&input_args, "Haplotype converge changes")?)
+        } else {
+            None
+        };
+    let refine_genotypes = !parse_flag(&input_args, "No genotype refinement")?;
+    let singleton_phase_name = input_args
+        .value_of("Singleton phase")
+        .chain_err(|| "Singleton phase not defined.")?;
+    let singleton_phase = SingletonPhase::from_name(singleton_phase_name)
+        .chain_err(|| format!("Unknown --singleton_phase mode {}.", singleton_phase_name))?;
+    let singleton_rescue_qual: f64 =
+        parse_nonnegative_f64(&input_args, "Singleton rescue quality")?;
+    let phaser_name = input_args
+        .value_of("Phaser")
+        .chain_err(|| "Phaser not defined.")?;
+    let phaser: Phaser = Phaser::from_name(phaser_name)
+        .chain_err(|| format!("Unknown --phaser {}.", phaser_name))?;
+    let wmec_max_cov: usize = parse_usize(&input_args, "wMEC max coverage")?;
+    let mapq_weighting: bool = parse_flag(&input_args, "MAPQ weighted phasing")?;
+    let min_junction_qual: Option<f64> = match input_args.value_of("Min junction quality") {
+        Some(_) => Some(parse_nonnegative_f64(&input_args, "Min junction quality")?),
+        None => None,
+    };
+    ensure!(
+        wmec_max_cov >= 1 && wmec_max_cov <= 20,
+        "--wmec_max_cov must be between 1 and 20."
+    );
+    let hap_iteration_stats: Option<String> = input_args
+        .value_of("Haplotype iteration stats")
+        .map(|s| s.to_string());
+    let potential_snv_cutoff_phred = parse_positive_f64(&input_args, "Potential SNV Cutoff")?;
+    let min_alt_count_set = input_args.is_present("Potential SNV Min Alt Count");
+    let min_alt_frac_set = input_args.is_present("Potential SNV Min Alt Fraction");
+    // the fixed thresholds that are used if only one of the two is set (and by --ultra_deep)
+    let potential_snv_min_alt_count: usize = if min_alt_count_set {
+        parse_usize(&input_args, "Potential SNV Min Alt Count")?
+    } else {
+        3
+    };
+    let potential_snv_min_alt_frac: f64 = if min_alt_frac_set {
+        parse_nonnegative_f64(&input_args, "Potential SNV Min Alt Fraction")?
+    } else {
+        0.125
+    };
+    // the potential SNV thresholds are derived from the error model unless they are set explicitly
+    let auto_candidate_thresholds: bool = !min_alt_count_set && !min_alt_frac_set;
+    let candidate_fdr: f64 = parse_positive_f64(&input_args, "Candidate FDR")?;
+    let candidate_sensitivity: Option<f64> = if input_args.is_present("Candidate sensitivity") {
+        let sensitivity = parse_positive_f64(&input_args, "Candidate sensitivity")?;
+        ensure!(sensitivity < 1.0, "--candidate_sensitivity must be less than 1.");
+        Some(sensitivity)
+    } else {
+        None
+    };
+    let hom_snv_rate: LogProb = parse_prob_into_logprob(&input_args, "Homozygous SNV Rate")?;
+    let het_snv_rate: LogProb = parse_prob_into_logprob(&input_args, "Heterozygous SNV Rate")?;
+    let hom_indel_rate: LogProb = parse_prob_into_logprob(&input_args, "Homozygous Indel Rate")?;
+    let het_indel_rate: LogProb = parse_prob_into_logprob(&input_args, "Heterozygous Indel Rate")?;
+    let sample_id_args: Vec<String> = input_args
+        .values_of(&"Sample ID")
+        .chain_err(|| "Sample ID not defined.")?
+        .map(|s| s.to_string())
+        .collect();
+    let normal_bam: Option<String> = input_args.value_of("Normal BAM").map(|s| s.to_string());
+    let somatic = normal_bam != None;
+    let mut sample_names: Vec<String> = if bam_files.len() == 1 {
+        ensure!(
+            sample_id_args.len() == 1,
+            "Only one sample ID can be specified for a single BAM file."
+        );
+        sample_id_args
+    } else if input_args.occurrences_of("Sample ID") > 0 {
+        ensure!(
+            sample_id_args.len() == bam_files.len(),
+            "The number of sample IDs must match the number of BAM files."
+        );
+        sample_id_args
+    } else {
+        let mut names: Vec<String> = vec![];
+        for (i, bam_file) in bam_files.iter().enumerate() {
+            let bam_sample_names = parse_bam_sample_names(bam_file)?;
+            if bam_sample_names.len() == 1 {
+                names.push(bam_sample_names[0].clone());
+            } else {
+                names.push(format!("SAMPLE{}", i + 1));
+            }
+        }
+        names
+    };
+    // the samples of a multi-sample BAM file are called like the BAM files of separate samples,
+    // each of which only reads the reads of its own read groups
+    if let Some(samples) = input_args.value_of("Samples") {
+        ensure!(
+            bam_files.len() == 1
+                && input_args.occurrences_of("Sample ID") == 0
+                && normal_bam.is_none(),
+            "--samples requires a single BAM file, and cannot be used with --sample_id or --normal_bam."
+        );
+        let bam_sample_names = parse_bam_sample_names(&bam_files[0])?;
+        let selected: Vec<String> = if samples == "all" {
+            bam_sample_names.clone()
+        } else {
+            samples
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        ensure!(!selected.is_empty(), "No samples given with --samples.");
+        for sample in &selected {
+            ensure!(
+                bam_sample_names.contains(sample),
+                "Sample {} of --samples is not in the read groups of {}.",
+                sample,
+                bam_files[0]
+            );
+        }
+        bam_files = selected
+            .iter()
+            .map(|sample| sample_bam_name(&bam_files[0], sample))
+            .collect();
+        sample_names = selected;
+    }
+    // in somatic mode the normal sample is processed as a second sample after the tumor
+    if let Some(ref normal_bam_file) = normal_bam {
+        ensure!(
+            bam_files.len() == 1,
+            "Somatic mode requires a single tumor BAM file."
+        );
+        bam_files.push(normal_bam_file.clone());
+        sample_names.push(
+            input_args
+                .value_of("Normal sample ID")
+                .chain_err(|| "Normal sample ID not defined.")?
+                .to_string(),
+        );
+    }
+    let somatic_params = SomaticParameters {
+        purity: parse_positive_f64(&input_args, "Tumor purity")?,
+        somatic_rate: parse_nonnegative_f64(&input_args, "Somatic rate")?,
+        n_cellular_fractions: 20,
+    };
+    let mosaic = parse_flag(&input_args, "Mosaic")?;
+    let mosaic_params = MosaicParameters {
+        min_vaf: parse_positive_f64(&input_args, "Mosaic min VAF")?,
+        min_posterior: parse_positive_f64(&input_args, "Mosaic min posterior")?,
+        mosaic_rate: 0.001,
+        n_fractions: 48,
+    };
+    for i in 0..sample_names.len() {
+        ensure!(
+            !sample_names[i + 1..].contains(&sample_names[i]),
+            format!("Sample ID {} is used for more than one BAM file.", sample_names[i])
+        );
+    }
+    let multisample: bool = bam_files.len() > 1;
+    ensure!(
+        !(ultra_deep && multisample),
+        "Ultra-deep mode supports a single BAM file."
+    );
+    ensure!(
+        !(out_fragments.is_some() && multisample),
+        "--out-fragments supports a single BAM file."
+    );
+    ensure!(
+        !(out_read_matrix.is_some() && multisample),
+        "--out-read-matrix supports a single BAM file."
+    );
+    ensure!(
+        !(out_read_haps.is_some() && multisample),
+        "--out-read-haps supports a single BAM file."
+    );
+    ensure!(
+        !(out_junctions.is_some() && multisample),
+        "--out-junctions supports a single BAM file."
+    );
+    ensure!(
+        !(out_evidence.is_some() && multisample),
+        "--out-evidence supports a single BAM file."
+    );
+    ensure!(
+        !(debug_realign_site.is_some() && multisample),
+        "--debug-realign supports a single BAM file."
+    );
+    ensure!(
+        !(sv_vcf.is_some() && multisample),
+        "--sv_vcf supports a single BAM file."
+    );
+    let potential_variants_file: Option<&str> = input_args.value_of("Potential Variants VCF");
+    let genotype_only = parse_flag(&input_args, "Genotype only")?;
+    if genotype_only {
+        ensure!(
+            potential_variants_file.is_some(),
+            "The --genotype_only option requires a --potential_variants VCF."
+        );
+        output_rg = true;
+    }
+    let merge_mnvs = parse_flag(&input_args, "Merge MNVs")?;
+    let keep_mnv_components =
+        parse_flag(&input_args, "Keep MNV components")? || (emit_all && merge_mnvs);
+    ensure!(
+        !(merge_mnvs && multisample),
+        "--merge_mnvs supports a single BAM file."
+    );
+    ensure!(
+        !keep_mnv_components || merge_mnvs,
+        "The --keep_mnv_components option requires --merge_mnvs."
+    );
+    let local_assembly = parse_flag(&input_args, "Local assembly")?;
+    ensure!(
+        !(local_assembly && potential_variants_file.is_some()),
+        "The --local_assembly option cannot be used with a --potential_variants VCF."
+    );
+    let candidate_bam: Option<String> = input_args.value_of("Candidate BAM").map(|x| x.to_string());
+    let candidates_out: Option<String> = input_args
+        .value_of("Candidates output")
+        .map(|x| x.to_string());
+    let candidates_in: Option<String> = input_args
+        .value_of("Candidates input")
+        .map(|x| x.to_string());
+    if candidates_out.is_some() || candidates_in.is_some() {
+        ensure!(
+            potential_variants_file.is_none(),
+            "The --candidates-in and --candidates-out options cannot be used with a --potential_variants VCF."
+        );
+        ensure!(
+            !(candidates_out.is_some() && candidates_in.is_some()),
+            "The --candidates-in and --candidates-out options cannot be used together."
+        );
+    }
+    if candidates_in.is_some() {
+        ensure!(
+            !local_assembly && candidate_bam.is_none(),
+            "The --candidates-in option cannot be used with --local_assembly or --candidate_bam, which find the candidates."
+        );
+    }
+    if let Some(ref cb) = candidate_bam {
+        ensure!(
+            potential_variants_file.is_none(),
+            "The --candidate_bam option cannot be used with a --potential_variants VCF."
+        );
+        ensure!(
+            parse_target_names(cb)? == parse_target_names(&bamfile_name)?,
+            "The candidate BAM {} must be aligned to the same contigs (in the same order) as {}.",
+            cb,
+            bamfile_name
+        );
+    }
+    ensure!(
+        out_callable.is_none()
+            || (potential_variants_file.is_none() && candidates_in.is_none() && !local_assembly),
+        "The --out-callable option cannot be used with --potential_variants, --candidates-in or --local_assembly, which skip the pileup of candidate discovery."
+    );
+    let ploidy: usize = parse_usize(&input_args, "Ploidy")?;
+    let mut ploidy_regions: Vec<(GenomicInterval, u8)> = match input_args.value_of("Ploidy regions") {
+        Some(bed_file) => parse_ploidy_bed(&bed_file.to_string(), &bamfile_name)?,
+        None => vec![],
+    };
+    let tandem_repeats: Vec<GenomicInterval> = match input_args.value_of("Tandem repeats") {
+        Some(bed_file) => parse_tandem_repeat_bed(&bed_file.to_string(), &bamfile_name)?,
+        None => vec![],
+    };
+    let contig_params: Vec<ContigParams> = match input_args.value_of("Contig parameters") {
+        Some(params_file) => parse_contig_params(&params_file.to_string(), &bamfile_name)?,
+        None => vec![],
+    };
+    let polyploid_blocks_file: Option<String> = input_args
+        .value_of("Polyploid blocks")
+        .map(|s| s.to_string());
+    let de_novo_rate: f64 = parse_nonnegative_f64(&input_args, "De novo rate")?;
+    let population_af_vcf: Option<String> = input_args
+        .value_of("Population AF VCF")
+        .map(|s| s.to_string());
+    let population_af_tag: String = input_args
+        .value_of("Population AF tag")
+        .chain_err(|| "Population AF tag not defined.")?
+        .to_string();
+    let population_af_weight: f64 = parse_nonnegative_f64(&input_args, "Population AF weight")?;
+    let genotype_prior_name: String = match input_args.value_of("Genotype prior") {
+        Some(name) => name.to_string(),
+        None if population_af_vcf.is_some() => "population-af".to_string(),
+        None => "het-rate".to_string(),
+    };
+    ensure!(
+        genotype_prior_name != "population-af" || population_af_vcf.is_some(),
+        "--genotype_prior population-af requires --population_af_vcf."
+    );
+    let truth_vcf: Option<String> = input_args.value_of("Truth VCF").map(|s| s.to_string());
+    let phasing_report_file: Option<String> = input_args
+        .value_of("Phasing report")
+        .map(|s| s.to_string());
+    ensure!(
+        truth_vcf.is_some() == phasing_report_file.is_some(),
+        "--truth_vcf and --phasing_report must be used together."
+    );
+    let annotation_vcf: Option<AnnotationVcf> = match input_args.value_of("Annotation VCF") {
+        Some(path) => Some(
+            AnnotationVcf::open(
+                &path.to_string(),
+                input_args.value_of("Annotation fields"),
+                &output_info_ids(&sample_names)?,
+            )
+            .chain_err(|| "Error reading the annotation VCF.")?,
+        ),
+        None => None,
+    };
+    ensure!(
+        annotation_vcf.is_some() || input_args.occurrences_of("Annotation fields") == 0,
+        "--annotation_fields can only be used with --annotation_vcf."
+    );
+    let phase_vcf: Option<String> = input_args.value_of("Phase VCF").map(|s| s.to_string());
+    let use_hp_tags: bool = parse_flag(&input_args, "Use HP tags")?;
+    let phase_trust: f64 = parse_nonnegative_f64(&input_args, "Phase trust")?;
+    ensure!(
+        input_args.occurrences_of("Phase trust") == 0 || phase_vcf.is_some() || use_hp_tags,
+        "--phase_trust can only be used with --phase_vcf or --use_hp_tags."
+    );
+    let calibration: Option<QualityCalibration> = match input_args.value_of("Calibration") {
+        Some(calibration_file) => Some(QualityCalibration::from_file(
+            &calibration_file.to_string(),
+        )?),
+        None => None,
+    };
+    let fit_calibration: Option<String> = input_args
+        .value_of("Fit calibration")
+        .map(|s| s.to_string());
+    let calibration_truth: Option<String> = input_args
+        .value_of("Calibration truth VCF")
+        .map(|s| s.to_string());
+    ensure!(
+        fit_calibration.is_some() == calibration_truth.is_some(),
+        "--fit_calibration and --calibration_truth must be used together."
+    );
+    ensure!(
+        calibration.is_none() || fit_calibration.is_none(),
+        "--calibration cannot be used with --fit_calibration, which is fitted to the raw qualities."
+    );
+    ensure!(
+        fit_calibration.is_some() || input_args.occurrences_of("Calibration regions") == 0,
+        "--calibration_regions requires --fit_calibration."
+    );
+    ensure!(
+        fit_calibration.is_none() || !resume,
+        "--fit_calibration cannot be used with --resume, since the calls of the finished regions are not kept."
+    );
+    let trios: Vec<Trio> = match input_args.value_of("Pedigree") {
+        Some(ped_file) => {
+            ensure!(
+                multisample,
+                "A pedigree can only be used when calling multiple samples."
+            );
+            let trios = parse_pedigree(&ped_file.to_string(), &sample_names)?;
+            if trios.is_empty() {
+                warn!(
+                    "{} WARNING: No mother/father/child trios were found among the samples in the pedigree file.",
+                    print_time()
+                );
+            }
+            trios
+        }
+        None => vec![],
+    };
+
+    // sanity checks on values that aren't covered by parsing functions
+    ensure!(
+        ll_delta < 1.0,
+        format!("Haplotype Convergence Delta must be less than 1.0!")
+    );
+    ensure!(
+        hap_max_rounds >= 1,
+        "The maximum number of haplotype assembly rounds must be at least 1."
+    );
+    ensure!(
+        min_aligned_frac <= 1.0,
+        format!("Min aligned fraction must be between 0.0 and 1.0!")
+    );
+    ensure!(
+        de_novo_rate <= 1.0,
+        format!("De novo rate must be between 0.0 and 1.0!")
+    );
+    ensure!(
+        population_af_weight <= 1.0,
+        format!("Population AF weight must be between 0.0 and 1.0!")
+    );
+    ensure!(
+        candidate_fdr < 1.0,
+        format!("Candidate FDR must be less than 1.0!")
+    );
+    ensure!(ploidy >= 1, format!("Ploidy must be at least 1!"));
+    ensure!(
+        somatic_params.purity <= 1.0,
+        format!("Tumor purity must be between 0.0 and 1.0!")
+    );
+    ensure!(
+        somatic_params.somatic_rate < 1.0,
+        format!("Somatic rate must be less than 1.0!")
+    );
+    if somatic {
+        ensure!(
+            trios.is_empty() && ploidy == 2,
+            "Somatic mode cannot be combined with --pedigree or --ploidy."
+        );
+    }
+    ensure!(
+        mosaic_params.min_vaf < 0.5,
+        format!("Mosaic min VAF must be less than 0.5!")
+    );
+    ensure!(
+        mosaic_params.min_posterior <= 1.0,
+        format!("Mosaic min posterior must be between 0.0 and 1.0!")
+    );
+    if mosaic {
+        ensure!(
+            !somatic && !merge_mnvs && ploidy == 2,
+            "Mosaic mode cannot be combined with --normal_bam, --merge_mnvs or --ploidy."
+        );
+    }
+    if methylation_phasing {
+        ensure!(
+            !no_haps && !somatic && ploidy <= 2,
+            "Methylation phasing cannot be combined with --no_haps, --normal_bam or a ploidy greater than 2."
+        );
+        ensure!(
+            phaser != Phaser::Wmec,
+            "Methylation phasing cannot be combined with --phaser wmec."
+        );
+    }
+    if phase_vcf.is_some() || use_hp_tags {
+        ensure!(
+            !no_haps && !somatic && ploidy <= 2,
+            "--phase_vcf and --use_hp_tags cannot be combined with --no_haps, --normal_bam or a ploidy greater than 2."
+        );
+    }
+    if out_read_haps.is_some() {
+        ensure!(
+            !no_haps && !somatic,
+            "--out-read-haps cannot be combined with --no_haps or --normal_bam."
+        );
+    }
+    if out_junctions.is_some() || min_junction_qual.is_some() {
+        ensure!(
+            !no_haps && !somatic,
+            "--out-junctions and --min_junction_qual cannot be combined with --no_haps or --normal_bam."
+        );
+    }
+    if ploidy > 2 {
+        ensure!(
+            polyploid_blocks_file != None,
+            "A --polyploid_blocks output file must be specified when ploidy is greater than 2."
+        );
+        ensure!(
+            !multisample,
+            "Polyploid phasing supports a single BAM file."
+        );
+        ensure!(
+            out_bam == None,
+            "Haplotype-separated BAM output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            hap_coverage == None,
+            "Haplotype coverage tracks are not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            hap_fastq == None,
+            "Haplotype-separated FASTQ output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_fragments == None,
+            "Fragment file output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_read_matrix == None,
+            "Read allele matrix output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_read_haps == None,
+            "Read haplotype output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_junctions == None && min_junction_qual == None,
+            "Phase junction scores are not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_evidence == None,
+            "Evidence output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_parquet == None,
+            "Parquet output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            sv_vcf == None,
+            "Structural variant output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            population_af_vcf == None,
+            "Population allele frequency priors are not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            out_dense_regions == None,
+            "Dense region output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            consensus_fasta == None,
+            "Consensus sequence output is not supported when ploidy is greater than 2."
+        );
+        ensure!(
+            block_consensus == None,
+            "Phase block sequence output is not supported when ploidy is greater than 2."
+        );
+    }
+
+    // manipulations to get some of the option values into forms we want
+    let max_p_miscall: f64 = *Prob::from(PHREDProb(min_allele_qual));
+    ensure!(
+        (phase_vcf.is_none() && !use_hp_tags)
+            || (phase_trust > 0.5 && 1.0 - phase_trust < max_p_miscall),
+        "--phase_trust must be greater than 0.5, and 1 - --phase_trust must be less than the miscall probability of --min_allele_qual."
+    );
+    let hap_max_p_misassign: f64 = match hap_tag_posterior {
+        Some(posterior) => 1.0 - posterior,
+        None => *Prob::from(PHREDProb(hap_assignment_qual)),
+    };
+    let potential_snv_cutoff: LogProb = LogProb::from(PHREDProb(potential_snv_cutoff_phred));
+
+    // if VCF file exists, throw error unless --force_overwrite option is set
+    let vcf = Path::new(&output_vcf_file);
+    ensure!(
+        !vcf.is_file() || force,
+        "Variant output file already exists. Rerun with -F option to force overwrite."
+    );
+    if let Some(ref filename) = consensus_fasta {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Consensus FASTA file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref prefix) = block_consensus {
+        for ext in &["fa", "bed"] {
+            ensure!(
+                !Path::new(&format!("{}.{}", prefix, ext)).is_file() || force,
+                "Phase block sequence file already exists. Rerun with -F option to force overwrite."
+            );
+        }
+    }
+    if let Some(ref filename) = liftover_vcf_file {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Lifted-over VCF file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = candidates_out {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Candidates VCF file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_dense_regions {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Dense regions BED file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_soft_clip_clusters {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Soft-clip clusters BED file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_callable {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Callable regions BED file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_junctions {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Phase junctions file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_evidence {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Evidence output file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = out_parquet {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Parquet output file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = stats_out {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Run statistics file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(ref filename) = sv_vcf {
+        ensure!(
+            !Path::new(filename).is_file() || force,
+            "Structural variant VCF file already exists. Rerun with -F option to force overwrite."
+        );
+    }
+    if let Some(filename) = out_bam {
+        for sample_name in &sample_names {
+            let sample_filename = sample_bam_output_name(filename, sample_name, multisample);
+            ensure!(
+                !Path::new(&sample_filename).is_file() || force,
+                "Output bam file already exists. Rerun with -F option to force overwrite."
+            );
+        }
+    }
+
+
+    // ensure that FASTA file is indexed
+    let fai_str = fasta_file.clone() + ".fai";
+    ensure!(Path::new(&fai_str).is_file(), "FASTA reference file must be indexed with samtools faidx. Index file should have same name as FASTA file with .fai appended.");
+    if is_gzipped(&fasta_file)? {
+        let gzi_str = fasta_file.clone() + ".gzi";
+        ensure!(Path::new(&gzi_str).is_file(), "A bgzip-compressed FASTA reference file must be indexed with samtools faidx, which writes both a .fai and a .gzi index with the same name as the FASTA file.");
+    }
+
+    // check if variant debug directory exists
+    // if it does, delete the directory if --force_overwrite option is set or throw an error
+    let variant_debug_directory: Option<String> = match input_args
+        .value_of("Variant debug directory")
+    {
+        Some(dir) => {
+            let p = Path::new(&dir);
+            if p.exists() {
+                if !force {
+                    bail!("Variant debug directory already exists. Rerun with -F option to force overwrite.");
+                }
+                if !dry_run {
+                    remove_dir_all(p).chain_err(|| "Error removing variant debug directory.")?;
+                }
+            }
+            if !dry_run {
+                create_dir(&dir).chain_err(|| "Error creating variant debug directory.")?;
+            }
+            Some(dir.to_string())
+        }
+        None => None,
+    };
+
+    // multiply by 2.0 because internally we use this value as the probability of transition to
+    // a single transversion base, not the combined probability of transversion to either one
+    let ts_tv_ratio = 2.0 * parse_positive_f64(&input_args, "ts/tv Ratio")?;
+
+    let dn_params = input_args
+        .value_of("Density parameters")
+        .chain_err(|| "Density parameters not defined.")?
+        .split(":")
+        .collect::<Vec<&str>>();
+
+    if dn_params.len() != 3 {
+        bail!(
+            "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
+        );
+    }
+
+    let dn_count = dn_params[0].parse::<usize>().chain_err(|| {
+        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
+    })?;
+    let dn_len = dn_params[1].parse::<usize>().chain_err(|| {
+        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
+    })?;
+    let dn_gq = dn_params[2].parse::<usize>().chain_err(|| {
+        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
+    })?;
+
+    // the separate options override the values of --density_params
+    let (dn_count, dn_auto) = match input_args.value_of("Density count") {
+        Some("auto") => (dn_count, true),
+        Some(_) => (parse_usize(&input_args, "Density count")?, false),
+        None => (dn_count, false),
+    };
+    let dn_len = if input_args.is_present("Density window") {
+        parse_usize(&input_args, "Density window")?
+    } else {
+        dn_len
+    };
+    let dn_gq = if input_args.is_present("Density quality") {
+        parse_nonnegative_f64(&input_args, "Density quality")?
+    } else {
+        dn_gq as f64
+    };
+    let dn_score_name = input_args
+        .value_of("Density score")
+        .chain_err(|| "Density score not defined.")?;
+    let dn_score = DensityScore::from_name(dn_score_name)
+        .chain_err(|| format!("Unknown --density_score {}.", dn_score_name))?;
+
+    let density_params = DensityParameters {
+        n: dn_count,
+        len: dn_len,
+        gq: dn_gq,
+        score: dn_score,
+        auto_n: dn_auto,
+        neighbor_window: parse_usize(&input_args, "Neighbor window")?,
+    };
+
+    let hap_iteration_params = HapIterationParams {
+        ll_delta: ll_delta,
+        max_rounds: hap_max_rounds,
+        max_genotype_changes: hap_converge_changes,
+        refine_genotypes: refine_genotypes,
+        phaser: phaser,
+        wmec_max_cov: wmec_max_cov,
+        mapq_weighting: mapq_weighting,
+    };
+
+    let alignment_type = match (
+        parse_flag(&input_args, "Numerically stable alignment")?,
+        parse_flag(&input_args, "Max alignment")?,
+        parse_flag(&input_args, "Reduced precision alignment")?,
+    ) {
+        (false, false, false) => AlignmentType::ForwardAlgorithmNonNumericallyStable,
+        (true, false, false) => AlignmentType::ForwardAlgorithmNumericallyStable,
+        (_, false, true) => AlignmentType::ForwardAlgorithmNumericallyStableF32,
+        (false, true, false) => AlignmentType::ViterbiMaxScoringAlignment,
+        (true, true, _) => {
+            bail!(
+                "Numerically stable alignment option and max alignment options are incompatible."
+            );
+        }
+        (false, true, true) => {
+            bail!("Reduced precision alignment option and max alignment options are incompatible.");
+        }
+    };
+    let alignment_type = if parse_flag(&input_args, "Run-length alignment")? {
+        ensure!(
+            alignment_type != AlignmentType::ViterbiMaxScoringAlignment
+                && alignment_type != AlignmentType::ForwardAlgorithmNumericallyStableF32,
+            "--rle_alignment cannot be used with --max_alignment or --reduced_precision."
+        );
+        AlignmentType::ForwardAlgorithmRunLength
+    } else {
+        alignment_type
+    };
+    let alignment_type = match input_args.value_of("Realignment model") {
+        Some(name) => {
+            ensure!(
+                alignment_type == AlignmentType::ForwardAlgorithmNonNumericallyStable
+                    && !parse_flag(&input_args, "Numerically stable alignment")?,
+                "--realignment_model cannot be used with -S, -x, --reduced_precision or --rle_alignment."
+            );
+            AlignmentType::from_name(name)
+                .chain_err(|| format!("Unknown --realignment_model {}.", name))?
+        }
+        None => alignment_type,
+    };
+
+    let pileup_only = parse_flag(&input_args, "Pileup only")?;
+    ensure!(
+        !pileup_only
+            || (alignment_type == AlignmentType::ForwardAlgorithmNonNumericallyStable
+                && !partial_reads),
+        "--fast cannot be used with the realignment options -S, -x, --reduced_precision, --rle_alignment, --realignment_model and --partial_reads."
+    );
+
+    let band_width: usize = parse_usize(&input_args, "Band width")?;
+    ensure!(
+        band_width <= MAX_WINDOW_LEN,
+        "--band_width must be at most {} bases.",
+        MAX_WINDOW_LEN
+    );
+    let repeat_band = parse_flag(&input_args, "Repeat band")?;
+    let ref_ambiguity_name = input_args
+        .value_of("Reference ambiguity")
+        .chain_err(|| "Reference ambiguity not defined.")?;
+    let ref_ambiguity: RefAmbiguity = RefAmbiguity::from_name(ref_ambiguity_name)
+        .chain_err(|| format!("Unknown --ref_ambiguity mode {}.", ref_ambiguity_name))?;
+    // the alphabet is set for the whole run, before any sequence is read
+    let alphabet_name = input_args
+        .value_of("Alphabet")
+        .chain_err(|| "Alphabet not defined.")?;
+    Alphabet::from_name(alphabet_name)
+        .chain_err(|| format!("Unknown --alphabet {}.", alphabet_name))?
+        .set_current();
+    let alignment_cache_size: usize = parse_usize(&input_args, "Alignment cache size")?;
+    //let use_poa = parse_flag(&input_args, "Use POA");
+    let min_cov: u32 = parse_u32(&input_args, "Min coverage")?;
+    let depth_definition: DepthDefinition = match input_args.value_of("Depth definition") {
+        Some("all") | None => DepthDefinition::All,
+        Some("gap_excluded") => DepthDefinition::GapExcluded,
+        Some(d) => bail!("Invalid depth definition {}. Must be 'all' or 'gap_excluded'.", d),
+    };
+
+    let par_bed: Option<String> = input_args.value_of("PAR BED").map(|s| s.to_string());
+    ensure!(
+        par_bed.is_none() || input_args.is_present("Sex"),
+        "The --par_bed option requires --sex."
+    );
+    if let Some(sex) = input_args.value_of("Sex") {
+        ensure!(!multisample, "--sex supports a single sample.");
+        let sex_chromosomes = SexChromosomes::new(&bamfile_name, &par_bed)?;
+        let sex = match sex {
+            "male" => Sex::Male,
+            "female" => Sex::Female,
+            _ => sex_chromosomes.infer_sex(&bamfile_name, depth_definition)?,
+        };
+        // appended after the regions of --ploidy_bed, which take precedence
+        ploidy_regions.extend(sex_chromosomes.ploidy_regions(sex, ploidy as u8));
+    }
+
+    let auto_max_cov = parse_flag(&input_args, "Auto max coverage")?;
+    let max_cov: u32 = match auto_max_cov {
+        false => {
+            // manually assigned coverage cutoff from user
+            parse_u32(&input_args, "Max coverage")?
+        }
+        true if dry_run => {
+            info!(
+                "{} Dry run: the max read coverage will be estimated from the mean read coverage.",
+                print_time()
+            );
+            parse_u32(&input_args, "Max coverage")?
+        }
+        true => {
+            info!(
+                "{} Automatically determining max read coverage.",
+                print_time()
+            );
+            // with multiple samples the coverage cutoff is applied per sample, so we use the
+            // largest cutoff out of all the samples
+            let mut calculated_max_cov: u32 = 0;
+            for bam_file in &bam_files {
+                info!("{} Estimating mean read coverage...", print_time());
+                let mean_coverage: f64 = calculate_mean_coverage(bam_file, &interval, depth_definition)
+                    .chain_err(|| "Error calculating mean coverage for BAM file.")?;
+                let sample_max_cov =
+                    (mean_coverage as f64 + 5.0 * (mean_coverage as f64).sqrt()) as u32;
+                info!("{} Mean read coverage: {:.2}", print_time(), mean_coverage);
+                if sample_max_cov > calculated_max_cov {
+                    calculated_max_cov = sample_max_cov;
+                }
+            }
+
+            calculated_max_cov
+        }
+    };
+    // the short reads of the candidate BAM usually have a different coverage than the reads that
+    // are genotyped
+    let candidate_max_cov: u32 = match candidate_bam {
+        Some(ref cb) if auto_max_cov && !dry_run => {
+            info!("{} Estimating mean read coverage of the candidate BAM...", print_time());
+            let mean_coverage: f64 = calculate_mean_coverage(cb, &interval, depth_definition)
+                .chain_err(|| "Error calculating mean coverage for the candidate BAM file.")?;
+            info!("{} Mean read coverage: {:.2}", print_time(), mean_coverage);
+            (mean_coverage + 5.0 * mean_coverage.sqrt()) as u32
+        }
+        _ => max_cov,
+    };
+
+    if max_cov > 0 {
+        info!("{} Min read coverage set to {}.", print_time(), min_cov);
+        info!("{} Max read coverage set to {}.", print_time(), max_cov);
+    } else {
+        ensure!(
+            subcommand.writes_vcf(),
+            "The max read coverage is 0, so the alignment parameters cannot be estimated."
+        );
+        // should print empty VCF file here before bailing 09/04/2020
+
+        let vcf_path = Path::new(&output_vcf_file);
+        let vcf_display = vcf_path.display();
+        // Open a file in write-only mode, returns `io::Result<File>`
+        let mut file = File::create(&vcf_path)
+            .chain_err(|| ErrorKind::CreateFileError(vcf_display.to_string()))?;
+
+        let fasta = open_fasta(&fasta_file)?;
+
+        print_vcf_header(
+            &mut file,
+            &vcf_display,
+            &sample_names,
+            potential_variants_file.is_some(),
+            &Some(fasta),
+        )
+        .chain_err(|| "Error printing VCF output.")?;
+        bail!("{} ERROR: Max read coverage set to 0. printing empty VCF file");
+    }
+
+    // in low memory mode, we only store the read IDs if we will be separating the reads by
+    // haplotype, writing them to the fragment file, or matching them to the methylation calls.
+    // to separate the reads, we compute sets holding the separated read IDs and then refer back
+    // to the original BAM and write to separate files based on set membership
+    let low_memory = parse_flag(&input_args, "Low memory")?;
+    // the low memory profile includes spilling the fragments to disk
+    let spill_fragments = parse_flag(&input_args, "Spill fragments")? || low_memory;
+    let max_depth: Option<usize> = match input_args.value_of("Max depth") {
+        Some(_) => {
+            let d = parse_usize(&input_args, "Max depth")?;
+            ensure!(d > 0, "--max-depth must be greater than 0.");
+            Some(d)
+        }
+        None => None,
+    };
+    let max_realign_depth: Option<usize> = match input_args.value_of("Max realign depth") {
+        Some(_) => {
+            let d = parse_usize(&input_args, "Max realign depth")?;
+            ensure!(d > 0, "--max_realign_depth must be greater than 0.");
+            Some(d)
+        }
+        None => None,
+    };
+    SHOW_PROGRESS.store(parse_flag(&input_args, "Progress")?, Ordering::Relaxed);
+    let stream_output = parse_flag(&input_args, "Stream output")?;
+    let stream_window: u32 = parse_u32(&input_args, "Stream window")?;
+    ensure!(
+        !stream_output
+            || (interval.is_none()
+                && potential_variants_file.is_none()
+                && out_bam.is_none()
+                && hap_coverage.is_none()
+                && hap_fastq.is_none()
+                && out_fragments.is_none()
+                && truth_vcf.is_none()
+                && variant_debug_directory.is_none()),
+        "--stream_output cannot be used with --region, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir."
+    );
+    let bed_padding: u32 = parse_u32(&input_args, "Target BED padding")?;
+    let target_intervals: Option<Vec<GenomicInterval>> = match input_args.value_of("Target BED") {
+        Some(bed_file) => Some(parse_target_bed(
+            &bed_file.to_string(),
+            &bamfile_name,
+            bed_padding,
+        )?),
+        None => None,
+    };
+    ensure!(
+        target_intervals.is_none()
+            || (interval.is_none()
+                && !stream_output
+                && potential_variants_file.is_none()
+                && out_bam.is_none()
+                && hap_coverage.is_none()
+                && hap_fastq.is_none()
+                && out_fragments.is_none()
+                && truth_vcf.is_none()
+                && variant_debug_directory.is_none()),
+        "--bed cannot be used with --region, --stream_output, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir."
+    );
+    let quick_look_windows: usize = parse_usize(&input_args, "Quick look")?;
+    let quick_look = quick_look_windows > 0;
+    ensure!(
+        !quick_look
+            || (!stream_output
+                && potential_variants_file.is_none()
+                && out_bam.is_none()
+                && hap_coverage.is_none()
+                && hap_fastq.is_none()
+                && out_fragments.is_none()
+                && truth_vcf.is_none()
+                && variant_debug_directory.is_none()),
+        "--quick-look cannot be used with --stream_output, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir."
+    );
+    let region_queue: Option<String> = input_args.value_of("Region queue").map(|x| x.to_string());
+    ensure!(
+        region_queue.is_none()
+            || (interval.is_none()
+                && target_intervals.is_none()
+                && !stream_output
+                && !quick_look
+                && potential_variants_file.is_none()
+                && out_bam.is_none()
+                && hap_coverage.is_none()
+                && hap_fastq.is_none()
+                && out_fragments.is_none()
+                && truth_vcf.is_none()
+                && variant_debug_directory.is_none()),
+        "--region_queue cannot be used with --region, --bed, --stream_output, --quick-look, --potential_variants, --out_bam, --hap_coverage, --hap_fastq, --out-fragments, --truth_vcf or --variant_debug_dir."
+    );
+    let checkpoint_dir: Option<String> = input_args
+        .value_of("Checkpoint directory")
+        .map(|x| x.to_string());
+    ensure!(
+        checkpoint_dir.is_none() || ((stream_output || target_intervals.is_some()) && !quick_look),
+        "--checkpoint_dir requires --stream_output or --bed, and cannot be used with --quick-look."
+    );
+    ensure!(
+        !resume || checkpoint_dir.is_some(),
+        "--resume requires the --checkpoint_dir of the interrupted run."
+    );
+    ensure!(
+        checkpoint_dir.is_none() || out_evidence.is_none(),
+        "--out-evidence cannot be used with --checkpoint_dir."
+    );
+    ensure!(
+        checkpoint_dir.is_none() || out_parquet.is_none(),
+        "--out-parquet cannot be used with --checkpoint_dir."
+    );
+    // whole-genome mode with the contigs called in separate processes. the options for outputs
+    // other than the VCF are not supported, since each process would overwrite them
+    let contig_threads: Option<usize> = if input_args.is_present("Threads") {
+        Some(parse_usize(&input_args, "Threads")?)
+    } else if input_args.is_present("Contigs") {
+        Some(1)
+    } else {
+        None
+    };
+    let scheduler_name = input_args
+        .value_of("Scheduler")
+        .chain_err(|| "Scheduler not defined.")?;
+    let scheduler = SchedulerMode::from_name(scheduler_name)
+        .chain_err(|| format!("Unknown --scheduler {}.", scheduler_name))?;
+    ensure!(
+        contig_threads.is_some() || input_args.occurrences_of("Scheduler") == 0,
+        "--scheduler requires --threads or --contigs."
+    );
+    let parallel_contigs: Option<Vec<GenomicInterval>> = match contig_threads {
+        Some(threads) => {
+            ensure!(threads >= 1, "--threads must be at least 1.");
+            let unsupported = [
+                "Region",
+                "Target BED",
+                "Stream output",
+                "Region queue",
+                "Checkpoint directory",
+                "Potential Variants VCF",
+                "Candidates output",
+                "Candidates input",
+                "Ultra-deep mode",
+                "Debug realign",
+                "Bam Output",
+                "Haplotype coverage",
+                "Haplotype FASTQ",
+                "Consensus FASTA",
+                "Block consensus",
+                "Fragments Output",
+                "Read matrix output",
+                "Read haplotypes output",
+                "Junctions output",
+                "Evidence output",
+                "Parquet output",
+                "Dense regions output",
+                "Soft clip clusters output",
+                "Callable regions output",
+                "Haplotype iteration stats",
+                "Stats output",
+                "Liftover output",
+                "SV output",
+                "Truth VCF",
+                "Phasing report",
+                "Polyploid blocks",
+                "Variant debug directory",
+                "Fit calibration",
+            ];
+            ensure!(
+                !quick_look && unsupported.iter().all(|&name| input_args.occurrences_of(name) == 0),
+                "--threads and --contigs cannot be used with --region, --bed, --stream_output, --quick-look, --region_queue, --checkpoint_dir, --potential_variants, --candidates-in, --candidates-out, --ultra_deep, --debug-realign, --variant_debug_dir, --truth_vcf, --fit_calibration or the options for outputs other than the VCF (e.g. --out_bam, --hap_coverage, --stats-out)."
+            );
+            Some(match input_args.value_of("Contigs") {
+                Some(contigs) => parse_contig_list(contigs, &bamfile_name)?,
+                None => get_whole_genome_intervals(&bamfile_name)?,
+            })
+        }
+        None => None,
+    };
+
+    let store_read_id = !low_memory
+        || out_bam != None
+        || hap_coverage.is_some()
+        || hap_fastq.is_some()
+        || out_fragments.is_some()
+        || out_read_matrix.is_some()
+        || out_read_haps.is_some()
+        || sv_vcf.is_some()
+        || stitch_split_reads
+        || methylation_phasing;
+
+    let extract_fragment_parameters = ExtractFragmentParameters {
+        read_filter,
+        alignment_type,
+        band_width,
+        anchor_length,
+        variant_cluster_max_size: variant_cluster_max_size,
+        cluster_work_budget,
+        max_window_padding,
+        window_flank,
+        exact_anchors,
+        window_merge_distance,
+        max_cigar_indel,
+        store_read_id,
+        min_alignment_score,
+        min_aligned_frac,
+        max_soft_clip,
+        soft_clip_window,
+        min_soft_clip_cluster,
+        missing_qual_policy,
+        low_memory,
+        spill_fragments,
+        max_depth,
+        max_realign_depth,
+        alignment_cache_size,
+        stitch_split_reads,
+        partial_reads,
+        pileup_only,
+        prefilter_edits,
+        repeat_band,
+        ref_ambiguity,
+        strict,
+    };
+
+    // interval_lst has either the single specified genomic region, the target regions of the BED
+    // file, or list of regions covering all chromosomes. in quick-look mode it has the sampled
+    // windows instead, so that the alignment parameters are also estimated from the reads in those
+    // windows
+    let interval_lst: Vec<GenomicInterval> = match target_intervals {
+        _ if parallel_contigs.is_some() => parallel_contigs.clone().unwrap_or_default(),
+        Some(ref targets) => {
+            info!(
+                "{} Calling variants in {} target regions ({} bp)...",
+                print_time(),
+                targets.len(),
+                interval_lst_len(targets)
+            );
+            targets.clone()
+        }
+        None => get_interval_lst(&bamfile_name, &interval)?,
+    };
+    let interval_lst: Vec<GenomicInterval> = if quick_look {
+        let windows = sample_windows(
+            &interval_lst,
+            quick_look_windows,
+            QUICK_LOOK_WINDOW_SIZE,
+            QUICK_LOOK_SEED,
+        );
+        info!(
+            "{} Quick-look mode: calling {} randomly sampled windows of {} bp...",
+            print_time(),
+            windows.len(),
+            QUICK_LOOK_WINDOW_SIZE
+        );
+        windows
+    } else {
+        interval_lst
+    };
+
+    // check the inputs and outputs, print what would be done and stop before any real work
+    if dry_run {
+        info!("{} Dry run: checking the input files...", print_time());
+        for bam_file in bam_files.iter().chain(candidate_bam.iter()) {
+            check_reference_consistency(bam_file, &fasta_file, &interval_lst)?;
+        }
+        if let Some(ref params_file) = read_params_file {
+            read_alignment_parameters(params_file)
+                .chain_err(|| "Error reading alignment parameters file.")?;
+        }
+        if let Some(ref chain_file) = liftover_chain {
+            ensure!(
+                Path::new(chain_file).is_file(),
+                "Liftover chain file {} does not exist.",
+                chain_file
+            );
+        }
+        if let Some(ref af_vcf) = population_af_vcf {
+            check_population_af_vcf(af_vcf, &population_af_tag)
+                .chain_err(|| "Error reading population allele frequency VCF.")?;
+        }
 
-    // ensure that FASTA file is indexed
-    let fai_str = fasta_file.clone() + ".fai";
-    ensure!(Path::new(&fai_str).is_file(), "FASTA reference file must be indexed with samtools faidx. Index file should have same name as FASTA file with .fai appended.");
+        let mut output_files: Vec<String> = vec![];
+        if subcommand.writes_vcf() {
+            output_files.push(output_vcf_file.clone());
+        }
+        if let Some(filename) = out_bam {
+            for sample_name in &sample_names {
+                output_files.push(sample_bam_output_name(filename, sample_name, multisample));
+            }
+        }
+        for file in vec![
+            hap_coverage.map(|prefix| prefix.to_string()),
+            hap_fastq.map(|prefix| format!("{}.hap1.fastq.gz", prefix)),
+            consensus_fasta.clone(),
+            block_consensus.as_ref().map(|prefix| format!("{}.fa", prefix)),
+            block_consensus.as_ref().map(|prefix| format!("{}.bed", prefix)),
+            out_fragments.clone(),
+            out_read_matrix.clone(),
+            out_read_haps.clone(),
+            out_junctions.clone(),
+            out_evidence.clone(),
+            out_parquet.clone(),
+            liftover_vcf_file.clone(),
+            sv_vcf.clone(),
+            candidates_out.clone(),
+            out_dense_regions.clone(),
+            out_soft_clip_clusters.clone(),
+            out_callable.clone(),
+            stats_out.clone(),
+            write_params_file.clone(),
+            phasing_report_file.clone(),
+            polyploid_blocks_file.clone(),
+        ]
+        .into_iter()
+        .flat_map(|f| f)
+        {
+            output_files.push(file);
+        }
+        for file in &output_files {
+            check_output_writable(file)?;
+        }
 
-    // check if variant debug directory exists
-    // if it does, delete the directory if --force_overwrite option is set or throw an error
-    let variant_debug_directory: Option<String> = match input_args
-        .value_of("Variant debug directory")
-    {
-        Some(dir) => {
-            let p = Path::new(&dir);
-            if p.exists() {
-                if force {
-                    remove_dir_all(p).chain_err(|| "Error removing variant debug directory.")?;
-                } else {
-                    bail!("Variant debug directory already exists. Rerun with -F option to force overwrite.");
+        let bases = interval_lst_len(&interval_lst);
+        let separate_regions =
+            stream_output || quick_look || target_intervals.is_some() || parallel_contigs.is_some();
+        info!(
+            "{} Dry run: {} sample(s) ({}), {} region(s) with {} bp{}:",
+            print_time(),
+            sample_names.len(),
+            sample_names.join(", "),
+            interval_lst.len(),
+            bases,
+            if separate_regions {
+                ", each called and written separately"
+            } else {
+                ""
+            }
+        );
+        for iv in interval_lst.iter().take(10) {
+            info!("{} {}:{}-{}", SPACER, iv.chrom, iv.start_pos + 1, iv.end_pos + 1);
+        }
+        if interval_lst.len() > 10 {
+            info!("{} ... and {} more", SPACER, interval_lst.len() - 10);
+        }
+        if let Some(ref queue_file) = region_queue {
+            info!(
+                "{} Only the regions requested in {} will be called.",
+                SPACER, queue_file
+            );
+        }
+        info!(
+            "{} Settings: min MAPQ {}, coverage {}-{}, band width {}, max variant cluster size {}{}{}",
+            print_time(),
+            min_mapq,
+            min_cov,
+            max_cov,
+            band_width,
+            variant_cluster_max_size,
+            match max_depth {
+                Some(d) => format!(", downsampled to {} reads per kb", d),
+                None => "".to_string(),
+            },
+            if low_memory { ", low memory mode" } else { "" }
+        );
+
+        // about one variant per kb, and a few hundred bytes per VCF line
+        let vcf_mb = (bases as f64 / 1000.0) * (400.0 + 50.0 * sample_names.len() as f64) / 1e6;
+        info!(
+            "{} Estimated output size: {:.1} MB of VCF (assuming about one variant per kb)",
+            print_time(),
+            vcf_mb
+        );
+        if out_bam.is_some() {
+            let mut bam_mb = 0.0;
+            for bam_file in &bam_files {
+                bam_mb += metadata(bam_file).map(|m| m.len()).unwrap_or(0) as f64 / 1e6;
+            }
+            info!(
+                "{} Estimated output size: up to {:.1} MB of haplotagged BAM (the size of the input BAM files)",
+                print_time(),
+                bam_mb
+            );
+        }
+        info!("{} Dry run finished: the inputs are valid.", print_time());
+        return Ok(());
+    }
+
+    // the running time of each stage is printed at the end
+    let mut stage_timer = StageTimer::new();
+    stage_timer.start("parameter estimation");
+
+    // the outputs that are appended to after each region are truncated to the last finished
+    // region when the run is resumed
+    let mut checkpoint: Option<Checkpoint> = match checkpoint_dir {
+        Some(ref dir) => {
+            let output_files: Vec<String> = vec![
+                Some(output_vcf_file.clone()),
+                out_read_matrix.clone(),
+                out_read_haps.clone(),
+                out_junctions.clone(),
+                sv_vcf.clone(),
+                candidates_out.clone(),
+                consensus_fasta.clone(),
+                block_consensus.as_ref().map(|prefix| format!("{}.fa", prefix)),
+                block_consensus.as_ref().map(|prefix| format!("{}.bed", prefix)),
+                out_dense_regions.clone(),
+                out_soft_clip_clusters.clone(),
+                out_callable.clone(),
+            ]
+            .into_iter()
+            .flat_map(|f| f)
+            .collect();
+            if resume {
+                Some(Checkpoint::resume(dir, &output_files)?)
+            } else {
+                Some(Checkpoint::create(dir, &output_files, force_overwrite)?)
+            }
+        }
+        None => None,
+    };
+
+    // the regions that the alignment parameters are estimated from: the called regions, or the
+    // windows sampled from them with --param_windows
+    let param_interval_lst: Vec<GenomicInterval> = match param_windows {
+        Some(n) if !resume => {
+            stratified_training_windows(&fasta_file, &interval_lst, n, param_window_size)?
+        }
+        _ => interval_lst.clone(),
+    };
+
+    // alignment parameters are estimated separately for each sample
+    let mut alignment_parameters_lst: Vec<AlignmentParameters> = vec![];
+    let mut strand_parameters_lst: Vec<Option<StrandAlignmentParameters>> = vec![];
+    let mut context_parameters_lst: Vec<Option<ContextAlignmentParameters>> = vec![];
+    let mut read_group_parameters_lst: Vec<Option<ReadGroupAlignmentParameters>> = vec![];
+    for (sample_ix, bam_file) in bam_files.iter().enumerate() {
+        let checkpoint_params: Option<String> =
+            checkpoint.as_ref().map(|c| c.params_file(sample_ix));
+        let (alignment_parameters, strand_parameters, context_parameters, read_group_parameters) =
+            match read_params_file {
+                Some(ref params_file) => {
+                    info!(
+                        "{} Reading alignment parameters from {}...",
+                        print_time(),
+                        params_file
+                    );
+                    read_alignment_parameters(params_file)?
+                }
+                None if resume => {
+                    let params_file = checkpoint_params
+                        .as_ref()
+                        .chain_err(|| "Checkpoint alignment parameters not defined.")?;
+                    info!(
+                        "{} Reading alignment parameters from the checkpoint...",
+                        print_time()
+                    );
+                    read_alignment_parameters(params_file)?
                 }
+                None if preset.is_some()
+                    && subcommand != Subcommand::Train
+                    && context_k.is_none()
+                    && !strand_error_model
+                    && !read_group_error_model =>
+                {
+                    let p = preset.chain_err(|| "Preset not defined.")?;
+                    info!(
+                        "{} Using the alignment parameters of the {} preset...",
+                        print_time(),
+                        p.name()
+                    );
+                    (p.alignment_parameters(), None, None, None)
+                }
+                None => {
+                    info!("{} Estimating alignment parameters...", print_time());
+                    estimate_alignment_parameters(
+                        bam_file,
+                        &fasta_file,
+                        &param_interval_lst,
+                        min_mapq,
+                        max_cigar_indel as u32,
+                        context_k,
+                        strand_error_model,
+                        read_group_error_model,
+                    )
+                    .chain_err(|| "Error estimating alignment parameters.")?
+                }
+            };
+        if let Some(ref params_file) = write_params_file {
+            write_alignment_parameters(
+                params_file,
+                &alignment_parameters,
+                &strand_parameters,
+                &context_parameters,
+                &read_group_parameters,
+            )?;
+        }
+        if let (Some(ref params_file), false) = (checkpoint_params, resume) {
+            write_alignment_parameters(
+                params_file,
+                &alignment_parameters,
+                &strand_parameters,
+                &context_parameters,
+                &read_group_parameters,
+            )?;
+        }
+        alignment_parameters_lst.push(alignment_parameters);
+        strand_parameters_lst.push(strand_parameters);
+        context_parameters_lst.push(context_parameters);
+        read_group_parameters_lst.push(read_group_parameters);
+    }
+    if subcommand == Subcommand::Train {
+        // the write-params file is required by longshot train
+        info!(
+            "{} Wrote the alignment parameters to {}.",
+            print_time(),
+            write_params_file
+                .as_ref()
+                .chain_err(|| "Write-params file not defined.")?
+        );
+        return Ok(());
+    }
+    // the error model of the candidate BAM is only used to find the candidates in its pileup
+    let candidate_alignment_parameters: Option<AlignmentParameters> = match candidate_bam {
+        Some(ref cb) => {
+            info!(
+                "{} Estimating alignment parameters of the candidate BAM...",
+                print_time()
+            );
+            let (params, _, _, _) = estimate_alignment_parameters(
+                cb,
+                &fasta_file,
+                &param_interval_lst,
+                min_mapq,
+                max_cigar_indel as u32,
+                None,
+                false,
+                false,
+            )
+            .chain_err(|| "Error estimating alignment parameters of the candidate BAM.")?;
+            Some(params)
+        }
+        None => None,
+    };
+
+    /***********************************************************************************************/
+    // WHOLE-GENOME MODE: CALL THE CONTIGS IN PARALLEL PROCESSES
+    /***********************************************************************************************/
+
+    if let (&Some(ref contigs), Some(threads)) = (&parallel_contigs, contig_threads) {
+        let tmp_dir = PathBuf::from(format!("{}.contigs", output_vcf_file));
+        if tmp_dir.exists() {
+            ensure!(
+                force,
+                "Directory {} already exists. Rerun with -F option to force overwrite.",
+                tmp_dir.display()
+            );
+            remove_dir_all(&tmp_dir)
+                .chain_err(|| format!("Could not remove the directory {}.", tmp_dir.display()))?;
+        }
+        create_dir(&tmp_dir)
+            .chain_err(|| format!("Could not create the directory {}.", tmp_dir.display()))?;
+
+        // the contigs get the max coverage and (with a single sample) the alignment parameters
+        // of the whole genome, instead of estimating them from the reads of the contig
+        let mut replaced_options: Vec<CommandLineOption> = PER_CONTIG_OPTIONS.to_vec();
+        replaced_options.push(("--auto_max_cov", Some("-A"), false));
+        replaced_options.push(("--max_cov", Some("-C"), true));
+        // the windows of the windows mode are called as a BED file without padding
+        replaced_options.push(("--bed_padding", None, true));
+        let single_params = bam_files.len() == 1 && read_params_file.is_none();
+        if single_params {
+            replaced_options.push(("--context_error_model", None, true));
+            replaced_options.push(("--strand_error_model", None, false));
+            replaced_options.push(("--rg_error_model", None, false));
+        }
+        let mut cmd_args: Vec<String> = std::env::args().skip(1).collect();
+        // the processes read the copy of standard input
+        if let Some(ref spooled) = stdin_bam {
+            cmd_args = replace_stdin_bam(&cmd_args, &spooled.name());
+        }
+        let mut child_args = strip_options(&cmd_args, &replaced_options, &HYPHEN_VALUE_OPTIONS);
+        child_args.push("--max_cov".to_string());
+        child_args.push(max_cov.to_string());
+        if single_params {
+            let params_file = tmp_dir.join("alignment_parameters.txt");
+            let params_file = params_file.to_string_lossy().to_string();
+            write_alignment_parameters(
+                &params_file,
+                &alignment_parameters_lst[0],
+                &strand_parameters_lst[0],
+                &context_parameters_lst[0],
+                &read_group_parameters_lst[0],
+            )?;
+            child_args.push("--read-params".to_string());
+            child_args.push(params_file);
+        }
+
+        stage_timer.start("contigs");
+        match scheduler {
+            SchedulerMode::Contigs => {
+                info!(
+                    "{} Calling {} contigs with {} at a time...",
+                    print_time(),
+                    contigs.len(),
+                    threads
+                );
+                call_contigs_in_parallel(
+                    &child_args,
+                    contigs,
+                    threads,
+                    &tmp_dir,
+                    &output_vcf_file,
+                )
+                .chain_err(|| "Error calling the contigs in parallel.")?;
             }
-            create_dir(&dir).chain_err(|| "Error creating variant debug directory.")?;
-            Some(dir.to_string())
+            SchedulerMode::Windows => {
+                info!(
+                    "{} Calling {} contigs in windows with {} threads...",
+                    print_time(),
+                    contigs.len(),
+                    threads
+                );
+                call_windows_in_parallel(
+                    &child_args,
+                    contigs,
+                    &bamfile_name,
+                    threads,
+                    &tmp_dir,
+                    &output_vcf_file,
+                )
+                .chain_err(|| "Error calling the windows in parallel.")?;
+            }
+        }
+        info!("{} Finished calling {} contigs.", print_time(), contigs.len());
+        stage_timer.print();
+        return Ok(());
+    }
+
+    /***********************************************************************************************/
+    // ULTRA-DEEP MODE: STREAM ALLELE FRACTIONS FROM PILEUP
+    /***********************************************************************************************/
+
+    if ultra_deep {
+        info!(
+            "{} Calculating allele fractions in ultra-deep mode...",
+            print_time()
+        );
+        let num_sites = call_allele_fractions(
+            &bamfile_name,
+            &fasta_file,
+            &interval,
+            &output_vcf_file,
+            &sample_names[0],
+            min_mapq,
+            potential_snv_min_alt_count,
+            ultra_deep_min_af,
+            alignment_parameters_lst[0].emission_probs.not_equal,
+            depth_definition,
+        )
+        .chain_err(|| "Error calculating allele fractions.")?;
+        info!(
+            "{} {} sites with alternate alleles written to VCF.",
+            print_time(),
+            num_sites
+        );
+        return Ok(());
+    }
+
+    /***********************************************************************************************/
+    // GET GENOTYPE PRIORS
+    /***********************************************************************************************/
+
+    let het_rate_priors = GenotypePriors::new(
+        hom_snv_rate,
+        het_snv_rate,
+        hom_indel_rate,
+        het_indel_rate,
+        ts_tv_ratio,
+    )
+    .chain_err(|| "Error estimating genotype priors.")?;
+    if let Some(ref af_vcf) = population_af_vcf {
+        check_population_af_vcf(af_vcf, &population_af_tag)
+            .chain_err(|| "Error reading population allele frequency VCF.")?;
+    }
+    let genotype_priors: Box<dyn GenotypePrior> = match genotype_prior_name.as_str() {
+        "population-af" => Box::new(PopulationAfPrior {
+            base: Box::new(het_rate_priors),
+            weight: population_af_weight,
+        }),
+        "uniform" => Box::new(UniformPrior),
+        _ => Box::new(het_rate_priors),
+    };
+
+    /***********************************************************************************************/
+    // FIND INITIAL SNVS WITH READ PILEUP
+    /***********************************************************************************************/
+
+    // in streaming mode, each window of a contig is processed separately and its calls are
+    // appended to the VCF as soon as it is finished, so that only the reads and calls of one
+    // window are held in memory. the same is done for each sampled window in quick-look mode,
+    // and for each target region of the BED file. with a region queue, the regions are called
+    // as they are requested until the end of the queue
+    let regions: Box<dyn Iterator<Item = Option<GenomicInterval>>> = match region_queue {
+        Some(ref queue_file) => {
+            info!(
+                "{} Waiting for requested regions in {}...",
+                print_time(),
+                queue_file
+            );
+            Box::new(RegionQueue::new(
+                queue_file,
+                &bamfile_name,
+                Duration::from_secs(1),
+            ))
+        }
+        None if stream_output => Box::new(
+            split_into_windows(&interval_lst, stream_window)
+                .into_iter()
+                .map(|iv| Some(iv)),
+        ),
+        None if quick_look || target_intervals.is_some() => {
+            Box::new(interval_lst.into_iter().map(|iv| Some(iv)))
+        }
+        None => Box::new(vec![interval.clone()].into_iter()),
+    };
+    let mut call_summary = CallSummary::default();
+    let mut run_stats = RunStats::new();
+    // the per-read evidence of the candidate sites (--out-evidence)
+    let mut evidence: Option<EvidenceTensor> = out_evidence.as_ref().map(|_| EvidenceTensor::new());
+    // the final calls are written to the Parquet file as a row group per region
+    let mut parquet_writer: Option<ParquetCallWriter> = match out_parquet {
+        Some(ref filename) => Some(ParquetCallWriter::create(filename)?),
+        None => None,
+    };
+
+    // the truth genotypes of each sample and the calls compared to them, for --fit_calibration
+    let mut calibration_calls = CalibrationCalls::default();
+    let mut calibration_truth_genotypes = vec![];
+    let mut calibration_regions: Option<Vec<GenomicInterval>> = None;
+    if let Some(ref truth_vcf) = calibration_truth {
+        let target_names = parse_target_names(&bamfile_name)?;
+        for sample_name in &sample_names {
+            let truth = parse_truth_genotypes(truth_vcf, sample_name, &target_names)
+                .chain_err(|| "Error reading the calibration truth VCF.")?;
+            if truth.is_none() {
+                warn!(
+                    "{} WARNING: Sample {} was not found in the calibration truth VCF.",
+                    print_time(),
+                    sample_name
+                );
+            }
+            calibration_truth_genotypes.push(truth);
+        }
+        if let Some(bed_file) = input_args.value_of("Calibration regions") {
+            calibration_regions =
+                Some(parse_target_bed(&bed_file.to_string(), &bamfile_name, 0)?);
+        }
+    }
+    // the known phase of each sample, for --phase_vcf
+    let mut known_phases = vec![];
+    if let Some(ref phase_vcf) = phase_vcf {
+        let target_names = parse_target_names(&bamfile_name)?;
+        for sample_name in &sample_names {
+            let known = parse_truth_vcf(phase_vcf, sample_name, &target_names)
+                .chain_err(|| "Error reading the phased VCF.")?;
+            match known {
+                Some(ref known) => info!(
+                    "{} Read the phase of {} heterozygous variants of sample {} from {}.",
+                    print_time(),
+                    known.len(),
+                    sample_name,
+                    phase_vcf
+                ),
+                None => warn!(
+                    "{} WARNING: Sample {} was not found in the phased VCF.",
+                    print_time(),
+                    sample_name
+                ),
+            }
+            known_phases.push(known);
+        }
+    }
+    // the deletions and insertions to genotype, for --genotype_sv
+    let external_svs: Option<Vec<ExternalSv>> = match genotype_sv_vcf {
+        Some(ref sv_file) => {
+            let svs = parse_sv_vcf(sv_file)
+                .chain_err(|| "Error reading the structural variant VCF.")?;
+            info!(
+                "{} Read {} deletions and insertions to genotype from {}.",
+                print_time(),
+                svs.len(),
+                sv_file
+            );
+            Some(svs)
+        }
+        None => None,
+    };
+    // the candidates of a previous run, for --candidates-in
+    let input_candidates: Option<VarList> = match candidates_in {
+        Some(ref file) => {
+            let candidates = parse_vcf_potential_variants(file, &bamfile_name)
+                .chain_err(|| "Error reading the candidates VCF.")?;
+            info!(
+                "{} Read {} candidate variants from {}.",
+                print_time(),
+                candidates.lst.len(),
+                file
+            );
+            Some(candidates)
         }
         None => None,
     };
+    let total_bases = interval_lst_len(&interval_lst);
+
+    for (region_ix, interval) in regions.enumerate() {
+        if let Some(ref c) = checkpoint {
+            let iv = interval
+                .as_ref()
+                .chain_err(|| "Checkpoints need a region to resume from.")?;
+            if c.is_finished(iv) {
+                info!(
+                    "{} Skipping region {}:{}-{}, which was finished before the run was interrupted.",
+                    print_time(),
+                    iv.chrom,
+                    iv.start_pos + 1,
+                    iv.end_pos + 1
+                );
+                continue;
+            }
+        }
+        run_stats.calls.regions += 1;
+        run_stats.calls.bases += match interval {
+            Some(ref iv) => (iv.end_pos - iv.start_pos + 1) as usize,
+            None => total_bases,
+        };
+        if quick_look {
+            let window = interval
+                .as_ref()
+                .chain_err(|| "Quick-look windows not defined.")?;
+            call_summary.regions += 1;
+            call_summary.bases += (window.end_pos - window.start_pos + 1) as usize;
+        }
+        if stream_output {
+            let window = interval
+                .as_ref()
+                .chain_err(|| "Streamed output needs a region.")?;
+            info!(
+                "{} Calling variants in {}:{}-{}...",
+                print_time(),
+                window.chrom,
+                window.start_pos + 1,
+                window.end_pos + 1
+            );
+        }
+        // the VCF header is printed with the first region
+        let append_vcf = region_ix > 0;
+
+        stage_timer.start("candidate discovery");
+
+        // with multiple samples, potential variants are called in each sample and combined into a
+        // single list of candidate sites that is shared by all of the samples
+        // the callable regions of each BAM that candidate variants are found in (--out-callable)
+        let mut callable_regions: Vec<(String, CallableRegions)> = vec![];
+        let mut varlist = match potential_variants_file {
+            Some(file) => {
+                info!(
+                    "{} Reading potential variants from input VCF...",
+                    print_time()
+                );
+
+                parse_vcf_potential_variants(&file.to_string(), &bamfile_name)
+                    .chain_err(|| "Error reading potential variants VCF file.")?
+            }
+            None if input_candidates.is_some() => {
+                // the candidates that the previous run found in this region
+                let candidates = input_candidates
+                    .as_ref()
+                    .chain_err(|| "Input candidates not defined.")?;
+                let lst: Vec<Var> = candidates
+                    .lst
+                    .iter()
+                    .filter(|var| match interval {
+                        Some(ref iv) => {
+                            var.tid == iv.tid
+                                && var.pos0 >= iv.start_pos as usize
+                                && var.pos0 <= iv.end_pos as usize
+                        }
+                        None => true,
+                    })
+                    .cloned()
+                    .collect();
+                VarList::new(lst, candidates.target_names.clone())?
+            }
+            None => {
+                // the reads that the candidates are found in: the reads of the candidate BAM, or
+                // those of each sample. in somatic mode, candidate variants are only found in the
+                // tumor
+                let candidate_sources: Vec<(&String, String, String, AlignmentParameters, u32)> =
+                    match (&candidate_bam, candidate_alignment_parameters) {
+                        (&Some(ref cb), Some(params)) => vec![(
+                            cb,
+                            format!("candidate BAM {}", cb),
+                            cb.clone(),
+                            params,
+                            candidate_max_cov,
+                        )],
+                        _ => bam_files
+                            .iter()
+                            .zip(sample_names.iter())
+                            .zip(alignment_parameters_lst.iter())
+                            .take(if somatic { 1 } else { bam_files.len() })
+                            .map(|((bam_file, name), params)| {
+                                let source_name = format!("sample {}", name);
+                                (bam_file, source_name, name.clone(), *params, max_cov)
+                            })
+                            .collect(),
+                    };
+                let mut joint_varlist: Option<VarList> = None;
+                for &(
+                    bam_file,
+                    ref source_name,
+                    ref callable_name,
+                    ref source_params,
+                    source_max_cov,
+                ) in &candidate_sources
+                {
+                    let thresholds = if auto_candidate_thresholds {
+                        let variant_rate = *Prob::from(LogProb::ln_add_exp(hom_snv_rate, het_snv_rate));
+                        CandidateThresholds::from_error_model(
+                            source_params.emission_probs.not_equal,
+                            variant_rate,
+                            candidate_fdr,
+                            candidate_sensitivity,
+                            source_max_cov as usize,
+                        )
+                    } else {
+                        CandidateThresholds::fixed(
+                            potential_snv_min_alt_count,
+                            potential_snv_min_alt_frac,
+                        )
+                    };
+                    if auto_candidate_thresholds {
+                        info!(
+                            "{} Potential SNV thresholds for {} (estimated substitution error rate {:.4}):",
+                            print_time(),
+                            source_name,
+                            3.0 * source_params.emission_probs.not_equal
+                        );
+                    } else {
+                        info!(
+                            "{} Potential SNV thresholds for {}:",
+                            print_time(),
+                            source_name
+                        );
+                    }
+                    thresholds.print_depth_strata();
+
+                    let mut sample_varlist = if local_assembly {
+                        info!(
+                            "{} Calling potential variants using local assembly...",
+                            print_time()
+                        );
+
+                        local_assembly::call_potential_variants_assembly(
+                            bam_file,
+                            &fasta_file,
+                            &interval,
+                            min_cov,
+                            source_max_cov,
+                            &thresholds,
+                            &read_filter,
+                            max_indel_len,
+                            depth_definition,
+                            &contig_params,
+                        )
+                        .chain_err(|| "Error calling potential variants using local assembly.")?
+                    } else {
+                        info!("{} Calling potential SNVs using pileup...", print_time());
+
+                        let mut source_callable = CallableRegions::new();
+                        let sample_varlist = call_potential_snvs::call_potential_snvs(
+                            bam_file,
+                            &fasta_file,
+                            &interval,
+                            &genotype_priors,
+                            min_cov,
+                            source_max_cov,
+                            &thresholds,
+                            &read_filter,
+                            source_params.ln(),
+                            potential_snv_cutoff,
+                            max_indel_len,
+                            max_alt_alleles,
+                            depth_definition,
+                            &contig_params,
+                            out_callable.as_ref().map(|_| &mut source_callable),
+                        )
+                        .chain_err(|| "Error calling potential SNVs.")?;
+                        if out_callable.is_some() {
+                            callable_regions.push((callable_name.clone(), source_callable));
+                        }
+                        sample_varlist
+                    };
+
+                    joint_varlist = match joint_varlist {
+                        Some(mut vl) => {
+                            if sample_varlist.lst.len() > 0 {
+                                if vl.lst.len() > 0 {
+                                    vl.combine(&mut sample_varlist)
+                                        .chain_err(|| "Error combining potential variants of samples.")?;
+                                } else {
+                                    vl = sample_varlist;
+                                }
+                            }
+                            Some(vl)
+                        }
+                        None => Some(sample_varlist),
+                    };
+                }
+                joint_varlist.chain_err(|| "No input BAM files.")?
+            }
+        };
+        if let Some(ref filename) = candidates_out {
+            print_candidates_vcf(
+                &varlist,
+                &interval,
+                &Some(fasta_file.clone()),
+                filename,
+                append_vcf,
+            )
+            .chain_err(|| "Error printing the candidates VCF.")?;
+        }
+
+        // haploid regions are genotyped with haploid priors and likelihoods, and ploidy 0 regions are skipped
+        varlist
+            .assign_ploidy(ploidy, &ploidy_regions)
+            .chain_err(|| "Error assigning ploidy to potential variants.")?;
+
+        if !tandem_repeats.is_empty() {
+            let n_repeat = varlist.annotate_tandem_repeats(&tandem_repeats);
+            info!(
+                "{} {} potential variants are in annotated tandem repeats.",
+                print_time(),
+                n_repeat
+            );
+        }
+
+        if let Some(ref af_vcf) = population_af_vcf {
+            let n_known = annotate_population_afs(&mut varlist, af_vcf, &population_af_tag)
+                .chain_err(|| "Error reading population allele frequency VCF.")?;
+            info!(
+                "{} {} potential variants have population allele frequencies.",
+                print_time(),
+                n_known
+            );
+        }
+
+        print_variant_debug(
+            &mut varlist,
+            &interval,
+            &variant_debug_directory,
+            &"1.0.potential_SNVs.vcf",
+            max_cov,
+            &density_params,
+            &sample_names[0],
+            &contig_params,
+        )?;
+
+        info!(
+            "{} {} potential variants identified.",
+            print_time(),
+            varlist.lst.len()
+        );
+
+        if varlist.lst.len() == 0 {
+            /* no variants identified, but still print empty VCF file with header, 02/12/20 */
+            info!("No candidate variants identified, printing empty VCF file...");
+            let mut varlists: Vec<VarList> = vec![varlist; sample_names.len()];
+            print_vcf_multisample(
+                &mut varlists,
+                &interval,
+                &Some(fasta_file.clone()),
+                &output_vcf_file,
+                false,
+                max_cov,
+                &density_params,
+                &sample_names,
+                potential_variants_file != None,
+                &contig_params,
+                append_vcf,
+            )
+            .chain_err(|| "Error printing VCF output.")?;
+            if let Some(ref filename) = out_callable {
+                print_callable_regions(
+                    &callable_regions,
+                    &[],
+                    &interval,
+                    &bamfile_name,
+                    &density_params,
+                    filename,
+                    append_vcf,
+                )
+                .chain_err(|| "Error printing callable regions.")?;
+            }
+            if let Some(ref mut c) = checkpoint {
+                c.finish_region(
+                    interval
+                        .as_ref()
+                        .chain_err(|| "Checkpoints need a region to resume from.")?,
+                )?;
+            }
+            continue;
+        }
+
+        // each sample is genotyped and phased separately at the shared candidate sites.
+        // the per-sample variant lists are joined into a multi-sample VCF at the end.
+        let mut sample_varlists: Vec<VarList> = vec![];
+        // the clusters of soft-clipped reads of each sample (--soft_clip_cluster)
+        let mut sample_soft_clip_clusters: Vec<Vec<SoftClipCluster>> = vec![];
+        // the variants written to the VCF with adjacent phased variants merged (--merge_mnvs)
+        let mut mnv_varlist: Option<VarList> = None;
+        // in somatic mode, the likelihoods of the tumor reads for a range of allele fractions
+        let mut tumor_likelihoods: Vec<Vec<LogProb>> = vec![];
+        // in mosaic mode, the likelihoods of the reads of each sample for a range of allele
+        // fractions
+        let mut mosaic_likelihoods: Vec<Vec<Vec<LogProb>>> = vec![];
+
+        for (sample_ix, bam_file) in bam_files.iter().enumerate() {
+            let sample_name = &sample_names[sample_ix];
+            let alignment_parameters = alignment_parameters_lst[sample_ix];
+            let strand_parameters = &strand_parameters_lst[sample_ix];
+            let context_parameters = &context_parameters_lst[sample_ix];
+            let read_group_parameters = &read_group_parameters_lst[sample_ix];
+            let mut varlist = varlist.clone();
+
+            if multisample {
+                info!("{} Processing sample {}...", print_time(), sample_name);
+            }
+
+            // with multiple samples, each sample writes its debug files to a separate subdirectory
+            let variant_debug_directory: Option<String> = match variant_debug_directory {
+                Some(ref dir) if multisample => {
+                    let sample_dir = match Path::new(dir).join(sample_name).to_str() {
+                        Some(s) => s.to_owned(),
+                        None => {
+                            bail!("Invalid unicode provided for variant debug directory");
+                        }
+                    };
+                    create_dir(&sample_dir).chain_err(|| "Error creating variant debug directory.")?;
+                    Some(sample_dir)
+                }
+                ref d => d.clone(),
+            };
+
+            /*******************************************************************************************/
+            // EXTRACT FRAGMENT INFORMATION FROM READS
+            /*******************************************************************************************/
+
+            stage_timer.start("read realignment");
+
+            info!(
+                "{} Generating haplotype fragments from reads...",
+                print_time()
+            );
+            let (mut flist, read_counts, soft_clip_clusters) = extract_fragments::extract_fragments(
+                bam_file,
+                &fasta_file,
+                &mut varlist,
+                &interval,
+                extract_fragment_parameters,
+                alignment_parameters,
+                context_parameters,
+                &contig_params,
+                strand_parameters,
+                read_group_parameters,
+            )
+            .chain_err(|| "Error generating haplotype fragments from BAM reads.")?;
+            run_stats.reads.add_counts(&read_counts);
+
+            // the SNV candidates in clusters of soft-clipped reads are often misaligned, so their
+            // allele calls are not used. they are flagged before the output.
+            if !soft_clip_clusters.is_empty() {
+                let in_cluster = soft_clip_cluster_snvs(&varlist, &soft_clip_clusters);
+                for frag in flist.iter_mut() {
+                    frag.calls.retain(|c| !in_cluster[c.var_ix]);
+                }
+                info!(
+                    "{} Suppressed {} SNV candidates in clusters of soft-clipped reads.",
+                    print_time(),
+                    in_cluster.iter().filter(|&&x| x).count()
+                );
+            }
+            sample_soft_clip_clusters.push(soft_clip_clusters);
+
+            if mapq_likelihood {
+                let n_adjusted = apply_mismapping_probs(&mut flist, &varlist);
+                info!(
+                    "{} Weighted {} allele calls by the mapping quality of their reads.",
+                    print_time(),
+                    n_adjusted
+                );
+            }
+
+            if let Some(ref site) = debug_realign_site {
+                let in_interval = match interval {
+                    Some(ref iv) => {
+                        site.tid == iv.tid
+                            && site.start_pos >= iv.start_pos
+                            && site.start_pos <= iv.end_pos
+                    }
+                    None => true,
+                };
+                if in_interval {
+                    write_realignment_debug_sam(
+                        bam_file,
+                        &fasta_file,
+                        &varlist,
+                        site,
+                        extract_fragment_parameters,
+                        alignment_parameters,
+                        context_parameters,
+                        &contig_params,
+                        strand_parameters,
+                        read_group_parameters,
+                        &debug_realign_out,
+                    )
+                    .chain_err(|| "Error writing the read realignments at the debug site.")?;
+                }
+            }
+
+            // if we're printing out variant "debug" information, print out a fragment file to that debug directory
+            match &variant_debug_directory {
+                &Some(ref debug_dir) => {
+                    let ffn = match Path::new(&debug_dir).join(&"fragments.txt").to_str() {
+                        Some(s) => s.to_owned(),
+                        None => {
+                            bail!("Invalid unicode provided for variant debug directory");
+                        }
+                    };
+                    // normally phase_variant is used to select which variants are heterozygous, so that
+                    // we only pass to HapCUT2 heterozygous variants
+                    // in this case, we set them all to 1 so we generate fragments for all variants
+                    // (except multiallelic variants, since the fragment format only has alleles 0 and 1)
+                    let phase_variant: Vec<bool> = varlist
+                        .lst
+                        .iter()
+                        .map(|var| var.alleles.len() == 2)
+                        .collect();
+                    // generate_flist_buffer generates a Vec<Vec<u8>> where each inner vector is a file line
+                    // together the lines represent the contents of a fragment file in HapCUT-like format
+                    let fragment_buffer =
+                        generate_flist_buffer(&flist, &phase_variant, max_p_miscall, true)
+                            .chain_err(|| "Error generating fragment list buffer.")?;
+
+                    // convert the buffer of u8s into strings and print them to the fragment file
+                    let fragment_file_path = Path::new(&ffn);
+                    let mut fragment_file = File::create(&fragment_file_path)
+                        .chain_err(|| "Could not open fragment file for writing.")?;
+                    for mut line_u8 in fragment_buffer {
+                        line_u8.pop();
+                        writeln!(fragment_file, "{}", u8_to_string(&line_u8)?)
+                            .chain_err(|| "Error writing to fragment file.")?;
+                    }
+                }
+                &None => {}
+            }
+
+            /*******************************************************************************************/
+            // CALL GENOTYPES USING REFINED QUALITY SCORES
+            /*******************************************************************************************/
+
+            stage_timer.start("genotyping");
+
+            info!(
+                "{} Calling initial genotypes using pair-HMM realignment...",
+                print_time()
+            );
+            call_genotypes_no_haplotypes(&flist, &mut varlist, &genotype_priors, max_p_miscall)
+                .chain_err(|| "Error calling initial genotypes with estimated allele qualities.")?;
+
+            // use Fishers exact test to check if allele observations are biased toward one strand or the other
+            // (at multiallelic sites, the reference allele against all of the alternate alleles)
+            for mut var in &mut varlist.lst {
+                let alt_forward: u32 = var
+                    .allele_counts_forward
+                    .iter()
+                    .skip(1)
+                    .map(|&c| c as u32)
+                    .sum();
+                let alt_reverse: u32 = var
+                    .allele_counts_reverse
+                    .iter()
+                    .skip(1)
+                    .map(|&c| c as u32)
+                    .sum();
+                let counts: [u32; 4] = [
+                    var.allele_counts_forward[0] as u32,
+                    var.allele_counts_reverse[0] as u32,
+                    alt_forward,
+                    alt_reverse,
+                ];
+                let fishers_exact_pvalues = fishers_exact(&counts)
+                    .chain_err(|| "Error calculating Fisher's exact test for strand bias.")?;
+
+                //println!("{:?} {:?} {:?}  {:?}",&counts, fishers_exact_pvalues.two_tail_pvalue, fishers_exact_pvalues.less_pvalue, fishers_exact_pvalues.greater_pvalue);
+                var.strand_bias_pvalue = if fishers_exact_pvalues.two_tail_pvalue <= 500.0 {
+                    *PHREDProb::from(Prob(fishers_exact_pvalues.two_tail_pvalue))
+                } else {
+                    500.0
+                };
+
+                if fishers_exact_pvalues.two_tail_pvalue < strand_bias_pvalue_cutoff {
+                    var.filter.add_filter(VarFilter::StrandBias);
+                    var.genotype = Genotype(0, 0);
+                    var.gq = 0.0;
+                }
+            }
+
+            for f in 0..flist.len() {
+                &flist[f].calls.retain(|&c| {
+                    !varlist.lst[c.var_ix as usize]
+                        .filter
+                        .has_filter(VarFilter::StrandBias)
+                });
+            }
+
+            if mosaic {
+                let noise_rate = estimate_noise_rate(&flist, &varlist, max_p_miscall);
+                info!(
+                    "{} Estimated a read noise rate of {:.5} for the mosaic model.",
+                    SPACER, noise_rate
+                );
+                mosaic_likelihoods.push(mosaic_allele_fraction_likelihoods(
+                    &flist,
+                    &varlist,
+                    max_p_miscall,
+                    noise_rate,
+                    &mosaic_params,
+                ));
+            }
+
+            print_variant_debug(
+                &mut varlist,
+                &interval,
+                &variant_debug_directory,
+                &"2.0.realigned_genotypes.vcf",
+                max_cov,
+                &density_params,
+                sample_name,
+                &contig_params,
+            )?;
+
+            // polyploid samples are phased by clustering reads into k haplotypes instead of HapCUT2
+            if ploidy > 2 {
+                info!(
+                    "{} Phasing variants into {} haplotypes...",
+                    print_time(),
+                    ploidy
+                );
+                let phase_variant: Vec<bool> = varlist
+                    .lst
+                    .iter()
+                    .map(|var| {
+                        var.alleles.len() == 2
+                            && var.alleles[0].len() == 1
+                            && var.alleles[1].len() == 1
+                            && var.ploidy != 1
+                            && var.genotype != Genotype(0, 0)
+                    })
+                    .collect();
+                let blocks = phase_polyploid(&flist, &phase_variant, ploidy, max_p_miscall, 10);
+                info!("{} Phased {} blocks.", SPACER, blocks.len());
+                if let Some(ref blocks_file) = polyploid_blocks_file {
+                    print_polyploid_blocks(&blocks, &varlist, blocks_file)
+                        .chain_err(|| "Error printing polyploid haplotype blocks.")?;
+                }
+                sample_varlists.push(varlist);
+                continue;
+            }
+
+            if somatic && sample_ix == 0 {
+                tumor_likelihoods = tumor_allele_fraction_likelihoods(
+                    &flist,
+                    varlist.lst.len(),
+                    max_p_miscall,
+                    &somatic_params.allele_fractions(),
+                );
+            }
+
+            // if haplotype information usage is turned off, skip haplotype assembly for this sample.
+            // the somatic model uses the unphased genotypes of the tumor and normal samples.
+            if no_haps || somatic {
+                if let Some(ref fragments_file) = out_fragments {
+                    info!("{} Writing haplotype fragments to file...", print_time());
+                    print_fragment_file(
+                        &flist,
+                        &varlist,
+                        &interval,
+                        output_rg,
+                        max_p_miscall,
+                        fragments_file,
+                    )
+                    .chain_err(|| "Error writing fragment file.")?;
+                }
+                if let Some(ref matrix_file) = out_read_matrix {
+                    info!("{} Writing read allele matrix to file...", print_time());
+                    print_read_matrix(
+                        &flist,
+                        &varlist,
+                        &interval,
+                        LogProb::from(Prob(1.0 - hap_max_p_misassign)),
+                        max_p_miscall,
+                        matrix_file,
+                        append_vcf,
+                    )
+                    .chain_err(|| "Error writing read allele matrix.")?;
+                }
+                if let Some(ref mut evidence) = evidence {
+                    evidence.add_sites(&flist, &varlist, &interval);
+                }
+                if let Some(ref sv_file) = sv_vcf {
+                    match external_svs {
+                        Some(ref svs) => {
+                            info!("{} Genotyping structural variants...", print_time());
+                            genotype_external_svs(
+                                bam_file,
+                                &fasta_file,
+                                &interval,
+                                sample_name,
+                                &HashMap::new(),
+                                &HashMap::new(),
+                                svs,
+                                min_mapq,
+                                min_sv_support,
+                                alignment_parameters.ln(),
+                                band_width,
+                                sv_file,
+                                append_vcf,
+                            )
+                            .chain_err(|| "Error genotyping structural variants.")?;
+                        }
+                        None => {
+                            info!("{} Finding structural variant breakpoints...", print_time());
+                            call_sv_breakpoints(
+                                bam_file,
+                                &fasta_file,
+                                &interval,
+                                sample_name,
+                                &HashMap::new(),
+                                &HashMap::new(),
+                                min_mapq,
+                                min_sv_len,
+                                min_sv_support,
+                                sv_file,
+                                append_vcf,
+                            )
+                            .chain_err(|| "Error calling structural variant breakpoints.")?;
+                        }
+                    }
+                }
+                sample_varlists.push(varlist);
+                continue;
+            }
+
+            /*******************************************************************************************/
+            // ITERATIVELY ASSEMBLE HAPLOTYPES AND CALL GENOTYPES
+            /*******************************************************************************************/
+
+            stage_timer.start("haplotype assembly");
+
+            let methylation_markers = if methylation_phasing {
+                info!(
+                    "{} Finding CpG methylation markers for phasing...",
+                    print_time()
+                );
+                let markers = find_methylation_markers(
+                    bam_file,
+                    &fasta_file,
+                    &interval,
+                    &extract_fragment_parameters,
+                )
+                .chain_err(|| "Error reading methylation calls from BAM reads.")?;
+                info!(
+                    "{} Found {} CpG markers with calls in {} reads.",
+                    SPACER,
+                    markers.sites.len(),
+                    markers.calls.len()
+                );
+                Some(markers)
+            } else {
+                None
+            };
+
+            let known_phase = match known_phases.get(sample_ix) {
+                Some(&Some(ref known)) => {
+                    let constraints = known_phase_constraints(&varlist, known, phase_trust);
+                    info!(
+                        "{} {} variants have a known phase, in {} phase sets.",
+                        SPACER,
+                        constraints.hap1_alleles.iter().filter(|a| a.is_some()).count(),
+                        constraints.fragments.len()
+                    );
+                    Some(constraints)
+                }
+                _ if use_hp_tags => {
+                    let constraints =
+                        tagged_read_constraints(&flist, &varlist, max_p_miscall, phase_trust);
+                    info!(
+                        "{} {} variants are phased by the HP tags of the reads, in {} phase sets.",
+                        SPACER,
+                        constraints.hap1_alleles.iter().filter(|a| a.is_some()).count(),
+                        constraints.fragments.len()
+                    );
+                    Some(constraints)
+                }
+                _ => None,
+            };
+
+            info!(
+                "{} Iteratively assembling haplotypes and refining genotypes...",
+                print_time()
+            );
+            let hap_rounds = call_genotypes_with_haplotypes(
+                &mut flist,
+                &mut varlist,
+                &interval,
+                &genotype_priors,
+                &variant_debug_directory,
+                3,
+                max_cov,
+                &density_params,
+                max_p_miscall,
+                sample_name,
+                &hap_iteration_params,
+                &methylation_markers,
+                &known_phase,
+                &contig_params,
+            )
+            .chain_err(|| "Error during haplotype/genotype iteration procedure.")?;
+
+            if let Some(ref stats_file) = hap_iteration_stats {
+                print_hap_iteration_stats(
+                    &hap_rounds,
+                    &interval,
+                    sample_name,
+                    stats_file,
+                    append_vcf || sample_ix > 0,
+                )
+                .chain_err(|| "Error writing haplotype iteration statistics.")?;
+            }
+
+            /*
+            if use_poa {
+                /***********************************************************************************************/
+            // PERFORM PARTIAL ORDER ALIGNMENT TO FIND NEW VARIANTS
+            /***********************************************************************************************/
+            let (h1,h2) = separate_reads_by_haplotype(&flist, LogProb::from(Prob(0.99)));
 
-    // multiply by 2.0 because internally we use this value as the probability of transition to
-    // a single transversion base, not the combined probability of transversion to either one
-    let ts_tv_ratio = 2.0 * parse_positive_f64(&input_args, "ts/tv Ratio")?;
+            info!("{} Using Partial Order Alignment (POA) to find new variants...", print_time());
 
-    let dn_params = input_args
-        .value_of("Density parameters")
-        .chain_err(|| "Density parameters not defined.")?
-        .split(":")
-        .collect::<Vec<&str>>();
+            let mut varlist_poa = call_potential_snvs::call_potential_variants_poa(&bamfile_name,
+            &fasta_file,
+            &interval,
+            &h1,
+            &h2,
+            max_cov,
+            min_mapq,
+            alignment_parameters.ln());
 
-    if dn_params.len() != 3 {
-        bail!(
-            "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
-        );
-    }
+            info!("{} Merging POA variants with pileup SNVs...",print_time());
 
-    let dn_count = dn_params[0].parse::<usize>().chain_err(|| {
-        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
-    })?;
-    let dn_len = dn_params[1].parse::<usize>().chain_err(|| {
-        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
-    })?;
-    let dn_gq = dn_params[2].parse::<usize>().chain_err(|| {
-        "Format for density params should be <n>:<l>:<gq>, with all 3 values being integers."
-    })?;
+            varlist.combine(&mut varlist_poa);
 
-    let density_params = DensityParameters {
-        n: dn_count,
-        len: dn_len,
-        gq: dn_gq as f64,
-    };
+            print_variant_debug(&mut varlist, &interval, &variant_debug_directory,&"4.0.new_potential_SNVs_after_POA.vcf", max_cov, &density_params, &sample_name);
 
-    let alignment_type = match (
-        parse_flag(&input_args, "Numerically stable alignment")?,
-        parse_flag(&input_args, "Max alignment")?,
-    ) {
-        (false, false) => AlignmentType::ForwardAlgorithmNonNumericallyStable,
-        (true, false) => AlignmentType::ForwardAlgorithmNumericallyStable,
-        (false, true) => AlignmentType::ViterbiMaxScoringAlignment,
-        (true, true) => {
-            bail!(
-                "Numerically stable alignment option and max alignment options are incompatible."
-            );
-        }
-    };
+            info!("{} {} potential variants after POA.", print_time(),varlist.lst.len());
 
-    let band_width: usize = parse_usize(&input_args, "Band width")?;
-    //let use_poa = parse_flag(&input_args, "Use POA");
-    let min_cov: u32 = parse_u32(&input_args, "Min coverage")?;
+            /***********************************************************************************************/
+            // PRODUCE FRAGMENT DATA FOR NEW VARIANTS
+            /***********************************************************************************************/
+            info!("{} Producing condensed read data for POA variants...",print_time());
+            let mut flist2 = extract_fragments::extract_fragments(&bamfile_name,
+            &fasta_file,
+            &varlist,
+            &interval,
+            extract_fragment_parameters,
+            alignment_parameters,
+            None);  // Some(flist)
 
-    let max_cov: u32 = match parse_flag(&input_args, "Auto max coverage")? {
-        false => {
-            // manually assigned coverage cutoff from user
-            parse_u32(&input_args, "Max coverage")?
-        }
-        true => {
-            eprintln!(
-                "{} Automatically determining max read coverage.",
-                print_time()
-            );
-            eprintln!("{} Estimating mean read coverage...", print_time());
-            let mean_coverage: f64 = calculate_mean_coverage(&bamfile_name, &interval)
-                .chain_err(|| "Error calculating mean coverage for BAM file.")?;
-            let calculated_max_cov =
-                (mean_coverage as f64 + 5.0 * (mean_coverage as f64).sqrt()) as u32;
-            eprintln!("{} Mean read coverage: {:.2}", print_time(), mean_coverage);
+            call_genotypes_no_haplotypes(&flist2, &mut varlist, &genotype_priors, max_p_miscall); // temporary
+            print_variant_debug(&mut varlist, &interval, &variant_debug_directory,&"5.0.realigned_genotypes_after_POA.vcf", max_cov, &density_params, &sample_name);
 
-            calculated_max_cov
-        }
-    };
+            info!("{} Iteratively assembling haplotypes and refining genotypes (with POA variants)...",print_time());
+            call_genotypes_with_haplotypes(&mut flist2, &mut varlist, &interval, &genotype_priors,
+            &variant_debug_directory, 6, max_cov, max_p_miscall, &sample_name, ll_delta);
 
-    if max_cov > 0 {
-        eprintln!("{} Min read coverage set to {}.", print_time(), min_cov);
-        eprintln!("{} Max read coverage set to {}.", print_time(), max_cov);
-    } else {
-        // should print empty VCF file here before bailing 09/04/2020
+            /***********************************************************************************************/
+            // PERFORM FINAL FILTERING STEPS AND PRINT OUTPUT VCF
+            /***********************************************************************************************/
+            //calculate_mec(&flist2, &mut varlist);
+            }
 
-        let vcf_path = Path::new(&output_vcf_file);
-        let vcf_display = vcf_path.display();
-        // Open a file in write-only mode, returns `io::Result<File>`
-        let mut file = File::create(&vcf_path)
-            .chain_err(|| ErrorKind::CreateFileError(vcf_display.to_string()))?;
+            let debug_filename = if use_poa {
+            "7.0.final_genotypes.vcf"
+            } else {
+            "4.0.final_genotypes.vcf"
+            };
+             */
+
+            if out_junctions.is_some() || min_junction_qual.is_some() {
+                let mut junctions = phase_junctions(&flist, &varlist, max_p_miscall);
+                if let Some(min_qual) = min_junction_qual {
+                    let n_split = split_phase_blocks(&mut varlist, &mut junctions, min_qual);
+                    info!(
+                        "{} Split phase blocks at {} of {} junctions with a quality below {}.",
+                        print_time(),
+                        n_split,
+                        junctions.len(),
+                        min_qual
+                    );
+                }
+                if let Some(ref junctions_file) = out_junctions {
+                    print_phase_junctions(
+                        &junctions,
+                        &varlist,
+                        &interval,
+                        junctions_file,
+                        append_vcf,
+                    )
+                    .chain_err(|| "Error writing phase junctions.")?;
+                }
+            }
 
-        let fasta = IndexedReader::from_file(&fasta_file).chain_err(|| ErrorKind::IndexedFastaOpenError)?;
+            let (n_singletons, n_rescued) = resolve_singleton_phase_blocks(
+                &flist,
+                &mut varlist,
+                singleton_phase,
+                singleton_rescue_qual,
+                max_p_miscall,
+            );
+            if singleton_phase == SingletonPhase::Rescue {
+                info!(
+                    "{} {} of {} singleton phase blocks joined to another block.",
+                    print_time(),
+                    n_rescued,
+                    n_singletons
+                );
+            }
 
-        print_vcf_header(
-            &mut file,
-            &vcf_display,
-            &sample_name,
-            potential_variants_file.is_some(),
-            &Some(fasta),
-        )
-        .chain_err(|| "Error printing VCF output.")?;
-        bail!("{} ERROR: Max read coverage set to 0. printing empty VCF file");
-    }
+            // calculate MEC-based statistics for variants and blocks
+            calculate_mec(&flist, &mut varlist, max_p_miscall)
+                .chain_err(|| "Error calculating MEC for haplotype blocks.")?;
+            calculate_phasing_quality(&flist, &mut varlist, max_p_miscall);
+            count_haplotype_alleles(
+                &flist,
+                &mut varlist,
+                LogProb::from(Prob(1.0 - hap_max_p_misassign)),
+                max_p_miscall,
+            );
 
-    // we store the read IDs if we will be separating the reads by haplotype
-    // we will compute sets holding the separated read IDs and then refer back to the original BAM
-    // and write to separate files based on set membership
-    let store_read_id = out_bam != None;
+            if let Some(ref fragments_file) = out_fragments {
+                info!("{} Writing haplotype fragments to file...", print_time());
+                print_fragment_file(
+                    &flist,
+                    &varlist,
+                    &interval,
+                    output_rg,
+                    max_p_miscall,
+                    fragments_file,
+                )
+                .chain_err(|| "Error writing fragment file.")?;
+            }
 
-    let extract_fragment_parameters = ExtractFragmentParameters {
-        min_mapq,
-        alignment_type,
-        band_width,
-        anchor_length,
-        variant_cluster_max_size: variant_cluster_max_size,
-        max_window_padding,
-        max_cigar_indel,
-        store_read_id,
-    };
+            if let Some(ref matrix_file) = out_read_matrix {
+                info!("{} Writing read allele matrix to file...", print_time());
+                print_read_matrix(
+                    &flist,
+                    &varlist,
+                    &interval,
+                    LogProb::from(Prob(1.0 - hap_max_p_misassign)),
+                    max_p_miscall,
+                    matrix_file,
+                    append_vcf,
+                )
+                .chain_err(|| "Error writing read allele matrix.")?;
+            }
 
-    eprintln!("{} Estimating alignment parameters...", print_time());
-    let alignment_parameters = estimate_alignment_parameters(
-        &bamfile_name,
-        &fasta_file,
-        &interval,
-        min_mapq,
-        max_cigar_indel as u32,
-    )
-    .chain_err(|| "Error estimating alignment parameters.")?;
+            if let Some(ref read_haps_file) = out_read_haps {
+                info!("{} Writing read haplotype assignments to file...", print_time());
+                print_read_haplotypes(
+                    &flist,
+                    &varlist,
+                    LogProb::from(Prob(1.0 - hap_max_p_misassign)),
+                    max_p_miscall,
+                    read_haps_file,
+                    append_vcf,
+                )
+                .chain_err(|| "Error writing read haplotype assignments.")?;
+            }
 
-    /***********************************************************************************************/
-    // GET GENOTYPE PRIORS
-    /***********************************************************************************************/
+            if let Some(ref mut evidence) = evidence {
+                evidence.add_sites(&flist, &varlist, &interval);
+            }
 
-    let genotype_priors = GenotypePriors::new(
-        hom_snv_rate,
-        het_snv_rate,
-        hom_indel_rate,
-        het_indel_rate,
-        ts_tv_ratio,
-    )
-    .chain_err(|| "Error estimating genotype priors.")?;
+            // if haplotype-based read separation is turned on,
+            // write BAM files for h1,h2, and unassigned
+            if out_bam.is_some() || hap_coverage.is_some() || hap_fastq.is_some() || sv_vcf.is_some()
+            {
+                info!(
+                    "{} Calculating number of reads (filtered reads only) assigned to either haplotype...",
+                    print_time()
+                );
+                // h1 and h2 are hash-maps keyed on qnames of the reads assigned to haplotype 1 and 2 respectively.
+                // the values are the phase set that it belongs to
+                let (h1, h2, margins, error_rates, contigs) = separate_fragments_by_haplotype(
+                    &flist,
+                    &varlist,
+                    LogProb::from(Prob(1.0 - hap_max_p_misassign)),
+                    max_p_miscall,
+                )?;
 
-    /***********************************************************************************************/
-    // FIND INITIAL SNVS WITH READ PILEUP
-    /***********************************************************************************************/
+                if let Some(filename) = out_bam {
+                    info!(
+                        "{} Writing haplotype-assigned reads to bam files...",
+                        print_time()
+                    );
+                    let sample_filename = sample_bam_output_name(filename, sample_name, multisample);
+                    separate_bam_reads_by_haplotype(
+                        bam_file,
+                        &interval,
+                        &sample_filename,
+                        &h1,
+                        &h2,
+                        &margins,
+                        &error_rates,
+                        &contigs,
+                        min_mapq,
+                    )
+                    .chain_err(|| "Error separating BAM reads by haplotype.")?;
+                }
 
-    //let bam_file: String = "test_data/test.bam".to_string();
-    let mut varlist = match potential_variants_file {
-        Some(file) => {
-            eprintln!(
-                "{} Reading potential variants from input VCF...",
-                print_time()
-            );
+                if let Some(prefix) = hap_coverage {
+                    info!(
+                        "{} Writing haplotype coverage tracks...",
+                        print_time()
+                    );
+                    let sample_prefix = if multisample {
+                        format!("{}.{}", prefix, sample_name)
+                    } else {
+                        prefix.to_string()
+                    };
+                    write_haplotype_coverage(
+                        bam_file,
+                        &interval,
+                        &sample_prefix,
+                        &h1,
+                        &h2,
+                        min_mapq,
+                    )
+                    .chain_err(|| "Error writing haplotype coverage tracks.")?;
+                }
 
-            parse_vcf_potential_variants(&file.to_string(), &bamfile_name)
-                .chain_err(|| "Error reading potential variants VCF file.")?
-        }
-        None => {
-            eprintln!("{} Calling potential SNVs using pileup...", print_time());
+                if let Some(prefix) = hap_fastq {
+                    info!(
+                        "{} Writing haplotype-separated reads to FASTQ files...",
+                        print_time()
+                    );
+                    let sample_prefix = if multisample {
+                        format!("{}.{}", prefix, sample_name)
+                    } else {
+                        prefix.to_string()
+                    };
+                    write_haplotype_fastq(bam_file, &interval, &sample_prefix, &h1, &h2)
+                        .chain_err(|| "Error writing haplotype-separated FASTQ files.")?;
+                }
 
-            call_potential_snvs::call_potential_snvs(
-                &bamfile_name,
-                &fasta_file,
+                if let Some(ref sv_file) = sv_vcf {
+                    match external_svs {
+                        Some(ref svs) => {
+                            info!("{} Genotyping structural variants...", print_time());
+                            genotype_external_svs(
+                                bam_file,
+                                &fasta_file,
+                                &interval,
+                                sample_name,
+                                &h1,
+                                &h2,
+                                svs,
+                                min_mapq,
+                                min_sv_support,
+                                alignment_parameters.ln(),
+                                band_width,
+                                sv_file,
+                                append_vcf,
+                            )
+                            .chain_err(|| "Error genotyping structural variants.")?;
+                        }
+                        None => {
+                            info!(
+                                "{} Finding structural variant breakpoints...",
+                                print_time()
+                            );
+                            call_sv_breakpoints(
+                                bam_file,
+                                &fasta_file,
+                                &interval,
+                                sample_name,
+                                &h1,
+                                &h2,
+                                min_mapq,
+                                min_sv_len,
+                                min_sv_support,
+                                sv_file,
+                                append_vcf,
+                            )
+                            .chain_err(|| "Error calling structural variant breakpoints.")?;
+                        }
+                    }
+                }
+            }
+
+            print_variant_debug(
+                &mut varlist,
                 &interval,
-                &genotype_priors,
-                min_cov,
+                &variant_debug_directory,
+                "4.0.final_genotypes.vcf",
                 max_cov,
-                potential_snv_min_alt_count,
-                potential_snv_min_alt_frac,
-                min_mapq,
-                alignment_parameters.ln(),
-                potential_snv_cutoff,
-            )
-            .chain_err(|| "Error calling potential SNVs.")?
-        }
-    };
-    /*let mut varlist = call_potential_snvs::call_potential_snvs(
-        &bamfile_name,
-        &fasta_file,
-        &interval,
-        &genotype_priors,
-        min_cov,
-        max_cov,
-        potential_snv_min_alt_count,
-        potential_snv_min_alt_frac,
-        min_mapq,
-        alignment_parameters.ln(),
-        potential_snv_cutoff,
-    )
-    .chain_err(|| "Error calling potential SNVs.")?;*/
-
-    print_variant_debug(
-        &mut varlist,
-        &interval,
-        &variant_debug_directory,
-        &"1.0.potential_SNVs.vcf",
-        max_cov,
-        &density_params,
-        &sample_name,
-    )?;
-
-    eprintln!(
-        "{} {} potential variants identified.",
-        print_time(),
-        varlist.lst.len()
-    );
+                &density_params,
+                sample_name,
+                &contig_params,
+            )?;
 
-    if varlist.lst.len() == 0 {
-        /* no variants identified, but still print empty VCF file with header, 02/12/20 */
-        eprintln!("No candidate variants identified, printing empty VCF file...");
-        print_vcf(
-            &mut varlist,
-            &interval,
-            &Some(fasta_file),
-            &output_vcf_file,
-            false,
-            max_cov,
-            &density_params,
-            &sample_name,
-            false,
-            potential_variants_file != None,
-        )
-        .chain_err(|| "Error printing VCF output.")?;
-        return Ok(());
-    }
+            // the fragments index into varlist, so the merged records go into a separate list
+            if merge_mnvs {
+                info!(
+                    "{} Merging adjacent phased variants into MNVs...",
+                    print_time()
+                );
+                mnv_varlist = Some(
+                    merge_phased_mnvs(&flist, &varlist, max_p_miscall, keep_mnv_components)
+                        .chain_err(|| "Error merging adjacent phased variants.")?,
+                );
+            }
 
-    /***********************************************************************************************/
-    // EXTRACT FRAGMENT INFORMATION FROM READS
-    /***********************************************************************************************/
+            sample_varlists.push(varlist);
+        }
 
-    eprintln!(
-        "{} Generating haplotype fragments from reads...",
-        print_time()
-    );
-    let mut flist = extract_fragments::extract_fragments(
-        &bamfile_name,
-        &fasta_file,
-        &mut varlist,
-        &interval,
-        extract_fragment_parameters,
-        alignment_parameters,
-    )
-    .chain_err(|| "Error generating haplotype fragments from BAM reads.")?;
+        if somatic {
+            info!("{} Calculating somatic variant posteriors...", print_time());
+            let (tumor_varlists, normal_varlists) = sample_varlists.split_at_mut(1);
+            call_somatic_variants(
+                &mut tumor_varlists[0],
+                &normal_varlists[0],
+                &tumor_likelihoods,
+                &somatic_params,
+            );
+        }
 
-    // if we're printing out variant "debug" information, print out a fragment file to that debug directory
-    match &variant_debug_directory {
-        &Some(ref debug_dir) => {
-            let ffn = match Path::new(&debug_dir).join(&"fragments.txt").to_str() {
-                Some(s) => s.to_owned(),
-                None => {
-                    bail!("Invalid unicode provided for variant debug directory");
-                }
-            };
-            // normally phase_variant is used to select which variants are heterozygous, so that
-            // we only pass to HapCUT2 heterozygous variants
-            // in this case, we set them all to 1 so we generate fragments for all variants
-            let phase_variant: Vec<bool> = vec![true; varlist.lst.len()];
-            // generate_flist_buffer generates a Vec<Vec<u8>> where each inner vector is a file line
-            // together the lines represent the contents of a fragment file in HapCUT-like format
-            let fragment_buffer =
-                generate_flist_buffer(&flist, &phase_variant, max_p_miscall, true)
-                    .chain_err(|| "Error generating fragment list buffer.")?;
+        if mosaic {
+            info!("{} Calculating mosaic variant posteriors...", print_time());
+            for (varlist, likelihoods) in
+                sample_varlists.iter_mut().zip(mosaic_likelihoods.iter())
+            {
+                let n_mosaic = call_mosaic_variants(varlist, likelihoods, &mosaic_params);
+                info!("{} Called {} mosaic variants.", SPACER, n_mosaic);
+            }
+        }
+
+        if !trios.is_empty() {
+            info!(
+                "{} Applying Mendelian inheritance priors to {} trio(s)...",
+                print_time(),
+                trios.len()
+            );
+            apply_pedigree_priors(&mut sample_varlists, &trios, &genotype_priors, de_novo_rate)
+                .chain_err(|| "Error applying pedigree priors.")?;
+        }
+
+        // variants that were realigned together get a quality for their cluster as a whole
+        for varlist in sample_varlists.iter_mut() {
+            calculate_cluster_quals(varlist);
+        }
+        if let Some(ref mut vl) = mnv_varlist {
+            calculate_cluster_quals(vl);
+        }
 
-            // convert the buffer of u8s into strings and print them to the fragment file
-            let fragment_file_path = Path::new(&ffn);
-            let mut fragment_file = File::create(&fragment_file_path)
-                .chain_err(|| "Could not open fragment file for writing.")?;
-            for mut line_u8 in fragment_buffer {
-                line_u8.pop();
-                writeln!(fragment_file, "{}", u8_to_string(&line_u8)?)
-                    .chain_err(|| "Error writing to fragment file.")?;
+        for (varlist, truth) in sample_varlists.iter().zip(calibration_truth_genotypes.iter()) {
+            if let &Some(ref truth) = truth {
+                calibration_calls.add_varlist(varlist, truth, &calibration_regions);
+            }
+        }
+        if let Some(ref c) = calibration {
+            for varlist in sample_varlists.iter_mut().chain(mnv_varlist.iter_mut()) {
+                c.apply(varlist);
             }
         }
-        &None => {}
-    }
 
-    /***********************************************************************************************/
-    // CALL GENOTYPES USING REFINED QUALITY SCORES
-    /***********************************************************************************************/
+        if !site_filters.is_empty() {
+            for varlist in sample_varlists.iter_mut().chain(mnv_varlist.iter_mut()) {
+                apply_site_filters(varlist, &site_filters, &fasta_file)
+                    .chain_err(|| "Error applying the variant filters.")?;
+            }
+        }
+        if min_soft_clip_cluster.is_some() {
+            for (varlist, clusters) in
+                sample_varlists.iter_mut().zip(sample_soft_clip_clusters.iter())
+            {
+                flag_soft_clip_cluster_snvs(varlist, clusters);
+            }
+            // the merged MNV records are written for a single sample
+            if let Some(ref mut vl) = mnv_varlist {
+                flag_soft_clip_cluster_snvs(vl, &sample_soft_clip_clusters[0]);
+            }
+        }
 
-    eprintln!(
-        "{} Calling initial genotypes using pair-HMM realignment...",
-        print_time()
-    );
-    call_genotypes_no_haplotypes(&flist, &mut varlist, &genotype_priors, max_p_miscall)
-        .chain_err(|| "Error calling initial genotypes with estimated allele qualities.")?;
+        if emit_all {
+            for varlist in sample_varlists.iter_mut().chain(mnv_varlist.iter_mut()) {
+                flag_reference_calls(varlist);
+            }
+        }
 
-    // use Fishers exact test to check if allele observations are biased toward one strand or the other
-    for mut var in &mut varlist.lst {
-        if !var.alleles.len() == 2 {
-            continue;
+        if let Some(ref annotation) = annotation_vcf {
+            for (varlist, sample_name) in sample_varlists.iter_mut().zip(sample_names.iter()) {
+                let n_matched = annotate_variants(varlist, annotation, sample_name)
+                    .chain_err(|| "Error reading the annotation VCF.")?;
+                info!(
+                    "{} {} variants of sample {} match a record of the annotation VCF.",
+                    print_time(),
+                    n_matched,
+                    sample_name
+                );
+            }
+            // the merged MNV records are written for a single sample
+            if let Some(ref mut vl) = mnv_varlist {
+                annotate_variants(vl, annotation, &sample_names[0])
+                    .chain_err(|| "Error reading the annotation VCF.")?;
+            }
         }
-        let counts: [u32; 4] = [
-            var.allele_counts_forward[0] as u32,
-            var.allele_counts_reverse[0] as u32,
-            var.allele_counts_forward[1] as u32,
-            var.allele_counts_reverse[1] as u32,
-        ];
-        let fishers_exact_pvalues = fishers_exact(&counts)
-            .chain_err(|| "Error calculating Fisher's exact test for strand bias.")?;
-
-        //println!("{:?} {:?} {:?}  {:?}",&counts, fishers_exact_pvalues.two_tail_pvalue, fishers_exact_pvalues.less_pvalue, fishers_exact_pvalues.greater_pvalue);
-        var.strand_bias_pvalue = if fishers_exact_pvalues.two_tail_pvalue <= 500.0 {
-            *PHREDProb::from(Prob(fishers_exact_pvalues.two_tail_pvalue))
-        } else {
-            500.0
-        };
 
-        if fishers_exact_pvalues.two_tail_pvalue < strand_bias_pvalue_cutoff {
-            var.filter.add_filter(VarFilter::StrandBias);
-            var.genotype = Genotype(0, 0);
-            var.gq = 0.0;
+        // Print the final VCF output
+        stage_timer.start("output");
+        info!("{} Printing VCF file...", print_time());
+        if !multisample {
+            // the reference sequence context is not printed if haplotype assembly is turned off
+            let print_fasta = if no_haps {
+                None
+            } else {
+                Some(fasta_file.clone())
+            };
+            print_vcf(
+                match mnv_varlist {
+                    Some(ref mut vl) => vl,
+                    None => &mut sample_varlists[0],
+                },
+                &interval,
+                &print_fasta,
+                &output_vcf_file,
+                output_rg, // change to command line parameter output_ref
+                max_cov,
+                &density_params,
+                &sample_names[0],
+                false,
+                potential_variants_file != None,
+                &contig_params,
+                append_vcf,
+            )
+            .chain_err(|| "Error printing VCF output.")?;
+        } else {
+            print_vcf_multisample(
+                &mut sample_varlists,
+                &interval,
+                &Some(fasta_file.clone()),
+                &output_vcf_file,
+                output_rg,
+                max_cov,
+                &density_params,
+                &sample_names,
+                potential_variants_file != None,
+                &contig_params,
+                append_vcf,
+            )
+            .chain_err(|| "Error printing VCF output.")?;
         }
-    }
 
-    for f in 0..flist.len() {
-        &flist[f].calls.retain(|&c| {
-            !varlist.lst[c.var_ix as usize]
-                .filter
-                .has_filter(VarFilter::StrandBias)
-        });
-    }
+        if let Some(ref mut writer) = parquet_writer {
+            let written = if !multisample {
+                let varlist = match mnv_varlist {
+                    Some(ref vl) => vl,
+                    None => &sample_varlists[0],
+                };
+                writer.write_row_group(&[varlist], &sample_names, &interval, output_rg)
+            } else {
+                let varlists: Vec<&VarList> = sample_varlists.iter().collect();
+                writer.write_row_group(&varlists, &sample_names, &interval, output_rg)
+            };
+            written.chain_err(|| "Error writing the Parquet output.")?;
+        }
 
-    print_variant_debug(
-        &mut varlist,
-        &interval,
-        &variant_debug_directory,
-        &"2.0.realigned_genotypes.vcf",
-        max_cov,
-        &density_params,
-        &sample_name,
-    )?;
+        if let Some(ref filename) = consensus_fasta {
+            info!("{} Printing consensus sequences...", print_time());
+            for (i, (sample_name, varlist)) in
+                sample_names.iter().zip(sample_varlists.iter()).enumerate()
+            {
+                print_consensus_fasta(
+                    varlist,
+                    &interval,
+                    &bamfile_name,
+                    &fasta_file,
+                    filename,
+                    sample_name,
+                    append_vcf || i > 0,
+                )
+                .chain_err(|| "Error printing consensus sequences.")?;
+            }
+        }
 
-    // if haplotype information usage is turned off, immediately print VCF and terminate.
-    if no_haps {
-        print_vcf(
-            &mut varlist,
-            &interval,
-            &None,
-            &output_vcf_file,
-            output_rg, // change to command line parameter output_ref
-            max_cov,
-            &density_params,
-            &sample_name,
-            false,
-            potential_variants_file != None,
-        )
-        .chain_err(|| "Error printing VCF output.")?;
-        return Ok(());
-    }
-    /***********************************************************************************************/
-    // ITERATIVELY ASSEMBLE HAPLOTYPES AND CALL GENOTYPES
-    /***********************************************************************************************/
+        if let Some(ref prefix) = block_consensus {
+            info!("{} Printing phase block sequences...", print_time());
+            for (i, (sample_name, varlist)) in
+                sample_names.iter().zip(sample_varlists.iter()).enumerate()
+            {
+                print_block_consensus(
+                    varlist,
+                    &interval,
+                    &fasta_file,
+                    prefix,
+                    sample_name,
+                    append_vcf || i > 0,
+                )
+                .chain_err(|| "Error printing phase block sequences.")?;
+            }
+        }
 
-    eprintln!(
-        "{} Iteratively assembling haplotypes and refining genotypes...",
-        print_time()
-    );
-    call_genotypes_with_haplotypes(
-        &mut flist,
-        &mut varlist,
-        &interval,
-        &genotype_priors,
-        &variant_debug_directory,
-        3,
-        max_cov,
-        &density_params,
-        max_p_miscall,
-        &sample_name,
-        ll_delta,
-    )
-    .chain_err(|| "Error during haplotype/genotype iteration procedure.")?;
+        if let Some(ref filename) = out_dense_regions {
+            for (i, (sample_name, varlist)) in
+                sample_names.iter().zip(sample_varlists.iter()).enumerate()
+            {
+                print_dense_regions(
+                    varlist,
+                    &interval,
+                    &bamfile_name,
+                    &density_params,
+                    filename,
+                    sample_name,
+                    append_vcf || i > 0,
+                )
+                .chain_err(|| "Error printing dense variant regions.")?;
+            }
+        }
 
-    /*
-    if use_poa {
-        /***********************************************************************************************/
-    // PERFORM PARTIAL ORDER ALIGNMENT TO FIND NEW VARIANTS
-    /***********************************************************************************************/
-    let (h1,h2) = separate_reads_by_haplotype(&flist, LogProb::from(Prob(0.99)));
+        if let Some(ref filename) = out_callable {
+            print_callable_regions(
+                &callable_regions,
+                &sample_varlists,
+                &interval,
+                &bamfile_name,
+                &density_params,
+                filename,
+                append_vcf,
+            )
+            .chain_err(|| "Error printing callable regions.")?;
+        }
 
-    eprintln!("{} Using Partial Order Alignment (POA) to find new variants...", print_time());
+        if let Some(ref filename) = out_soft_clip_clusters {
+            for (i, ((sample_name, varlist), clusters)) in sample_names
+                .iter()
+                .zip(sample_varlists.iter())
+                .zip(sample_soft_clip_clusters.iter())
+                .enumerate()
+            {
+                print_soft_clip_clusters(
+                    clusters,
+                    varlist,
+                    &interval,
+                    filename,
+                    sample_name,
+                    append_vcf || i > 0,
+                )
+                .chain_err(|| "Error printing soft-clip clusters.")?;
+            }
+        }
 
-    let mut varlist_poa = call_potential_snvs::call_potential_variants_poa(&bamfile_name,
-    &fasta_file,
-    &interval,
-    &h1,
-    &h2,
-    max_cov,
-    min_mapq,
-    alignment_parameters.ln());
+        if let (&Some(ref truth_vcf), &Some(ref report_file)) = (&truth_vcf, &phasing_report_file) {
+            info!(
+                "{} Evaluating phasing accuracy against {}...",
+                print_time(),
+                truth_vcf
+            );
+            let mut sample_stats = vec![];
+            for (sample_name, varlist) in sample_names.iter().zip(sample_varlists.iter()) {
+                match parse_truth_vcf(&truth_vcf, sample_name, &varlist.target_names)
+                    .chain_err(|| "Error reading truth VCF.")?
+                {
+                    Some(truth) => {
+                        let stats = evaluate_phasing(varlist, &truth);
+                        info!(
+                            "{} {}: {} of {} phased variants compared, switch error rate {:.4}, flip error rate {:.4}, phase block N50 {} bp",
+                            SPACER,
+                            sample_name,
+                            stats.compared_variants,
+                            stats.phased_variants,
+                            stats.switch_error_rate(),
+                            stats.flip_error_rate(),
+                            stats.phase_block_n50
+                        );
+                        sample_stats.push((sample_name.clone(), stats));
+                    }
+                    None => {
+                        warn!(
+                            "{} WARNING: Sample {} was not found in the truth VCF.",
+                            print_time(),
+                            sample_name
+                        );
+                    }
+                }
+            }
+            print_phasing_report(&sample_stats, &truth_vcf, &report_file)?;
+        }
 
-    eprintln!("{} Merging POA variants with pileup SNVs...",print_time());
+        if quick_look {
+            for varlist in &sample_varlists {
+                call_summary.add_varlist(varlist);
+            }
+        }
+        for varlist in &sample_varlists {
+            run_stats.add_varlist(varlist);
+        }
 
-    varlist.combine(&mut varlist_poa);
+        if let Some(ref mut c) = checkpoint {
+            c.finish_region(
+                interval
+                    .as_ref()
+                    .chain_err(|| "Checkpoints need a region to resume from.")?,
+            )?;
+        }
+    }
 
-    print_variant_debug(&mut varlist, &interval, &variant_debug_directory,&"4.0.new_potential_SNVs_after_POA.vcf", max_cov, &density_params, &sample_name);
+    if let (&Some(ref evidence), &Some(ref filename)) = (&evidence, &out_evidence) {
+        info!(
+            "{} Writing the evidence of {} sites ({} allele calls) to {}...",
+            print_time(),
+            evidence.n_sites(),
+            evidence.n_rows(),
+            filename
+        );
+        evidence
+            .write_npz(filename)
+            .chain_err(|| "Error writing the evidence archive.")?;
+    }
 
-    eprintln!("{} {} potential variants after POA.", print_time(),varlist.lst.len());
+    if let (Some(writer), &Some(ref filename)) = (parquet_writer, &out_parquet) {
+        info!("{} Writing the Parquet footer to {}...", print_time(), filename);
+        writer
+            .finish()
+            .chain_err(|| "Error writing the Parquet output.")?;
+    }
 
-    /***********************************************************************************************/
-    // PRODUCE FRAGMENT DATA FOR NEW VARIANTS
-    /***********************************************************************************************/
-    eprintln!("{} Producing condensed read data for POA variants...",print_time());
-    let mut flist2 = extract_fragments::extract_fragments(&bamfile_name,
-    &fasta_file,
-    &varlist,
-    &interval,
-    extract_fragment_parameters,
-    alignment_parameters,
-    None);  // Some(flist)
-
-    call_genotypes_no_haplotypes(&flist2, &mut varlist, &genotype_priors, max_p_miscall); // temporary
-    print_variant_debug(&mut varlist, &interval, &variant_debug_directory,&"5.0.realigned_genotypes_after_POA.vcf", max_cov, &density_params, &sample_name);
-
-    eprintln!("{} Iteratively assembling haplotypes and refining genotypes (with POA variants)...",print_time());
-    call_genotypes_with_haplotypes(&mut flist2, &mut varlist, &interval, &genotype_priors,
-    &variant_debug_directory, 6, max_cov, max_p_miscall, &sample_name, ll_delta);
+    if quick_look {
+        info!("{} Quick-look summary:", print_time());
+        call_summary.print();
+    }
 
-    /***********************************************************************************************/
-    // PERFORM FINAL FILTERING STEPS AND PRINT OUTPUT VCF
-    /***********************************************************************************************/
-    //calculate_mec(&flist2, &mut varlist);
+    if let (&Some(ref calibration_file), &Some(ref truth_vcf)) =
+        (&fit_calibration, &calibration_truth)
+    {
+        info!(
+            "{} Fitting the quality calibration to {} calls in the truth set...",
+            print_time(),
+            calibration_calls.qual.len()
+        );
+        calibration_calls.fit()?.write(calibration_file, truth_vcf)?;
     }
 
-    let debug_filename = if use_poa {
-    "7.0.final_genotypes.vcf"
-    } else {
-    "4.0.final_genotypes.vcf"
-    };
-     */
+    // the metrics of a resumed run would only cover the regions called after the interruption
+    if !no_haps && !resume {
+        add_vcf_header_lines(&output_vcf_file, &run_stats.phasing_header_lines())
+            .chain_err(|| "Error writing the phasing metrics to the VCF header.")?;
+    }
 
-    // calculate MEC-based statistics for variants and blocks
-    calculate_mec(&flist, &mut varlist, max_p_miscall)
-        .chain_err(|| "Error calculating MEC for haplotype blocks.")?;
+    if let Some(ref annotation) = annotation_vcf {
+        add_vcf_header_lines(&output_vcf_file, &annotation.header_lines())
+            .chain_err(|| "Error writing the annotation fields to the VCF header.")?;
+    }
 
-    // if haplotype-based read separation is turned on,
-    // write BAM files for h1,h2, and unassigned
-    match out_bam {
-        Some(filename) => {
-            eprintln!(
-                "{} Calculating number of reads (filtered reads only) assigned to either haplotype...",
-                print_time()
-            );
-            // h1 and h2 are hash-maps keyed on qnames of the reads assigned to haplotype 1 and 2 respectively.
-            // the values are the phase set that it belongs to
-            let (h1, h2) = separate_fragments_by_haplotype(
-                &flist,
-                &varlist,
-                LogProb::from(Prob(1.0 - hap_max_p_misassign)),
-                max_p_miscall,
-            )?;
+    if let (Some(chain_file), Some(liftover_file)) = (liftover_chain, liftover_vcf_file) {
+        stage_timer.start("liftover");
+        info!("{} Lifting variants over to another assembly...", print_time());
+        liftover_vcf(&output_vcf_file, &chain_file, &liftover_file)?;
+    }
 
-            eprintln!(
-                "{} Writing haplotype-assigned reads to bam files...",
-                print_time()
-            );
-            separate_bam_reads_by_haplotype(&bamfile_name, &interval, filename, &h1, &h2, min_mapq)
-                .chain_err(|| "Error separating BAM reads by haplotype.")?;
-        }
-        None => {}
-    }
-
-    // Print the final VCF output
-    eprintln!("{} Printing VCF file...", print_time());
-    print_variant_debug(
-        &mut varlist,
-        &interval,
-        &variant_debug_directory,
-        "4.0.final_genotypes.vcf",
-        max_cov,
-        &density_params,
-        &sample_name,
-    )?;
-    print_vcf(
-        &mut varlist,
-        &interval,
-        &Some(fasta_file),
-        &output_vcf_file,
-        output_rg, // change to command line parameter output_ref
-        max_cov,
-        &density_params,
-        &sample_name,
-        false,
-        potential_variants_file != None,
-    )
-    .chain_err(|| "Error printing VCF output.")?;
+    stage_timer.print();
 
+    if let Some(ref filename) = stats_out {
+        // the effective value of each parameter that affects the calls, by option name
+        let mut parameters: Vec<(String, String)> = vec![];
+        for &(name, long) in &[
+            ("Preset", "preset"),
+            ("Min coverage", "min_cov"),
+            ("Min mapq", "min_mapq"),
+            ("Min allele quality", "min_allele_qual"),
+            ("Haplotype assignment quality", "hap_assignment_qual"),
+            ("Haplotype tag posterior", "hap_tag_posterior"),
+            ("Potential SNV Cutoff", "potential_snv_cutoff"),
+            ("Potential SNV Min Alt Count", "min_alt_count"),
+            ("Potential SNV Min Alt Fraction", "min_alt_frac"),
+            ("Candidate FDR", "candidate_fdr"),
+            ("Candidate sensitivity", "candidate_sensitivity"),
+            ("Candidates input", "candidates-in"),
+            ("Variant cluster max size", "max_snvs"),
+            ("Max indel length", "max_indel_len"),
+            ("Max alt alleles", "max_alt_alleles"),
+            ("Max window padding", "max_window"),
+            ("Window flank", "window_flank"),
+            ("Window merge distance", "window_merge_distance"),
+            ("Max CIGAR indel", "max_cigar_indel"),
+            ("Band width", "band_width"),
+            ("Reference ambiguity", "ref_ambiguity"),
+            ("Alphabet", "alphabet"),
+            ("Density parameters", "density_params"),
+            ("Density count", "density_count"),
+            ("Density window", "density_window"),
+            ("Density quality", "density_qual"),
+            ("Density score", "density_score"),
+            ("Homozygous SNV Rate", "hom_snv_rate"),
+            ("Heterozygous SNV Rate", "het_snv_rate"),
+            ("Homozygous Indel Rate", "hom_indel_rate"),
+            ("Heterozygous Indel Rate", "het_indel_rate"),
+            ("ts/tv Ratio", "ts_tv_ratio"),
+            ("Strand Bias P-value cutoff", "strand_bias_pvalue_cutoff"),
+            ("Haplotype Convergence Delta", "hap_converge_delta"),
+            ("Haplotype max rounds", "hap_max_rounds"),
+            ("Haplotype converge changes", "hap_converge_changes"),
+            ("Phaser", "phaser"),
+            ("Singleton phase", "singleton_phase"),
+            ("Singleton rescue quality", "singleton_rescue_qual"),
+            ("Ploidy", "ploidy"),
+            ("Mosaic min VAF", "mosaic_min_vaf"),
+            ("Mosaic min posterior", "mosaic_min_posterior"),
+            ("Context error model", "context_error_model"),
+            ("Parameter windows", "param_windows"),
+            ("Parameter window size", "param_window_size"),
+            ("Missing base quality policy", "missing_base_qual"),
+            ("Missing base quality value", "missing_base_qual_value"),
+        ] {
+            let value = match long {
+                // the thresholds are estimated for each region
+                "min_alt_count" | "min_alt_frac" if auto_candidate_thresholds => {
+                    Some("auto".to_string())
+                }
+                "min_alt_count" => Some(potential_snv_min_alt_count.to_string()),
+                "min_alt_frac" => Some(potential_snv_min_alt_frac.to_string()),
+                _ => input_args.value_of(name).map(|v| v.to_string()),
+            };
+            if let Some(v) = value {
+                parameters.push((long.to_string(), v));
+            }
+        }
+        parameters.push(("subcommand".to_string(), subcommand.name().to_string()));
+        // the max coverage may have been estimated from the mean coverage
+        parameters.push(("max_cov".to_string(), max_cov.to_string()));
+        parameters.push(("genotype_prior".to_string(), genotype_prior_name.clone()));
+        parameters.push((
+            "realignment_model".to_string(),
+            alignment_type.model().name().to_string(),
+        ));
+        for &(name, long) in &[
+            ("Strand error model", "strand_error_model"),
+            ("Read group error model", "rg_error_model"),
+            ("Repeat band", "repeat_band"),
+            ("Exact anchors", "exact_anchors"),
+            ("No haplotypes", "no_haps"),
+            ("No genotype refinement", "no_genotype_refinement"),
+            ("Mosaic", "mosaic"),
+        ] {
+            parameters.push((long.to_string(), input_args.is_present(name).to_string()));
+        }
+        let sample_alignment_parameters: Vec<(String, AlignmentParameters)> = sample_names
+            .iter()
+            .cloned()
+            .zip(alignment_parameters_lst.iter().cloned())
+            .collect();
+        run_stats.write(
+            filename,
+            &parameters,
+            &sample_alignment_parameters,
+            &stage_timer.stages(),
+            &stage_timer.stage_work(),
+        )?;
+        info!("{} Wrote run statistics to {}.", print_time(), filename);
+    }
     Ok(())
 }
+
+/// Returns the name of the haplotype-separated BAM output file for a sample. With multiple
+/// samples, the sample name is added before the file extension (e.g. out.bam -> out.NA12878.bam)
+fn sample_bam_output_name(filename: &str, sample_name: &str, multisample: bool) -> String {
+    if !multisample {
+        return filename.to_string();
+    }
+    match filename.rfind(".bam") {
+        Some(i) => format!("{}.{}{}", &filename[..i], sample_name, &filename[i..]),
+        None => format!("{}.{}", filename, sample_name),
+    }
+}
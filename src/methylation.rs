@@ -0,0 +1,429 @@
+//! This module extracts CpG methylation calls from the base modification tags of the reads, for
+//! use as additional markers in haplotype assembly.
+//!
+//! Allele-specific methylation (e.g. at imprinted loci) is informative for phasing in regions where
+//! heterozygous SNVs are too sparse to be linked by the reads. The 5mC probabilities are read from
+//! the ```MM```/```ML``` tags written by nanopore and PacBio basecallers, and CpG sites where the
+//! reads are consistently split between methylated and unmethylated are used as markers. Each
+//! read's methylation state at the markers is then phased together with its variant calls by
+//! HapCUT2. The markers themselves are never written to the output VCF.
+
+use errors::*;
+use extract_fragments::{check_read_filters, ExtractFragmentParameters};
+use hashbrown::HashMap;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Cigar, Record};
+use rust_htslib::htslib;
+use std::ffi::CString;
+use util::*;
+use variants_and_fragments::*;
+
+/// minimum probability of the called state (methylated or unmethylated) to use a methylation call
+static MIN_CALL_PROB: f64 = 0.8;
+/// minimum number of reads with a confident methylation call for a CpG site to be used as a marker
+static MIN_MARKER_COVERAGE: usize = 6;
+/// the fraction of methylated reads at a marker must lie in this range, so that the site looks like
+/// one methylated and one unmethylated haplotype
+static MIN_MARKER_METHYL_FRAC: f64 = 0.25;
+static MAX_MARKER_METHYL_FRAC: f64 = 0.75;
+
+/// CpG sites used as phasing markers, together with each read's methylation calls at them
+pub struct MethylationMarkers {
+    /// (tid, 0-based position of the C) for each CpG marker, in sorted order
+    pub sites: Vec<(u32, usize)>,
+    /// the read ID and marker calls of each read that has a call at one or more markers.
+    /// In the calls, ```var_ix``` is the index of the marker in ```sites``` and allele 1 means
+    /// methylated.
+    pub calls: Vec<(String, Vec<FragCall>)>,
+}
+
+/// Reads the ```ML``` (or legacy ```Ml```) tag of a record, an array of 8-bit modification
+/// probabilities. ```rust_htslib``` does not expose B-array tags, so this goes through htslib.
+fn read_ml_tag(record: &Record) -> Vec<u8> {
+    let mut ml: Vec<u8> = vec![];
+    for tag in &["ML", "Ml"] {
+        let c_tag = CString::new(*tag).unwrap();
+        unsafe {
+            let aux = htslib::bam_aux_get(record.inner, c_tag.as_ptr() as *mut i8);
+            if aux.is_null() || *aux != b'B' {
+                continue;
+            }
+            for i in 0..htslib::bam_auxB_len(aux) {
+                ml.push(htslib::bam_auxB2i(aux, i) as u8);
+            }
+        }
+        break;
+    }
+    ml
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+/// Parses the 5mC calls of a read from its ```MM``` tag and ML values
+///
+/// # Arguments
+/// - ```mm```: the ```MM``` tag string, e.g. ```C+m?,5,0,12;```
+/// - ```ml```: the ```ML``` tag values, one per skip count and modification code in ```mm```
+/// - ```seq```: the read sequence as stored in the BAM record
+/// - ```reverse```: whether the read is aligned to the reverse strand
+///
+/// # Returns
+/// Returns a result containing (index into ```seq```, probability of 5mC) for each cytosine with
+/// a call
+///
+/// # Errors
+/// - Error if ```mm``` cannot be parsed, or it refers to more bases or ML values than exist
+fn parse_5mc_calls(
+    mm: &str,
+    ml: &Vec<u8>,
+    seq: &Vec<u8>,
+    reverse: bool,
+) -> Result<Vec<(usize, f64)>> {
+    // the MM tag counts bases in the orientation the read was sequenced in
+    let orig: Vec<u8> = if reverse {
+        seq.iter().rev().map(|&b| complement(b)).collect()
+    } else {
+        seq.clone()
+    };
+
+    let mut calls: Vec<(usize, f64)> = vec![];
+    let mut ml_ix = 0;
+    for entry in mm.split(';') {
+        if entry.is_empty() {
+            continue;
+        }
+        let mut fields = entry.split(',');
+        let header = fields.next().unwrap_or("").as_bytes();
+        ensure!(header.len() >= 3, "Invalid MM tag entry {}.", entry);
+
+        let base = header[0];
+        let codes: Vec<u8> = header[2..]
+            .iter()
+            .cloned()
+            .filter(|&c| c != b'?' && c != b'.')
+            .collect();
+        // a ChEBI code is a number, and is a single modification
+        let n_codes = if codes.iter().all(|c| c.is_ascii_digit()) {
+            1
+        } else {
+            codes.len()
+        };
+        let m_ix: Option<usize> = if header[1] == b'+' && base == b'C' && n_codes == codes.len() {
+            codes.iter().position(|&c| c == b'm')
+        } else {
+            None
+        };
+
+        let mut i = 0;
+        for field in fields {
+            let mut skip: usize = field
+                .parse()
+                .chain_err(|| format!("Invalid MM tag entry {}.", entry))?;
+            loop {
+                ensure!(i < orig.len(), "MM tag refers to more bases than the read has.");
+                if base == b'N' || orig[i] == base {
+                    if skip == 0 {
+                        break;
+                    }
+                    skip -= 1;
+                }
+                i += 1;
+            }
+            ensure!(ml_ix + n_codes <= ml.len(), "MM tag has more calls than the ML tag.");
+            if let Some(m) = m_ix {
+                let p = (ml[ml_ix + m] as f64 + 0.5) / 256.0;
+                let seq_ix = if reverse { orig.len() - 1 - i } else { i };
+                calls.push((seq_ix, p));
+            }
+            ml_ix += n_codes;
+            i += 1;
+        }
+    }
+
+    Ok(calls)
+}
+
+/// Returns the reference position aligned to each base of the read (```None``` for inserted and
+/// soft-clipped bases)
+fn read_ref_positions(record: &Record) -> Vec<Option<usize>> {
+    let mut positions: Vec<Option<usize>> = Vec::with_capacity(record.seq().len());
+    let mut ref_pos = record.pos() as usize;
+    for op in record.cigar().iter() {
+        match *op {
+            Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                for _ in 0..l {
+                    positions.push(Some(ref_pos));
+                    ref_pos += 1;
+                }
+            }
+            Cigar::Ins(l) | Cigar::SoftClip(l) => {
+                for _ in 0..l {
+                    positions.push(None);
+                }
+            }
+            Cigar::Del(l) | Cigar::RefSkip(l) => {
+                ref_pos += l as usize;
+            }
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+    positions
+}
+
+/// Returns (0-based reference position of the CpG's C, probability of methylation) for each
+/// reference CpG site that the read has a 5mC call at. Reads without base modification tags,
+/// hard-clipped reads, and reads with tags that do not match the read sequence have no calls.
+fn read_cpg_calls(record: &Record, ref_seq: &RefSeqWindow) -> Vec<(usize, f64)> {
+    // the base modification tags describe the whole read, so they can't be used after hard clipping
+    for op in record.cigar().iter() {
+        if let Cigar::HardClip(_) = *op {
+            return vec![];
+        }
+    }
+    let mm: String = match record.aux(b"MM").or_else(|| record.aux(b"Mm")) {
+        Some(bam::record::Aux::String(s)) => String::from_utf8_lossy(s).to_string(),
+        _ => return vec![],
+    };
+    let ml = read_ml_tag(record);
+    let seq = record.seq().as_bytes();
+    let mod_calls = match parse_5mc_calls(&mm, &ml, &seq, record.is_reverse()) {
+        Ok(c) => c,
+        Err(_) => return vec![],
+    };
+
+    let positions = read_ref_positions(record);
+    let mut calls: Vec<(usize, f64)> = vec![];
+    for (seq_ix, p) in mod_calls {
+        let r = match positions.get(seq_ix) {
+            Some(&Some(r)) => r,
+            _ => continue,
+        };
+        // on a reverse strand read the call is for the C on the opposite strand, which pairs with
+        // the G of the CpG on the reference
+        if record.is_reverse() {
+            if r > 0 && r < ref_seq.len() && ref_seq[r] == 'G' && ref_seq[r - 1] == 'C' {
+                calls.push((r - 1, p));
+            }
+        } else if r + 1 < ref_seq.len() && ref_seq[r] == 'C' && ref_seq[r + 1] == 'G' {
+            calls.push((r, p));
+        }
+    }
+    calls
+}
+
+/// Calls ```f(read ID, tid, CpG calls)``` for every read that passes the read filters
+fn for_each_read_cpg_calls<F>(
+    bam_file: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    extract_params: &ExtractFragmentParameters,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(String, u32, Vec<(usize, f64)>),
+{
+    let t_names = parse_target_names(&bam_file)?;
+
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bam_file, interval)?;
+    let mut bam_ix =
+        open_indexed_bam(bam_file)?;
+
+    for iv in interval_lst {
+        bam_ix
+            .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| "Error seeking BAM file while reading methylation calls.")?;
+
+        for r in bam_ix.records() {
+            let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if check_read_filters(&record, extract_params).is_some() {
+                continue;
+            }
+
+            // the reference under the read, with the neighboring base of a CpG at either end
+            let tid: usize = record.tid() as usize;
+            let ref_seq = ref_reader.window(
+                &t_names[tid],
+                (record.pos() as usize).saturating_sub(1),
+                record.cigar().end_pos() as usize + 1,
+            )?;
+
+            let calls = read_cpg_calls(&record, ref_seq);
+            if !calls.is_empty() {
+                f(u8_to_string(record.qname())?, tid as u32, calls);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds CpG sites with allele-specific methylation and extracts each read's calls at them
+///
+/// A first pass over the reads counts the confidently methylated and unmethylated reads at every
+/// CpG site. Sites with at least ```MIN_MARKER_COVERAGE``` such reads, and a methylated fraction
+/// between ```MIN_MARKER_METHYL_FRAC``` and ```MAX_MARKER_METHYL_FRAC```, become markers. A second
+/// pass extracts the calls of each read at the markers.
+///
+/// # Arguments
+/// - ```bam_file```: the BAM file, with ```MM```/```ML``` base modification tags
+/// - ```fasta_file```: the reference FASTA file
+/// - ```interval```: the genomic interval being called, or ```None``` for the whole genome
+/// - ```extract_params```: the fragment extraction parameters, for the read filters
+///
+/// # Errors
+/// - Error if the BAM or FASTA file cannot be read
+pub fn find_methylation_markers(
+    bam_file: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    extract_params: &ExtractFragmentParameters,
+) -> Result<MethylationMarkers> {
+    // (methylated, unmethylated) read counts for each CpG site
+    let mut site_counts: HashMap<(u32, usize), (usize, usize)> = HashMap::new();
+    for_each_read_cpg_calls(bam_file, fasta_file, interval, extract_params, |_, tid, calls| {
+        for (pos, p) in calls {
+            let counts = site_counts.entry((tid, pos)).or_insert((0, 0));
+            if p >= MIN_CALL_PROB {
+                counts.0 += 1;
+            } else if 1.0 - p >= MIN_CALL_PROB {
+                counts.1 += 1;
+            }
+        }
+    })?;
+
+    let mut sites: Vec<(u32, usize)> = site_counts
+        .iter()
+        .filter(|&(_, &(methylated, unmethylated))| {
+            let total = methylated + unmethylated;
+            let frac = methylated as f64 / total as f64;
+            total >= MIN_MARKER_COVERAGE
+                && frac >= MIN_MARKER_METHYL_FRAC
+                && frac <= MAX_MARKER_METHYL_FRAC
+        })
+        .map(|(&site, _)| site)
+        .collect();
+    sites.sort();
+
+    let site_ix: HashMap<(u32, usize), usize> =
+        sites.iter().enumerate().map(|(i, &site)| (site, i)).collect();
+
+    let mut read_calls: Vec<(String, Vec<FragCall>)> = vec![];
+    for_each_read_cpg_calls(bam_file, fasta_file, interval, extract_params, |id, tid, calls| {
+        let mut marker_calls: Vec<FragCall> = vec![];
+        for (pos, p) in calls {
+            if let Some(&ix) = site_ix.get(&(tid, pos)) {
+                if p >= MIN_CALL_PROB {
+                    marker_calls.push(FragCall::new(0, ix, 1, 1.0 - p));
+                } else if 1.0 - p >= MIN_CALL_PROB {
+                    marker_calls.push(FragCall::new(0, ix, 0, p));
+                }
+            }
+        }
+        if !marker_calls.is_empty() {
+            marker_calls.sort_by_key(|c| c.var_ix);
+            read_calls.push((id, marker_calls));
+        }
+    })?;
+
+    Ok(MethylationMarkers {
+        sites,
+        calls: read_calls,
+    })
+}
+
+/// Adds the methylation marker calls to a fragment list, for haplotype assembly
+///
+/// The markers are numbered after the variants, so marker ```i``` has ```var_ix``` equal to
+/// ```n_var + i```. Reads with marker calls but no variant calls become new fragments, since
+/// they can link markers (and through them, variants) across regions without heterozygous SNVs.
+///
+/// # Arguments
+/// - ```flist```: the fragments with variant calls
+/// - ```markers```: the methylation markers
+/// - ```n_var```: the number of variants
+pub fn add_methylation_calls(
+    flist: &Vec<Fragment>,
+    markers: &MethylationMarkers,
+    n_var: usize,
+) -> Vec<Fragment> {
+    let read_ix: HashMap<&String, usize> = markers
+        .calls
+        .iter()
+        .enumerate()
+        .map(|(i, &(ref id, _))| (id, i))
+        .collect();
+    let mut used: Vec<bool> = vec![false; markers.calls.len()];
+
+    let offset_calls = |calls: &Vec<FragCall>| -> Vec<FragCall> {
+        calls
+            .iter()
+            .map(|c| {
+                let mut c = c.clone();
+                c.var_ix += n_var;
+                c
+            })
+            .collect()
+    };
+
+    let mut combined: Vec<Fragment> = Vec::with_capacity(flist.len() + markers.calls.len());
+    for frag in flist {
+        let mut frag = frag.clone();
+        if let Some(&ix) = frag.id.as_ref().and_then(|id| read_ix.get(&id)) {
+            frag.calls.extend(offset_calls(&markers.calls[ix].1));
+            used[ix] = true;
+        }
+        combined.push(frag);
+    }
+    for (ix, &(ref id, ref calls)) in markers.calls.iter().enumerate() {
+        if !used[ix] {
+            combined.push(Fragment::new(Some(id.clone()), offset_calls(calls)));
+        }
+    }
+    combined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_5mc_calls() {
+        let seq: Vec<u8> = b"ACGTCGACCG".to_vec();
+        // forward read: C at 1, 4, 7, 8. Skip 0 -> 1, skip 1 -> 7
+        let calls = parse_5mc_calls("C+m?,0,1;", &vec![250, 10], &seq, false).unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].0, 1);
+        assert_eq!(calls[1].0, 7);
+        assert!(calls[0].1 > 0.97 && calls[1].1 < 0.05);
+
+        // two codes per skip count: the ML values are interleaved
+        let calls = parse_5mc_calls("C+hm,2;", &vec![0, 200], &seq, false).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, 7);
+        assert!((calls[0].1 - 200.5 / 256.0).abs() < 1e-9);
+
+        // other modifications are skipped, but their ML values are still consumed
+        let calls = parse_5mc_calls("A+a,0;C+m,3;", &vec![100, 50], &seq, false).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, 8);
+        assert!((calls[0].1 - 50.5 / 256.0).abs() < 1e-9);
+
+        // reverse read: the original read is CGGTCGACGT, with C at 0, 4, 7
+        let calls = parse_5mc_calls("C+m,1;", &vec![255], &seq, true).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, 5);
+        assert_eq!(seq[calls[0].0], b'G');
+
+        assert!(parse_5mc_calls("C+m,10;", &vec![255], &seq, false).is_err());
+        assert!(parse_5mc_calls("C+m,0,0;", &vec![255], &seq, false).is_err());
+    }
+}
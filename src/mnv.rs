@@ -0,0 +1,292 @@
+//! Merging of phased variants that are adjacent on the same haplotype into MNV (multi-nucleotide
+//! variant) and complex records, so that the combined change is annotated correctly downstream
+//! (e.g. two SNVs in one codon give a single amino acid change, not two).
+
+use bio::stats::{LogProb, PHREDProb, Prob};
+use errors::*;
+use genotype_probs::{Genotype, GenotypeProbs};
+use util::MAX_VCF_QUAL;
+use variants_and_fragments::{Fragment, Var, VarFilter, VarList};
+
+// whether a variant can be part of an MNV: an unfiltered, non-reference diploid call whose alleles
+// on each haplotype are known, because it is either phased or homozygous
+fn mergeable(var: &Var) -> bool {
+    var.ploidy == 2
+        && var.filter == VarFilter::Pass
+        && var.genotype != Genotype(0, 0)
+        && (var.phase_set.is_some() || var.genotype.0 == var.genotype.1)
+}
+
+// whether var can extend the run of variants, i.e. it starts right after the reference allele of
+// the last variant of the run, and is phased in the same block as the run (or is homozygous)
+fn extends_run(run: &[&Var], var: &Var) -> bool {
+    let last = run[run.len() - 1];
+    let run_phase_set = run.iter().filter_map(|v| v.phase_set).next();
+    var.tid == last.tid
+        && var.pos0 == last.pos0 + last.alleles[0].len()
+        && match (run_phase_set, var.phase_set) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+}
+
+/// Creates the merged record of a run of adjacent variants, or returns None if no haplotype
+/// carries the alternate alleles of at least two of the variants (e.g. two heterozygous SNVs on
+/// opposite haplotypes are left as they are).
+///
+/// The alleles of the merged record are the reference alleles of the variants joined together,
+/// and the distinct non-reference haplotype sequences. Its QUAL, GQ and depth are the minimum over
+/// the variants, and the genotype probabilities put the remaining probability (from the GQ)
+/// evenly on the other genotypes. The allele counts are the reads with a confident call at every
+/// variant of the run that matches one of the haplotype sequences; other reads that cover the run
+/// are counted as ambiguous.
+fn merge_run(run: &[&Var], flist: &Vec<Fragment>, max_p_miscall: f64) -> Option<Var> {
+    let carries_two = |h: usize| {
+        run.iter()
+            .filter(|v| (if h == 0 { v.genotype.0 } else { v.genotype.1 }) != 0)
+            .count()
+            >= 2
+    };
+    if run.len() < 2 || !(carries_two(0) || carries_two(1)) {
+        return None;
+    }
+
+    // the alleles of the merged record, and the allele of each variant that each one is made of
+    let ref_allele: String = run.iter().map(|v| v.alleles[0].as_str()).collect();
+    let mut alleles: Vec<String> = vec![ref_allele];
+    let mut components: Vec<Vec<u8>> = vec![vec![0; run.len()]];
+    let mut genotype = Genotype(0, 0);
+    for h in 0..2 {
+        let hap: Vec<u8> = run
+            .iter()
+            .map(|v| if h == 0 { v.genotype.0 } else { v.genotype.1 })
+            .collect();
+        let ix = match components.iter().position(|c| *c == hap) {
+            Some(ix) => ix,
+            None => {
+                alleles.push(
+                    run.iter()
+                        .zip(hap.iter())
+                        .map(|(v, &a)| v.alleles[a as usize].as_str())
+                        .collect(),
+                );
+                components.push(hap);
+                components.len() - 1
+            }
+        };
+        if h == 0 {
+            genotype.0 = ix as u8;
+        } else {
+            genotype.1 = ix as u8;
+        }
+    }
+
+    let mut var = Var::new(run[0].tid, run[0].pos0, alleles);
+    var.genotype = genotype;
+    var.unphased_genotype = genotype;
+    var.phase_set = run.iter().filter_map(|v| v.phase_set).next();
+    var.merged_mnv = true;
+    // the per-allele MAPQ and allele quality statistics aren't known for the merged alleles
+    var.mapq_median = vec![];
+    var.mapq_mad = vec![];
+    var.mapq_mean = vec![];
+
+    var.qual = run.iter().map(|v| v.qual).fold(MAX_VCF_QUAL, f64::min);
+    var.gq = run.iter().map(|v| v.gq).fold(MAX_VCF_QUAL, f64::min);
+    var.unphased_gq = var.gq;
+    var.mean_allele_qual = run
+        .iter()
+        .map(|v| v.mean_allele_qual)
+        .fold(MAX_VCF_QUAL, f64::min);
+    var.dp = run.iter().map(|v| v.dp).min().unwrap();
+    var.dp_any_mq = run.iter().map(|v| v.dp_any_mq).min().unwrap();
+    var.strand_bias_pvalue = run.iter().map(|v| v.strand_bias_pvalue).fold(0.0, f64::max);
+    var.phase_qual = run
+        .iter()
+        .filter_map(|v| v.phase_qual)
+        .fold(None, |q: Option<f64>, p| Some(q.map_or(p, |q| q.min(p))));
+
+    // the called genotype gets the probability of the GQ, and the other genotypes share the rest
+    let possible = var.possible_genotypes();
+    let p_wrong = LogProb::from(PHREDProb(var.gq));
+    let p_other = p_wrong - LogProb::from(Prob((possible.len() - 1) as f64));
+    let mut post = GenotypeProbs::zeros(var.alleles.len());
+    for &g in &possible {
+        if g == genotype {
+            post.set(g, LogProb::ln_one_minus_exp(&p_wrong));
+        } else {
+            post.set(g, p_other);
+        }
+    }
+    var.genotype_post = post.clone();
+    var.genotype_likelihoods = post;
+
+    // count the reads that support each allele of the merged record
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    for frag in flist {
+        let mut observed: Vec<Option<u8>> = vec![None; run.len()];
+        let mut covered = false;
+        let mut reverse_strand = frag.reverse_strand;
+        for call in &frag.calls {
+            if let Some(k) = run.iter().position(|v| v.ix == call.var_ix) {
+                covered = true;
+                reverse_strand = call.reverse_strand;
+                if call.qual < ln_max_p_miscall {
+                    observed[k] = Some(call.allele);
+                }
+            }
+        }
+        if !covered {
+            continue;
+        }
+        let matched = components.iter().position(|c| {
+            c.iter()
+                .zip(observed.iter())
+                .all(|(&a, &o)| o == Some(a))
+        });
+        match matched {
+            Some(a) => {
+                var.allele_counts[a] += 1;
+                if reverse_strand {
+                    var.allele_counts_reverse[a] += 1;
+                } else {
+                    var.allele_counts_forward[a] += 1;
+                }
+            }
+            None => {
+                var.ambiguous_count += 1;
+            }
+        }
+    }
+
+    Some(var)
+}
+
+/// Merges phased variants that are adjacent on the same haplotype into MNV and complex records.
+///
+/// Runs of variants that each start right after the reference allele of the previous one, that
+/// are phased in the same block (or homozygous), and of which at least two are on the same
+/// haplotype are replaced by a single record at the position of the first variant (see
+/// ```merge_run```). Filtered and unphased heterozygous variants are never merged.
+///
+/// # Arguments
+/// - ```flist```: the haplotype fragments, with calls indexed into ```varlist```
+/// - ```varlist```: the genotyped and phased variants
+/// - ```max_p_miscall```: the maximum probability of an allele miscall to count a read's allele
+/// - ```keep_components```: also keep the merged variants, annotated with the position of their
+///                          merged record
+///
+/// # Returns
+/// Returns a new ```VarList``` with the merged records. ```varlist``` is not modified, so that the
+/// fragments still index into it.
+pub fn merge_phased_mnvs(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    max_p_miscall: f64,
+    keep_components: bool,
+) -> Result<VarList> {
+    let mut lst: Vec<Var> = Vec::with_capacity(varlist.lst.len());
+    let mut i = 0;
+    while i < varlist.lst.len() {
+        let mut run: Vec<&Var> = vec![&varlist.lst[i]];
+        if mergeable(&varlist.lst[i]) {
+            while i + run.len() < varlist.lst.len() {
+                let next = &varlist.lst[i + run.len()];
+                if !mergeable(next) || !extends_run(&run, next) {
+                    break;
+                }
+                run.push(next);
+            }
+        }
+
+        match merge_run(&run, flist, max_p_miscall) {
+            Some(merged) => {
+                let mnv_id = merged.pos0 + 1;
+                lst.push(merged);
+                if keep_components {
+                    for var in &run {
+                        let mut component = (*var).clone();
+                        component.mnv_id = Some(mnv_id);
+                        lst.push(component);
+                    }
+                }
+            }
+            None => {
+                lst.extend(run.iter().map(|v| (*v).clone()));
+            }
+        }
+        i += run.len();
+    }
+
+    // the sort is stable, so merged records stay in front of their components
+    VarList::new(lst, varlist.target_names.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use variants_and_fragments::fragments_from_strings;
+
+    fn phased_var(
+        pos0: usize,
+        alleles: &[&str],
+        genotype: Genotype,
+        phase_set: Option<usize>,
+    ) -> Var {
+        let mut var = Var::new(0, pos0, alleles.iter().map(|a| a.to_string()).collect());
+        var.genotype = genotype;
+        var.phase_set = phase_set;
+        var.qual = 100.0;
+        var.gq = 50.0;
+        var.dp = 20;
+        var
+    }
+
+    #[test]
+    fn test_merge_phased_mnvs() {
+        let lst = vec![
+            // a dinucleotide change on haplotype 2
+            phased_var(100, &["A", "G"], Genotype(0, 1), Some(101)),
+            phased_var(101, &["C", "T"], Genotype(0, 1), Some(101)),
+            // adjacent heterozygous SNVs on opposite haplotypes are not merged
+            phased_var(200, &["A", "G"], Genotype(0, 1), Some(101)),
+            phased_var(201, &["C", "T"], Genotype(1, 0), Some(101)),
+            // a homozygous SNV next to a phased deletion
+            phased_var(300, &["G", "T"], Genotype(1, 1), None),
+            phased_var(301, &["CA", "C"], Genotype(1, 0), Some(101)),
+        ];
+        let varlist = VarList::new(lst, vec!["chr1".to_string()]).unwrap();
+        let flist =
+            fragments_from_strings(&["00----", "11----", "11----", "-1----", "10----"], 0.01);
+
+        let merged = merge_phased_mnvs(&flist, &varlist, 0.1, false).unwrap();
+        assert_eq!(merged.lst.len(), 4);
+
+        let mnv = &merged.lst[0];
+        assert_eq!(mnv.alleles, vec!["AC".to_string(), "GT".to_string()]);
+        assert_eq!(mnv.genotype, Genotype(0, 1));
+        assert_eq!(mnv.phase_set, Some(101));
+        assert!(mnv.merged_mnv);
+        assert_eq!(mnv.allele_counts, vec![1, 2]);
+        assert_eq!(mnv.ambiguous_count, 2);
+        assert!((mnv.gq - 50.0).abs() < 1e-9);
+
+        assert_eq!(merged.lst[1].pos0, 200);
+        assert_eq!(merged.lst[2].pos0, 201);
+
+        let complex = &merged.lst[3];
+        assert_eq!(
+            complex.alleles,
+            vec!["GCA".to_string(), "TC".to_string(), "TCA".to_string()]
+        );
+        assert_eq!(complex.genotype, Genotype(1, 2));
+
+        // the components are kept after their merged record
+        let merged = merge_phased_mnvs(&flist, &varlist, 0.1, true).unwrap();
+        assert_eq!(merged.lst.len(), 8);
+        assert!(merged.lst[0].merged_mnv);
+        assert_eq!(merged.lst[1].mnv_id, Some(101));
+        assert_eq!(merged.lst[2].mnv_id, Some(101));
+        assert_eq!(merged.lst[3].mnv_id, None);
+    }
+}
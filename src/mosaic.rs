@@ -0,0 +1,307 @@
+//! Mosaic and low allele fraction variant calling (```--mosaic```).
+//!
+//! The diploid genotype model calls a variant heterozygous or homozygous, so a variant that is
+//! present in a small fraction of the cells (somatic mosaicism) or of the molecules (mitochondrial
+//! heteroplasmy) is called homozygous reference. In mosaic mode, the reads at each biallelic site
+//! that is called homozygous reference are compared under two hypotheses:
+//! - germline: the alternate allele fraction is that of a genotype (0, 0.5 or 1, or 0 or 1 at a
+//!   haploid site)
+//! - mosaic: the alternate allele fraction has a uniform prior over a grid of values from the
+//!   minimum VAF to 0.5 (to 1 minus the minimum VAF at a haploid site)
+//!
+//! A read allele is wrong with the probability of its allele quality, or else with the noise rate
+//! of the sample, which is learned from the data: it is the excess of the reads that support the
+//! other allele at confidently homozygous sites, over the miscalls that the allele qualities
+//! predict. The noise rate absorbs the systematic errors (e.g. of the library or the sequencing
+//! chemistry) that the realignment doesn't see, which would otherwise look like low fraction
+//! variants.
+//!
+//! The mosaic quality (MOSQ) is the PHRED-scaled probability that the variant is NOT mosaic, and
+//! the mosaic allele fraction (MOSVAF) is the most likely allele fraction under the mosaic
+//! hypothesis. A site with a mosaic posterior of at least the minimum posterior is called with the
+//! genotype 0/1.
+
+use bio::stats::{LogProb, PHREDProb, Prob};
+use genotype_probs::Genotype;
+use util::*;
+use variants_and_fragments::*;
+
+// minimum genotype quality of the homozygous sites that the noise rate is learned from
+static MIN_NOISE_GQ: f64 = 30.0;
+
+/// Parameters for the mosaic posterior model
+#[derive(Clone, Copy)]
+pub struct MosaicParameters {
+    /// minimum alternate allele fraction of a mosaic variant
+    pub min_vaf: f64,
+    /// minimum posterior probability that a variant is mosaic for it to be called
+    pub min_posterior: f64,
+    /// prior probability of a mosaic variant at a site
+    pub mosaic_rate: f64,
+    /// number of allele fraction values in the grid of the mosaic hypothesis
+    pub n_fractions: usize,
+}
+
+impl MosaicParameters {
+    /// returns the allele fractions to evaluate at a site with the given ploidy. The first ones
+    /// are the germline fractions (see ```germline_fractions```); the remaining ones are the
+    /// mosaic fractions, from ```min_vaf``` up to (but not including) 0.5, or 1 - ```min_vaf```
+    /// at a haploid site.
+    pub fn allele_fractions(&self, ploidy: u8) -> Vec<f64> {
+        let max_vaf = if ploidy == 1 { 1.0 - self.min_vaf } else { 0.5 };
+        let mut fractions = germline_fractions(ploidy);
+        for i in 0..self.n_fractions {
+            fractions
+                .push(self.min_vaf + (max_vaf - self.min_vaf) * i as f64 / self.n_fractions as f64);
+        }
+        fractions
+    }
+}
+
+/// the alternate allele fractions of the genotypes of a site with the given ploidy
+fn germline_fractions(ploidy: u8) -> Vec<f64> {
+    if ploidy == 1 {
+        vec![0.0, 1.0]
+    } else {
+        vec![0.0, 0.5, 1.0]
+    }
+}
+
+/// Estimates the noise rate of a sample: the probability that a read allele is wrong beyond the
+/// probability of its allele quality
+///
+/// The reads at the biallelic sites with a homozygous genotype of quality at least
+/// ```MIN_NOISE_GQ``` are counted. The noise rate is the number of the reads that support the
+/// other allele minus the expected number of miscalls, over the number of reads, with a
+/// pseudo-count of one noisy read in two.
+///
+/// # Arguments
+/// - ```flist```: the fragments of the sample
+/// - ```varlist```: the genotyped variants of the sample
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a read allele to be used
+pub fn estimate_noise_rate(flist: &Vec<Fragment>, varlist: &VarList, max_p_miscall: f64) -> f64 {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut n_calls: usize = 0;
+    let mut n_discordant: usize = 0;
+    let mut expected_miscalls: f64 = 0.0;
+
+    for frag in flist {
+        for call in &frag.calls {
+            if call.qual >= ln_max_p_miscall || call.allele > 1 {
+                continue;
+            }
+            let var = &varlist.lst[call.var_ix];
+            if var.alleles.len() != 2 || var.genotype.0 != var.genotype.1 || var.gq < MIN_NOISE_GQ {
+                continue;
+            }
+            n_calls += 1;
+            if call.allele != var.genotype.0 {
+                n_discordant += 1;
+            }
+            expected_miscalls += *Prob::from(call.qual);
+        }
+    }
+
+    ((n_discordant as f64 - expected_miscalls).max(0.0) + 1.0) / (n_calls as f64 + 2.0)
+}
+
+/// Calculates the likelihood of the reads at each variant for the allele fractions of
+/// ```params.allele_fractions```
+///
+/// # Arguments
+/// - ```flist```: the fragments of the sample
+/// - ```varlist```: the variants of the sample
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a read allele to be used
+/// - ```noise_rate```: the noise rate of the sample, from ```estimate_noise_rate```
+/// - ```params```: the mosaic model parameters
+///
+/// # Returns
+/// Returns a vector where element ```[v][i]``` is the log-likelihood of the reads at variant
+/// ```v``` if the fraction of reads carrying allele 1 is ```params.allele_fractions(ploidy)[i]```,
+/// for the ploidy of the variant. The vector of a multiallelic variant is empty, and reads with an
+/// allele other than 0 or 1 are ignored.
+pub fn mosaic_allele_fraction_likelihoods(
+    flist: &Vec<Fragment>,
+    varlist: &VarList,
+    max_p_miscall: f64,
+    noise_rate: f64,
+    params: &MosaicParameters,
+) -> Vec<Vec<LogProb>> {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let var_fractions: Vec<Vec<f64>> = varlist
+        .lst
+        .iter()
+        .map(|var| {
+            if var.alleles.len() == 2 {
+                params.allele_fractions(var.ploidy)
+            } else {
+                vec![]
+            }
+        })
+        .collect();
+    let mut likelihoods: Vec<Vec<LogProb>> = var_fractions
+        .iter()
+        .map(|fractions| vec![LogProb::ln_one(); fractions.len()])
+        .collect();
+
+    for frag in flist {
+        for call in &frag.calls {
+            if call.qual >= ln_max_p_miscall || call.allele > 1 {
+                continue;
+            }
+            // the read allele is wrong if it is miscalled or noisy, but not both
+            let p_miscall = *Prob::from(call.qual);
+            let p_wrong = p_miscall + noise_rate - 2.0 * p_miscall * noise_rate;
+            // probability of the observed allele if the read came from an allele 0 or allele 1 molecule
+            let (p_from_0, p_from_1) = if call.allele == 0 {
+                (1.0 - p_wrong, p_wrong)
+            } else {
+                (p_wrong, 1.0 - p_wrong)
+            };
+            for (i, &f) in var_fractions[call.var_ix].iter().enumerate() {
+                likelihoods[call.var_ix][i] = likelihoods[call.var_ix][i]
+                    + LogProb::from(Prob((1.0 - f) * p_from_0 + f * p_from_1));
+            }
+        }
+    }
+
+    likelihoods
+}
+
+/// Calculates the mosaic posterior of the homozygous reference variants and calls the mosaic
+/// ones
+///
+/// For each biallelic variant with a homozygous reference genotype, ```mosaic_qual``` and
+/// ```mosaic_vaf``` are set. If the mosaic posterior is at least ```params.min_posterior```, the
+/// genotype is set to 0/1 with the mosaic quality as its GQ. A haploid site is made diploid, so
+/// that a heteroplasmic variant is written as 0/1 too. Returns the number of called variants.
+///
+/// # Arguments
+/// - ```varlist```: the genotyped variants of the sample
+/// - ```likelihoods```: the output of ```mosaic_allele_fraction_likelihoods``` for the variants
+/// - ```params```: the mosaic model parameters
+pub fn call_mosaic_variants(
+    varlist: &mut VarList,
+    likelihoods: &Vec<Vec<LogProb>>,
+    params: &MosaicParameters,
+) -> usize {
+    assert_eq!(varlist.lst.len(), likelihoods.len());
+
+    let ln_mosaic_rate = LogProb::from(Prob(params.mosaic_rate));
+    let ln_germline_rate = LogProb::from(Prob(1.0 - params.mosaic_rate));
+    let ln_n_fractions = LogProb::from(Prob(1.0 / params.n_fractions as f64));
+    let ln_min_posterior = LogProb::from(Prob(params.min_posterior));
+    let mut n_called = 0;
+
+    for (v, var) in varlist.lst.iter_mut().enumerate() {
+        if var.alleles.len() != 2 || var.genotype != Genotype(0, 0) {
+            continue;
+        }
+        let lik = &likelihoods[v];
+        let fractions = params.allele_fractions(var.ploidy);
+        let n_germline = germline_fractions(var.ploidy).len();
+
+        // the germline genotypes have the same prior, since the reference genotype was called
+        let ln_n_germline = LogProb::from(Prob(1.0 / n_germline as f64));
+        let germline = ln_germline_rate + ln_n_germline + LogProb::ln_sum_exp(&lik[..n_germline]);
+        let mosaic = ln_mosaic_rate + ln_n_fractions + LogProb::ln_sum_exp(&lik[n_germline..]);
+
+        let p_mosaic = mosaic - LogProb::ln_add_exp(mosaic, germline);
+        let p_not_mosaic = LogProb::ln_one_minus_exp(&p_mosaic);
+
+        // the most likely mosaic allele fraction
+        let mut max_ix = n_germline;
+        for i in n_germline..fractions.len() {
+            if lik[i] > lik[max_ix] {
+                max_ix = i;
+            }
+        }
+
+        let mosaic_qual = (*PHREDProb::from(p_not_mosaic)).min(MAX_VCF_QUAL);
+        var.mosaic_qual = Some(mosaic_qual);
+        var.mosaic_vaf = Some(fractions[max_ix]);
+
+        if p_mosaic >= ln_min_posterior {
+            var.genotype = Genotype(0, 1);
+            var.gq = mosaic_qual;
+            var.ploidy = 2;
+            n_called += 1;
+        }
+    }
+
+    n_called
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_varlist(genotype: Genotype, gq: f64) -> VarList {
+        let mut var = Var::new(0, 100, vec!["A".to_string(), "G".to_string()]);
+        var.genotype = genotype;
+        var.gq = gq;
+        VarList::new(vec![var], vec!["chr1".to_string()]).unwrap()
+    }
+
+    fn params() -> MosaicParameters {
+        MosaicParameters {
+            min_vaf: 0.02,
+            min_posterior: 0.9,
+            mosaic_rate: 0.001,
+            n_fractions: 48,
+        }
+    }
+
+    #[test]
+    fn test_mosaic_allele_fractions() {
+        let fractions = params().allele_fractions(2);
+        assert_eq!(fractions.len(), 3 + 48);
+        assert_eq!(&fractions[..4], &[0.0, 0.5, 1.0, 0.02]);
+        assert!(fractions[3..].iter().all(|&f| f >= 0.02 && f < 0.5));
+
+        let fractions = params().allele_fractions(1);
+        assert_eq!(fractions.len(), 2 + 48);
+        assert!(fractions[2..].iter().all(|&f| f >= 0.02 && f < 0.98));
+    }
+
+    #[test]
+    fn test_estimate_noise_rate() {
+        // 5 alt reads out of 200 at a confident homozygous reference site, with miscalls expected
+        // in 1% of the reads, leave 3 noisy reads
+        let flist = fragments_from_strings(&[vec!["0"; 195], vec!["1"; 5]].concat(), 0.01);
+        let varlist = generate_varlist(Genotype(0, 0), 100.0);
+        let noise_rate = estimate_noise_rate(&flist, &varlist, 0.1);
+        assert!((noise_rate - 4.0 / 202.0).abs() < 1e-10);
+
+        // the reads of a low quality genotype aren't used
+        let varlist = generate_varlist(Genotype(0, 0), 10.0);
+        assert!((estimate_noise_rate(&flist, &varlist, 0.1) - 0.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_call_mosaic_variants() {
+        // 20 alt reads out of 400, of which 1.1% are expected to be wrong, fit an allele
+        // fraction of 4%
+        let flist = fragments_from_strings(&[vec!["0"; 380], vec!["1"; 20]].concat(), 0.01);
+        let mut varlist = generate_varlist(Genotype(0, 0), 30.0);
+        let lik = mosaic_allele_fraction_likelihoods(&flist, &varlist, 0.1, 0.001, &params());
+        assert_eq!(call_mosaic_variants(&mut varlist, &lik, &params()), 1);
+        let var = &varlist.lst[0];
+        assert_eq!(var.genotype, Genotype(0, 1));
+        assert!(var.mosaic_qual.unwrap() > 10.0);
+        assert!((var.mosaic_vaf.unwrap() - 0.04).abs() < 1e-10);
+
+        // with a noise rate of 5%, the alt reads are noise
+        let mut varlist = generate_varlist(Genotype(0, 0), 30.0);
+        let lik = mosaic_allele_fraction_likelihoods(&flist, &varlist, 0.1, 0.05, &params());
+        assert_eq!(call_mosaic_variants(&mut varlist, &lik, &params()), 0);
+        assert_eq!(varlist.lst[0].genotype, Genotype(0, 0));
+        assert!(varlist.lst[0].mosaic_qual.unwrap() < 10.0);
+
+        // a heterozygous site is left to the genotype model
+        let mut varlist = generate_varlist(Genotype(0, 1), 30.0);
+        let lik = mosaic_allele_fraction_likelihoods(&flist, &varlist, 0.1, 0.001, &params());
+        assert_eq!(call_mosaic_variants(&mut varlist, &lik, &params()), 0);
+        assert_eq!(varlist.lst[0].mosaic_qual, None);
+    }
+}
@@ -0,0 +1,556 @@
+//! Columnar output of the variant calls as an Apache Parquet file (```--out-parquet```), for
+//! cohort analytics (e.g. with Spark, Polars or DuckDB) without a VCF parsing step.
+//!
+//! The file has one row per call and sample, with the columns in ```PARQUET_COLUMNS```. The calls
+//! of each region are written as a row group as soon as the region is finished, and the file
+//! footer with the schema and the row groups is written at the end of the run. The columns are
+//! written with the PLAIN encoding and without compression, and the file metadata is encoded with
+//! the Thrift compact protocol as described in the Parquet format specification.
+
+use errors::*;
+use genotype_probs::Genotype;
+use print_output::format_genotype;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use util::*;
+use variants_and_fragments::{Var, VarList};
+
+/// Parquet physical types
+const TYPE_INT32: i32 = 1;
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+/// Parquet encodings
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+
+/// The kind of values of a column
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColumnKind {
+    /// a UTF-8 string (```BYTE_ARRAY``` with the ```UTF8``` converted type)
+    Utf8,
+    Int32,
+    Int64,
+    Double,
+    /// an ```INT64``` that can be null
+    OptionalInt64,
+}
+
+impl ColumnKind {
+    fn physical_type(&self) -> i32 {
+        match *self {
+            ColumnKind::Utf8 => TYPE_BYTE_ARRAY,
+            ColumnKind::Int32 => TYPE_INT32,
+            ColumnKind::Int64 | ColumnKind::OptionalInt64 => TYPE_INT64,
+            ColumnKind::Double => TYPE_DOUBLE,
+        }
+    }
+}
+
+/// The columns of the Parquet file: the contig, the 1-based position, the reference allele, the
+/// comma-separated alternate alleles, the sample name, the genotype as in the VCF GT field, the
+/// genotype quality, the sample's QUAL (the PHRED-scaled probability that its genotype is
+/// homozygous reference), the FILTER value, the read depth, the allele counts as in the VCF AD
+/// field, the counts of the reference and of all alternate alleles, and the phase set (null for
+/// unphased calls).
+static PARQUET_COLUMNS: [(&str, ColumnKind); 14] = [
+    ("chrom", ColumnKind::Utf8),
+    ("pos", ColumnKind::Int64),
+    ("ref", ColumnKind::Utf8),
+    ("alt", ColumnKind::Utf8),
+    ("sample", ColumnKind::Utf8),
+    ("gt", ColumnKind::Utf8),
+    ("gq", ColumnKind::Int32),
+    ("qual", ColumnKind::Double),
+    ("filter", ColumnKind::Utf8),
+    ("dp", ColumnKind::Int32),
+    ("ad", ColumnKind::Utf8),
+    ("ref_count", ColumnKind::Int32),
+    ("alt_count", ColumnKind::Int32),
+    ("phase_set", ColumnKind::OptionalInt64),
+];
+
+/// A value of a row, in the column of the same kind
+enum Value {
+    Str(String),
+    Int32(i32),
+    Int64(Option<i64>),
+    Double(f64),
+}
+
+/// The values of a call, in the order of ```PARQUET_COLUMNS```
+fn call_row(var: &Var, chrom: &str, sample_name: &str) -> Vec<Value> {
+    let sep = if var.phase_set.is_some() || var.phased_no_ps {
+        "|"
+    } else {
+        "/"
+    };
+    let ref_count = var.allele_counts[0] as i32;
+    let total: i32 = var.allele_counts.iter().map(|&c| c as i32).sum();
+    vec![
+        Value::Str(chrom.to_string()),
+        Value::Int64(Some(var.pos0 as i64 + 1)),
+        Value::Str(var.alleles[0].clone()),
+        Value::Str(var.alleles[1..].join(",")),
+        Value::Str(sample_name.to_string()),
+        Value::Str(format_genotype(var.genotype, var.ploidy, sep)),
+        Value::Int32((var.gq + 0.4999).round() as i32),
+        Value::Double(var.qual),
+        Value::Str(var.filter.to_string()),
+        Value::Int32(var.dp as i32),
+        Value::Str(
+            var.allele_counts
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+        Value::Int32(ref_count),
+        Value::Int32(total - ref_count),
+        Value::Int64(var.phase_set.map(|ps| ps as i64)),
+    ]
+}
+
+/// The values of a column of a row group: the PLAIN-encoded non-null values, and whether each
+/// row is not null (for the definition levels of an optional column)
+struct ColumnBuffer {
+    kind: ColumnKind,
+    values: Vec<u8>,
+    defined: Vec<bool>,
+}
+
+impl ColumnBuffer {
+    fn new(kind: ColumnKind) -> ColumnBuffer {
+        ColumnBuffer {
+            kind: kind,
+            values: vec![],
+            defined: vec![],
+        }
+    }
+
+    fn push(&mut self, value: &Value) {
+        match *value {
+            Value::Str(ref s) => {
+                self.values.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                self.values.extend_from_slice(s.as_bytes());
+            }
+            Value::Int32(v) => self.values.extend_from_slice(&v.to_le_bytes()),
+            Value::Int64(Some(v)) => self.values.extend_from_slice(&v.to_le_bytes()),
+            Value::Int64(None) => {}
+            Value::Double(v) => self.values.extend_from_slice(&v.to_bits().to_le_bytes()),
+        }
+        self.defined.push(match *value {
+            Value::Int64(None) => false,
+            _ => true,
+        });
+    }
+
+    /// the contents of the data page of the column: the definition levels of an optional
+    /// column, followed by the values
+    fn page_data(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        if self.kind == ColumnKind::OptionalInt64 {
+            let levels = rle_bit_width_1(&self.defined);
+            data.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+            data.extend_from_slice(&levels);
+        }
+        data.extend_from_slice(&self.values);
+        data
+    }
+}
+
+/// Encodes a list of 0/1 levels with the RLE part of the RLE/bit-packing hybrid encoding, with a
+/// bit width of 1: each run of equal levels is its length (shifted left by one bit, as a varint)
+/// and the level (as a byte)
+fn rle_bit_width_1(levels: &Vec<bool>) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    let mut i = 0;
+    while i < levels.len() {
+        let mut j = i;
+        while j < levels.len() && levels[j] == levels[i] {
+            j += 1;
+        }
+        write_varint(&mut out, ((j - i) as u64) << 1);
+        out.push(levels[i] as u8);
+        i = j;
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8 & 0x7f) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// Thrift compact protocol type codes of struct fields and list elements
+const CT_I32: u8 = 5;
+const CT_I64: u8 = 6;
+const CT_BINARY: u8 = 8;
+const CT_LIST: u8 = 9;
+const CT_STRUCT: u8 = 12;
+
+/// A minimal encoder for the Thrift compact protocol, for the Parquet page headers and file
+/// metadata. The fields of each struct must be written in increasing order of their IDs.
+struct ThriftWriter {
+    buf: Vec<u8>,
+    /// the ID of the last field of each open struct
+    last_field: Vec<i16>,
+}
+
+impl ThriftWriter {
+    fn new() -> ThriftWriter {
+        ThriftWriter {
+            buf: vec![],
+            last_field: vec![0],
+        }
+    }
+
+    fn field_header(&mut self, id: i16, ctype: u8) {
+        let last = self.last_field.last_mut().unwrap();
+        let delta = id - *last;
+        if delta > 0 && delta <= 15 {
+            self.buf.push(((delta as u8) << 4) | ctype);
+        } else {
+            self.buf.push(ctype);
+            write_varint(&mut self.buf, zigzag(id as i64));
+        }
+        *last = id;
+    }
+
+    fn i32_value(&mut self, v: i32) {
+        write_varint(&mut self.buf, zigzag(v as i64));
+    }
+
+    fn binary_value(&mut self, v: &[u8]) {
+        write_varint(&mut self.buf, v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+
+    fn i32_field(&mut self, id: i16, v: i32) {
+        self.field_header(id, CT_I32);
+        self.i32_value(v);
+    }
+
+    fn i64_field(&mut self, id: i16, v: i64) {
+        self.field_header(id, CT_I64);
+        write_varint(&mut self.buf, zigzag(v));
+    }
+
+    fn binary_field(&mut self, id: i16, v: &[u8]) {
+        self.field_header(id, CT_BINARY);
+        self.binary_value(v);
+    }
+
+    /// starts a list field of ```size``` elements of type ```elem_type```
+    fn list_field(&mut self, id: i16, elem_type: u8, size: usize) {
+        self.field_header(id, CT_LIST);
+        if size < 15 {
+            self.buf.push(((size as u8) << 4) | elem_type);
+        } else {
+            self.buf.push(0xf0 | elem_type);
+            write_varint(&mut self.buf, size as u64);
+        }
+    }
+
+    /// starts a struct field
+    fn struct_field(&mut self, id: i16) {
+        self.field_header(id, CT_STRUCT);
+        self.struct_begin();
+    }
+
+    /// starts a struct that is a list element (or the top-level struct)
+    fn struct_begin(&mut self) {
+        self.last_field.push(0);
+    }
+
+    fn struct_end(&mut self) {
+        self.buf.push(0);
+        self.last_field.pop();
+    }
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+/// The location of a column chunk in the file
+struct ColumnChunkMeta {
+    offset: u64,
+    size: u64,
+    num_values: usize,
+}
+
+/// A Parquet file of variant calls, with a row group per region
+pub struct ParquetCallWriter {
+    file: BufWriter<File>,
+    display: String,
+    offset: u64,
+    row_groups: Vec<(Vec<ColumnChunkMeta>, usize)>,
+}
+
+impl ParquetCallWriter {
+    /// Creates the file and writes its leading magic number.
+    pub fn create(output_file: &String) -> Result<ParquetCallWriter> {
+        let path = Path::new(output_file);
+        let display = path.display().to_string();
+        let file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.clone()))?;
+        let mut writer = ParquetCallWriter {
+            file: BufWriter::new(file),
+            display: display,
+            offset: 0,
+            row_groups: vec![],
+        };
+        writer.write(b"PAR1")?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let display = &self.display;
+        self.file
+            .write_all(data)
+            .chain_err(|| ErrorKind::FileWriteError(display.clone()))?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the calls of a region as a row group: a row for each sample at the sites in
+    /// ```interval``` (all sites if None) where any sample has a non-reference genotype, or at
+    /// every site if ```print_reference_genotype```. The variant lists of the samples must have
+    /// the same sites, in the same order.
+    pub fn write_row_group(
+        &mut self,
+        varlists: &[&VarList],
+        sample_names: &Vec<String>,
+        interval: &Option<GenomicInterval>,
+        print_reference_genotype: bool,
+    ) -> Result<()> {
+        ensure!(
+            !varlists.is_empty() && varlists.len() == sample_names.len(),
+            "Number of variant lists must match the number of samples."
+        );
+        let mut columns: Vec<ColumnBuffer> = PARQUET_COLUMNS
+            .iter()
+            .map(|&(_, kind)| ColumnBuffer::new(kind))
+            .collect();
+        let mut num_rows = 0;
+        for i in 0..varlists[0].lst.len() {
+            let site = &varlists[0].lst[i];
+            if let &Some(ref iv) = interval {
+                if site.tid != iv.tid
+                    || site.pos0 < iv.start_pos as usize
+                    || site.pos0 > iv.end_pos as usize
+                {
+                    continue;
+                }
+            }
+            if !print_reference_genotype
+                && varlists.iter().all(|vl| vl.lst[i].genotype == Genotype(0, 0))
+            {
+                continue;
+            }
+            let chrom = &varlists[0].target_names[site.tid as usize];
+            for (varlist, sample_name) in varlists.iter().zip(sample_names.iter()) {
+                for (column, value) in columns
+                    .iter_mut()
+                    .zip(call_row(&varlist.lst[i], chrom, sample_name).iter())
+                {
+                    column.push(value);
+                }
+                num_rows += 1;
+            }
+        }
+        if num_rows == 0 {
+            return Ok(());
+        }
+
+        let mut chunks: Vec<ColumnChunkMeta> = vec![];
+        for column in &columns {
+            let data = column.page_data();
+            ensure!(
+                data.len() <= i32::max_value() as usize,
+                "The calls of a region are too large for a Parquet data page."
+            );
+            let mut header = ThriftWriter::new();
+            header.i32_field(1, 0); // DATA_PAGE
+            header.i32_field(2, data.len() as i32); // uncompressed size
+            header.i32_field(3, data.len() as i32); // compressed size
+            header.struct_field(5); // DataPageHeader
+            header.i32_field(1, num_rows as i32);
+            header.i32_field(2, ENCODING_PLAIN);
+            header.i32_field(3, ENCODING_RLE); // definition levels
+            header.i32_field(4, ENCODING_RLE); // repetition levels
+            header.struct_end();
+            header.struct_end();
+
+            let offset = self.offset;
+            self.write(&header.buf)?;
+            self.write(&data)?;
+            chunks.push(ColumnChunkMeta {
+                offset: offset,
+                size: self.offset - offset,
+                num_values: num_rows,
+            });
+        }
+        self.row_groups.push((chunks, num_rows));
+        Ok(())
+    }
+
+    /// Writes the file metadata (the schema and the row groups) and the trailing magic number.
+    pub fn finish(mut self) -> Result<()> {
+        let metadata = file_metadata(&self.row_groups);
+        self.write(&metadata)?;
+        self.write(&(metadata.len() as u32).to_le_bytes())?;
+        self.write(b"PAR1")?;
+        let display = self.display.clone();
+        self.file
+            .flush()
+            .chain_err(|| ErrorKind::FileWriteError(display))?;
+        Ok(())
+    }
+}
+
+/// Encodes the Parquet FileMetaData struct of a file with the row groups ```row_groups``` (the
+/// column chunks and number of rows of each)
+fn file_metadata(row_groups: &Vec<(Vec<ColumnChunkMeta>, usize)>) -> Vec<u8> {
+    let mut t = ThriftWriter::new();
+    t.i32_field(1, 1); // version
+
+    // the schema is a root element followed by the columns
+    t.list_field(2, CT_STRUCT, PARQUET_COLUMNS.len() + 1);
+    t.struct_begin();
+    t.binary_field(4, b"schema");
+    t.i32_field(5, PARQUET_COLUMNS.len() as i32); // number of children
+    t.struct_end();
+    for &(name, kind) in PARQUET_COLUMNS.iter() {
+        t.struct_begin();
+        t.i32_field(1, kind.physical_type());
+        // repetition type: REQUIRED or OPTIONAL
+        t.i32_field(3, if kind == ColumnKind::OptionalInt64 { 1 } else { 0 });
+        t.binary_field(4, name.as_bytes());
+        if kind == ColumnKind::Utf8 {
+            t.i32_field(6, 0); // converted type UTF8
+        }
+        t.struct_end();
+    }
+
+    let total_rows: usize = row_groups.iter().map(|&(_, n)| n).sum();
+    t.i64_field(3, total_rows as i64);
+
+    t.list_field(4, CT_STRUCT, row_groups.len());
+    for &(ref chunks, num_rows) in row_groups {
+        t.struct_begin();
+        t.list_field(1, CT_STRUCT, chunks.len());
+        for (chunk, &(name, kind)) in chunks.iter().zip(PARQUET_COLUMNS.iter()) {
+            t.struct_begin(); // ColumnChunk
+            t.i64_field(2, chunk.offset as i64); // file offset
+            t.struct_field(3); // ColumnMetaData
+            t.i32_field(1, kind.physical_type());
+            t.list_field(2, CT_I32, 2); // encodings
+            t.i32_value(ENCODING_PLAIN);
+            t.i32_value(ENCODING_RLE);
+            t.list_field(3, CT_BINARY, 1); // path in schema
+            t.binary_value(name.as_bytes());
+            t.i32_field(4, 0); // UNCOMPRESSED
+            t.i64_field(5, chunk.num_values as i64);
+            t.i64_field(6, chunk.size as i64); // uncompressed size
+            t.i64_field(7, chunk.size as i64); // compressed size
+            t.i64_field(9, chunk.offset as i64); // data page offset
+            t.struct_end();
+            t.struct_end();
+        }
+        let total_size: u64 = chunks.iter().map(|c| c.size).sum();
+        t.i64_field(2, total_size as i64);
+        t.i64_field(3, num_rows as i64);
+        t.struct_end();
+    }
+    t.binary_field(6, format!("longshot version {}", env!("CARGO_PKG_VERSION")).as_bytes());
+    t.struct_end();
+    t.buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_thrift_compact_encoding() {
+        let mut t = ThriftWriter::new();
+        t.i32_field(1, 3);
+        t.i64_field(2, -1);
+        // a field ID delta larger than 15 is written after the type
+        t.binary_field(20, b"ab");
+        t.list_field(21, CT_I32, 2);
+        t.i32_value(0);
+        t.i32_value(64);
+        t.struct_field(22);
+        t.i32_field(1, 1);
+        t.struct_end();
+        t.struct_end();
+        assert_eq!(
+            t.buf,
+            vec![
+                0x15, 0x06, 0x16, 0x01, 0x08, 0x28, 0x02, b'a', b'b', 0x19, 0x25, 0x00, 0x80, 0x01,
+                0x1c, 0x15, 0x02, 0x00, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rle_bit_width_1() {
+        assert_eq!(rle_bit_width_1(&vec![]), Vec::<u8>::new());
+        assert_eq!(
+            rle_bit_width_1(&vec![true, true, true, false, true]),
+            vec![0x06, 1, 0x02, 0, 0x02, 1]
+        );
+        let levels = vec![true; 100];
+        assert_eq!(rle_bit_width_1(&levels), vec![0xc8, 0x01, 1]);
+    }
+
+    #[test]
+    fn test_parquet_call_writer() {
+        let mut vars = vec![
+            Var::new(0, 99, vec!["A".to_string(), "G".to_string()]),
+            Var::new(0, 199, vec!["C".to_string(), "T".to_string()]),
+            Var::new(0, 299, vec!["G".to_string(), "GA".to_string()]),
+        ];
+        vars[0].genotype = Genotype(0, 1);
+        vars[0].phase_set = Some(100);
+        vars[2].genotype = Genotype(1, 1);
+        let varlist = VarList::new(vars, vec!["chr1".to_string()]).unwrap();
+
+        let path = env::temp_dir().join(format!("longshot_test_parquet_{}", std::process::id()));
+        let filename = path.to_str().unwrap().to_string();
+        let mut writer = ParquetCallWriter::create(&filename).unwrap();
+        writer
+            .write_row_group(&[&varlist], &vec!["sample".to_string()], &None, false)
+            .unwrap();
+        assert_eq!(writer.row_groups.len(), 1);
+        assert_eq!(writer.row_groups[0].1, 2);
+        // the null phase set of the second call is counted as a value
+        let phase_set = &writer.row_groups[0].0[PARQUET_COLUMNS.len() - 1];
+        assert_eq!(phase_set.num_values, 2);
+        writer.finish().unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let n = contents.len();
+        assert_eq!(&contents[..4], b"PAR1");
+        assert_eq!(&contents[n - 4..], b"PAR1");
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&contents[n - 8..n - 4]);
+        let metadata_len = u32::from_le_bytes(len_bytes) as usize;
+        let metadata = &contents[n - 8 - metadata_len..n - 8];
+        let created_by = format!("longshot version {}", env!("CARGO_PKG_VERSION"));
+        assert!(metadata.ends_with(&[created_by.as_bytes(), &[0]].concat()));
+        // the length-prefixed genotype of the phased call
+        assert!(contents.windows(7).any(|w| w == b"\x03\x00\x00\x000|1"));
+    }
+}
@@ -0,0 +1,277 @@
+//! This module handles pedigree information for calling related samples together.
+//!
+//! A PED file is read to find parent-offspring trios among the samples being called. After each
+//! sample has been genotyped separately, the genotype posteriors of the trio members are updated
+//! under a Mendelian inheritance model (with a small de novo mutation rate), and variants where
+//! the child likely carries an allele not present in either parent are flagged as de novo
+//! candidates.
+
+use bio::stats::{LogProb, PHREDProb};
+use errors::*;
+use genotype_probs::*;
+use std::fs::File;
+use std::io::prelude::*;
+use util::*;
+use variants_and_fragments::VarList;
+
+/// A parent-offspring trio, with each member represented by its index in the list of samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trio {
+    pub child: usize,
+    pub father: usize,
+    pub mother: usize,
+}
+
+/// Reads a PED file and returns the trios formed by the samples being called.
+///
+/// Each non-comment line of the PED file has (at least) 6 whitespace-separated columns:
+/// family ID, individual ID, father ID, mother ID, sex, phenotype. A father or mother ID of
+/// ```0``` means that parent is unknown. Individuals that are not among the samples being called
+/// are ignored.
+///
+/// # Arguments
+/// - ```ped_file```: path to the PED file
+/// - ```sample_names```: the names of the samples being called, in the order of the BAM files
+///
+/// # Returns
+/// Returns a result containing the trios with a child and both parents among ```sample_names```
+///
+/// # Errors
+/// - Error if the PED file cannot be read
+/// - Error if a line of the PED file has fewer than 6 columns
+pub fn parse_pedigree(ped_file: &String, sample_names: &Vec<String>) -> Result<Vec<Trio>> {
+    let mut contents = String::new();
+    File::open(ped_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| ErrorKind::FileReadError(ped_file.clone()))?;
+
+    let sample_ix = |name: &str| sample_names.iter().position(|s| s == name);
+    let mut trios: Vec<Trio> = vec![];
+
+    for (line_num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        ensure!(
+            cols.len() >= 6,
+            "Line {} of PED file {} has {} columns, expected at least 6.",
+            line_num + 1,
+            ped_file,
+            cols.len()
+        );
+
+        if let (Some(child), Some(father), Some(mother)) =
+            (sample_ix(cols[1]), sample_ix(cols[2]), sample_ix(cols[3]))
+        {
+            ensure!(
+                child != father && child != mother && father != mother,
+                "Sample {} is listed as its own parent, or with the same sample as both parents, in PED file {}.",
+                cols[1],
+                ped_file
+            );
+            trios.push(Trio {
+                child,
+                father,
+                mother,
+            });
+        }
+    }
+
+    Ok(trios)
+}
+
+/// Updates the genotypes of each trio using Mendelian inheritance priors
+///
+/// The trios are grouped into families of two parents and all of their children, and for every
+/// variant, the genotype posteriors of each family member are replaced by the family posteriors
+/// (see ```calculate_family_posteriors```). Siblings are updated jointly rather than one trio at a
+/// time, so that each parent's prior (and each sibling's reads) is only counted once. The genotype,
+/// genotype quality and variant quality of each member are recalculated from the new posteriors,
+/// and each child's ```de_novo_qual``` is set to the PHRED-scaled probability that the child's
+/// genotype is Mendelian-consistent. Families that share a parent (half-siblings, or a child that
+/// is also a parent) are updated one after another, each starting from the posteriors left by the
+/// ones before it.
+///
+/// # Arguments
+/// - ```sample_varlists```: the genotyped ```VarList``` for each sample. These must all contain
+///   the same variants in the same order.
+/// - ```trios```: the trios found in the PED file
+/// - ```genotype_priors```: the genotype priors that were used to genotype each sample
+/// - ```de_novo_rate```: the per-allele probability of a de novo mutation
+///
+/// # Errors
+/// - Error if a genotype prior cannot be found for a variant's alleles
+pub fn apply_pedigree_priors(
+    sample_varlists: &mut Vec<VarList>,
+    trios: &Vec<Trio>,
+    genotype_priors: &dyn GenotypePrior,
+    de_novo_rate: f64,
+) -> Result<()> {
+    // the children of each pair of parents, in the order that the families first appear
+    let mut families: Vec<(usize, usize, Vec<usize>)> = vec![];
+    for trio in trios {
+        match families
+            .iter_mut()
+            .find(|f| f.0 == trio.father && f.1 == trio.mother)
+        {
+            Some(family) => {
+                if !family.2.contains(&trio.child) {
+                    family.2.push(trio.child);
+                }
+            }
+            None => families.push((trio.father, trio.mother, vec![trio.child])),
+        }
+    }
+
+    for &(father, mother, ref children) in &families {
+        let n_vars = sample_varlists[father].lst.len();
+        assert_eq!(n_vars, sample_varlists[mother].lst.len());
+        for &child in children {
+            assert_eq!(n_vars, sample_varlists[child].lst.len());
+        }
+
+        for i in 0..n_vars {
+            // the diploid inheritance model does not apply to haploid sites (e.g. chrX in males).
+            // A haploid child is left out of the family, while its siblings are still updated.
+            if sample_varlists[father].lst[i].ploidy != 2
+                || sample_varlists[mother].lst[i].ploidy != 2
+            {
+                continue;
+            }
+            let diploid_children: Vec<usize> = children
+                .iter()
+                .cloned()
+                .filter(|&c| sample_varlists[c].lst[i].ploidy == 2)
+                .collect();
+            if diploid_children.is_empty() {
+                continue;
+            }
+
+            let mut child_priors: Vec<GenotypeProbs> = vec![];
+            for &child in &diploid_children {
+                let child_var = &sample_varlists[child].lst[i];
+                child_priors.push(
+                    genotype_priors
+                        .get_all_site_priors(&child_var.alleles, 2, &child_var.population_af)
+                        .chain_err(|| {
+                            "Error getting genotype priors while applying pedigree priors"
+                        })?,
+                );
+            }
+
+            let family_posts = {
+                let child_posts: Vec<&GenotypeProbs> = diploid_children
+                    .iter()
+                    .map(|&c| &sample_varlists[c].lst[i].genotype_post)
+                    .collect();
+                let child_prior_refs: Vec<&GenotypeProbs> = child_priors.iter().collect();
+                calculate_family_posteriors(
+                    &child_posts,
+                    &child_prior_refs,
+                    &sample_varlists[father].lst[i].genotype_post,
+                    &sample_varlists[mother].lst[i].genotype_post,
+                    de_novo_rate,
+                )
+            };
+
+            let mut updates: Vec<(usize, &GenotypeProbs)> = vec![
+                (father, &family_posts.father),
+                (mother, &family_posts.mother),
+            ];
+            for (&child, posts) in diploid_children.iter().zip(family_posts.children.iter()) {
+                updates.push((child, posts));
+            }
+
+            for &(s, posts) in &updates {
+                let var = &mut sample_varlists[s].lst[i];
+                let (max_g, _) = posts.max_genotype_post(var.phase_set.is_some(), false);
+
+                var.genotype_post = posts.clone();
+                var.genotype = max_g;
+                var.gq = posts
+                    .genotype_qual(max_g, &var.possible_genotypes())
+                    .min(MAX_VCF_QUAL);
+                var.qual = posts.discovery_qual().min(MAX_VCF_QUAL);
+            }
+
+            for (&child, de_novo) in diploid_children.iter().zip(family_posts.de_novo.iter()) {
+                let de_novo_qual = *PHREDProb::from(LogProb::ln_one_minus_exp(de_novo));
+                sample_varlists[child].lst[i].de_novo_qual = Some(de_novo_qual.min(MAX_VCF_QUAL));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::stats::Prob;
+    use variants_and_fragments::Var;
+
+    fn varlist(post: Vec<Vec<f64>>) -> VarList {
+        let mut var = Var::new(0, 100, vec!["A".to_string(), "G".to_string()]);
+        var.genotype_post = GenotypeProbs {
+            tab: post
+                .iter()
+                .map(|row| row.iter().map(|&p| LogProb::from(Prob(p))).collect())
+                .collect(),
+        };
+        VarList::new(vec![var], vec!["chr1".to_string()]).unwrap()
+    }
+
+    fn het_prob(varlist: &VarList) -> f64 {
+        let post = &varlist.lst[0].genotype_post;
+        *Prob::from(LogProb::ln_add_exp(
+            post.get(Genotype(0, 1)),
+            post.get(Genotype(1, 0)),
+        ))
+    }
+
+    #[test]
+    fn test_pedigree_priors_siblings() {
+        // father equally likely homozygous reference or heterozygous, mother homozygous reference,
+        // and two children with the same evidence of heterozygosity (the uniform prior makes the
+        // likelihoods of the child genotypes 0/0, 0/1 and 1/1 proportional to 0.2, 0.8 and 0)
+        let mut sample_varlists = vec![
+            varlist(vec![vec![0.5, 0.25], vec![0.25, 0.0]]),
+            varlist(vec![vec![1.0, 0.0], vec![0.0, 0.0]]),
+            varlist(vec![vec![0.2, 0.4], vec![0.4, 0.0]]),
+            varlist(vec![vec![0.2, 0.4], vec![0.4, 0.0]]),
+        ];
+        let trios = vec![
+            Trio {
+                child: 2,
+                father: 0,
+                mother: 1,
+            },
+            Trio {
+                child: 3,
+                father: 0,
+                mother: 1,
+            },
+        ];
+
+        apply_pedigree_priors(&mut sample_varlists, &trios, &UniformPrior, 1e-8).unwrap();
+
+        // each child is 0/0 with probability 1 if the father is 0/0, and 0/0 or 0/1 with
+        // probability 0.5 each if the father is 0/1, so the father is 0/1 with probability
+        // 0.5 * 0.5^2 / (0.5 * 0.2^2 + 0.5 * 0.5^2), counting his prior once
+        assert!((het_prob(&sample_varlists[0]) - 0.25 / 0.29).abs() < 1e-6);
+        assert!(het_prob(&sample_varlists[1]) < 1e-6);
+
+        // both children are updated with the evidence from their sibling, in the same way
+        let child_het = (0.5 * 0.5 * 0.4) / (0.5 * 0.2 * 0.2 + 0.5 * 0.5 * 0.5);
+        assert!((het_prob(&sample_varlists[2]) - child_het).abs() < 1e-6);
+        assert!((het_prob(&sample_varlists[3]) - child_het).abs() < 1e-6);
+        assert_eq!(sample_varlists[2].lst[0].genotype, Genotype(0, 1));
+        assert_eq!(sample_varlists[3].lst[0].genotype, Genotype(0, 1));
+
+        for varlist in &sample_varlists {
+            varlist.lst[0].genotype_post.assert_approx_normalized();
+        }
+    }
+}
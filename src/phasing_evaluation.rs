@@ -0,0 +1,433 @@
+//! Evaluation of phasing accuracy against a truth set of phased variants.
+//!
+//! The phased heterozygous variants called by Longshot are compared to the phased heterozygous
+//! variants of a truth VCF (e.g. phased using a parent-offspring trio). Each variant that is
+//! phased in both has an orientation: whether Longshot's first haplotype carries the same allele
+//! as the truth's first haplotype. Within a phase block, consecutive variants with different
+//! orientations are a phasing error:
+//! - a flip error is a single variant whose orientation differs from both of its neighbors (the
+//!   phase is switched and immediately switched back)
+//! - every other change of orientation is a switch error
+//!
+//! The switch error rate is the number of switch errors divided by the number of pairs of
+//! consecutive compared variants, and the flip error rate is the number of flip errors divided by
+//! the number of compared variants. The phase block N50 is computed from the span of each of
+//! Longshot's phase blocks on the reference.
+
+use errors::*;
+use hashbrown::HashMap;
+use rust_htslib::bcf;
+use rust_htslib::bcf::record::GenotypeAllele;
+use rust_htslib::bcf::Read as bcfread;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use util::*;
+use variants_and_fragments::VarList;
+
+/// The phased alleles of a heterozygous truth variant
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruthPhase {
+    /// the alleles on the first and second truth haplotypes
+    pub haps: (String, String),
+    /// the phase set (PS) of the truth variant. If the truth VCF has no PS field, every variant
+    /// on a chromosome is in the same phase set.
+    pub phase_set: Option<i32>,
+}
+
+/// Phasing accuracy statistics for a single sample
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PhasingStats {
+    /// number of phased heterozygous variants in the Longshot output
+    pub phased_variants: usize,
+    /// number of phased variants that are also phased heterozygous in the truth, with the same
+    /// alleles
+    pub compared_variants: usize,
+    pub switch_errors: usize,
+    /// number of pairs of consecutive compared variants in the same phase block
+    pub switch_positions: usize,
+    pub flip_errors: usize,
+    /// number of compared variants in phase blocks with at least 2 compared variants
+    pub flip_positions: usize,
+    pub phase_blocks: usize,
+    /// N50 of the phase block spans (first to last phased variant, in bp)
+    pub phase_block_n50: usize,
+}
+
+impl PhasingStats {
+    pub fn switch_error_rate(&self) -> f64 {
+        if self.switch_positions > 0 {
+            self.switch_errors as f64 / self.switch_positions as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn flip_error_rate(&self) -> f64 {
+        if self.flip_positions > 0 {
+            self.flip_errors as f64 / self.flip_positions as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Reads the phased heterozygous variants of one sample from a truth VCF
+///
+/// # Arguments
+/// - ```truth_vcf```: path to the truth VCF
+/// - ```sample_name```: the sample to read. If the truth VCF has a single sample, it is used
+///   regardless of its name.
+/// - ```target_names```: the contig names of the BAM file, used to convert contig names to TIDs
+///
+/// # Returns
+/// Returns a result containing a map from (tid, 0-based position) to the phased truth alleles.
+/// Returns ```None``` if the truth VCF does not contain the sample.
+///
+/// # Errors
+/// - ```BCFOpenError```: error opening the truth VCF
+/// - ```BCFReadError```: error reading a record or its genotype from the truth VCF
+pub fn parse_truth_vcf(
+    truth_vcf: &String,
+    sample_name: &String,
+    target_names: &Vec<String>,
+) -> Result<Option<HashMap<(u32, usize), TruthPhase>>> {
+    let mut vcf = bcf::Reader::from_path(truth_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(truth_vcf.clone()))?;
+    let vcfh = bcf::Reader::from_path(truth_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(truth_vcf.clone()))?;
+
+    let sample_ix = if vcfh.header().sample_count() == 1 {
+        0
+    } else {
+        match vcfh.header().sample_id(sample_name.as_bytes()) {
+            Some(ix) => ix,
+            None => {
+                return Ok(None);
+            }
+        }
+    };
+
+    let mut chrom2tid: HashMap<String, u32> = HashMap::new();
+    for (t, name) in target_names.iter().enumerate() {
+        chrom2tid.insert(name.clone(), t as u32);
+    }
+
+    let mut truth: HashMap<(u32, usize), TruthPhase> = HashMap::new();
+
+    for r in vcf.records() {
+        let mut record = r.chain_err(|| ErrorKind::BCFReadError)?;
+
+        let rid = record.rid().chain_err(|| "Error accessing vcf RID")?;
+        let chrom: String = u8_to_string(
+            vcfh.header()
+                .rid2name(rid)
+                .chain_err(|| ErrorKind::BCFReadError)?,
+        )?;
+        let tid = match chrom2tid.get(&chrom) {
+            Some(&tid) => tid,
+            None => {
+                continue;
+            }
+        };
+
+        let alleles: Vec<String> = record
+            .alleles()
+            .iter()
+            .map(|a| u8_to_string(a))
+            .collect::<Result<Vec<String>>>()?;
+
+        let gt = record
+            .genotypes()
+            .chain_err(|| ErrorKind::BCFReadError)?
+            .get(sample_ix);
+
+        // the first allele of a genotype is always marked unphased, so the phasing of the
+        // genotype is the phasing of the second allele
+        let (a0, a1) = match (gt.len(), gt.get(0), gt.get(1)) {
+            (2, Some(&GenotypeAllele::Unphased(a0)), Some(&GenotypeAllele::Phased(a1)))
+            | (2, Some(&GenotypeAllele::Phased(a0)), Some(&GenotypeAllele::Phased(a1))) => {
+                (a0 as usize, a1 as usize)
+            }
+            _ => {
+                continue;
+            }
+        };
+
+        if a0 == a1 || a0 >= alleles.len() || a1 >= alleles.len() {
+            continue;
+        }
+
+        let phase_set: Option<i32> = match record.format(b"PS").integer() {
+            Ok(ps) => Some(ps[sample_ix][0]),
+            Err(_) => None,
+        };
+
+        truth.insert(
+            (tid, record.pos() as usize),
+            TruthPhase {
+                haps: (alleles[a0].clone(), alleles[a1].clone()),
+                phase_set: phase_set,
+            },
+        );
+    }
+
+    Ok(Some(truth))
+}
+
+/// Counts the switch and flip errors in a phase block
+///
+/// # Arguments
+/// - ```orientations```: for each compared variant in the block in order, whether the phase of the
+///   variant agrees with the truth
+///
+/// # Returns
+/// Returns a tuple ```(switch_errors, flip_errors)```
+pub fn count_switch_flip_errors(orientations: &Vec<bool>) -> (usize, usize) {
+    let mut switch_errors = 0;
+    let mut flip_errors = 0;
+    let mut i = 1;
+    while i < orientations.len() {
+        if orientations[i] != orientations[i - 1] {
+            if i + 1 < orientations.len() && orientations[i + 1] != orientations[i] {
+                // the phase changes and immediately changes back: a single flipped variant
+                flip_errors += 1;
+                i += 2;
+                continue;
+            }
+            switch_errors += 1;
+        }
+        i += 1;
+    }
+    (switch_errors, flip_errors)
+}
+
+/// Returns the N50 of a list of lengths: the largest length L such that the lengths of at least L
+/// add up to at least half of the total
+pub fn n50(lengths: &Vec<usize>) -> usize {
+    let mut sorted = lengths.clone();
+    sorted.sort_by(|a, b| b.cmp(a));
+    let total: usize = sorted.iter().sum();
+    let mut cumulative = 0;
+    for &l in &sorted {
+        cumulative += l;
+        if 2 * cumulative >= total {
+            return l;
+        }
+    }
+    0
+}
+
+/// Compares the phased variants of a sample to the truth
+///
+/// # Arguments
+/// - ```varlist```: the phased variants called for the sample
+/// - ```truth```: the phased truth variants, as returned by ```parse_truth_vcf```
+///
+/// # Returns
+/// Returns the phasing accuracy statistics. Variants are compared within each Longshot phase
+/// block, and a block is split wherever the truth phase set changes. A truth phase set that
+/// resumes after a different one is a new segment, since the two runs are not phased relative to
+/// each other.
+pub fn evaluate_phasing(
+    varlist: &VarList,
+    truth: &HashMap<(u32, usize), TruthPhase>,
+) -> PhasingStats {
+    let mut stats = PhasingStats::default();
+
+    // (tid, phase set) -> (first position, last position) of the Longshot phase blocks
+    let mut block_spans: HashMap<(u32, usize), (usize, usize)> = HashMap::new();
+    // (tid, Longshot phase set) -> truth phase set and first position of the current run of
+    // compared variants with that truth phase set
+    let mut current_segments: HashMap<(u32, usize), (Option<i32>, usize)> = HashMap::new();
+    // (tid, Longshot phase set, truth phase set, run start) -> orientation of each compared variant
+    let mut block_orientations: HashMap<(u32, usize, Option<i32>, usize), Vec<bool>> =
+        HashMap::new();
+
+    for var in &varlist.lst {
+        let ps = match var.phase_set {
+            Some(ps) => ps,
+            None => {
+                continue;
+            }
+        };
+        if var.genotype.0 == var.genotype.1 {
+            continue;
+        }
+        stats.phased_variants += 1;
+
+        let span = block_spans
+            .entry((var.tid, ps))
+            .or_insert((var.pos0, var.pos0));
+        span.1 = var.pos0;
+
+        let t = match truth.get(&(var.tid, var.pos0)) {
+            Some(t) => t,
+            None => {
+                continue;
+            }
+        };
+        let h0 = &var.alleles[var.genotype.0 as usize];
+        let h1 = &var.alleles[var.genotype.1 as usize];
+        let orientation = if *h0 == t.haps.0 && *h1 == t.haps.1 {
+            true
+        } else if *h0 == t.haps.1 && *h1 == t.haps.0 {
+            false
+        } else {
+            // the variant has different alleles (or a different representation) in the truth
+            continue;
+        };
+
+        stats.compared_variants += 1;
+        let segment = current_segments
+            .entry((var.tid, ps))
+            .or_insert((t.phase_set, var.pos0));
+        if segment.0 != t.phase_set {
+            *segment = (t.phase_set, var.pos0);
+        }
+        block_orientations
+            .entry((var.tid, ps, segment.0, segment.1))
+            .or_insert(vec![])
+            .push(orientation);
+    }
+
+    for orientations in block_orientations.values() {
+        if orientations.len() < 2 {
+            continue;
+        }
+        let (switch_errors, flip_errors) = count_switch_flip_errors(orientations);
+        stats.switch_errors += switch_errors;
+        stats.flip_errors += flip_errors;
+        stats.switch_positions += orientations.len() - 1;
+        stats.flip_positions += orientations.len();
+    }
+
+    let spans: Vec<usize> = block_spans.values().map(|&(s, e)| e - s + 1).collect();
+    stats.phase_blocks = spans.len();
+    stats.phase_block_n50 = n50(&spans);
+
+    stats
+}
+
+/// Writes a summary report of phasing accuracy for each evaluated sample
+///
+/// # Arguments
+/// - ```sample_stats```: the name and phasing statistics of each evaluated sample
+/// - ```truth_vcf```: path to the truth VCF (printed in the report)
+/// - ```output_file```: path of the report to write
+///
+/// # Errors
+/// - ```CreateFileError```: error creating the report file
+/// - ```FileWriteError```: error writing to the report file
+pub fn print_phasing_report(
+    sample_stats: &Vec<(String, PhasingStats)>,
+    truth_vcf: &String,
+    output_file: &String,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file =
+        File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+
+    writeln!(file, "#truth_vcf={}", truth_vcf)
+        .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    writeln!(
+        file,
+        "#sample\tphased_variants\tcompared_variants\tswitch_errors\tswitch_error_rate\tflip_errors\tflip_error_rate\tphase_blocks\tphase_block_N50"
+    )
+    .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+
+    for &(ref sample_name, ref stats) in sample_stats {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{:.6}\t{}\t{:.6}\t{}\t{}",
+            sample_name,
+            stats.phased_variants,
+            stats.compared_variants,
+            stats.switch_errors,
+            stats.switch_error_rate(),
+            stats.flip_errors,
+            stats.flip_error_rate(),
+            stats.phase_blocks,
+            stats.phase_block_n50
+        )
+        .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genotype_probs::Genotype;
+    use variants_and_fragments::Var;
+
+    #[test]
+    fn test_count_switch_flip_errors() {
+        // no errors, and a block that is entirely flipped relative to the truth
+        assert_eq!(count_switch_flip_errors(&vec![true, true, true, true]), (0, 0));
+        assert_eq!(count_switch_flip_errors(&vec![false, false, false]), (0, 0));
+        // a single switch
+        assert_eq!(count_switch_flip_errors(&vec![true, true, false, false]), (1, 0));
+        // a single flipped variant
+        assert_eq!(count_switch_flip_errors(&vec![true, false, true, true]), (0, 1));
+        // a switch at the last variant is a switch, not a flip
+        assert_eq!(count_switch_flip_errors(&vec![true, true, true, false]), (1, 0));
+        // a flip followed by a switch
+        assert_eq!(
+            count_switch_flip_errors(&vec![true, false, true, true, false, false]),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn test_n50() {
+        assert_eq!(n50(&vec![]), 0);
+        assert_eq!(n50(&vec![100]), 100);
+        // total 100: 40 + 30 >= 50
+        assert_eq!(n50(&vec![10, 30, 40, 20]), 30);
+    }
+
+    #[test]
+    fn test_evaluate_phasing_truth_segments() {
+        // one Longshot phase block over truth phase sets 1, 2 and then 1 again. Each run of the
+        // truth is oriented differently, which is not an error since the runs are not phased
+        // relative to each other.
+        let truth_ps = vec![1, 1, 2, 2, 1, 1];
+        let orientations = vec![true, true, false, false, false, false];
+
+        let mut lst: Vec<Var> = vec![];
+        let mut truth: HashMap<(u32, usize), TruthPhase> = HashMap::new();
+        for i in 0..truth_ps.len() {
+            let pos0 = 100 * (i + 1);
+            let mut var = Var::new(0, pos0, vec!["A".to_string(), "G".to_string()]);
+            var.genotype = Genotype(0, 1);
+            var.phase_set = Some(100);
+            lst.push(var);
+
+            let haps = if orientations[i] {
+                ("A".to_string(), "G".to_string())
+            } else {
+                ("G".to_string(), "A".to_string())
+            };
+            truth.insert(
+                (0, pos0),
+                TruthPhase {
+                    haps: haps,
+                    phase_set: Some(truth_ps[i]),
+                },
+            );
+        }
+        let varlist = VarList::new(lst, vec!["chr1".to_string()]).unwrap();
+
+        let stats = evaluate_phasing(&varlist, &truth);
+        assert_eq!(stats.phased_variants, 6);
+        assert_eq!(stats.compared_variants, 6);
+        assert_eq!(stats.switch_errors, 0);
+        assert_eq!(stats.flip_errors, 0);
+        // one pair of consecutive variants in each of the 3 runs
+        assert_eq!(stats.switch_positions, 3);
+        assert_eq!(stats.phase_blocks, 1);
+    }
+}
@@ -0,0 +1,433 @@
+//! Phasing of polyploid samples by clustering reads into ```k``` haplotypes.
+//!
+//! HapCUT2 (and the haplotype-informed genotyping built on it) only handles two haplotypes. For
+//! samples with a higher ploidy, variants are first split into blocks (connected components of
+//! variants linked by reads), and within each block the reads are clustered into ```ploidy```
+//! groups by hard expectation-maximization: each read is assigned to the haplotype it fits best,
+//! and each haplotype allele is then re-estimated from the reads assigned to it. The clustering is
+//! restarted from several random initializations and the most likely solution is kept.
+//!
+//! For each block we report the MEC (number of read alleles that disagree with the haplotype the
+//! read was assigned to) and the mean probability that a read is assigned to the wrong haplotype,
+//! as a measure of the uncertainty of the phasing.
+
+use bio::stats::{LogProb, Prob};
+use errors::*;
+use rand::{Rng, SeedableRng, StdRng};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use variants_and_fragments::*;
+
+/// A block of variants phased into ```k``` haplotypes
+pub struct PolyploidBlock {
+    /// indices of the variants in the block (into the ```VarList```), in increasing order
+    pub var_ixs: Vec<usize>,
+    /// ```haps[h][j]``` is the allele of haplotype ```h``` at variant ```var_ixs[j]```
+    pub haps: Vec<Vec<u8>>,
+    /// indices of the fragments used to phase the block
+    pub frag_ixs: Vec<usize>,
+    /// ```assignments[i]``` is the haplotype that fragment ```frag_ixs[i]``` was assigned to
+    pub assignments: Vec<usize>,
+    /// number of read alleles that do not match the haplotype their read was assigned to
+    pub mec: usize,
+    /// total number of read alleles used in the block
+    pub n_calls: usize,
+    /// mean probability that a read is assigned to the wrong haplotype
+    pub uncertainty: f64,
+    /// log-likelihood of the reads under the phased haplotypes (uniform mixture over haplotypes)
+    pub likelihood: LogProb,
+}
+
+/// finds the representative of a variant's set in the union-find forest
+fn find_root(parent: &mut Vec<usize>, mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// log-likelihood of a read's calls given a haplotype (calls at variants outside the block are ignored)
+fn read_likelihood(calls: &Vec<&FragCall>, block_ix: &Vec<Option<usize>>, hap: &Vec<u8>) -> LogProb {
+    let mut p = LogProb::ln_one();
+    for call in calls {
+        if let Some(j) = block_ix[call.var_ix] {
+            if call.allele == hap[j] {
+                p = p + call.one_minus_qual;
+            } else {
+                p = p + call.qual;
+            }
+        }
+    }
+    p
+}
+
+/// Clusters the reads covering one block into ```ploidy``` haplotypes by hard EM
+///
+/// returns ```(haps, assignments, likelihood)``` for the best of ```restarts``` random starts.
+fn cluster_block(
+    block_calls: &Vec<Vec<&FragCall>>,
+    block_ix: &Vec<Option<usize>>,
+    n_block_vars: usize,
+    ploidy: usize,
+    restarts: usize,
+    rng: &mut StdRng,
+) -> (Vec<Vec<u8>>, Vec<usize>, LogProb) {
+    let max_iterations = 100;
+    let ln_ploidy = LogProb::from(Prob(1.0 / ploidy as f64));
+
+    let mut best: Option<(Vec<Vec<u8>>, Vec<usize>, LogProb)> = None;
+
+    for _ in 0..restarts {
+        // start from a random assignment of reads to haplotypes
+        let mut assignments: Vec<usize> = (0..block_calls.len())
+            .map(|_| rng.gen_range(0, ploidy))
+            .collect();
+        let mut haps: Vec<Vec<u8>> = vec![vec![0u8; n_block_vars]; ploidy];
+
+        for _ in 0..max_iterations {
+            // update each haplotype allele using the reads currently assigned to the haplotype.
+            // score[h][j][a] is the log-likelihood of the reads on haplotype h at variant j if the
+            // haplotype allele is a
+            let mut score: Vec<Vec<[LogProb; 2]>> =
+                vec![vec![[LogProb::ln_one(), LogProb::ln_one()]; n_block_vars]; ploidy];
+            for (r, calls) in block_calls.iter().enumerate() {
+                for call in calls {
+                    if let Some(j) = block_ix[call.var_ix] {
+                        for a in 0..2 {
+                            let s = &mut score[assignments[r]][j][a];
+                            *s = *s + if call.allele == a as u8 {
+                                call.one_minus_qual
+                            } else {
+                                call.qual
+                            };
+                        }
+                    }
+                }
+            }
+            for h in 0..ploidy {
+                for j in 0..n_block_vars {
+                    haps[h][j] = if score[h][j][1] > score[h][j][0] { 1 } else { 0 };
+                }
+            }
+
+            // reassign each read to the haplotype that it fits best
+            let mut changed = false;
+            for (r, calls) in block_calls.iter().enumerate() {
+                let mut best_h = assignments[r];
+                let mut best_p = read_likelihood(calls, block_ix, &haps[best_h]);
+                for h in 0..ploidy {
+                    let p = read_likelihood(calls, block_ix, &haps[h]);
+                    if p > best_p {
+                        best_p = p;
+                        best_h = h;
+                    }
+                }
+                if best_h != assignments[r] {
+                    assignments[r] = best_h;
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        // the likelihood of the solution treats each read as drawn uniformly from the haplotypes
+        let mut likelihood = LogProb::ln_one();
+        for calls in block_calls {
+            let p_hap: Vec<LogProb> = haps
+                .iter()
+                .map(|hap| ln_ploidy + read_likelihood(calls, block_ix, hap))
+                .collect();
+            likelihood = likelihood + LogProb::ln_sum_exp(&p_hap);
+        }
+
+        let better = match best {
+            Some((_, _, best_likelihood)) => likelihood > best_likelihood,
+            None => true,
+        };
+        if better {
+            best = Some((haps, assignments, likelihood));
+        }
+    }
+
+    best.unwrap()
+}
+
+/// Phases variants into ```ploidy``` haplotypes
+///
+/// # Arguments
+/// - ```flist```: the list of haplotype fragments
+/// - ```phase_variant```: ```phase_variant[i]``` is true if variant ```i``` should be phased.
+///   Only biallelic variants should be selected, since haplotype alleles are 0 or 1.
+/// - ```ploidy```: the number of haplotypes
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a read allele to be used
+/// - ```restarts```: the number of random initializations of the read clustering per block
+///
+/// # Returns
+/// Returns the phased blocks, ordered by their first variant. Blocks consist of at least two
+/// variants linked by reads that cover two or more of them.
+pub fn phase_polyploid(
+    flist: &Vec<Fragment>,
+    phase_variant: &Vec<bool>,
+    ploidy: usize,
+    max_p_miscall: f64,
+    restarts: usize,
+) -> Vec<PolyploidBlock> {
+    assert!(ploidy >= 2);
+    let n_var = phase_variant.len();
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let mut rng: StdRng = StdRng::from_seed(&[0]);
+
+    // the calls of each fragment at variants that are being phased
+    let frag_calls: Vec<Vec<&FragCall>> = flist
+        .iter()
+        .map(|f| {
+            f.calls
+                .iter()
+                .filter(|c| phase_variant[c.var_ix] && c.qual < ln_max_p_miscall)
+                .collect()
+        })
+        .collect();
+
+    // join variants that are linked by a read into blocks
+    let mut parent: Vec<usize> = (0..n_var).collect();
+    for calls in &frag_calls {
+        for w in calls.windows(2) {
+            let a = find_root(&mut parent, w[0].var_ix);
+            let b = find_root(&mut parent, w[1].var_ix);
+            if a != b {
+                parent[b.max(a)] = a.min(b);
+            }
+        }
+    }
+
+    let mut block_vars: Vec<Vec<usize>> = vec![vec![]; n_var];
+    for v in 0..n_var {
+        if phase_variant[v] {
+            let root = find_root(&mut parent, v);
+            block_vars[root].push(v);
+        }
+    }
+
+    let mut block_frags: Vec<Vec<usize>> = vec![vec![]; n_var];
+    for (f, calls) in frag_calls.iter().enumerate() {
+        if calls.len() >= 2 {
+            let root = find_root(&mut parent, calls[0].var_ix);
+            block_frags[root].push(f);
+        }
+    }
+
+    let mut blocks: Vec<PolyploidBlock> = vec![];
+
+    for root in 0..n_var {
+        let var_ixs = &block_vars[root];
+        if var_ixs.len() < 2 {
+            continue;
+        }
+
+        // block_ix[v] is the position of variant v within this block
+        let mut block_ix: Vec<Option<usize>> = vec![None; n_var];
+        for (j, &v) in var_ixs.iter().enumerate() {
+            block_ix[v] = Some(j);
+        }
+
+        let frag_ixs = block_frags[root].clone();
+        let block_calls: Vec<Vec<&FragCall>> =
+            frag_ixs.iter().map(|&f| frag_calls[f].clone()).collect();
+
+        let (haps, assignments, likelihood) = cluster_block(
+            &block_calls,
+            &block_ix,
+            var_ixs.len(),
+            ploidy,
+            restarts,
+            &mut rng,
+        );
+
+        let mut mec = 0;
+        let mut n_calls = 0;
+        let mut p_wrong_total = 0.0;
+        for (r, calls) in block_calls.iter().enumerate() {
+            for call in calls {
+                n_calls += 1;
+                if call.allele != haps[assignments[r]][block_ix[call.var_ix].unwrap()] {
+                    mec += 1;
+                }
+            }
+
+            // posterior probability of the assigned haplotype (uniform prior over haplotypes)
+            let p_hap: Vec<LogProb> = haps
+                .iter()
+                .map(|hap| read_likelihood(calls, &block_ix, hap))
+                .collect();
+            let p_assigned = p_hap[assignments[r]] - LogProb::ln_sum_exp(&p_hap);
+            p_wrong_total += 1.0 - *Prob::from(p_assigned);
+        }
+
+        let uncertainty = if block_calls.len() > 0 {
+            p_wrong_total / block_calls.len() as f64
+        } else {
+            0.0
+        };
+
+        blocks.push(PolyploidBlock {
+            var_ixs: var_ixs.clone(),
+            haps,
+            frag_ixs,
+            assignments,
+            mec,
+            n_calls,
+            uncertainty,
+            likelihood,
+        });
+    }
+
+    blocks
+}
+
+/// Writes the phased polyploid blocks to a file
+///
+/// Each block starts with a header line
+/// ```BLOCK <chrom> <first pos> <last pos> <variants> <reads> <MEC> <MEC fraction> <uncertainty>```
+/// followed by one line per variant: ```<chrom> <pos> <ref> <alt> <hap 1 allele> ... <hap k allele> <alt dosage>```.
+/// Blocks are separated by a line of asterisks. Positions are 1-based.
+///
+/// # Errors
+/// - Error if the output file cannot be created or written to
+pub fn print_polyploid_blocks(
+    blocks: &Vec<PolyploidBlock>,
+    varlist: &VarList,
+    output_file: &String,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let display = path.display();
+    let mut file =
+        File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+
+    for block in blocks {
+        let first = &varlist.lst[block.var_ixs[0]];
+        let last = &varlist.lst[block.var_ixs[block.var_ixs.len() - 1]];
+        let chrom = &varlist.target_names[first.tid as usize];
+        let mec_frac = if block.n_calls > 0 {
+            block.mec as f64 / block.n_calls as f64
+        } else {
+            0.0
+        };
+
+        writeln!(
+            file,
+            "BLOCK\t{}\t{}\t{}\tvariants={}\treads={}\tMEC={}\tMEC_frac={:.4}\tuncertainty={:.4}",
+            chrom,
+            first.pos0 + 1,
+            last.pos0 + 1,
+            block.var_ixs.len(),
+            block.frag_ixs.len(),
+            block.mec,
+            mec_frac,
+            block.uncertainty
+        )
+        .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+
+        for (j, &v) in block.var_ixs.iter().enumerate() {
+            let var = &varlist.lst[v];
+            let alleles: Vec<String> = block.haps.iter().map(|hap| hap[j].to_string()).collect();
+            let dosage: usize = block.haps.iter().filter(|hap| hap[j] == 1).count();
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                varlist.target_names[var.tid as usize],
+                var.pos0 + 1,
+                var.alleles[0],
+                var.alleles[1],
+                alleles.join("\t"),
+                dosage
+            )
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        }
+
+        writeln!(file, "********").chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_fragment(frag_ix: usize, alleles: &[(usize, u8)]) -> Fragment {
+        let qual = LogProb::from(Prob(0.01));
+        let one_minus_qual = LogProb::from(Prob(0.99));
+        Fragment {
+            id: Some(format!("read{}", frag_ix)),
+            calls: alleles
+                .iter()
+                .map(|&(var_ix, allele)| FragCall {
+                    frag_ix,
+                    var_ix,
+                    allele,
+                    qual,
+                    one_minus_qual,
+                    reverse_strand: false,
+                    read_pos: u32::MAX,
+                    base_qual: 255,
+                })
+                .collect(),
+            p_read_hap: [LogProb::ln_one(), LogProb::ln_one()],
+            reverse_strand: false,
+            mapq: 60,
+            error_rate: None,
+            hp_tag: None,
+            ps_tag: None,
+        }
+    }
+
+    #[test]
+    fn test_phase_polyploid_triploid() {
+        // three haplotypes over four variants, each sampled by several reads
+        let true_haps: Vec<Vec<u8>> = vec![vec![1, 0, 0, 1], vec![0, 1, 0, 1], vec![0, 0, 1, 0]];
+        let mut flist: Vec<Fragment> = vec![];
+        for _ in 0..5 {
+            for hap in &true_haps {
+                let f = flist.len();
+                let calls: Vec<(usize, u8)> = (0..4).map(|v| (v, hap[v])).collect();
+                flist.push(generate_fragment(f, &calls));
+            }
+        }
+        let phase_variant = vec![true; 4];
+
+        let blocks = phase_polyploid(&flist, &phase_variant, 3, 0.1, 10);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].var_ixs, vec![0, 1, 2, 3]);
+        assert_eq!(blocks[0].mec, 0);
+        let mut haps = blocks[0].haps.clone();
+        haps.sort();
+        let mut expected = true_haps.clone();
+        expected.sort();
+        assert_eq!(haps, expected);
+        assert!(blocks[0].uncertainty < 0.01);
+    }
+
+    #[test]
+    fn test_phase_polyploid_blocks() {
+        // variants 0-1 and 2-3 are not linked by any read, so they form two blocks
+        let flist = vec![
+            generate_fragment(0, &[(0, 0), (1, 1)]),
+            generate_fragment(1, &[(0, 1), (1, 0)]),
+            generate_fragment(2, &[(2, 1), (3, 1)]),
+            generate_fragment(3, &[(2, 0), (3, 0)]),
+        ];
+        let phase_variant = vec![true; 4];
+
+        let blocks = phase_polyploid(&flist, &phase_variant, 4, 0.1, 5);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].var_ixs, vec![0, 1]);
+        assert_eq!(blocks[1].var_ixs, vec![2, 3]);
+    }
+}
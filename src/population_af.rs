@@ -0,0 +1,213 @@
+//! Population allele frequencies of known variant sites, used to inform the genotype priors.
+//!
+//! An annotation VCF (e.g. gnomAD) with an allele frequency INFO field is read for the candidate
+//! variants of each region, and every candidate variant allele that matches an allele of the VCF
+//! at the same position gets its population allele frequency. At these sites the genotype prior
+//! is a mixture of the default genotype priors and the Hardy-Weinberg genotype frequencies (with
+//! ```--genotype_prior population-af```, see ```PopulationAfPrior```), so that common variants
+//! need less evidence from the reads to be called, while a variant that is absent or rare in the
+//! population still has to be supported as well as a novel variant. Sites that are not in the VCF
+//! use the default priors.
+
+use errors::*;
+use hashbrown::HashMap;
+use rust_htslib::bcf;
+use rust_htslib::bcf::header::TagType;
+use rust_htslib::bcf::Read as bcfread;
+use util::*;
+use variants_and_fragments::VarList;
+
+/// A known variant allele from the population allele frequency VCF:
+/// (reference allele, alternate allele, allele frequency)
+pub type KnownAllele = (String, String, f64);
+
+/// Checks that the population allele frequency VCF is indexed and has the allele frequency INFO
+/// field as a float.
+///
+/// # Errors
+/// - ```BCFOpenError```: the VCF can't be opened, e.g. because it is not indexed
+/// - Error if the header doesn't define ```af_tag``` as a Float INFO field
+pub fn check_population_af_vcf(af_vcf: &String, af_tag: &String) -> Result<()> {
+    let vcf = bcf::IndexedReader::from_path(af_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(af_vcf.clone()))?;
+    match vcf.header().info_type(af_tag.as_bytes()) {
+        Ok((TagType::Float, _)) => Ok(()),
+        _ => bail!(
+            "Population allele frequency VCF {} does not have a Float INFO field {}.",
+            af_vcf,
+            af_tag
+        ),
+    }
+}
+
+// removes the reference bases shared by the end of the reference and alternate allele (keeping at
+// least one base), so that alleles padded differently to represent a multiallelic site compare
+// equal, e.g. ACT>GCT and A>G
+pub fn trim_common_suffix<'a>(ref_allele: &'a str, alt_allele: &'a str) -> (&'a str, &'a str) {
+    let (r, a) = (ref_allele.as_bytes(), alt_allele.as_bytes());
+    let mut n = 0;
+    while n + 1 < r.len() && n + 1 < a.len() && r[r.len() - 1 - n] == a[a.len() - 1 - n] {
+        n += 1;
+    }
+    (
+        &ref_allele[..ref_allele.len() - n],
+        &alt_allele[..alt_allele.len() - n],
+    )
+}
+
+/// Finds the population allele frequencies of the alleles of a variant.
+///
+/// # Arguments
+/// - ```alleles```: the alleles of the variant, with ```alleles[0]``` the reference allele
+/// - ```known```: the known variant alleles at the same position
+///
+/// # Returns
+/// Returns the allele frequency of each allele (indices match up with ```alleles```), where
+/// variant alleles that are not known have frequency 0 and the reference allele has the remaining
+/// frequency. Returns ```None``` if none of the variant alleles are known.
+pub fn allele_frequencies(alleles: &Vec<String>, known: &[KnownAllele]) -> Option<Vec<f64>> {
+    let mut afs: Vec<f64> = vec![0.0; alleles.len()];
+    let mut found = false;
+    for (i, allele) in alleles.iter().enumerate().skip(1) {
+        let var_key = trim_common_suffix(&alleles[0], allele);
+        for &(ref ref_allele, ref alt_allele, af) in known {
+            if trim_common_suffix(ref_allele, alt_allele) == var_key {
+                afs[i] = af.max(0.0).min(1.0);
+                found = true;
+                break;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+    let alt_total: f64 = afs[1..].iter().sum();
+    afs[0] = (1.0 - alt_total).max(0.0);
+    Some(afs)
+}
+
+// reads the known variant alleles of the VCF on a contig between two positions (0-based,
+// inclusive), by position
+fn read_known_alleles(
+    vcf: &mut bcf::IndexedReader,
+    chrom: &String,
+    start_pos: u32,
+    end_pos: u32,
+    af_tag: &String,
+) -> Result<HashMap<usize, Vec<KnownAllele>>> {
+    let mut known: HashMap<usize, Vec<KnownAllele>> = HashMap::new();
+    let rid = match vcf.header().name2rid(chrom.as_bytes()) {
+        Ok(rid) => rid,
+        Err(_) => {
+            return Ok(known);
+        }
+    };
+    vcf.fetch(rid, start_pos, end_pos).chain_err(|| ErrorKind::BCFReadError)?;
+
+    for r in vcf.records() {
+        let mut record = r.chain_err(|| ErrorKind::BCFReadError)?;
+        let pos0 = record.pos() as usize;
+        let alleles: Vec<String> = record
+            .alleles()
+            .iter()
+            .map(|a| u8_to_string(a).map(|s| s.to_ascii_uppercase()))
+            .collect::<Result<Vec<String>>>()?;
+        let afs: Vec<f32> = match record.info(af_tag.as_bytes()).float() {
+            Ok(Some(afs)) => afs.to_vec(),
+            _ => {
+                continue;
+            }
+        };
+        for (alt_allele, &af) in alleles.iter().skip(1).zip(afs.iter()) {
+            // missing values are NaN
+            if af.is_nan() {
+                continue;
+            }
+            known
+                .entry(pos0)
+                .or_insert(vec![])
+                .push((alleles[0].clone(), alt_allele.clone(), af as f64));
+        }
+    }
+
+    Ok(known)
+}
+
+/// Sets the population allele frequencies (```var.population_af```) of the variants from an
+/// indexed VCF. Only the records spanned by the variants of each contig are read.
+///
+/// # Arguments
+/// - ```varlist```: the variants, sorted by position
+/// - ```af_vcf```: the population allele frequency VCF (bgzipped and indexed)
+/// - ```af_tag```: the INFO field of ```af_vcf``` holding the allele frequency of each ALT allele
+///
+/// # Returns
+/// Returns the number of variants with a known population allele frequency
+///
+/// # Errors
+/// - ```BCFOpenError```: error opening the VCF
+/// - ```BCFReadError```: error reading the records of the VCF
+pub fn annotate_population_afs(
+    varlist: &mut VarList,
+    af_vcf: &String,
+    af_tag: &String,
+) -> Result<usize> {
+    let mut vcf = bcf::IndexedReader::from_path(af_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(af_vcf.clone()))?;
+    let mut n_known = 0;
+    let mut i = 0;
+
+    while i < varlist.lst.len() {
+        // the variants of the same contig are consecutive
+        let tid = varlist.lst[i].tid;
+        let mut j = i;
+        while j < varlist.lst.len() && varlist.lst[j].tid == tid {
+            j += 1;
+        }
+
+        let known = read_known_alleles(
+            &mut vcf,
+            &varlist.target_names[tid as usize],
+            varlist.lst[i].pos0 as u32,
+            varlist.lst[j - 1].pos0 as u32,
+            af_tag,
+        )?;
+        for var in varlist.lst[i..j].iter_mut() {
+            var.population_af = match known.get(&var.pos0) {
+                Some(k) => allele_frequencies(&var.alleles, k),
+                None => None,
+            };
+            if var.population_af.is_some() {
+                n_known += 1;
+            }
+        }
+
+        i = j;
+    }
+
+    Ok(n_known)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(r: &str, a: &str, af: f64) -> KnownAllele {
+        (r.to_string(), a.to_string(), af)
+    }
+
+    #[test]
+    fn test_allele_frequencies() {
+        let alleles = vec!["A".to_string(), "G".to_string(), "T".to_string()];
+        // one of the variant alleles is known
+        let afs = allele_frequencies(&alleles, &[known("A", "G", 0.2), known("A", "C", 0.1)]);
+        assert_eq!(afs, Some(vec![0.8, 0.2, 0.0]));
+        // none of the variant alleles is known
+        assert_eq!(allele_frequencies(&alleles, &[known("A", "C", 0.1)]), None);
+
+        // a split multiallelic site where the deletion is padded with more reference bases
+        let alleles = vec!["ACT".to_string(), "A".to_string(), "GCT".to_string()];
+        let afs = allele_frequencies(&alleles, &[known("ACT", "A", 0.25), known("A", "G", 0.5)]);
+        assert_eq!(afs, Some(vec![0.25, 0.25, 0.5]));
+    }
+}
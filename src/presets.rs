@@ -0,0 +1,578 @@
+//! Technology presets: sensible values of the realignment and candidate discovery options, and
+//! alignment parameters, for the common long-read sequencing technologies.
+//!
+//! A preset works by setting the options that are not given on the command line, so every option
+//! of a preset can still be overridden individually. The alignment parameters of a preset are used
+//! instead of estimating them from the reads, unless they are read from a file (```--read-params```)
+//! or a context-, strand- or read-group-specific error model is requested, which has to be
+//! estimated.
+//!
+//! With ```--preset auto```, the preset is chosen from the platform that the header and the first
+//! reads of the BAM file point to (see ```detect_preset```).
+
+use errors::*;
+use realignment::{AlignmentParameters, EmissionProbs, TransitionProbs};
+use rust_htslib::bam;
+use rust_htslib::bam::record::Cigar;
+use rust_htslib::bam::Read;
+use util::{open_bam, print_time, u8_to_string};
+
+/// the names of the presets, as given to ```--preset```
+pub static PRESET_NAMES: [&str; 4] = ["ont-r10", "ont-r9", "pacbio-hifi", "pacbio-clr"];
+
+/// the values of ```--preset```: the presets, or ```auto``` to detect the platform
+pub static PRESET_ARG_VALUES: [&str; 5] =
+    ["auto", "ont-r10", "ont-r9", "pacbio-hifi", "pacbio-clr"];
+
+/// the number of primary alignments at the start of the BAM file that the read statistics of the
+/// platform detection are computed from
+static DETECTION_READS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    OntR10,
+    OntR9,
+    PacbioHifi,
+    PacbioClr,
+}
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "ont-r10" => Some(Preset::OntR10),
+            "ont-r9" => Some(Preset::OntR9),
+            "pacbio-hifi" => Some(Preset::PacbioHifi),
+            "pacbio-clr" => Some(Preset::PacbioClr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Preset::OntR10 => "ont-r10",
+            Preset::OntR9 => "ont-r9",
+            Preset::PacbioHifi => "pacbio-hifi",
+            Preset::PacbioClr => "pacbio-clr",
+        }
+    }
+
+    /// The options set by the preset, as (long option, short option, value)
+    pub fn options(&self) -> Vec<(&'static str, Option<&'static str>, &'static str)> {
+        // the missing base quality is the typical base quality of the reads of the technology
+        let (band_width, max_window, max_snvs, candidate_fdr, missing_base_qual) = match *self {
+            Preset::OntR10 => ("20", "60", "4", "0.02", "20"),
+            Preset::OntR9 => ("30", "80", "3", "0.05", "10"),
+            Preset::PacbioHifi => ("10", "30", "5", "0.01", "30"),
+            Preset::PacbioClr => ("20", "50", "3", "0.05", "10"),
+        };
+        let mut options = vec![
+            ("--band_width", Some("-B"), band_width),
+            ("--max_window", Some("-W"), max_window),
+            ("--max_snvs", Some("-m"), max_snvs),
+            ("--candidate_fdr", None, candidate_fdr),
+            ("--missing_base_qual_value", None, missing_base_qual),
+        ];
+        // HiFi reads are accurate enough to genotype short indels
+        if *self == Preset::PacbioHifi {
+            options.push(("--max_indel_len", None, "20"));
+        }
+        options
+    }
+
+    /// Typical alignment parameters of the reads of the technology
+    pub fn alignment_parameters(&self) -> AlignmentParameters {
+        // (match, insertion, deletion from match), (insertion, deletion extension) and the
+        // probability that a matched base is equal to the reference
+        let ((mm, im, dm), (ii, dd), equal) = match *self {
+            Preset::OntR10 => ((0.96, 0.015, 0.025), (0.2, 0.25), 0.98),
+            Preset::OntR9 => ((0.90, 0.04, 0.06), (0.25, 0.3), 0.94),
+            Preset::PacbioHifi => ((0.996, 0.002, 0.002), (0.25, 0.25), 0.999),
+            Preset::PacbioClr => ((0.879, 0.0987, 0.0223), (0.1116, 0.0217), 0.9762),
+        };
+        AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: mm,
+                insertion_from_match: im,
+                deletion_from_match: dm,
+                insertion_from_insertion: ii,
+                match_from_insertion: 1.0 - ii,
+                deletion_from_deletion: dd,
+                match_from_deletion: 1.0 - dd,
+            },
+            emission_probs: EmissionProbs {
+                equal: equal,
+                not_equal: (1.0 - equal) / 3.0,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        }
+    }
+}
+
+// whether an option is given in the command line arguments, as "--long value", "--long=value",
+// "-s value" or "-svalue"
+pub fn option_given(args: &[String], long: &str, short: Option<&str>) -> bool {
+    let long_eq = format!("{}=", long);
+    args.iter().any(|a| {
+        a == long
+            || a.starts_with(&long_eq)
+            || match short {
+                Some(s) => a.starts_with(s) && !a.starts_with("--"),
+                None => false,
+            }
+    })
+}
+
+/// What the header and the first reads of a BAM file tell about its sequencing platform
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlatformEvidence {
+    /// the read group (@RG) and program (@PG) lines of the header, in upper case
+    pub header_lines: Vec<String>,
+    /// the number of primary alignments that the statistics are computed from
+    pub n_reads: usize,
+    /// the median read length
+    pub median_read_len: usize,
+    /// the mean base quality of the reads with base qualities, or None if none has them
+    pub mean_base_qual: Option<f64>,
+    /// the edit distance (NM tag) per aligned base, or None if the reads have no NM tags
+    pub error_rate: Option<f64>,
+}
+
+impl PlatformEvidence {
+    /// Reads the header and the first ```DETECTION_READS``` primary alignments of a BAM file
+    pub fn from_bam(bam_file: &str) -> Result<PlatformEvidence> {
+        let mut bam = open_bam(bam_file)?;
+        let header_lines: Vec<String> = u8_to_string(bam.header().as_bytes())?
+            .lines()
+            .filter(|line| line.starts_with("@RG") || line.starts_with("@PG"))
+            .map(|line| line.to_ascii_uppercase())
+            .collect();
+
+        let mut read_lens: Vec<usize> = vec![];
+        let (mut qual_sum, mut qual_bases) = (0.0, 0usize);
+        let (mut edits, mut aligned, mut has_nm) = (0usize, 0usize, false);
+        for r in bam.records() {
+            let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if read_lens.len() >= DETECTION_READS {
+                break;
+            }
+            if record.is_unmapped() || record.is_secondary() || record.is_supplementary() {
+                continue;
+            }
+            read_lens.push(record.seq().len());
+            match record.qual().first() {
+                Some(&255) | None => {}
+                Some(_) => {
+                    qual_sum += record.qual().iter().map(|&q| q as f64).sum::<f64>();
+                    qual_bases += record.qual().len();
+                }
+            }
+            if let Some(bam::record::Aux::Integer(nm)) = record.aux(b"NM") {
+                has_nm = true;
+                edits += nm as usize;
+                for c in record.cigar().iter() {
+                    match c {
+                        &Cigar::Match(l)
+                        | &Cigar::Equal(l)
+                        | &Cigar::Diff(l)
+                        | &Cigar::Ins(l)
+                        | &Cigar::Del(l) => aligned += l as usize,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        read_lens.sort();
+
+        Ok(PlatformEvidence {
+            header_lines: header_lines,
+            n_reads: read_lens.len(),
+            median_read_len: read_lens.get(read_lens.len() / 2).cloned().unwrap_or(0),
+            mean_base_qual: if qual_bases > 0 {
+                Some(qual_sum / qual_bases as f64)
+            } else {
+                None
+            },
+            error_rate: if has_nm && aligned > 0 {
+                Some(edits as f64 / aligned as f64)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Chooses the preset of the sequencing platform of a BAM file
+///
+/// The platform (PacBio or Oxford Nanopore) is taken from the header: the platform tag of the
+/// read groups (PL), the names of PacBio and ONT instruments (PM) and programs (@PG lines), and
+/// the read type of PacBio read groups. The chemistry is taken from the header where it is
+/// recorded (the read type of PacBio reads, and the basecalling model of ONT reads), and from the
+/// error rate and base qualities of the reads otherwise: HiFi reads have less than 2% errors
+/// (or a mean base quality of at least 20), and R10 reads less than 6% (or at least 15). Without
+/// a platform in the header, the reads with less than 2% errors are taken to be HiFi reads, the
+/// reads without base qualities CLR reads, and the others ONT reads.
+///
+/// # Returns
+/// Returns the preset and the reason it was chosen, or None if the reads are of another platform
+/// (e.g. Illumina) or there is too little evidence.
+pub fn detect_preset(evidence: &PlatformEvidence) -> Option<(Preset, String)> {
+    let has = |keys: &[&str]| {
+        evidence
+            .header_lines
+            .iter()
+            .any(|line| keys.iter().any(|key| line.contains(key)))
+    };
+    let pacbio = has(&[
+        "\tPL:PACBIO",
+        "READTYPE=",
+        "\tPN:CCS",
+        "\tPN:PBMM2",
+        "SEQUEL",
+        "REVIO",
+    ]);
+    let ont = has(&[
+        "\tPL:ONT",
+        "\tPN:DORADO",
+        "\tPN:GUPPY",
+        "BASECALL_MODEL",
+        "PROMETHION",
+        "MINION",
+        "GRIDION",
+    ]);
+    let other_platform = has(&["\tPL:"]) && !pacbio && !ont;
+
+    // qualities of 0 (!) are the placeholder qualities of PacBio subreads
+    let quals = evidence.mean_base_qual.filter(|&q| q >= 3.0);
+    let accurate = |error_rate: f64, min_qual: f64| match (evidence.error_rate, quals) {
+        (Some(e), _) => e < error_rate,
+        (None, Some(q)) => q >= min_qual,
+        (None, None) => false,
+    };
+    let stats = match (evidence.error_rate, quals) {
+        (Some(e), Some(q)) => format!("{:.1}% errors, mean base quality {:.1}", 100.0 * e, q),
+        (Some(e), None) => format!("{:.1}% errors, no base qualities", 100.0 * e),
+        (None, Some(q)) => format!("mean base quality {:.1}", q),
+        (None, None) => "no base qualities".to_string(),
+    };
+
+    if other_platform || evidence.n_reads == 0 {
+        return None;
+    }
+    match (pacbio, ont) {
+        (true, false) => Some(if has(&["READTYPE=CCS", "\tPN:CCS"]) {
+            (
+                Preset::PacbioHifi,
+                "PacBio CCS reads in the BAM header".to_string(),
+            )
+        } else if has(&["READTYPE=SUBREAD"]) {
+            (
+                Preset::PacbioClr,
+                "PacBio subreads in the BAM header".to_string(),
+            )
+        } else if accurate(0.02, 20.0) {
+            (Preset::PacbioHifi, format!("PacBio reads with {}", stats))
+        } else {
+            (Preset::PacbioClr, format!("PacBio reads with {}", stats))
+        }),
+        (false, true) => Some(if has(&["_R10."]) {
+            (
+                Preset::OntR10,
+                "ONT R10 basecalling model in the BAM header".to_string(),
+            )
+        } else if has(&["_R9."]) {
+            (
+                Preset::OntR9,
+                "ONT R9 basecalling model in the BAM header".to_string(),
+            )
+        } else if accurate(0.06, 15.0) {
+            (Preset::OntR10, format!("ONT reads with {}", stats))
+        } else {
+            (Preset::OntR9, format!("ONT reads with {}", stats))
+        }),
+        _ => {
+            if evidence.error_rate.is_none() && quals.is_none() {
+                None
+            } else if accurate(0.02, 20.0) {
+                Some((Preset::PacbioHifi, format!("reads with {}", stats)))
+            } else if quals.is_none() {
+                Some((Preset::PacbioClr, format!("reads with {}", stats)))
+            } else if accurate(0.06, 15.0) {
+                Some((Preset::OntR10, format!("reads with {}", stats)))
+            } else {
+                Some((Preset::OntR9, format!("reads with {}", stats)))
+            }
+        }
+    }
+}
+
+/// the value of an option in the command line arguments, given as "--long value",
+/// "--long=value", "-s value" or "-svalue" (the first if the option is given several times)
+fn option_value(args: &[String], long: &str, short: Option<&str>) -> Option<String> {
+    let long_eq = format!("{}=", long);
+    for (i, a) in args.iter().enumerate() {
+        if a == long || short.map_or(false, |s| a == s) {
+            return args.get(i + 1).cloned();
+        } else if a.starts_with(&long_eq) {
+            return Some(a[long_eq.len()..].to_string());
+        } else if let Some(s) = short {
+            if a.starts_with(s) && !a.starts_with("--") {
+                return Some(a[s.len()..].to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Replaces ```--preset auto``` in the command line arguments with the preset of the platform of
+/// the first BAM file (see ```detect_preset```), and logs the decision. If the platform is not
+/// detected, the arguments are returned unchanged and no preset is used.
+pub fn resolve_auto_preset(args: Vec<String>) -> Result<Vec<String>> {
+    let auto_ix = match args.iter().position(|a| a == "--preset=auto") {
+        Some(i) => i,
+        None => match args
+            .windows(2)
+            .position(|w| w[0] == "--preset" && w[1] == "auto")
+        {
+            Some(i) => i + 1,
+            None => {
+                return Ok(args);
+            }
+        },
+    };
+    // without a BAM file, the argument parser reports the missing option
+    let bam_file = match option_value(&args[1..], "--bam", Some("-b")) {
+        Some(b) => b,
+        None => {
+            return Ok(args);
+        }
+    };
+
+    let evidence = PlatformEvidence::from_bam(&bam_file)
+        .chain_err(|| format!("Error detecting the sequencing platform of {}.", bam_file))?;
+    let mut args = args;
+    match detect_preset(&evidence) {
+        Some((preset, reason)) => {
+            info!(
+                "{} Detected the {} preset from {} (override with --preset <name>).",
+                print_time(),
+                preset.name(),
+                reason
+            );
+            args[auto_ix] = if args[auto_ix] == "auto" {
+                preset.name().to_string()
+            } else {
+                format!("--preset={}", preset.name())
+            };
+        }
+        None => {
+            warn!(
+                "{} WARNING: Could not detect the sequencing platform of {}, so no preset is used.",
+                print_time(),
+                bam_file
+            );
+        }
+    }
+    Ok(args)
+}
+
+/// Finds the preset in the command line arguments (```--preset <name>``` or
+/// ```--preset=<name>```), and inserts the options of the preset that are not given on the command
+/// line after the program name, so that they take the place of the default values.
+///
+/// # Returns
+/// Returns the arguments with the options of the preset, and the preset. The arguments are
+/// returned unchanged if there is no (valid) preset, which is reported by the argument parser.
+pub fn apply_preset(args: Vec<String>) -> (Vec<String>, Option<Preset>) {
+    let mut preset: Option<Preset> = None;
+    for (i, a) in args.iter().enumerate() {
+        if a == "--preset" && i + 1 < args.len() {
+            preset = Preset::from_name(&args[i + 1]);
+        } else if a.starts_with("--preset=") {
+            preset = Preset::from_name(&a["--preset=".len()..]);
+        }
+    }
+    let p = match preset {
+        Some(p) => p,
+        None => {
+            return (args, None);
+        }
+    };
+
+    let mut preset_args: Vec<String> = vec![];
+    for (long, short, value) in p.options() {
+        if !option_given(&args[1..], long, short) {
+            preset_args.push(long.to_string());
+            preset_args.push(value.to_string());
+        }
+    }
+    let mut new_args: Vec<String> = args[..1].to_vec();
+    new_args.extend(preset_args);
+    new_args.extend(args[1..].iter().cloned());
+    (new_args, Some(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_preset() {
+        // the band width is given on the command line, so the preset doesn't set it
+        let (new_args, preset) =
+            apply_preset(args("longshot --preset ont-r9 -B 25 --max_window=40"));
+        assert_eq!(preset, Some(Preset::OntR9));
+        assert_eq!(
+            new_args,
+            args(
+                "longshot --max_snvs 3 --candidate_fdr 0.05 --missing_base_qual_value 10 --preset ont-r9 -B 25 --max_window=40"
+            )
+        );
+
+        let (new_args, preset) = apply_preset(args("longshot --preset=pacbio-hifi -m5"));
+        assert_eq!(preset, Some(Preset::PacbioHifi));
+        assert_eq!(
+            new_args,
+            args("longshot --band_width 10 --max_window 30 --candidate_fdr 0.01 --missing_base_qual_value 30 --max_indel_len 20 --preset=pacbio-hifi -m5")
+        );
+
+        // without a valid preset, the arguments are unchanged
+        for a in &["longshot -B 25", "longshot --preset ont"] {
+            assert_eq!(apply_preset(args(a)), (args(a), None));
+        }
+    }
+
+    #[test]
+    fn test_detect_preset() {
+        let evidence =
+            |header: &[&str], error_rate: Option<f64>, qual: Option<f64>| PlatformEvidence {
+                header_lines: header.iter().map(|l| l.to_ascii_uppercase()).collect(),
+                n_reads: 1000,
+                median_read_len: 15000,
+                mean_base_qual: qual,
+                error_rate: error_rate,
+            };
+        let preset = |e: PlatformEvidence| detect_preset(&e).map(|(p, _)| p);
+
+        // the read type and basecalling model in the header decide the chemistry
+        let ccs = "@RG\tID:a\tPL:PACBIO\tDS:READTYPE=CCS;BINDINGKIT=101-894-200\tPM:SEQUELII";
+        let dorado = "@RG\tID:b\tDS:basecall_model=dna_r10.4.1_e8.2_400bps_sup@v4.2.0";
+        assert_eq!(
+            preset(evidence(&[ccs], Some(0.1), None)),
+            Some(Preset::PacbioHifi)
+        );
+        assert_eq!(
+            preset(evidence(&[dorado], Some(0.1), None)),
+            Some(Preset::OntR10)
+        );
+        assert_eq!(
+            preset(evidence(
+                &["@PG\tID:guppy\tPN:guppy\tCL:guppy -c dna_r9.4.1_450bps_hac.cfg"],
+                None,
+                None
+            )),
+            Some(Preset::OntR9)
+        );
+
+        // otherwise the error rate or base qualities of the reads
+        let pacbio = "@RG\tID:a\tPL:PACBIO";
+        let ont = "@RG\tID:a\tPL:ONT";
+        assert_eq!(
+            preset(evidence(&[pacbio], Some(0.005), Some(35.0))),
+            Some(Preset::PacbioHifi)
+        );
+        assert_eq!(
+            preset(evidence(&[pacbio], None, Some(0.0))),
+            Some(Preset::PacbioClr)
+        );
+        assert_eq!(
+            preset(evidence(&[ont], Some(0.03), Some(18.0))),
+            Some(Preset::OntR10)
+        );
+        assert_eq!(
+            preset(evidence(&[ont], None, Some(11.0))),
+            Some(Preset::OntR9)
+        );
+        // "chr10" in a command line is not an R10 basecalling model
+        assert_eq!(
+            preset(evidence(
+                &[ont, "@PG\tID:mm2\tPN:minimap2\tCL:minimap2 chr10.fa"],
+                Some(0.09),
+                None
+            )),
+            Some(Preset::OntR9)
+        );
+
+        // without a platform in the header
+        assert_eq!(
+            preset(evidence(&[], Some(0.004), Some(30.0))),
+            Some(Preset::PacbioHifi)
+        );
+        assert_eq!(
+            preset(evidence(&[], Some(0.12), Some(0.0))),
+            Some(Preset::PacbioClr)
+        );
+        assert_eq!(
+            preset(evidence(&[], Some(0.04), Some(16.0))),
+            Some(Preset::OntR10)
+        );
+        assert_eq!(preset(evidence(&[], None, None)), None);
+
+        // other platforms and empty BAM files
+        assert_eq!(
+            preset(evidence(
+                &["@RG\tID:a\tPL:ILLUMINA"],
+                Some(0.004),
+                Some(35.0)
+            )),
+            None
+        );
+        let mut empty = evidence(&[ccs], None, None);
+        empty.n_reads = 0;
+        assert_eq!(preset(empty), None);
+    }
+
+    #[test]
+    fn test_option_value() {
+        let a = args("longshot -v sites.vcf --bam=x.bam -b y.bam");
+        assert_eq!(
+            option_value(&a[1..], "--bam", Some("-b")),
+            Some("x.bam".to_string())
+        );
+        assert_eq!(
+            option_value(&a[1..], "--potential_variants", Some("-v")),
+            Some("sites.vcf".to_string())
+        );
+        assert_eq!(
+            option_value(&args("longshot -by.bam")[1..], "--bam", Some("-b")),
+            Some("y.bam".to_string())
+        );
+        assert_eq!(option_value(&a[1..], "--ref", Some("-f")), None);
+
+        // without --preset auto, or without a BAM file, the arguments are unchanged
+        for a in &[
+            "longshot --preset ont-r9 -b y.bam",
+            "longshot --preset auto -f ref.fa",
+        ] {
+            assert_eq!(resolve_auto_preset(args(a)).unwrap(), args(a));
+        }
+    }
+
+    #[test]
+    fn test_preset_alignment_parameters() {
+        for name in PRESET_NAMES.iter() {
+            let params = Preset::from_name(name).unwrap().alignment_parameters();
+            let t = params.transition_probs;
+            let e = params.emission_probs;
+            assert!(
+                (t.match_from_match + t.insertion_from_match + t.deletion_from_match - 1.0).abs()
+                    < 1e-9
+            );
+            assert!((e.equal + 3.0 * e.not_equal - 1.0).abs() < 1e-9);
+        }
+    }
+}
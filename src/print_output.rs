@@ -1,21 +1,348 @@
 //! Print Longshot output in VCF format
 
-use bio::io::fasta::IndexedReader;
-use bio::stats::PHREDProb;
+use bio::stats::{LogProb, PHREDProb, Prob};
+use call_genotypes::HapIterationRound;
+use callable_regions::CallableRegions;
 use errors::*;
-use genotype_probs::Genotype;
-use std::fs::File;
+use fishers_exact::fishers_exact;
+use genotype_probs::{Genotype, GenotypeProbs};
+use hashbrown::HashMap;
+use soft_clip_clusters::SoftClipCluster;
+use std::fmt;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::path::Path;
 use util::*; //{MAX_VCF_QUAL, ln_sum_matrix, GenotypePriors, VarList, Fragment, FragCall, GenomicInterval};
-use variants_and_fragments::{var_filter, VarList};
+use variants_and_fragments::{var_filter, Var, VarFilter, VarList};
+use vcf_annotation::{GenotypeConcordance, CONCORDANCE_INFO_ID};
+
+/// formats a genotype for the VCF GT field. Haploid genotypes ```Genotype(a, a)``` are printed
+/// as a single allele, and unphased genotypes (with separator ```/```) with the smaller allele
+/// first, e.g. ```1/2``` at a multiallelic site.
+pub fn format_genotype(genotype: Genotype, ploidy: u8, sep: &str) -> String {
+    if ploidy == 1 {
+        genotype.0.to_string()
+    } else if sep == "/" && genotype.0 > genotype.1 {
+        vec![genotype.1.to_string(), genotype.0.to_string()].join(sep)
+    } else {
+        vec![genotype.0.to_string(), genotype.1.to_string()].join(sep)
+    }
+}
+
+/// the PHRED-scaled likelihoods of the unphased genotypes for the VCF PL field, relative to the
+/// most likely genotype (which has a PL of 0). The genotypes are in the order of the VCF
+/// specification: at diploid sites ```j/k``` (with ```j <= k```) is at index ```k*(k+1)/2 + j```,
+/// and at haploid sites allele ```a``` is at index ```a```. The likelihood of an unphased
+/// heterozygous genotype is the mean of the likelihoods of its two phases.
+fn phred_genotype_likelihoods(likelihoods: &GenotypeProbs, ploidy: u8) -> Vec<u32> {
+    let ln_half = LogProb::from(Prob(0.5));
+    let mut unphased: Vec<LogProb> = vec![];
+    for k in 0..likelihoods.n_alleles() {
+        if ploidy == 1 {
+            unphased.push(likelihoods.tab[k][k]);
+            continue;
+        }
+        for j in 0..k + 1 {
+            if j == k {
+                unphased.push(likelihoods.tab[k][k]);
+            } else {
+                unphased.push(
+                    ln_half + LogProb::ln_add_exp(likelihoods.tab[j][k], likelihoods.tab[k][j]),
+                );
+            }
+        }
+    }
+
+    let max_likelihood = unphased
+        .iter()
+        .fold(LogProb::ln_zero(), |max, &l| if l > max { l } else { max });
+    unphased
+        .iter()
+        .map(|&l| (*PHREDProb::from(l - max_likelihood)).min(MAX_VCF_QUAL).round() as u32)
+        .collect()
+}
+
+/// A single VCF data line, built field by field and formatted with ```Display```.
+///
+/// INFO fields and FORMAT keys are written in the order they are added, so that new annotations
+/// can be added to the output without changing the existing ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcfRecord {
+    pub chrom: String,
+    /// 1-based position
+    pub pos: usize,
+    pub id: String,
+    pub ref_allele: String,
+    pub alt_alleles: Vec<String>,
+    /// written rounded to an integer
+    pub qual: f64,
+    pub filter: String,
+    /// (key, value) pairs of the INFO column, with a value of None for flags
+    pub info: Vec<(String, Option<String>)>,
+    pub format: Vec<String>,
+    /// the values of the FORMAT fields for each sample
+    pub samples: Vec<Vec<String>>,
+}
+
+impl VcfRecord {
+    pub fn new(chrom: &str, pos: usize, ref_allele: &str, alt_alleles: &[String]) -> VcfRecord {
+        VcfRecord {
+            chrom: chrom.to_string(),
+            pos: pos,
+            id: ".".to_string(),
+            ref_allele: ref_allele.to_string(),
+            alt_alleles: alt_alleles.to_vec(),
+            qual: 0.0,
+            filter: ".".to_string(),
+            info: vec![],
+            format: vec![],
+            samples: vec![],
+        }
+    }
+
+    /// adds an INFO field with a value. Floats should be formatted by the caller (e.g.
+    /// ```format!("{:.2}", x)```) to choose the precision.
+    pub fn add_info<T: fmt::Display>(&mut self, key: &str, value: T) {
+        self.info.push((key.to_string(), Some(value.to_string())));
+    }
+
+    /// adds an INFO flag (a field without a value)
+    pub fn add_info_flag(&mut self, key: &str) {
+        self.info.push((key.to_string(), None));
+    }
+
+    /// returns the value of an INFO field, or None if the field is absent or is a flag
+    pub fn info_value(&self, key: &str) -> Option<&str> {
+        self.info
+            .iter()
+            .find(|&&(ref k, _)| k == key)
+            .and_then(|&(_, ref v)| v.as_ref().map(|v| v.as_str()))
+    }
+
+    /// returns true if the record has an INFO field or flag with this key
+    pub fn has_info(&self, key: &str) -> bool {
+        self.info.iter().any(|&(ref k, _)| k == key)
+    }
+
+    pub fn set_format(&mut self, keys: &[&str]) {
+        self.format = keys.iter().map(|k| k.to_string()).collect();
+    }
+
+    /// adds the FORMAT values of the next sample, in the order of the FORMAT keys
+    pub fn add_sample(&mut self, values: Vec<String>) {
+        assert_eq!(values.len(), self.format.len());
+        self.samples.push(values);
+    }
+}
+
+impl fmt::Display for VcfRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{:.0}\t{}\t",
+            self.chrom,
+            self.pos,
+            self.id,
+            self.ref_allele,
+            self.alt_alleles.join(","),
+            self.qual,
+            self.filter
+        )?;
+        if self.info.is_empty() {
+            write!(f, ".")?;
+        }
+        for &(ref key, ref value) in &self.info {
+            match value {
+                &Some(ref v) => write!(f, "{}={};", key, v)?,
+                &None => write!(f, "{};", key)?,
+            }
+        }
+        if !self.format.is_empty() {
+            write!(f, "\t{}", self.format.join(":"))?;
+            for sample in &self.samples {
+                write!(f, "\t{}", sample.join(":"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the neighboring calls of each variant: the positions of the variant calls (sites with a
+/// non-reference genotype) must be given in sorted order as ```(tid, pos0)```.
+///
+/// # Returns
+/// For each variant in ```vars```, the distance to the nearest other call on the same contig (None
+/// if there is none) and the number of other calls within ```window``` bp. A variant that is a call
+/// itself does not count as its own neighbor.
+fn neighbor_calls(
+    vars: &[(u32, usize, bool)],
+    calls: &[(u32, usize)],
+    window: usize,
+) -> Vec<(Option<usize>, usize)> {
+    let mut result = vec![];
+    for &(tid, pos0, is_call) in vars {
+        // the first call at or after the variant
+        let ix = match calls.binary_search_by(|c| {
+            if *c < (tid, pos0) {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        }) {
+            Ok(ix) | Err(ix) => ix,
+        };
+
+        let mut nearest: Option<usize> = None;
+        let mut count = 0;
+        let mut skipped_self = !is_call;
+        for &(c_tid, c_pos0) in calls[ix..].iter() {
+            if c_tid != tid {
+                break;
+            }
+            if !skipped_self && c_pos0 == pos0 {
+                skipped_self = true;
+                continue;
+            }
+            let dist = c_pos0 - pos0;
+            nearest = Some(nearest.map_or(dist, |d| d.min(dist)));
+            if dist > window {
+                break;
+            }
+            count += 1;
+        }
+        for &(c_tid, c_pos0) in calls[..ix].iter().rev() {
+            if c_tid != tid {
+                break;
+            }
+            let dist = pos0 - c_pos0;
+            nearest = Some(nearest.map_or(dist, |d| d.min(dist)));
+            if dist > window {
+                break;
+            }
+            count += 1;
+        }
+        result.push((nearest, count));
+    }
+    result
+}
+
+/// the FORMAT fields written for each sample
+const FORMAT_KEYS: [&str; 12] = [
+    "GT", "GQ", "DP", "AD", "AB", "SB", "PL", "PS", "UG", "UQ", "HDP", "HAD",
+];
+
+/// joins a list of values with commas
+fn join_values<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// adds the INFO fields copied from the annotation VCF (--annotation_vcf) to a record
+fn add_annotation_info(record: &mut VcfRecord, var: &Var) {
+    for &(ref id, ref value) in &var.annotations {
+        match *value {
+            Some(ref v) => record.add_info(id, v),
+            None => record.add_info_flag(id),
+        }
+    }
+}
+
+/// the FORMAT values of a sample's genotype call, in the order of ```FORMAT_KEYS```
+fn format_sample_values(var: &Var) -> Vec<String> {
+    let ps = match var.phase_set {
+        Some(ps) => format!("{}", ps),
+        None => ".".to_string(),
+    };
+    let sep = if var.phase_set.is_some() || var.phased_no_ps {
+        "|"
+    } else {
+        "/"
+    };
+    // the allele balance and strand counts are over all of the alternate alleles
+    let total: u16 = var.allele_counts.iter().sum();
+    let ab = if total > 0 {
+        format!("{:.3}", (total - var.allele_counts[0]) as f64 / total as f64)
+    } else {
+        ".".to_string()
+    };
+    let alt_forward: u32 = var
+        .allele_counts_forward
+        .iter()
+        .skip(1)
+        .map(|&c| c as u32)
+        .sum();
+    let alt_reverse: u32 = var
+        .allele_counts_reverse
+        .iter()
+        .skip(1)
+        .map(|&c| c as u32)
+        .sum();
+    let sb = join_values(&[
+        var.allele_counts_forward[0] as u32,
+        var.allele_counts_reverse[0] as u32,
+        alt_forward,
+        alt_reverse,
+    ]);
+    // the allele counts of haplotype 1 are followed by those of haplotype 2
+    let (hdp, had) = match var.hap_counts {
+        Some(ref counts) => (
+            join_values(&counts.depth),
+            join_values(&[&counts.allele_counts[0][..], &counts.allele_counts[1][..]].concat()),
+        ),
+        None => (".".to_string(), ".".to_string()),
+    };
+    vec![
+        format_genotype(var.genotype, var.ploidy, sep),
+        format!("{:.0}", var.gq + 0.4999), // round off to integer
+        var.dp.to_string(),
+        join_values(&var.allele_counts),
+        ab,
+        sb,
+        join_values(&phred_genotype_likelihoods(&var.genotype_likelihoods, var.ploidy)),
+        ps,
+        format_genotype(var.unphased_genotype, var.ploidy, "/"),
+        format!("{:.2}", var.unphased_gq),
+        hdp,
+        had,
+    ]
+}
+
+/// the PHRED-scaled p-value of Fisher's exact test for strand bias of the reference forward,
+/// reference reverse, alternate forward and alternate reverse counts (the INFO field FS)
+fn strand_bias_qual(counts: &[u32; 4]) -> Result<f64> {
+    let pvalues = fishers_exact(counts)
+        .chain_err(|| "Error calculating Fisher's exact test for strand bias.")?;
+    Ok((*PHREDProb::from(Prob(pvalues.two_tail_pvalue))).min(500.0))
+}
+
+/// reports the variants flagged by the variant density filter, so that it does not filter a
+/// divergent sample silently
+fn print_density_filter_summary(density_params: &DensityParameters, n: usize, n_dense: usize) {
+    if n_dense > 0 {
+        info!(
+            "{} {} variants flagged as part of dense clusters (dn: more than {} other variants within {} bp{}).",
+            print_time(),
+            n_dense,
+            n,
+            density_params.len,
+            if density_params.auto_n && n > density_params.n {
+                ", raised for the variant density of the region"
+            } else {
+                ""
+            }
+        );
+    }
+}
 
 pub fn print_vcf_header<W: Write>(
     mut output_file: W,
     filename: &std::path::Display,
-    sample_name: &str,
+    sample_names: &[String],
     used_potential_variants_vcf: bool,
-    fasta_file: &Option<IndexedReader<File>>,
+    fasta_file: &Option<FastaReader>,
 ) -> Result<()> {
     writeln!(output_file, "##fileformat=VCFv4.2\n##source=Longshot v0.4.2")
         .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
@@ -34,7 +361,31 @@ pub fn print_vcf_header<W: Write>(
 ##INFO=<ID=MF,Number=1,Type=Float,Description=\"Minimum Error Correction (MEC) Fraction for this variant.\">
 ##INFO=<ID=MB,Number=1,Type=Float,Description=\"Minimum Error Correction (MEC) Fraction for this variant's haplotype block.\">
 ##INFO=<ID=AQ,Number=1,Type=Float,Description=\"Mean Allele Quality value (PHRED-scaled).\">
-##INFO=<ID=GM,Number=1,Type=Integer,Description=\"Phased genotype matches unphased genotype (boolean).\">";
+##INFO=<ID=SCF,Number=1,Type=Float,Description=\"Fraction of overlapping reads that are soft-clipped near the variant.\">
+##INFO=<ID=FS,Number=1,Type=Float,Description=\"PHRED-scaled p-value of Fisher's exact test for strand bias of the reference and alternate allele observations.\">
+##INFO=<ID=GM,Number=1,Type=Integer,Description=\"Phased genotype matches unphased genotype (boolean).\">
+##INFO=<ID=PHQ,Number=1,Type=Float,Description=\"PHRED-scaled probability that the alleles of a phased variant do NOT segregate with the haplotypes of its phase block.\">
+##INFO=<ID=MQM,Number=A,Type=Float,Description=\"Median mapping quality of the reads supporting each alternate allele.\">
+##INFO=<ID=MQMR,Number=1,Type=Float,Description=\"Median mapping quality of the reads supporting the reference allele.\">
+##INFO=<ID=MQMAD,Number=A,Type=Float,Description=\"Median absolute deviation of the mapping quality of the reads supporting each alternate allele.\">
+##INFO=<ID=MQMADR,Number=1,Type=Float,Description=\"Median absolute deviation of the mapping quality of the reads supporting the reference allele.\">
+##INFO=<ID=MQ,Number=1,Type=Float,Description=\"Mean mapping quality of the reads with an unambiguous allele call.\">
+##INFO=<ID=BQ,Number=R,Type=Float,Description=\"Mean PHRED-scaled allele quality (from realignment) of the unambiguous calls of each allele.\">
+##INFO=<ID=RLR,Number=A,Type=Float,Description=\"Log10 realignment likelihood ratio of each alternate allele versus the reference allele, summed over the reads.\">
+##INFO=<ID=VAF,Number=1,Type=Float,Description=\"Alternate allele fraction, estimated from the per-read allele posteriors.\">
+##INFO=<ID=VAFCI,Number=2,Type=Float,Description=\"Equal-tailed 95% credible interval of the alternate allele fraction, from the Beta posterior of the per-read allele posteriors.\">
+##INFO=<ID=CID,Number=1,Type=Integer,Description=\"Realignment cluster ID: the position of the first variant of the cluster of variants that were realigned together with this one.\">
+##INFO=<ID=CQ,Number=1,Type=Float,Description=\"PHRED-scaled upper bound on the probability that any of the variant calls in the realignment cluster is wrong.\">
+##INFO=<ID=CSPLIT,Number=0,Type=Flag,Description=\"The realignment cluster of this variant exceeded the haplotype enumeration budget and was split next to it in some reads.\">
+##INFO=<ID=RSKIP,Number=1,Type=Integer,Description=\"Number of overlapping reads that were not realigned to the variant because its depth exceeded --max_realign_depth.\">
+##INFO=<ID=MNV,Number=0,Type=Flag,Description=\"MNV or complex record merged from phased variants that are adjacent on the same haplotype (--merge_mnvs).\">
+##INFO=<ID=MID,Number=1,Type=Integer,Description=\"Position of the merged MNV record that this variant is part of (--keep_mnv_components).\">
+##INFO=<ID=ND,Number=1,Type=Integer,Description=\"Distance to the nearest other variant call (non-reference genotype) on the same contig.\">
+##INFO=<ID=NW,Number=1,Type=Integer,Description=\"Number of other variant calls within the window set by --neighbor_window.\">
+##INFO=<ID=TR,Number=2,Type=Integer,Description=\"First and last position of the annotated tandem repeat (--tandem_repeats) that the variant overlaps.\">
+##INFO=<ID=MOSAIC,Number=0,Type=Flag,Description=\"Mosaic call: the variant is present at an allele fraction below that of a heterozygous variant (--mosaic).\">
+##INFO=<ID=MOSQ,Number=1,Type=Float,Description=\"PHRED-scaled probability that the variant is NOT mosaic (maximum over samples).\">
+##INFO=<ID=MOSVAF,Number=1,Type=Float,Description=\"Most likely allele fraction of the variant if it is mosaic.\">";
     writeln!(output_file, "{}", headerstr1)
         .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
 
@@ -49,24 +400,102 @@ pub fn print_vcf_header<W: Write>(
         writeln!(output_file, "{}", headerstr2)
             .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
     }
+    // de novo and somatic annotations are only possible with multiple samples (trios from a
+    // pedigree, or a tumor/normal pair)
+    if sample_names.len() > 1 {
+        let headerstr_de_novo = &"##INFO=<ID=DN,Number=0,Type=Flag,Description=\"De novo candidate: a trio child likely carries an allele not present in either parent.\">
+##INFO=<ID=DQ,Number=1,Type=Float,Description=\"PHRED-scaled probability that the trio child's genotype is NOT de novo (maximum over trios).\">
+##INFO=<ID=SOMATIC,Number=0,Type=Flag,Description=\"Somatic candidate: the variant is more likely somatic than germline in the tumor/normal pair.\">
+##INFO=<ID=SQ,Number=1,Type=Float,Description=\"PHRED-scaled probability that the tumor variant is NOT somatic.\">
+##INFO=<ID=SVAF,Number=1,Type=Float,Description=\"Most likely tumor allele fraction of the variant if it is somatic.\">";
+        writeln!(output_file, "{}", headerstr_de_novo)
+            .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
+    }
     // last part of the header
     let headerstr3 = format!("##INFO=<ID=PH,Number=G,Type=Float,Description=\"PHRED-scaled Probabilities of Phased Genotypes\">
 ##INFO=<ID=SC,Number=1,Type=String,Description=\"Reference Sequence in 21-bp window around variant.\">
 ##FILTER=<ID=dn,Description=\"In a dense cluster of variants\">
 ##FILTER=<ID=dp,Description=\"Exceeds maximum depth\">
 ##FILTER=<ID=sb,Description=\"Allelic strand bias\">
+##FILTER=<ID=ldp,Description=\"Below minimum depth\">
+##FILTER=<ID=ab,Description=\"Heterozygous allele balance out of range\">
+##FILTER=<ID=hp,Description=\"Next to a homopolymer run on the reference\">
+##FILTER=<ID=sc,Description=\"SNV in a cluster of soft-clipped reads (putative structural variant or misassembly)\">
+##FILTER=<ID=tr,Description=\"Low-quality call in an annotated tandem repeat\">
+##FILTER=<ID=rc,Description=\"Homozygous reference call at an evaluated candidate site (--emit-all)\">
 ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
-##FORMAT=<ID=GQ,Number=1,Type=Integer,Description=\"Genotype Quality\">
+##FORMAT=<ID=GQ,Number=1,Type=Integer,Description=\"Genotype Quality: PHRED-scaled probability that the genotype call is wrong, ignoring phase\">
 ##FORMAT=<ID=PS,Number=1,Type=Integer,Description=\"Phase Set\">
 ##FORMAT=<ID=UG,Number=1,Type=String,Description=\"Unphased Genotype (pre-haplotype-assembly)\">
 ##FORMAT=<ID=UQ,Number=1,Type=Float,Description=\"Unphased Genotype Quality (pre-haplotype-assembly)\">
 ##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read depth\">
-#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", sample_name);
+##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allelic depths: number of reads with an unambiguous call of each allele\">
+##FORMAT=<ID=AB,Number=1,Type=Float,Description=\"Allele balance: fraction of the reads with an unambiguous allele call that support an alternate allele\">
+##FORMAT=<ID=PL,Number=G,Type=Integer,Description=\"PHRED-scaled genotype likelihoods of the unphased genotypes, relative to the most likely genotype\">
+##FORMAT=<ID=SB,Number=4,Type=Integer,Description=\"Strand counts of the reads with an unambiguous allele call: reference forward, reference reverse, alternate forward, alternate reverse\">
+##FORMAT=<ID=HDP,Number=2,Type=Integer,Description=\"Haplotype read depths: number of reads with an allele call assigned to haplotype 1 and haplotype 2 of a phased variant\">
+##FORMAT=<ID=HAD,Number=.,Type=Integer,Description=\"Haplotype allelic depths: number of reads assigned to haplotype 1 with an unambiguous call of each allele, followed by the same for haplotype 2\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", sample_names.join("\t"));
     writeln!(output_file, "{}", headerstr3)
         .chain_err(|| ErrorKind::FileWriteError(filename.to_string()))?;
     Ok(())
 }
 
+/// Returns the IDs of the INFO fields of the output VCF header
+pub fn output_info_ids(sample_names: &[String]) -> Result<Vec<String>> {
+    let mut header: Vec<u8> = vec![];
+    print_vcf_header(
+        &mut header,
+        &Path::new("").display(),
+        sample_names,
+        false,
+        &None,
+    )?;
+    Ok(u8_to_string(&header)?
+        .lines()
+        .filter(|line| line.starts_with("##INFO=<ID="))
+        .map(|line| line["##INFO=<ID=".len()..].split(',').next().unwrap().to_string())
+        .collect())
+}
+
+/// Adds lines to the header of a VCF file that is already written, before the ```#CHROM``` line.
+/// The lines are given without the leading ```##```. The file is rewritten in place.
+pub fn add_vcf_header_lines(vcf_file: &str, lines: &[String]) -> Result<()> {
+    let mut contents = String::new();
+    File::open(vcf_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| format!("Could not read the VCF {}.", vcf_file))?;
+    let chrom_line = match contents.find("\n#CHROM") {
+        Some(i) => i + 1,
+        None => bail!("The VCF {} has no #CHROM header line.", vcf_file),
+    };
+    let mut out = File::create(vcf_file)
+        .chain_err(|| ErrorKind::CreateFileError(vcf_file.to_string()))?;
+    out.write_all(contents[..chrom_line].as_bytes())
+        .chain_err(|| ErrorKind::FileWriteError(vcf_file.to_string()))?;
+    for line in lines {
+        writeln!(out, "##{}", line)
+            .chain_err(|| ErrorKind::FileWriteError(vcf_file.to_string()))?;
+    }
+    out.write_all(contents[chrom_line..].as_bytes())
+        .chain_err(|| ErrorKind::FileWriteError(vcf_file.to_string()))?;
+    Ok(())
+}
+
+/// Creates the output VCF file, or opens it to append the records of another region (without
+/// printing the header again) if ```append``` is true.
+fn open_vcf_file(vcf_path: &Path, append: bool) -> Result<File> {
+    if append {
+        OpenOptions::new()
+            .append(true)
+            .open(vcf_path)
+            .chain_err(|| ErrorKind::FileWriteError(vcf_path.display().to_string()))
+    } else {
+        File::create(vcf_path)
+            .chain_err(|| ErrorKind::CreateFileError(vcf_path.display().to_string()))
+    }
+}
+
 pub fn print_vcf(
     varlist: &mut VarList,
     interval: &Option<GenomicInterval>,
@@ -77,36 +506,55 @@ pub fn print_vcf(
     density_params: &DensityParameters,
     sample_name: &String,
     print_outside_region: bool,
-    used_potential_variants_vcf: bool
+    used_potential_variants_vcf: bool,
+    contig_params: &Vec<ContigParams>,
+    append: bool,
 ) -> Result<()> {
     // first, add filter flags for variant density
-    var_filter(
-        varlist,
-        density_params.gq,
-        density_params.len,
-        density_params.n,
-        max_cov,
+    let (density_count, n_dense) = var_filter(varlist, density_params, max_cov, contig_params);
+    print_density_filter_summary(density_params, density_count, n_dense);
+
+    let calls: Vec<(u32, usize)> = varlist
+        .lst
+        .iter()
+        .filter(|var| var.genotype != Genotype(0, 0))
+        .map(|var| (var.tid, var.pos0))
+        .collect();
+    let neighbors = neighbor_calls(
+        &varlist
+            .lst
+            .iter()
+            .map(|var| (var.tid, var.pos0, var.genotype != Genotype(0, 0)))
+            .collect::<Vec<_>>(),
+        &calls,
+        density_params.neighbor_window,
     );
 
-    let mut fasta = match fasta_file {
-        &Some(ref ff) => Some(
-            IndexedReader::from_file(&ff).chain_err(|| ErrorKind::IndexedFastaOpenError)?,
-        ),
+    let fasta = match fasta_file {
+        &Some(ref ff) => Some(open_fasta(ff)?),
+        None => None,
+    };
+    let mut ref_reader = match fasta_file {
+        &Some(ref ff) => Some(RefSeqReader::open(ff)?),
         None => None,
     };
 
-    let mut ref_seq: Vec<char> = vec![]; // this vector will be used to hold the reference sequence
-    let mut prev_tid = 4294967295;
 
     let vcf_path = Path::new(output_vcf_file);
     let vcf_display = vcf_path.display();
-    // Open a file in write-only mode, returns `io::Result<File>`
-    let mut file = File::create(&vcf_path)
-        .chain_err(|| ErrorKind::CreateFileError(vcf_display.to_string()))?;
+    let mut file = open_vcf_file(vcf_path, append)?;
 
-    print_vcf_header(&mut file, &vcf_display, sample_name, used_potential_variants_vcf, &fasta)?;
+    if !append {
+        print_vcf_header(
+            &mut file,
+            &vcf_display,
+            &[sample_name.clone()],
+            used_potential_variants_vcf,
+            &fasta,
+        )?;
+    }
 
-    for var in &varlist.lst {
+    for (var, &(nearest, n_window)) in varlist.lst.iter().zip(neighbors.iter()) {
         assert!(var.alleles.len() >= 2);
         assert!(var.allele_counts.len() == var.alleles.len());
         assert!(var.genotype_post.n_alleles() == var.alleles.len());
@@ -130,28 +578,6 @@ pub fn print_vcf(
             }
         }
 
-        match fasta {
-            Some(ref mut fa) => {
-                // if we're on a different contig/chrom, we need to read in the sequence for that
-                // contig/chrom from the FASTA into the ref_seq vector
-                if var.tid != prev_tid {
-                    let mut ref_seq_u8: Vec<u8> = vec![];
-                    fa.fetch_all(&varlist.target_names[var.tid as usize])
-                        .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                    fa.read(&mut ref_seq_u8)
-                        .chain_err(|| ErrorKind::IndexedFastaReadError)?;
-                    ref_seq = dna_vec(&ref_seq_u8);
-                }
-                prev_tid = var.tid;
-            }
-            None => {}
-        }
-
-        let ps = match var.phase_set {
-            Some(ps) => format!("{}", ps),
-            None => ".".to_string(),
-        };
-
         let allele_counts_str = var
             .allele_counts
             .clone()
@@ -170,25 +596,6 @@ pub fn print_vcf(
 
         let post_str = post_vec.join(",");
 
-        let mut var_alleles: Vec<String> = vec![];
-        for allele in var.alleles[1..].iter() {
-            var_alleles.push(allele.clone());
-        }
-
-        assert!(var_alleles.len() == var.alleles.len() - 1);
-
-        let sep = match var.phase_set {
-            Some(_) => "|",
-            None => "/",
-        };
-
-        let genotype_str = vec![var.genotype.0.to_string(), var.genotype.1.to_string()].join(sep);
-        let unphased_genotype_str = vec![
-            var.unphased_genotype.0.to_string(),
-            var.unphased_genotype.1.to_string(),
-        ]
-        .join("/");
-
         let genotypes_match: usize = (var.genotype == var.unphased_genotype
             || Genotype(var.genotype.1, var.genotype.0) == var.unphased_genotype)
             as usize;
@@ -196,8 +603,8 @@ pub fn print_vcf(
         // we want to save the sequence context (21 bp window around variant on reference)
         // this will be printed to the VCF later and may help diagnose variant calling
         // issues e.g. if the variant occurs inside a large homopolymer or etc.
-        let sequence_context: String = match fasta {
-            Some(_) => {
+        let sequence_context: String = match ref_reader {
+            Some(ref mut rr) => {
                 // get the position 10 bases to the left
                 let l_window = if var.pos0 >= 10 {
                     var.pos0 as usize - 10
@@ -205,54 +612,455 @@ pub fn print_vcf(
                     0
                 };
                 // get the position 11 bases to the right
-                let mut r_window = var.pos0 as usize + 11;
-                if r_window >= ref_seq.len() {
-                    r_window = ref_seq.len();
+                let chrom = &varlist.target_names[var.tid as usize];
+                let r_window = (var.pos0 as usize + 11).min(rr.contig_len(chrom)?);
+
+                (rr.window(chrom, l_window, r_window)?[l_window..r_window])
+                    .iter()
+                    .collect::<String>()
+            }
+            None => "None".to_string(),
+        };
+
+        let mut record = VcfRecord::new(
+            &varlist.target_names[var.tid as usize],
+            var.pos0 + 1,
+            &var.alleles[0],
+            &var.alleles[1..],
+        );
+        record.qual = var.qual + 0.4999; // round off to integer, 09/04/2020
+        record.filter = var.filter.to_string();
+        record.add_info("DP", var.dp);
+        record.add_info("AC", allele_counts_str);
+        record.add_info("AM", var.ambiguous_count);
+        record.add_info("MC", var.mec);
+        record.add_info("MF", format!("{:.3}", var.mec_frac_variant));
+        record.add_info("MB", format!("{:.3}", var.mec_frac_block));
+        record.add_info("AQ", format!("{:.2}", var.mean_allele_qual));
+        record.add_info("SCF", format!("{:.3}", var.soft_clip_frac));
+        record.add_info("FS", format!("{:.2}", var.strand_bias_pvalue));
+        record.add_info("GM", genotypes_match);
+        if let Some(phq) = var.phase_qual {
+            record.add_info("PHQ", format!("{:.2}", phq));
+        }
+        if var.mapq_median.len() == var.alleles.len() {
+            let format_alts = |values: &Vec<f64>| {
+                values[1..]
+                    .iter()
+                    .map(|v| format!("{:.1}", v))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            };
+            record.add_info("MQM", format_alts(&var.mapq_median));
+            record.add_info("MQMR", format!("{:.1}", var.mapq_median[0]));
+            record.add_info("MQMAD", format_alts(&var.mapq_mad));
+            record.add_info("MQMADR", format!("{:.1}", var.mapq_mad[0]));
+        }
+        if var.mapq_mean.len() == var.alleles.len() {
+            let total: u16 = var.allele_counts.iter().sum();
+            if total > 0 {
+                let mapq_sum: f64 = var
+                    .mapq_mean
+                    .iter()
+                    .zip(var.allele_counts.iter())
+                    .map(|(&mq, &count)| mq * count as f64)
+                    .sum();
+                record.add_info("MQ", format!("{:.1}", mapq_sum / total as f64));
+            }
+            let format_values = |values: &[f64]| {
+                values
+                    .iter()
+                    .map(|v| format!("{:.2}", v))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            };
+            record.add_info("BQ", format_values(&var.allele_qual_mean));
+            record.add_info("RLR", format_values(&var.realignment_llr[1..]));
+            record.add_info("VAF", format!("{:.4}", var.alt_frac));
+            record.add_info(
+                "VAFCI",
+                format!("{:.4},{:.4}", var.alt_frac_ci.0, var.alt_frac_ci.1),
+            );
+        }
+        if let Some(cid) = var.cluster_id {
+            record.add_info("CID", cid);
+            if let Some(cq) = var.cluster_qual {
+                record.add_info("CQ", format!("{:.2}", cq));
+            }
+        }
+        if var.cluster_split {
+            record.add_info_flag("CSPLIT");
+        }
+        if var.realign_skipped > 0 {
+            record.add_info("RSKIP", var.realign_skipped);
+        }
+        if var.merged_mnv {
+            record.add_info_flag("MNV");
+        }
+        if let Some(mid) = var.mnv_id {
+            record.add_info("MID", mid);
+        }
+        if let Some((start, end)) = var.tandem_repeat {
+            record.add_info("TR", format!("{},{}", start + 1, end + 1));
+        }
+        if let (Some(mq), Some(mvaf)) = (var.mosaic_qual, var.mosaic_vaf) {
+            if var.genotype != Genotype(0, 0) {
+                record.add_info_flag("MOSAIC");
+            }
+            record.add_info("MOSQ", format!("{:.2}", mq));
+            record.add_info("MOSVAF", format!("{:.3}", mvaf));
+        }
+        add_annotation_info(&mut record, var);
+        if let Some(gc) = var.genotype_concordance {
+            record.add_info(CONCORDANCE_INFO_ID, gc.name());
+        }
+        if let Some(nd) = nearest {
+            record.add_info("ND", nd);
+        }
+        record.add_info("NW", n_window);
+        if !used_potential_variants_vcf {
+            record.add_info("DA", var.dp_any_mq);
+            record.add_info("MQ10", format!("{:.2}", var.mq10_frac));
+            record.add_info("MQ20", format!("{:.2}", var.mq20_frac));
+            record.add_info("MQ30", format!("{:.2}", var.mq30_frac));
+            record.add_info("MQ40", format!("{:.2}", var.mq40_frac));
+            record.add_info("MQ50", format!("{:.2}", var.mq50_frac));
+        }
+        record.add_info("PH", post_str);
+        record.add_info("SC", sequence_context);
+        record.set_format(&FORMAT_KEYS);
+        record.add_sample(format_sample_values(var));
+
+        writeln!(file, "{}", record)
+            .chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Prints a multi-sample VCF from the per-sample variant lists of a run with several samples
+///
+/// The candidate sites are shared, but every sample is genotyped and phased separately (there is
+/// no joint genotyping), so the variant lists must be in the same order and contain the same sites.
+/// The site QUAL is the PHRED-scaled probability that every sample is homozygous reference,
+/// the site FILTER is the union of the per-sample filters, and the site INFO fields of the read
+/// observations are pooled over samples: the counts are summed, the mean qualities are weighted by
+/// the allele counts (or the depth), and the strand bias test (FS) uses the summed strand counts.
+/// The INFO fields of a sample's genotype and phasing (e.g. MF, PHQ, PH and VAF) are not written,
+/// and per-sample genotype information is written in the sample columns.
+///
+/// # Arguments
+/// - ```varlists```: the variant lists for each sample, in the same order as ```sample_names```
+/// - ```interval```: the (optional) GenomicInterval within which variants were called
+/// - ```fasta_file```: optional FASTA file name, used to write the sequence context and contigs
+/// - ```output_vcf_file```: the output VCF file name
+/// - ```print_reference_genotype```: print sites where every sample is homozygous reference
+/// - ```max_cov```, ```density_params```: parameters for the variant filters
+/// - ```sample_names```: the sample names to write in the VCF header
+/// - ```used_potential_variants_vcf```: whether the candidate sites were read from an input VCF
+/// - ```contig_params```: per-contig parameter overrides (for the coverage filter)
+/// - ```append```: append the records to an existing VCF file without printing the header
+///
+/// # Errors
+/// - ```IndexedFastaOpenError```, ```IndexedFastaReadError```: errors reading the FASTA file
+/// - ```CreateFileError```, ```FileWriteError```: errors writing the output VCF
+pub fn print_vcf_multisample(
+    varlists: &mut Vec<VarList>,
+    interval: &Option<GenomicInterval>,
+    fasta_file: &Option<String>,
+    output_vcf_file: &String,
+    print_reference_genotype: bool,
+    max_cov: u32,
+    density_params: &DensityParameters,
+    sample_names: &Vec<String>,
+    used_potential_variants_vcf: bool,
+    contig_params: &Vec<ContigParams>,
+    append: bool,
+) -> Result<()> {
+    ensure!(
+        varlists.len() > 0 && varlists.len() == sample_names.len(),
+        "Number of variant lists must match the number of samples."
+    );
+
+    for varlist in varlists.iter_mut() {
+        let (density_count, n_dense) = var_filter(varlist, density_params, max_cov, contig_params);
+        print_density_filter_summary(density_params, density_count, n_dense);
+    }
+
+    let fasta = match fasta_file {
+        &Some(ref ff) => Some(open_fasta(ff)?),
+        None => None,
+    };
+    let mut ref_reader = match fasta_file {
+        &Some(ref ff) => Some(RefSeqReader::open(ff)?),
+        None => None,
+    };
+
+
+    let vcf_path = Path::new(output_vcf_file);
+    let vcf_display = vcf_path.display();
+    let mut file = open_vcf_file(vcf_path, append)?;
+
+    if !append {
+        print_vcf_header(
+            &mut file,
+            &vcf_display,
+            sample_names,
+            used_potential_variants_vcf,
+            &fasta,
+        )?;
+    }
+
+    let n_var = varlists[0].lst.len();
+    for varlist in varlists.iter() {
+        ensure!(
+            varlist.lst.len() == n_var,
+            "Per-sample variant lists must contain the same sites."
+        );
+    }
+
+    // a site is a call if any sample has a non-reference genotype
+    let sites: Vec<(u32, usize, bool)> = (0..n_var)
+        .map(|i| {
+            let site = &varlists[0].lst[i];
+            let is_call = varlists
+                .iter()
+                .any(|varlist| varlist.lst[i].genotype != Genotype(0, 0));
+            (site.tid, site.pos0, is_call)
+        })
+        .collect();
+    let calls: Vec<(u32, usize)> = sites
+        .iter()
+        .filter(|&&(_, _, is_call)| is_call)
+        .map(|&(tid, pos0, _)| (tid, pos0))
+        .collect();
+    let neighbors = neighbor_calls(&sites, &calls, density_params.neighbor_window);
+
+    for i in 0..n_var {
+        let site = &varlists[0].lst[i];
+
+        match interval {
+            &Some(ref iv) => {
+                if site.tid != iv.tid
+                    || site.pos0 < iv.start_pos as usize
+                    || site.pos0 > iv.end_pos as usize
+                {
+                    continue;
                 }
+            }
+            &None => {}
+        }
 
-                (ref_seq[l_window..r_window]).iter().collect::<String>()
+        let mut any_nonref = false;
+        let mut qual: f64 = 0.0;
+        let mut filter = site.filter;
+        let mut dp: usize = 0;
+        let mut ambiguous_count: usize = 0;
+        let mut allele_counts: Vec<usize> = vec![0; site.alleles.len()];
+        // reference forward, reference reverse, alternate forward and alternate reverse counts
+        let mut strand_counts: [u32; 4] = [0; 4];
+        let mut mec: usize = 0;
+        let mut allele_qual_sum: f64 = 0.0;
+        let mut soft_clip_sum: f64 = 0.0;
+        let mut realign_skipped: usize = 0;
+        // the sums of the per-allele mean MAPQ and allele quality, weighted by the allele counts,
+        // and of the realignment likelihood ratios
+        let mut read_stats = true;
+        let mut mapq_sums: Vec<f64> = vec![0.0; site.alleles.len()];
+        let mut bq_sums: Vec<f64> = vec![0.0; site.alleles.len()];
+        let mut rlr_sums: Vec<f64> = vec![0.0; site.alleles.len()];
+        let mut dp_any_mq: usize = 0;
+        let mut mq_frac_sums: [f64; 5] = [0.0; 5];
+        let mut de_novo_qual: Option<f64> = None;
+        let mut somatic: Option<(f64, f64)> = None;
+        // the mosaic quality, allele fraction and call of the sample with the highest quality
+        let mut mosaic: Option<(f64, f64, bool)> = None;
+        let mut concordance: Vec<Option<GenotypeConcordance>> = vec![];
+        let mut sample_values: Vec<Vec<String>> = vec![];
+
+        for varlist in varlists.iter() {
+            let var = &varlist.lst[i];
+            assert!(var.tid == site.tid && var.pos0 == site.pos0);
+            assert!(var.alleles == site.alleles);
+
+            if var.genotype != Genotype(0, 0) {
+                any_nonref = true;
+            }
+            qual += var.qual;
+            filter.add_filter(var.filter);
+            dp += var.dp;
+            ambiguous_count += var.ambiguous_count as usize;
+            for (a, c) in var.allele_counts.iter().enumerate() {
+                allele_counts[a] += *c as usize;
+            }
+            strand_counts[0] += var.allele_counts_forward[0] as u32;
+            strand_counts[1] += var.allele_counts_reverse[0] as u32;
+            for a in 1..var.alleles.len() {
+                strand_counts[2] += var.allele_counts_forward[a] as u32;
+                strand_counts[3] += var.allele_counts_reverse[a] as u32;
+            }
+            mec += var.mec;
+            let n_calls: usize = var.allele_counts.iter().map(|&c| c as usize).sum();
+            allele_qual_sum += var.mean_allele_qual * n_calls as f64;
+            soft_clip_sum += var.soft_clip_frac * var.dp as f64;
+            realign_skipped += var.realign_skipped;
+            if var.mapq_mean.len() == var.alleles.len() {
+                for a in 0..var.alleles.len() {
+                    let count = var.allele_counts[a] as f64;
+                    mapq_sums[a] += var.mapq_mean[a] * count;
+                    bq_sums[a] += var.allele_qual_mean[a] * count;
+                    rlr_sums[a] += var.realignment_llr[a];
+                }
+            } else {
+                read_stats = false;
+            }
+            dp_any_mq += var.dp_any_mq;
+            for (k, &frac) in [
+                var.mq10_frac,
+                var.mq20_frac,
+                var.mq30_frac,
+                var.mq40_frac,
+                var.mq50_frac,
+            ]
+            .iter()
+            .enumerate()
+            {
+                mq_frac_sums[k] += frac * var.dp_any_mq as f64;
+            }
+            if let Some(q) = var.de_novo_qual {
+                de_novo_qual = Some(de_novo_qual.map_or(q, |dq| dq.max(q)));
+            }
+            if let (Some(sq), Some(svaf)) = (var.somatic_qual, var.somatic_vaf) {
+                somatic = Some((sq, svaf));
+            }
+            concordance.push(var.genotype_concordance);
+            if let (Some(mq), Some(mvaf)) = (var.mosaic_qual, var.mosaic_vaf) {
+                if mosaic.map_or(true, |(q, _, _)| mq > q) {
+                    mosaic = Some((mq, mvaf, var.genotype != Genotype(0, 0)));
+                }
+            }
+
+            sample_values.push(format_sample_values(var));
+        }
+
+        if !print_reference_genotype && !any_nonref {
+            continue;
+        }
+        // the site is a call if any of the samples has a non-reference genotype
+        if any_nonref {
+            filter.remove_filter(VarFilter::RefCall);
+        }
+
+        if qual > MAX_VCF_QUAL {
+            qual = MAX_VCF_QUAL;
+        }
+
+        let sequence_context: String = match ref_reader {
+            Some(ref mut rr) => {
+                let l_window = if site.pos0 >= 10 { site.pos0 - 10 } else { 0 };
+                let chrom = &varlists[0].target_names[site.tid as usize];
+                let r_window = (site.pos0 + 11).min(rr.contig_len(chrom)?);
+                (rr.window(chrom, l_window, r_window)?[l_window..r_window])
+                    .iter()
+                    .collect::<String>()
             }
             None => "None".to_string(),
         };
 
-        write!(file,
-                       "{}\t{}\t.\t{}\t{}\t{:.0}\t{}\tDP={};AC={};AM={};MC={};MF={:.3};MB={:.3};AQ={:.2};GM={};",
-                       varlist.target_names[var.tid as usize],
-                       var.pos0 + 1,
-                       var.alleles[0],
-                       var_alleles.join(","),
-                       var.qual+0.4999, // round off to integer, 09/04/2020
-                       var.filter,
-                       var.dp,
-                       allele_counts_str,
-                       var.ambiguous_count,
-                       var.mec,
-                       var.mec_frac_variant,
-                       var.mec_frac_block,
-                       var.mean_allele_qual,
-                       genotypes_match).chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
+        let allele_counts_str = allele_counts
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
 
+        let mut record = VcfRecord::new(
+            &varlists[0].target_names[site.tid as usize],
+            site.pos0 + 1,
+            &site.alleles[0],
+            &site.alleles[1..],
+        );
+        record.qual = qual + 0.4999; // round off to integer
+        record.filter = filter.to_string();
+        record.add_info("DP", dp);
+        record.add_info("AC", allele_counts_str);
+        record.add_info("AM", ambiguous_count);
+        record.add_info("MC", mec);
+        let n_calls: usize = allele_counts.iter().sum();
+        let weighted_mean = |sum: f64, n: usize| if n > 0 { sum / n as f64 } else { 0.0 };
+        let aq = weighted_mean(allele_qual_sum, n_calls);
+        record.add_info("AQ", format!("{:.2}", aq));
+        record.add_info("SCF", format!("{:.3}", weighted_mean(soft_clip_sum, dp)));
+        record.add_info("FS", format!("{:.2}", strand_bias_qual(&strand_counts)?));
+        if read_stats {
+            if n_calls > 0 {
+                let mapq_sum: f64 = mapq_sums.iter().sum();
+                record.add_info("MQ", format!("{:.1}", mapq_sum / n_calls as f64));
+            }
+            let bq: Vec<String> = bq_sums
+                .iter()
+                .zip(allele_counts.iter())
+                .map(|(&sum, &count)| format!("{:.2}", weighted_mean(sum, count)))
+                .collect();
+            record.add_info("BQ", bq.join(","));
+            let rlr: Vec<String> = rlr_sums[1..].iter().map(|v| format!("{:.2}", v)).collect();
+            record.add_info("RLR", rlr.join(","));
+        }
+        if realign_skipped > 0 {
+            record.add_info("RSKIP", realign_skipped);
+        }
+        if let Some((start, end)) = site.tandem_repeat {
+            record.add_info("TR", format!("{},{}", start + 1, end + 1));
+        }
+        // a de novo candidate is flagged if it is more likely than not (DQ > PHRED(0.5))
+        if let Some(dq) = de_novo_qual {
+            if dq > *PHREDProb::from(Prob(0.5)) {
+                record.add_info_flag("DN");
+            }
+            record.add_info("DQ", format!("{:.2}", dq));
+        }
+        if let Some((sq, svaf)) = somatic {
+            if sq > *PHREDProb::from(Prob(0.5)) {
+                record.add_info_flag("SOMATIC");
+            }
+            record.add_info("SQ", format!("{:.2}", sq));
+            record.add_info("SVAF", format!("{:.3}", svaf));
+        }
+        if let Some((mq, mvaf, called)) = mosaic {
+            if called {
+                record.add_info_flag("MOSAIC");
+            }
+            record.add_info("MOSQ", format!("{:.2}", mq));
+            record.add_info("MOSVAF", format!("{:.3}", mvaf));
+        }
+        // the annotations are the same for every sample, and the concordance is per sample
+        add_annotation_info(&mut record, site);
+        if concordance.iter().any(|gc| gc.is_some()) {
+            let values: Vec<&str> = concordance
+                .iter()
+                .map(|gc| gc.map_or(".", |gc| gc.name()))
+                .collect();
+            record.add_info(CONCORDANCE_INFO_ID, values.join(","));
+        }
+        if let Some(nd) = neighbors[i].0 {
+            record.add_info("ND", nd);
+        }
+        record.add_info("NW", neighbors[i].1);
         if !used_potential_variants_vcf {
-            write!(file,
-                     "DA={};MQ10={:.2};MQ20={:.2};MQ30={:.2};MQ40={:.2};MQ50={:.2};",
-                     var.dp_any_mq,
-                     var.mq10_frac,
-                     var.mq20_frac,
-                     var.mq30_frac,
-                     var.mq40_frac,
-                     var.mq50_frac).chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
-        }
-        writeln!(file,
-                 "PH={};SC={};\tGT:GQ:DP:PS:UG:UQ\t{}:{:.0}:{}:{}:{}:{:.2}",
-                 post_str,
-                 sequence_context,
-                 genotype_str,
-                 var.gq+0.4999, // round off to integer
-		 var.dp,
-                 ps,
-                 unphased_genotype_str,
-                 var.unphased_gq).chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
+            record.add_info("DA", dp_any_mq);
+            for (k, &sum) in mq_frac_sums.iter().enumerate() {
+                record.add_info(
+                    &format!("MQ{}", 10 * (k + 1)),
+                    format!("{:.2}", weighted_mean(sum, dp_any_mq)),
+                );
+            }
+        }
+        record.add_info("SC", sequence_context);
+        record.set_format(&FORMAT_KEYS);
+        for values in sample_values {
+            record.add_sample(values);
+        }
 
+        writeln!(file, "{}", record)
+            .chain_err(|| ErrorKind::FileWriteError(vcf_display.to_string()))?;
     }
     Ok(())
 }
@@ -265,6 +1073,7 @@ pub fn print_variant_debug(
     max_cov: u32,
     density_params: &DensityParameters,
     sample_name: &String,
+    contig_params: &Vec<ContigParams>,
 ) -> Result<()> {
     match variant_debug_directory {
         &Some(ref dir) => {
@@ -284,7 +1093,9 @@ pub fn print_variant_debug(
                 density_params,
                 sample_name,
                 true,
-                true // don't print MQ statistics in VCF because they may or may not be present
+                true, // don't print MQ statistics in VCF because they may or may not be present
+                contig_params,
+                false,
             )
             .chain_err(|| "Error printing debug VCF file.")?;
         }
@@ -292,3 +1103,953 @@ pub fn print_variant_debug(
     };
     Ok(())
 }
+
+/// Counts of the called variants, for the summary printed at the end of a quick-look run
+#[derive(Debug, Default)]
+pub struct CallSummary {
+    pub regions: usize,
+    pub bases: usize,
+    pub candidates: usize,
+    pub filtered: usize,
+    pub snvs: usize,
+    pub indels: usize,
+    pub het: usize,
+    pub hom_alt: usize,
+    pub transitions: usize,
+    pub transversions: usize,
+    pub phased: usize,
+}
+
+impl CallSummary {
+    /// Adds the variants of a region to the summary. Only variant genotypes that pass the
+    /// filters are counted as calls.
+    pub fn add_varlist(&mut self, varlist: &VarList) {
+        for var in &varlist.lst {
+            self.candidates += 1;
+            let Genotype(a1, a2) = var.genotype;
+            if a1 == 0 && a2 == 0 {
+                continue;
+            }
+            if var.filter != VarFilter::Pass {
+                self.filtered += 1;
+                continue;
+            }
+            if a1 == a2 {
+                self.hom_alt += 1;
+            } else {
+                self.het += 1;
+            }
+            if var.phase_set.is_some() {
+                self.phased += 1;
+            }
+
+            let ref_allele = &var.alleles[0];
+            let alt_ix = if a2 != 0 { a2 } else { a1 };
+            let alt_allele = &var.alleles[alt_ix as usize];
+            if ref_allele.len() == 1 && alt_allele.len() == 1 {
+                self.snvs += 1;
+                match (ref_allele.as_str(), alt_allele.as_str()) {
+                    ("A", "G") | ("G", "A") | ("C", "T") | ("T", "C") => self.transitions += 1,
+                    _ => self.transversions += 1,
+                }
+            } else {
+                self.indels += 1;
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        let calls = self.het + self.hom_alt;
+        let per_mb = |n: usize| n as f64 / (self.bases.max(1) as f64 / 1000000.0);
+        let ratio = |a: usize, b: usize| if b > 0 { a as f64 / b as f64 } else { 0.0 };
+        info!("{} {:<30}{}", SPACER, "Regions called:", self.regions);
+        info!("{} {:<30}{}", SPACER, "Reference bases:", self.bases);
+        info!("{} {:<30}{}", SPACER, "Candidate sites:", self.candidates);
+        info!("{} {:<30}{}", SPACER, "Filtered variants:", self.filtered);
+        info!(
+            "{} {:<30}{} ({:.1} per Mb)",
+            SPACER,
+            "Variants called (PASS):",
+            calls,
+            per_mb(calls)
+        );
+        info!(
+            "{} {:<30}{} SNVs, {} indels",
+            SPACER, "Variant types:", self.snvs, self.indels
+        );
+        info!(
+            "{} {:<30}{:.2}",
+            SPACER,
+            "Het/hom ratio:",
+            ratio(self.het, self.hom_alt)
+        );
+        info!(
+            "{} {:<30}{:.2}",
+            SPACER,
+            "Ts/Tv ratio:",
+            ratio(self.transitions, self.transversions)
+        );
+        info!(
+            "{} {:<30}{} ({:.1}%)",
+            SPACER,
+            "Phased variants:",
+            self.phased,
+            100.0 * ratio(self.phased, calls)
+        );
+    }
+}
+
+/// the IUPAC ambiguity code for a pair of bases
+fn iupac_code(a: u8, b: u8) -> u8 {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+    match (a, b) {
+        _ if a == b => a,
+        (b'A', b'C') => b'M',
+        (b'A', b'G') => b'R',
+        (b'A', b'T') => b'W',
+        (b'C', b'G') => b'S',
+        (b'C', b'T') => b'Y',
+        (b'G', b'T') => b'K',
+        _ => b'N',
+    }
+}
+
+/// Applies the variant calls of a region to its reference sequence (```ref_seq```, which starts at
+/// position ```start```) and returns the consensus sequences with the number of heterozygous
+/// variants that could not be written.
+///
+/// Only variants that pass the filters are used. If none of the heterozygous variants are phased,
+/// a single sequence is returned in which heterozygous SNVs (and MNVs) are written as IUPAC
+/// ambiguity codes. Otherwise, a sequence is returned for each haplotype: phased heterozygous
+/// variants are written to their haplotype, while unphased ones are written as IUPAC codes in both
+/// sequences. Unphased heterozygous indels can't be written as ambiguity codes, so the reference
+/// sequence is kept for them; so are variants that overlap a previous variant.
+fn consensus_sequences(vars: &[&Var], ref_seq: &[u8], start: usize) -> (Vec<Vec<u8>>, usize) {
+    let is_het = |var: &Var| var.genotype.0 != var.genotype.1;
+    let n_seqs = if vars.iter().any(|var| is_het(var) && var.phase_set.is_some()) {
+        2
+    } else {
+        1
+    };
+
+    let mut seqs: Vec<Vec<u8>> = vec![Vec::with_capacity(ref_seq.len()); n_seqs];
+    let mut skipped = 0;
+    let mut pos = start; // the next reference position to write
+
+    for var in vars {
+        let Genotype(a1, a2) = var.genotype;
+        let ref_allele = var.alleles[0].as_bytes();
+        if var.filter != VarFilter::Pass
+            || (a1 == 0 && a2 == 0)
+            || var.pos0 < pos
+            || var.pos0 + ref_allele.len() > start + ref_seq.len()
+        {
+            continue;
+        }
+        let allele1 = var.alleles[a1 as usize].as_bytes();
+        let allele2 = var.alleles[a2 as usize].as_bytes();
+
+        // the allele written to each of the sequences
+        let alleles: Vec<Vec<u8>> = if !is_het(var) {
+            vec![allele1.to_vec(); n_seqs]
+        } else if var.phase_set.is_some() {
+            vec![allele1.to_vec(), allele2.to_vec()]
+        } else if allele1.len() == allele2.len() {
+            let code: Vec<u8> = allele1
+                .iter()
+                .zip(allele2.iter())
+                .map(|(&b1, &b2)| iupac_code(b1, b2))
+                .collect();
+            vec![code; n_seqs]
+        } else {
+            skipped += 1;
+            continue;
+        };
+
+        for (seq, allele) in seqs.iter_mut().zip(alleles.iter()) {
+            seq.extend_from_slice(&ref_seq[(pos - start)..(var.pos0 - start)]);
+            seq.extend_from_slice(allele);
+        }
+        pos = var.pos0 + ref_allele.len();
+    }
+
+    for seq in seqs.iter_mut() {
+        seq.extend_from_slice(&ref_seq[(pos - start)..]);
+    }
+    (seqs, skipped)
+}
+
+/// Writes the consensus sequences of the sample for each region (or for each contig if there is no
+/// region) to a FASTA file, or appends them if ```append``` is true. Regions with phased
+/// heterozygous variants get a sequence for each haplotype (named ```<sample>_<region>_hap1``` and
+/// ```_hap2```), other regions a single sequence with IUPAC ambiguity codes for the heterozygous
+/// variants (named ```<sample>_<region>```). See ```consensus_sequences```.
+pub fn print_consensus_fasta(
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    bam_file: &String,
+    fasta_file: &String,
+    output_file: &String,
+    sample_name: &String,
+    append: bool,
+) -> Result<()> {
+    let mut fasta = open_fasta(fasta_file)?;
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+
+    let mut contig_vars: HashMap<u32, Vec<&Var>> = HashMap::new();
+    for var in &varlist.lst {
+        contig_vars.entry(var.tid).or_insert(vec![]).push(var);
+    }
+
+    let mut skipped = 0;
+    for iv in get_interval_lst(bam_file, interval)? {
+        let start = iv.start_pos as usize;
+        let end = iv.end_pos as usize;
+        let mut ref_seq: Vec<u8> = vec![];
+        read_fasta_region(&mut fasta, &iv.chrom, start as u64, (end + 1) as u64, &mut ref_seq)?;
+        ref_seq.make_ascii_uppercase();
+
+        let vars: Vec<&Var> = match contig_vars.get(&iv.tid) {
+            Some(vars) => vars
+                .iter()
+                .filter(|var| var.pos0 >= start && var.pos0 <= end)
+                .map(|&var| var)
+                .collect(),
+            None => vec![],
+        };
+        let (seqs, region_skipped) = consensus_sequences(&vars, &ref_seq, start);
+        skipped += region_skipped;
+
+        let name = format!("{}_{}:{}-{}", sample_name, iv.chrom, start + 1, end + 1);
+        for (i, seq) in seqs.iter().enumerate() {
+            let header = if seqs.len() == 1 {
+                format!(">{}", name)
+            } else {
+                format!(">{}_hap{}", name, i + 1)
+            };
+            writeln!(file, "{}", header)
+                .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+            for line in seq.chunks(60) {
+                file.write_all(line)
+                    .and_then(|_| file.write_all(b"\n"))
+                    .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+            }
+        }
+    }
+
+    if skipped > 0 {
+        info!(
+            "{} {} unphased heterozygous indels were not written to the consensus sequences.",
+            print_time(),
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// The span of a phase block: from the first to the end of the last phased heterozygous variant
+/// of the block that passes the filters
+#[derive(Debug, Clone, PartialEq)]
+struct PhaseBlockSpan {
+    tid: u32,
+    phase_set: usize,
+    /// 0-based start, and end (exclusive) of the last variant's reference allele
+    start: usize,
+    end: usize,
+    n_phased: usize,
+}
+
+/// Returns the spans of the phase blocks of the variants, ordered by position
+fn phase_block_spans(vars: &[&Var]) -> Vec<PhaseBlockSpan> {
+    let mut blocks: HashMap<(u32, usize), PhaseBlockSpan> = HashMap::new();
+    for var in vars {
+        let ps = match var.phase_set {
+            Some(ps) if var.filter == VarFilter::Pass && var.genotype.0 != var.genotype.1 => ps,
+            _ => {
+                continue;
+            }
+        };
+        let end = var.pos0 + var.alleles[0].len();
+        let block = blocks.entry((var.tid, ps)).or_insert(PhaseBlockSpan {
+            tid: var.tid,
+            phase_set: ps,
+            start: var.pos0,
+            end: end,
+            n_phased: 0,
+        });
+        block.start = block.start.min(var.pos0);
+        block.end = block.end.max(end);
+        block.n_phased += 1;
+    }
+    let mut spans: Vec<PhaseBlockSpan> = blocks.into_iter().map(|(_, b)| b).collect();
+    spans.sort_by_key(|b| (b.tid, b.start, b.phase_set));
+    spans
+}
+
+/// Returns the haplotype sequences of a phase block (see ```consensus_sequences```), where
+/// ```ref_seq``` is the reference sequence of the block span. Heterozygous variants of other phase
+/// blocks within the span are written as if they were unphased.
+fn phase_block_sequences(
+    vars: &[&Var],
+    block: &PhaseBlockSpan,
+    ref_seq: &[u8],
+) -> (Vec<Vec<u8>>, usize) {
+    let block_vars: Vec<Var> = vars
+        .iter()
+        .filter(|var| {
+            var.tid == block.tid
+                && var.pos0 >= block.start
+                && var.pos0 + var.alleles[0].len() <= block.end
+        })
+        .map(|&var| {
+            let mut var = var.clone();
+            if var.phase_set != Some(block.phase_set) {
+                var.phase_set = None;
+            }
+            var
+        })
+        .collect();
+    let block_var_refs: Vec<&Var> = block_vars.iter().collect();
+    consensus_sequences(&block_var_refs, ref_seq, block.start)
+}
+
+/// Writes the two haplotype sequences of each phase block of the sample to ```<prefix>.fa```
+/// (named ```<sample>_<chrom>:<start>-<end>_PS<phase set>_hap1``` and ```_hap2```), and the block
+/// coordinates with the sequence name, the number of phased variants and the sample name to
+/// ```<prefix>.bed```, or appends them if ```append``` is true. The variant filters must have been
+/// applied (```var_filter```, e.g. by ```print_vcf```) before.
+pub fn print_block_consensus(
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    fasta_file: &String,
+    prefix: &String,
+    sample_name: &String,
+    append: bool,
+) -> Result<()> {
+    let mut fasta = open_fasta(fasta_file)?;
+    let fasta_name = format!("{}.fa", prefix);
+    let bed_name = format!("{}.bed", prefix);
+    let fasta_path = Path::new(&fasta_name);
+    let bed_path = Path::new(&bed_name);
+    let mut fasta_out = open_vcf_file(fasta_path, append)?;
+    let mut bed_out = open_vcf_file(bed_path, append)?;
+    if !append {
+        writeln!(bed_out, "#chrom\tstart\tend\tname\tphased_variants\tsample")
+            .chain_err(|| ErrorKind::FileWriteError(bed_path.display().to_string()))?;
+    }
+
+    let vars: Vec<&Var> = varlist
+        .lst
+        .iter()
+        .filter(|var| match interval {
+            &Some(ref iv) => {
+                var.tid == iv.tid
+                    && var.pos0 >= iv.start_pos as usize
+                    && var.pos0 <= iv.end_pos as usize
+            }
+            &None => true,
+        })
+        .collect();
+
+    let mut skipped = 0;
+    for block in phase_block_spans(&vars) {
+        let chrom = &varlist.target_names[block.tid as usize];
+        let mut ref_seq: Vec<u8> = vec![];
+        read_fasta_region(
+            &mut fasta,
+            chrom,
+            block.start as u64,
+            block.end as u64,
+            &mut ref_seq,
+        )?;
+        ref_seq.make_ascii_uppercase();
+
+        let (seqs, block_skipped) = phase_block_sequences(&vars, &block, &ref_seq);
+        skipped += block_skipped;
+
+        let name = format!(
+            "{}_{}:{}-{}_PS{}",
+            sample_name,
+            chrom,
+            block.start + 1,
+            block.end,
+            block.phase_set
+        );
+        for (i, seq) in seqs.iter().enumerate() {
+            writeln!(fasta_out, ">{}_hap{}", name, i + 1)
+                .chain_err(|| ErrorKind::FileWriteError(fasta_path.display().to_string()))?;
+            for line in seq.chunks(60) {
+                fasta_out
+                    .write_all(line)
+                    .and_then(|_| fasta_out.write_all(b"\n"))
+                    .chain_err(|| ErrorKind::FileWriteError(fasta_path.display().to_string()))?;
+            }
+        }
+        writeln!(
+            bed_out,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            chrom, block.start, block.end, name, block.n_phased, sample_name
+        )
+        .chain_err(|| ErrorKind::FileWriteError(bed_path.display().to_string()))?;
+    }
+
+    if skipped > 0 {
+        info!(
+            "{} {} unphased heterozygous indels were not written to the phase block sequences.",
+            print_time(),
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// A region of variants flagged by the variant density filter (```dn```)
+#[derive(Debug, Clone, PartialEq)]
+struct DenseRegion {
+    tid: u32,
+    /// 0-based start, and end (exclusive) of the last variant's reference allele
+    start: usize,
+    end: usize,
+    /// the union of the filters of the variants in the region
+    filter: VarFilter,
+    n_variants: usize,
+    n_het: usize,
+    n_hom_alt: usize,
+}
+
+/// Merges the density-filtered variants into regions. Consecutive flagged variants less than
+/// ```max_dist``` bases apart are in the same region.
+fn dense_regions(vars: &[&Var], max_dist: usize) -> Vec<DenseRegion> {
+    let mut regions: Vec<DenseRegion> = vec![];
+    for var in vars {
+        if !var.filter.has_filter(VarFilter::Density) {
+            continue;
+        }
+        let end = var.pos0 + var.alleles[0].len();
+        let extend = match regions.last() {
+            Some(r) => r.tid == var.tid && var.pos0 <= r.end + max_dist,
+            None => false,
+        };
+        if !extend {
+            regions.push(DenseRegion {
+                tid: var.tid,
+                start: var.pos0,
+                end: end,
+                filter: VarFilter::Pass,
+                n_variants: 0,
+                n_het: 0,
+                n_hom_alt: 0,
+            });
+        }
+        let region = regions.last_mut().unwrap();
+        region.end = region.end.max(end);
+        region.filter.add_filter(var.filter);
+        region.n_variants += 1;
+        if var.genotype.0 != var.genotype.1 {
+            region.n_het += 1;
+        } else if var.genotype.0 != 0 {
+            region.n_hom_alt += 1;
+        }
+    }
+    regions
+}
+
+/// Writes the regions of variants flagged by the variant density filter to a BED file, or
+/// appends them if ```append``` is true. Each line has the region, the filters of its variants as
+/// a reason code (e.g. ```dn;dp``` if some of them also exceed the maximum depth, which points to
+/// a collapsed repeat), the number of variants, and how many of them are heterozygous and
+/// homozygous alternate, followed by the sample name. The variant filters must have been applied
+/// (```var_filter```, e.g. by ```print_vcf```) before.
+pub fn print_dense_regions(
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    bam_file: &String,
+    density_params: &DensityParameters,
+    output_file: &String,
+    sample_name: &String,
+    append: bool,
+) -> Result<()> {
+    let target_names = parse_target_names(bam_file)?;
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+    if !append {
+        writeln!(file, "#chrom\tstart\tend\treason\tvariants\thet\thom_alt\tsample")
+            .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+
+    let vars: Vec<&Var> = varlist
+        .lst
+        .iter()
+        .filter(|var| match interval {
+            &Some(ref iv) => {
+                var.tid == iv.tid
+                    && var.pos0 >= iv.start_pos as usize
+                    && var.pos0 <= iv.end_pos as usize
+            }
+            &None => true,
+        })
+        .collect();
+    for region in dense_regions(&vars, density_params.len) {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            target_names[region.tid as usize],
+            region.start,
+            region.end,
+            region.filter,
+            region.n_variants,
+            region.n_het,
+            region.n_hom_alt,
+            sample_name
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+/// Writes the callable regions of each source of candidate variants (a sample, or the candidate
+/// BAM) to a BED file, or appends them if ```append``` is true. Each line has the region, its
+/// status (e.g. CALLABLE or LOW_COVERAGE) and the name of the source. The regions of the variants
+/// that the variant density filter flagged in any of ```varlists``` are marked as DENSE.
+pub fn print_callable_regions(
+    callable: &[(String, CallableRegions)],
+    varlists: &[VarList],
+    interval: &Option<GenomicInterval>,
+    bam_file: &String,
+    density_params: &DensityParameters,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let target_names = parse_target_names(bam_file)?;
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+    if !append {
+        writeln!(file, "#chrom\tstart\tend\tstatus\tsample")
+            .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+
+    let mut dense: Vec<(u32, usize, usize)> = vec![];
+    for varlist in varlists {
+        let vars: Vec<&Var> = varlist
+            .lst
+            .iter()
+            .filter(|var| match interval {
+                &Some(ref iv) => {
+                    var.tid == iv.tid
+                        && var.pos0 >= iv.start_pos as usize
+                        && var.pos0 <= iv.end_pos as usize
+                }
+                &None => true,
+            })
+            .collect();
+        for region in dense_regions(&vars, density_params.len) {
+            dense.push((region.tid, region.start, region.end));
+        }
+    }
+
+    for &(ref source_name, ref source_callable) in callable {
+        for region in source_callable.regions(&dense) {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}",
+                target_names[region.tid as usize],
+                region.start,
+                region.end,
+                region.status.name(),
+                source_name
+            )
+            .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the clusters of soft-clipped reads to a BED file, or appends them if ```append``` is
+/// true. Each line has the region, the number of clipped read ends, how many of them are clipped
+/// before and after the breakpoint, the number of SNVs in the region that were flagged
+/// (```sc```), and the sample name. Only the clusters centered in ```interval``` are written, so
+/// that a cluster spanning two regions is written once.
+pub fn print_soft_clip_clusters(
+    clusters: &[SoftClipCluster],
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    output_file: &String,
+    sample_name: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+    if !append {
+        writeln!(
+            file,
+            "#chrom\tstart\tend\tclipped\tclipped_before\tclipped_after\tsnvs\tsample"
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+
+    for cluster in clusters {
+        let center = (cluster.start + cluster.end) / 2;
+        if let &Some(ref iv) = interval {
+            if cluster.tid != iv.tid
+                || center < iv.start_pos as usize
+                || center > iv.end_pos as usize
+            {
+                continue;
+            }
+        }
+        let n_snvs = varlist
+            .lst
+            .iter()
+            .filter(|var| {
+                var.tid == cluster.tid
+                    && var.pos0 >= cluster.start
+                    && var.pos0 < cluster.end
+                    && var.filter.has_filter(VarFilter::SoftClipCluster)
+            })
+            .count();
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            varlist.target_names[cluster.tid as usize],
+            cluster.start,
+            cluster.end,
+            cluster.n_clipped(),
+            cluster.n_clipped_before,
+            cluster.n_clipped_after,
+            n_snvs,
+            sample_name
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+/// Writes the diagnostics of each round of haplotype assembly and genotype refinement
+/// (```--hap_iteration_stats```) as a tab-separated file, or appends them if ```append``` is true.
+/// Each line has the region and sample, the round, the number of phased heterozygous SNVs after
+/// the round, the PHRED-scaled total likelihood before and after haplotype assembly and after
+/// genotype refinement, the number of genotypes that changed (ignoring phase), and the number of
+/// passes of the greedy genotype refinement.
+pub fn print_hap_iteration_stats(
+    rounds: &Vec<HapIterationRound>,
+    interval: &Option<GenomicInterval>,
+    sample_name: &String,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+    if !append {
+        writeln!(
+            file,
+            "#region\tsample\tround\tphased_het_snvs\tll_before_assembly\tll_after_assembly\tll_after_refinement\tgenotype_changes\trefinement_passes"
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+
+    let region = match interval {
+        &Some(ref iv) => format!("{}:{}-{}", iv.chrom, iv.start_pos + 1, iv.end_pos + 1),
+        &None => ".".to_string(),
+    };
+    for round in rounds {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}",
+            region,
+            sample_name,
+            round.round,
+            round.num_phased,
+            *PHREDProb::from(round.likelihood_before_assembly),
+            *PHREDProb::from(round.likelihood_after_assembly),
+            *PHREDProb::from(round.likelihood_after_refinement),
+            round.genotype_changes,
+            round.refinement_passes
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+/// Writes the candidate variants found by the discovery stage (```--candidates-out```) as a
+/// sites-only VCF, or appends the candidates of another region if ```append``` is true. The QUAL
+/// of each record is the quality of the candidate from the pileup, and the depth and allele
+/// counts are in the INFO field. The contigs of the reference are listed in the header, so that
+/// the VCF can be read back with ```--candidates-in``` without an index.
+pub fn print_candidates_vcf(
+    varlist: &VarList,
+    interval: &Option<GenomicInterval>,
+    fasta_file: &Option<String>,
+    output_file: &String,
+    append: bool,
+) -> Result<()> {
+    let path = Path::new(output_file);
+    let mut file = open_vcf_file(path, append)?;
+    if !append {
+        writeln!(file, "##fileformat=VCFv4.2\n##source=Longshot v0.4.2 candidates")
+            .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+        if let &Some(ref ff) = fasta_file {
+            for sequence in open_fasta(ff)?.index.sequences().iter() {
+                writeln!(file, "##contig=<ID={},length={}>", sequence.name, sequence.len)
+                    .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+            }
+        }
+        writeln!(
+            file,
+            "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth of reads passing MAPQ filter\">
+##INFO=<ID=AC,Number=R,Type=Integer,Description=\"Number of Observations of Each Allele\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO"
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+
+    for var in varlist.lst.iter() {
+        if let &Some(ref iv) = interval {
+            if var.tid != iv.tid
+                || var.pos0 < iv.start_pos as usize
+                || var.pos0 > iv.end_pos as usize
+            {
+                continue;
+            }
+        }
+        let counts: Vec<String> = var.allele_counts.iter().map(|c| c.to_string()).collect();
+        writeln!(
+            file,
+            "{}\t{}\t.\t{}\t{}\t{:.2}\t.\tDP={};AC={}",
+            varlist.target_names[var.tid as usize],
+            var.pos0 + 1,
+            var.alleles[0],
+            var.alleles[1..].join(","),
+            var.qual,
+            var.dp,
+            counts.join(",")
+        )
+        .chain_err(|| ErrorKind::FileWriteError(path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phred_genotype_likelihoods() {
+        let lp = |p: f64| LogProb::from(Prob(p));
+        // triallelic site, likelihoods of the phased genotypes
+        let likelihoods = GenotypeProbs {
+            tab: vec![
+                vec![lp(0.01), lp(0.4), lp(0.001)],
+                vec![lp(0.2), lp(0.1), lp(0.0001)],
+                vec![lp(0.001), lp(0.0001), lp(0.0)],
+            ],
+        };
+        // 0/0, 0/1, 1/1, 0/2, 1/2, 2/2
+        assert_eq!(
+            phred_genotype_likelihoods(&likelihoods, 2),
+            vec![15, 0, 5, 25, 35, MAX_VCF_QUAL as u32]
+        );
+        // haploid: 0, 1, 2
+        assert_eq!(
+            phred_genotype_likelihoods(&likelihoods, 1),
+            vec![10, 0, MAX_VCF_QUAL as u32]
+        );
+    }
+
+    #[test]
+    fn test_format_genotype() {
+        assert_eq!(format_genotype(Genotype(2, 1), 2, "/"), "1/2");
+        assert_eq!(format_genotype(Genotype(2, 1), 2, "|"), "2|1");
+        assert_eq!(format_genotype(Genotype(0, 1), 2, "/"), "0/1");
+        assert_eq!(format_genotype(Genotype(2, 2), 1, "/"), "2");
+    }
+
+    #[test]
+    fn test_vcf_record() {
+        let mut record = VcfRecord::new("chr1", 100, "A", &vec!["G".to_string(), "T".to_string()]);
+        assert_eq!(record.to_string(), "chr1\t100\t.\tA\tG,T\t0\t.\t.");
+
+        record.qual = 29.6;
+        record.filter = "PASS".to_string();
+        record.add_info("DP", 30);
+        record.add_info_flag("DN");
+        record.add_info("AQ", format!("{:.2}", 7.0));
+        record.set_format(&["GT", "GQ"]);
+        record.add_sample(vec!["0/1".to_string(), "50".to_string()]);
+        record.add_sample(vec!["1|2".to_string(), "12".to_string()]);
+        assert_eq!(
+            record.to_string(),
+            "chr1\t100\t.\tA\tG,T\t30\tPASS\tDP=30;DN;AQ=7.00;\tGT:GQ\t0/1:50\t1|2:12"
+        );
+
+        assert_eq!(record.info_value("DP"), Some("30"));
+        assert_eq!(record.info_value("DN"), None);
+        assert!(record.has_info("DN"));
+        assert!(!record.has_info("SQ"));
+    }
+
+    #[test]
+    fn test_consensus_sequences() {
+        let var = |pos0: usize, alleles: &[&str], genotype: Genotype, phase_set: Option<usize>| {
+            let mut var = Var::new(0, pos0, alleles.iter().map(|a| a.to_string()).collect());
+            var.genotype = genotype;
+            var.phase_set = phase_set;
+            var
+        };
+        // reference ACGTACGTAC starting at position 100
+        let ref_seq = b"ACGTACGTAC";
+        let het_snv = var(101, &["C", "T"], Genotype(0, 1), None);
+        let hom_del = var(103, &["TA", "T"], Genotype(1, 1), None);
+        let het_ins = var(106, &["G", "GTT"], Genotype(1, 0), None);
+        let mut filtered = var(108, &["A", "G"], Genotype(1, 1), None);
+        filtered.filter = VarFilter::Density;
+
+        // without phased variants, a single sequence with an IUPAC code for the het SNV, and the
+        // reference for the unphased het insertion
+        let vars = vec![&het_snv, &hom_del, &het_ins, &filtered];
+        let (seqs, skipped) = consensus_sequences(&vars, ref_seq, 100);
+        assert_eq!(seqs, vec![b"AYGTCGTAC".to_vec()]);
+        assert_eq!(skipped, 1);
+
+        // once the insertion is phased, each haplotype gets its own sequence
+        let phased_ins = var(106, &["G", "GTT"], Genotype(1, 0), Some(101));
+        let vars = vec![&het_snv, &hom_del, &phased_ins];
+        let (seqs, skipped) = consensus_sequences(&vars, ref_seq, 100);
+        assert_eq!(seqs, vec![b"AYGTCGTTTAC".to_vec(), b"AYGTCGTAC".to_vec()]);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_phase_block_sequences() {
+        let var = |pos0: usize, alleles: &[&str], genotype: Genotype, phase_set: Option<usize>| {
+            let mut var = Var::new(0, pos0, alleles.iter().map(|a| a.to_string()).collect());
+            var.genotype = genotype;
+            var.phase_set = phase_set;
+            var
+        };
+        // reference ACGTACGTAC starting at position 100, with two interleaved phase blocks
+        let ref_seq = b"ACGTACGTAC";
+        let vars = vec![
+            var(99, &["T", "G"], Genotype(0, 1), Some(99)),
+            var(101, &["C", "T"], Genotype(0, 1), Some(101)),
+            var(103, &["T", "C"], Genotype(1, 0), Some(99)),
+            var(106, &["G", "GTT"], Genotype(1, 0), Some(101)),
+            var(108, &["A", "G"], Genotype(1, 1), None),
+        ];
+        let var_refs: Vec<&Var> = vars.iter().collect();
+
+        let blocks = phase_block_spans(&var_refs);
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|b| (b.phase_set, b.start, b.end, b.n_phased))
+                .collect::<Vec<_>>(),
+            vec![(99, 99, 104, 2), (101, 101, 107, 2)]
+        );
+
+        // the het SNV of the other block within the span is written as an IUPAC code
+        let (seqs, skipped) = phase_block_sequences(&var_refs, &blocks[1], &ref_seq[1..7]);
+        assert_eq!(seqs, vec![b"CGYACGTT".to_vec(), b"TGYACG".to_vec()]);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_neighbor_calls() {
+        // the variant at 150 is not a call
+        let vars = vec![
+            (0, 100, true),
+            (0, 150, false),
+            (0, 180, true),
+            (0, 400, true),
+            (1, 100, true),
+        ];
+        let calls = vec![(0, 100), (0, 180), (0, 400), (1, 100)];
+        assert_eq!(
+            neighbor_calls(&vars, &calls, 100),
+            vec![
+                (Some(80), 1),
+                (Some(30), 2),
+                (Some(80), 1),
+                (Some(220), 0),
+                (None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dense_regions() {
+        let var = |tid: u32, pos0: usize, genotype: Genotype, filter: VarFilter| {
+            let mut var = Var::new(tid, pos0, vec!["AC".to_string(), "A".to_string()]);
+            var.genotype = genotype;
+            var.filter = filter;
+            var
+        };
+        let vars = vec![
+            var(0, 100, Genotype(0, 1), VarFilter::Density),
+            var(0, 150, Genotype(1, 1), VarFilter::DensityAndDepth),
+            var(0, 200, Genotype(0, 1), VarFilter::Pass),
+            var(0, 300, Genotype(1, 0), VarFilter::Density),
+            var(0, 1000, Genotype(0, 1), VarFilter::Density),
+            var(1, 1001, Genotype(0, 1), VarFilter::Density),
+        ];
+        let regions = dense_regions(&vars.iter().collect::<Vec<&Var>>(), 500);
+        assert_eq!(
+            regions
+                .iter()
+                .map(|r| (r.tid, r.start, r.end, r.filter, r.n_variants, r.n_het, r.n_hom_alt))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 100, 302, VarFilter::DensityAndDepth, 3, 2, 1),
+                (0, 1000, 1002, VarFilter::Density, 1, 1, 0),
+                (1, 1001, 1003, VarFilter::Density, 1, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_candidates_vcf() {
+        let var = |tid: u32, pos0: usize, alleles: &[&str], qual: f64, counts: Vec<u16>| {
+            let mut var = Var::new(tid, pos0, alleles.iter().map(|a| a.to_string()).collect());
+            var.qual = qual;
+            var.dp = counts.iter().map(|&c| c as usize).sum();
+            var.allele_counts = counts;
+            var
+        };
+        let varlist = VarList::new(
+            vec![
+                var(0, 99, &["A", "G"], 35.5, vec![10, 8]),
+                var(0, 199, &["C", "T", "CA"], 12.0, vec![5, 3, 2]),
+                var(1, 49, &["G", "T"], 50.0, vec![2, 9]),
+            ],
+            vec!["chr1".to_string(), "chr2".to_string()],
+        )
+        .unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("longshot_test_candidates_{}.vcf", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        // the candidates of each region are appended after the header
+        for (tid, chrom) in varlist.target_names.iter().enumerate() {
+            let interval = Some(GenomicInterval {
+                tid: tid as u32,
+                chrom: chrom.clone(),
+                start_pos: 0,
+                end_pos: 1000,
+            });
+            print_candidates_vcf(&varlist, &interval, &None, &path, tid > 0).unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<&str> = contents.lines().filter(|l| !l.starts_with("#")).collect();
+        assert_eq!(contents.matches("#CHROM").count(), 1);
+        assert_eq!(
+            records,
+            vec![
+                "chr1\t100\t.\tA\tG\t35.50\t.\tDP=18;AC=10,8",
+                "chr1\t200\t.\tC\tT,CA\t12.00\t.\tDP=10;AC=5,3,2",
+                "chr2\t50\t.\tG\tT\t50.00\t.\tDP=11;AC=2,9",
+            ]
+        );
+    }
+}
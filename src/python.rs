@@ -0,0 +1,268 @@
+//! Python module ```longshot```: the Pair-HMM forward algorithm, the Viterbi alignment score and
+//! the pileup genotype likelihoods, called exactly as the longshot binary calls them.
+//!
+//! All probabilities returned to Python are natural-log probabilities, like the ```LogProb```
+//! values used throughout longshot.
+
+use bio::stats::{LogProb, PHREDProb, Prob};
+use call_genotypes::calculate_genotype_posteriors_no_haplotypes;
+use estimate_alignment_parameters::read_alignment_parameters;
+use genotype_probs::{possible_genotypes_with_ploidy, GenotypePrior, GenotypePriors};
+use presets::{Preset, PRESET_NAMES};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use realignment::{
+    check_window_lengths, forward_algorithm_numerically_stable, viterbi_max_scoring_alignment,
+    AlignmentParameters, EmissionProbs, LnAlignmentParameters, TransitionProbs,
+};
+use std::collections::HashMap;
+use variants_and_fragments::FragCall;
+
+/// Pair-HMM alignment parameters, as estimated by longshot from the reads.
+#[pyclass(name = "AlignmentParameters")]
+#[derive(Clone)]
+pub struct PyAlignmentParameters {
+    params: AlignmentParameters,
+}
+
+#[pymethods]
+impl PyAlignmentParameters {
+    /// Creates alignment parameters from the transition probabilities out of the match state, the
+    /// insertion and deletion extension probabilities, and the probability that a matched base is
+    /// equal to the haplotype base. Mismatches are spread evenly over the other three bases.
+    #[new]
+    fn new(
+        match_from_match: f64,
+        insertion_from_match: f64,
+        deletion_from_match: f64,
+        insertion_from_insertion: f64,
+        deletion_from_deletion: f64,
+        equal: f64,
+    ) -> PyResult<PyAlignmentParameters> {
+        for &p in &[
+            match_from_match,
+            insertion_from_match,
+            deletion_from_match,
+            insertion_from_insertion,
+            deletion_from_deletion,
+            equal,
+        ] {
+            if !(p >= 0.0 && p <= 1.0) {
+                return Err(PyValueError::new_err(format!(
+                    "alignment probabilities must be between 0 and 1, got {}",
+                    p
+                )));
+            }
+        }
+        let from_match = match_from_match + insertion_from_match + deletion_from_match;
+        if (from_match - 1.0).abs() > 1e-6 {
+            return Err(PyValueError::new_err(format!(
+                "the transition probabilities from the match state must sum to 1, got {}",
+                from_match
+            )));
+        }
+
+        Ok(PyAlignmentParameters {
+            params: AlignmentParameters {
+                transition_probs: TransitionProbs {
+                    match_from_match: match_from_match,
+                    insertion_from_match: insertion_from_match,
+                    deletion_from_match: deletion_from_match,
+                    insertion_from_insertion: insertion_from_insertion,
+                    match_from_insertion: 1.0 - insertion_from_insertion,
+                    deletion_from_deletion: deletion_from_deletion,
+                    match_from_deletion: 1.0 - deletion_from_deletion,
+                },
+                emission_probs: EmissionProbs {
+                    equal: equal,
+                    not_equal: (1.0 - equal) / 3.0,
+                    insertion: 1.0,
+                    deletion: 1.0,
+                },
+            },
+        })
+    }
+
+    /// The typical alignment parameters of a technology preset (see ```--preset```)
+    #[staticmethod]
+    fn preset(name: &str) -> PyResult<PyAlignmentParameters> {
+        match Preset::from_name(name) {
+            Some(preset) => Ok(PyAlignmentParameters {
+                params: preset.alignment_parameters(),
+            }),
+            None => Err(PyValueError::new_err(format!(
+                "unknown preset {}, expected one of {}",
+                name,
+                PRESET_NAMES.join(", ")
+            ))),
+        }
+    }
+
+    /// Reads the alignment parameters from a file written with ```--write-params```. Only the
+    /// genome-wide parameters are returned, not the strand-, context- or read-group-specific ones.
+    #[staticmethod]
+    fn from_file(path: String) -> PyResult<PyAlignmentParameters> {
+        let (params, _, _, _) = read_alignment_parameters(&path)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyAlignmentParameters { params: params })
+    }
+
+    fn __repr__(&self) -> String {
+        let t = &self.params.transition_probs;
+        let e = &self.params.emission_probs;
+        format!(
+            "AlignmentParameters(match_from_match={}, insertion_from_match={}, \
+             deletion_from_match={}, insertion_from_insertion={}, deletion_from_deletion={}, \
+             equal={})",
+            t.match_from_match,
+            t.insertion_from_match,
+            t.deletion_from_match,
+            t.insertion_from_insertion,
+            t.deletion_from_deletion,
+            e.equal
+        )
+    }
+}
+
+// the alignment functions take the parameters for each base of the haplotype, and upper-case
+// sequences as read from the reference and the BAM file
+fn alignment_inputs(
+    read: &str,
+    haplotype: &str,
+    params: &PyAlignmentParameters,
+) -> PyResult<(Vec<u8>, Vec<u8>, Vec<LnAlignmentParameters>)> {
+    if read.is_empty() || haplotype.is_empty() {
+        return Err(PyValueError::new_err(
+            "the read and haplotype sequences must not be empty",
+        ));
+    }
+    check_window_lengths(read.len(), haplotype.len())
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let v: Vec<u8> = read.to_uppercase().into_bytes();
+    let w: Vec<u8> = haplotype.to_uppercase().into_bytes();
+    let ln_params = vec![params.params.ln(); w.len()];
+    Ok((v, w, ln_params))
+}
+
+/// The log probability of a read given a haplotype, summed over all alignments in the band with
+/// the Pair-HMM forward algorithm.
+#[pyfunction]
+#[pyo3(signature = (read, haplotype, params, band_width = 20))]
+fn forward_algorithm(
+    read: &str,
+    haplotype: &str,
+    params: &PyAlignmentParameters,
+    band_width: usize,
+) -> PyResult<f64> {
+    let (v, w, ln_params) = alignment_inputs(read, haplotype, params)?;
+    Ok(*forward_algorithm_numerically_stable(&v, &w, &ln_params, band_width))
+}
+
+/// The log probabilities of the single most likely alignment of a read to a haplotype in the band
+/// (as used with ```--max_alignment```) and of the second most likely alignment, as a tuple.
+#[pyfunction]
+#[pyo3(signature = (read, haplotype, params, band_width = 20))]
+fn viterbi_alignment(
+    read: &str,
+    haplotype: &str,
+    params: &PyAlignmentParameters,
+    band_width: usize,
+) -> PyResult<(f64, f64)> {
+    let (v, w, ln_params) = alignment_inputs(read, haplotype, params)?;
+    let scores = viterbi_max_scoring_alignment(&v, &w, &ln_params, band_width);
+    Ok((*scores.best, *scores.second_best))
+}
+
+/// The genotype log likelihoods of a site from its allele observations, given as (allele index,
+/// probability that the call is an error) pairs. Observations below ```min_allele_qual``` are
+/// ignored, as in the longshot binary. Returns a dict from genotype (allele index pair) to the
+/// log likelihood, normalized over the possible genotypes.
+#[pyfunction]
+#[pyo3(signature = (
+    alleles,
+    calls,
+    ploidy = 2,
+    min_allele_qual = 7.0,
+    hom_snv_rate = 0.0005,
+    het_snv_rate = 0.001,
+    hom_indel_rate = 0.00005,
+    het_indel_rate = 0.00001,
+    ts_tv_ratio = 0.5
+))]
+fn genotype_likelihoods(
+    alleles: Vec<String>,
+    calls: Vec<(u8, f64)>,
+    ploidy: u8,
+    min_allele_qual: f64,
+    hom_snv_rate: f64,
+    het_snv_rate: f64,
+    hom_indel_rate: f64,
+    het_indel_rate: f64,
+    ts_tv_ratio: f64,
+) -> PyResult<HashMap<(u8, u8), f64>> {
+    if alleles.len() < 2 {
+        return Err(PyValueError::new_err(
+            "a site needs the reference allele and at least one alternative allele",
+        ));
+    }
+    if ploidy != 1 && ploidy != 2 {
+        return Err(PyValueError::new_err("ploidy must be 1 or 2"));
+    }
+    let mut pileup: Vec<FragCall> = Vec::with_capacity(calls.len());
+    for (i, &(allele, p_miscall)) in calls.iter().enumerate() {
+        if allele as usize >= alleles.len() {
+            return Err(PyValueError::new_err(format!(
+                "allele index {} out of range for {} alleles",
+                allele,
+                alleles.len()
+            )));
+        }
+        if !(p_miscall >= 0.0 && p_miscall <= 1.0) {
+            return Err(PyValueError::new_err(format!(
+                "miscall probabilities must be between 0 and 1, got {}",
+                p_miscall
+            )));
+        }
+        pileup.push(FragCall::new(i, 0, allele, p_miscall));
+    }
+
+    // the binary doubles the ts/tv ratio given on the command line in the same way
+    let genotype_priors = GenotypePriors::new(
+        LogProb::from(Prob(hom_snv_rate)),
+        LogProb::from(Prob(het_snv_rate)),
+        LogProb::from(Prob(hom_indel_rate)),
+        LogProb::from(Prob(het_indel_rate)),
+        2.0 * ts_tv_ratio,
+    )
+    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let max_p_miscall: f64 = *Prob::from(PHREDProb(min_allele_qual));
+
+    let to_py_err = |e: ::errors::Error| PyValueError::new_err(e.to_string());
+    let posteriors = calculate_genotype_posteriors_no_haplotypes(
+        &pileup,
+        &genotype_priors,
+        &alleles,
+        max_p_miscall,
+        ploidy,
+        &None,
+    )
+    .map_err(to_py_err)?;
+    let priors = genotype_priors
+        .get_all_site_priors(&alleles, ploidy, &None)
+        .map_err(to_py_err)?;
+    let likelihoods = posteriors.likelihoods(&priors);
+
+    Ok(possible_genotypes_with_ploidy(&alleles, ploidy)
+        .into_iter()
+        .map(|g| ((g.0, g.1), *likelihoods.get(g)))
+        .collect())
+}
+
+#[pymodule]
+fn longshot(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyAlignmentParameters>()?;
+    m.add_function(wrap_pyfunction!(forward_algorithm, m)?)?;
+    m.add_function(wrap_pyfunction!(viterbi_alignment, m)?)?;
+    m.add_function(wrap_pyfunction!(genotype_likelihoods, m)?)?;
+    Ok(())
+}
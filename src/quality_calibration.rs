@@ -0,0 +1,438 @@
+//! Recalibration of the variant (QUAL) and genotype (GQ) qualities against a truth set.
+//!
+//! The qualities that Longshot computes from the genotype posteriors are overconfident at the high
+//! end, since the posteriors assume that the reads are independent and that the error model is
+//! exact. With ```--fit_calibration```, the calls of a sample with a known truth set (e.g. a
+//! Genome in a Bottle sample) are compared to the truth in its confident regions, and a monotone
+//! mapping from each raw quality to the error rate that is actually observed at that quality is
+//! fitted by isotonic regression (the pool-adjacent-violators algorithm). The mapping is written to
+//! a calibration file, which later runs on samples of the same data type apply with
+//! ```--calibration```.
+//!
+//! A call is counted as correct for the QUAL calibration if the truth has a variant at the same
+//! position with one of its called alternative alleles, and for the GQ calibration if the truth
+//! has the same (unphased) genotype. The truth must therefore represent the variants like Longshot
+//! does, i.e. normalized (left-aligned) and with MNVs decomposed.
+
+use errors::*;
+use hashbrown::HashMap;
+use rust_htslib::bcf;
+use rust_htslib::bcf::record::GenotypeAllele;
+use rust_htslib::bcf::Read as bcfread;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use util::*;
+use variants_and_fragments::VarList;
+
+/// A monotone mapping from raw qualities to calibrated qualities, as a step function: the raw
+/// qualities up to ```steps[k].raw_max``` (and above ```steps[k - 1].raw_max```) are mapped to
+/// ```steps[k].calibrated```. Raw qualities above the last step get its calibrated quality.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityMap {
+    pub steps: Vec<CalibrationStep>,
+}
+
+/// One step of a ```QualityMap```, pooled from the calls of the truth set
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationStep {
+    pub raw_max: f64,
+    pub calibrated: f64,
+    /// the number of truth set calls and errors that the step was fitted to
+    pub calls: usize,
+    pub errors: usize,
+}
+
+impl QualityMap {
+    /// Fits the mapping to the raw quality of each call and whether the call was an error, by
+    /// isotonic regression of the error rate on the raw quality: adjacent groups of calls (in order
+    /// of raw quality) are pooled until the error rate never increases with the raw quality. The
+    /// calibrated quality of a step is the PHRED-scaled error rate of its calls, with a pseudocount
+    /// of one error and one correct call so that a step without errors doesn't get an infinite
+    /// quality.
+    pub fn fit(calls: &mut Vec<(f64, bool)>) -> QualityMap {
+        calls.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        // (raw_max, calls, errors) of each pooled block
+        let mut blocks: Vec<(f64, usize, usize)> = vec![];
+        for &(raw, error) in calls.iter() {
+            // calls with the same raw quality can't be told apart
+            if blocks.last().map_or(false, |last| last.0 == raw) {
+                let last = blocks.last_mut().unwrap();
+                last.1 += 1;
+                last.2 += error as usize;
+            } else {
+                blocks.push((raw, 1, error as usize));
+            }
+            // pool while the error rate of the last block is higher than that of the one before
+            while blocks.len() >= 2 {
+                let (r1, n1, e1) = blocks[blocks.len() - 1];
+                let (_, n0, e0) = blocks[blocks.len() - 2];
+                if e1 * n0 <= e0 * n1 {
+                    break;
+                }
+                blocks.pop();
+                let last = blocks.last_mut().unwrap();
+                *last = (r1, n0 + n1, e0 + e1);
+            }
+        }
+
+        let mut steps: Vec<CalibrationStep> = vec![];
+        for (raw_max, n, e) in blocks {
+            let error_rate = (e as f64 + 1.0) / (n as f64 + 2.0);
+            let mut calibrated = (-10.0 * error_rate.log10()).min(MAX_VCF_QUAL);
+            // the pseudocount weighs more on small blocks, which must not break the monotonicity
+            if let Some(prev) = steps.last() {
+                calibrated = calibrated.max(prev.calibrated);
+            }
+            steps.push(CalibrationStep {
+                raw_max: raw_max,
+                calibrated: calibrated,
+                calls: n,
+                errors: e,
+            });
+        }
+        QualityMap { steps: steps }
+    }
+
+    pub fn calibrate(&self, raw: f64) -> f64 {
+        match self.steps.iter().find(|s| raw <= s.raw_max) {
+            Some(s) => s.calibrated,
+            None => self.steps.last().map_or(raw, |s| s.calibrated),
+        }
+    }
+}
+
+/// The calibration of the QUAL and GQ fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityCalibration {
+    pub qual: QualityMap,
+    pub gq: QualityMap,
+}
+
+impl QualityCalibration {
+    /// Replaces the QUAL and GQ of every variant with the calibrated qualities
+    pub fn apply(&self, varlist: &mut VarList) {
+        for var in varlist.lst.iter_mut() {
+            var.qual = self.qual.calibrate(var.qual);
+            var.gq = self.gq.calibrate(var.gq);
+        }
+    }
+
+    /// Writes the calibration to a file that can be read with ```from_file```: a header, and a
+    /// line with the field (QUAL or GQ), the upper raw quality, the calibrated quality and the
+    /// number of calls and errors of each step.
+    pub fn write(&self, output_file: &String, truth_vcf: &String) -> Result<()> {
+        let path = Path::new(output_file);
+        let display = path.display();
+        let mut file =
+            File::create(&path).chain_err(|| ErrorKind::CreateFileError(display.to_string()))?;
+
+        writeln!(file, "#longshot quality calibration")
+            .and_then(|_| writeln!(file, "#truth_vcf={}", truth_vcf))
+            .and_then(|_| writeln!(file, "#field\traw_max\tcalibrated\tcalls\terrors"))
+            .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+        for &(field, ref map) in &[("QUAL", &self.qual), ("GQ", &self.gq)] {
+            for s in &map.steps {
+                writeln!(
+                    file,
+                    "{}\t{:.4}\t{:.4}\t{}\t{}",
+                    field, s.raw_max, s.calibrated, s.calls, s.errors
+                )
+                .chain_err(|| ErrorKind::FileWriteError(display.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a calibration file written with ```--fit_calibration```
+    ///
+    /// # Errors
+    /// Fails if the file can't be read, a line is malformed, or it has no steps for QUAL or GQ.
+    pub fn from_file(calibration_file: &String) -> Result<QualityCalibration> {
+        let f = File::open(calibration_file)
+            .chain_err(|| ErrorKind::FileReadError(calibration_file.clone()))?;
+        let mut calibration = QualityCalibration {
+            qual: QualityMap { steps: vec![] },
+            gq: QualityMap { steps: vec![] },
+        };
+        for (line_num, line) in BufReader::new(f).lines().enumerate() {
+            let line = line.chain_err(|| ErrorKind::FileReadError(calibration_file.clone()))?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let cols: Vec<&str> = line.split('\t').collect();
+            let parse_err = || {
+                format!(
+                    "Invalid line {} of the calibration file {}.",
+                    line_num + 1,
+                    calibration_file
+                )
+            };
+            ensure!(cols.len() == 5, parse_err());
+            let step = CalibrationStep {
+                raw_max: cols[1].parse().chain_err(parse_err)?,
+                calibrated: cols[2].parse().chain_err(parse_err)?,
+                calls: cols[3].parse().chain_err(parse_err)?,
+                errors: cols[4].parse().chain_err(parse_err)?,
+            };
+            match cols[0] {
+                "QUAL" => calibration.qual.steps.push(step),
+                "GQ" => calibration.gq.steps.push(step),
+                _ => bail!(parse_err()),
+            }
+        }
+        ensure!(
+            !calibration.qual.steps.is_empty() && !calibration.gq.steps.is_empty(),
+            "The calibration file {} has no QUAL or no GQ calibration.",
+            calibration_file
+        );
+        Ok(calibration)
+    }
+}
+
+/// The unphased genotype of a truth variant, as the sorted allele sequences
+pub type TruthGenotype = Vec<String>;
+
+/// Reads the genotypes of one sample from a truth VCF, skipping homozygous reference and missing
+/// genotypes
+///
+/// # Arguments
+/// - ```truth_vcf```: path to the truth VCF
+/// - ```sample_name```: the sample to read. If the truth VCF has a single sample, it is used
+///   regardless of its name.
+/// - ```target_names```: the contig names of the BAM file, used to convert contig names to TIDs
+///
+/// # Returns
+/// Returns a map from (tid, 0-based position) to the truth genotype, or ```None``` if the truth VCF
+/// does not contain the sample.
+///
+/// # Errors
+/// - ```BCFOpenError```: error opening the truth VCF
+/// - ```BCFReadError```: error reading a record or its genotype from the truth VCF
+pub fn parse_truth_genotypes(
+    truth_vcf: &String,
+    sample_name: &String,
+    target_names: &Vec<String>,
+) -> Result<Option<HashMap<(u32, usize), TruthGenotype>>> {
+    let mut vcf = bcf::Reader::from_path(truth_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(truth_vcf.clone()))?;
+    let vcfh = bcf::Reader::from_path(truth_vcf)
+        .chain_err(|| ErrorKind::BCFOpenError(truth_vcf.clone()))?;
+
+    let sample_ix = if vcfh.header().sample_count() == 1 {
+        0
+    } else {
+        match vcfh.header().sample_id(sample_name.as_bytes()) {
+            Some(ix) => ix,
+            None => {
+                return Ok(None);
+            }
+        }
+    };
+
+    let chrom2tid: HashMap<&String, u32> = target_names
+        .iter()
+        .enumerate()
+        .map(|(t, name)| (name, t as u32))
+        .collect();
+
+    let mut truth: HashMap<(u32, usize), TruthGenotype> = HashMap::new();
+    for r in vcf.records() {
+        let mut record = r.chain_err(|| ErrorKind::BCFReadError)?;
+        let rid = record.rid().chain_err(|| "Error accessing vcf RID")?;
+        let chrom: String = u8_to_string(
+            vcfh.header()
+                .rid2name(rid)
+                .chain_err(|| ErrorKind::BCFReadError)?,
+        )?;
+        let tid = match chrom2tid.get(&chrom) {
+            Some(&tid) => tid,
+            None => {
+                continue;
+            }
+        };
+
+        let alleles: Vec<String> = record
+            .alleles()
+            .iter()
+            .map(|a| u8_to_string(a))
+            .collect::<Result<Vec<String>>>()?;
+        let gt = record
+            .genotypes()
+            .chain_err(|| ErrorKind::BCFReadError)?
+            .get(sample_ix);
+
+        let mut genotype: TruthGenotype = vec![];
+        for a in gt.iter() {
+            match *a {
+                GenotypeAllele::Unphased(i) | GenotypeAllele::Phased(i)
+                    if (i as usize) < alleles.len() =>
+                {
+                    genotype.push(alleles[i as usize].clone())
+                }
+                _ => {}
+            }
+        }
+        if genotype.is_empty() || genotype.iter().all(|a| a == &alleles[0]) {
+            continue;
+        }
+        genotype.sort();
+        truth.insert((tid, record.pos() as usize), genotype);
+    }
+
+    Ok(Some(truth))
+}
+
+/// Whether a position is in one of the merged, sorted regions
+fn in_regions(regions: &Vec<GenomicInterval>, tid: u32, pos: usize) -> bool {
+    regions
+        .binary_search_by(|r| {
+            if (r.tid, r.end_pos as usize) < (tid, pos) {
+                Ordering::Less
+            } else if (r.tid, r.start_pos as usize) > (tid, pos) {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// The raw qualities of the calls of a sample in the confident regions, and whether each call is
+/// an error, for fitting a ```QualityCalibration```
+#[derive(Debug, Clone, Default)]
+pub struct CalibrationCalls {
+    /// (QUAL, whether no called alternative allele is in the truth)
+    pub qual: Vec<(f64, bool)>,
+    /// (GQ, whether the genotype differs from the truth)
+    pub gq: Vec<(f64, bool)>,
+}
+
+impl CalibrationCalls {
+    /// Compares the variant calls (other than homozygous reference) of a sample in the confident
+    /// regions to the truth genotypes, and adds their qualities
+    pub fn add_varlist(
+        &mut self,
+        varlist: &VarList,
+        truth: &HashMap<(u32, usize), TruthGenotype>,
+        confident_regions: &Option<Vec<GenomicInterval>>,
+    ) {
+        for var in &varlist.lst {
+            if var.genotype.0 == 0 && var.genotype.1 == 0 {
+                continue;
+            }
+            if let &Some(ref regions) = confident_regions {
+                if !in_regions(regions, var.tid, var.pos0) {
+                    continue;
+                }
+            }
+            let mut called: TruthGenotype = [var.genotype.0, var.genotype.1]
+                .iter()
+                .map(|&a| var.alleles[a as usize].clone())
+                .collect();
+            called.sort();
+
+            let (qual_error, gq_error) = match truth.get(&(var.tid, var.pos0)) {
+                Some(genotype) => (
+                    !called
+                        .iter()
+                        .any(|a| a != &var.alleles[0] && genotype.contains(a)),
+                    &called != genotype,
+                ),
+                None => (true, true),
+            };
+            self.qual.push((var.qual, qual_error));
+            self.gq.push((var.gq, gq_error));
+        }
+    }
+
+    /// Fits the calibration to the calls
+    ///
+    /// # Errors
+    /// Fails if there are no calls to fit to.
+    pub fn fit(&mut self) -> Result<QualityCalibration> {
+        ensure!(
+            !self.qual.is_empty(),
+            "No variant calls in the confident regions of the truth set to fit the quality calibration to."
+        );
+        Ok(QualityCalibration {
+            qual: QualityMap::fit(&mut self.qual),
+            gq: QualityMap::fit(&mut self.gq),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_map_fit() {
+        // the error rate goes down with the raw quality, except for the violation at 30
+        let mut calls: Vec<(f64, bool)> = vec![];
+        for &(raw, n, errors) in &[(10.0, 10, 5), (20.0, 10, 1), (30.0, 10, 3), (40.0, 98, 0)] {
+            for i in 0..n {
+                calls.push((raw, i < errors));
+            }
+        }
+        let map = QualityMap::fit(&mut calls);
+        let pooled: Vec<(f64, usize, usize)> = map
+            .steps
+            .iter()
+            .map(|s| (s.raw_max, s.calls, s.errors))
+            .collect();
+        assert_eq!(pooled, vec![(10.0, 10, 5), (30.0, 20, 4), (40.0, 98, 0)]);
+        // 6 / 12 errors with the pseudocounts, then 5 / 22 and 1 / 100
+        assert!((map.steps[0].calibrated - 3.0103).abs() < 1e-3);
+        assert!((map.steps[1].calibrated - 6.4345).abs() < 1e-3);
+        assert!((map.steps[2].calibrated - 20.0).abs() < 1e-3);
+
+        assert_eq!(map.calibrate(5.0), map.steps[0].calibrated);
+        assert_eq!(map.calibrate(20.0), map.steps[1].calibrated);
+        assert_eq!(map.calibrate(30.5), map.steps[2].calibrated);
+        // raw qualities above the truth set calls get the highest calibrated quality
+        assert_eq!(map.calibrate(500.0), map.steps[2].calibrated);
+    }
+
+    #[test]
+    fn test_calibration_file() {
+        let calibration = QualityCalibration {
+            qual: QualityMap::fit(&mut vec![(5.0, true), (50.0, false), (60.0, false)]),
+            gq: QualityMap::fit(&mut vec![(3.0, false), (3.0, true), (40.0, false)]),
+        };
+        let path = std::env::temp_dir()
+            .join(format!("longshot_test_calibration_{}.txt", std::process::id()))
+            .display()
+            .to_string();
+        calibration.write(&path, &"truth.vcf".to_string()).unwrap();
+        let read = QualityCalibration::from_file(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(read.qual.steps.len(), calibration.qual.steps.len());
+        assert_eq!(read.gq.steps.len(), 2);
+        for (a, b) in read.gq.steps.iter().zip(calibration.gq.steps.iter()) {
+            assert!((a.calibrated - b.calibrated).abs() < 1e-3);
+            assert_eq!((a.calls, a.errors), (b.calls, b.errors));
+        }
+    }
+
+    #[test]
+    fn test_in_regions() {
+        let iv = |tid: u32, start_pos: u32, end_pos: u32| GenomicInterval {
+            tid: tid,
+            chrom: String::new(),
+            start_pos: start_pos,
+            end_pos: end_pos,
+        };
+        let regions = vec![iv(0, 10, 20), iv(0, 30, 40), iv(2, 0, 5)];
+        assert!(in_regions(&regions, 0, 10));
+        assert!(in_regions(&regions, 0, 20));
+        assert!(!in_regions(&regions, 0, 25));
+        assert!(in_regions(&regions, 0, 35));
+        assert!(!in_regions(&regions, 1, 35));
+        assert!(in_regions(&regions, 2, 5));
+        assert!(!in_regions(&regions, 2, 6));
+    }
+}
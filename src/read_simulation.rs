@@ -0,0 +1,643 @@
+//! Simulation of a reference, phased variants and long reads aligned to the reference, for
+//! end-to-end tests and sensitivity sweeps (```longshot simulate```).
+//!
+//! The reference is a random sequence, with SNVs and short indels planted on two haplotypes at a
+//! given rate. The reads are sampled uniformly from the haplotypes and get sequencing errors from
+//! the same pair HMM that the caller realigns them with (```AlignmentParameters```): every base of
+//! the haplotype is read in the match state, where it is equal to the haplotype base with the
+//! ```equal``` emission probability, and the insertion and deletion states add random bases and
+//! skip haplotype bases. The reads are written with their true alignment to the reference, so
+//! the output is a reference FASTA, a sorted and indexed BAM file and a truth VCF with the phased
+//! genotypes, which can be given to ```--truth_vcf```.
+
+use errors::*;
+use rand::{Rng, SeedableRng, StdRng};
+use realignment::AlignmentParameters;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use util::print_time;
+
+/// The name of the simulated contig, and the sample name of the simulated reads
+pub static SIMULATED_CONTIG: &str = "sim";
+pub static SIMULATED_SAMPLE: &str = "sim";
+
+/// Planted variants are at least this many bases apart, and this far from the reference ends
+static MIN_VARIANT_SPACING: usize = 20;
+/// The base quality of the simulated reads (longshot does not use them for realignment)
+static SIMULATED_BASE_QUAL: u8 = 20;
+/// The line length of the simulated reference FASTA
+static FASTA_LINE_LEN: usize = 60;
+/// The longest homopolymer run of the haplotype windows of ```simulate_read_window```
+static MAX_HOMOPOLYMER_LEN: usize = 8;
+
+static BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+#[derive(Clone, Copy)]
+pub struct ReadSimulationParameters {
+    pub ref_len: usize,
+    /// probability that a reference position has a planted SNV, and a planted indel
+    pub snv_rate: f64,
+    pub indel_rate: f64,
+    /// planted indels are 1 to ```max_indel_len``` bases long
+    pub max_indel_len: usize,
+    /// fraction of the planted variants that are homozygous
+    pub hom_frac: f64,
+    /// mean depth of the reads
+    pub coverage: f64,
+    /// the read lengths are uniform between half and 1.5 times the mean length
+    pub mean_read_len: usize,
+    /// the sequencing error model
+    pub alignment_params: AlignmentParameters,
+    pub seed: usize,
+}
+
+/// A planted variant. ```genotype``` is the allele of each haplotype (0 or 1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedVariant {
+    /// 0-based position of the reference allele
+    pub pos0: usize,
+    pub ref_allele: Vec<u8>,
+    pub alt_allele: Vec<u8>,
+    pub genotype: (u8, u8),
+}
+
+/// A simulated read with its true alignment to the reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedRead {
+    pub name: String,
+    /// 0-based position of the first aligned base
+    pub pos0: usize,
+    pub cigar: Vec<Cigar>,
+    pub seq: Vec<u8>,
+    pub reverse_strand: bool,
+}
+
+// a haplotype as a sequence of bases with the reference position of each base (None for the
+// bases inserted relative to the reference)
+type Haplotype = Vec<(u8, Option<usize>)>;
+
+fn random_base(rng: &mut StdRng) -> u8 {
+    BASES[rng.gen_range(0, 4)]
+}
+
+fn random_other_base(rng: &mut StdRng, base: u8) -> u8 {
+    loop {
+        let b = random_base(rng);
+        if b != base {
+            return b;
+        }
+    }
+}
+
+// plants the variants on the reference. indels are only planted where they are left-aligned
+// (the base before the indel differs from its last inserted or deleted base), so that the truth
+// has the same representation as the calls.
+fn plant_variants(
+    reference: &[u8],
+    params: &ReadSimulationParameters,
+    rng: &mut StdRng,
+) -> Vec<SimulatedVariant> {
+    let mut variants: Vec<SimulatedVariant> = vec![];
+    let mut last_end = 0;
+    let mut pos0 = MIN_VARIANT_SPACING;
+    while pos0 + params.max_indel_len + MIN_VARIANT_SPACING < reference.len() {
+        if pos0 < last_end + MIN_VARIANT_SPACING {
+            pos0 += 1;
+            continue;
+        }
+        let r: f64 = rng.gen();
+        let (ref_allele, alt_allele) = if r < params.snv_rate {
+            (
+                vec![reference[pos0]],
+                vec![random_other_base(rng, reference[pos0])],
+            )
+        } else if r < params.snv_rate + params.indel_rate && params.max_indel_len > 0 {
+            let len = rng.gen_range(1, params.max_indel_len + 1);
+            if rng.gen::<bool>() {
+                let deleted = &reference[pos0 + 1..pos0 + 1 + len];
+                if deleted[len - 1] == reference[pos0] {
+                    pos0 += 1;
+                    continue;
+                }
+                (reference[pos0..pos0 + 1 + len].to_vec(), vec![reference[pos0]])
+            } else {
+                let inserted: Vec<u8> = (0..len).map(|_| random_base(rng)).collect();
+                if inserted[len - 1] == reference[pos0] {
+                    pos0 += 1;
+                    continue;
+                }
+                let mut alt_allele = vec![reference[pos0]];
+                alt_allele.extend_from_slice(&inserted);
+                (vec![reference[pos0]], alt_allele)
+            }
+        } else {
+            pos0 += 1;
+            continue;
+        };
+
+        let genotype = if rng.gen::<f64>() < params.hom_frac {
+            (1, 1)
+        } else if rng.gen::<bool>() {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        last_end = pos0 + ref_allele.len();
+        variants.push(SimulatedVariant {
+            pos0: pos0,
+            ref_allele: ref_allele,
+            alt_allele: alt_allele,
+            genotype: genotype,
+        });
+        pos0 = last_end;
+    }
+    variants
+}
+
+// applies the alleles of haplotype ```h``` of the variants to the reference
+fn build_haplotype(reference: &[u8], variants: &[SimulatedVariant], h: usize) -> Haplotype {
+    let mut hap: Haplotype = Vec::with_capacity(reference.len());
+    let mut pos0 = 0;
+    for var in variants {
+        let allele = if h == 0 { var.genotype.0 } else { var.genotype.1 };
+        if allele == 0 {
+            continue;
+        }
+        while pos0 < var.pos0 {
+            hap.push((reference[pos0], Some(pos0)));
+            pos0 += 1;
+        }
+        // the first base of the alternate allele takes the place of the first reference base
+        // (the same base for an indel), and the other bases are inserted
+        hap.push((var.alt_allele[0], Some(pos0)));
+        for &b in &var.alt_allele[1..] {
+            hap.push((b, None));
+        }
+        pos0 = var.pos0 + var.ref_allele.len();
+    }
+    while pos0 < reference.len() {
+        hap.push((reference[pos0], Some(pos0)));
+        pos0 += 1;
+    }
+    hap
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HmmState {
+    Match,
+    Insertion,
+    Deletion,
+}
+
+// reads a stretch of a haplotype with sequencing errors from the pair HMM. returns the read bases
+// with their reference positions.
+fn add_sequencing_errors(
+    bases: &[(u8, Option<usize>)],
+    params: &AlignmentParameters,
+    rng: &mut StdRng,
+) -> Vec<(u8, Option<usize>)> {
+    let t = &params.transition_probs;
+    let mut read: Vec<(u8, Option<usize>)> = Vec::with_capacity(bases.len() * 11 / 10);
+    let mut state = HmmState::Match;
+    let mut i = 0;
+    while i < bases.len() {
+        let r: f64 = rng.gen();
+        state = match state {
+            HmmState::Match if r < t.insertion_from_match => HmmState::Insertion,
+            HmmState::Match if r < t.insertion_from_match + t.deletion_from_match => {
+                HmmState::Deletion
+            }
+            HmmState::Insertion if r < t.insertion_from_insertion => HmmState::Insertion,
+            HmmState::Deletion if r < t.deletion_from_deletion => HmmState::Deletion,
+            _ => HmmState::Match,
+        };
+        match state {
+            HmmState::Match => {
+                let (base, ref_pos) = bases[i];
+                if rng.gen::<f64>() < params.emission_probs.equal {
+                    read.push((base, ref_pos));
+                } else {
+                    read.push((random_other_base(rng, base), ref_pos));
+                }
+                i += 1;
+            }
+            HmmState::Insertion => {
+                read.push((random_base(rng), None));
+            }
+            HmmState::Deletion => {
+                i += 1;
+            }
+        }
+    }
+    read
+}
+
+/// Converts read bases with their reference positions to an alignment: the position of the
+/// first aligned base and the CIGAR. Inserted bases at the ends of the read are removed.
+///
+/// # Returns
+/// Returns ```None``` if no base of the read is aligned to the reference
+fn read_alignment(read: &[(u8, Option<usize>)]) -> Option<(usize, Vec<Cigar>, Vec<u8>)> {
+    let first = read.iter().position(|&(_, p)| p.is_some())?;
+    let last = read.iter().rposition(|&(_, p)| p.is_some())?;
+    let read = &read[first..last + 1];
+
+    let mut cigar: Vec<Cigar> = vec![];
+    let push = |cigar: &mut Vec<Cigar>, op: Cigar| {
+        let merged = match (cigar.last(), &op) {
+            (Some(&Cigar::Match(a)), &Cigar::Match(b)) => Some(Cigar::Match(a + b)),
+            (Some(&Cigar::Ins(a)), &Cigar::Ins(b)) => Some(Cigar::Ins(a + b)),
+            (Some(&Cigar::Del(a)), &Cigar::Del(b)) => Some(Cigar::Del(a + b)),
+            _ => None,
+        };
+        match merged {
+            Some(m) => {
+                cigar.pop();
+                cigar.push(m);
+            }
+            None => cigar.push(op),
+        }
+    };
+    let mut last_ref_pos: Option<usize> = None;
+    for &(_, ref_pos) in read {
+        match ref_pos {
+            Some(p) => {
+                if let Some(q) = last_ref_pos {
+                    if p > q + 1 {
+                        push(&mut cigar, Cigar::Del((p - q - 1) as u32));
+                    }
+                }
+                push(&mut cigar, Cigar::Match(1));
+                last_ref_pos = Some(p);
+            }
+            None => push(&mut cigar, Cigar::Ins(1)),
+        }
+    }
+    let seq: Vec<u8> = read.iter().map(|&(b, _)| b).collect();
+    Some((read[0].1.unwrap(), cigar, seq))
+}
+
+/// Simulates the reference, the planted variants and the reads
+///
+/// # Returns
+/// Returns the reference sequence, the variants (sorted by position) and the reads (sorted by
+/// their alignment position). The simulation is deterministic for a given ```params.seed```.
+pub fn simulate_reads(
+    params: &ReadSimulationParameters,
+) -> (Vec<u8>, Vec<SimulatedVariant>, Vec<SimulatedRead>) {
+    let mut rng: StdRng = StdRng::from_seed(&[params.seed]);
+    let reference: Vec<u8> = (0..params.ref_len).map(|_| random_base(&mut rng)).collect();
+    let variants = plant_variants(&reference, params, &mut rng);
+    let haplotypes: [Haplotype; 2] = [
+        build_haplotype(&reference, &variants, 0),
+        build_haplotype(&reference, &variants, 1),
+    ];
+
+    let min_len = (params.mean_read_len / 2).max(1);
+    let max_len = params.mean_read_len + min_len;
+    let num_reads =
+        (params.coverage * params.ref_len as f64 / params.mean_read_len as f64).round() as usize;
+    let mut reads: Vec<SimulatedRead> = Vec::with_capacity(num_reads);
+    for r in 0..num_reads {
+        let h = rng.gen_range(0, 2);
+        let hap = &haplotypes[h];
+        let len = rng.gen_range(min_len, max_len + 1).min(hap.len());
+        let start = rng.gen_range(0, hap.len() - len + 1);
+        let read_bases = add_sequencing_errors(
+            &hap[start..start + len],
+            &params.alignment_params,
+            &mut rng,
+        );
+        if let Some((pos0, cigar, seq)) = read_alignment(&read_bases) {
+            reads.push(SimulatedRead {
+                // the haplotype in the read name, e.g. to check haplotagged reads
+                name: format!("sim_read_{}_hap{}", r, h + 1),
+                pos0: pos0,
+                cigar: cigar,
+                seq: seq,
+                reverse_strand: rng.gen::<bool>(),
+            });
+        }
+    }
+    reads.sort_by_key(|read| read.pos0);
+    (reference, variants, reads)
+}
+
+/// Simulates a haplotype window of ```len``` bases and a read of it with sequencing errors from
+/// the pair HMM, e.g. as a fixture for benchmarks of the alignment functions. With
+/// ```homopolymers```, the window is made of homopolymer runs of 1 to ```MAX_HOMOPOLYMER_LEN```
+/// bases, as in the windows where most nanopore and CLR errors occur.
+///
+/// # Returns
+/// Returns the read and the haplotype window. The simulation is deterministic for a given
+/// ```seed```.
+pub fn simulate_read_window(
+    len: usize,
+    params: &AlignmentParameters,
+    homopolymers: bool,
+    seed: usize,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut rng: StdRng = StdRng::from_seed(&[seed]);
+    let mut window: Vec<u8> = Vec::with_capacity(len);
+    while window.len() < len {
+        if homopolymers {
+            let base = match window.last() {
+                Some(&b) => random_other_base(&mut rng, b),
+                None => random_base(&mut rng),
+            };
+            let run_len = rng.gen_range(1, MAX_HOMOPOLYMER_LEN + 1);
+            window.extend(vec![base; run_len.min(len - window.len())]);
+        } else {
+            window.push(random_base(&mut rng));
+        }
+    }
+
+    let bases: Vec<(u8, Option<usize>)> = window
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| (b, Some(i)))
+        .collect();
+    let read: Vec<u8> = add_sequencing_errors(&bases, params, &mut rng)
+        .iter()
+        .map(|&(b, _)| b)
+        .collect();
+    (read, window)
+}
+
+// writes the reference FASTA and its index
+fn write_reference(reference: &[u8], fasta_file: &Path) -> Result<()> {
+    let write_error = || ErrorKind::FileWriteError(fasta_file.display().to_string());
+    let mut fasta = File::create(fasta_file)
+        .chain_err(|| ErrorKind::CreateFileError(fasta_file.display().to_string()))?;
+    let header = format!(">{}\n", SIMULATED_CONTIG);
+    fasta.write_all(header.as_bytes()).chain_err(&write_error)?;
+    for line in reference.chunks(FASTA_LINE_LEN) {
+        fasta.write_all(line).chain_err(&write_error)?;
+        fasta.write_all(b"\n").chain_err(&write_error)?;
+    }
+
+    let fai_file = format!("{}.fai", fasta_file.display());
+    let mut fai =
+        File::create(&fai_file).chain_err(|| ErrorKind::CreateFileError(fai_file.clone()))?;
+    writeln!(
+        fai,
+        "{}\t{}\t{}\t{}\t{}",
+        SIMULATED_CONTIG,
+        reference.len(),
+        header.len(),
+        FASTA_LINE_LEN,
+        FASTA_LINE_LEN + 1
+    )
+    .chain_err(|| ErrorKind::FileWriteError(fai_file.clone()))?;
+    Ok(())
+}
+
+// writes the reads to a BAM file and indexes it
+fn write_reads(reads: &[SimulatedRead], ref_len: usize, bam_file: &Path) -> Result<()> {
+    let mut header = bam::Header::new();
+    header.push_record(
+        bam::header::HeaderRecord::new(b"HD")
+            .push_tag(b"VN", &"1.6")
+            .push_tag(b"SO", &"coordinate"),
+    );
+    header.push_record(
+        bam::header::HeaderRecord::new(b"SQ")
+            .push_tag(b"SN", &SIMULATED_CONTIG)
+            .push_tag(b"LN", &ref_len),
+    );
+    header.push_record(
+        bam::header::HeaderRecord::new(b"RG")
+            .push_tag(b"ID", &SIMULATED_SAMPLE)
+            .push_tag(b"SM", &SIMULATED_SAMPLE),
+    );
+    {
+        let mut writer = bam::Writer::from_path(bam_file, &header, bam::Format::BAM)
+            .chain_err(|| ErrorKind::CreateFileError(bam_file.display().to_string()))?;
+        for read in reads {
+            let mut record = bam::Record::new();
+            let qual: Vec<u8> = vec![SIMULATED_BASE_QUAL; read.seq.len()];
+            record.set(
+                read.name.as_bytes(),
+                Some(&CigarString(read.cigar.clone())),
+                &read.seq,
+                &qual,
+            );
+            record.set_tid(0);
+            record.set_pos(read.pos0 as i32);
+            record.set_mapq(60);
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            // BAM_FREVERSE
+            record.set_flags(if read.reverse_strand { 16 } else { 0 });
+            record.push_aux(b"RG", &Aux::String(SIMULATED_SAMPLE.as_bytes()));
+            writer
+                .write(&record)
+                .chain_err(|| ErrorKind::FileWriteError(bam_file.display().to_string()))?;
+        }
+    }
+    bam::index::build(bam_file, None, bam::index::Type::BAI, 1)
+        .chain_err(|| format!("Error indexing simulated BAM file {}.", bam_file.display()))?;
+    Ok(())
+}
+
+// writes the planted variants with their phased genotypes
+fn write_truth_vcf(variants: &[SimulatedVariant], ref_len: usize, vcf_file: &Path) -> Result<()> {
+    let write_error = || ErrorKind::FileWriteError(vcf_file.display().to_string());
+    let mut vcf = File::create(vcf_file)
+        .chain_err(|| ErrorKind::CreateFileError(vcf_file.display().to_string()))?;
+    writeln!(vcf, "##fileformat=VCFv4.2").chain_err(&write_error)?;
+    writeln!(vcf, "##contig=<ID={},length={}>", SIMULATED_CONTIG, ref_len)
+        .chain_err(&write_error)?;
+    writeln!(
+        vcf,
+        "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">"
+    )
+    .chain_err(&write_error)?;
+    writeln!(
+        vcf,
+        "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}",
+        SIMULATED_SAMPLE
+    )
+    .chain_err(&write_error)?;
+    for var in variants {
+        writeln!(
+            vcf,
+            "{}\t{}\t.\t{}\t{}\t.\tPASS\t.\tGT\t{}|{}",
+            SIMULATED_CONTIG,
+            var.pos0 + 1,
+            String::from_utf8_lossy(&var.ref_allele),
+            String::from_utf8_lossy(&var.alt_allele),
+            var.genotype.0,
+            var.genotype.1
+        )
+        .chain_err(&write_error)?;
+    }
+    Ok(())
+}
+
+/// Simulates a dataset and writes it to ```out_dir```: the reference (```ref.fa``` and its
+/// index), the reads aligned to it (```reads.bam``` and its index) and the planted variants with
+/// their phased genotypes (```truth.vcf```)
+///
+/// # Errors
+/// - ```CreateFileError```, ```FileWriteError```: error writing an output file
+/// - Error if the BAM file can't be indexed
+pub fn write_simulated_dataset(params: &ReadSimulationParameters, out_dir: &Path) -> Result<()> {
+    let (reference, variants, reads) = simulate_reads(params);
+    write_reference(&reference, &out_dir.join("ref.fa"))?;
+    write_reads(&reads, reference.len(), &out_dir.join("reads.bam"))?;
+    write_truth_vcf(&variants, reference.len(), &out_dir.join("truth.vcf"))?;
+    info!(
+        "{} Simulated {} variants and {} reads on a {} bp reference in {}.",
+        print_time(),
+        variants.len(),
+        reads.len(),
+        reference.len(),
+        out_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use realignment::{EmissionProbs, TransitionProbs};
+
+    fn error_free_params() -> AlignmentParameters {
+        AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 1.0,
+                insertion_from_match: 0.0,
+                deletion_from_match: 0.0,
+                insertion_from_insertion: 0.0,
+                match_from_insertion: 1.0,
+                deletion_from_deletion: 0.0,
+                match_from_deletion: 1.0,
+            },
+            emission_probs: EmissionProbs {
+                equal: 1.0,
+                not_equal: 0.0,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_build_haplotype_and_read_alignment() {
+        let reference = b"ACGTACGTAC".to_vec();
+        let variants = vec![
+            SimulatedVariant {
+                pos0: 1,
+                ref_allele: b"C".to_vec(),
+                alt_allele: b"T".to_vec(),
+                genotype: (1, 1),
+            },
+            SimulatedVariant {
+                pos0: 3,
+                ref_allele: b"TAC".to_vec(),
+                alt_allele: b"T".to_vec(),
+                genotype: (0, 1),
+            },
+            SimulatedVariant {
+                pos0: 7,
+                ref_allele: b"T".to_vec(),
+                alt_allele: b"TGG".to_vec(),
+                genotype: (0, 1),
+            },
+        ];
+        let hap = build_haplotype(&reference, &variants, 1);
+        let seq: Vec<u8> = hap.iter().map(|&(b, _)| b).collect();
+        assert_eq!(seq, b"ATGTGTGGAC".to_vec());
+
+        let (pos0, cigar, read_seq) = read_alignment(&hap).unwrap();
+        assert_eq!(pos0, 0);
+        assert_eq!(
+            cigar,
+            vec![
+                Cigar::Match(4),
+                Cigar::Del(2),
+                Cigar::Match(2),
+                Cigar::Ins(2),
+                Cigar::Match(2),
+            ]
+        );
+        assert_eq!(read_seq, seq);
+
+        // inserted bases at the ends are removed
+        let (pos0, cigar, read_seq) = read_alignment(&hap[8..]).unwrap();
+        assert_eq!((pos0, cigar, read_seq), (8, vec![Cigar::Match(2)], b"AC".to_vec()));
+        assert_eq!(read_alignment(&hap[6..8]), None);
+    }
+
+    #[test]
+    fn test_simulate_reads() {
+        let params = ReadSimulationParameters {
+            ref_len: 5000,
+            snv_rate: 0.01,
+            indel_rate: 0.002,
+            max_indel_len: 3,
+            hom_frac: 0.3,
+            coverage: 10.0,
+            mean_read_len: 1000,
+            alignment_params: error_free_params(),
+            seed: 1,
+        };
+        let (reference, variants, reads) = simulate_reads(&params);
+        assert_eq!(reference.len(), 5000);
+        assert!(!variants.is_empty());
+        assert_eq!(reads.len(), 50);
+        assert!(variants.windows(2).all(|w| w[0].pos0 < w[1].pos0));
+        assert!(reads.windows(2).all(|w| w[0].pos0 <= w[1].pos0));
+
+        // without sequencing errors, the reads only differ from the reference at the variants
+        for read in &reads {
+            let mut ref_pos = read.pos0;
+            let mut read_pos = 0;
+            for op in &read.cigar {
+                match *op {
+                    Cigar::Match(l) => {
+                        for _ in 0..l {
+                            if read.seq[read_pos] != reference[ref_pos] {
+                                assert!(variants.iter().any(|v| v.pos0 == ref_pos));
+                            }
+                            ref_pos += 1;
+                            read_pos += 1;
+                        }
+                    }
+                    Cigar::Ins(l) => read_pos += l as usize,
+                    Cigar::Del(l) => ref_pos += l as usize,
+                    _ => panic!("unexpected CIGAR operation"),
+                }
+            }
+            assert_eq!(read_pos, read.seq.len());
+        }
+
+        // the simulation is deterministic
+        assert_eq!(simulate_reads(&params).2, reads);
+    }
+
+    #[test]
+    fn test_simulate_read_window() {
+        let (read, window) = simulate_read_window(200, &error_free_params(), true, 1);
+        assert_eq!(window.len(), 200);
+        assert_eq!(read, window);
+        // the homopolymer runs are at most MAX_HOMOPOLYMER_LEN bases long
+        let mut run_len = 1;
+        for w in window.windows(2) {
+            run_len = if w[0] == w[1] { run_len + 1 } else { 1 };
+            assert!(run_len <= MAX_HOMOPOLYMER_LEN);
+        }
+
+        // the simulation is deterministic
+        assert_eq!(
+            simulate_read_window(200, &error_free_params(), true, 1),
+            (read, window)
+        );
+        let (_, window) = simulate_read_window(200, &error_free_params(), false, 1);
+        assert_eq!(window.len(), 200);
+    }
+}
@@ -2,14 +2,61 @@
 // original author: Ben Pullman
 // modified: Peter Edge, September 2017
 
+use alphabet::{Alphabet, BaseMatch};
 use bio::stats::{LogProb, Prob};
+use errors::*;
+use estimate_alignment_parameters::AlignmentState;
+use hashbrown::HashMap;
+use std::f32;
 use std::f64;
+use std::mem;
+use telemetry::count_alignment;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum AlignmentType {
     ForwardAlgorithmNonNumericallyStable,
     ForwardAlgorithmNumericallyStable,
+    ForwardAlgorithmNumericallyStableF32,
+    ForwardAlgorithmRunLength,
     ViterbiMaxScoringAlignment,
+    EditDistance,
+}
+
+/// The names of the realignment models, for ```--realignment_model```, in the order of
+/// ```AlignmentType```
+pub static REALIGNMENT_MODEL_NAMES: [&str; 6] = [
+    "forward",
+    "forward-logspace",
+    "forward-f32",
+    "forward-rle",
+    "viterbi",
+    "edit-distance",
+];
+
+impl AlignmentType {
+    pub fn from_name(name: &str) -> Option<AlignmentType> {
+        match name {
+            "forward" => Some(AlignmentType::ForwardAlgorithmNonNumericallyStable),
+            "forward-logspace" => Some(AlignmentType::ForwardAlgorithmNumericallyStable),
+            "forward-f32" => Some(AlignmentType::ForwardAlgorithmNumericallyStableF32),
+            "forward-rle" => Some(AlignmentType::ForwardAlgorithmRunLength),
+            "viterbi" => Some(AlignmentType::ViterbiMaxScoringAlignment),
+            "edit-distance" => Some(AlignmentType::EditDistance),
+            _ => None,
+        }
+    }
+
+    /// the model that scores the reads against the haplotypes
+    pub fn model(&self) -> &'static dyn RealignmentModel {
+        match *self {
+            AlignmentType::ForwardAlgorithmNonNumericallyStable => &PairHmmForward,
+            AlignmentType::ForwardAlgorithmNumericallyStable => &PairHmmForwardLogSpace,
+            AlignmentType::ForwardAlgorithmNumericallyStableF32 => &PairHmmForwardF32,
+            AlignmentType::ForwardAlgorithmRunLength => &PairHmmForwardRunLength,
+            AlignmentType::ViterbiMaxScoringAlignment => &PairHmmViterbi,
+            AlignmentType::EditDistance => &EditDistance,
+        }
+    }
 }
 
 // these parameters describe state transition probabilities for a pair HMM
@@ -102,14 +149,190 @@ impl AlignmentParameters {
     }
 }
 
+/// Alignment parameters estimated separately for the reads aligned to the forward strand and the
+/// reads aligned to the reverse strand.
+#[derive(Clone, Copy)]
+pub struct StrandAlignmentParameters {
+    pub forward: AlignmentParameters,
+    pub reverse: AlignmentParameters,
+}
+
+impl StrandAlignmentParameters {
+    /// Returns the parameters for a read aligned to the reverse strand if ```reverse``` is true,
+    /// or to the forward strand otherwise.
+    pub fn for_strand(&self, reverse: bool) -> AlignmentParameters {
+        if reverse {
+            self.reverse
+        } else {
+            self.forward
+        }
+    }
+}
+
+/// Alignment parameters estimated separately for the reads of each read group (RG tag), e.g. for a
+/// BAM file that mixes reads from different flowcells or sequencing chemistries.
+#[derive(Clone)]
+pub struct ReadGroupAlignmentParameters {
+    /// the parameters for each read group ID
+    pub params: HashMap<String, AlignmentParameters>,
+}
+
+impl ReadGroupAlignmentParameters {
+    /// Returns the parameters for a read of read group ```read_group```, or ```default``` if the
+    /// read has no read group or its read group has no parameters.
+    pub fn for_read_group(
+        &self,
+        read_group: Option<&str>,
+        default: AlignmentParameters,
+    ) -> AlignmentParameters {
+        match read_group.and_then(|rg| self.params.get(rg)) {
+            Some(p) => *p,
+            None => default,
+        }
+    }
+}
+
+/// The haplotype base that matches any read base, to which the ambiguous reference bases (N and
+/// the IUPAC ambiguity codes) are set with ```--ref_ambiguity wildcard```
+pub const WILDCARD_BASE: u8 = b'*';
+
+/// A DNA sequence packed into the 4-bit codes of its bases (see ```Alphabet::code```), two bases
+/// per byte. The alignment functions take the sequences as byte slices and pack them once per
+/// alignment, so that the dynamic programming compares small integers and keeps the read and
+/// haplotype windows in an eighth of the memory of a ```Vec<char>```.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackedSeq {
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl PackedSeq {
+    pub fn new(seq: &[u8]) -> PackedSeq {
+        let alphabet = Alphabet::current();
+        let mut data: Vec<u8> = vec![0; (seq.len() + 1) / 2];
+        for (i, &b) in seq.iter().enumerate() {
+            data[i / 2] |= alphabet.code(b) << ((i % 2) * 4);
+        }
+        PackedSeq {
+            data: data,
+            len: seq.len(),
+        }
+    }
+
+    /// the code of the base at position ```i```
+    #[inline]
+    pub fn get(&self, i: usize) -> u8 {
+        (self.data[i / 2] >> ((i % 2) * 4)) & 0xf
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Returns the index of the k-mer centered at ```seq[pos]``` (A=0, C=1, G=2, T=3, with the first
+/// base of the k-mer as the most significant digit), or None if the k-mer runs past either end of
+/// the sequence or contains a base other than A, C, G or T.
+pub fn kmer_context_index<B: Copy + Into<char>>(seq: &[B], pos: usize, k: usize) -> Option<usize> {
+    let half = k / 2;
+    if pos < half || pos + k - half > seq.len() {
+        return None;
+    }
+
+    let mut ix = 0;
+    for c in &seq[pos - half..pos + k - half] {
+        ix = ix * 4 + match (*c).into() {
+            'A' => 0,
+            'C' => 1,
+            'G' => 2,
+            'T' => 3,
+            _ => return None,
+        };
+    }
+    Some(ix)
+}
+
+/// Alignment parameters conditioned on the sequence context. The parameters for a k-mer are used
+/// for the transitions into and emissions at the center base of the k-mer, on the haplotype
+/// (reference) side of the alignment.
+#[derive(Clone)]
+pub struct ContextAlignmentParameters {
+    /// the length of the k-mer context (odd, so that the k-mer is centered on a base)
+    pub k: usize,
+    /// the parameters for each k-mer, indexed by ```kmer_context_index```
+    pub params: Vec<AlignmentParameters>,
+}
+
+impl ContextAlignmentParameters {
+    /// Returns the alignment parameters to use at each position of ```seq```. Positions without a
+    /// full k-mer context (at the ends of the sequence, or next to an N) use ```default```.
+    pub fn for_sequence(
+        &self,
+        seq: &[u8],
+        default: AlignmentParameters,
+    ) -> Vec<AlignmentParameters> {
+        (0..seq.len())
+            .map(|pos| match kmer_context_index(seq, pos, self.k) {
+                Some(ix) => self.params[ix],
+                None => default,
+            })
+            .collect()
+    }
+}
+
+/// The number of cells of the dynamic programming matrix of a banded alignment of a read of
+/// length ```n``` to a haplotype of length ```m```, with the band extending ```half_width``` cells
+/// on each side of its middle
+pub fn band_cells(n: usize, m: usize, half_width: usize) -> usize {
+    (1..n + 1)
+        .map(|i| {
+            let (band_start, band_end) = band_bounds(i, n, m, half_width);
+            (band_end + 1).saturating_sub(band_start)
+        })
+        .sum()
+}
+
+/// The difference of the lengths of a read and a haplotype. The band of their alignment is widened
+/// by it, so that the band reaches the last cell of the dynamic programming matrix.
+fn len_diff(n: usize, m: usize) -> usize {
+    if n > m {
+        n - m
+    } else {
+        m - n
+    }
+}
+
+/// The first and last columns of the band in row ```i``` of the dynamic programming matrix of a
+/// read of length ```n``` and a haplotype of length ```m```, with the band extending
+/// ```half_width``` cells on each side of its middle. The middle is computed in 64 bits, as the
+/// product of the lengths of the windows of ultralong reads overflows a 32-bit ```usize```.
+fn band_bounds(i: usize, n: usize, m: usize, half_width: usize) -> (usize, usize) {
+    let band_middle = ((m as u64 * i as u64) / n as u64) as usize;
+    (
+        band_middle.saturating_sub(half_width).max(1),
+        band_middle.saturating_add(half_width).min(m),
+    )
+}
+
+// the alignment functions take a vector of parameters with the parameters to use at each
+// position of w (the haplotype sequence). The transitions into column j and the emissions in
+// column j of the dynamic programming matrix use the parameters of w[j - 1].
+
 pub fn forward_algorithm_non_numerically_stable(
-    v: &Vec<char>,
-    w: &Vec<char>,
-    params: AlignmentParameters,
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<AlignmentParameters>,
     min_band_width: usize,
 ) -> LogProb {
-    let len_diff = ((v.len() as i32) - (w.len() as i32)).abs() as usize;
-    let band_width = min_band_width + len_diff;
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    let band_width = min_band_width.saturating_add(len_diff(v.len(), w.len()));
+    count_alignment(band_cells(v.len(), w.len(), band_width / 2));
 
     let mut lower_prev: Vec<f64> = vec![0.0; w.len() + 1];
     let mut middle_prev: Vec<f64> = vec![0.0; w.len() + 1];
@@ -120,43 +343,33 @@ pub fn forward_algorithm_non_numerically_stable(
 
     middle_prev[0] = 1.0;
 
-    upper_prev[1] = params.transition_probs.deletion_from_match;
+    upper_prev[1] = params[0].transition_probs.deletion_from_match;
     for j in 2..(w.len() + 1) {
-        upper_prev[j] = upper_prev[j - 1] * params.transition_probs.deletion_from_deletion;
+        upper_prev[j] = upper_prev[j - 1] * params[j - 1].transition_probs.deletion_from_deletion;
         middle_prev[j] = 0.0;
     }
 
 
-    let t = params.transition_probs;
-    let e = params.emission_probs;
-
     for i in 1..(v.len() + 1) {
-        let band_middle = (w.len() * i) / v.len();
-        let band_start = if band_middle >= band_width / 2 + 1 {
-            band_middle - band_width / 2
-        } else {
-            1
-        };
-        let band_end = if band_middle + band_width / 2 <= w.len() {
-            band_middle + band_width / 2
-        } else {
-            w.len()
-        };
+        let (band_start, band_end) = band_bounds(i, v.len(), w.len(), band_width / 2);
 
 
         if band_start == 1 {
             upper_curr[0] = 0.0;
             middle_curr[0] = 0.0;
             if i == 1 {
-                lower_curr[0] = params.transition_probs.insertion_from_match
+                lower_curr[0] = params[0].transition_probs.insertion_from_match
             } else {
                 lower_curr[0] =
-                    lower_prev[0] * params.transition_probs.insertion_from_insertion;
+                    lower_prev[0] * params[0].transition_probs.insertion_from_insertion;
             }
         }
 
 
         for j in band_start..(band_end + 1) {
+            let t = params[j - 1].transition_probs;
+            let e = params[j - 1].emission_probs;
+
             let lower_continue = lower_prev[j] * t.insertion_from_insertion;
             let lower_from_middle = middle_prev[j] * t.insertion_from_match;
             lower_curr[j] = e.insertion * (lower_continue + lower_from_middle);
@@ -168,11 +381,9 @@ pub fn forward_algorithm_non_numerically_stable(
             let middle_from_lower = lower_prev[j - 1] * t.match_from_insertion;
             let middle_continue = middle_prev[j - 1] * t.match_from_match;
             let middle_from_upper = upper_prev[j - 1] * t.match_from_deletion;
-            let match_emission: f64 = if v[i - 1] == w[j - 1] {
-                e.equal
-            } else {
-                e.not_equal
-            };
+            let match_emission: f64 = alphabet
+                .compare(v.get(i - 1), w.get(j - 1))
+                .emission(e.equal, e.not_equal);
             middle_curr[j] =
                 match_emission * (middle_from_lower + middle_continue + middle_from_upper);
         }
@@ -198,18 +409,67 @@ pub fn forward_algorithm_non_numerically_stable(
     if middle_prev[w.len()] != 0.0 {
         LogProb::from(Prob(middle_prev[w.len()]))
     } else {
-        forward_algorithm_numerically_stable(v, w, params.ln(), band_width)
+        let ln_params: Vec<LnAlignmentParameters> = params.iter().map(|p| p.ln()).collect();
+        forward_algorithm_packed(&v, &w, &ln_params, None, band_width)
     }
 }
 
 pub fn forward_algorithm_numerically_stable(
-    v: &Vec<char>,
-    w: &Vec<char>,
-    params: LnAlignmentParameters,
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
     min_band_width: usize,
 ) -> LogProb {
-    let len_diff = ((v.len() as i32) - (w.len() as i32)).abs() as usize;
-    let band_width = min_band_width + len_diff;
+    forward_algorithm_packed(
+        &PackedSeq::new(v),
+        &PackedSeq::new(w),
+        params,
+        None,
+        min_band_width,
+    )
+}
+
+/// Same as forward_algorithm_numerically_stable, but each base of the read ```v``` is also
+/// miscalled with the probability in ```base_errors``` (from its base quality), on top of the
+/// mismatches of the alignment parameters.
+pub fn forward_algorithm_base_quals(
+    v: &[u8],
+    base_errors: &[f64],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
+    min_band_width: usize,
+) -> LogProb {
+    assert_eq!(base_errors.len(), v.len());
+    forward_algorithm_packed(
+        &PackedSeq::new(v),
+        &PackedSeq::new(w),
+        params,
+        Some(base_errors),
+        min_band_width,
+    )
+}
+
+// the match emission of a read base that is miscalled with probability ```error```: the base
+// emitted by the alignment model is read as one of the three other bases, with the same probability
+#[inline]
+fn base_qual_emission(m: BaseMatch, e: &LnEmissionProbs, error: f64) -> LogProb {
+    let (equal, not_equal) = ((*e.equal).exp(), (*e.not_equal).exp());
+    let qual_equal = equal * (1.0 - error) + not_equal * error;
+    let qual_not_equal = not_equal * (1.0 - error) + (equal + 2.0 * not_equal) * error / 3.0;
+    m.ln_emission(LogProb(qual_equal.ln()), LogProb(qual_not_equal.ln()))
+}
+
+fn forward_algorithm_packed(
+    v: &PackedSeq,
+    w: &PackedSeq,
+    params: &Vec<LnAlignmentParameters>,
+    base_errors: Option<&[f64]>,
+    min_band_width: usize,
+) -> LogProb {
+    assert_eq!(params.len(), w.len());
+    let alphabet = Alphabet::current();
+    let band_width = min_band_width.saturating_add(len_diff(v.len(), w.len()));
+    count_alignment(band_cells(v.len(), w.len(), band_width / 2));
 
     let mut lower_prev: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
     let mut middle_prev: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
@@ -219,38 +479,29 @@ pub fn forward_algorithm_numerically_stable(
     let mut upper_curr: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
 
     middle_prev[0] = LogProb::ln_one();
-    let t = params.transition_probs;
-    let e = params.emission_probs;
 
-    upper_prev[1] = params.transition_probs.deletion_from_match;
+    upper_prev[1] = params[0].transition_probs.deletion_from_match;
     for j in 2..(w.len() + 1) {
-        upper_prev[j] = upper_prev[j - 1] + params.transition_probs.deletion_from_deletion;
+        upper_prev[j] = upper_prev[j - 1] + params[j - 1].transition_probs.deletion_from_deletion;
     }
 
     for i in 1..(v.len() + 1) {
-        let band_middle = (w.len() * i) / v.len();
-        let band_start = if band_middle >= band_width / 2 + 1 {
-            band_middle - band_width / 2
-        } else {
-            1
-        };
-        let band_end = if band_middle + band_width / 2 <= w.len() {
-            band_middle + band_width / 2
-        } else {
-            w.len()
-        };
+        let (band_start, band_end) = band_bounds(i, v.len(), w.len(), band_width / 2);
 
         if band_start == 1 {
             middle_curr[0] = LogProb::ln_zero();
             if i == 1 {
-                lower_curr[0] = params.transition_probs.insertion_from_match
+                lower_curr[0] = params[0].transition_probs.insertion_from_match
             } else {
                 lower_curr[0] =
-                    lower_prev[0] + params.transition_probs.insertion_from_insertion;
+                    lower_prev[0] + params[0].transition_probs.insertion_from_insertion;
             }
         }
 
         for j in band_start..(band_end + 1) {
+            let t = params[j - 1].transition_probs;
+            let e = params[j - 1].emission_probs;
+
             let lower_continue = lower_prev[j] + t.insertion_from_insertion;
             let lower_from_middle = middle_prev[j] + t.insertion_from_match;
             lower_curr[j] = e.insertion + LogProb::ln_add_exp(lower_continue, lower_from_middle);
@@ -263,10 +514,10 @@ pub fn forward_algorithm_numerically_stable(
             let middle_continue = middle_prev[j - 1] + t.match_from_match;
             let middle_from_upper = upper_prev[j - 1] + t.match_from_deletion;
             let options3 = [middle_from_lower, middle_continue, middle_from_upper];
-            let match_emission: LogProb = if v[i - 1] == w[j - 1] {
-                e.equal
-            } else {
-                e.not_equal
+            let base_match = alphabet.compare(v.get(i - 1), w.get(j - 1));
+            let match_emission: LogProb = match base_errors {
+                Some(errors) => base_qual_emission(base_match, &e, errors[i - 1]),
+                None => base_match.ln_emission(e.equal, e.not_equal),
             };
             middle_curr[j] = match_emission + LogProb::ln_sum_exp(&options3);
         }
@@ -292,14 +543,22 @@ pub fn forward_algorithm_numerically_stable(
     middle_prev[w.len()]
 }
 
-pub fn viterbi_max_scoring_alignment(
-    v: &Vec<char>,
-    w: &Vec<char>,
-    params: LnAlignmentParameters,
-    min_band_width: usize,
+/// Forward algorithm for a read that ends within the haplotype window. The alignment starts at
+/// the start of both sequences, and ends with the last base of the read ```v``` aligned to any base
+/// of the haplotype ```w```, so the rest of the haplotype (which the read doesn't reach) is not
+/// penalized. A read that starts within the window is aligned with the reversed sequences and
+/// parameters. The whole matrix is computed, since the end of the read on the haplotype isn't
+/// known in advance.
+pub fn forward_algorithm_free_end(
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
 ) -> LogProb {
-    let len_diff = ((v.len() as i32) - (w.len() as i32)).abs() as usize;
-    let band_width = min_band_width + len_diff;
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    assert!(w.len() > 0);
+    count_alignment(v.len() * w.len());
 
     let mut lower_prev: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
     let mut middle_prev: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
@@ -309,72 +568,503 @@ pub fn viterbi_max_scoring_alignment(
     let mut upper_curr: Vec<LogProb> = vec![LogProb::ln_zero(); w.len() + 1];
 
     middle_prev[0] = LogProb::ln_one();
-    let t = params.transition_probs;
-    let e = params.emission_probs;
-
-    upper_prev[1] = params.transition_probs.deletion_from_match;
+    upper_prev[1] = params[0].transition_probs.deletion_from_match;
     for j in 2..(w.len() + 1) {
-        upper_prev[j] = upper_prev[j - 1] + params.transition_probs.deletion_from_deletion;
+        upper_prev[j] = upper_prev[j - 1] + params[j - 1].transition_probs.deletion_from_deletion;
     }
 
-
     for i in 1..(v.len() + 1) {
-        let band_middle = (w.len() * i) / v.len();
-        let band_start = if band_middle >= band_width / 2 + 1 {
-            band_middle - band_width / 2
-        } else {
-            1
-        };
-        let band_end = if band_middle + band_width / 2 <= w.len() {
-            band_middle + band_width / 2
+        let t0 = params[0].transition_probs;
+        lower_curr[0] = params[0].emission_probs.insertion
+            + LogProb::ln_add_exp(
+                lower_prev[0] + t0.insertion_from_insertion,
+                middle_prev[0] + t0.insertion_from_match,
+            );
+        middle_curr[0] = LogProb::ln_zero();
+        upper_curr[0] = LogProb::ln_zero();
+
+        for j in 1..(w.len() + 1) {
+            let t = params[j - 1].transition_probs;
+            let e = params[j - 1].emission_probs;
+
+            let lower_continue = lower_prev[j] + t.insertion_from_insertion;
+            let lower_from_middle = middle_prev[j] + t.insertion_from_match;
+            lower_curr[j] = e.insertion + LogProb::ln_add_exp(lower_continue, lower_from_middle);
+
+            let upper_continue = upper_curr[j - 1] + t.deletion_from_deletion;
+            let upper_from_middle = middle_curr[j - 1] + t.deletion_from_match;
+            upper_curr[j] = e.deletion + LogProb::ln_add_exp(upper_continue, upper_from_middle);
+
+            let middle_from_lower = lower_prev[j - 1] + t.match_from_insertion;
+            let middle_continue = middle_prev[j - 1] + t.match_from_match;
+            let middle_from_upper = upper_prev[j - 1] + t.match_from_deletion;
+            let match_emission: LogProb = alphabet
+                .compare(v.get(i - 1), w.get(j - 1))
+                .ln_emission(e.equal, e.not_equal);
+            middle_curr[j] = match_emission
+                + LogProb::ln_sum_exp(&[middle_from_lower, middle_continue, middle_from_upper]);
+        }
+
+        lower_prev.copy_from_slice(&lower_curr);
+        middle_prev.copy_from_slice(&middle_curr);
+        upper_prev.copy_from_slice(&upper_curr);
+    }
+
+    // the read ends with a match at any position of the haplotype
+    LogProb::ln_sum_exp(&middle_prev[1..])
+}
+
+/// A sequence compressed into runs of the same base (homopolymer compression): the base with the
+/// code ```bases[r]``` (see ```Alphabet::code```) repeated ```lens[r]``` times is the r-th run, and
+/// ```starts[r]``` is the position of its first base in the uncompressed sequence.
+pub struct RunLengthSeq {
+    pub bases: Vec<u8>,
+    pub lens: Vec<usize>,
+    pub starts: Vec<usize>,
+}
+
+pub fn run_length_encode(seq: &[u8]) -> RunLengthSeq {
+    let mut rle = RunLengthSeq {
+        bases: vec![],
+        lens: vec![],
+        starts: vec![],
+    };
+    let alphabet = Alphabet::current();
+    for (pos, &b) in seq.iter().enumerate() {
+        let c = alphabet.code(b);
+        let n = rle.bases.len();
+        if n > 0 && rle.bases[n - 1] == c {
+            rle.lens[n - 1] += 1;
         } else {
-            w.len()
-        };
+            rle.bases.push(c);
+            rle.lens.push(1);
+            rle.starts.push(pos);
+        }
+    }
+    rle
+}
+
+/// The log probability that a homopolymer run of ```hap_len``` bases on the haplotype is read as
+/// a run of ```read_len``` bases. Each base of the run is lengthened or shortened with the
+/// probability of opening an insertion or deletion (so longer runs are more error prone), and
+/// longer length errors are geometrically less likely, with the probability of extending an
+/// insertion or deletion.
+pub fn ln_run_length_prob(read_len: usize, hap_len: usize, t: &LnTransitionProbs) -> LogProb {
+    // the probabilities of a longer and a shorter run, capped so that the correct length stays
+    // the most likely one for long runs
+    let p_longer = (hap_len as f64 * (*t.insertion_from_match).exp()).min(0.25);
+    let p_shorter = if hap_len > 1 {
+        (hap_len as f64 * (*t.deletion_from_match).exp()).min(0.25)
+    } else {
+        0.0
+    };
+    if read_len == hap_len {
+        LogProb::from(Prob(1.0 - p_longer - p_shorter))
+    } else if read_len > hap_len {
+        LogProb::from(Prob(p_longer * (1.0 - (*t.insertion_from_insertion).exp())))
+            + LogProb((read_len - hap_len - 1) as f64 * *t.insertion_from_insertion)
+    } else {
+        LogProb::from(Prob(p_shorter * (1.0 - (*t.deletion_from_deletion).exp())))
+            + LogProb((hap_len - read_len - 1) as f64 * *t.deletion_from_deletion)
+    }
+}
+
+/// Forward algorithm in run-length space (```--rle_alignment```). The read ```v``` and the
+/// haplotype ```w``` are compressed into homopolymer runs, which are aligned with the pair-HMM
+/// like single bases. A matched run pair is scored with the emission probability of its bases and
+/// the probability of the difference of the run lengths (```ln_run_length_prob```), and an
+/// inserted or deleted run with the cost of the insertion or deletion of each of its bases. The
+/// parameters of a haplotype run are those of its first base.
+///
+/// Since most errors of nanopore reads are wrong homopolymer lengths, which only change the
+/// length term here, the compressed sequences are both shorter and align more simply than the
+/// bases; an allele that changes the length of a homopolymer is told apart by its run length.
+pub fn forward_algorithm_run_length(
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
+    min_band_width: usize,
+) -> LogProb {
+    assert_eq!(params.len(), w.len());
+    let v = run_length_encode(v);
+    let w = run_length_encode(w);
+    let alphabet = Alphabet::current();
+    let params: Vec<LnAlignmentParameters> = w.starts.iter().map(|&pos| params[pos]).collect();
+    let (n, m) = (v.bases.len(), w.bases.len());
+    let band_width = min_band_width.saturating_add(len_diff(n, m));
+    count_alignment(band_cells(n, m, band_width / 2));
+
+    // the cost of the bases of a run after the first one, for an inserted or deleted run
+    let ins_run = |i: usize, p: &LnAlignmentParameters| {
+        LogProb(
+            (v.lens[i] - 1) as f64
+                * *(p.transition_probs.insertion_from_insertion + p.emission_probs.insertion),
+        )
+    };
+    let del_run = |j: usize, p: &LnAlignmentParameters| {
+        LogProb(
+            (w.lens[j] - 1) as f64
+                * *(p.transition_probs.deletion_from_deletion + p.emission_probs.deletion),
+        )
+    };
+
+    let mut lower_prev: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+    let mut middle_prev: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+    let mut upper_prev: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+    let mut lower_curr: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+    let mut middle_curr: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+    let mut upper_curr: Vec<LogProb> = vec![LogProb::ln_zero(); m + 1];
+
+    middle_prev[0] = LogProb::ln_one();
+
+    upper_prev[1] = params[0].transition_probs.deletion_from_match + del_run(0, &params[0]);
+    for j in 2..(m + 1) {
+        upper_prev[j] = upper_prev[j - 1]
+            + params[j - 1].transition_probs.deletion_from_deletion
+            + del_run(j - 1, &params[j - 1]);
+    }
+
+    for i in 1..(n + 1) {
+        let (band_start, band_end) = band_bounds(i, n, m, band_width / 2);
 
         if band_start == 1 {
             middle_curr[0] = LogProb::ln_zero();
-            if i == 1 {
-                lower_curr[0] = params.transition_probs.insertion_from_match
-            } else {
-                lower_curr[0] =
-                    lower_prev[0] + params.transition_probs.insertion_from_insertion;
-            }
+            let t0 = params[0].transition_probs;
+            lower_curr[0] = ins_run(i - 1, &params[0])
+                + if i == 1 {
+                    t0.insertion_from_match
+                } else {
+                    lower_prev[0] + t0.insertion_from_insertion
+                };
         }
 
-
         for j in band_start..(band_end + 1) {
+            let t = params[j - 1].transition_probs;
+            let e = params[j - 1].emission_probs;
+
             let lower_continue = lower_prev[j] + t.insertion_from_insertion;
             let lower_from_middle = middle_prev[j] + t.insertion_from_match;
-            lower_curr[j] = if lower_continue > lower_from_middle {
-                e.insertion + lower_continue
-            } else {
-                e.insertion + lower_from_middle
-            };
+            lower_curr[j] = e.insertion
+                + ins_run(i - 1, &params[j - 1])
+                + LogProb::ln_add_exp(lower_continue, lower_from_middle);
 
             let upper_continue = upper_curr[j - 1] + t.deletion_from_deletion;
             let upper_from_middle = middle_curr[j - 1] + t.deletion_from_match;
-            upper_curr[j] = if upper_continue > upper_from_middle {
-                e.deletion + upper_continue
-            } else {
-                e.deletion + upper_from_middle
-            };
+            upper_curr[j] = e.deletion
+                + del_run(j - 1, &params[j - 1])
+                + LogProb::ln_add_exp(upper_continue, upper_from_middle);
 
             let middle_from_lower = lower_prev[j - 1] + t.match_from_insertion;
             let middle_continue = middle_prev[j - 1] + t.match_from_match;
             let middle_from_upper = upper_prev[j - 1] + t.match_from_deletion;
-            let mut max_option: LogProb = LogProb::ln_zero();
-            let options: Vec<LogProb> = vec![middle_from_lower, middle_continue, middle_from_upper];
-            for option in options {
-                if option > max_option {
-                    max_option = option;
-                }
-            }
-            let match_emission: LogProb = if v[i - 1] == w[j - 1] {
-                e.equal
+            let base_emission: LogProb = alphabet
+                .compare(v.bases[i - 1], w.bases[j - 1])
+                .ln_emission(e.equal, e.not_equal);
+            middle_curr[j] = base_emission
+                + ln_run_length_prob(v.lens[i - 1], w.lens[j - 1], &t)
+                + LogProb::ln_sum_exp(&[middle_from_lower, middle_continue, middle_from_upper]);
+        }
+
+        for j in (band_start - 1)..(band_end + 1) {
+            upper_prev[j] = upper_curr[j];
+            middle_prev[j] = middle_curr[j];
+            lower_prev[j] = lower_curr[j];
+        }
+        if band_start >= 2 {
+            upper_prev[band_start - 2] = LogProb(f64::NAN);
+            middle_prev[band_start - 2] = LogProb(f64::NAN);
+            lower_prev[band_start - 2] = LogProb(f64::NAN);
+        }
+
+        upper_curr[band_start] = LogProb::ln_zero();
+        middle_curr[band_start] = LogProb::ln_zero();
+        lower_curr[band_start] = LogProb::ln_zero();
+    }
+
+    middle_prev[m]
+}
+
+// the log-space alignment parameters of one haplotype position, in single precision
+#[derive(Clone, Copy)]
+struct LnAlignmentParametersF32 {
+    match_from_match: f32,
+    insertion_from_match: f32,
+    deletion_from_match: f32,
+    insertion_from_insertion: f32,
+    match_from_insertion: f32,
+    deletion_from_deletion: f32,
+    match_from_deletion: f32,
+    equal: f32,
+    not_equal: f32,
+    insertion: f32,
+    deletion: f32,
+}
+
+impl LnAlignmentParametersF32 {
+    fn from(p: &LnAlignmentParameters) -> LnAlignmentParametersF32 {
+        let t = p.transition_probs;
+        let e = p.emission_probs;
+        LnAlignmentParametersF32 {
+            match_from_match: *t.match_from_match as f32,
+            insertion_from_match: *t.insertion_from_match as f32,
+            deletion_from_match: *t.deletion_from_match as f32,
+            insertion_from_insertion: *t.insertion_from_insertion as f32,
+            match_from_insertion: *t.match_from_insertion as f32,
+            deletion_from_deletion: *t.deletion_from_deletion as f32,
+            match_from_deletion: *t.match_from_deletion as f32,
+            equal: *e.equal as f32,
+            not_equal: *e.not_equal as f32,
+            insertion: *e.insertion as f32,
+            deletion: *e.deletion as f32,
+        }
+    }
+}
+
+fn ln_add_exp_f32(a: f32, b: f32) -> f32 {
+    let (max, min) = if a > b { (a, b) } else { (b, a) };
+    if max == f32::NEG_INFINITY {
+        max
+    } else {
+        max + (min - max).exp().ln_1p()
+    }
+}
+
+fn ln_sum_exp3_f32(a: f32, b: f32, c: f32) -> f32 {
+    let max = a.max(b).max(c);
+    if max == f32::NEG_INFINITY {
+        max
+    } else {
+        max + ((a - max).exp() + (b - max).exp() + (c - max).exp()).ln()
+    }
+}
+
+// Same as forward_algorithm_numerically_stable, but the dynamic programming matrix is stored and
+// computed with 32-bit floats, which halves the memory traffic of the alignment. Working in log
+// space, the range of f32 is never a problem; only the precision is reduced. Every log-sum-exp adds a
+// relative error of at most about 2^-23 to the log-likelihood, so the absolute error of the result is
+// bounded by roughly (v.len() + w.len()) * 2^-23 * |result|: less than 0.001 (natural log units) for
+// realignment windows of a few hundred bases, far below the score differences between haplotypes
+// that affect the genotypes.
+pub fn forward_algorithm_numerically_stable_f32(
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
+    min_band_width: usize,
+) -> LogProb {
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    let band_width = min_band_width.saturating_add(len_diff(v.len(), w.len()));
+    count_alignment(band_cells(v.len(), w.len(), band_width / 2));
+    let params: Vec<LnAlignmentParametersF32> =
+        params.iter().map(|p| LnAlignmentParametersF32::from(p)).collect();
+
+    let mut lower_prev: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+    let mut middle_prev: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+    let mut upper_prev: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+    let mut lower_curr: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+    let mut middle_curr: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+    let mut upper_curr: Vec<f32> = vec![f32::NEG_INFINITY; w.len() + 1];
+
+    middle_prev[0] = 0.0;
+
+    upper_prev[1] = params[0].deletion_from_match;
+    for j in 2..(w.len() + 1) {
+        upper_prev[j] = upper_prev[j - 1] + params[j - 1].deletion_from_deletion;
+    }
+
+    for i in 1..(v.len() + 1) {
+        let (band_start, band_end) = band_bounds(i, v.len(), w.len(), band_width / 2);
+
+        if band_start == 1 {
+            middle_curr[0] = f32::NEG_INFINITY;
+            if i == 1 {
+                lower_curr[0] = params[0].insertion_from_match
             } else {
-                e.not_equal
+                lower_curr[0] = lower_prev[0] + params[0].insertion_from_insertion;
+            }
+        }
+
+        for j in band_start..(band_end + 1) {
+            let p = params[j - 1];
+
+            let lower_continue = lower_prev[j] + p.insertion_from_insertion;
+            let lower_from_middle = middle_prev[j] + p.insertion_from_match;
+            lower_curr[j] = p.insertion + ln_add_exp_f32(lower_continue, lower_from_middle);
+
+            let upper_continue = upper_curr[j - 1] + p.deletion_from_deletion;
+            let upper_from_middle = middle_curr[j - 1] + p.deletion_from_match;
+            upper_curr[j] = p.deletion + ln_add_exp_f32(upper_continue, upper_from_middle);
+
+            let middle_from_lower = lower_prev[j - 1] + p.match_from_insertion;
+            let middle_continue = middle_prev[j - 1] + p.match_from_match;
+            let middle_from_upper = upper_prev[j - 1] + p.match_from_deletion;
+            let match_emission: f32 = match alphabet.compare(v.get(i - 1), w.get(j - 1)) {
+                BaseMatch::Equal => p.equal,
+                BaseMatch::NotEqual => p.not_equal,
+                m => *m.ln_emission(LogProb(p.equal as f64), LogProb(p.not_equal as f64)) as f32,
             };
-            middle_curr[j] = match_emission + max_option;
+            middle_curr[j] = match_emission
+                + ln_sum_exp3_f32(middle_from_lower, middle_continue, middle_from_upper);
+        }
+
+        for j in (band_start - 1)..(band_end + 1) {
+            upper_prev[j] = upper_curr[j];
+            middle_prev[j] = middle_curr[j];
+            lower_prev[j] = lower_curr[j];
+        }
+        // set these to NaN to make sure they aren't used again (see forward_algorithm_numerically_stable)
+        if band_start >= 2 {
+            upper_prev[band_start - 2] = f32::NAN;
+            middle_prev[band_start - 2] = f32::NAN;
+            lower_prev[band_start - 2] = f32::NAN;
+        }
+
+        upper_curr[band_start] = f32::NEG_INFINITY;
+        middle_curr[band_start] = f32::NEG_INFINITY;
+        lower_curr[band_start] = f32::NEG_INFINITY;
+    }
+
+    LogProb(middle_prev[w.len()] as f64)
+}
+
+/// The scores of the best and second-best alignment paths found by
+/// ```viterbi_max_scoring_alignment```.
+#[derive(Clone, Copy, Debug)]
+pub struct ViterbiScores {
+    /// log probability of the single most likely alignment path
+    pub best: LogProb,
+    /// log probability of the most likely path that differs from the best path, or ln(0) if the
+    /// band holds no other path
+    pub second_best: LogProb,
+}
+
+impl ViterbiScores {
+    /// The log-odds of the best path over the second-best path. A margin near zero means that the
+    /// read aligns about equally well in two different ways, e.g. with an indel placed in either
+    /// of two positions that give different alleles.
+    pub fn margin(&self) -> f64 {
+        *self.best - *self.second_best
+    }
+}
+
+// the two largest of the candidate scores, which are ln(0) if there are fewer than two candidates.
+// NaN candidates (outside of the band) are never chosen.
+fn top_two(candidates: &[LogProb]) -> [LogProb; 2] {
+    let mut top = [LogProb::ln_zero(); 2];
+    for &c in candidates {
+        if c > top[0] {
+            top[1] = top[0];
+            top[0] = c;
+        } else if c > top[1] {
+            top[1] = c;
+        }
+    }
+    top
+}
+
+// adds a transition or emission score to both of the two best paths into a cell
+fn plus(scores: [LogProb; 2], p: LogProb) -> [LogProb; 2] {
+    [scores[0] + p, scores[1] + p]
+}
+
+/// Finds the best alignment path of v to w, and the best path that differs from it. Every cell of
+/// the dynamic programming matrix keeps the scores of its two best incoming paths, which are
+/// distinct paths, so the two best scores in the last cell belong to the best and second-best
+/// paths overall.
+pub fn viterbi_max_scoring_alignment(
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
+    min_band_width: usize,
+) -> ViterbiScores {
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    let band_width = min_band_width.saturating_add(len_diff(v.len(), w.len()));
+    count_alignment(band_cells(v.len(), w.len(), band_width / 2));
+
+    let zero = [LogProb::ln_zero(); 2];
+    let mut lower_prev: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+    let mut middle_prev: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+    let mut upper_prev: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+    let mut lower_curr: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+    let mut middle_curr: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+    let mut upper_curr: Vec<[LogProb; 2]> = vec![zero; w.len() + 1];
+
+    middle_prev[0] = [LogProb::ln_one(), LogProb::ln_zero()];
+
+    upper_prev[1] = [params[0].transition_probs.deletion_from_match, LogProb::ln_zero()];
+    for j in 2..(w.len() + 1) {
+        upper_prev[j] = plus(
+            upper_prev[j - 1],
+            params[j - 1].transition_probs.deletion_from_deletion,
+        );
+    }
+
+
+    for i in 1..(v.len() + 1) {
+        let (band_start, band_end) = band_bounds(i, v.len(), w.len(), band_width / 2);
+
+        if band_start == 1 {
+            middle_curr[0] = zero;
+            if i == 1 {
+                lower_curr[0] = [
+                    params[0].transition_probs.insertion_from_match,
+                    LogProb::ln_zero(),
+                ];
+            } else {
+                lower_curr[0] =
+                    plus(lower_prev[0], params[0].transition_probs.insertion_from_insertion);
+            }
+        }
+
+
+        for j in band_start..(band_end + 1) {
+            let t = params[j - 1].transition_probs;
+            let e = params[j - 1].emission_probs;
+
+            let lower_continue = plus(lower_prev[j], t.insertion_from_insertion);
+            let lower_from_middle = plus(middle_prev[j], t.insertion_from_match);
+            lower_curr[j] = plus(
+                top_two(&[
+                    lower_continue[0],
+                    lower_continue[1],
+                    lower_from_middle[0],
+                    lower_from_middle[1],
+                ]),
+                e.insertion,
+            );
+
+            let upper_continue = plus(upper_curr[j - 1], t.deletion_from_deletion);
+            let upper_from_middle = plus(middle_curr[j - 1], t.deletion_from_match);
+            upper_curr[j] = plus(
+                top_two(&[
+                    upper_continue[0],
+                    upper_continue[1],
+                    upper_from_middle[0],
+                    upper_from_middle[1],
+                ]),
+                e.deletion,
+            );
+
+            let middle_from_lower = plus(lower_prev[j - 1], t.match_from_insertion);
+            let middle_continue = plus(middle_prev[j - 1], t.match_from_match);
+            let middle_from_upper = plus(upper_prev[j - 1], t.match_from_deletion);
+            let match_emission: LogProb = alphabet
+                .compare(v.get(i - 1), w.get(j - 1))
+                .ln_emission(e.equal, e.not_equal);
+            middle_curr[j] = plus(
+                top_two(&[
+                    middle_from_lower[0],
+                    middle_from_lower[1],
+                    middle_continue[0],
+                    middle_continue[1],
+                    middle_from_upper[0],
+                    middle_from_upper[1],
+                ]),
+                match_emission,
+            );
         }
 
         for j in (band_start-1)..(band_end + 1) {
@@ -385,15 +1075,1142 @@ pub fn viterbi_max_scoring_alignment(
         // we previously had a bug at the left boundary of the band... set these to NaN to make sure they
         // aren't used again
         if band_start >= 2 {
-            upper_prev[band_start-2] = LogProb(f64::NAN);
-            middle_prev[band_start-2] = LogProb(f64::NAN);
-            lower_prev[band_start-2] = LogProb(f64::NAN);
+            upper_prev[band_start-2] = [LogProb(f64::NAN); 2];
+            middle_prev[band_start-2] = [LogProb(f64::NAN); 2];
+            lower_prev[band_start-2] = [LogProb(f64::NAN); 2];
         }
 
-        upper_curr[band_start] = LogProb::ln_zero();
-        middle_curr[band_start] = LogProb::ln_zero();
-        lower_curr[band_start] = LogProb::ln_zero();
+        upper_curr[band_start] = zero;
+        middle_curr[band_start] = zero;
+        lower_curr[band_start] = zero;
     }
 
-    middle_prev[w.len()]
+    ViterbiScores {
+        best: middle_prev[w.len()][0],
+        second_best: middle_prev[w.len()][1],
+    }
+}
+
+/// Returns the score (log probability) of aligning v to w along a single alignment path, with the
+/// same parameter convention as the alignment functions above. The path is the sequence of
+/// states, and must consume all of v and w and end in the match state. Returns None if the path
+/// doesn't fit the sequences.
+pub fn alignment_path_score(
+    v: &[u8],
+    w: &[u8],
+    path: &Vec<AlignmentState>,
+    params: &Vec<LnAlignmentParameters>,
+) -> Option<LogProb> {
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    let mut i = 0; // position in v
+    let mut j = 0; // position in w
+    let mut prev_state = AlignmentState::Match;
+    let mut score = LogProb::ln_one();
+
+    for &state in path {
+        match state {
+            AlignmentState::Match => {
+                if i >= v.len() || j >= w.len() {
+                    return None;
+                }
+                let p = &params[j];
+                score = score + match prev_state {
+                    AlignmentState::Match => p.transition_probs.match_from_match,
+                    AlignmentState::Insertion => p.transition_probs.match_from_insertion,
+                    AlignmentState::Deletion => p.transition_probs.match_from_deletion,
+                };
+                score = score
+                    + alphabet
+                        .compare(v.get(i), w.get(j))
+                        .ln_emission(p.emission_probs.equal, p.emission_probs.not_equal);
+                i += 1;
+                j += 1;
+            }
+            AlignmentState::Insertion => {
+                if i >= v.len() {
+                    return None;
+                }
+                let p = &params[if j > 0 { j - 1 } else { 0 }];
+                score = score + match prev_state {
+                    AlignmentState::Match => p.transition_probs.insertion_from_match,
+                    AlignmentState::Insertion => p.transition_probs.insertion_from_insertion,
+                    AlignmentState::Deletion => return None,
+                };
+                score = score + p.emission_probs.insertion;
+                i += 1;
+            }
+            AlignmentState::Deletion => {
+                if j >= w.len() {
+                    return None;
+                }
+                let p = &params[j];
+                score = score + match prev_state {
+                    AlignmentState::Match => p.transition_probs.deletion_from_match,
+                    AlignmentState::Deletion => p.transition_probs.deletion_from_deletion,
+                    AlignmentState::Insertion => return None,
+                };
+                score = score + p.emission_probs.deletion;
+                j += 1;
+            }
+        }
+        prev_state = state;
+    }
+
+    if i == v.len() && j == w.len() && prev_state == AlignmentState::Match {
+        Some(score)
+    } else {
+        None
+    }
+}
+
+/// Finds the single most likely alignment path of v to w and its score, over the whole dynamic
+/// programming matrix (without a band), for reporting alignments rather than scoring them. The
+/// path has the same convention as ```alignment_path_score```, which gives it the same score.
+/// Returns None if either sequence is empty.
+pub fn viterbi_alignment_path(
+    v: &[u8],
+    w: &[u8],
+    params: &Vec<LnAlignmentParameters>,
+) -> Option<(LogProb, Vec<AlignmentState>)> {
+    assert_eq!(params.len(), w.len());
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    if v.is_empty() || w.is_empty() {
+        return None;
+    }
+    let (n, m) = (v.len(), w.len());
+    count_alignment(n * m);
+    let zero = LogProb::ln_zero();
+
+    // the best score of a path ending in each state at each cell, and the state before it
+    let mut middle: Vec<Vec<LogProb>> = vec![vec![zero; m + 1]; n + 1];
+    let mut lower: Vec<Vec<LogProb>> = vec![vec![zero; m + 1]; n + 1];
+    let mut upper: Vec<Vec<LogProb>> = vec![vec![zero; m + 1]; n + 1];
+    let mut middle_from: Vec<Vec<AlignmentState>> = vec![vec![AlignmentState::Match; m + 1]; n + 1];
+    let mut lower_from: Vec<Vec<AlignmentState>> = vec![vec![AlignmentState::Match; m + 1]; n + 1];
+    let mut upper_from: Vec<Vec<AlignmentState>> = vec![vec![AlignmentState::Match; m + 1]; n + 1];
+    middle[0][0] = LogProb::ln_one();
+
+    // the better of two incoming paths, preferring the first on ties
+    let best = |a: (LogProb, AlignmentState), b: (LogProb, AlignmentState)| {
+        if b.0 > a.0 {
+            b
+        } else {
+            a
+        }
+    };
+
+    for i in 0..(n + 1) {
+        for j in 0..(m + 1) {
+            if i > 0 && j > 0 {
+                let p = &params[j - 1];
+                let (score, from) = best(
+                    best(
+                        (
+                            middle[i - 1][j - 1] + p.transition_probs.match_from_match,
+                            AlignmentState::Match,
+                        ),
+                        (
+                            lower[i - 1][j - 1] + p.transition_probs.match_from_insertion,
+                            AlignmentState::Insertion,
+                        ),
+                    ),
+                    (
+                        upper[i - 1][j - 1] + p.transition_probs.match_from_deletion,
+                        AlignmentState::Deletion,
+                    ),
+                );
+                let emission = alphabet
+                    .compare(v.get(i - 1), w.get(j - 1))
+                    .ln_emission(p.emission_probs.equal, p.emission_probs.not_equal);
+                middle[i][j] = score + emission;
+                middle_from[i][j] = from;
+            }
+            if i > 0 {
+                let p = &params[if j > 0 { j - 1 } else { 0 }];
+                let (score, from) = best(
+                    (
+                        middle[i - 1][j] + p.transition_probs.insertion_from_match,
+                        AlignmentState::Match,
+                    ),
+                    (
+                        lower[i - 1][j] + p.transition_probs.insertion_from_insertion,
+                        AlignmentState::Insertion,
+                    ),
+                );
+                lower[i][j] = score + p.emission_probs.insertion;
+                lower_from[i][j] = from;
+            }
+            if j > 0 {
+                let p = &params[j - 1];
+                let (score, from) = best(
+                    (
+                        middle[i][j - 1] + p.transition_probs.deletion_from_match,
+                        AlignmentState::Match,
+                    ),
+                    (
+                        upper[i][j - 1] + p.transition_probs.deletion_from_deletion,
+                        AlignmentState::Deletion,
+                    ),
+                );
+                upper[i][j] = score + p.emission_probs.deletion;
+                upper_from[i][j] = from;
+            }
+        }
+    }
+
+    // trace the path back from the match state in the last cell
+    let mut path: Vec<AlignmentState> = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (n, m);
+    let mut state = AlignmentState::Match;
+    while i > 0 || j > 0 {
+        path.push(state);
+        state = match state {
+            AlignmentState::Match => {
+                let from = middle_from[i][j];
+                i -= 1;
+                j -= 1;
+                from
+            }
+            AlignmentState::Insertion => {
+                let from = lower_from[i][j];
+                i -= 1;
+                from
+            }
+            AlignmentState::Deletion => {
+                let from = upper_from[i][j];
+                j -= 1;
+                from
+            }
+        };
+    }
+    path.reverse();
+
+    Some((middle[n][m], path))
+}
+
+/// The longest read or haplotype window, in bases, that is realigned at all. The band of an
+/// alignment is widened by the difference of the lengths of the read and haplotype windows, so
+/// longer windows (e.g. from a very large ```--max_window```) would take hours to align and are
+/// refused with an error instead (see ```check_window_lengths```).
+pub static MAX_WINDOW_LEN: usize = 10_000_000;
+
+/// The longest read or haplotype window, in bases, that is aligned in one piece. The windows of
+/// ultralong reads that are longer are aligned in chunks of at most this many bases (see
+/// ```chunked_score```).
+pub static MAX_CHUNK_LEN: usize = 100_000;
+
+/// Returns an error if a read window of ```read_len``` bases or a haplotype window of
+/// ```hap_len``` bases is longer than ```MAX_WINDOW_LEN```.
+pub fn check_window_lengths(read_len: usize, hap_len: usize) -> Result<()> {
+    ensure!(
+        read_len <= MAX_WINDOW_LEN,
+        "The read window of {} bases is longer than the maximum realignment window of {} bases.",
+        read_len,
+        MAX_WINDOW_LEN
+    );
+    ensure!(
+        hap_len <= MAX_WINDOW_LEN,
+        "The haplotype window of {} bases is longer than the maximum realignment window of {} bases.",
+        hap_len,
+        MAX_WINDOW_LEN
+    );
+    Ok(())
+}
+
+/// Scores a read window against a haplotype window with ```model``` in chunks of at most
+/// ```chunk_len``` bases of either window, and sums the scores of the chunks. The windows are cut
+/// at the same fractions of their lengths, i.e. on the diagonal of the dynamic programming
+/// matrix, so the score approximates that of the alignments that pass through the cuts; the band
+/// of each chunk is widened by the difference of the lengths of its parts, as for a whole window.
+/// This keeps the alignments of the windows of ultralong reads within memory and time, and they
+/// are only compared between the haplotypes of the same read window, which are cut alike.
+pub fn chunked_score(
+    model: &dyn RealignmentModel,
+    read: &[u8],
+    hap: &[u8],
+    params: &Vec<AlignmentParameters>,
+    band_width: usize,
+    chunk_len: usize,
+) -> LogProb {
+    assert_eq!(params.len(), hap.len());
+    let longest = read.len().max(hap.len());
+    // every chunk has at least one base of both windows
+    let n_chunks = ((longest + chunk_len - 1) / chunk_len)
+        .min(read.len().min(hap.len()))
+        .max(1);
+    let cut = |len: usize, c: usize| ((len as u64 * c as u64) / n_chunks as u64) as usize;
+    (0..n_chunks)
+        .map(|c| {
+            let (r_start, r_end) = (cut(read.len(), c), cut(read.len(), c + 1));
+            let (h_start, h_end) = (cut(hap.len(), c), cut(hap.len(), c + 1));
+            model.score(
+                &read[r_start..r_end],
+                &hap[h_start..h_end],
+                &params[h_start..h_end].to_vec(),
+                band_width,
+            )
+        })
+        .fold(LogProb::ln_one(), |score, chunk_score| score + chunk_score)
+}
+
+/// A model that scores a read window against a short-haplotype window, i.e. the reference or an
+/// alternative allele of a variant cluster with the reference sequence around it. The allele
+/// calls of a read compare these scores between the haplotypes, so they only have to be
+/// comparable for the same read window. ```params``` holds the alignment parameters at each
+/// position of the haplotype (see ```ContextAlignmentParameters```).
+///
+/// The models are selected with ```AlignmentType``` (```--realignment_model```); a new model needs
+/// a variant and a name there, and an implementation of this trait.
+pub trait RealignmentModel {
+    fn name(&self) -> &'static str;
+
+    /// the (log) likelihood of the read window given the haplotype window
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb;
+
+    /// the single best alignment path of the read window to the haplotype window and its score,
+    /// for reporting alignments (```--debug-realign```), if the model has one
+    fn traceback(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+    ) -> Option<(LogProb, Vec<AlignmentState>)> {
+        viterbi_alignment_path(read, hap, &params.iter().map(|p| p.ln()).collect())
+    }
+}
+
+/// The pair HMM forward algorithm (the default)
+pub struct PairHmmForward;
+
+impl RealignmentModel for PairHmmForward {
+    fn name(&self) -> &'static str {
+        "forward"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        forward_algorithm_non_numerically_stable(read, hap, params, band_width)
+    }
+}
+
+/// The pair HMM forward algorithm in log space (```-S```)
+pub struct PairHmmForwardLogSpace;
+
+impl RealignmentModel for PairHmmForwardLogSpace {
+    fn name(&self) -> &'static str {
+        "forward-logspace"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        forward_algorithm_numerically_stable(
+            read,
+            hap,
+            &params.iter().map(|p| p.ln()).collect(),
+            band_width,
+        )
+    }
+}
+
+/// The pair HMM forward algorithm in log space with 32-bit floats (```--reduced_precision```)
+pub struct PairHmmForwardF32;
+
+impl RealignmentModel for PairHmmForwardF32 {
+    fn name(&self) -> &'static str {
+        "forward-f32"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        forward_algorithm_numerically_stable_f32(
+            read,
+            hap,
+            &params.iter().map(|p| p.ln()).collect(),
+            band_width,
+        )
+    }
+}
+
+/// The pair HMM forward algorithm in run-length space (```--rle_alignment```)
+pub struct PairHmmForwardRunLength;
+
+impl RealignmentModel for PairHmmForwardRunLength {
+    fn name(&self) -> &'static str {
+        "forward-rle"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        forward_algorithm_run_length(
+            read,
+            hap,
+            &params.iter().map(|p| p.ln()).collect(),
+            band_width,
+        )
+    }
+}
+
+/// The score of the best pair HMM alignment (```-x```)
+pub struct PairHmmViterbi;
+
+impl RealignmentModel for PairHmmViterbi {
+    fn name(&self) -> &'static str {
+        "viterbi"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        viterbi_max_scoring_alignment(
+            read,
+            hap,
+            &params.iter().map(|p| p.ln()).collect(),
+            band_width,
+        )
+        .best
+    }
+}
+
+/// The edit distance of the read to the haplotype, as the likelihood of that many independent
+/// errors: ```d * ln(p) + (n - d) * ln(1 - p)``` for a read window of length n with edit distance
+/// d, where p is the mean probability that a base of the haplotype is not read as a match of the
+/// same base under the alignment parameters. A fast baseline that ignores the error profile of the
+/// reads (e.g. that gap extensions are cheaper than gap opens).
+pub struct EditDistance;
+
+impl RealignmentModel for EditDistance {
+    fn name(&self) -> &'static str {
+        "edit-distance"
+    }
+    fn score(
+        &self,
+        read: &[u8],
+        hap: &[u8],
+        params: &Vec<AlignmentParameters>,
+        band_width: usize,
+    ) -> LogProb {
+        assert_eq!(params.len(), hap.len());
+        edit_distance_score(banded_edit_distance(read, hap, band_width), read.len(), params)
+    }
+}
+
+/// The score of the ```EditDistance``` model for a read window of length ```read_len``` with edit
+/// distance ```d``` to a haplotype window with the alignment parameters ```params```
+pub fn edit_distance_score(d: usize, read_len: usize, params: &Vec<AlignmentParameters>) -> LogProb {
+    let p_error: f64 = params
+        .iter()
+        .map(|p| 1.0 - p.transition_probs.match_from_match * p.emission_probs.equal)
+        .sum::<f64>()
+        / params.len().max(1) as f64;
+    let d = d as f64;
+    let n = (read_len as f64).max(d);
+    LogProb(d * p_error.ln() + (n - d) * (1.0 - p_error).ln())
+}
+
+/// The Levenshtein distance of v and w, within a band around the diagonal of ```min_band_width```
+/// plus the difference of their lengths
+pub fn banded_edit_distance(v: &[u8], w: &[u8], min_band_width: usize) -> usize {
+    let (v, w) = (PackedSeq::new(v), PackedSeq::new(w));
+    let alphabet = Alphabet::current();
+    let band_width = min_band_width.saturating_add(len_diff(v.len(), w.len()));
+    count_alignment(band_cells(v.len(), w.len(), band_width));
+    // cells outside of the band are never the best path
+    let outside = v.len() + w.len() + 1;
+
+    let mut prev: Vec<usize> = (0..w.len() + 1)
+        .map(|j| if j <= band_width { j } else { outside })
+        .collect();
+    let mut curr: Vec<usize> = vec![outside; w.len() + 1];
+    for i in 1..v.len() + 1 {
+        let (band_start, band_end) = band_bounds(i, v.len(), w.len(), band_width);
+
+        for x in curr.iter_mut() {
+            *x = outside;
+        }
+        if band_start == 1 {
+            curr[0] = i;
+        }
+        for j in band_start..band_end + 1 {
+            let mismatch = if alphabet.compare(v.get(i - 1), w.get(j - 1)).is_compatible() {
+                0
+            } else {
+                1
+            };
+            let substitution = prev[j - 1] + mismatch;
+            curr[j] = substitution.min(prev[j] + 1).min(curr[j - 1] + 1);
+        }
+        mem::swap(&mut prev, &mut curr);
+    }
+    prev[w.len()]
+}
+
+/// The alignment functions with the ```&Vec<char>``` sequences that they took before they were
+/// changed to byte slices, for the callers that are not migrated yet. Each one converts the
+/// sequences to bytes and calls the function of the same name in ```realignment```.
+pub mod chars {
+    use super::{AlignmentParameters, LnAlignmentParameters, ViterbiScores};
+    use bio::stats::LogProb;
+    use estimate_alignment_parameters::AlignmentState;
+
+    fn bytes(seq: &[char]) -> Vec<u8> {
+        seq.iter().map(|&c| c as u8).collect()
+    }
+
+    #[deprecated(
+        note = "use realignment::forward_algorithm_non_numerically_stable with byte slices"
+    )]
+    pub fn forward_algorithm_non_numerically_stable(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<AlignmentParameters>,
+        min_band_width: usize,
+    ) -> LogProb {
+        super::forward_algorithm_non_numerically_stable(
+            &bytes(v),
+            &bytes(w),
+            params,
+            min_band_width,
+        )
+    }
+
+    #[deprecated(note = "use realignment::forward_algorithm_numerically_stable with byte slices")]
+    pub fn forward_algorithm_numerically_stable(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+        min_band_width: usize,
+    ) -> LogProb {
+        super::forward_algorithm_numerically_stable(&bytes(v), &bytes(w), params, min_band_width)
+    }
+
+    #[deprecated(note = "use realignment::forward_algorithm_free_end with byte slices")]
+    pub fn forward_algorithm_free_end(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+    ) -> LogProb {
+        super::forward_algorithm_free_end(&bytes(v), &bytes(w), params)
+    }
+
+    #[deprecated(note = "use realignment::forward_algorithm_run_length with byte slices")]
+    pub fn forward_algorithm_run_length(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+        min_band_width: usize,
+    ) -> LogProb {
+        super::forward_algorithm_run_length(&bytes(v), &bytes(w), params, min_band_width)
+    }
+
+    #[deprecated(
+        note = "use realignment::forward_algorithm_numerically_stable_f32 with byte slices"
+    )]
+    pub fn forward_algorithm_numerically_stable_f32(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+        min_band_width: usize,
+    ) -> LogProb {
+        super::forward_algorithm_numerically_stable_f32(
+            &bytes(v),
+            &bytes(w),
+            params,
+            min_band_width,
+        )
+    }
+
+    #[deprecated(note = "use realignment::viterbi_max_scoring_alignment with byte slices")]
+    pub fn viterbi_max_scoring_alignment(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+        min_band_width: usize,
+    ) -> ViterbiScores {
+        super::viterbi_max_scoring_alignment(&bytes(v), &bytes(w), params, min_band_width)
+    }
+
+    #[deprecated(note = "use realignment::alignment_path_score with byte slices")]
+    pub fn alignment_path_score(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        path: &Vec<AlignmentState>,
+        params: &Vec<LnAlignmentParameters>,
+    ) -> Option<LogProb> {
+        super::alignment_path_score(&bytes(v), &bytes(w), path, params)
+    }
+
+    #[deprecated(note = "use realignment::viterbi_alignment_path with byte slices")]
+    pub fn viterbi_alignment_path(
+        v: &Vec<char>,
+        w: &Vec<char>,
+        params: &Vec<LnAlignmentParameters>,
+    ) -> Option<(LogProb, Vec<AlignmentState>)> {
+        super::viterbi_alignment_path(&bytes(v), &bytes(w), params)
+    }
+
+    #[deprecated(note = "use realignment::banded_edit_distance with byte slices")]
+    pub fn banded_edit_distance(v: &Vec<char>, w: &Vec<char>, min_band_width: usize) -> usize {
+        super::banded_edit_distance(&bytes(v), &bytes(w), min_band_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alphabet::{CODE_N, CODE_WILDCARD};
+
+    #[test]
+    fn test_kmer_context_index() {
+        let seq: Vec<char> = "ACGTNAC".chars().collect();
+        // CGT = 1*16 + 2*4 + 3
+        assert_eq!(kmer_context_index(&seq, 2, 3), Some(27));
+        assert_eq!(kmer_context_index(&seq, 0, 1), Some(0));
+        // runs past the start of the sequence, or contains an N
+        assert_eq!(kmer_context_index(&seq, 0, 3), None);
+        assert_eq!(kmer_context_index(&seq, 4, 3), None);
+        assert_eq!(kmer_context_index(&seq, 6, 3), None);
+    }
+
+    #[test]
+    fn test_packed_seq() {
+        let seq = PackedSeq::new(b"ACGTNa*R");
+        assert_eq!(seq.len(), 8);
+        let codes: Vec<u8> = (0..seq.len()).map(|i| seq.get(i)).collect();
+        assert_eq!(codes, vec![0, 1, 2, 3, CODE_N, 0, CODE_WILDCARD, CODE_N]);
+        assert_eq!(PackedSeq::new(b"ACG").get(2), 2);
+        assert!(PackedSeq::new(b"").is_empty());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_char_shims() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let v: Vec<char> = "ACGTTACAAG".chars().collect();
+        let w: Vec<char> = "ACGTTGCAAG".chars().collect();
+        let (vb, wb): (Vec<u8>, Vec<u8>) = (b"ACGTTACAAG".to_vec(), b"ACGTTGCAAG".to_vec());
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+        assert_eq!(
+            chars::forward_algorithm_numerically_stable(&v, &w, &ln_params, 20),
+            forward_algorithm_numerically_stable(&vb, &wb, &ln_params, 20)
+        );
+        assert!(
+            chars::viterbi_alignment_path(&v, &w, &ln_params)
+                == viterbi_alignment_path(&vb, &wb, &ln_params)
+        );
+        assert_eq!(chars::banded_edit_distance(&v, &w, 5), 1);
+    }
+
+    #[test]
+    fn test_band_cells() {
+        // rows of 2, 3, 3 and 2 cells around the diagonal
+        assert_eq!(band_cells(4, 4, 1), 10);
+        // a band wider than the haplotype covers the whole matrix
+        assert_eq!(band_cells(3, 5, 10), 15);
+        assert_eq!(band_cells(0, 5, 10), 0);
+
+        assert_eq!(len_diff(3, 5), 2);
+        assert_eq!(len_diff(5, 3), 2);
+        // the band is clipped to the first and last columns
+        assert_eq!(band_bounds(1, 4, 4, 1), (1, 2));
+        assert_eq!(band_bounds(4, 4, 4, 1), (3, 4));
+        // the band of windows of several Mb, whose lengths multiply past 32 bits
+        let len = 5_000_000;
+        assert_eq!(band_bounds(len, len, len, 10), (len - 10, len));
+        assert_eq!(band_bounds(len / 2, len, 2 * len, 4 * len), (1, 2 * len));
+    }
+
+    #[test]
+    fn test_forward_algorithm_f32() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.88,
+                insertion_from_match: 0.07,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.3,
+                match_from_insertion: 0.7,
+                deletion_from_deletion: 0.25,
+                match_from_deletion: 0.75,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.96,
+                not_equal: 0.0133,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let bases = [b'A', b'C', b'G', b'T'];
+        // a pseudo-random 300 bp haplotype, and a read with mismatches, an insertion and a deletion
+        let w: Vec<u8> = (0..300).map(|i| bases[(i * 7 + i / 3) % 4]).collect();
+        let mut v: Vec<u8> = w.clone();
+        v[20] = if v[20] == b'A' { b'C' } else { b'A' };
+        v.insert(100, b'G');
+        v.remove(200);
+        v.truncate(290);
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+
+        for &(ref v, ref w) in &[(&v, &w), (&w, &w), (&v[..50].to_vec(), &w[..60].to_vec())] {
+            let ln_params = ln_params[..w.len()].to_vec();
+            let score64 = forward_algorithm_numerically_stable(v, w, &ln_params, 20);
+            let score32 = forward_algorithm_numerically_stable_f32(v, w, &ln_params, 20);
+            let bound = (v.len() + w.len()) as f64 * 2.0f64.powi(-23) * score64.abs();
+            assert!(*score64 < 0.0);
+            assert!((*score32 - *score64).abs() <= bound.max(1e-5));
+            assert!((*score32 - *score64).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_forward_algorithm_base_quals() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let v = b"ACGTTACAAG";
+        let w = b"ACGTTGCAAG";
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+        let score = forward_algorithm_numerically_stable(v, w, &ln_params, 20);
+
+        // without base errors, the scores are those of the alignment parameters alone
+        let no_errors = forward_algorithm_base_quals(v, &[0.0; 10], w, &ln_params, 20);
+        assert!((*no_errors - *score).abs() < 1e-9);
+
+        // a low quality mismatch costs less, and low quality matches cost more
+        let mut errors = [0.0; 10];
+        errors[5] = 0.5;
+        assert!(*forward_algorithm_base_quals(v, &errors, w, &ln_params, 20) > *score);
+        let errors = [0.1; 10];
+        let self_score = forward_algorithm_numerically_stable(w, w, &ln_params, 20);
+        assert!(*forward_algorithm_base_quals(w, &errors, w, &ln_params, 20) < *self_score);
+    }
+
+    #[test]
+    fn test_run_length_encode() {
+        let seq: Vec<u8> = "AAACGGT".bytes().collect();
+        let rle = run_length_encode(&seq);
+        assert_eq!(rle.bases, vec![0, 1, 2, 3]);
+        assert_eq!(rle.lens, vec![3, 1, 2, 1]);
+        assert_eq!(rle.starts, vec![0, 3, 4, 6]);
+        assert!(run_length_encode(&[]).bases.is_empty());
+    }
+
+    #[test]
+    fn test_forward_algorithm_run_length() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.3,
+                match_from_insertion: 0.7,
+                deletion_from_deletion: 0.3,
+                match_from_deletion: 0.7,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let t = params.ln().transition_probs;
+        // the correct length is the most likely, and longer errors are less likely
+        for hap_len in 1..20 {
+            let correct = ln_run_length_prob(hap_len, hap_len, &t);
+            assert!(correct > ln_run_length_prob(hap_len + 1, hap_len, &t));
+            assert!(
+                ln_run_length_prob(hap_len + 1, hap_len, &t)
+                    > ln_run_length_prob(hap_len + 2, hap_len, &t)
+            );
+            if hap_len > 1 {
+                assert!(correct > ln_run_length_prob(hap_len - 1, hap_len, &t));
+            }
+        }
+        // a homopolymer length error is more likely in a longer run
+        assert!(ln_run_length_prob(9, 8, &t) > ln_run_length_prob(3, 2, &t));
+
+        // a read with a run of 5 G's supports the haplotype with 5 G's over those with 4 or 6
+        let seq = |s: &str| -> Vec<u8> { s.bytes().collect() };
+        let v = seq("ACTTAGGGGGCATCAG");
+        let haps = vec![
+            seq("ACTTAGGGGCATCAG"),
+            seq("ACTTAGGGGGCATCAG"),
+            seq("ACTTAGGGGGGCATCAG"),
+        ];
+        let scores: Vec<LogProb> = haps
+            .iter()
+            .map(|w| forward_algorithm_run_length(&v, w, &vec![params.ln(); w.len()], 20))
+            .collect();
+        assert!(scores[1] > scores[0] && scores[1] > scores[2]);
+        assert!(*scores[1] < 0.0);
+        // and a mismatch is still penalized
+        let w = seq("ACTTAGGGGGCTTCAG");
+        let mismatch = forward_algorithm_run_length(&v, &w, &vec![params.ln(); w.len()], 20);
+        assert!(mismatch < scores[1]);
+    }
+
+    #[test]
+    fn test_forward_algorithm_free_end() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let seq = |s: &str| s.bytes().collect::<Vec<u8>>();
+        let ref_hap = seq("ACGTTGCAAGCTAGGCATCGATTACG");
+        let alt_hap = seq("ACGTTGCAAGCTAGGCTTCGATTACG");
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); ref_hap.len()];
+
+        // a read that ends before the variant fits both haplotypes equally well
+        let read = seq("ACGTTGCAAGCTA");
+        let ref_score = forward_algorithm_free_end(&read, &ref_hap, &ln_params);
+        let alt_score = forward_algorithm_free_end(&read, &alt_hap, &ln_params);
+        assert!((*ref_score - *alt_score).abs() < 0.01);
+        // and better than the global alignment to the part of the haplotype it covers, since the
+        // alignments that end elsewhere are included
+        let global = forward_algorithm_numerically_stable(
+            &read,
+            &ref_hap[..read.len()].to_vec(),
+            &ln_params[..read.len()].to_vec(),
+            20,
+        );
+        assert!(ref_score >= global && *ref_score < 0.0);
+
+        // a read that ends just after the variant supports the allele it has
+        let read = seq("ACGTTGCAAGCTAGGCAT");
+        let ref_score = forward_algorithm_free_end(&read, &ref_hap, &ln_params);
+        let alt_score = forward_algorithm_free_end(&read, &alt_hap, &ln_params);
+        assert!(*ref_score - *alt_score > 2.0);
+    }
+
+    #[test]
+    fn test_alignment_path_score() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let v: Vec<u8> = "ACGTACGT".bytes().collect();
+        let w: Vec<u8> = "ACGTCGT".bytes().collect();
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+
+        // the best path has an insertion of the second A, and scores the same as the max scoring alignment
+        let mut path = vec![AlignmentState::Match; 4];
+        path.push(AlignmentState::Insertion);
+        path.extend(vec![AlignmentState::Match; 3]);
+        let score = alignment_path_score(&v, &w, &path, &ln_params).unwrap();
+        let max_score = viterbi_max_scoring_alignment(&v, &w, &ln_params, 20);
+        assert!((*score - *max_score.best).abs() < 1e-9);
+
+        // paths that don't consume both sequences, or don't end in a match
+        assert_eq!(alignment_path_score(&v, &w, &path[..7].to_vec(), &ln_params), None);
+        path.push(AlignmentState::Insertion);
+        assert_eq!(alignment_path_score(&v, &w, &path, &ln_params), None);
+    }
+
+    #[test]
+    fn test_viterbi_alignment_path() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+
+        // the read has an extra A, and the traced path has the score of the max scoring alignment
+        let v: Vec<u8> = "ACGTACGT".bytes().collect();
+        let w: Vec<u8> = "ACGTCGT".bytes().collect();
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+        let (score, path) = viterbi_alignment_path(&v, &w, &ln_params).unwrap();
+        let mut expected = vec![AlignmentState::Match; 4];
+        expected.push(AlignmentState::Insertion);
+        expected.extend(vec![AlignmentState::Match; 3]);
+        assert!(path == expected);
+        assert!((*score - *viterbi_max_scoring_alignment(&v, &w, &ln_params, 20).best).abs() < 1e-9);
+        let path_score = alignment_path_score(&v, &w, &path, &ln_params).unwrap();
+        assert!((*score - *path_score).abs() < 1e-9);
+
+        // the read is missing a base
+        let (score, path) = viterbi_alignment_path(&w, &v, &vec![params.ln(); v.len()]).unwrap();
+        assert_eq!(path.len(), 8);
+        assert!(path[4] == AlignmentState::Deletion);
+        assert!(score > LogProb::ln_zero());
+
+        assert!(viterbi_alignment_path(&[], &w, &ln_params).is_none());
+    }
+
+    #[test]
+    fn test_viterbi_second_best_path() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+
+        // the inserted G can be placed before any of the three G's of the homopolymer, so the best
+        // path isn't unique and the margin is zero
+        let v: Vec<u8> = "ACGGGT".bytes().collect();
+        let w: Vec<u8> = "ACGGT".bytes().collect();
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+        let scores = viterbi_max_scoring_alignment(&v, &w, &ln_params, 20);
+        let mut path = vec![AlignmentState::Match; 2];
+        path.push(AlignmentState::Insertion);
+        path.extend(vec![AlignmentState::Match; 3]);
+        let score = alignment_path_score(&v, &w, &path, &ln_params).unwrap();
+        assert!((*scores.best - *score).abs() < 1e-9);
+        assert!(scores.margin().abs() < 1e-9);
+
+        // a read equal to the haplotype has a single best path, well ahead of any path with indels
+        let v: Vec<u8> = "ACGTACGT".bytes().collect();
+        let w: Vec<u8> = "ACGTACGT".bytes().collect();
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); w.len()];
+        let scores = viterbi_max_scoring_alignment(&v, &w, &ln_params, 20);
+        let path = vec![AlignmentState::Match; 8];
+        let score = alignment_path_score(&v, &w, &path, &ln_params).unwrap();
+        assert!((*scores.best - *score).abs() < 1e-9);
+        assert!(scores.second_best > LogProb::ln_zero());
+        assert!(scores.margin() > 2.0);
+    }
+
+    #[test]
+    fn test_wildcard_base() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.5,
+                match_from_insertion: 0.5,
+                deletion_from_deletion: 0.5,
+                match_from_deletion: 0.5,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let seq = |s: &str| -> Vec<u8> { s.bytes().collect() };
+        let ln_params: Vec<LnAlignmentParameters> = vec![params.ln(); 10];
+        let read = seq("ACGTTGCAAG");
+        let score =
+            |hap: &str| forward_algorithm_numerically_stable(&read, &seq(hap), &ln_params, 20);
+
+        // a wildcard scores like the read base, and an N like any other mismatch
+        assert!((*score("ACGT*GCAAG") - *score("ACGTTGCAAG")).abs() < 1e-9);
+        assert!((*score("ACGTNGCAAG") - *score("ACGTAGCAAG")).abs() < 1e-9);
+        assert!(*score("ACGT*GCAAG") > *score("ACGTNGCAAG"));
+
+        assert_eq!(banded_edit_distance(&read, &seq("ACGT*GCAAG"), 5), 0);
+        assert_eq!(banded_edit_distance(&read, &seq("ACGTNGCAAG"), 5), 1);
+    }
+
+    #[test]
+    fn test_banded_edit_distance() {
+        let seq = |s: &str| -> Vec<u8> { s.bytes().collect() };
+        assert_eq!(banded_edit_distance(&seq("ACGTACGT"), &seq("ACGTACGT"), 5), 0);
+        assert_eq!(banded_edit_distance(&seq("ACGTACGT"), &seq("ACGAACGT"), 5), 1);
+        assert_eq!(banded_edit_distance(&seq("ACGTTACGT"), &seq("ACGTACGT"), 5), 1);
+        assert_eq!(banded_edit_distance(&seq("ACGACGT"), &seq("ACGTACGT"), 5), 1);
+        assert_eq!(banded_edit_distance(&seq("GCGTACGA"), &seq("ACGTTACG"), 5), 3);
+        assert_eq!(banded_edit_distance(&[], &seq("ACG"), 0), 3);
+    }
+
+    #[test]
+    fn test_realignment_models() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.3,
+                match_from_insertion: 0.7,
+                deletion_from_deletion: 0.3,
+                match_from_deletion: 0.7,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let ref_hap: Vec<u8> = "ACGTTGCAAGCTAGGCATGCAT".bytes().collect();
+        let mut alt_hap = ref_hap.clone();
+        alt_hap[11] = b'G';
+        let mut read = alt_hap.clone();
+        read.remove(3);
+
+        // every model prefers the haplotype that the read was taken from
+        for (i, name) in REALIGNMENT_MODEL_NAMES.iter().enumerate() {
+            let alignment_type = AlignmentType::from_name(name).unwrap();
+            let model = alignment_type.model();
+            assert_eq!(model.name(), *name);
+            assert_eq!(alignment_type as usize, i);
+            let ref_score = model.score(&read, &ref_hap, &vec![params; ref_hap.len()], 5);
+            let alt_score = model.score(&read, &alt_hap, &vec![params; alt_hap.len()], 5);
+            assert!(alt_score > ref_score, "{}", name);
+            let (path_score, path) = model
+                .traceback(&read, &alt_hap, &vec![params; alt_hap.len()])
+                .unwrap();
+            assert!(path_score > LogProb::ln_zero());
+            assert_eq!(path.len(), alt_hap.len());
+        }
+        assert!(AlignmentType::from_name("smith-waterman").is_none());
+
+        // the edit distance is scored as that many errors at the mean error rate
+        let p_error = 1.0 - 0.9 * 0.97;
+        let score = EditDistance.score(&read, &ref_hap, &vec![params; ref_hap.len()], 5);
+        assert!((*score - (2.0 * p_error.ln() + 19.0 * (1.0 - p_error).ln())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_chunked_score() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.9,
+                insertion_from_match: 0.05,
+                deletion_from_match: 0.05,
+                insertion_from_insertion: 0.3,
+                match_from_insertion: 0.7,
+                deletion_from_deletion: 0.3,
+                match_from_deletion: 0.7,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        };
+        let ref_hap: Vec<u8> = (0..2000)
+            .map(|i: usize| b"ACGT"[(i * i * 31 + i * 7) % 97 % 4])
+            .collect();
+        let mut alt_hap = ref_hap.clone();
+        alt_hap[700] = if ref_hap[700] == b'A' { b'C' } else { b'A' };
+        alt_hap.remove(1300);
+        let read = alt_hap.clone();
+
+        // windows of 2000 bases in chunks of at most 500 bases score close to the whole window
+        let model = AlignmentType::ForwardAlgorithmNonNumericallyStable.model();
+        let ref_params = vec![params; ref_hap.len()];
+        let alt_params = vec![params; alt_hap.len()];
+        let whole = model.score(&read, &alt_hap, &alt_params, 20);
+        let chunked = chunked_score(model, &read, &alt_hap, &alt_params, 20, 500);
+        assert!((*whole - *chunked).abs() < 1.0);
+        assert!(chunked > chunked_score(model, &read, &ref_hap, &ref_params, 20, 500));
+
+        // every chunk has a base of the shorter window
+        let short_hap = &ref_hap[..3];
+        let score = chunked_score(model, &read, short_hap, &ref_params[..3].to_vec(), 20, 500);
+        assert!((*score).is_finite());
+
+        assert!(check_window_lengths(MAX_WINDOW_LEN, 10).is_ok());
+        assert!(check_window_lengths(MAX_WINDOW_LEN + 1, 10).is_err());
+        assert!(check_window_lengths(10, MAX_WINDOW_LEN + 1).is_err());
+    }
 }
@@ -0,0 +1,169 @@
+//! A file of requested regions that is watched while longshot is running, for interactive review
+//! sessions in which a curator requests loci on demand.
+//!
+//! The BAM files, reference and alignment parameters are loaded once, and every region that is
+//! appended to the queue file (one ```<chrom>``` or ```<chrom:start-stop>``` per line) is called
+//! and appended to the output VCF as soon as it is finished. Lines starting with '#' are ignored,
+//! and a line ```END``` ends the session. A line is only read once it is terminated by a newline,
+//! so that a region that is still being written isn't read half-way.
+
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+use util::*;
+
+/// The line that ends the session
+pub const END_OF_QUEUE: &str = "END";
+
+/// A requested region of the queue file
+#[derive(Debug, PartialEq)]
+pub enum QueueEntry<'a> {
+    Region(&'a str),
+    End,
+}
+
+/// Returns the complete lines of the queue file after the first ```consumed``` complete lines,
+/// skipping blank lines and comments.
+///
+/// # Returns
+/// Returns the new entries and the number of complete lines in the file
+pub fn new_queue_entries(contents: &str, consumed: usize) -> (Vec<QueueEntry>, usize) {
+    // the text after the last newline is a line that is still being written
+    let complete = match contents.rfind('\n') {
+        Some(i) => &contents[..i + 1],
+        None => "",
+    };
+    let mut entries = vec![];
+    let mut n_lines = 0;
+    for line in complete.lines() {
+        n_lines += 1;
+        if n_lines <= consumed {
+            continue;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == END_OF_QUEUE {
+            entries.push(QueueEntry::End);
+        } else {
+            entries.push(QueueEntry::Region(line));
+        }
+    }
+    (entries, n_lines)
+}
+
+/// Iterator over the regions of a queue file as they are requested. It blocks until a new region
+/// is appended to the file, and stops after the ```END``` line.
+pub struct RegionQueue {
+    queue_file: String,
+    bamfile_name: String,
+    poll_interval: Duration,
+    consumed: usize,
+    pending: Vec<GenomicInterval>,
+    finished: bool,
+}
+
+impl RegionQueue {
+    pub fn new(queue_file: &String, bamfile_name: &String, poll_interval: Duration) -> RegionQueue {
+        RegionQueue {
+            queue_file: queue_file.clone(),
+            bamfile_name: bamfile_name.clone(),
+            poll_interval: poll_interval,
+            consumed: 0,
+            pending: vec![],
+            finished: false,
+        }
+    }
+
+    // reads the new lines of the queue file. invalid regions are reported and skipped, so that a
+    // typo doesn't end the session
+    fn poll(&mut self) {
+        // the file may not have been created yet
+        let contents = match fs::read_to_string(&self.queue_file) {
+            Ok(c) => c,
+            Err(_) => {
+                return;
+            }
+        };
+        let (entries, n_lines) = new_queue_entries(&contents, self.consumed);
+        self.consumed = n_lines;
+        for entry in entries {
+            match entry {
+                QueueEntry::End => {
+                    self.finished = true;
+                    break;
+                }
+                QueueEntry::Region(r) => match parse_region_string(Some(r), &self.bamfile_name) {
+                    Ok(Some(iv)) => {
+                        self.pending.push(iv);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!(
+                            "{} WARNING: Skipping requested region {}: {}",
+                            print_time(),
+                            r,
+                            e
+                        );
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Iterator for RegionQueue {
+    type Item = Option<GenomicInterval>;
+
+    fn next(&mut self) -> Option<Option<GenomicInterval>> {
+        loop {
+            if !self.pending.is_empty() {
+                let iv = self.pending.remove(0);
+                info!(
+                    "{} Calling variants in requested region {}:{}-{}...",
+                    print_time(),
+                    iv.chrom,
+                    iv.start_pos + 1,
+                    iv.end_pos + 1
+                );
+                return Some(Some(iv));
+            }
+            if self.finished {
+                return None;
+            }
+            self.poll();
+            if self.pending.is_empty() && !self.finished {
+                sleep(self.poll_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_queue_entries() {
+        let contents = "chr1:100-200\n# comment\n\nchr2\nchr3:1-";
+        let (entries, n_lines) = new_queue_entries(contents, 0);
+        // the last line isn't complete yet
+        assert_eq!(
+            entries,
+            vec![
+                QueueEntry::Region("chr1:100-200"),
+                QueueEntry::Region("chr2")
+            ]
+        );
+        assert_eq!(n_lines, 4);
+
+        let contents = "chr1:100-200\n# comment\n\nchr2\nchr3:1-500\nEND\n";
+        let (entries, n_lines) = new_queue_entries(contents, 4);
+        assert_eq!(
+            entries,
+            vec![QueueEntry::Region("chr3:1-500"), QueueEntry::End]
+        );
+        assert_eq!(n_lines, 6);
+    }
+}
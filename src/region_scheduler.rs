@@ -0,0 +1,492 @@
+//! Whole-genome runs with the contigs split into windows of about equal work, which the threads
+//! steal from each other (```--threads``` with ```--scheduler windows```).
+//!
+//! Calling a contig per process leaves the threads idle at the end of a run when the coverage is
+//! uneven (e.g. a deep chrM or a few contigs with most of the reads). Instead, the expected work of
+//! each contig is estimated from the depth and read length of a few sampled windows, and the
+//! contigs are split into work units so that there are ```UNITS_PER_THREAD``` units of about the
+//! same work for each thread. Every worker starts with a contiguous stretch of the units of the
+//! genome, and takes a batch of units from its front at a time: half of its remaining work, so that
+//! the batches get smaller towards the end of the run. A worker without units left steals half of
+//! the remaining work of the worker that has the most, from the back of its stretch.
+//!
+//! The units of a batch are called by one longshot process with a BED file of the units (see
+//! ```--bed```), which opens the BAM files and the reference once for the whole batch, and there
+//! are never more than ```threads``` processes with open readers. Neighbouring units of a batch are
+//! merged into one region. When every batch is finished, the VCFs of the batches are merged record
+//! by record, as by ```longshot merge``` (see ```vcf_merge```).
+
+extern crate rust_htslib;
+
+use errors::*;
+use rust_htslib::bam;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use util::*;
+use vcf_merge::merge_vcfs;
+
+/// number of windows that the depth and read length of a contig are sampled in
+static WORK_SAMPLE_WINDOWS: u32 = 4;
+/// length of the windows that the depth and read length of a contig are sampled in
+static WORK_SAMPLE_WINDOW_LEN: u32 = 10000;
+/// number of work units of about the same work that the genome is split into for each thread
+static UNITS_PER_THREAD: usize = 8;
+/// the minimum length of a work unit, so that few variants are near the boundaries of the units
+static MIN_UNIT_LEN: u32 = 1000000;
+
+/// How the work of a whole-genome run is shared between the threads
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedulerMode {
+    /// a longshot process for each contig, longest contigs first
+    Contigs,
+    /// work units of about equal work, stolen between the threads
+    Windows,
+}
+
+pub static SCHEDULER_NAMES: [&str; 2] = ["contigs", "windows"];
+
+impl SchedulerMode {
+    pub fn from_name(name: &str) -> Option<SchedulerMode> {
+        match name {
+            "contigs" => Some(SchedulerMode::Contigs),
+            "windows" => Some(SchedulerMode::Windows),
+            _ => None,
+        }
+    }
+}
+
+/// A window of a contig that is called as a whole, and its expected work (the number of read
+/// bases times the read length)
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkUnit {
+    pub interval: GenomicInterval,
+    pub work: f64,
+}
+
+/// Estimates the work of calling a base of a contig: the mean depth times the mean read length of
+/// ```WORK_SAMPLE_WINDOWS``` evenly spaced windows, since both the number of reads and the cost of
+/// the realignment of each read grow with them. Reads that fail the basic QC filters are not
+/// counted.
+pub fn sample_work_per_base(
+    bam_ix: &mut IndexedBamReader,
+    contig: &GenomicInterval,
+) -> Result<f64> {
+    let len = contig.end_pos - contig.start_pos + 1;
+    let (mut sampled_len, mut read_bases, mut read_len_sum, mut n_reads) = (0u64, 0u64, 0u64, 0u64);
+    for k in 0..WORK_SAMPLE_WINDOWS {
+        let start = contig.start_pos
+            + (len as u64 * (2 * k + 1) as u64 / (2 * WORK_SAMPLE_WINDOWS) as u64) as u32;
+        let end = (start + WORK_SAMPLE_WINDOW_LEN).min(contig.end_pos + 1);
+        sampled_len += (end - start) as u64;
+        bam_ix.fetch(contig.tid, start, end)?;
+        for r in bam_ix.records() {
+            let record: bam::Record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.is_duplicate()
+                || record.is_quality_check_failed()
+            {
+                continue;
+            }
+            let read_start = (record.pos().max(0) as u32).max(start);
+            let read_end = (record.cigar().end_pos().max(0) as u32).min(end);
+            if read_start < read_end {
+                read_bases += (read_end - read_start) as u64;
+            }
+            read_len_sum += record.seq().len() as u64;
+            n_reads += 1;
+        }
+    }
+    if n_reads == 0 || sampled_len == 0 {
+        return Ok(0.0);
+    }
+    let depth = read_bases as f64 / sampled_len as f64;
+    Ok(depth * read_len_sum as f64 / n_reads as f64)
+}
+
+/// Splits the contigs into work units, in the order of the contigs, so that there are about
+/// ```UNITS_PER_THREAD``` units of the same work for each thread. The units are at least
+/// ```MIN_UNIT_LEN``` bases long, and contigs without reads are a single unit.
+pub fn split_into_units(
+    contigs: &[GenomicInterval],
+    work_per_base: &[f64],
+    threads: usize,
+) -> Vec<WorkUnit> {
+    let contig_len = |iv: &GenomicInterval| (iv.end_pos - iv.start_pos + 1) as f64;
+    let total_work: f64 = contigs
+        .iter()
+        .zip(work_per_base)
+        .map(|(iv, w)| contig_len(iv) * w)
+        .sum();
+    let unit_work = total_work / (threads * UNITS_PER_THREAD).max(1) as f64;
+
+    let mut units: Vec<WorkUnit> = vec![];
+    for (iv, &w) in contigs.iter().zip(work_per_base) {
+        let len = iv.end_pos - iv.start_pos + 1;
+        let n_units: u32 = if w > 0.0 && unit_work > 0.0 {
+            let unit_len = (unit_work / w).max(MIN_UNIT_LEN as f64);
+            ((len as f64 / unit_len).ceil() as u32).max(1)
+        } else {
+            1
+        };
+        // the units of a contig have the same length, give or take a base
+        for u in 0..n_units {
+            let start = iv.start_pos + (len as u64 * u as u64 / n_units as u64) as u32;
+            let end = iv.start_pos + (len as u64 * (u + 1) as u64 / n_units as u64) as u32 - 1;
+            units.push(WorkUnit {
+                interval: GenomicInterval {
+                    tid: iv.tid,
+                    chrom: iv.chrom.clone(),
+                    start_pos: start,
+                    end_pos: end,
+                },
+                work: (end - start + 1) as f64 * w,
+            });
+        }
+    }
+    units
+}
+
+/// The work units of each worker, which it takes from the front of its own queue and which other
+/// workers steal from the back of it.
+pub struct WorkQueues {
+    queues: Vec<Mutex<VecDeque<WorkUnit>>>,
+}
+
+impl WorkQueues {
+    /// Deals the units (in the order of the genome) to ```workers``` queues as contiguous stretches
+    /// of about the same work
+    pub fn new(units: Vec<WorkUnit>, workers: usize) -> WorkQueues {
+        let total_work: f64 = units.iter().map(|u| u.work).sum();
+        let n_units = units.len();
+        let mut queues: Vec<VecDeque<WorkUnit>> = (0..workers).map(|_| VecDeque::new()).collect();
+        let mut cumulative_work = 0.0;
+        for (i, unit) in units.into_iter().enumerate() {
+            // without any work, the units are dealt by their number instead
+            let w = if total_work > 0.0 {
+                ((cumulative_work + unit.work / 2.0) / total_work * workers as f64) as usize
+            } else {
+                i * workers / n_units
+            };
+            cumulative_work += unit.work;
+            queues[w.min(workers - 1)].push_back(unit);
+        }
+        WorkQueues {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    fn remaining_work(queue: &VecDeque<WorkUnit>) -> f64 {
+        queue.iter().map(|u| u.work).sum()
+    }
+
+    /// Takes the next batch of units of a worker: units from the front of its queue up to half of
+    /// its remaining work, and at least one unit. If its queue is empty, it first steals half of the
+    /// remaining work of the worker with the most work left, from the back of that worker's queue.
+    ///
+    /// # Returns
+    /// Returns the units of the batch, which are empty when no worker has any units left
+    pub fn take_batch(&self, worker: usize) -> Vec<WorkUnit> {
+        if self.queues[worker].lock().unwrap().is_empty() {
+            let stolen = self.steal(worker);
+            self.queues[worker].lock().unwrap().extend(stolen);
+        }
+        let mut queue = self.queues[worker].lock().unwrap();
+        let half = WorkQueues::remaining_work(&queue) / 2.0;
+        let mut batch: Vec<WorkUnit> = vec![];
+        let mut batch_work = 0.0;
+        while let Some(unit) = queue.pop_front() {
+            batch_work += unit.work;
+            batch.push(unit);
+            if batch_work >= half {
+                break;
+            }
+        }
+        batch
+    }
+
+    /// Takes half of the remaining work (and at least one unit) from the back of the queue of the
+    /// worker with the most work left. The units are returned in the order of the genome.
+    fn steal(&self, thief: usize) -> Vec<WorkUnit> {
+        let mut victim: Option<(usize, f64, usize)> = None;
+        for (w, queue) in self.queues.iter().enumerate() {
+            if w == thief {
+                continue;
+            }
+            let queue = queue.lock().unwrap();
+            let work = WorkQueues::remaining_work(&queue);
+            if !queue.is_empty()
+                && victim.map_or(true, |(_, v_work, v_len)| {
+                    work > v_work || (work == v_work && queue.len() > v_len)
+                })
+            {
+                victim = Some((w, work, queue.len()));
+            }
+        }
+        let mut stolen: Vec<WorkUnit> = vec![];
+        if let Some((v, _, _)) = victim {
+            // the victim may have taken units since its work was looked at
+            let mut queue = self.queues[v].lock().unwrap();
+            let half = WorkQueues::remaining_work(&queue) / 2.0;
+            let mut stolen_work = 0.0;
+            // the victim keeps its first unit, unless it is the only one
+            while queue.len() > 1 || (stolen.is_empty() && !queue.is_empty()) {
+                let unit = queue.pop_back().unwrap();
+                stolen_work += unit.work;
+                stolen.push(unit);
+                if stolen_work >= half {
+                    break;
+                }
+            }
+        }
+        stolen.reverse();
+        stolen
+    }
+}
+
+/// Writes the units of a batch to a BED file, in the order of the genome
+fn write_batch_bed(batch: &[WorkUnit], bed_file: &Path) -> Result<()> {
+    let mut intervals: Vec<&GenomicInterval> = batch.iter().map(|u| &u.interval).collect();
+    intervals.sort_by_key(|iv| (iv.tid, iv.start_pos));
+    let f = File::create(bed_file)
+        .chain_err(|| ErrorKind::CreateFileError(bed_file.display().to_string()))?;
+    let mut out = BufWriter::new(f);
+    for iv in intervals {
+        writeln!(out, "{}\t{}\t{}", iv.chrom, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| ErrorKind::FileWriteError(bed_file.display().to_string()))?;
+    }
+    out.flush()
+        .chain_err(|| ErrorKind::FileWriteError(bed_file.display().to_string()))?;
+    Ok(())
+}
+
+/// Calls the variants of the contigs in work units of about equal work, which ```threads```
+/// workers call in batches with a longshot process each and steal from each other, and merges
+/// their calls into ```output_vcf_file```.
+///
+/// # Arguments
+/// - ```child_args```: the command line options of the processes, except for ```--bed```,
+///   ```--bed_padding``` and ```--out```
+/// - ```contigs```: the contigs to call, in the order of the BAM header
+/// - ```bam_file```: the BAM file that the depth and read length of the contigs are sampled from
+/// - ```threads```: the number of batches called at the same time
+/// - ```tmp_dir```: an empty directory for the BED files, VCFs and logs of the batches. It is
+///   removed when all batches were called successfully, and kept with the logs otherwise.
+///
+/// # Errors
+/// Fails if the process of a batch fails, after the processes that are already running have
+/// finished. No new batches are started after the first failure.
+pub fn call_windows_in_parallel(
+    child_args: &Vec<String>,
+    contigs: &Vec<GenomicInterval>,
+    bam_file: &String,
+    threads: usize,
+    tmp_dir: &Path,
+    output_vcf_file: &str,
+) -> Result<()> {
+    let exe = env::current_exe().chain_err(|| "Could not find the longshot executable.")?;
+
+    let mut bam_ix = open_indexed_bam(bam_file)?;
+    let mut work_per_base: Vec<f64> = vec![];
+    for iv in contigs {
+        work_per_base.push(sample_work_per_base(&mut bam_ix, iv)?);
+    }
+    let units = split_into_units(contigs, &work_per_base, threads);
+    let n_units = units.len();
+    info!(
+        "{} Split {} contigs into {} windows of about equal work.",
+        print_time(),
+        contigs.len(),
+        n_units
+    );
+
+    let queues = Arc::new(WorkQueues::new(units, threads));
+    let vcf_files: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(vec![]));
+    let errors: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+    let failed = Arc::new(AtomicBool::new(false));
+    let n_batches = Arc::new(AtomicUsize::new(0));
+    let n_finished = Arc::new(AtomicUsize::new(0));
+
+    let mut workers = vec![];
+    for worker in 0..threads {
+        let (exe, child_args, tmp_dir) = (exe.clone(), child_args.clone(), tmp_dir.to_path_buf());
+        let (queues, vcf_files, errors, failed, n_batches, n_finished) = (
+            queues.clone(),
+            vcf_files.clone(),
+            errors.clone(),
+            failed.clone(),
+            n_batches.clone(),
+            n_finished.clone(),
+        );
+        workers.push(thread::spawn(move || {
+            while !failed.load(Ordering::SeqCst) {
+                let batch = queues.take_batch(worker);
+                if batch.is_empty() {
+                    break;
+                }
+                let b = n_batches.fetch_add(1, Ordering::SeqCst);
+                let bed_file = tmp_dir.join(format!("batch_{}.bed", b));
+                let vcf_file = tmp_dir.join(format!("batch_{}.vcf", b));
+                let log_file = tmp_dir.join(format!("batch_{}.log", b));
+                let first = &batch[0].interval;
+                info!(
+                    "{} Calling variants in {} windows from {}:{}...",
+                    print_time(),
+                    batch.len(),
+                    first.chrom,
+                    first.start_pos + 1
+                );
+                let error = match write_batch_bed(&batch, &bed_file) {
+                    Err(e) => Some(format!("Could not write the batch {}: {}", b, e)),
+                    Ok(()) => {
+                        // the error messages of longshot are printed to stdout, so both go to
+                        // the log
+                        let status = File::create(&log_file)
+                            .and_then(|log| Ok((log.try_clone()?, log)))
+                            .and_then(|(stdout, stderr)| {
+                                Command::new(&exe)
+                                    .args(&child_args)
+                                    .arg("--bed")
+                                    .arg(&bed_file)
+                                    .arg("--bed_padding")
+                                    .arg("0")
+                                    .arg("--out")
+                                    .arg(&vcf_file)
+                                    .stdin(Stdio::null())
+                                    .stdout(stdout)
+                                    .stderr(stderr)
+                                    .status()
+                            });
+                        match status {
+                            Ok(ref s) if s.success() => None,
+                            Ok(s) => Some(format!(
+                                "Calling the windows of {} failed ({}), see the log {}.",
+                                bed_file.display(),
+                                s,
+                                log_file.display()
+                            )),
+                            Err(e) => Some(format!(
+                                "Could not run longshot on the windows of {}: {}",
+                                bed_file.display(),
+                                e
+                            )),
+                        }
+                    }
+                };
+                match error {
+                    None => {
+                        vcf_files.lock().unwrap().push(vcf_file);
+                        let n = n_finished.fetch_add(batch.len(), Ordering::SeqCst) + batch.len();
+                        info!(
+                            "{} Finished {} windows ({} of {}).",
+                            print_time(),
+                            batch.len(),
+                            n,
+                            n_units
+                        );
+                    }
+                    Some(e) => {
+                        failed.store(true, Ordering::SeqCst);
+                        errors.lock().unwrap().push(e);
+                    }
+                }
+            }
+        }));
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            bail!(ErrorKind::InternalError(
+                "a worker thread panicked".to_string()
+            ));
+        }
+    }
+
+    let errors = errors.lock().unwrap();
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+
+    let vcf_files: Vec<String> = vcf_files
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|f| f.to_string_lossy().to_string())
+        .collect();
+    info!(
+        "{} Merging the calls of {} batches...",
+        print_time(),
+        vcf_files.len()
+    );
+    merge_vcfs(&vcf_files, output_vcf_file)?;
+    fs::remove_dir_all(tmp_dir)
+        .chain_err(|| format!("Could not remove the directory {}.", tmp_dir.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contig(tid: u32, len: u32) -> GenomicInterval {
+        GenomicInterval {
+            tid: tid,
+            chrom: format!("chr{}", tid + 1),
+            start_pos: 0,
+            end_pos: len - 1,
+        }
+    }
+
+    #[test]
+    fn test_split_into_units() {
+        // chr1 has three times the work per base of chr2, and chr3 has no reads
+        let contigs = vec![contig(0, 20000000), contig(1, 20000000), contig(2, 5000000)];
+        let units = split_into_units(&contigs, &[300.0, 100.0, 0.0], 2);
+        let n_units = |tid: u32| units.iter().filter(|u| u.interval.tid == tid).count();
+        // 16 units of 5e8 work: about 1.7e6 bases of chr1 and 5e6 bases of chr2
+        assert_eq!(n_units(0), 12);
+        assert_eq!(n_units(1), 4);
+        assert_eq!(n_units(2), 1);
+        // the units of a contig cover it without gaps
+        let chr2: Vec<&WorkUnit> = units.iter().filter(|u| u.interval.tid == 1).collect();
+        assert_eq!(chr2[0].interval.start_pos, 0);
+        assert_eq!(chr2[3].interval.end_pos, 19999999);
+        for w in chr2.windows(2) {
+            assert_eq!(w[1].interval.start_pos, w[0].interval.end_pos + 1);
+        }
+        // the units are at least MIN_UNIT_LEN long
+        let units = split_into_units(&contigs, &[300.0, 100.0, 0.0], 64);
+        assert_eq!(units.iter().filter(|u| u.interval.tid == 0).count(), 20);
+    }
+
+    #[test]
+    fn test_work_stealing() {
+        let unit = |tid: u32, work: f64| WorkUnit {
+            interval: contig(tid, 1000),
+            work: work,
+        };
+        let units: Vec<WorkUnit> = (0..8).map(|i| unit(i, 1.0)).collect();
+        let queues = WorkQueues::new(units, 2);
+        let tids =
+            |batch: &Vec<WorkUnit>| -> Vec<u32> { batch.iter().map(|u| u.interval.tid).collect() };
+
+        // each worker takes half of its stretch of the genome, then half of the rest
+        assert_eq!(tids(&queues.take_batch(0)), vec![0, 1]);
+        assert_eq!(tids(&queues.take_batch(0)), vec![2]);
+        assert_eq!(tids(&queues.take_batch(0)), vec![3]);
+        // with its own units done, worker 0 steals half of the units of worker 1 from the back
+        assert_eq!(tids(&queues.take_batch(0)), vec![6]);
+        assert_eq!(tids(&queues.take_batch(0)), vec![7]);
+        assert_eq!(tids(&queues.take_batch(1)), vec![4]);
+        assert_eq!(tids(&queues.take_batch(1)), vec![5]);
+        assert!(queues.take_batch(0).is_empty());
+        assert!(queues.take_batch(1).is_empty());
+    }
+}
@@ -0,0 +1,512 @@
+//! A machine-readable report of the statistics of a run (```--stats-out```), for the QC
+//! dashboards of pipelines: the reads used and filtered by reason and the distribution of their
+//! realignment score differences, the candidate variants considered, called and filtered by
+//! reason, the phase blocks, the running time and work of each stage and the effective parameters.
+//!
+//! The report is written as JSON. Its objects are small and only hold numbers and strings, so they
+//! are formatted here instead of adding a JSON dependency.
+
+use errors::*;
+use extract_fragments::{ReadFilterCounts, CONCORDANCE_REPORT_BOUNDS};
+use genotype_probs::Genotype;
+use hashbrown::HashMap;
+use phasing_evaluation::n50;
+use print_output::CallSummary;
+use realignment::AlignmentParameters;
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::Duration;
+use telemetry::WorkCounters;
+use variants_and_fragments::{VarFilter, VarList};
+
+/// Formats a string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats a parameter value as a JSON number or boolean if it is one, or as a string otherwise
+fn json_value(s: &str) -> String {
+    match s.parse::<f64>() {
+        Ok(x) if x.is_finite() => s.to_string(),
+        _ if s == "true" || s == "false" => s.to_string(),
+        _ => json_string(s),
+    }
+}
+
+/// Formats a JSON object of already formatted values, indented by ```indent``` levels
+fn json_object(entries: &[(String, String)], indent: usize) -> String {
+    if entries.is_empty() {
+        return "{}".to_string();
+    }
+    let pad = "  ".repeat(indent + 1);
+    let fields: Vec<String> = entries
+        .iter()
+        .map(|&(ref key, ref value)| format!("{}{}: {}", pad, json_string(key), value))
+        .collect();
+    format!("{{\n{}\n{}}}", fields.join(",\n"), "  ".repeat(indent))
+}
+
+/// Formats a JSON array of already formatted values, one per line, indented by ```indent``` levels
+fn json_array(values: &[String], indent: usize) -> String {
+    if values.is_empty() {
+        return "[]".to_string();
+    }
+    let pad = "  ".repeat(indent + 1);
+    let items: Vec<String> = values.iter().map(|v| format!("{}{}", pad, v)).collect();
+    format!("[\n{}\n{}]", items.join(",\n"), "  ".repeat(indent))
+}
+
+/// Formats the work of each stage (see ```telemetry```) and their total as a JSON object
+fn work_json(stage_work: &Vec<(String, WorkCounters)>, indent: usize) -> String {
+    let mut total = WorkCounters::default();
+    let mut by_stage: Vec<(String, String)> = vec![];
+    for &(ref stage, ref work) in stage_work {
+        total.add(work);
+        by_stage.push((
+            stage.clone(),
+            json_object(&count_entries(&work.entries()), indent + 2),
+        ));
+    }
+    json_object(
+        &vec![
+            (
+                "total".to_string(),
+                json_object(&count_entries(&total.entries()), indent + 1),
+            ),
+            ("by_stage".to_string(), json_object(&by_stage, indent + 1)),
+        ],
+        indent,
+    )
+}
+
+fn count_entries(counts: &[(&str, usize)]) -> Vec<(String, String)> {
+    counts
+        .iter()
+        .map(|&(key, n)| (key.to_string(), n.to_string()))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct RunStats {
+    /// the reads of all samples and regions
+    pub reads: ReadFilterCounts,
+    /// the candidate variants and calls of all samples and regions
+    pub calls: CallSummary,
+    /// the number of filtered variants with each filter (a variant can have several filters)
+    filter_reasons: Vec<(String, usize)>,
+    /// the phase blocks of all samples and regions
+    phase_blocks: Vec<PhaseBlock>,
+    /// the number of heterozygous SNV calls
+    het_snvs: usize,
+    /// the number of heterozygous SNV calls that are phased
+    phased_het_snvs: usize,
+}
+
+/// The first and last heterozygous variant of a phase block
+pub struct PhaseBlock {
+    pub chrom: String,
+    /// the 0-based position of the first variant
+    pub start: usize,
+    /// the 0-based position of the last variant
+    pub end: usize,
+    /// the number of phased heterozygous variants
+    pub variants: usize,
+}
+
+impl PhaseBlock {
+    /// The span of the block from its first to its last variant, in bases
+    pub fn span(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        RunStats::default()
+    }
+
+    /// Adds the variants of a sample in a region to the statistics
+    pub fn add_varlist(&mut self, varlist: &VarList) {
+        self.calls.add_varlist(varlist);
+
+        // (tid, phase set) -> index of the phase block. The variants are sorted by position, so
+        // the blocks are added in the order of their first variant.
+        let mut block_ix: HashMap<(u32, usize), usize> = HashMap::new();
+        for var in &varlist.lst {
+            let Genotype(a1, a2) = var.genotype;
+            if a1 == 0 && a2 == 0 {
+                continue;
+            }
+            if var.filter != VarFilter::Pass {
+                for reason in var.filter.to_string().split(';') {
+                    match self.filter_reasons.iter().position(|&(ref r, _)| r == reason) {
+                        Some(i) => self.filter_reasons[i].1 += 1,
+                        None => self.filter_reasons.push((reason.to_string(), 1)),
+                    }
+                }
+                continue;
+            }
+            if a1 == a2 {
+                continue;
+            }
+            let snv = var.alleles[a1 as usize].len() == 1
+                && var.alleles[a2 as usize].len() == 1
+                && var.alleles[0].len() == 1;
+            if snv {
+                self.het_snvs += 1;
+            }
+            if let Some(ps) = var.phase_set {
+                if snv {
+                    self.phased_het_snvs += 1;
+                }
+                let phase_blocks = &mut self.phase_blocks;
+                let i = *block_ix.entry((var.tid, ps)).or_insert_with(|| {
+                    phase_blocks.push(PhaseBlock {
+                        chrom: varlist.target_names[var.tid as usize].clone(),
+                        start: var.pos0,
+                        end: var.pos0,
+                        variants: 0,
+                    });
+                    phase_blocks.len() - 1
+                });
+                phase_blocks[i].end = var.pos0;
+                phase_blocks[i].variants += 1;
+            }
+        }
+    }
+
+    /// The span of each phase block, in bases
+    fn phase_block_spans(&self) -> Vec<usize> {
+        self.phase_blocks.iter().map(|b| b.span()).collect()
+    }
+
+    /// The fraction of the heterozygous SNV calls that are phased
+    fn phased_het_snv_fraction(&self) -> f64 {
+        if self.het_snvs == 0 {
+            0.0
+        } else {
+            self.phased_het_snvs as f64 / self.het_snvs as f64
+        }
+    }
+
+    /// The distribution of the realignment score differences of the reads (see
+    /// ```RealignmentConcordance```) as a JSON object: its quantiles and the number of reads in
+    /// the bins of ```CONCORDANCE_REPORT_BOUNDS```
+    fn realignment_concordance_json(&self, indent: usize) -> String {
+        let rc = &self.reads.realignment_concordance;
+        let mut entries: Vec<(String, String)> = vec![("reads".to_string(), rc.reads.to_string())];
+        for &(key, q) in &[("median", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            entries.push((key.to_string(), format!("{:.1}", rc.quantile(q))));
+        }
+        entries.push(("max".to_string(), format!("{:.2}", rc.max)));
+        let hist = rc.histogram(&CONCORDANCE_REPORT_BOUNDS);
+        let mut bins: Vec<(String, String)> = vec![];
+        let mut lower = 0.0;
+        for (i, &n) in hist.iter().enumerate() {
+            let key = match CONCORDANCE_REPORT_BOUNDS.get(i) {
+                Some(upper) => format!("{}-{}", lower, upper),
+                None => format!(">={}", lower),
+            };
+            bins.push((key, n.to_string()));
+            lower = CONCORDANCE_REPORT_BOUNDS.get(i).cloned().unwrap_or(lower);
+        }
+        entries.push(("histogram".to_string(), json_object(&bins, indent + 1)));
+        json_object(&entries, indent)
+    }
+
+    /// The phasing metrics as VCF header lines (without the leading ```##```), for the output VCF
+    pub fn phasing_header_lines(&self) -> Vec<String> {
+        let spans = self.phase_block_spans();
+        vec![
+            format!("longshot_phase_blocks={}", spans.len()),
+            format!("longshot_phase_block_n50={}", n50(&spans)),
+            format!(
+                "longshot_largest_phase_block={}",
+                spans.iter().cloned().max().unwrap_or(0)
+            ),
+            format!("longshot_het_snvs={}", self.het_snvs),
+            format!("longshot_phased_het_snvs={}", self.phased_het_snvs),
+            format!(
+                "longshot_phased_het_snv_fraction={:.4}",
+                self.phased_het_snv_fraction()
+            ),
+        ]
+    }
+
+    /// Formats the report as JSON
+    ///
+    /// # Arguments
+    /// - ```parameters```: the effective value of each parameter, by option name
+    /// - ```alignment_parameters```: the alignment parameters used for each sample
+    /// - ```stage_times```: the running time of each stage
+    /// - ```stage_work```: the alignments and cache lookups of each stage
+    pub fn to_json(
+        &self,
+        parameters: &Vec<(String, String)>,
+        alignment_parameters: &Vec<(String, AlignmentParameters)>,
+        stage_times: &Vec<(String, Duration)>,
+        stage_work: &Vec<(String, WorkCounters)>,
+    ) -> String {
+        let r = &self.reads;
+        let mut reads: Vec<(String, String)> = count_entries(&[
+            ("total", r.total),
+            ("used", r.total - r.filtered()),
+            ("filtered", r.filtered()),
+        ]);
+        reads.push((
+            "filtered_by_reason".to_string(),
+            json_object(&count_entries(&r.reasons()), 2),
+        ));
+        reads.push((
+            "realignment_score_difference".to_string(),
+            self.realignment_concordance_json(2),
+        ));
+
+        let c = &self.calls;
+        let called = c.het + c.hom_alt;
+        let mut filter_reasons = self.filter_reasons.clone();
+        filter_reasons.sort();
+        let mut candidates: Vec<(String, String)> = count_entries(&[
+            ("considered", c.candidates),
+            ("called", called),
+            ("filtered", c.filtered),
+            ("reference", c.candidates - called - c.filtered),
+        ]);
+        let filter_entries: Vec<(String, String)> = filter_reasons
+            .iter()
+            .map(|&(ref reason, n)| (reason.clone(), n.to_string()))
+            .collect();
+        candidates.push((
+            "filtered_by_reason".to_string(),
+            json_object(&filter_entries, 2),
+        ));
+
+        let calls = count_entries(&[
+            ("snvs", c.snvs),
+            ("indels", c.indels),
+            ("het", c.het),
+            ("hom_alt", c.hom_alt),
+            ("transitions", c.transitions),
+            ("transversions", c.transversions),
+        ]);
+
+        let spans = self.phase_block_spans();
+        let mut phasing = count_entries(&[
+            ("phased_variants", c.phased),
+            ("phase_blocks", spans.len()),
+            ("phase_block_n50", n50(&spans)),
+            (
+                "largest_phase_block",
+                spans.iter().cloned().max().unwrap_or(0),
+            ),
+            ("het_snvs", self.het_snvs),
+            ("phased_het_snvs", self.phased_het_snvs),
+        ]);
+        phasing.push((
+            "phased_het_snv_fraction".to_string(),
+            format!("{:.4}", self.phased_het_snv_fraction()),
+        ));
+        let blocks: Vec<String> = self
+            .phase_blocks
+            .iter()
+            .map(|b| {
+                format!(
+                    "{{\"chrom\": {}, \"start\": {}, \"end\": {}, \"span\": {}, \"variants\": {}}}",
+                    json_string(&b.chrom),
+                    b.start + 1,
+                    b.end + 1,
+                    b.span(),
+                    b.variants
+                )
+            })
+            .collect();
+        phasing.push(("blocks".to_string(), json_array(&blocks, 2)));
+
+        let stages: Vec<(String, String)> = stage_times
+            .iter()
+            .map(|&(ref stage, d)| {
+                let secs = d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9;
+                (stage.clone(), format!("{:.3}", secs))
+            })
+            .collect();
+
+        let params: Vec<(String, String)> = parameters
+            .iter()
+            .map(|&(ref key, ref value)| (key.clone(), json_value(value)))
+            .collect();
+        let align_params: Vec<(String, String)> = alignment_parameters
+            .iter()
+            .map(|&(ref sample, ref p)| {
+                let t = &p.transition_probs;
+                let values: Vec<(String, String)> = [
+                    ("match_from_match", t.match_from_match),
+                    ("insertion_from_match", t.insertion_from_match),
+                    ("deletion_from_match", t.deletion_from_match),
+                    ("insertion_from_insertion", t.insertion_from_insertion),
+                    ("deletion_from_deletion", t.deletion_from_deletion),
+                    ("equal", p.emission_probs.equal),
+                    ("not_equal", p.emission_probs.not_equal),
+                ]
+                .iter()
+                .map(|&(key, x)| (key.to_string(), format!("{:e}", x)))
+                .collect();
+                (sample.clone(), json_object(&values, 2))
+            })
+            .collect();
+
+        let report: Vec<(String, String)> = vec![
+            (
+                "version".to_string(),
+                json_string(env!("CARGO_PKG_VERSION")),
+            ),
+            (
+                "regions".to_string(),
+                json_object(
+                    &count_entries(&[("called", c.regions), ("bases", c.bases)]),
+                    1,
+                ),
+            ),
+            ("reads".to_string(), json_object(&reads, 1)),
+            ("candidates".to_string(), json_object(&candidates, 1)),
+            ("calls".to_string(), json_object(&calls, 1)),
+            ("phasing".to_string(), json_object(&phasing, 1)),
+            ("stage_seconds".to_string(), json_object(&stages, 1)),
+            ("work".to_string(), work_json(stage_work, 1)),
+            ("parameters".to_string(), json_object(&params, 1)),
+            (
+                "alignment_parameters".to_string(),
+                json_object(&align_params, 1),
+            ),
+        ];
+        json_object(&report, 0) + "\n"
+    }
+
+    /// Writes the report to ```output_file``` (see ```to_json```)
+    pub fn write(
+        &self,
+        output_file: &String,
+        parameters: &Vec<(String, String)>,
+        alignment_parameters: &Vec<(String, AlignmentParameters)>,
+        stage_times: &Vec<(String, Duration)>,
+        stage_work: &Vec<(String, WorkCounters)>,
+    ) -> Result<()> {
+        let mut file = File::create(output_file)
+            .chain_err(|| ErrorKind::CreateFileError(output_file.clone()))?;
+        file.write_all(
+            self.to_json(parameters, alignment_parameters, stage_times, stage_work)
+                .as_bytes(),
+        )
+        .chain_err(|| ErrorKind::FileWriteError(output_file.clone()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_formatting() {
+        assert_eq!(json_string("a \"b\"\\c\n"), "\"a \\\"b\\\"\\\\c\\n\"");
+        assert_eq!(json_value("0.05"), "0.05");
+        assert_eq!(json_value("true"), "true");
+        assert_eq!(json_value("ont-r10"), "\"ont-r10\"");
+        assert_eq!(json_value("inf"), "\"inf\"");
+
+        let entries = vec![
+            ("a".to_string(), "1".to_string()),
+            (
+                "b".to_string(),
+                json_object(&vec![("c".to_string(), "2".to_string())], 1),
+            ),
+        ];
+        assert_eq!(
+            json_object(&entries, 0),
+            "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": 2\n  }\n}"
+        );
+        assert_eq!(json_object(&[], 0), "{}");
+        assert_eq!(
+            json_array(&vec!["1".to_string(), "{}".to_string()], 1),
+            "[\n    1,\n    {}\n  ]"
+        );
+        assert_eq!(json_array(&[], 0), "[]");
+    }
+
+    #[test]
+    fn test_realignment_concordance_json() {
+        let mut stats = RunStats::new();
+        for &diff in &[0.5, 1.5, 1.5, 30.0, 200.0] {
+            stats.reads.realignment_concordance.add(diff);
+        }
+        let json = stats.realignment_concordance_json(0);
+        assert!(json.starts_with("{\n  \"reads\": 5,\n  \"median\": 1.6,"));
+        assert!(json.contains("\"max\": 200.00,"));
+        assert!(json.contains("\"0-1\": 1,\n    \"1-2\": 2,"));
+        assert!(json.contains("\"20-50\": 1,"));
+        assert!(json.ends_with("\">=100\": 1\n  }\n}"));
+    }
+
+    #[test]
+    fn test_work_json() {
+        let work = |alignments: usize, hits: usize| WorkCounters {
+            alignments: alignments,
+            dp_cells: 10 * alignments,
+            cache_lookups: 4,
+            cache_hits: hits,
+            prefiltered: 0,
+        };
+        let stage_work = vec![
+            ("genotyping".to_string(), work(3, 1)),
+            ("output".to_string(), WorkCounters::default()),
+            ("read realignment".to_string(), work(5, 2)),
+        ];
+        let json = work_json(&stage_work, 0);
+        assert!(json.starts_with(
+            "{\n  \"total\": {\n    \"alignments\": 8,\n    \"dp_cells\": 80,\n    \"cache_lookups\": 8,\n    \"cache_hits\": 3,"
+        ));
+        assert!(json.contains("\"by_stage\": {\n    \"genotyping\": {\n      \"alignments\": 3,"));
+        assert!(json.contains("\"output\": {\n      \"alignments\": 0,"));
+        assert!(json.ends_with("\"prefiltered_windows\": 0\n    }\n  }\n}"));
+    }
+
+    #[test]
+    fn test_phasing_header_lines() {
+        let block = |chrom: &str, start: usize, end: usize| PhaseBlock {
+            chrom: chrom.to_string(),
+            start: start,
+            end: end,
+            variants: 2,
+        };
+        let mut stats = RunStats::new();
+        stats.phase_blocks = vec![
+            block("chr1", 99, 1098),
+            block("chr1", 5000, 5499),
+            block("chr2", 0, 2999),
+        ];
+        stats.het_snvs = 8;
+        stats.phased_het_snvs = 6;
+        assert_eq!(
+            stats.phasing_header_lines(),
+            vec![
+                "longshot_phase_blocks=3",
+                "longshot_phase_block_n50=3000",
+                "longshot_largest_phase_block=3000",
+                "longshot_het_snvs=8",
+                "longshot_phased_het_snvs=6",
+                "longshot_phased_het_snv_fraction=0.7500",
+            ]
+        );
+    }
+}
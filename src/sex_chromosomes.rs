@@ -0,0 +1,302 @@
+//! Ploidy of the sex chromosomes (```--sex```).
+//!
+//! In males, chrX and chrY are called with haploid genotypes outside the pseudoautosomal regions
+//! (PARs), where the reads of the single copy would otherwise produce false heterozygous calls. In
+//! females, chrY is skipped. In both sexes the PARs of chrX are called like the autosomes and the
+//! PARs of chrY are skipped, because the reads of the PARs align to either copy (or only to chrX,
+//! with a reference that masks the PARs of chrY). The PARs are those of GRCh38 or GRCh37, which
+//! are recognized by the length of chrX, or are read from a BED file.
+
+use errors::*;
+use estimate_read_coverage::interval_mean_coverage;
+use util::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+impl Sex {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Sex::Male => "male",
+            Sex::Female => "female",
+        }
+    }
+}
+
+/// The PARs of the human reference builds (0-based, end-exclusive): the length of chrX, the PARs
+/// of chrX and the PARs of chrY
+static BUILTIN_PARS: [(u32, [(u32, u32); 2], [(u32, u32); 2]); 2] = [
+    // GRCh38
+    (
+        156040895,
+        [(10000, 2781479), (155701382, 156030895)],
+        [(10000, 2781479), (56887902, 57217415)],
+    ),
+    // GRCh37
+    (
+        155270560,
+        [(60000, 2699520), (154931043, 155260560)],
+        [(10000, 2649520), (59034049, 59363566)],
+    ),
+];
+
+/// the number of windows that the coverage of chrX and of the autosomes are sampled in to infer
+/// the sex of a sample
+static SEX_COVERAGE_WINDOWS: u32 = 20;
+static SEX_COVERAGE_WINDOW_LEN: u32 = 10000;
+/// a sample is inferred to be male if the coverage of chrX outside the PARs is less than this
+/// fraction of the coverage of the autosomes
+static MALE_MAX_X_COVERAGE_RATIO: f64 = 0.75;
+
+fn find_contig(contigs: &[GenomicInterval], names: &[&str]) -> Option<GenomicInterval> {
+    contigs
+        .iter()
+        .find(|c| names.contains(&c.chrom.as_str()))
+        .cloned()
+}
+
+fn overlaps(a: &GenomicInterval, b: &GenomicInterval) -> bool {
+    a.tid == b.tid && a.start_pos <= b.end_pos && b.start_pos <= a.end_pos
+}
+
+/// The sex chromosomes of the reference and their PARs
+pub struct SexChromosomes {
+    /// the whole chrX contig, if the reference has one
+    x: Option<GenomicInterval>,
+    /// the whole chrY contig, if the reference has one
+    y: Option<GenomicInterval>,
+    /// the PARs of chrX and chrY
+    pars: Vec<GenomicInterval>,
+    /// the autosomes (the contigs named 1 to 22, with or without a chr prefix)
+    autosomes: Vec<GenomicInterval>,
+}
+
+impl SexChromosomes {
+    /// Finds the sex chromosomes (```chrX``` and ```chrY```, or ```X``` and ```Y```) in the
+    /// header of the BAM file, with the PARs of ```par_bed``` or the built-in PARs of the
+    /// reference build.
+    ///
+    /// # Errors
+    /// Fails if the reference has neither chrX nor chrY, or if no PAR BED file is given and the
+    /// length of chrX is not that of a known reference build.
+    pub fn new(bam_file: &String, par_bed: &Option<String>) -> Result<SexChromosomes> {
+        let contigs = get_whole_genome_intervals(bam_file)?;
+        let x = find_contig(&contigs, &["chrX", "X"]);
+        let y = find_contig(&contigs, &["chrY", "Y"]);
+        ensure!(
+            x.is_some() || y.is_some(),
+            "--sex requires a reference with a chrX or chrY contig."
+        );
+
+        let pars: Vec<GenomicInterval> = match *par_bed {
+            Some(ref bed_file) => parse_target_bed(bed_file, bam_file, 0)
+                .chain_err(|| format!("Error reading the PAR BED file {}.", bed_file))?,
+            None => {
+                let x_len = x.as_ref().map_or(0, |c| c.end_pos + 1);
+                let &(_, ref x_pars, ref y_pars) =
+                    match BUILTIN_PARS.iter().find(|p| p.0 == x_len) {
+                        Some(p) => p,
+                        None => bail!(
+                            "The PARs of a reference with a chrX of length {} are not known. Give them with --par_bed.",
+                            x_len
+                        ),
+                    };
+                let mut pars = vec![];
+                for &(ref contig, contig_pars) in &[(&x, x_pars), (&y, y_pars)] {
+                    if let Some(ref c) = **contig {
+                        for &(start, end) in contig_pars.iter() {
+                            pars.push(GenomicInterval {
+                                tid: c.tid,
+                                chrom: c.chrom.clone(),
+                                start_pos: start,
+                                end_pos: (end - 1).min(c.end_pos),
+                            });
+                        }
+                    }
+                }
+                pars
+            }
+        };
+
+        let autosomes: Vec<GenomicInterval> = contigs
+            .into_iter()
+            .filter(|c| {
+                let name = c.chrom.trim_start_matches("chr");
+                name.parse::<u32>().map_or(false, |n| n >= 1 && n <= 22)
+            })
+            .collect();
+
+        Ok(SexChromosomes {
+            x: x,
+            y: y,
+            pars: pars,
+            autosomes: autosomes,
+        })
+    }
+
+    /// The ploidy regions (see ```VarList::assign_ploidy```) of the sex chromosomes. Regions that
+    /// come first take precedence, so the PARs are listed before the whole contigs.
+    pub fn ploidy_regions(&self, sex: Sex, default_ploidy: u8) -> Vec<(GenomicInterval, u8)> {
+        let mut regions: Vec<(GenomicInterval, u8)> = vec![];
+        for par in &self.pars {
+            let on_y = self.y.as_ref().map_or(false, |y| y.tid == par.tid);
+            regions.push((par.clone(), if on_y { 0 } else { default_ploidy }));
+        }
+        if let Some(ref x) = self.x {
+            if sex == Sex::Male {
+                regions.push((x.clone(), 1));
+            }
+        }
+        if let Some(ref y) = self.y {
+            regions.push((y.clone(), if sex == Sex::Male { 1 } else { 0 }));
+        }
+        regions
+    }
+
+    /// Infers the sex of a sample from the coverage of chrX outside the PARs relative to the
+    /// coverage of the autosomes, which are both sampled in ```SEX_COVERAGE_WINDOWS``` windows.
+    ///
+    /// # Errors
+    /// Fails if the reference has no chrX or no autosomes, or if the autosomes have no coverage.
+    pub fn infer_sex(&self, bam_file: &String, depth_definition: DepthDefinition) -> Result<Sex> {
+        let x = match self.x {
+            Some(ref x) => x,
+            None => bail!("Can't infer the sex without a chrX contig. Give it with --sex male or --sex female."),
+        };
+        ensure!(
+            !self.autosomes.is_empty(),
+            "Can't infer the sex without autosomes (contigs named 1 to 22). Give it with --sex male or --sex female."
+        );
+
+        // evenly spaced windows on a contig
+        let window = |contig: &GenomicInterval, k: u32, n: u32| {
+            let len = contig.end_pos + 1;
+            let start = (len as u64 * (2 * k + 1) as u64 / (2 * n) as u64) as u32;
+            GenomicInterval {
+                tid: contig.tid,
+                chrom: contig.chrom.clone(),
+                start_pos: start,
+                end_pos: (start + SEX_COVERAGE_WINDOW_LEN - 1).min(contig.end_pos),
+            }
+        };
+        let x_windows: Vec<GenomicInterval> = (0..SEX_COVERAGE_WINDOWS)
+            .map(|k| window(x, k, SEX_COVERAGE_WINDOWS))
+            .filter(|w| !self.pars.iter().any(|par| overlaps(par, w)))
+            .collect();
+        let n_autosomes = self.autosomes.len() as u32;
+        let autosome_windows: Vec<GenomicInterval> = (0..SEX_COVERAGE_WINDOWS)
+            .map(|k| {
+                let contig = &self.autosomes[(k % n_autosomes) as usize];
+                let per_contig = (SEX_COVERAGE_WINDOWS + n_autosomes - 1) / n_autosomes;
+                window(contig, k / n_autosomes, per_contig)
+            })
+            .collect();
+
+        let mut bam_ix = open_indexed_bam(bam_file)?;
+        let mut mean_coverage = |windows: &Vec<GenomicInterval>| -> Result<f64> {
+            let mut sum = 0.0;
+            for w in windows {
+                sum += interval_mean_coverage(&mut bam_ix, w, depth_definition)?;
+            }
+            Ok(sum / windows.len().max(1) as f64)
+        };
+        let x_coverage = mean_coverage(&x_windows)?;
+        let autosome_coverage = mean_coverage(&autosome_windows)?;
+        ensure!(
+            autosome_coverage > 0.0,
+            "Can't infer the sex: the reads don't cover the autosomes. Give it with --sex male or --sex female."
+        );
+
+        let ratio = x_coverage / autosome_coverage;
+        let sex = if ratio < MALE_MAX_X_COVERAGE_RATIO {
+            Sex::Male
+        } else {
+            Sex::Female
+        };
+        info!(
+            "{} Coverage of chrX relative to the autosomes: {:.2}, inferred sex: {}.",
+            print_time(),
+            ratio,
+            sex.name()
+        );
+        Ok(sex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use variants_and_fragments::{Var, VarList};
+
+    fn contig(tid: u32, chrom: &str, len: u32) -> GenomicInterval {
+        GenomicInterval {
+            tid: tid,
+            chrom: chrom.to_string(),
+            start_pos: 0,
+            end_pos: len - 1,
+        }
+    }
+
+    #[test]
+    fn test_ploidy_regions() {
+        let x = contig(1, "chrX", 1000);
+        let y = contig(2, "chrY", 500);
+        let sex_chromosomes = SexChromosomes {
+            x: Some(x.clone()),
+            y: Some(y.clone()),
+            pars: vec![
+                GenomicInterval {
+                    start_pos: 0,
+                    end_pos: 99,
+                    ..x.clone()
+                },
+                GenomicInterval {
+                    start_pos: 0,
+                    end_pos: 99,
+                    ..y.clone()
+                },
+            ],
+            autosomes: vec![contig(0, "chr1", 2000)],
+        };
+
+        let male = sex_chromosomes.ploidy_regions(Sex::Male, 2);
+        let ploidies: Vec<(u32, u32, u8)> = male
+            .iter()
+            .map(|&(ref iv, p)| (iv.tid, iv.start_pos, p))
+            .collect();
+        assert_eq!(ploidies, vec![(1, 0, 2), (2, 0, 0), (1, 0, 1), (2, 0, 1)]);
+
+        // the first region that contains a variant sets its ploidy, and variants with ploidy 0
+        // are removed
+        let varlist_ploidies = |regions: &Vec<(GenomicInterval, u8)>| {
+            let vars = vec![
+                Var::new(0, 300, vec!["A".to_string(), "G".to_string()]),
+                Var::new(1, 50, vec!["C".to_string(), "T".to_string()]),
+                Var::new(1, 500, vec!["G".to_string(), "A".to_string()]),
+                Var::new(2, 50, vec!["T".to_string(), "C".to_string()]),
+                Var::new(2, 300, vec!["A".to_string(), "C".to_string()]),
+            ];
+            let target_names = vec!["chr1".to_string(), "chrX".to_string(), "chrY".to_string()];
+            let mut varlist = VarList::new(vars, target_names).unwrap();
+            varlist.assign_ploidy(2, regions).unwrap();
+            varlist
+                .lst
+                .iter()
+                .map(|var| (var.tid, var.pos0, var.ploidy))
+                .collect::<Vec<(u32, usize, u8)>>()
+        };
+        assert_eq!(
+            varlist_ploidies(&male),
+            vec![(0, 300, 2), (1, 50, 2), (1, 500, 1), (2, 300, 1)]
+        );
+
+        let female = sex_chromosomes.ploidy_regions(Sex::Female, 2);
+        assert_eq!(
+            varlist_ploidies(&female),
+            vec![(0, 300, 2), (1, 50, 2), (1, 500, 2)]
+        );
+    }
+}
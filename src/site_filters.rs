@@ -0,0 +1,286 @@
+//! Hard filters on the called variant sites, which set named flags in the VCF FILTER column.
+//!
+//! Each filter implements ```SiteFilter```. The built-in filters are configured on the command
+//! line and are all off by default; a new filter needs a flag in ```VarFilter``` (with its name in
+//! ```VAR_FILTER_NAMES``` and a header line in ```print_vcf_header```) and an implementation of
+//! the trait.
+
+use errors::*;
+use genotype_probs::Genotype;
+use util::*;
+use variants_and_fragments::{Var, VarFilter, VarList};
+
+/// A hard filter on variant sites
+pub trait SiteFilter {
+    /// the FILTER flag that is set on the variants that fail the filter
+    fn flag(&self) -> VarFilter;
+    /// a short description of the variants that fail the filter, for the log
+    fn description(&self) -> String;
+    /// whether the variant fails the filter. ```ref_seq``` holds the reference sequence around the
+    /// variant.
+    fn fails(&self, var: &Var, ref_seq: &RefSeqWindow) -> bool;
+}
+
+/// Fails variants with fewer reads than ```min_depth``` (```DP```)
+pub struct MinDepthFilter {
+    pub min_depth: usize,
+}
+
+impl SiteFilter for MinDepthFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::LowDepth
+    }
+    fn description(&self) -> String {
+        format!("with a depth below {}", self.min_depth)
+    }
+    fn fails(&self, var: &Var, _ref_seq: &RefSeqWindow) -> bool {
+        var.dp < self.min_depth
+    }
+}
+
+/// Fails variants with more reads than ```max_depth``` (```DP```). This sets the same flag as the
+/// maximum coverage cutoff (```--max_cov```).
+pub struct MaxDepthFilter {
+    pub max_depth: usize,
+}
+
+impl SiteFilter for MaxDepthFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::Depth
+    }
+    fn description(&self) -> String {
+        format!("with a depth above {}", self.max_depth)
+    }
+    fn fails(&self, var: &Var, _ref_seq: &RefSeqWindow) -> bool {
+        var.dp > self.max_depth
+    }
+}
+
+/// Fails heterozygous calls whose allele balance is outside of ```min..=max```: the fraction of
+/// the reads supporting either called allele that support the second one. For a ```0/1``` call,
+/// this is the fraction of alternate reads, as in the ```AB``` FORMAT field.
+pub struct AlleleBalanceFilter {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl SiteFilter for AlleleBalanceFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::AlleleBalance
+    }
+    fn description(&self) -> String {
+        format!("with a heterozygous allele balance outside of {}-{}", self.min, self.max)
+    }
+    fn fails(&self, var: &Var, _ref_seq: &RefSeqWindow) -> bool {
+        let Genotype(a, b) = var.genotype;
+        if a == b {
+            return false;
+        }
+        let (a, b) = (a.min(b) as usize, a.max(b) as usize);
+        let total = var.allele_counts[a] as usize + var.allele_counts[b] as usize;
+        if total == 0 {
+            return false;
+        }
+        let balance = var.allele_counts[b] as f64 / total as f64;
+        balance < self.min || balance > self.max
+    }
+}
+
+/// Fails variants with a strand bias above ```max_phred```: the PHRED-scaled p-value of Fisher's
+/// exact test for the strands of the reference and alternate reads (```FS```)
+pub struct StrandBiasFilter {
+    pub max_phred: f64,
+}
+
+impl SiteFilter for StrandBiasFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::StrandBias
+    }
+    fn description(&self) -> String {
+        format!("with a strand bias (FS) above {}", self.max_phred)
+    }
+    fn fails(&self, var: &Var, _ref_seq: &RefSeqWindow) -> bool {
+        var.strand_bias_pvalue > self.max_phred
+    }
+}
+
+/// Fails variants next to a homopolymer run of at least ```min_len``` bases on the reference,
+/// where long reads often have indel errors
+pub struct HomopolymerFilter {
+    pub min_len: usize,
+}
+
+/// The length of the longest homopolymer run on the reference that ends right before the variant
+/// or starts right after its reference allele
+pub fn adjacent_homopolymer_len(var: &Var, ref_seq: &RefSeqWindow) -> usize {
+    let seq_end = ref_seq.start + ref_seq.seq.len();
+    let mut left = 0;
+    if var.pos0 > ref_seq.start {
+        let base = ref_seq[var.pos0 - 1];
+        while var.pos0 - left > ref_seq.start && ref_seq[var.pos0 - left - 1] == base {
+            left += 1;
+        }
+    }
+    let end = var.pos0 + var.alleles[0].len();
+    let mut right = 0;
+    if end < seq_end {
+        let base = ref_seq[end];
+        while end + right < seq_end && ref_seq[end + right] == base {
+            right += 1;
+        }
+    }
+    left.max(right)
+}
+
+impl SiteFilter for HomopolymerFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::Homopolymer
+    }
+    fn description(&self) -> String {
+        format!("next to a homopolymer of at least {} bases", self.min_len)
+    }
+    fn fails(&self, var: &Var, ref_seq: &RefSeqWindow) -> bool {
+        adjacent_homopolymer_len(var, ref_seq) >= self.min_len
+    }
+}
+
+/// Fails variants in an annotated tandem repeat (```Var::tandem_repeat```) with a ```QUAL```
+/// below ```min_qual```, where the read alignments and the allele calls are less reliable
+pub struct TandemRepeatFilter {
+    pub min_qual: f64,
+}
+
+impl SiteFilter for TandemRepeatFilter {
+    fn flag(&self) -> VarFilter {
+        VarFilter::TandemRepeat
+    }
+    fn description(&self) -> String {
+        format!("in an annotated tandem repeat with a QUAL below {}", self.min_qual)
+    }
+    fn fails(&self, var: &Var, _ref_seq: &RefSeqWindow) -> bool {
+        var.tandem_repeat.is_some() && var.qual < self.min_qual
+    }
+}
+
+// the reference margin around the variants for the filters, which is longer than any
+// homopolymer that a filter would look for
+const REF_MARGIN: usize = 100;
+
+/// Applies the site filters to the variants with a non-reference genotype, adding their flags
+/// to ```var.filter```.
+///
+/// # Errors
+/// Fails if the reference sequence around the variants can't be read.
+pub fn apply_site_filters(
+    varlist: &mut VarList,
+    filters: &Vec<Box<dyn SiteFilter>>,
+    fasta_file: &String,
+) -> Result<()> {
+    if filters.is_empty() || varlist.lst.is_empty() {
+        return Ok(());
+    }
+    let mut fasta =
+        open_fasta(fasta_file)?;
+
+    let mut i = 0;
+    while i < varlist.lst.len() {
+        // the reference sequence around the variants on one contig
+        let tid = varlist.lst[i].tid;
+        let mut j = i;
+        let mut end = 0;
+        while j < varlist.lst.len() && varlist.lst[j].tid == tid {
+            end = end.max(varlist.lst[j].pos0 + varlist.lst[j].alleles[0].len());
+            j += 1;
+        }
+        let chrom = &varlist.target_names[tid as usize];
+        let contig_len = match fasta.index.sequences().iter().find(|s| &s.name == chrom) {
+            Some(sequence) => sequence.len as usize,
+            None => bail!("Contig {} of a variant is not in the reference FASTA.", chrom),
+        };
+        let start = varlist.lst[i].pos0.saturating_sub(REF_MARGIN);
+        let mut ref_seq_u8: Vec<u8> = vec![];
+        read_fasta_region(
+            &mut fasta,
+            chrom,
+            start as u64,
+            (end + REF_MARGIN).min(contig_len) as u64,
+            &mut ref_seq_u8,
+        )?;
+        let ref_seq = RefSeqWindow {
+            start: start,
+            seq: dna_vec(&ref_seq_u8),
+            contig_len: contig_len,
+        };
+
+        for var in &mut varlist.lst[i..j] {
+            if var.genotype == Genotype(0, 0) {
+                continue;
+            }
+            for filter in filters {
+                if filter.fails(var, &ref_seq) {
+                    var.filter.add_filter(filter.flag());
+                }
+            }
+        }
+        i = j;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(seq: &str, start: usize) -> RefSeqWindow {
+        RefSeqWindow {
+            start: start,
+            seq: seq.chars().collect(),
+            contig_len: 0,
+        }
+    }
+
+    #[test]
+    fn test_adjacent_homopolymer_len() {
+        let ref_seq = window("ACAAAAGTCCCTA", 10);
+        // SNV at the G, after the A run
+        let var = Var::new(0, 16, vec!["G".to_string(), "T".to_string()]);
+        assert_eq!(adjacent_homopolymer_len(&var, &ref_seq), 4);
+        // SNV at the T, before the C run
+        let var = Var::new(0, 17, vec!["T".to_string(), "A".to_string()]);
+        assert_eq!(adjacent_homopolymer_len(&var, &ref_seq), 3);
+        // a deletion next to both runs
+        let var = Var::new(0, 16, vec!["GT".to_string(), "G".to_string()]);
+        assert_eq!(adjacent_homopolymer_len(&var, &ref_seq), 4);
+        // at the end of the window
+        let var = Var::new(0, 22, vec!["A".to_string(), "G".to_string()]);
+        assert_eq!(adjacent_homopolymer_len(&var, &ref_seq), 1);
+        let var = Var::new(0, 10, vec!["A".to_string(), "G".to_string()]);
+        assert_eq!(adjacent_homopolymer_len(&var, &ref_seq), 1);
+    }
+
+    #[test]
+    fn test_site_filters() {
+        let ref_seq = window("ACGTACGTACGT", 0);
+        let mut var = Var::new(0, 5, vec!["C".to_string(), "T".to_string()]);
+        var.genotype = Genotype(0, 1);
+        var.dp = 20;
+        var.allele_counts = vec![16, 4];
+        var.strand_bias_pvalue = 30.0;
+
+        assert!(!MinDepthFilter { min_depth: 20 }.fails(&var, &ref_seq));
+        assert!(MinDepthFilter { min_depth: 21 }.fails(&var, &ref_seq));
+        assert!(MaxDepthFilter { max_depth: 19 }.fails(&var, &ref_seq));
+        assert!(StrandBiasFilter { max_phred: 20.0 }.fails(&var, &ref_seq));
+        assert!(!StrandBiasFilter { max_phred: 40.0 }.fails(&var, &ref_seq));
+        assert!(!HomopolymerFilter { min_len: 2 }.fails(&var, &ref_seq));
+
+        let ab = AlleleBalanceFilter { min: 0.25, max: 0.75 };
+        assert!(ab.fails(&var, &ref_seq));
+        var.allele_counts = vec![10, 10];
+        assert!(!ab.fails(&var, &ref_seq));
+        // homozygous calls are not checked
+        var.genotype = Genotype(1, 1);
+        var.allele_counts = vec![0, 20];
+        assert!(!ab.fails(&var, &ref_seq));
+    }
+}
@@ -0,0 +1,168 @@
+//! Clusters of soft-clipped read ends, which mark putative structural variant breakpoints or
+//! misassemblies of the reference.
+//!
+//! Where many reads are soft-clipped at (nearly) the same position, the aligner could not place
+//! the rest of the reads, e.g. an insertion that is longer than the aligner's gap penalties allow,
+//! or a sequence that is missing from the reference. The aligned bases next to such a breakpoint
+//! are often misaligned, which shows up as spurious SNV candidates. The clipped read ends are
+//! collected while the fragments are extracted (```extract_fragments```), and the candidate SNVs
+//! inside a cluster are suppressed and flagged (```sc```).
+
+use variants_and_fragments::{Var, VarFilter, VarList};
+
+/// A soft-clipped end of an aligned read: (target ID, 0-based position of the aligned base next to
+/// the clipped bases, whether the clipped bases are before that position, i.e. the start of the
+/// alignment is clipped)
+pub type ClippedEnd = (u32, usize, bool);
+
+/// A cluster of soft-clipped read ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftClipCluster {
+    pub tid: u32,
+    /// 0-based start, and end (exclusive) of the region: the clipped ends padded by the window
+    pub start: usize,
+    pub end: usize,
+    /// number of read ends clipped before (to the left of) the breakpoint, and after it. An
+    /// insertion has reads clipped on both sides.
+    pub n_clipped_before: usize,
+    pub n_clipped_after: usize,
+}
+
+impl SoftClipCluster {
+    pub fn n_clipped(&self) -> usize {
+        self.n_clipped_before + self.n_clipped_after
+    }
+}
+
+/// Clusters the soft-clipped read ends. Consecutive clipped ends at most ```window``` bases apart
+/// are in the same cluster, and clusters with at least ```min_reads``` clipped ends are returned,
+/// sorted by position. The region of each cluster extends ```window``` bases past its first and
+/// last clipped end.
+pub fn find_soft_clip_clusters(
+    clipped_ends: &mut Vec<ClippedEnd>,
+    window: usize,
+    min_reads: usize,
+) -> Vec<SoftClipCluster> {
+    clipped_ends.sort();
+    let mut clusters: Vec<SoftClipCluster> = vec![];
+    let mut i = 0;
+    while i < clipped_ends.len() {
+        let (tid, first_pos, _) = clipped_ends[i];
+        let mut j = i + 1;
+        while j < clipped_ends.len()
+            && clipped_ends[j].0 == tid
+            && clipped_ends[j].1 <= clipped_ends[j - 1].1 + window
+        {
+            j += 1;
+        }
+        let n_clipped_before = clipped_ends[i..j].iter().filter(|c| c.2).count();
+        if j - i >= min_reads {
+            clusters.push(SoftClipCluster {
+                tid: tid,
+                start: first_pos.saturating_sub(window),
+                end: clipped_ends[j - 1].1 + window + 1,
+                n_clipped_before: n_clipped_before,
+                n_clipped_after: j - i - n_clipped_before,
+            });
+        }
+        i = j;
+    }
+    clusters
+}
+
+fn is_snv(var: &Var) -> bool {
+    var.alleles.iter().all(|a| a.len() == 1)
+}
+
+/// Returns for each variant of ```varlist``` whether it is an SNV inside one of the clusters.
+/// The clusters must be sorted by position, as returned by ```find_soft_clip_clusters```.
+pub fn soft_clip_cluster_snvs(varlist: &VarList, clusters: &[SoftClipCluster]) -> Vec<bool> {
+    let mut in_cluster: Vec<bool> = vec![false; varlist.lst.len()];
+    let mut c = 0;
+    for (i, var) in varlist.lst.iter().enumerate() {
+        // skip the clusters that end before the variant
+        while c < clusters.len()
+            && (clusters[c].tid < var.tid
+                || (clusters[c].tid == var.tid && clusters[c].end <= var.pos0))
+        {
+            c += 1;
+        }
+        if c == clusters.len() {
+            break;
+        }
+        in_cluster[i] =
+            clusters[c].tid == var.tid && clusters[c].start <= var.pos0 && is_snv(var);
+    }
+    in_cluster
+}
+
+/// Sets the FILTER flag ```sc``` on the SNVs inside the soft-clip clusters and returns their
+/// number.
+pub fn flag_soft_clip_cluster_snvs(varlist: &mut VarList, clusters: &[SoftClipCluster]) -> usize {
+    let in_cluster = soft_clip_cluster_snvs(varlist, clusters);
+    for (var, &flag) in varlist.lst.iter_mut().zip(in_cluster.iter()) {
+        if flag {
+            var.filter.add_filter(VarFilter::SoftClipCluster);
+        }
+    }
+    in_cluster.iter().filter(|&&flag| flag).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_soft_clip_clusters() {
+        let mut clipped_ends: Vec<ClippedEnd> = vec![
+            (0, 1005, false),
+            (0, 1000, true),
+            (0, 500, true),
+            (0, 1012, true),
+            (1, 1008, false),
+            (0, 1003, false),
+        ];
+        let clusters = find_soft_clip_clusters(&mut clipped_ends, 10, 3);
+        assert_eq!(
+            clusters,
+            vec![SoftClipCluster {
+                tid: 0,
+                start: 990,
+                end: 1023,
+                n_clipped_before: 2,
+                n_clipped_after: 2,
+            }]
+        );
+        assert_eq!(clusters[0].n_clipped(), 4);
+        // the clipped ends on the other contig and far from the others are single clusters
+        assert_eq!(find_soft_clip_clusters(&mut clipped_ends, 10, 1).len(), 3);
+    }
+
+    #[test]
+    fn test_flag_soft_clip_cluster_snvs() {
+        let lst = vec![
+            Var::new(0, 989, vec!["A".to_string(), "G".to_string()]),
+            Var::new(0, 995, vec!["C".to_string(), "T".to_string()]),
+            Var::new(0, 1000, vec!["CAT".to_string(), "C".to_string()]),
+            Var::new(0, 1023, vec!["G".to_string(), "A".to_string()]),
+            Var::new(1, 1000, vec!["T".to_string(), "C".to_string()]),
+        ];
+        let mut varlist =
+            VarList::new(lst, vec!["chr1".to_string(), "chr2".to_string()]).unwrap();
+        let clusters = vec![SoftClipCluster {
+            tid: 0,
+            start: 990,
+            end: 1023,
+            n_clipped_before: 2,
+            n_clipped_after: 2,
+        }];
+        // only the SNV inside the cluster is flagged, not the deletion
+        assert_eq!(
+            soft_clip_cluster_snvs(&varlist, &clusters),
+            vec![false, true, false, false, false]
+        );
+        assert_eq!(flag_soft_clip_cluster_snvs(&mut varlist, &clusters), 1);
+        assert_eq!(varlist.lst[1].filter, VarFilter::SoftClipCluster);
+        assert_eq!(varlist.lst[0].filter, VarFilter::Pass);
+    }
+}
@@ -0,0 +1,194 @@
+//! Somatic variant calling for paired tumor/normal samples.
+//!
+//! Candidate variants are found in the tumor and genotyped in both samples as usual. At each
+//! biallelic site, the tumor reads are then compared under two hypotheses:
+//! - germline: the tumor allele fraction is determined by the normal genotype (0, 0.5 or 1)
+//! - somatic: the normal is homozygous reference and the variant is present in a fraction of the
+//!   tumor cells, so that the tumor allele fraction is ```purity * cellular_fraction / 2```.
+//!   The cellular fraction has a uniform prior over a grid of values in (0, 1], which allows for
+//!   subclonal variants.
+//!
+//! The somatic quality (SQ) is the PHRED-scaled probability that the variant is not somatic, and the
+//! somatic allele fraction (SVAF) is the most likely tumor allele fraction under the somatic
+//! hypothesis.
+
+use bio::stats::{LogProb, PHREDProb, Prob};
+use genotype_probs::Genotype;
+use util::*;
+use variants_and_fragments::*;
+
+/// Parameters for the somatic posterior model
+#[derive(Clone, Copy)]
+pub struct SomaticParameters {
+    /// fraction of the cells in the tumor sample that are tumor cells
+    pub purity: f64,
+    /// prior probability of a somatic mutation at a site
+    pub somatic_rate: f64,
+    /// number of cellular fraction values in the grid used to model subclonal variants
+    pub n_cellular_fractions: usize,
+}
+
+impl SomaticParameters {
+    /// returns the tumor allele fractions to evaluate. The first three are the germline fractions
+    /// 0.0, 0.5 and 1.0; the remaining ones are the somatic fractions on the cellular fraction grid.
+    pub fn allele_fractions(&self) -> Vec<f64> {
+        let mut fractions = vec![0.0, 0.5, 1.0];
+        for i in 1..self.n_cellular_fractions + 1 {
+            let cellular_fraction = i as f64 / self.n_cellular_fractions as f64;
+            fractions.push(self.purity * cellular_fraction / 2.0);
+        }
+        fractions
+    }
+}
+
+/// Calculates the likelihood of the tumor reads at each variant for a set of allele fractions
+///
+/// # Arguments
+/// - ```flist```: the fragments extracted from the tumor reads
+/// - ```n_var```: the number of variants
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a read allele to be used
+/// - ```fractions```: the alternate allele fractions to evaluate
+///
+/// # Returns
+/// Returns a vector where element ```[v][i]``` is the log-likelihood of the tumor reads at
+/// variant ```v``` if the fraction of reads carrying allele 1 is ```fractions[i]```.
+/// Reads with an allele other than 0 or 1 are ignored.
+pub fn tumor_allele_fraction_likelihoods(
+    flist: &Vec<Fragment>,
+    n_var: usize,
+    max_p_miscall: f64,
+    fractions: &Vec<f64>,
+) -> Vec<Vec<LogProb>> {
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+    let ln_fractions: Vec<(LogProb, LogProb)> = fractions
+        .iter()
+        .map(|&f| (LogProb::from(Prob(1.0 - f)), LogProb::from(Prob(f))))
+        .collect();
+    let mut likelihoods: Vec<Vec<LogProb>> = vec![vec![LogProb::ln_one(); fractions.len()]; n_var];
+
+    for frag in flist {
+        for call in &frag.calls {
+            if call.qual >= ln_max_p_miscall || call.allele > 1 {
+                continue;
+            }
+            // probability of the observed allele if the read came from an allele 0 or allele 1 molecule
+            let (p_from_0, p_from_1) = if call.allele == 0 {
+                (call.one_minus_qual, call.qual)
+            } else {
+                (call.qual, call.one_minus_qual)
+            };
+            for (i, &(ln_f0, ln_f1)) in ln_fractions.iter().enumerate() {
+                likelihoods[call.var_ix][i] = likelihoods[call.var_ix][i]
+                    + LogProb::ln_add_exp(ln_f0 + p_from_0, ln_f1 + p_from_1);
+            }
+        }
+    }
+
+    likelihoods
+}
+
+/// Calculates the somatic posterior for each variant and annotates the tumor variants
+///
+/// The normal genotype posteriors (```genotype_post```) act as the prior over germline
+/// genotypes. For each biallelic variant, ```somatic_qual``` and ```somatic_vaf``` of the tumor
+/// variant are set, and if the variant is more likely somatic than not, the tumor genotype is set
+/// to heterozygous (the diploid genotype model does not call low-fraction variants).
+///
+/// # Arguments
+/// - ```tumor_varlist```: the genotyped tumor variants
+/// - ```normal_varlist```: the genotyped normal variants, at the same sites as the tumor
+/// - ```tumor_likelihoods```: the output of ```tumor_allele_fraction_likelihoods``` evaluated on
+///   ```params.allele_fractions()```
+/// - ```params```: the somatic model parameters
+pub fn call_somatic_variants(
+    tumor_varlist: &mut VarList,
+    normal_varlist: &VarList,
+    tumor_likelihoods: &Vec<Vec<LogProb>>,
+    params: &SomaticParameters,
+) {
+    assert_eq!(tumor_varlist.lst.len(), normal_varlist.lst.len());
+    assert_eq!(tumor_varlist.lst.len(), tumor_likelihoods.len());
+
+    let fractions = params.allele_fractions();
+    let ln_somatic_rate = LogProb::from(Prob(params.somatic_rate));
+    let ln_germline_rate = LogProb::from(Prob(1.0 - params.somatic_rate));
+    let ln_n_cellular_fractions = LogProb::from(Prob(1.0 / params.n_cellular_fractions as f64));
+
+    for (v, tumor_var) in tumor_varlist.lst.iter_mut().enumerate() {
+        let normal_var = &normal_varlist.lst[v];
+        if tumor_var.alleles.len() != 2 {
+            continue;
+        }
+        let lik = &tumor_likelihoods[v];
+
+        // P(normal genotype) * P(tumor reads | germline allele fraction of the genotype)
+        let post_00 = normal_var.genotype_post.get(Genotype(0, 0));
+        let post_het = LogProb::ln_add_exp(
+            normal_var.genotype_post.get(Genotype(0, 1)),
+            normal_var.genotype_post.get(Genotype(1, 0)),
+        );
+        let post_11 = normal_var.genotype_post.get(Genotype(1, 1));
+        let germline = ln_germline_rate
+            + LogProb::ln_sum_exp(&[post_00 + lik[0], post_het + lik[1], post_11 + lik[2]]);
+
+        // the somatic hypothesis averages over the cellular fraction grid
+        let somatic_liks: Vec<LogProb> = lik[3..]
+            .iter()
+            .map(|&l| l + ln_n_cellular_fractions)
+            .collect();
+        let somatic = ln_somatic_rate + post_00 + LogProb::ln_sum_exp(&somatic_liks);
+
+        let p_somatic = somatic - LogProb::ln_add_exp(somatic, germline);
+        let p_not_somatic = LogProb::ln_one_minus_exp(&p_somatic);
+
+        // the most likely somatic allele fraction
+        let mut max_ix = 3;
+        for i in 3..fractions.len() {
+            if lik[i] > lik[max_ix] {
+                max_ix = i;
+            }
+        }
+
+        tumor_var.somatic_qual = Some((*PHREDProb::from(p_not_somatic)).min(MAX_VCF_QUAL));
+        tumor_var.somatic_vaf = Some(fractions[max_ix]);
+
+        if p_somatic > LogProb::from(Prob(0.5)) && tumor_var.genotype == Genotype(0, 0) {
+            tumor_var.genotype = Genotype(0, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allele_fractions() {
+        let params = SomaticParameters {
+            purity: 0.6,
+            somatic_rate: 1e-6,
+            n_cellular_fractions: 4,
+        };
+        let fractions = params.allele_fractions();
+        let expected = vec![0.0, 0.5, 1.0, 0.075, 0.15, 0.225, 0.3];
+        assert_eq!(fractions.len(), expected.len());
+        for (f, e) in fractions.iter().zip(expected.iter()) {
+            assert!((f - e).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_tumor_allele_fraction_likelihoods() {
+        // 15 alt reads out of 100 should best fit an allele fraction of 0.15
+        let flist = fragments_from_strings(&[vec!["0"; 85], vec!["1"; 15]].concat(), 0.01);
+        let fractions = vec![0.0, 0.5, 1.0, 0.075, 0.15, 0.225, 0.3];
+        let lik = tumor_allele_fraction_likelihoods(&flist, 1, 0.1, &fractions);
+        let mut max_ix = 0;
+        for i in 0..fractions.len() {
+            if lik[0][i] > lik[0][max_ix] {
+                max_ix = i;
+            }
+        }
+        assert_eq!(max_ix, 4);
+    }
+}
@@ -0,0 +1,195 @@
+//! Reading a coordinate-sorted BAM file from standard input (```--bam -```), for pipelines like
+//! ```minimap2 | samtools sort | longshot``` without an indexed BAM file in between.
+//!
+//! Longshot reads the reads of each region several times (to estimate the alignment parameters,
+//! find the candidate variants, realign the reads and phase them), and in parallel for several
+//! samples, so the reads are copied once from standard input to a temporary BAM file in
+//! ```$TMPDIR```, which is indexed and read like any other BAM file. The input is checked to be
+//! sorted while it is copied. The temporary file and its index are removed when longshot exits.
+
+extern crate rust_htslib;
+
+use errors::*;
+use rust_htslib::bam;
+use rust_htslib::bam::Read;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use util::*;
+
+/// The name of the BAM file that is read from standard input
+pub const STDIN_BAM: &str = "-";
+
+/// A temporary copy of the BAM file of standard input and its index, which are removed when it
+/// is dropped
+pub struct SpooledBam {
+    pub path: PathBuf,
+}
+
+impl SpooledBam {
+    /// The path of the copy, to be given in place of ```-```
+    pub fn name(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for SpooledBam {
+    fn drop(&mut self) {
+        // the files may not have been written if copying the input failed
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(format!("{}.bai", self.path.display()));
+    }
+}
+
+/// The sort order of the @HD line of a BAM header (```SO``` tag), if it has one
+pub fn header_sort_order(header_text: &str) -> Option<&str> {
+    header_text
+        .lines()
+        .find(|line| line.starts_with("@HD"))
+        .and_then(|line| line.split('\t').find(|f| f.starts_with("SO:")))
+        .map(|f| &f[3..])
+}
+
+/// Whether a read at ```(tid, pos)``` can follow a read at ```previous``` in a coordinate-sorted
+/// BAM file. Unmapped reads without a position (tid -1) are at the end of the file.
+pub fn in_coordinate_order(previous: (i32, i32), tid: i32, pos: i32) -> bool {
+    tid < 0 || (previous.0 >= 0 && (tid, pos) >= previous)
+}
+
+/// Copies the coordinate-sorted BAM file of standard input to a temporary BAM file and indexes it.
+///
+/// # Errors
+/// Fails if standard input can't be read as a BAM file, if it isn't sorted by coordinate (e.g. if
+/// it is sorted by read name, as it comes out of an aligner), or if the copy can't be written.
+pub fn spool_stdin_bam() -> Result<SpooledBam> {
+    let mut reader =
+        bam::Reader::from_stdin().chain_err(|| ErrorKind::BamOpenError(STDIN_BAM.to_string()))?;
+    let header_text = u8_to_string(reader.header().as_bytes())?;
+    if let Some(order) = header_sort_order(&header_text) {
+        ensure!(
+            order != "queryname" && order != "unsorted",
+            "The BAM file of standard input is sorted by {} rather than by coordinate. Pipe it through samtools sort first.",
+            order
+        );
+    }
+
+    let spooled = SpooledBam {
+        path: env::temp_dir().join(format!("longshot_stdin_{}.bam", std::process::id())),
+    };
+    info!(
+        "{} Copying the BAM file of standard input to {}...",
+        print_time(),
+        spooled.path.display()
+    );
+    let mut n_reads: usize = 0;
+    {
+        let header = bam::Header::from_template(reader.header());
+        let mut writer = bam::Writer::from_path(&spooled.path, &header, bam::Format::BAM)
+            .chain_err(|| ErrorKind::CreateFileError(spooled.name()))?;
+        let mut previous: (i32, i32) = (0, 0);
+        let mut record = bam::Record::new();
+        loop {
+            match reader.read(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => bail!(
+                    "Could not read the BAM file of standard input after {} reads: {}",
+                    n_reads,
+                    e
+                ),
+            }
+            ensure!(
+                in_coordinate_order(previous, record.tid(), record.pos()),
+                "The BAM file of standard input is not sorted by coordinate (read {} is out of order). Pipe it through samtools sort first.",
+                String::from_utf8_lossy(record.qname())
+            );
+            previous = (record.tid(), record.pos());
+            writer
+                .write(&record)
+                .chain_err(|| ErrorKind::FileWriteError(spooled.name()))?;
+            n_reads += 1;
+        }
+    }
+    bam::index::build(&spooled.path, None, bam::index::Type::BAI, 1).chain_err(|| {
+        format!(
+            "Error indexing the copy {} of standard input.",
+            spooled.name()
+        )
+    })?;
+    info!(
+        "{} Copied {} reads from standard input.",
+        print_time(),
+        n_reads
+    );
+    Ok(spooled)
+}
+
+/// Replaces the BAM file ```-``` of ```--bam``` (or ```-b```) with the copy of standard input in
+/// command line arguments, for the processes of the whole-genome mode, which don't get the input
+pub fn replace_stdin_bam(args: &[String], spooled: &str) -> Vec<String> {
+    let mut replaced: Vec<String> = vec![];
+    for (i, a) in args.iter().enumerate() {
+        let after_bam = i > 0 && (args[i - 1] == "--bam" || args[i - 1] == "-b");
+        if after_bam && a == STDIN_BAM {
+            replaced.push(spooled.to_string());
+        } else if a == "--bam=-" {
+            replaced.push(format!("--bam={}", spooled));
+        } else {
+            replaced.push(a.clone());
+        }
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_sort_order() {
+        let header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n";
+        assert_eq!(header_sort_order(header), Some("coordinate"));
+        assert_eq!(
+            header_sort_order("@HD\tVN:1.6\tSO:queryname\n"),
+            Some("queryname")
+        );
+        assert_eq!(
+            header_sort_order("@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n"),
+            None
+        );
+        assert_eq!(header_sort_order("@SQ\tSN:chr1\tLN:1000\n"), None);
+    }
+
+    #[test]
+    fn test_in_coordinate_order() {
+        assert!(in_coordinate_order((0, 100), 0, 100));
+        assert!(in_coordinate_order((0, 100), 0, 150));
+        assert!(in_coordinate_order((0, 100), 1, 5));
+        assert!(!in_coordinate_order((0, 100), 0, 50));
+        assert!(!in_coordinate_order((1, 5), 0, 500));
+        // the unmapped reads are at the end, and no mapped read can follow them
+        assert!(in_coordinate_order((1, 5), -1, -1));
+        assert!(!in_coordinate_order((-1, -1), 0, 10));
+    }
+
+    #[test]
+    fn test_replace_stdin_bam() {
+        let args: Vec<String> = ["--bam", "-", "-r", "chr1", "-b", "-", "--bam=-", "-F"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            replace_stdin_bam(&args, "/tmp/in.bam"),
+            vec![
+                "--bam",
+                "/tmp/in.bam",
+                "-r",
+                "chr1",
+                "-b",
+                "/tmp/in.bam",
+                "--bam=/tmp/in.bam",
+                "-F"
+            ]
+        );
+    }
+}
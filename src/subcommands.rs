@@ -0,0 +1,188 @@
+//! The subcommands of the command line interface: ```longshot call```, ```train```,
+//! ```genotype```, ```haplotag``` and ```stats```.
+//!
+//! Each subcommand is a use case of the main command, with the options that the use case needs
+//! required and the options that it implies set. The arguments of a subcommand are rewritten into
+//! those of the main command, so that all of the options of the main command can be used with
+//! every subcommand. Without a subcommand, the arguments are those of ```longshot call```.
+
+use errors::*;
+use presets::option_given;
+
+/// the names of the subcommands, as given as the first argument
+pub static SUBCOMMAND_NAMES: [&str; 5] = ["call", "train", "genotype", "haplotag", "stats"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subcommand {
+    /// call, genotype and phase variants (the default)
+    Call,
+    /// only estimate the alignment parameters and write them to a file (```--write-params```)
+    Train,
+    /// genotype the sites of a VCF (```--potential_variants```, with ```--genotype_only```)
+    Genotype,
+    /// call and phase variants, and write the reads with haplotype tags (```--out_bam```)
+    Haplotag,
+    /// call variants, and write the statistics of the run for QC (```--stats-out```)
+    Stats,
+}
+
+impl Subcommand {
+    pub fn from_name(name: &str) -> Option<Subcommand> {
+        match name {
+            "call" => Some(Subcommand::Call),
+            "train" => Some(Subcommand::Train),
+            "genotype" => Some(Subcommand::Genotype),
+            "haplotag" => Some(Subcommand::Haplotag),
+            "stats" => Some(Subcommand::Stats),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Subcommand::Call => "call",
+            Subcommand::Train => "train",
+            Subcommand::Genotype => "genotype",
+            Subcommand::Haplotag => "haplotag",
+            Subcommand::Stats => "stats",
+        }
+    }
+
+    /// whether the subcommand writes a VCF of the calls (```--out```)
+    pub fn writes_vcf(&self) -> bool {
+        *self != Subcommand::Train
+    }
+
+    /// the options that must be given with the subcommand, as ```(long, short)```
+    fn required_options(&self) -> Vec<(&'static str, Option<&'static str>)> {
+        match *self {
+            Subcommand::Call => vec![],
+            Subcommand::Train => vec![("--write-params", None)],
+            Subcommand::Genotype => vec![("--potential_variants", Some("-v"))],
+            Subcommand::Haplotag => vec![("--out_bam", Some("-O"))],
+            Subcommand::Stats => vec![("--stats-out", None)],
+        }
+    }
+
+    /// the options that cannot be given with the subcommand, as ```(long, short)```
+    fn excluded_options(&self) -> Vec<(&'static str, Option<&'static str>)> {
+        match *self {
+            Subcommand::Train => vec![
+                ("--out", Some("-o")),
+                ("--read-params", None),
+                ("--checkpoint_dir", None),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// the flags that the subcommand sets
+    fn implied_flags(&self) -> Vec<&'static str> {
+        match *self {
+            Subcommand::Genotype => vec!["--genotype_only"],
+            _ => vec![],
+        }
+    }
+}
+
+/// Finds the subcommand (the first argument after the program name), and rewrites the arguments
+/// into those of the main command: the subcommand is removed and the flags that it implies are
+/// inserted after the program name.
+///
+/// # Returns
+/// Returns the arguments of the main command and the subcommand, which is ```Subcommand::Call```
+/// if the first argument is an option (or there are no arguments).
+///
+/// # Errors
+/// - The first argument is neither an option nor a subcommand
+/// - An option that the subcommand requires is missing, or an option that it excludes is given
+pub fn apply_subcommand(args: Vec<String>) -> Result<(Vec<String>, Subcommand)> {
+    let subcommand = match args.get(1) {
+        Some(a) if !a.starts_with("-") => match Subcommand::from_name(a) {
+            Some(s) => s,
+            None => bail!(
+                "Unknown subcommand {}. The subcommands are {} (and simulate and merge).",
+                a,
+                SUBCOMMAND_NAMES.join(", ")
+            ),
+        },
+        _ => {
+            return Ok((args, Subcommand::Call));
+        }
+    };
+
+    let options = &args[2..];
+    for (long, short) in subcommand.required_options() {
+        ensure!(
+            option_given(options, long, short),
+            "longshot {} requires {}.",
+            subcommand.name(),
+            long
+        );
+    }
+    for (long, short) in subcommand.excluded_options() {
+        ensure!(
+            !option_given(options, long, short),
+            "{} cannot be used with longshot {}.",
+            long,
+            subcommand.name()
+        );
+    }
+
+    let mut new_args: Vec<String> = args[..1].to_vec();
+    for flag in subcommand.implied_flags() {
+        if !option_given(options, flag, None) {
+            new_args.push(flag.to_string());
+        }
+    }
+    new_args.extend(options.iter().cloned());
+    Ok((new_args, subcommand))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_subcommand() {
+        // without a subcommand, the arguments are those of longshot call
+        let a = args("longshot -b reads.bam -f ref.fa -o out.vcf");
+        assert_eq!(
+            apply_subcommand(a.clone()).unwrap(),
+            (a.clone(), Subcommand::Call)
+        );
+        assert_eq!(
+            apply_subcommand(args("longshot call -b reads.bam -f ref.fa -o out.vcf")).unwrap(),
+            (a, Subcommand::Call)
+        );
+
+        assert_eq!(
+            apply_subcommand(args("longshot genotype -v sites.vcf.gz -o out.vcf")).unwrap(),
+            (
+                args("longshot --genotype_only -v sites.vcf.gz -o out.vcf"),
+                Subcommand::Genotype
+            )
+        );
+        assert_eq!(
+            apply_subcommand(args("longshot train -b reads.bam --write-params=p.txt")).unwrap(),
+            (
+                args("longshot -b reads.bam --write-params=p.txt"),
+                Subcommand::Train
+            )
+        );
+
+        for a in &[
+            "longshot genotype -o out.vcf",
+            "longshot haplotag -o out.vcf",
+            "longshot stats -o out.vcf",
+            "longshot train --write-params p.txt -o out.vcf",
+            "longshot polish -o out.vcf",
+        ] {
+            assert!(apply_subcommand(args(a)).is_err());
+        }
+    }
+}
@@ -0,0 +1,1223 @@
+//! This module nominates the breakpoints of large deletions and insertions (structural variants)
+//! from the read alignments, and phases them with the reads assigned to each haplotype.
+//!
+//! Two kinds of evidence are collected from the primary alignments: long deletions and insertions
+//! in the CIGAR string, and split reads, whose supplementary alignments (from the ```SA``` tag) lie
+//! on the same contig and strand as the primary alignment, separated by a gap on the reference or
+//! on the read. The evidence is clustered by type, position and length, and clusters supported by
+//! enough reads are reported as symbolic ```<DEL>``` and ```<INS>``` records.
+//!
+//! The breakpoints are genotyped from the supporting reads and the reads that span them without
+//! the event. If both haplotypes of a phase block have assigned reads at the breakpoint, the
+//! genotype is phased with the surrounding SNVs, by the allele most reads of each haplotype
+//! support.
+//!
+//! Alternatively, the deletions and insertions of a VCF from a dedicated structural variant
+//! caller are genotyped and phased the same way (```--genotype_sv```). Reads without the event in
+//! their alignment are realigned to the reference and alternate haplotypes around it, so that
+//! reads whose aligner split the event into smaller indels or soft-clipped it still count.
+
+use errors::*;
+use hashbrown::HashMap;
+use print_output::VcfRecord;
+use realignment::{forward_algorithm_numerically_stable, LnAlignmentParameters};
+use rust_htslib::bam;
+use rust_htslib::bcf;
+use rust_htslib::bcf::Read as bcfread;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use util::*;
+
+/// evidence within this many bases of the first evidence of a cluster can join it
+static BREAKPOINT_TOLERANCE: usize = 50;
+/// the lengths of the evidence in a cluster must be within this ratio of each other
+static MIN_LENGTH_RATIO: f64 = 0.7;
+/// reads must extend this many bases past both ends of an event to count as reference reads
+static SPANNING_FLANK: usize = 10;
+/// fraction of the reads that must support an unphased event to call it homozygous
+static MIN_HOM_ALT_FRACTION: f64 = 0.8;
+/// reference bases on each side of an external event in the haplotypes that reads are realigned to
+static REALIGNMENT_FLANK: usize = 100;
+/// external events longer than this are genotyped without realignment, from the reads with the
+/// event in their alignment and the reads spanning it
+static MAX_REALIGNED_SV_LEN: usize = 5000;
+/// the minimum likelihood ratio between the haplotypes for a realigned read to count for one
+static MIN_REALIGNMENT_LR: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvType {
+    Deletion,
+    Insertion,
+}
+
+/// A piece of evidence for a structural variant from a single read
+#[derive(Debug, Clone, PartialEq)]
+struct SvEvidence {
+    sv_type: SvType,
+    /// 0-based position of the first deleted base, or of the base after the insertion
+    pos: usize,
+    len: usize,
+    read_ix: usize,
+}
+
+/// The alignment of a part of a read, in the coordinates of the forward strand of the reference
+#[derive(Debug, Clone, PartialEq)]
+struct AlignedSegment {
+    chrom: String,
+    reverse: bool,
+    mapq: u8,
+    ref_start: usize,
+    ref_end: usize,
+    /// start and end of the aligned part of the read, counted from the left end of the alignment
+    query_start: usize,
+    query_end: usize,
+}
+
+/// builds an aligned segment from the alignment start and the CIGAR operations (as (length,
+/// operation) pairs)
+fn aligned_segment(
+    chrom: &str,
+    reverse: bool,
+    mapq: u8,
+    ref_start: usize,
+    ops: &[(usize, char)],
+) -> AlignedSegment {
+    let mut ref_end = ref_start;
+    let mut query_start = 0;
+    let mut query_end = 0;
+    let mut seen_aligned = false;
+    for &(len, op) in ops {
+        match op {
+            'S' | 'H' => {
+                if !seen_aligned {
+                    query_start += len;
+                    query_end += len;
+                }
+            }
+            'M' | '=' | 'X' => {
+                seen_aligned = true;
+                ref_end += len;
+                query_end += len;
+            }
+            'I' => query_end += len,
+            'D' | 'N' => ref_end += len,
+            _ => {}
+        }
+    }
+    AlignedSegment {
+        chrom: chrom.to_string(),
+        reverse: reverse,
+        mapq: mapq,
+        ref_start: ref_start,
+        ref_end: ref_end,
+        query_start: query_start,
+        query_end: query_end,
+    }
+}
+
+/// parses a CIGAR string into (length, operation) pairs
+fn parse_cigar_string(cigar: &str) -> Option<Vec<(usize, char)>> {
+    let mut ops = vec![];
+    let mut len = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            len.push(c);
+        } else {
+            ops.push((len.parse().ok()?, c));
+            len.clear();
+        }
+    }
+    if len.is_empty() {
+        Some(ops)
+    } else {
+        None
+    }
+}
+
+/// parses the supplementary alignments of a read from its ```SA``` tag
+/// (```rname,pos,strand,CIGAR,mapQ,NM;```)
+fn parse_sa_tag(sa: &str) -> Vec<AlignedSegment> {
+    let mut segments = vec![];
+    for entry in sa.split(';') {
+        let fields: Vec<&str> = entry.split(',').collect();
+        if fields.len() < 6 {
+            continue;
+        }
+        let pos: usize = match fields[1].parse() {
+            Ok(p) if p > 0 => p,
+            _ => continue,
+        };
+        let mapq: u8 = match fields[4].parse() {
+            Ok(q) => q,
+            _ => continue,
+        };
+        if let Some(ops) = parse_cigar_string(fields[3]) {
+            segments.push(aligned_segment(
+                fields[0],
+                fields[2] == "-",
+                mapq,
+                pos - 1,
+                &ops,
+            ));
+        }
+    }
+    segments
+}
+
+/// Finds the large deletions and insertions implied by consecutive aligned segments of a split
+/// read. Only segments on the same contig and strand, in the same order on the read and on the
+/// reference, are used.
+///
+/// # Returns
+/// ```(type, position, length)``` for each event
+fn split_read_events(
+    segments: &mut Vec<AlignedSegment>,
+    min_sv_len: usize,
+) -> Vec<(SvType, usize, usize)> {
+    segments.sort_by_key(|s| s.query_start);
+    let mut events = vec![];
+    for pair in segments.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if a.chrom != b.chrom || a.reverse != b.reverse || b.ref_start < a.ref_end {
+            continue;
+        }
+        let ref_gap = b.ref_start - a.ref_end;
+        let query_gap = b.query_start as isize - a.query_end as isize;
+        let diff = ref_gap as isize - query_gap;
+        if diff >= min_sv_len as isize {
+            events.push((SvType::Deletion, a.ref_end, diff as usize));
+        } else if -diff >= min_sv_len as isize {
+            events.push((SvType::Insertion, a.ref_end, (-diff) as usize));
+        }
+    }
+    events
+}
+
+/// whether a read is used as evidence: a primary alignment with at least ```min_mapq```
+fn use_read(record: &bam::Record, min_mapq: u8) -> bool {
+    !(record.is_quality_check_failed()
+        || record.is_duplicate()
+        || record.is_unmapped()
+        || record.is_secondary()
+        || record.is_supplementary()
+        || record.mapq() < min_mapq)
+}
+
+/// the CIGAR operations of a read as (length, operation) pairs
+fn cigar_ops(record: &bam::Record) -> Vec<(usize, char)> {
+    record
+        .cigar()
+        .iter()
+        .map(|c| (c.len() as usize, c.char()))
+        .collect()
+}
+
+/// Finds the deletions and insertions of at least ```min_sv_len``` bases in a read, from the
+/// CIGAR operations ```ops``` of its primary alignment and from its split alignments.
+///
+/// # Returns
+/// The primary alignment, and ```(type, position, length, split)``` for each event, where
+/// ```split``` is true for the events between split alignments
+fn read_sv_events(
+    record: &bam::Record,
+    ops: &[(usize, char)],
+    chrom: &str,
+    min_mapq: u8,
+    min_sv_len: usize,
+) -> (AlignedSegment, Vec<(SvType, usize, usize, bool)>) {
+    let primary = aligned_segment(
+        chrom,
+        record.is_reverse(),
+        record.mapq(),
+        record.pos() as usize,
+        ops,
+    );
+
+    let mut events: Vec<(SvType, usize, usize, bool)> = vec![];
+    let mut ref_pos = primary.ref_start;
+    for &(len, op) in ops {
+        match op {
+            'M' | '=' | 'X' => ref_pos += len,
+            'D' | 'N' => {
+                if op == 'D' && len >= min_sv_len {
+                    events.push((SvType::Deletion, ref_pos, len, false));
+                }
+                ref_pos += len;
+            }
+            'I' if len >= min_sv_len => {
+                events.push((SvType::Insertion, ref_pos, len, false));
+            }
+            _ => {}
+        }
+    }
+    if let Some(bam::record::Aux::String(sa)) = record.aux(b"SA") {
+        let mut segments: Vec<AlignedSegment> = parse_sa_tag(&String::from_utf8_lossy(sa))
+            .into_iter()
+            .filter(|s| s.mapq >= min_mapq)
+            .collect();
+        segments.push(primary.clone());
+        for (sv_type, pos, len) in split_read_events(&mut segments, min_sv_len) {
+            events.push((sv_type, pos, len, true));
+        }
+    }
+    (primary, events)
+}
+
+/// A cluster of evidence for the same structural variant
+#[derive(Debug, Clone, PartialEq)]
+struct SvCluster {
+    sv_type: SvType,
+    pos: usize,
+    len: usize,
+    /// indices of the supporting reads
+    reads: Vec<usize>,
+}
+
+fn median(v: &mut Vec<usize>) -> usize {
+    v.sort();
+    v[v.len() / 2]
+}
+
+/// Clusters the evidence by type, position and length, and returns the clusters supported by at
+/// least ```min_support``` distinct reads. The position and length of a cluster are the medians
+/// of its evidence.
+fn cluster_evidence(mut evidence: Vec<SvEvidence>, min_support: usize) -> Vec<SvCluster> {
+    evidence.sort_by_key(|e| (e.sv_type, e.pos));
+
+    let mut clusters: Vec<Vec<SvEvidence>> = vec![];
+    // the clusters that evidence can still join (their first evidence is close enough)
+    let mut open: Vec<usize> = vec![];
+    for e in evidence {
+        open.retain(|&c| {
+            clusters[c][0].sv_type == e.sv_type
+                && clusters[c][0].pos + BREAKPOINT_TOLERANCE >= e.pos
+        });
+        let joined = open.iter().cloned().find(|&c| {
+            let first_len = clusters[c][0].len as f64;
+            let ratio = first_len.min(e.len as f64) / first_len.max(e.len as f64);
+            ratio >= MIN_LENGTH_RATIO
+        });
+        match joined {
+            Some(c) => clusters[c].push(e),
+            None => {
+                clusters.push(vec![e]);
+                open.push(clusters.len() - 1);
+            }
+        }
+    }
+
+    let mut result = vec![];
+    for cluster in clusters {
+        let mut reads: Vec<usize> = cluster.iter().map(|e| e.read_ix).collect();
+        reads.sort();
+        reads.dedup();
+        if reads.len() < min_support {
+            continue;
+        }
+        result.push(SvCluster {
+            sv_type: cluster[0].sv_type,
+            pos: median(&mut cluster.iter().map(|e| e.pos).collect()),
+            len: median(&mut cluster.iter().map(|e| e.len).collect()),
+            reads: reads,
+        });
+    }
+    result.sort_by_key(|c| (c.pos, c.sv_type));
+    result
+}
+
+/// Finds the phase set that most of the supporting reads (```alt_reads```) were assigned in, and
+/// counts the supporting and reference reads assigned to each of its haplotypes.
+///
+/// # Returns
+/// The phase set, and the number of supporting and reference reads assigned to haplotype 1 and 2
+fn haplotype_counts(
+    alt_reads: &[usize],
+    ref_reads: &[usize],
+    read_names: &[String],
+    h1: &HashMap<String, usize>,
+    h2: &HashMap<String, usize>,
+) -> (Option<usize>, [usize; 2], [usize; 2]) {
+    let mut phase_set_counts: HashMap<usize, usize> = HashMap::new();
+    for &r in alt_reads {
+        if let Some(&ps) = h1.get(&read_names[r]).or_else(|| h2.get(&read_names[r])) {
+            *phase_set_counts.entry(ps).or_insert(0) += 1;
+        }
+    }
+    let phase_set: Option<usize> = phase_set_counts
+        .iter()
+        .max_by_key(|&(&ps, &count)| (count, ps))
+        .map(|(&ps, _)| ps);
+    let hap_counts = |reads: &[usize]| -> [usize; 2] {
+        let mut counts = [0, 0];
+        if let Some(ps) = phase_set {
+            for &r in reads {
+                if h1.get(&read_names[r]) == Some(&ps) {
+                    counts[0] += 1;
+                } else if h2.get(&read_names[r]) == Some(&ps) {
+                    counts[1] += 1;
+                }
+            }
+        }
+        counts
+    };
+    (phase_set, hap_counts(alt_reads), hap_counts(ref_reads))
+}
+
+/// Genotypes a structural variant from the read counts.
+///
+/// ```alt_haps``` and ```ref_haps``` are the number of supporting and reference reads assigned to
+/// haplotype 1 and 2 of ```phase_set```.
+///
+/// # Returns
+/// The GT and PS values
+fn genotype_sv(
+    alt: usize,
+    reference: usize,
+    alt_haps: [usize; 2],
+    ref_haps: [usize; 2],
+    phase_set: Option<usize>,
+) -> (String, String) {
+    if let Some(ps) = phase_set {
+        if alt_haps[0] + ref_haps[0] > 0 && alt_haps[1] + ref_haps[1] > 0 {
+            let h1 = (alt_haps[0] > ref_haps[0]) as u8;
+            let h2 = (alt_haps[1] > ref_haps[1]) as u8;
+            if h1 + h2 > 0 {
+                return (format!("{}|{}", h1, h2), ps.to_string());
+            }
+        }
+    }
+    let alt_fraction = alt as f64 / (alt + reference) as f64;
+    if alt_fraction >= MIN_HOM_ALT_FRACTION {
+        ("1/1".to_string(), ".".to_string())
+    } else {
+        ("0/1".to_string(), ".".to_string())
+    }
+}
+
+/// Prints the header of the structural variant VCF
+fn print_sv_vcf_header<W: Write>(
+    mut output_file: W,
+    output_vcf_file: &str,
+    fasta_reader: &FastaReader,
+    sample_name: &str,
+) -> Result<()> {
+    writeln!(output_file, "##fileformat=VCFv4.2\n##source=Longshot v0.4.2")
+        .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.to_string()))?;
+    for sequence in fasta_reader.index.sequences().iter() {
+        writeln!(output_file, "##contig=<ID={},length={}>", sequence.name, sequence.len)
+            .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.to_string()))?;
+    }
+    let header = format!("##ALT=<ID=DEL,Description=\"Deletion\">
+##ALT=<ID=INS,Description=\"Insertion\">
+##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">
+##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Difference in length between the ALT and REF alleles\">
+##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant\">
+##INFO=<ID=SR,Number=1,Type=Integer,Description=\"Number of supporting split reads\">
+##INFO=<ID=CR,Number=1,Type=Integer,Description=\"Number of supporting reads with the event in their CIGAR string\">
+##INFO=<ID=RA,Number=1,Type=Integer,Description=\"Number of supporting reads from realignment to the reference and alternate haplotypes (--genotype_sv)\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=PS,Number=1,Type=Integer,Description=\"Phase Set\">
+##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Number of reads spanning the reference allele and supporting the event\">
+##FORMAT=<ID=HA,Number=2,Type=Integer,Description=\"Number of supporting reads assigned to haplotype 1 and 2\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", sample_name);
+    writeln!(output_file, "{}", header)
+        .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.to_string()))?;
+    Ok(())
+}
+
+/// Finds large deletions and insertions (at least ```min_sv_len``` bases) supported by at least
+/// ```min_support``` reads, and writes them to a VCF with symbolic ALT alleles.
+///
+/// ```h1``` and ```h2``` map the reads assigned to each haplotype to their phase set, as returned
+/// by ```separate_fragments_by_haplotype```; they are empty if the reads were not phased. Only
+/// primary alignments with at least ```min_mapq``` are used. If ```append``` is true, the records
+/// are appended to the VCF (for the next region) without printing the header again.
+pub fn call_sv_breakpoints(
+    bamfile_name: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    sample_name: &str,
+    h1: &HashMap<String, usize>,
+    h2: &HashMap<String, usize>,
+    min_mapq: u8,
+    min_sv_len: usize,
+    min_support: usize,
+    output_vcf_file: &String,
+    append: bool,
+) -> Result<()> {
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bamfile_name, interval)
+        .chain_err(|| "Error getting genomic interval list.")?;
+    let target_names = parse_target_names(bamfile_name)?;
+    let mut bam_ix =
+        open_indexed_bam(bamfile_name)?;
+    let mut fasta_reader =
+        open_fasta(fasta_file)?;
+
+    let mut output_file = if append {
+        OpenOptions::new()
+            .append(true)
+            .open(output_vcf_file)
+            .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.clone()))?
+    } else {
+        let file = File::create(output_vcf_file)
+            .chain_err(|| ErrorKind::CreateFileError(output_vcf_file.clone()))?;
+        print_sv_vcf_header(&file, output_vcf_file, &fasta_reader, sample_name)?;
+        file
+    };
+
+    let mut sv_count = 0;
+    for iv in interval_lst {
+        let chrom = &target_names[iv.tid as usize];
+        bam_ix
+            .fetch(iv.tid, iv.start_pos, iv.end_pos + 1)
+            .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+
+        let mut read_names: Vec<String> = vec![];
+        let mut read_spans: Vec<(usize, usize)> = vec![];
+        let mut evidence: Vec<SvEvidence> = vec![];
+        let mut split_read: Vec<bool> = vec![];
+        for r in bam_ix.records() {
+            let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+            if !use_read(&record, min_mapq) {
+                continue;
+            }
+            let read_ix = read_names.len();
+            let ops = cigar_ops(&record);
+            let (primary, events) = read_sv_events(&record, &ops, chrom, min_mapq, min_sv_len);
+
+            // only count events that start in the interval, so that overlapping intervals don't
+            // count them twice
+            let mut is_split_read = false;
+            for (sv_type, pos, len, split) in events {
+                if pos >= iv.start_pos as usize && pos <= iv.end_pos as usize {
+                    evidence.push(SvEvidence {
+                        sv_type: sv_type,
+                        pos: pos,
+                        len: len,
+                        read_ix: read_ix,
+                    });
+                    is_split_read = is_split_read || split;
+                }
+            }
+            read_names.push(u8_to_string(record.qname())?);
+            read_spans.push((primary.ref_start, primary.ref_end));
+            split_read.push(is_split_read);
+        }
+
+        for cluster in cluster_evidence(evidence, min_support) {
+            let end = match cluster.sv_type {
+                SvType::Deletion => cluster.pos + cluster.len,
+                SvType::Insertion => cluster.pos,
+            };
+
+            let ref_reads: Vec<usize> = (0..read_names.len())
+                .filter(|r| {
+                    read_spans[*r].0 + SPANNING_FLANK <= cluster.pos
+                        && read_spans[*r].1 >= end + SPANNING_FLANK
+                        && cluster.reads.binary_search(r).is_err()
+                })
+                .collect();
+            let (phase_set, alt_haps, ref_haps) =
+                haplotype_counts(&cluster.reads, &ref_reads, &read_names, h1, h2);
+            let (gt, ps) = genotype_sv(
+                cluster.reads.len(),
+                ref_reads.len(),
+                alt_haps,
+                ref_haps,
+                phase_set,
+            );
+
+            // the record is anchored at the base before the event
+            let anchor = cluster.pos.max(1) - 1;
+            let mut ref_base: Vec<u8> = vec![];
+            read_fasta_region(
+                &mut fasta_reader,
+                chrom,
+                anchor as u64,
+                anchor as u64 + 1,
+                &mut ref_base,
+            )?;
+            let ref_allele = String::from_utf8_lossy(&ref_base).to_uppercase();
+
+            let (alt, svlen) = match cluster.sv_type {
+                SvType::Deletion => ("<DEL>", -(cluster.len as isize)),
+                SvType::Insertion => ("<INS>", cluster.len as isize),
+            };
+            let split_reads = cluster.reads.iter().filter(|&&r| split_read[r]).count();
+            let mut record = VcfRecord::new(chrom, anchor + 1, &ref_allele, &[alt.to_string()]);
+            record.filter = "PASS".to_string();
+            record.qual = cluster.reads.len() as f64;
+            record.add_info("SVTYPE", &alt[1..4]);
+            record.add_info("SVLEN", svlen);
+            record.add_info("END", end);
+            record.add_info("SR", split_reads);
+            record.add_info("CR", cluster.reads.len() - split_reads);
+            record.set_format(&["GT", "PS", "AD", "HA"]);
+            record.add_sample(vec![
+                gt,
+                ps,
+                format!("{},{}", ref_reads.len(), cluster.reads.len()),
+                format!("{},{}", alt_haps[0], alt_haps[1]),
+            ]);
+            writeln!(output_file, "{}", record)
+                .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.clone()))?;
+            sv_count += 1;
+        }
+    }
+
+    info!(
+        "{}     {} structural variant breakpoints found.",
+        print_time(),
+        sv_count
+    );
+    Ok(())
+}
+
+/// A deletion or insertion from the VCF of a dedicated structural variant caller (e.g. cuteSV or
+/// Sniffles), to be genotyped with ```--genotype_sv```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalSv {
+    pub chrom: String,
+    /// 0-based position of the record (the base before the event)
+    pub anchor: usize,
+    pub id: String,
+    /// the REF and ALT alleles of the record, which are written unchanged
+    pub ref_allele: String,
+    pub alt_allele: String,
+    pub sv_type: SvType,
+    /// 0-based position of the first deleted base, or of the base after the insertion
+    pub pos: usize,
+    pub len: usize,
+    /// the inserted sequence, if the record has it (not for symbolic ```<INS>``` alleles)
+    pub seq: Option<String>,
+}
+
+impl ExternalSv {
+    /// 0-based position of the base after the deletion, or of the base after the insertion
+    fn end(&self) -> usize {
+        match self.sv_type {
+            SvType::Deletion => self.pos + self.len,
+            SvType::Insertion => self.pos,
+        }
+    }
+
+    /// whether a deletion or insertion found in a read is this event, by the same criteria as
+    /// the clustering of the evidence
+    fn matches(&self, sv_type: SvType, pos: usize, len: usize) -> bool {
+        let ratio = self.len.min(len) as f64 / self.len.max(len) as f64;
+        sv_type == self.sv_type
+            && (pos as isize - self.pos as isize).abs() <= BREAKPOINT_TOLERANCE as isize
+            && ratio >= MIN_LENGTH_RATIO
+    }
+}
+
+/// Determines the type, position and length of a deletion or insertion from its VCF record: the
+/// 0-based position of the record, its REF and ALT alleles, and its SVTYPE, SVLEN and END INFO
+/// fields. Symbolic ```<DEL>``` and ```<INS>``` alleles take their length from SVLEN (or END for
+/// deletions), and other alleles must be a single base followed by the deleted or inserted bases.
+///
+/// # Returns
+/// ```(type, position, length, inserted sequence)```, or None for other kinds of records
+fn external_sv_event(
+    anchor: usize,
+    ref_allele: &str,
+    alt_allele: &str,
+    svtype: Option<&str>,
+    svlen: Option<i32>,
+    end: Option<i32>,
+) -> Option<(SvType, usize, usize, Option<String>)> {
+    let pos = anchor + 1;
+    if alt_allele.starts_with('<') {
+        let sv_type = match svtype {
+            Some("DEL") => SvType::Deletion,
+            Some("INS") => SvType::Insertion,
+            _ if alt_allele.starts_with("<DEL") => SvType::Deletion,
+            _ if alt_allele.starts_with("<INS") => SvType::Insertion,
+            _ => return None,
+        };
+        let len = match (svlen, end, sv_type) {
+            (Some(l), _, _) => l.abs() as usize,
+            (None, Some(e), SvType::Deletion) if e as usize > pos => e as usize - pos,
+            _ => return None,
+        };
+        if len == 0 {
+            return None;
+        }
+        return Some((sv_type, pos, len, None));
+    }
+    let ref_allele = ref_allele.to_ascii_uppercase();
+    let alt_allele = alt_allele.to_ascii_uppercase();
+    if alt_allele.len() == 1 && ref_allele.len() > 1 && ref_allele.starts_with(&alt_allele) {
+        Some((SvType::Deletion, pos, ref_allele.len() - 1, None))
+    } else if ref_allele.len() == 1 && alt_allele.len() > 1 && alt_allele.starts_with(&ref_allele) {
+        Some((
+            SvType::Insertion,
+            pos,
+            alt_allele.len() - 1,
+            Some(alt_allele[1..].to_string()),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Reads the deletions and insertions of a structural variant VCF to genotype with
+/// ```--genotype_sv```. Other records (inversions, duplications, breakends, complex or
+/// multiallelic records) are skipped with a warning.
+///
+/// # Errors
+/// - ```BCFOpenError```: error opening the VCF
+/// - ```BCFReadError```: error reading a record of the VCF
+pub fn parse_sv_vcf(sv_vcf: &String) -> Result<Vec<ExternalSv>> {
+    let mut vcf =
+        bcf::Reader::from_path(sv_vcf).chain_err(|| ErrorKind::BCFOpenError(sv_vcf.clone()))?;
+    let vcfh =
+        bcf::Reader::from_path(sv_vcf).chain_err(|| ErrorKind::BCFOpenError(sv_vcf.clone()))?;
+
+    let mut svs: Vec<ExternalSv> = vec![];
+    let mut skipped = 0;
+    for r in vcf.records() {
+        let mut record = r.chain_err(|| ErrorKind::BCFReadError)?;
+        let rid = record.rid().chain_err(|| "Error accessing vcf RID")?;
+        let chrom: String = u8_to_string(
+            vcfh.header()
+                .rid2name(rid)
+                .chain_err(|| ErrorKind::BCFReadError)?,
+        )?;
+        let alleles: Vec<String> = record
+            .alleles()
+            .iter()
+            .map(|a| u8_to_string(a))
+            .collect::<Result<Vec<String>>>()?;
+        if alleles.len() != 2 {
+            skipped += 1;
+            continue;
+        }
+        let svtype: Option<String> = match record.info(b"SVTYPE").string() {
+            Ok(Some(values)) => values
+                .first()
+                .map(|v| String::from_utf8_lossy(v).into_owned()),
+            _ => None,
+        };
+        let svlen: Option<i32> = match record.info(b"SVLEN").integer() {
+            Ok(Some(values)) => values.first().cloned(),
+            _ => None,
+        };
+        let end: Option<i32> = match record.info(b"END").integer() {
+            Ok(Some(values)) => values.first().cloned(),
+            _ => None,
+        };
+        let anchor = record.pos() as usize;
+        let event = external_sv_event(
+            anchor,
+            &alleles[0],
+            &alleles[1],
+            svtype.as_ref().map(|s| s.as_str()),
+            svlen,
+            end,
+        );
+        match event {
+            Some((sv_type, pos, len, seq)) => svs.push(ExternalSv {
+                chrom: chrom,
+                anchor: anchor,
+                id: u8_to_string(&record.id())?,
+                ref_allele: alleles[0].clone(),
+                alt_allele: alleles[1].clone(),
+                sv_type: sv_type,
+                pos: pos,
+                len: len,
+                seq: seq,
+            }),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "{} WARNING: {} records of {} are not deletions or insertions and are not genotyped.",
+            print_time(),
+            skipped,
+            sv_vcf
+        );
+    }
+    Ok(svs)
+}
+
+/// Returns the part of a read aligned to the reference from ```start``` to ```end``` (0-based,
+/// exclusive), as the range of query positions (counting soft-clipped bases) from the first base
+/// aligned at or after ```start``` to the first base aligned at or after ```end```. Returns None
+/// if the alignment doesn't span the whole region.
+fn query_range(
+    ref_start: usize,
+    ops: &[(usize, char)],
+    start: usize,
+    end: usize,
+) -> Option<(usize, usize)> {
+    if ref_start > start {
+        return None;
+    }
+    let mut ref_pos = ref_start;
+    let mut query_pos = 0;
+    let mut query_start: Option<usize> = None;
+    for &(len, op) in ops {
+        let consumes_ref = match op {
+            'M' | '=' | 'X' | 'D' | 'N' => true,
+            _ => false,
+        };
+        let consumes_query = match op {
+            'M' | '=' | 'X' | 'I' | 'S' => true,
+            _ => false,
+        };
+        if consumes_ref {
+            // the reference positions in a deletion map to the next aligned base of the read
+            let offset = |p: usize| if consumes_query { p - ref_pos } else { 0 };
+            if query_start.is_none() && start < ref_pos + len {
+                query_start = Some(query_pos + offset(start));
+            }
+            if end < ref_pos + len {
+                return query_start.map(|qs| (qs, query_pos + offset(end)));
+            }
+            ref_pos += len;
+        }
+        if consumes_query {
+            query_pos += len;
+        }
+    }
+    None
+}
+
+/// Realigns the part of a read around an event to the reference and alternate haplotypes with
+/// the pair-HMM.
+///
+/// # Returns
+/// Some(true) if the read supports the alternate haplotype by at least ```MIN_REALIGNMENT_LR```,
+/// Some(false) if it supports the reference haplotype by as much, and None otherwise
+fn realigned_allele(
+    read_seq: &[u8],
+    ref_hap: &[u8],
+    alt_hap: &[u8],
+    ln_align_params: LnAlignmentParameters,
+    band_width: usize,
+) -> Option<bool> {
+    let p_ref = forward_algorithm_numerically_stable(
+        read_seq,
+        ref_hap,
+        &vec![ln_align_params; ref_hap.len()],
+        band_width,
+    );
+    let p_alt = forward_algorithm_numerically_stable(
+        read_seq,
+        alt_hap,
+        &vec![ln_align_params; alt_hap.len()],
+        band_width,
+    );
+    let min_llr = MIN_REALIGNMENT_LR.ln();
+    if *p_alt - *p_ref >= min_llr {
+        Some(true)
+    } else if *p_ref - *p_alt >= min_llr {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Genotypes and phases the deletions and insertions of a structural variant VCF (see
+/// ```parse_sv_vcf```), and writes them to a VCF like the one of ```call_sv_breakpoints```, with
+/// the ID, REF and ALT of the input records.
+///
+/// Each read is counted once, as supporting the event if it has a matching deletion or insertion
+/// in its CIGAR string or between its split alignments, or otherwise by realigning the part of
+/// the read around the event to the reference and alternate haplotypes (for events of at most
+/// ```MAX_REALIGNED_SV_LEN``` bases with a known sequence), or as a reference read if it spans the
+/// event without it. An event with fewer than ```min_support``` supporting reads is genotyped as
+/// homozygous reference. The genotypes are phased with the reads assigned to each haplotype
+/// (```h1``` and ```h2```) as in ```call_sv_breakpoints```.
+///
+/// Only the records that start in the genotyped region are written. If ```append``` is true, the
+/// records are appended to the VCF (for the next region) without printing the header again.
+pub fn genotype_external_svs(
+    bamfile_name: &String,
+    fasta_file: &String,
+    interval: &Option<GenomicInterval>,
+    sample_name: &str,
+    h1: &HashMap<String, usize>,
+    h2: &HashMap<String, usize>,
+    svs: &Vec<ExternalSv>,
+    min_mapq: u8,
+    min_support: usize,
+    ln_align_params: LnAlignmentParameters,
+    band_width: usize,
+    output_vcf_file: &String,
+    append: bool,
+) -> Result<()> {
+    let interval_lst: Vec<GenomicInterval> = get_interval_lst(bamfile_name, interval)
+        .chain_err(|| "Error getting genomic interval list.")?;
+    let target_names = parse_target_names(bamfile_name)?;
+    let mut bam_ix = open_indexed_bam(bamfile_name)?;
+    let mut ref_reader = RefSeqReader::open(fasta_file)?;
+
+    let mut output_file = if append {
+        OpenOptions::new()
+            .append(true)
+            .open(output_vcf_file)
+            .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.clone()))?
+    } else {
+        let fasta_reader = open_fasta(fasta_file)?;
+        let file = File::create(output_vcf_file)
+            .chain_err(|| ErrorKind::CreateFileError(output_vcf_file.clone()))?;
+        print_sv_vcf_header(&file, output_vcf_file, &fasta_reader, sample_name)?;
+        file
+    };
+
+    let mut sv_count = 0;
+    let mut non_ref_count = 0;
+    for iv in interval_lst {
+        let chrom = &target_names[iv.tid as usize];
+        let contig_len = ref_reader.contig_len(chrom)?;
+        for sv in svs.iter().filter(|sv| {
+            &sv.chrom == chrom && sv.pos >= iv.start_pos as usize && sv.pos <= iv.end_pos as usize
+        }) {
+            // the reference and alternate haplotypes around the event
+            let end = sv.end();
+            let hap_start = sv.pos.saturating_sub(REALIGNMENT_FLANK);
+            let hap_end = (end + REALIGNMENT_FLANK).min(contig_len);
+            let haplotypes: Option<(Vec<u8>, Vec<u8>)> =
+                if sv.len > MAX_REALIGNED_SV_LEN || end > contig_len {
+                    None
+                } else {
+                    let window = ref_reader.window(chrom, hap_start, hap_end)?;
+                    let bytes = |s: &[char]| s.iter().map(|&c| c as u8).collect::<Vec<u8>>();
+                    let ref_hap: Vec<u8> = bytes(&window[hap_start..hap_end]);
+                    match (sv.sv_type, &sv.seq) {
+                        (SvType::Deletion, _) => {
+                            let mut alt_hap: Vec<u8> = bytes(&window[hap_start..sv.pos]);
+                            alt_hap.extend(bytes(&window[end..hap_end]));
+                            Some((ref_hap, alt_hap))
+                        }
+                        (SvType::Insertion, &Some(ref seq)) => {
+                            let mut alt_hap: Vec<u8> = bytes(&window[hap_start..sv.pos]);
+                            alt_hap.extend(seq.bytes());
+                            alt_hap.extend(bytes(&window[sv.pos..hap_end]));
+                            Some((ref_hap, alt_hap))
+                        }
+                        (SvType::Insertion, &None) => None,
+                    }
+                };
+            // the events in the reads must be long enough to match this one
+            let min_event_len = ((sv.len as f64 * MIN_LENGTH_RATIO).ceil() as usize).max(1);
+
+            bam_ix
+                .fetch(iv.tid, hap_start as u32, hap_end.max(hap_start + 1) as u32)
+                .chain_err(|| ErrorKind::IndexedBamFetchError)?;
+            let mut read_names: Vec<String> = vec![];
+            let mut alt_reads: Vec<usize> = vec![];
+            let mut ref_reads: Vec<usize> = vec![];
+            let mut split_reads = 0;
+            let mut realigned_reads = 0;
+            for r in bam_ix.records() {
+                let record = r.chain_err(|| ErrorKind::IndexedBamRecordReadError)?;
+                if !use_read(&record, min_mapq) {
+                    continue;
+                }
+                let read_ix = read_names.len();
+                let ops = cigar_ops(&record);
+                let (primary, events) =
+                    read_sv_events(&record, &ops, chrom, min_mapq, min_event_len);
+
+                let matching: Vec<bool> = events
+                    .iter()
+                    .filter(|&&(sv_type, pos, len, _)| sv.matches(sv_type, pos, len))
+                    .map(|&(_, _, _, split)| split)
+                    .collect();
+                let realigned: Option<bool> = match haplotypes {
+                    Some((ref ref_hap, ref alt_hap)) if matching.is_empty() => {
+                        match query_range(primary.ref_start, &ops, hap_start, hap_end) {
+                            Some((query_start, query_end)) => {
+                                let seq = record.seq();
+                                let read_seq: Vec<u8> = (query_start..query_end)
+                                    .map(|i| seq[i].to_ascii_uppercase())
+                                    .collect();
+                                realigned_allele(
+                                    &read_seq,
+                                    ref_hap,
+                                    alt_hap,
+                                    ln_align_params,
+                                    band_width,
+                                )
+                            }
+                            None => None,
+                        }
+                    }
+                    _ => None,
+                };
+                let spans = primary.ref_start + SPANNING_FLANK <= sv.pos
+                    && primary.ref_end >= end + SPANNING_FLANK;
+
+                if !matching.is_empty() {
+                    if matching.iter().any(|&split| split) {
+                        split_reads += 1;
+                    }
+                    alt_reads.push(read_ix);
+                } else if realigned == Some(true) {
+                    realigned_reads += 1;
+                    alt_reads.push(read_ix);
+                } else if realigned == Some(false) || (realigned.is_none() && spans) {
+                    ref_reads.push(read_ix);
+                } else {
+                    continue;
+                }
+                read_names.push(u8_to_string(record.qname())?);
+            }
+
+            let (phase_set, alt_haps, ref_haps) =
+                haplotype_counts(&alt_reads, &ref_reads, &read_names, h1, h2);
+            let (gt, ps) = if alt_reads.len() >= min_support {
+                non_ref_count += 1;
+                genotype_sv(
+                    alt_reads.len(),
+                    ref_reads.len(),
+                    alt_haps,
+                    ref_haps,
+                    phase_set,
+                )
+            } else if ref_reads.is_empty() && alt_reads.is_empty() {
+                ("./.".to_string(), ".".to_string())
+            } else {
+                ("0/0".to_string(), ".".to_string())
+            };
+
+            let (svtype, svlen) = match sv.sv_type {
+                SvType::Deletion => ("DEL", -(sv.len as isize)),
+                SvType::Insertion => ("INS", sv.len as isize),
+            };
+            let mut record = VcfRecord::new(
+                chrom,
+                sv.anchor + 1,
+                &sv.ref_allele,
+                &[sv.alt_allele.clone()],
+            );
+            record.id = sv.id.clone();
+            record.filter = "PASS".to_string();
+            record.qual = alt_reads.len() as f64;
+            record.add_info("SVTYPE", svtype);
+            record.add_info("SVLEN", svlen);
+            record.add_info("END", end);
+            record.add_info("SR", split_reads);
+            record.add_info("CR", alt_reads.len() - split_reads - realigned_reads);
+            record.add_info("RA", realigned_reads);
+            record.set_format(&["GT", "PS", "AD", "HA"]);
+            record.add_sample(vec![
+                gt,
+                ps,
+                format!("{},{}", ref_reads.len(), alt_reads.len()),
+                format!("{},{}", alt_haps[0], alt_haps[1]),
+            ]);
+            writeln!(output_file, "{}", record)
+                .chain_err(|| ErrorKind::FileWriteError(output_vcf_file.clone()))?;
+            sv_count += 1;
+        }
+    }
+
+    info!(
+        "{}     {} structural variants genotyped, {} of them non-reference.",
+        print_time(),
+        sv_count,
+        non_ref_count
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use realignment::{AlignmentParameters, EmissionProbs, TransitionProbs};
+
+    #[test]
+    fn test_split_read_events() {
+        // 1000 bases aligned at 100, then the rest of the read aligned 500 bases further
+        let mut segments = parse_sa_tag("chr1,1601,+,1000S800M,60,3;chr2,1,+,1000S800M,60,0;");
+        assert_eq!(segments.len(), 2);
+        segments.truncate(1);
+        segments.push(aligned_segment(
+            "chr1",
+            false,
+            60,
+            100,
+            &parse_cigar_string("1000M800S").unwrap(),
+        ));
+        assert_eq!(
+            split_read_events(&mut segments.clone(), 50),
+            vec![(SvType::Deletion, 1100, 500)]
+        );
+        // on the other strand, the segments don't belong together
+        segments[0].reverse = true;
+        assert_eq!(split_read_events(&mut segments, 50), vec![]);
+
+        // 200 inserted bases between the segments
+        let mut segments = parse_sa_tag("chr1,1101,+,1200S800M,60,0;");
+        segments.push(aligned_segment(
+            "chr1",
+            false,
+            60,
+            100,
+            &parse_cigar_string("1000M1000S").unwrap(),
+        ));
+        assert_eq!(
+            split_read_events(&mut segments, 50),
+            vec![(SvType::Insertion, 1100, 200)]
+        );
+        assert_eq!(parse_cigar_string("10M5"), None);
+    }
+
+    #[test]
+    fn test_cluster_evidence() {
+        let evidence = |sv_type, pos, len, read_ix| SvEvidence {
+            sv_type: sv_type,
+            pos: pos,
+            len: len,
+            read_ix: read_ix,
+        };
+        let clusters = cluster_evidence(
+            vec![
+                evidence(SvType::Deletion, 1000, 300, 0),
+                evidence(SvType::Deletion, 1010, 310, 1),
+                evidence(SvType::Deletion, 1020, 290, 2),
+                // too short for the cluster, and not enough support on its own
+                evidence(SvType::Deletion, 1005, 100, 3),
+                // the same read twice
+                evidence(SvType::Insertion, 1000, 300, 4),
+                evidence(SvType::Insertion, 1000, 300, 4),
+                evidence(SvType::Insertion, 1030, 300, 5),
+            ],
+            2,
+        );
+        assert_eq!(
+            clusters,
+            vec![
+                SvCluster {
+                    sv_type: SvType::Insertion,
+                    pos: 1000,
+                    len: 300,
+                    reads: vec![4, 5],
+                },
+                SvCluster {
+                    sv_type: SvType::Deletion,
+                    pos: 1010,
+                    len: 300,
+                    reads: vec![0, 1, 2],
+                },
+            ]
+        );
+
+        // phased heterozygous on haplotype 2
+        assert_eq!(
+            genotype_sv(4, 5, [0, 4], [5, 0], Some(1000)),
+            ("0|1".to_string(), "1000".to_string())
+        );
+        // no reads assigned to haplotype 1
+        assert_eq!(
+            genotype_sv(4, 0, [0, 4], [0, 0], Some(1000)),
+            ("1/1".to_string(), ".".to_string())
+        );
+        assert_eq!(
+            genotype_sv(4, 5, [0, 0], [0, 0], None),
+            ("0/1".to_string(), ".".to_string())
+        );
+    }
+
+    #[test]
+    fn test_external_sv_event() {
+        // explicit alleles
+        assert_eq!(
+            external_sv_event(99, "ACGTACGT", "A", Some("DEL"), Some(-7), None),
+            Some((SvType::Deletion, 100, 7, None))
+        );
+        assert_eq!(
+            external_sv_event(99, "a", "aTTTT", None, None, None),
+            Some((SvType::Insertion, 100, 4, Some("TTTT".to_string())))
+        );
+        // symbolic alleles
+        assert_eq!(
+            external_sv_event(99, "A", "<DEL>", Some("DEL"), None, Some(400)),
+            Some((SvType::Deletion, 100, 300, None))
+        );
+        assert_eq!(
+            external_sv_event(99, "A", "<INS>", Some("INS"), Some(250), Some(100)),
+            Some((SvType::Insertion, 100, 250, None))
+        );
+        // other events
+        assert_eq!(
+            external_sv_event(99, "A", "<INV>", Some("INV"), None, Some(400)),
+            None
+        );
+        assert_eq!(external_sv_event(99, "ACGT", "TT", None, None, None), None);
+        assert_eq!(external_sv_event(99, "A", "<INS>", None, None, None), None);
+    }
+
+    #[test]
+    fn test_query_range() {
+        // 5 soft-clipped bases, 10 aligned at 100, a deletion of 20, 3 inserted bases, 10 aligned
+        let ops = parse_cigar_string("5S10M20D3I10M").unwrap();
+        assert_eq!(query_range(100, &ops, 102, 108), Some((7, 13)));
+        // the deleted bases map to the next aligned base of the read
+        assert_eq!(query_range(100, &ops, 105, 115), Some((10, 15)));
+        assert_eq!(query_range(100, &ops, 105, 132), Some((10, 20)));
+        // not spanned
+        assert_eq!(query_range(100, &ops, 99, 108), None);
+        assert_eq!(query_range(100, &ops, 105, 140), None);
+    }
+
+    #[test]
+    fn test_realigned_allele() {
+        let params = AlignmentParameters {
+            transition_probs: TransitionProbs {
+                match_from_match: 0.879,
+                insertion_from_match: 0.1,
+                deletion_from_match: 0.021,
+                insertion_from_insertion: 0.4,
+                match_from_insertion: 0.6,
+                deletion_from_deletion: 0.25,
+                match_from_deletion: 0.75,
+            },
+            emission_probs: EmissionProbs {
+                equal: 0.97,
+                not_equal: 0.01,
+                insertion: 1.0,
+                deletion: 1.0,
+            },
+        }
+        .ln();
+        let flank: Vec<u8> = b"ACGTTGCAAGGCTTACCGATGCATGCCTAG".to_vec();
+        let deleted: Vec<u8> = b"TTGACCAGTAGGCATTACGGATCCAGTTAC".to_vec();
+        let mut ref_hap = flank.clone();
+        ref_hap.extend(&deleted);
+        ref_hap.extend(flank.iter().rev());
+        let mut alt_hap = flank.clone();
+        alt_hap.extend(flank.iter().rev());
+
+        assert_eq!(
+            realigned_allele(&alt_hap, &ref_hap, &alt_hap, params, 20),
+            Some(true)
+        );
+        assert_eq!(
+            realigned_allele(&ref_hap, &ref_hap, &alt_hap, params, 20),
+            Some(false)
+        );
+
+        let sv = ExternalSv {
+            chrom: "chr1".to_string(),
+            anchor: 99,
+            id: "sv1".to_string(),
+            ref_allele: "A".to_string(),
+            alt_allele: "<DEL>".to_string(),
+            sv_type: SvType::Deletion,
+            pos: 100,
+            len: 300,
+            seq: None,
+        };
+        assert_eq!(sv.end(), 400);
+        assert!(sv.matches(SvType::Deletion, 130, 250));
+        assert!(!sv.matches(SvType::Deletion, 160, 300));
+        assert!(!sv.matches(SvType::Deletion, 100, 150));
+        assert!(!sv.matches(SvType::Insertion, 100, 300));
+    }
+}
@@ -0,0 +1,140 @@
+//! Counters of the work done in the hot spots of longshot: the pair-HMM and edit distance
+//! alignments of the realignment, the cells of their dynamic programming matrices, and the lookups
+//! of the alignment cache. With the running time of each stage (see ```StageTimer```), they show
+//! where the time of a run goes, e.g. whether a larger ```--alignment_cache``` or a narrower
+//! ```--band_width``` would help. They are printed at the end of the run and written to the JSON
+//! statistics (```--stats-out```).
+//!
+//! The counters are global, so that the alignment functions don't need to pass them around, and
+//! are only updated once per alignment.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALIGNMENTS: AtomicUsize = AtomicUsize::new(0);
+static DP_CELLS: AtomicUsize = AtomicUsize::new(0);
+static CACHE_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+static PREFILTERED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts an alignment that computed ```cells``` cells of a dynamic programming matrix
+pub fn count_alignment(cells: usize) {
+    ALIGNMENTS.fetch_add(1, Ordering::Relaxed);
+    DP_CELLS.fetch_add(cells, Ordering::Relaxed);
+}
+
+/// Counts the lookups and hits of an alignment cache, and the read windows that were scored by
+/// the edit distance pre-filter instead (see ```AlignmentCache```)
+pub fn count_alignment_cache(lookups: usize, hits: usize, prefiltered: usize) {
+    CACHE_LOOKUPS.fetch_add(lookups, Ordering::Relaxed);
+    CACHE_HITS.fetch_add(hits, Ordering::Relaxed);
+    PREFILTERED.fetch_add(prefiltered, Ordering::Relaxed);
+}
+
+/// The work counted since the start of the run, or in a stage of it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkCounters {
+    /// pair-HMM and edit distance alignments
+    pub alignments: usize,
+    /// cells of their dynamic programming matrices
+    pub dp_cells: usize,
+    pub cache_lookups: usize,
+    pub cache_hits: usize,
+    /// read windows scored by their edit distances instead of being aligned
+    pub prefiltered: usize,
+}
+
+impl WorkCounters {
+    /// The work counted since the start of the run
+    pub fn current() -> WorkCounters {
+        WorkCounters {
+            alignments: ALIGNMENTS.load(Ordering::Relaxed),
+            dp_cells: DP_CELLS.load(Ordering::Relaxed),
+            cache_lookups: CACHE_LOOKUPS.load(Ordering::Relaxed),
+            cache_hits: CACHE_HITS.load(Ordering::Relaxed),
+            prefiltered: PREFILTERED.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The work counted since ```start```, an earlier value of ```current```
+    pub fn since(&self, start: &WorkCounters) -> WorkCounters {
+        WorkCounters {
+            alignments: self.alignments - start.alignments,
+            dp_cells: self.dp_cells - start.dp_cells,
+            cache_lookups: self.cache_lookups - start.cache_lookups,
+            cache_hits: self.cache_hits - start.cache_hits,
+            prefiltered: self.prefiltered - start.prefiltered,
+        }
+    }
+
+    pub fn add(&mut self, other: &WorkCounters) {
+        self.alignments += other.alignments;
+        self.dp_cells += other.dp_cells;
+        self.cache_lookups += other.cache_lookups;
+        self.cache_hits += other.cache_hits;
+        self.prefiltered += other.prefiltered;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == WorkCounters::default()
+    }
+
+    /// The counters by name, as in the JSON statistics
+    pub fn entries(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("alignments", self.alignments),
+            ("dp_cells", self.dp_cells),
+            ("cache_lookups", self.cache_lookups),
+            ("cache_hits", self.cache_hits),
+            ("prefiltered_windows", self.prefiltered),
+        ]
+    }
+
+    /// A short summary for the log, e.g. ```1200 alignments, 5400000 DP cells, 35.0% cache hits```
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} alignments, {} DP cells", self.alignments, self.dp_cells);
+        if self.cache_lookups > 0 {
+            summary += &format!(
+                ", {:.1}% cache hits",
+                100.0 * self.cache_hits as f64 / self.cache_lookups as f64
+            );
+        }
+        if self.prefiltered > 0 {
+            summary += &format!(", {} windows pre-filtered", self.prefiltered);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_work_counters() {
+        let start = WorkCounters::current();
+        count_alignment(100);
+        count_alignment(50);
+        count_alignment_cache(4, 1, 2);
+        // other tests may count alignments at the same time
+        let work = WorkCounters::current().since(&start);
+        assert!(work.alignments >= 2 && work.dp_cells >= 150);
+        assert!(work.cache_lookups >= 4 && work.cache_hits >= 1 && work.prefiltered >= 2);
+
+        let mut total = WorkCounters {
+            alignments: 2,
+            dp_cells: 150,
+            cache_lookups: 4,
+            cache_hits: 1,
+            prefiltered: 0,
+        };
+        assert_eq!(
+            total.summary(),
+            "2 alignments, 150 DP cells, 25.0% cache hits"
+        );
+        let copy = total;
+        total.add(&copy);
+        assert_eq!(total.dp_cells, 300);
+        assert_eq!(total.since(&total), WorkCounters::default());
+        assert!(total.since(&total).is_empty());
+    }
+}
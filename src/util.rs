@@ -1,13 +1,29 @@
 //! Miscellaneous utility functions for printing, parsing, type conversion, etc.
 
+use alphabet::Alphabet;
+use bio::io::fasta;
 use bio::stats::{LogProb, Prob};
 use chrono::prelude::*;
 use clap::ArgMatches;
 use errors::*;
+use hashbrown::HashMap;
+use log::{self, LevelFilter, Log, Metadata, Record};
+use memmap::Mmap;
+use rand::{Rng, SeedableRng, StdRng};
 use rust_htslib::bam;
 use rust_htslib::bam::Read;
+use rust_htslib::htslib;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read as IoRead, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_long, c_void};
+use std::ops::{Index, Range};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use telemetry::WorkCounters;
+use url::Url;
 
-pub static INDEX_FREQ: usize = 1000;
 pub static MAX_VCF_QUAL: f64 = 500.0;
 
 pub fn print_time() -> String {
@@ -18,6 +34,219 @@ pub fn print_time() -> String {
 // lines that document the time
 pub static SPACER: &str = "                   ";
 
+/// the names of the log levels of ```--log_level```, from the fewest to the most messages
+pub static LOG_LEVEL_NAMES: [&str; 3] = ["off", "warn", "info"];
+
+/// The logger of the ```log``` macros, which writes each message to stderr as it is. The messages
+/// start with their own timestamp (```print_time```), so that the continuation lines of a
+/// message can be aligned with ```SPACER```.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Prints the messages of the ```log``` macros up to the given level to stderr. Can be called
+/// again to change the level.
+pub fn init_logging(level: LevelFilter) {
+    // the logger can only be set once, and is already set if the level is changed
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+/// Parses the log level of ```--log_level``` (one of ```LOG_LEVEL_NAMES```)
+pub fn parse_log_level(name: &str) -> Result<LevelFilter> {
+    match name {
+        "off" => Ok(LevelFilter::Off),
+        "warn" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        _ => bail!(
+            "Invalid log level {}, must be one of {}.",
+            name,
+            LOG_LEVEL_NAMES.join(", ")
+        ),
+    }
+}
+
+/// whether to print progress reports with ProgressReporter (set with --progress)
+pub static SHOW_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// minimum number of seconds between two progress reports of the same stage
+static PROGRESS_INTERVAL: u64 = 30;
+
+/// formats a duration as e.g. "1h02m03s"
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Reports the progress of a stage that processes a known amount of work (e.g. reference bases
+/// or variants), with an estimate of the remaining time. Reports are only printed with
+/// --progress, at most every PROGRESS_INTERVAL seconds.
+pub struct ProgressReporter {
+    stage: String,
+    unit: String,
+    total: usize,
+    done: usize,
+    start: Instant,
+    last_report: Instant,
+    enabled: bool,
+}
+
+impl ProgressReporter {
+    pub fn new(stage: &str, unit: &str, total: usize) -> ProgressReporter {
+        let now = Instant::now();
+        ProgressReporter {
+            stage: stage.to_string(),
+            unit: unit.to_string(),
+            total: total,
+            done: 0,
+            start: now,
+            last_report: now,
+            enabled: SHOW_PROGRESS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// adds ```n``` units of completed work
+    pub fn add(&mut self, n: usize) {
+        let done = self.done + n;
+        self.set(done);
+    }
+
+    /// sets the amount of completed work, and prints a report if it is due
+    pub fn set(&mut self, done: usize) {
+        self.done = done;
+        if !self.enabled || self.last_report.elapsed().as_secs() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let elapsed = self.start.elapsed();
+        let frac = if self.total > 0 {
+            (self.done as f64 / self.total as f64).min(1.0)
+        } else {
+            0.0
+        };
+        let eta = if frac > 0.0 {
+            let remaining = elapsed.as_secs() as f64 * (1.0 - frac) / frac;
+            format_duration(Duration::from_secs(remaining as u64))
+        } else {
+            "unknown".to_string()
+        };
+        info!(
+            "{} {}: {} of {} {} ({:.1}%), elapsed {}, remaining {}",
+            print_time(),
+            self.stage,
+            self.done,
+            self.total,
+            self.unit,
+            frac * 100.0,
+            format_duration(elapsed),
+            eta
+        );
+    }
+}
+
+/// Records the running time of each stage of the program, to print a summary at the end
+pub struct StageTimer {
+    stages: Vec<(String, Duration)>,
+    /// the alignments and cache lookups of each stage (see ```telemetry```)
+    work: Vec<WorkCounters>,
+    current: Option<(String, Instant, WorkCounters)>,
+}
+
+impl StageTimer {
+    pub fn new() -> StageTimer {
+        StageTimer {
+            stages: vec![],
+            work: vec![],
+            current: None,
+        }
+    }
+
+    /// starts timing a stage, finishing the current one. Stages with the same name (e.g. for
+    /// each sample) are added together.
+    pub fn start(&mut self, stage: &str) {
+        self.finish();
+        self.current = Some((stage.to_string(), Instant::now(), WorkCounters::current()));
+    }
+
+    pub fn finish(&mut self) {
+        if let Some((stage, start, start_work)) = self.current.take() {
+            let elapsed = start.elapsed();
+            let work = WorkCounters::current().since(&start_work);
+            match self.stages.iter().position(|&(ref s, _)| *s == stage) {
+                Some(i) => {
+                    self.stages[i].1 += elapsed;
+                    self.work[i].add(&work);
+                }
+                None => {
+                    self.stages.push((stage, elapsed));
+                    self.work.push(work);
+                }
+            }
+        }
+    }
+
+    /// the running time of each stage, finishing the current one
+    pub fn stages(&mut self) -> Vec<(String, Duration)> {
+        self.finish();
+        self.stages.clone()
+    }
+
+    /// the work counted in each stage, finishing the current one
+    pub fn stage_work(&mut self) -> Vec<(String, WorkCounters)> {
+        self.finish();
+        self.stages
+            .iter()
+            .map(|&(ref stage, _)| stage.clone())
+            .zip(self.work.iter().cloned())
+            .collect()
+    }
+
+    pub fn print(&mut self) {
+        self.finish();
+        info!("{} Time per stage:", print_time());
+        let mut total = WorkCounters::default();
+        for (&(ref stage, elapsed), work) in self.stages.iter().zip(self.work.iter()) {
+            let work_summary = if work.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", work.summary())
+            };
+            info!(
+                "{} {:<30}{}{}",
+                SPACER,
+                format!("{}:", stage),
+                format_duration(elapsed),
+                work_summary
+            );
+            total.add(work);
+        }
+        if !total.is_empty() {
+            info!("{} {:<30}{}", SPACER, "total work:", total.summary());
+        }
+    }
+}
+
 pub fn parse_u8(argmatch: &ArgMatches, arg_name: &str) -> Result<u8> {
     let parse_result: u8 = argmatch
         .value_of(arg_name)
@@ -36,6 +265,20 @@ pub fn parse_u32(argmatch: &ArgMatches, arg_name: &str) -> Result<u32> {
     Ok(parse_result)
 }
 
+/// parses a set of SAM flag bits, given in decimal or in hexadecimal with a 0x prefix
+pub fn parse_sam_flags(argmatch: &ArgMatches, arg_name: &str) -> Result<u16> {
+    let value = argmatch
+        .value_of(arg_name)
+        .chain_err(|| format!("{} not defined.", arg_name))?;
+    let parse_result = if value.starts_with("0x") || value.starts_with("0X") {
+        u16::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse::<u16>()
+    }
+    .chain_err(|| format!("{} must be a SAM flag value!", arg_name))?;
+    Ok(parse_result)
+}
+
 pub fn parse_usize(argmatch: &ArgMatches, arg_name: &str) -> Result<usize> {
     let parse_result: usize = argmatch
         .value_of(arg_name)
@@ -95,12 +338,29 @@ pub fn parse_flag(argmatch: &ArgMatches, arg_name: &str) -> Result<bool> {
     Ok(is_flag_set)
 }
 
+/// Parses a single site given as ```<chrom>:<pos>``` (1-based) into an interval of length 1
+pub fn parse_site_string(site_string: &str, bamfile_name: &String) -> Result<GenomicInterval> {
+    let fields: Vec<&str> = site_string.split(":").collect();
+    ensure!(
+        fields.len() == 2 && !fields[1].contains("-"),
+        "Invalid format for site. Please use <chrom:pos>"
+    );
+    let pos = fields[1]
+        .parse::<u32>()
+        .chain_err(|| "Invalid position value specified in site string.")?;
+    let region = format!("{}:{}-{}", fields[0], pos, pos);
+    match parse_region_string(Some(&region), bamfile_name)? {
+        Some(interval) => Ok(interval),
+        None => bail!("Invalid format for site. Please use <chrom:pos>"),
+    }
+}
+
 // this is really ugly. TODO a less verbose implementation
 pub fn parse_region_string(
     region_string: Option<&str>,
     bamfile_name: &String,
 ) -> Result<Option<GenomicInterval>> {
-    let bam = bam::Reader::from_path(bamfile_name).chain_err(|| ErrorKind::BamOpenError)?;
+    let bam = open_bam(bamfile_name)?;
 
     match region_string {
         Some(r) if r.contains(":") && r.contains("-") => {
@@ -195,31 +455,146 @@ pub struct GenomicInterval {
     // end of interval (0-indexed, inclusive)
 }
 
+/// A window of a contig's reference sequence that is indexed by the position on the contig, so
+/// that it can be used in place of the whole contig sequence. Indexing a position outside of the
+/// window panics.
+pub struct RefSeqWindow {
+    /// 0-based position on the contig of the first base in ```seq```
+    pub start: usize,
+    pub seq: Vec<char>,
+    /// the length of the whole contig
+    pub contig_len: usize,
+}
+
+impl RefSeqWindow {
+    /// Creates a window that holds the whole contig sequence
+    pub fn whole_contig(seq: Vec<char>) -> RefSeqWindow {
+        let contig_len = seq.len();
+        RefSeqWindow {
+            start: 0,
+            seq: seq,
+            contig_len: contig_len,
+        }
+    }
+
+    /// the length of the whole contig (not of the window)
+    pub fn len(&self) -> usize {
+        self.contig_len
+    }
+
+    /// whether the window holds every position in ```start..end```
+    pub fn covers(&self, start: usize, end: usize) -> bool {
+        start >= self.start && end <= self.start + self.seq.len()
+    }
+}
+
+impl Index<usize> for RefSeqWindow {
+    type Output = char;
+
+    fn index(&self, pos: usize) -> &char {
+        &self.seq[pos - self.start]
+    }
+}
+
+impl Index<Range<usize>> for RefSeqWindow {
+    type Output = [char];
+
+    fn index(&self, range: Range<usize>) -> &[char] {
+        &self.seq[range.start - self.start..range.end - self.start]
+    }
+}
+
+/// The quality of a variant that the variant density filter compares to its threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DensityScore {
+    /// the variant quality (QUAL)
+    Qual,
+    /// the genotype quality (GQ)
+    Gq,
+}
+
+/// The names of the variant density filter scores, for ```--density_score```, in the order of
+/// ```DensityScore```
+pub static DENSITY_SCORE_NAMES: [&str; 2] = ["qual", "gq"];
+
+impl DensityScore {
+    pub fn from_name(name: &str) -> Option<DensityScore> {
+        match name {
+            "qual" => Some(DensityScore::Qual),
+            "gq" => Some(DensityScore::Gq),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DensityParameters {
     pub n: usize,
     pub len: usize,
     pub gq: f64,
+    /// the quality that is compared to ```gq```
+    pub score: DensityScore,
+    /// raise ```n``` to the number of variants expected in a window of ```len``` bp at the density
+    /// of the variant calls of the region (```--density_count auto```)
+    pub auto_n: bool,
+    /// the window (in bp on either side) in which the neighboring calls of each variant are
+    /// counted for the NW annotation
+    pub neighbor_window: usize,
+}
+
+/// Which reads overlapping a site count toward its read depth. The same definition is used for
+/// the DP field, the min/max coverage cutoffs and the mean coverage estimate for --auto_max_cov.
+/// Clipped bases are never aligned to a site, so clipped reads only count where they are aligned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthDefinition {
+    /// every read spanning the site, including reads with a deletion or reference skip there
+    All,
+    /// only reads with a base aligned to the site (as with ```samtools depth```)
+    GapExcluded,
+}
+
+impl DepthDefinition {
+    pub fn counts(&self, alignment: &bam::pileup::Alignment) -> bool {
+        self.counts_gap(alignment.is_del() || alignment.is_refskip())
+    }
+
+    /// Whether a read with a deletion or reference skip at the site (```gap```), or otherwise with
+    /// a base aligned to it, counts toward the depth
+    pub fn counts_gap(&self, gap: bool) -> bool {
+        match *self {
+            DepthDefinition::All => true,
+            DepthDefinition::GapExcluded => !gap,
+        }
+    }
 }
 
 pub fn u8_to_string(u: &[u8]) -> Result<String> {
     Ok(String::from_utf8(u.to_vec()).chain_err(|| "Error converting u8 to String.")?)
 }
 
-//
+/// the IUPAC codes of the ambiguous bases other than N
+pub static IUPAC_AMBIGUITY_CODES: [char; 10] = ['R', 'Y', 'S', 'W', 'K', 'M', 'B', 'D', 'H', 'V'];
+
+/// Whether a base of a sequence read with ```dna_vec``` is ambiguous: an N, or an IUPAC ambiguity
+/// code (which are only kept with ```--alphabet iupac```)
+pub fn is_ambiguous_base(c: char) -> bool {
+    c == 'N' || IUPAC_AMBIGUITY_CODES.contains(&c)
+}
+
+// converts a sequence to upper case bases of the alphabet of the run (see ```Alphabet```)
 pub fn dna_vec(u: &[u8]) -> Vec<char> {
+    let alphabet = Alphabet::current();
     let mut v: Vec<char> = Vec::with_capacity(u.len());
-    for cu in u.to_ascii_uppercase() {
-        let c = cu as char;
-        //assert!(c == 'A' || c == 'C' || c == 'G' || c == 'T' || c == 'N');
-        if c == 'A' || c == 'C' || c == 'G' || c == 'T' || c == 'N' {
-            v.push(c);
-        } else {
-            eprintln!(
-                "Warning: Unexpected base \"{}\" encountered. Replaced with \"N\".",
-                c
-            );
-            v.push('N');
+    for &cu in u {
+        match alphabet.normalize(cu as char) {
+            Some(c) => v.push(c),
+            None => {
+                warn!(
+                    "Unexpected base \"{}\" encountered. Replaced with \"N\".",
+                    (cu as char).to_ascii_uppercase()
+                );
+                v.push('N');
+            }
         }
     }
     v
@@ -234,8 +609,24 @@ pub fn has_non_acgt(s: &String) -> bool {
     false
 }
 
+// the SplitMix64 finalizer, which scrambles the bits of a 64-bit value
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A pseudo-random number for the variant at a position, for the random choices made for each
+/// variant. Unlike the numbers drawn from a random number generator, it depends only on the seed
+/// and the position, and not on the other variants that are called in the same region, so the
+/// output is the same whether the genome is called as a whole, by contig or in parallel.
+pub fn position_hash(seed: u64, tid: u32, pos0: usize) -> u64 {
+    splitmix64(splitmix64(splitmix64(seed) ^ tid as u64) ^ pos0 as u64)
+}
+
 pub fn parse_target_names(bam_file: &String) -> Result<Vec<String>> {
-    let bam = bam::Reader::from_path(bam_file).chain_err(|| ErrorKind::BamOpenError)?;
+    let bam = open_bam(bam_file)?;
     let header_view = bam.header();
     let target_names_dec: Vec<&[u8]> = header_view.target_names();
     let mut target_names: Vec<String> = vec![];
@@ -253,8 +644,439 @@ pub fn parse_target_names(bam_file: &String) -> Result<Vec<String>> {
     Ok(target_names)
 }
 
+/// Reads a BED file of regions with a non-default ploidy
+///
+/// Each line has (at least) 4 tab- or space-separated columns: chromosome, start (0-based),
+/// end (exclusive) and ploidy. Header lines (starting with ```#```, ```track``` or ```browser```)
+/// are skipped, and so are regions on chromosomes that are not in the BAM file.
+///
+/// # Arguments
+/// - ```bed_file```: path to the BED file
+/// - ```bam_file```: BAM file used to convert chromosome names to target IDs
+///
+/// # Returns
+/// Returns a result containing a vector of ```(interval, ploidy)``` tuples, with intervals
+/// converted to 0-based inclusive coordinates.
+///
+/// # Errors
+/// - Error if the BED file cannot be read, or a line is not in the expected format
+pub fn parse_ploidy_bed(
+    bed_file: &String,
+    bam_file: &String,
+) -> Result<Vec<(GenomicInterval, u8)>> {
+    let target_names = parse_target_names(bam_file)?;
+    let mut contents = String::new();
+    File::open(bed_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| ErrorKind::FileReadError(bed_file.clone()))?;
+
+    let mut regions: Vec<(GenomicInterval, u8)> = vec![];
+    for (line_num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with("#")
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        ensure!(
+            cols.len() >= 4,
+            "Line {} of ploidy BED file has fewer than 4 columns (chrom, start, end, ploidy).",
+            line_num + 1
+        );
+        let start = cols[1]
+            .parse::<u32>()
+            .chain_err(|| {
+                format!(
+                    "Invalid start position on line {} of ploidy BED file.",
+                    line_num + 1
+                )
+            })?;
+        let end = cols[2]
+            .parse::<u32>()
+            .chain_err(|| {
+                format!(
+                    "Invalid end position on line {} of ploidy BED file.",
+                    line_num + 1
+                )
+            })?;
+        let ploidy = cols[3]
+            .parse::<u8>()
+            .chain_err(|| {
+                format!(
+                    "Invalid ploidy on line {} of ploidy BED file.",
+                    line_num + 1
+                )
+            })?;
+        ensure!(
+            end > start,
+            "End position must be greater than start position on line {} of ploidy BED file.",
+            line_num + 1
+        );
+
+        if let Some(tid) = target_names.iter().position(|t| t == cols[0]) {
+            regions.push((
+                GenomicInterval {
+                    tid: tid as u32,
+                    chrom: cols[0].to_string(),
+                    start_pos: start,
+                    end_pos: end - 1,
+                },
+                ploidy,
+            ));
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Reads a BED file of target regions to call variants in
+///
+/// Each region is extended by ```padding``` bases on both sides (up to the ends of its contig),
+/// and overlapping or adjacent regions are merged, so that each position is called once. Lines
+/// starting with ```#```, ```track``` or ```browser``` are skipped, as are regions on contigs
+/// that are not in the BAM file.
+///
+/// # Arguments
+/// - ```bed_file```: path to the BED file (0-based, half-open intervals)
+/// - ```bam_file```: BAM file used to convert contig names to target IDs and to get contig lengths
+/// - ```padding```: number of bases to add on each side of every region
+///
+/// # Returns
+/// Returns a result containing the merged ```GenomicInterval```s, sorted by position
+///
+/// # Errors
+/// - Error if the BED file cannot be read, a line is not in the expected format, or it has no
+///   regions on the contigs of the BAM file
+pub fn parse_target_bed(
+    bed_file: &String,
+    bam_file: &String,
+    padding: u32,
+) -> Result<Vec<GenomicInterval>> {
+    let contigs = get_whole_genome_intervals(bam_file)?;
+    let mut contents = String::new();
+    File::open(bed_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| ErrorKind::FileReadError(bed_file.clone()))?;
+
+    let mut regions: Vec<GenomicInterval> = vec![];
+    for (line_num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with("#")
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        ensure!(
+            cols.len() >= 3,
+            "Line {} of target BED file has fewer than 3 columns (chrom, start, end).",
+            line_num + 1
+        );
+        let start = cols[1].parse::<u32>().chain_err(|| {
+            format!(
+                "Invalid start position on line {} of target BED file.",
+                line_num + 1
+            )
+        })?;
+        let end = cols[2].parse::<u32>().chain_err(|| {
+            format!(
+                "Invalid end position on line {} of target BED file.",
+                line_num + 1
+            )
+        })?;
+        ensure!(
+            end > start,
+            "End position must be greater than start position on line {} of target BED file.",
+            line_num + 1
+        );
+
+        if let Some(contig) = contigs.iter().find(|c| c.chrom == cols[0]) {
+            if start > contig.end_pos {
+                continue;
+            }
+            regions.push(GenomicInterval {
+                tid: contig.tid,
+                chrom: contig.chrom.clone(),
+                start_pos: start.saturating_sub(padding),
+                end_pos: (end - 1).saturating_add(padding).min(contig.end_pos),
+            });
+        }
+    }
+    ensure!(
+        !regions.is_empty(),
+        "Target BED file {} has no regions on the contigs of the BAM file.",
+        bed_file
+    );
+
+    regions.sort_by_key(|r| (r.tid, r.start_pos));
+    let mut merged: Vec<GenomicInterval> = vec![];
+    for region in regions {
+        if let Some(last) = merged.last_mut() {
+            if last.tid == region.tid && region.start_pos <= last.end_pos.saturating_add(1) {
+                last.end_pos = last.end_pos.max(region.end_pos);
+                continue;
+            }
+        }
+        merged.push(region);
+    }
+
+    Ok(merged)
+}
+
+/// Reads a BED file of tandem repeats (e.g. the simple repeats of Tandem Repeats Finder or an STR
+/// catalog)
+///
+/// Only the first 3 columns are used. Overlapping repeats (e.g. the same repeat reported with
+/// different periods) are merged. Lines starting with ```#```, ```track``` or ```browser``` are
+/// skipped, as are repeats on contigs that are not in the BAM file.
+///
+/// # Arguments
+/// - ```bed_file```: path to the BED file (0-based, half-open intervals)
+/// - ```bam_file```: BAM file used to convert contig names to target IDs
+///
+/// # Returns
+/// Returns a result containing the merged repeats in 0-based inclusive coordinates, sorted by
+/// position
+///
+/// # Errors
+/// - Error if the BED file cannot be read, or a line is not in the expected format
+pub fn parse_tandem_repeat_bed(
+    bed_file: &String,
+    bam_file: &String,
+) -> Result<Vec<GenomicInterval>> {
+    let target_names = parse_target_names(bam_file)?;
+    let mut contents = String::new();
+    File::open(bed_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| ErrorKind::FileReadError(bed_file.clone()))?;
+
+    let mut repeats: Vec<GenomicInterval> = vec![];
+    for (line_num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with("#")
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        ensure!(
+            cols.len() >= 3,
+            "Line {} of tandem repeat BED file has fewer than 3 columns (chrom, start, end).",
+            line_num + 1
+        );
+        let start = cols[1].parse::<u32>().chain_err(|| {
+            format!(
+                "Invalid start position on line {} of tandem repeat BED file.",
+                line_num + 1
+            )
+        })?;
+        let end = cols[2].parse::<u32>().chain_err(|| {
+            format!(
+                "Invalid end position on line {} of tandem repeat BED file.",
+                line_num + 1
+            )
+        })?;
+        ensure!(
+            end > start,
+            "End position must be greater than start position on line {} of tandem repeat BED file.",
+            line_num + 1
+        );
+
+        if let Some(tid) = target_names.iter().position(|t| t == cols[0]) {
+            repeats.push(GenomicInterval {
+                tid: tid as u32,
+                chrom: cols[0].to_string(),
+                start_pos: start,
+                end_pos: end - 1,
+            });
+        }
+    }
+
+    repeats.sort_by_key(|r| (r.tid, r.start_pos));
+    let mut merged: Vec<GenomicInterval> = vec![];
+    for repeat in repeats {
+        if let Some(last) = merged.last_mut() {
+            if last.tid == repeat.tid && repeat.start_pos <= last.end_pos {
+                last.end_pos = last.end_pos.max(repeat.end_pos);
+                continue;
+            }
+        }
+        merged.push(repeat);
+    }
+
+    Ok(merged)
+}
+
+/// Parameter overrides for a single contig, from the --contig_params table. Parameters that are
+/// None use the value that is set for the whole run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContigParams {
+    pub max_cov: Option<u32>,
+    pub min_alt_count: Option<usize>,
+    pub min_alt_frac: Option<f64>,
+    pub band_width: Option<usize>,
+}
+
+/// Returns the maximum read coverage on the contig ```tid```: its override from
+/// ```contig_params```, or ```max_cov``` if it has none
+pub fn contig_max_cov(contig_params: &Vec<ContigParams>, tid: u32, max_cov: u32) -> u32 {
+    contig_params
+        .get(tid as usize)
+        .and_then(|p| p.max_cov)
+        .unwrap_or(max_cov)
+}
+
+/// Reads a table of per-contig parameter overrides
+///
+/// The first line is a header that names the columns, starting with ```#contig```. The other
+/// columns can be any of ```max_cov```, ```min_alt_count```, ```min_alt_frac``` and
+/// ```band_width```, in any order. Each following line has a contig name and the values of its
+/// overrides, separated by tabs or spaces, where ```.``` means that the parameter is not
+/// overridden. Contigs that are not in the BAM file are skipped.
+///
+/// # Arguments
+/// - ```params_file```: path to the table
+/// - ```bam_file```: BAM file used to convert contig names to target IDs
+///
+/// # Returns
+/// Returns a result containing the ```ContigParams``` for each target ID of the BAM file
+///
+/// # Errors
+/// - Error if the file cannot be read, the header names an unknown parameter, a value cannot be
+///   parsed, or a contig is listed more than once
+pub fn parse_contig_params(params_file: &String, bam_file: &String) -> Result<Vec<ContigParams>> {
+    let target_names = parse_target_names(bam_file)?;
+    let mut contents = String::new();
+    File::open(params_file)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .chain_err(|| ErrorKind::FileReadError(params_file.clone()))?;
+
+    contig_params_from_str(&contents, &target_names)
+}
+
+/// Parses the contents of a table of per-contig parameter overrides (see
+/// ```parse_contig_params```), with the contig names of ```target_names```
+fn contig_params_from_str(contents: &str, target_names: &Vec<String>) -> Result<Vec<ContigParams>> {
+    let mut contig_params: Vec<ContigParams> = vec![ContigParams::default(); target_names.len()];
+    let mut seen: Vec<bool> = vec![false; target_names.len()];
+    let mut columns: Option<Vec<String>> = None;
+
+    for (line_num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+
+        if columns.is_none() {
+            ensure!(
+                cols[0] == "#contig",
+                "The contig parameters file must start with a header line beginning with #contig."
+            );
+            for name in &cols[1..] {
+                match *name {
+                    "max_cov" | "min_alt_count" | "min_alt_frac" | "band_width" => {}
+                    _ => bail!("Unknown parameter {} in contig parameters file header.", name),
+                }
+            }
+            columns = Some(cols[1..].iter().map(|c| c.to_string()).collect());
+            continue;
+        }
+        let header = columns
+            .as_ref()
+            .chain_err(|| "The contig parameters file has no header line.")?;
+
+        ensure!(
+            cols.len() == header.len() + 1,
+            "Line {} of contig parameters file has {} columns, but the header has {}.",
+            line_num + 1,
+            cols.len(),
+            header.len() + 1
+        );
+        let tid = match target_names.iter().position(|t| t == cols[0]) {
+            Some(tid) => tid,
+            None => continue,
+        };
+        ensure!(
+            !seen[tid],
+            "Contig {} is listed more than once in contig parameters file.",
+            cols[0]
+        );
+        seen[tid] = true;
+
+        let invalid = || {
+            format!(
+                "Invalid parameter value on line {} of contig parameters file.",
+                line_num + 1
+            )
+        };
+        for (name, value) in header.iter().zip(cols[1..].iter()) {
+            if *value == "." {
+                continue;
+            }
+            let params = &mut contig_params[tid];
+            match name.as_str() {
+                "max_cov" => params.max_cov = Some(value.parse::<u32>().chain_err(&invalid)?),
+                "min_alt_count" => {
+                    params.min_alt_count = Some(value.parse::<usize>().chain_err(&invalid)?)
+                }
+                "min_alt_frac" => {
+                    params.min_alt_frac = Some(value.parse::<f64>().chain_err(&invalid)?)
+                }
+                "band_width" => {
+                    params.band_width = Some(value.parse::<usize>().chain_err(&invalid)?)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    Ok(contig_params)
+}
+
+/// Reads the sample names (SM tags) from the read group (@RG) lines of a BAM file header.
+/// Sample names are returned in order of first appearance without duplicates.
+pub fn parse_bam_sample_names(bam_file: &String) -> Result<Vec<String>> {
+    if let Some(sample) = split_sample_bam_name(bam_file).1 {
+        return Ok(vec![sample.to_string()]);
+    }
+    let mut sample_names: Vec<String> = vec![];
+
+    // the read groups of all of the merged BAM files
+    for file in merged_bam_files(bam_file) {
+        let bam = open_bam(file)?;
+        let header_text = u8_to_string(bam.header().as_bytes())?;
+        for line in header_text.lines() {
+            if !line.starts_with("@RG") {
+                continue;
+            }
+            for field in line.split('\t') {
+                if field.starts_with("SM:") {
+                    let name = field[3..].to_string();
+                    if !sample_names.contains(&name) {
+                        sample_names.push(name);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(sample_names)
+}
+
+/// Returns the read group ID (RG tag) of a BAM record, if it has one
+pub fn read_group(record: &bam::Record) -> Option<String> {
+    match record.aux(b"RG") {
+        Some(bam::record::Aux::String(rg)) => Some(String::from_utf8_lossy(rg).into_owned()),
+        _ => None,
+    }
+}
+
 pub fn get_whole_genome_intervals(bam_file: &String) -> Result<Vec<GenomicInterval>> {
-    let bam = bam::Reader::from_path(bam_file).chain_err(|| ErrorKind::BamOpenError)?;
+    let bam = open_bam(bam_file)?;
     let header_view = bam.header();
     let target_names_dec: Vec<&[u8]> = header_view.target_names();
     let mut intervals: Vec<GenomicInterval> = vec![];
@@ -303,3 +1125,985 @@ pub fn get_interval_lst(
         &None => get_whole_genome_intervals(bam_file),
     }
 }
+
+/// A bgzip-compressed file, opened either for random access, with the uncompressed offsets
+/// translated through its ```.gzi``` index, or to be read from start to end
+pub struct BgzfFile {
+    inner: *mut htslib::BGZF,
+}
+
+impl BgzfFile {
+    pub fn open(path: &str) -> Result<BgzfFile> {
+        let c_path = CString::new(path).chain_err(|| format!("Invalid file name {}.", path))?;
+        let inner = unsafe { htslib::bgzf_open(c_path.as_ptr(), b"r\0".as_ptr() as *const c_char) };
+        ensure!(!inner.is_null(), "Could not open {}.", path);
+        // wrap it right away so that it is closed on the errors below
+        let bgzf = BgzfFile { inner: inner };
+        ensure!(
+            unsafe { htslib::bgzf_compression(bgzf.inner) } == 2,
+            "{} is compressed with gzip rather than bgzip, so it can't be read at random positions. Recompress it with bgzip.",
+            path
+        );
+        ensure!(
+            unsafe { htslib::bgzf_index_load(bgzf.inner, c_path.as_ptr(), b".gzi\0".as_ptr() as *const c_char) } == 0,
+            "Could not load the bgzip index {}.gzi. Index the bgzip-compressed FASTA with samtools faidx.",
+            path
+        );
+        Ok(bgzf)
+    }
+
+    /// Opens a file to be read from start to end, which doesn't need an index. Files compressed
+    /// with gzip, and uncompressed files, are read as well.
+    pub fn open_stream(path: &str) -> Result<BgzfFile> {
+        let c_path = CString::new(path).chain_err(|| format!("Invalid file name {}.", path))?;
+        let inner = unsafe { htslib::bgzf_open(c_path.as_ptr(), b"r\0".as_ptr() as *const c_char) };
+        ensure!(!inner.is_null(), "Could not open {}.", path);
+        Ok(BgzfFile { inner: inner })
+    }
+}
+
+impl Drop for BgzfFile {
+    fn drop(&mut self) {
+        unsafe {
+            htslib::bgzf_close(self.inner);
+        }
+    }
+}
+
+impl IoRead for BgzfFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { htslib::bgzf_read(self.inner, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "Error reading a bgzip-compressed file."));
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Seek for BgzfFile {
+    // only seeking relative to the start or the current position is supported, which is all
+    // that the indexed FASTA reader needs
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(delta) => (unsafe { htslib::bgzf_utell(self.inner) }) as i64 + delta,
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Can't seek from the end of a bgzip-compressed file.",
+                ))
+            }
+        };
+        if offset < 0 || unsafe { htslib::bgzf_useek(self.inner, offset as c_long, 0) } < 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "Error seeking in a bgzip-compressed file."));
+        }
+        Ok(offset as u64)
+    }
+}
+
+/// A file written with bgzip compression, which any gzip reader can decompress
+pub struct BgzfWriter {
+    inner: *mut htslib::BGZF,
+}
+
+impl BgzfWriter {
+    pub fn create(path: &str) -> Result<BgzfWriter> {
+        let c_path = CString::new(path).chain_err(|| format!("Invalid file name {}.", path))?;
+        let inner = unsafe { htslib::bgzf_open(c_path.as_ptr(), b"w\0".as_ptr() as *const c_char) };
+        ensure!(!inner.is_null(), ErrorKind::CreateFileError(path.to_string()));
+        Ok(BgzfWriter { inner: inner })
+    }
+}
+
+impl Drop for BgzfWriter {
+    fn drop(&mut self) {
+        unsafe {
+            htslib::bgzf_close(self.inner);
+        }
+    }
+}
+
+impl Write for BgzfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { htslib::bgzf_write(self.inner, buf.as_ptr() as *const c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "Error writing a bgzip-compressed file."));
+        }
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if unsafe { htslib::bgzf_flush(self.inner) } < 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "Error writing a bgzip-compressed file."));
+        }
+        Ok(())
+    }
+}
+
+/// The reference FASTA file, either uncompressed or compressed with bgzip. An uncompressed file
+/// is memory-mapped, so that the windows of the reference that are read are served from the page
+/// cache (and shared between processes) rather than copied into a buffer of each reader.
+pub enum FastaFile {
+    Plain(File),
+    Mapped(io::Cursor<Mmap>),
+    Bgzf(BgzfFile),
+}
+
+impl IoRead for FastaFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut FastaFile::Plain(ref mut f) => f.read(buf),
+            &mut FastaFile::Mapped(ref mut f) => f.read(buf),
+            &mut FastaFile::Bgzf(ref mut f) => f.read(buf),
+        }
+    }
+}
+
+impl Seek for FastaFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            &mut FastaFile::Plain(ref mut f) => f.seek(pos),
+            &mut FastaFile::Mapped(ref mut f) => f.seek(pos),
+            &mut FastaFile::Bgzf(ref mut f) => f.seek(pos),
+        }
+    }
+}
+
+pub type FastaReader = fasta::IndexedReader<FastaFile>;
+
+/// Whether a file starts with the gzip magic bytes, as bgzip-compressed files do
+pub fn is_gzipped(path: &str) -> Result<bool> {
+    let mut magic = [0u8; 2];
+    let mut file = File::open(path).chain_err(|| format!("Could not open {}.", path))?;
+    let n = file.read(&mut magic).chain_err(|| format!("Could not read {}.", path))?;
+    Ok(n == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Opens an indexed reference FASTA, which is either uncompressed with a ```.fai``` index or
+/// compressed with bgzip with both a ```.fai``` and a ```.gzi``` index (as written by
+/// ```samtools faidx``` for a ```.fa.gz```).
+///
+/// # Errors
+/// Fails with ```IndexedFastaOpenError``` if the file or one of its indices can't be read.
+pub fn open_fasta(fasta_file: &str) -> Result<FastaReader> {
+    let open_error = || ErrorKind::IndexedFastaOpenError(fasta_file.to_string());
+    let index = fasta::Index::with_fasta_file(&fasta_file)
+        .chain_err(|| format!("Could not read the FASTA index {}.fai.", fasta_file))
+        .chain_err(&open_error)?;
+    let file = if is_gzipped(fasta_file).chain_err(&open_error)? {
+        FastaFile::Bgzf(BgzfFile::open(fasta_file).chain_err(&open_error)?)
+    } else {
+        let f = File::open(fasta_file).chain_err(&open_error)?;
+        // fall back to reading the file if it can't be mapped (e.g. on some network file systems)
+        match unsafe { Mmap::map(&f) } {
+            Ok(mmap) => FastaFile::Mapped(io::Cursor::new(mmap)),
+            Err(_) => FastaFile::Plain(f),
+        }
+    };
+    Ok(fasta::IndexedReader::with_index(file, index))
+}
+
+/// Reads the bases ```start..end``` (0-based, exclusive) of contig ```chrom``` of the reference
+/// into ```seq```
+///
+/// # Errors
+/// Fails with ```IndexedFastaRegionReadError``` naming the region if it can't be read.
+pub fn read_fasta_region(
+    fasta: &mut FastaReader,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    seq: &mut Vec<u8>,
+) -> Result<()> {
+    let region_error =
+        || ErrorKind::IndexedFastaRegionReadError(format!("{}:{}-{}", chrom, start + 1, end));
+    fasta.fetch(chrom, start, end).chain_err(&region_error)?;
+    fasta.read(seq).chain_err(&region_error)?;
+    Ok(())
+}
+
+/// The number of bases that ```RefSeqReader``` reads ahead of the requested window
+pub static REF_READAHEAD: usize = 1000000;
+
+/// Reads the reference sequence on demand, holding only a window of one contig in memory instead
+/// of whole contigs. The window is read again when a request isn't covered by it, with
+/// ```REF_READAHEAD``` bases past the request, so that requests moving along a contig (as for
+/// the sorted reads or a pileup) are usually served from the same window.
+pub struct RefSeqReader {
+    fasta: FastaReader,
+    contig_lens: HashMap<String, usize>,
+    chrom: String,
+    window: RefSeqWindow,
+}
+
+impl RefSeqReader {
+    pub fn open(fasta_file: &str) -> Result<RefSeqReader> {
+        let fasta = open_fasta(fasta_file)?;
+        let contig_lens: HashMap<String, usize> = fasta
+            .index
+            .sequences()
+            .into_iter()
+            .map(|s| (s.name, s.len as usize))
+            .collect();
+        Ok(RefSeqReader {
+            fasta: fasta,
+            contig_lens: contig_lens,
+            chrom: String::new(),
+            window: RefSeqWindow::whole_contig(vec![]),
+        })
+    }
+
+    /// the length of a contig of the reference
+    pub fn contig_len(&self, chrom: &str) -> Result<usize> {
+        match self.contig_lens.get(chrom) {
+            Some(&len) => Ok(len),
+            None => bail!("Contig {} is not in the reference FASTA.", chrom),
+        }
+    }
+
+    /// Returns a window of the reference that holds the bases ```start..end``` of ```chrom```
+    /// (with ```end``` clipped to the length of the contig).
+    ///
+    /// # Errors
+    /// Fails if the contig is not in the reference, or the FASTA file can't be read.
+    pub fn window(&mut self, chrom: &str, start: usize, end: usize) -> Result<&RefSeqWindow> {
+        let contig_len = self.contig_len(chrom)?;
+        let end = end.min(contig_len);
+        let start = start.min(end);
+        if self.chrom != chrom || !self.window.covers(start, end) {
+            let window_end = end.max((start + REF_READAHEAD).min(contig_len));
+            let mut ref_seq_u8: Vec<u8> = vec![];
+            read_fasta_region(
+                &mut self.fasta,
+                chrom,
+                start as u64,
+                window_end as u64,
+                &mut ref_seq_u8,
+            )?;
+            self.chrom = chrom.to_string();
+            self.window = RefSeqWindow {
+                start: start,
+                seq: dna_vec(&ref_seq_u8),
+                contig_len: contig_len,
+            };
+        }
+        Ok(&self.window)
+    }
+}
+
+/// Whether a BAM path is a URL such as ```http://host/sample.bam```, which htslib reads remotely
+/// with ranged requests rather than from the local file system
+pub fn is_url(path: &str) -> bool {
+    match path.find("://") {
+        Some(i) => {
+            i > 0
+                && path[..i]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+// the error message for a remote BAM file that can't be opened
+fn remote_bam_error(bam_file: &str) -> String {
+    format!(
+        "Could not open {}. Remote BAM files are read with htslib, which supports http:// and \
+         ftp:// URLs; https://, s3:// and gs:// need an htslib built with libcurl, which is not \
+         the case for the htslib bundled with longshot.",
+        bam_file
+    )
+}
+
+fn parse_bam_url(bam_file: &str) -> Result<Url> {
+    Url::parse(bam_file).chain_err(|| format!("Invalid URL {}.", bam_file))
+}
+
+/// The separator between a BAM argument and the sample whose reads are used (see
+/// ```sample_bam_name```)
+static SAMPLE_BAM_SEPARATOR: &str = "##sample##";
+
+/// The name for the reads of one sample of a multi-sample BAM file (or of merged BAM files, see
+/// ```merged_bam_files```), e.g. of a barcoded nanopore run: the reads of the read groups of the
+/// other samples (and the reads without a read group) are skipped when the BAM file is opened with
+/// ```open_indexed_bam```. The sample is appended to the file name like htslib's ```##idx##```
+/// suffix for index files.
+pub fn sample_bam_name(bam_file: &str, sample: &str) -> String {
+    format!("{}{}{}", bam_file, SAMPLE_BAM_SEPARATOR, sample)
+}
+
+/// Splits a BAM name into the BAM argument and the sample whose reads are used, if any (see
+/// ```sample_bam_name```)
+pub fn split_sample_bam_name(bam_file: &str) -> (&str, Option<&str>) {
+    match bam_file.rfind(SAMPLE_BAM_SEPARATOR) {
+        Some(i) => (
+            &bam_file[..i],
+            Some(&bam_file[i + SAMPLE_BAM_SEPARATOR.len()..]),
+        ),
+        None => (bam_file, None),
+    }
+}
+
+/// The BAM files of a BAM argument. Several BAM files of the same sample (e.g. of different
+/// flowcells) can be given as one comma-separated argument, and their reads are merged on the fly
+/// (see ```IndexedBamReader```).
+pub fn merged_bam_files(bam_file: &str) -> Vec<&str> {
+    split_sample_bam_name(bam_file).0.split(',').collect()
+}
+
+/// The IDs of the read groups (@RG lines) of a BAM header whose sample (SM tag) is ```sample```
+fn sample_read_groups(header: &bam::HeaderView, sample: &str) -> Result<Vec<String>> {
+    let header_text = u8_to_string(header.as_bytes())?;
+    let mut read_groups: Vec<String> = vec![];
+    for line in header_text.lines() {
+        if !line.starts_with("@RG") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.iter().any(|f| f.starts_with("SM:") && &f[3..] == sample) {
+            if let Some(id) = fields.iter().find(|f| f.starts_with("ID:")) {
+                read_groups.push(id[3..].to_string());
+            }
+        }
+    }
+    Ok(read_groups)
+}
+
+/// Opens a BAM file, which is either a local path or a URL (see ```is_url```). Of several merged
+/// BAM files (see ```merged_bam_files```), only the first is opened, e.g. to read the header.
+///
+/// # Errors
+/// Fails with ```BamOpenError``` if the file can't be opened.
+pub fn open_bam(bam_file: &str) -> Result<bam::Reader> {
+    let bam_file = merged_bam_files(bam_file)[0];
+    if is_url(bam_file) {
+        bam::Reader::from_url(&parse_bam_url(bam_file)?)
+            .chain_err(|| remote_bam_error(bam_file))
+            .chain_err(|| ErrorKind::BamOpenError(bam_file.to_string()))
+    } else {
+        bam::Reader::from_path(bam_file)
+            .chain_err(|| ErrorKind::BamOpenError(bam_file.to_string()))
+    }
+}
+
+/// Opens an indexed BAM file, which is either a local path or a URL (see ```is_url```). For a
+/// URL, htslib fetches the index from the same URL with ```.bai``` appended, and only the blocks
+/// of the BAM file that overlap the fetched regions are downloaded.
+///
+/// Several comma-separated BAM files (see ```merged_bam_files```) are opened together, and their
+/// reads are merged in the order of their positions. For the name of a sample of the BAM files
+/// (see ```sample_bam_name```), only the reads of that sample are read.
+///
+/// # Errors
+/// Fails with ```IndexedBamOpenError``` if a file or its index can't be opened, if the merged BAM
+/// files don't have the same contigs in the same order, and if none of them has a read group of
+/// the sample.
+pub fn open_indexed_bam(bam_file: &str) -> Result<IndexedBamReader> {
+    let sample = split_sample_bam_name(bam_file).1;
+    let mut readers: Vec<bam::IndexedReader> = vec![];
+    let mut read_groups: Vec<Vec<String>> = vec![];
+    for file in merged_bam_files(bam_file) {
+        let reader = if is_url(file) {
+            bam::IndexedReader::from_url(&parse_bam_url(file)?)
+                .chain_err(|| remote_bam_error(file))
+                .chain_err(|| ErrorKind::IndexedBamOpenError(file.to_string()))?
+        } else {
+            bam::IndexedReader::from_path(file)
+                .chain_err(|| ErrorKind::IndexedBamOpenError(file.to_string()))?
+        };
+        if let Some(first) = readers.first() {
+            ensure!(
+                reader.header().target_names() == first.header().target_names(),
+                "The merged BAM files {} do not have the same contigs in the same order.",
+                bam_file
+            );
+        }
+        if let Some(sample) = sample {
+            read_groups.push(sample_read_groups(reader.header(), sample)?);
+        }
+        readers.push(reader);
+    }
+    if let Some(sample) = sample {
+        ensure!(
+            read_groups.iter().any(|rgs| !rgs.is_empty()),
+            "No read group of sample {} in {}.",
+            sample,
+            split_sample_bam_name(bam_file).0
+        );
+    }
+    let n_readers = readers.len();
+    Ok(IndexedBamReader {
+        readers: readers,
+        files: merged_bam_files(bam_file)
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+        region: String::new(),
+        last_reads: vec![vec![]; n_readers],
+        next_records: (0..n_readers).map(|_| None).collect(),
+        primed: false,
+        sample_read_groups: sample.map(|_| read_groups),
+    })
+}
+
+/// An indexed BAM file, or several indexed BAM files of the same sample whose reads are merged on
+/// the fly: the reads of a fetched region are returned in the order of their positions, as if
+/// the files had been merged with ```samtools merge```. The header is that of the first file.
+pub struct IndexedBamReader {
+    readers: Vec<bam::IndexedReader>,
+    /// the name of each file, for error messages
+    files: Vec<String>,
+    /// the fetched region (e.g. ```chr1:1001-2000```), for error messages
+    region: String,
+    /// the name of the last read that was read from each file in the fetched region, for error
+    /// messages
+    last_reads: Vec<Vec<u8>>,
+    /// the next record of each file in the fetched region, or None at the end of the region
+    next_records: Vec<Option<bam::Record>>,
+    /// whether the next records were read since the last fetch
+    primed: bool,
+    /// with a selected sample, the IDs of the read groups of the sample in each file
+    sample_read_groups: Option<Vec<Vec<String>>>,
+}
+
+impl IndexedBamReader {
+    /// Seeks to the region from ```beg``` to ```end``` (0-based, exclusive) of contig ```tid```
+    ///
+    /// # Errors
+    /// Fails with ```IndexedBamRegionFetchError``` if the region can't be fetched from a file,
+    /// e.g. because the contig is not in its index.
+    pub fn fetch(&mut self, tid: u32, beg: u32, end: u32) -> Result<()> {
+        let contig = match self.header().target_names().get(tid as usize) {
+            Some(name) => String::from_utf8_lossy(name).into_owned(),
+            None => tid.to_string(),
+        };
+        self.region = format!("{}:{}-{}", contig, beg + 1, end);
+        for i in 0..self.readers.len() {
+            self.readers[i].fetch(tid, beg, end).chain_err(|| {
+                ErrorKind::IndexedBamRegionFetchError(self.files[i].clone(), self.region.clone())
+            })?;
+            self.last_reads[i].clear();
+        }
+        self.primed = false;
+        Ok(())
+    }
+
+    /// Reads the next record of the selected sample (if any) from file ```i``` into ```record```
+    fn read_file(&mut self, i: usize, record: &mut bam::Record) -> Result<bool> {
+        loop {
+            let found = self.readers[i].read(record).chain_err(|| {
+                let previous_read = if self.last_reads[i].is_empty() {
+                    "(none)".to_string()
+                } else {
+                    String::from_utf8_lossy(&self.last_reads[i]).into_owned()
+                };
+                ErrorKind::CorruptBamRecord(
+                    self.files[i].clone(),
+                    self.region.clone(),
+                    previous_read,
+                )
+            })?;
+            if !found {
+                return Ok(false);
+            }
+            self.last_reads[i].clear();
+            self.last_reads[i].extend_from_slice(record.qname());
+            let read_groups = match self.sample_read_groups {
+                Some(ref rgs) => &rgs[i],
+                None => return Ok(true),
+            };
+            if let Some(bam::record::Aux::String(rg)) = record.aux(b"RG") {
+                if read_groups.iter().any(|id| id.as_bytes() == rg) {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Reads the next record of the fetched region into ```record```. Returns ```Ok(false)``` at
+    /// the end of the region.
+    ///
+    /// # Errors
+    /// Fails with ```CorruptBamRecord``` if a record can't be decoded, naming the file, the
+    /// fetched region and the last read before the record.
+    pub fn read(&mut self, record: &mut bam::Record) -> Result<bool> {
+        if self.readers.len() == 1 {
+            return self.read_file(0, record);
+        }
+        if !self.primed {
+            for i in 0..self.readers.len() {
+                let mut next = bam::Record::new();
+                self.next_records[i] = if self.read_file(i, &mut next)? {
+                    Some(next)
+                } else {
+                    None
+                };
+            }
+            self.primed = true;
+        }
+
+        // the file whose next record has the smallest position
+        let mut first: Option<(usize, (i32, i32))> = None;
+        for (i, next) in self.next_records.iter().enumerate() {
+            if let Some(ref r) = *next {
+                let pos = (r.tid(), r.pos());
+                if first.map_or(true, |(_, first_pos)| pos < first_pos) {
+                    first = Some((i, pos));
+                }
+            }
+        }
+        let i = match first {
+            Some((i, _)) => i,
+            None => return Ok(false),
+        };
+        let mut next = match self.next_records[i].take() {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+        // copied rather than swapped, because pileups read into a record owned by htslib
+        unsafe {
+            htslib::bam_copy1(record.inner_mut(), next.inner_mut());
+        }
+        if self.read_file(i, &mut next)? {
+            self.next_records[i] = Some(next);
+        }
+        Ok(true)
+    }
+
+    /// Iterator over the records of the fetched region
+    pub fn records(&mut self) -> IndexedBamRecords {
+        IndexedBamRecords { reader: self }
+    }
+
+    /// Iterator over the pileups of the fetched region
+    pub fn pileup(&mut self) -> bam::pileup::Pileups<bam::IndexedReader> {
+        if self.readers.len() == 1 && self.sample_read_groups.is_none() {
+            return self.readers[0].pileup();
+        }
+        let data = self as *mut IndexedBamReader as *mut c_void;
+        let itr = unsafe { htslib::bam_plp_init(Some(filtered_pileup_read), data) };
+        // the pileups only keep the first reader borrowed, the reads come from filtered_pileup_read
+        bam::pileup::Pileups::new(&mut self.readers[0], itr)
+    }
+
+    /// The header of the (first) BAM file
+    pub fn header(&self) -> &bam::HeaderView {
+        self.readers[0].header()
+    }
+}
+
+/// Reads the next (merged or sample-filtered) record of an ```IndexedBamReader``` for htslib's
+/// pileup
+extern "C" fn filtered_pileup_read(data: *mut c_void, record: *mut htslib::bam1_t) -> i32 {
+    let reader = unsafe { &mut *(data as *mut IndexedBamReader) };
+    // the record is owned by htslib, so it isn't freed when the wrapper is dropped
+    let mut record = bam::Record::from_inner(record);
+    match reader.read(&mut record) {
+        Ok(true) => 0,
+        Ok(false) => -1,
+        Err(_) => -2,
+    }
+}
+
+/// Iterator over the records of an ```IndexedBamReader```
+pub struct IndexedBamRecords<'a> {
+    reader: &'a mut IndexedBamReader,
+}
+
+impl<'a> Iterator for IndexedBamRecords<'a> {
+    type Item = Result<bam::Record>;
+
+    fn next(&mut self) -> Option<Result<bam::Record>> {
+        let mut record = bam::Record::new();
+        match self.reader.read(&mut record) {
+            Ok(true) => Some(Ok(record)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Checks that a BAM file is indexed, and that each contig of ```interval_lst``` is in the FASTA
+/// index with the same length as in the BAM header. Mismatches are otherwise only found when
+/// the reads of the contig are realigned, often hours into a run.
+pub fn check_reference_consistency(
+    bam_file: &String,
+    fasta_file: &String,
+    interval_lst: &Vec<GenomicInterval>,
+) -> Result<()> {
+    open_indexed_bam(bam_file).chain_err(|| format!("{} is not indexed.", bam_file))?;
+    let fasta = open_fasta(fasta_file)?;
+    let fasta_lens: HashMap<String, u64> = fasta
+        .index
+        .sequences()
+        .into_iter()
+        .map(|s| (s.name, s.len))
+        .collect();
+
+    let bam_contigs = get_whole_genome_intervals(bam_file)?;
+    let mut checked = vec![false; bam_contigs.len()];
+    for iv in interval_lst {
+        if checked[iv.tid as usize] {
+            continue;
+        }
+        checked[iv.tid as usize] = true;
+        let bam_len = bam_contigs[iv.tid as usize].end_pos as u64 + 1;
+        match fasta_lens.get(&iv.chrom) {
+            Some(&len) => ensure!(
+                len == bam_len,
+                "Contig {} has length {} in {} but {} in {}. Are they aligned to the same reference?",
+                iv.chrom,
+                bam_len,
+                bam_file,
+                len,
+                fasta_file
+            ),
+            None => bail!("Contig {} of {} is not in {}.", iv.chrom, bam_file, fasta_file),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that an output file can be created: its directory exists and is not read-only.
+pub fn check_output_writable(output_file: &str) -> Result<()> {
+    let dir = match Path::new(output_file).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let metadata = fs::metadata(&dir)
+        .chain_err(|| format!("Output directory {} does not exist.", dir.display()))?;
+    ensure!(
+        metadata.is_dir() && !metadata.permissions().readonly(),
+        "Output directory {} is not writable.",
+        dir.display()
+    );
+    Ok(())
+}
+
+/// the total number of reference bases in a list of intervals, for progress reports
+pub fn interval_lst_len(interval_lst: &Vec<GenomicInterval>) -> usize {
+    interval_lst
+        .iter()
+        .map(|iv| (iv.end_pos - iv.start_pos + 1) as usize)
+        .sum()
+}
+
+/// the length of the windows that are called in quick-look mode
+pub static QUICK_LOOK_WINDOW_SIZE: u32 = 100000;
+/// the random seed for sampling the quick-look windows, so that repeated runs call the same windows
+pub static QUICK_LOOK_SEED: usize = 0;
+
+/// Splits a list of intervals into consecutive windows of ```window_size``` bases (the last window
+/// of each interval may be shorter). Returns the intervals unchanged if ```window_size``` is 0.
+pub fn split_into_windows(
+    interval_lst: &Vec<GenomicInterval>,
+    window_size: u32,
+) -> Vec<GenomicInterval> {
+    if window_size == 0 {
+        return interval_lst.clone();
+    }
+    let mut windows: Vec<GenomicInterval> = vec![];
+    for iv in interval_lst {
+        let mut start = iv.start_pos;
+        while start <= iv.end_pos {
+            let end = if iv.end_pos - start >= window_size {
+                start + window_size - 1
+            } else {
+                iv.end_pos
+            };
+            windows.push(GenomicInterval {
+                tid: iv.tid,
+                chrom: iv.chrom.clone(),
+                start_pos: start,
+                end_pos: end,
+            });
+            if end == iv.end_pos {
+                break;
+            }
+            start = end + 1;
+        }
+    }
+    windows
+}
+
+/// Splits a list of intervals into windows of ```window_size``` bases and returns ```n``` of them,
+/// sampled at random with the given seed and sorted by position. Returns all of the windows if
+/// there are fewer than ```n```.
+pub fn sample_windows(
+    interval_lst: &Vec<GenomicInterval>,
+    n: usize,
+    window_size: u32,
+    seed: usize,
+) -> Vec<GenomicInterval> {
+    let mut windows = split_into_windows(interval_lst, window_size);
+
+    let mut rng: StdRng = StdRng::from_seed(&[seed]);
+    rng.shuffle(&mut windows);
+    windows.truncate(n);
+    windows.sort_by_key(|w| (w.tid, w.start_pos));
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_dna_vec() {
+        let v: String = dna_vec(b"acgtNRyk-").iter().collect();
+        assert_eq!(v, "ACGTNNNNN");
+    }
+
+    // writes ```data``` as a bgzip-compressed file with a .gzi index, like ```bgzip -i```
+    fn write_bgzf(path: &str, data: &[u8]) {
+        let c_path = CString::new(path).unwrap();
+        unsafe {
+            let fp = htslib::bgzf_open(c_path.as_ptr(), b"w\0".as_ptr() as *const c_char);
+            assert!(!fp.is_null());
+            assert_eq!(htslib::bgzf_index_build_init(fp), 0);
+            assert_eq!(
+                htslib::bgzf_write(fp, data.as_ptr() as *const c_void, data.len()) as usize,
+                data.len()
+            );
+            assert_eq!(
+                htslib::bgzf_index_dump(fp, c_path.as_ptr(), b".gzi\0".as_ptr() as *const c_char),
+                0
+            );
+            assert_eq!(htslib::bgzf_close(fp), 0);
+        }
+    }
+
+    #[test]
+    fn test_position_hash() {
+        assert_eq!(position_hash(3, 1, 1000), position_hash(3, 1, 1000));
+        assert!(position_hash(3, 1, 1000) != position_hash(4, 1, 1000));
+        assert!(position_hash(3, 1, 1000) != position_hash(3, 2, 1000));
+        // the parities of the hashes of neighboring positions are balanced
+        let odd = (0..10000).filter(|&p| position_hash(0, 0, p) % 2 == 1).count();
+        assert!(odd > 4800 && odd < 5200);
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("http://example.com/sample.bam"));
+        assert!(is_url("ftp://example.com/data/sample.bam"));
+        assert!(is_url("s3://bucket/sample.bam"));
+        assert!(!is_url("sample.bam"));
+        assert!(!is_url("/data/sample.bam"));
+        assert!(!is_url("://sample.bam"));
+        assert!(!is_url("/data/run:1/sample.bam"));
+        assert!(!is_url("/data/a://b.bam"));
+    }
+
+    #[test]
+    fn test_open_fasta_bgzf() {
+        // a sequence spanning several BGZF blocks (of 64kb each)
+        let seq: Vec<u8> = (0..200000).map(|i| b"ACGGTCATTGCA"[(i * 7 + i / 13) % 12]).collect();
+        let mut fasta_str = b">chr1\n".to_vec();
+        for line in seq.chunks(60) {
+            fasta_str.extend_from_slice(line);
+            fasta_str.push(b'\n');
+        }
+        let fai = format!("chr1\t{}\t6\t60\t61\n", seq.len());
+
+        let prefix = env::temp_dir().join(format!("longshot_test_fasta_{}", std::process::id()));
+        let plain = format!("{}.fa", prefix.display());
+        let gz = format!("{}.fa.gz", prefix.display());
+        fs::write(&plain, &fasta_str).unwrap();
+        fs::write(format!("{}.fai", plain), &fai).unwrap();
+        write_bgzf(&gz, &fasta_str);
+        fs::write(format!("{}.fai", gz), &fai).unwrap();
+
+        assert!(!is_gzipped(&plain).unwrap());
+        assert!(is_gzipped(&gz).unwrap());
+
+        let mut plain_reader = open_fasta(&plain).unwrap();
+        let mut gz_reader = open_fasta(&gz).unwrap();
+        for &(start, end) in &[(0, 100), (65530, 65600), (150000, 200000), (70000, 70001)] {
+            let mut plain_seq: Vec<u8> = vec![];
+            let mut gz_seq: Vec<u8> = vec![];
+            plain_reader.fetch("chr1", start, end).unwrap();
+            plain_reader.read(&mut plain_seq).unwrap();
+            gz_reader.fetch("chr1", start, end).unwrap();
+            gz_reader.read(&mut gz_seq).unwrap();
+            assert_eq!(gz_seq, &seq[start as usize..end as usize]);
+            assert_eq!(gz_seq, plain_seq);
+        }
+
+        for path in &[&plain, &gz] {
+            let _ = fs::remove_file(path);
+            let _ = fs::remove_file(format!("{}.fai", path));
+        }
+        let _ = fs::remove_file(format!("{}.gzi", gz));
+    }
+
+    #[test]
+    fn test_ref_seq_reader() {
+        let seq: Vec<u8> = (0..3000).map(|i| b"ACGTTGCA"[(i * 5 + i / 11) % 8]).collect();
+        let mut fasta_str = b">chr1\n".to_vec();
+        for line in seq.chunks(60) {
+            fasta_str.extend_from_slice(line);
+            fasta_str.push(b'\n');
+        }
+        fasta_str.extend_from_slice(b">chr2\nacgtn\n");
+        let fai = format!(
+            "chr1\t{}\t6\t60\t61\nchr2\t5\t{}\t5\t6\n",
+            seq.len(),
+            fasta_str.len() - 6
+        );
+        let fa = format!(
+            "{}.fa",
+            env::temp_dir()
+                .join(format!("longshot_test_ref_reader_{}", std::process::id()))
+                .display()
+        );
+        fs::write(&fa, &fasta_str).unwrap();
+        fs::write(format!("{}.fai", fa), &fai).unwrap();
+
+        let mut reader = RefSeqReader::open(&fa).unwrap();
+        assert_eq!(reader.contig_len("chr1").unwrap(), 3000);
+        assert!(reader.contig_len("chr3").is_err());
+        for &(start, end) in &[(100, 200), (150, 160), (2990, 3000), (10, 20)] {
+            let window = reader.window("chr1", start, end).unwrap();
+            assert_eq!(window.len(), 3000);
+            for pos in start..end {
+                assert_eq!(window[pos], seq[pos] as char);
+            }
+        }
+        // the end is clipped to the contig, and the sequence is upper case
+        let window = reader.window("chr2", 2, 100).unwrap();
+        assert_eq!(&window[2..5], &['G', 'T', 'N']);
+
+        let _ = fs::remove_file(&fa);
+        let _ = fs::remove_file(format!("{}.fai", fa));
+    }
+
+    #[test]
+    fn test_merged_indexed_bam() {
+        let bam_file = "example_data/pacbio_reads_30x.bam";
+        let merged = format!("{},{}", bam_file, bam_file);
+        assert_eq!(merged_bam_files(&merged), vec![bam_file, bam_file]);
+
+        let mut single = open_indexed_bam(bam_file).unwrap();
+        let mut double = open_indexed_bam(&merged).unwrap();
+        single.fetch(0, 50000, 51000).unwrap();
+        double.fetch(0, 50000, 51000).unwrap();
+        let single_pos: Vec<i32> = single.records().map(|r| r.unwrap().pos()).collect();
+        let double_pos: Vec<i32> = double.records().map(|r| r.unwrap().pos()).collect();
+        assert!(!single_pos.is_empty());
+        let mut expected: Vec<i32> = single_pos.iter().flat_map(|&p| vec![p, p]).collect();
+        expected.sort();
+        assert_eq!(double_pos, expected);
+
+        single.fetch(0, 50000, 50001).unwrap();
+        double.fetch(0, 50000, 50001).unwrap();
+        let depth = |p: bam::pileup::Pileup| (p.pos(), p.depth());
+        let single_depths: Vec<(u32, u32)> = single.pileup().map(|p| depth(p.unwrap())).collect();
+        let double_depths: Vec<(u32, u32)> = double.pileup().map(|p| depth(p.unwrap())).collect();
+        assert_eq!(
+            double_depths,
+            single_depths
+                .iter()
+                .map(|&(pos, d)| (pos, 2 * d))
+                .collect::<Vec<(u32, u32)>>()
+        );
+    }
+
+    #[test]
+    fn test_contig_params_from_str() {
+        let target_names = vec!["chr1".to_string(), "chrM".to_string(), "chrX".to_string()];
+        let contents = "#contig\tmax_cov\tmin_alt_frac\n\
+                        chrM\t5000\t0.05\n\
+                        chrX\t.\t0.2\n\
+                        chrUn_gl000220\t100\t.\n";
+        let contig_params = contig_params_from_str(contents, &target_names).unwrap();
+        assert_eq!(contig_params.len(), 3);
+        assert_eq!(contig_params[0], ContigParams::default());
+        assert_eq!(contig_params[1].max_cov, Some(5000));
+        assert_eq!(contig_params[1].min_alt_frac, Some(0.05));
+        assert_eq!(contig_params[1].min_alt_count, None);
+        // '.' keeps the value of the whole run
+        assert_eq!(contig_params[2].max_cov, None);
+        assert_eq!(contig_params[2].min_alt_frac, Some(0.2));
+        // the contig that is not in the BAM file is skipped
+        assert!(contig_params.iter().all(|p| p.max_cov != Some(100)));
+
+        // a line with a missing column, a value that can't be parsed, a contig that is listed
+        // twice, an unknown parameter or a missing header are errors
+        for contents in &[
+            "#contig\tmax_cov\tband_width\nchrM\t5000\n",
+            "#contig\tmax_cov\nchrM\thigh\n",
+            "#contig\tmax_cov\nchrM\t5000\nchrM\t6000\n",
+            "#contig\tmax_depth\nchrM\t5000\n",
+            "chrM\t5000\n",
+        ] {
+            assert!(contig_params_from_str(contents, &target_names).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(parse_log_level("warn").unwrap(), LevelFilter::Warn);
+        assert_eq!(parse_log_level("off").unwrap(), LevelFilter::Off);
+        assert!(parse_log_level("verbose").is_err());
+        for name in LOG_LEVEL_NAMES.iter() {
+            assert!(parse_log_level(name).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_split_into_windows() {
+        let interval_lst = vec![
+            GenomicInterval {
+                tid: 0,
+                chrom: "chr1".to_string(),
+                start_pos: 0,
+                end_pos: 249,
+            },
+            GenomicInterval {
+                tid: 1,
+                chrom: "chr2".to_string(),
+                start_pos: 10,
+                end_pos: 59,
+            },
+        ];
+        let windows = split_into_windows(&interval_lst, 100);
+        let bounds: Vec<(u32, u32, u32)> = windows
+            .iter()
+            .map(|w| (w.tid, w.start_pos, w.end_pos))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![(0, 0, 99), (0, 100, 199), (0, 200, 249), (1, 10, 59)]
+        );
+        assert_eq!(windows[3].chrom, "chr2");
+        // a window size of 0 keeps the intervals whole
+        assert_eq!(split_into_windows(&interval_lst, 0).len(), 2);
+    }
+
+    #[test]
+    fn test_sample_bam_name() {
+        let name = sample_bam_name("fc1.bam,fc2.bam", "barcode01");
+        assert_eq!(
+            split_sample_bam_name(&name),
+            ("fc1.bam,fc2.bam", Some("barcode01"))
+        );
+        assert_eq!(merged_bam_files(&name), vec!["fc1.bam", "fc2.bam"]);
+        assert_eq!(split_sample_bam_name("sample.bam"), ("sample.bam", None));
+
+        let header = bam::HeaderView::from_bytes(
+            b"@HD\tVN:1.6\n@RG\tID:rg1\tSM:barcode01\n@RG\tSM:barcode02\tID:rg2\n\
+              @RG\tID:rg3\tSM:barcode01\n",
+        );
+        assert_eq!(
+            sample_read_groups(&header, "barcode01").unwrap(),
+            vec!["rg1".to_string(), "rg3".to_string()]
+        );
+        assert_eq!(
+            sample_read_groups(&header, "barcode02").unwrap(),
+            vec!["rg2".to_string()]
+        );
+        assert!(sample_read_groups(&header, "barcode03").unwrap().is_empty());
+    }
+}
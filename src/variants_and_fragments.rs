@@ -1,6 +1,6 @@
 //! Data structures to represent variants and haplotype fragments defined over those variants.
 
-use bio::stats::LogProb;
+use bio::stats::{LogProb, Prob};
 use call_potential_snvs::VARLIST_CAPACITY;
 use errors::*;
 use genotype_probs::*;
@@ -13,6 +13,7 @@ use std::cmp::Ordering;
 use std::convert::From;
 use std::fmt;
 use util::*;
+use vcf_annotation::GenotypeConcordance;
 
 #[derive(Clone, Copy)]
 pub struct FragCall {
@@ -21,6 +22,26 @@ pub struct FragCall {
     pub allele: u8,              // allele call
     pub qual: LogProb,           // LogProb probability the call is an error
     pub one_minus_qual: LogProb,           // LogProb 1-probability the call is an error
+    pub reverse_strand: bool,    // strand of the alignment of the call (a stitched read has both)
+    pub read_pos: u32,           // 0-based position of the variant in the read sequence as stored in the BAM (u32::MAX if unknown)
+    pub base_qual: u8,           // base quality of the read at the variant (255 if unknown)
+}
+
+impl FragCall {
+    /// Creates an allele call, where ```p_miscall``` is the (normal-space) probability that the
+    /// call is an error
+    pub fn new(frag_ix: usize, var_ix: usize, allele: u8, p_miscall: f64) -> FragCall {
+        FragCall {
+            frag_ix: frag_ix,
+            var_ix: var_ix,
+            allele: allele,
+            qual: LogProb::from(Prob(p_miscall)),
+            one_minus_qual: LogProb::from(Prob(1.0 - p_miscall)),
+            reverse_strand: false,
+            read_pos: u32::MAX,
+            base_qual: 255,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -28,62 +49,149 @@ pub struct Fragment {
     pub id: Option<String>,
     pub calls: Vec<FragCall>,
     pub p_read_hap: [LogProb; 2],
-    pub reverse_strand: bool
+    pub reverse_strand: bool,
+    pub mapq: u8, // mapping quality of the read (0 if unknown)
+    pub error_rate: Option<f32>, // effective per-base error rate of the read against its best haplotypes in the realignment windows
+    pub hp_tag: Option<u8>, // haplotype (HP tag, 1 or 2) of the read in the input BAM, if it was haplotype-tagged
+    pub ps_tag: Option<i32>, // phase set (PS tag) of the read in the input BAM
+}
+
+impl Fragment {
+    /// Creates a forward strand fragment that is equally likely to come from either haplotype
+    pub fn new(id: Option<String>, calls: Vec<FragCall>) -> Fragment {
+        let ln_half = LogProb::from(Prob(0.5));
+        Fragment {
+            id: id,
+            calls: calls,
+            p_read_hap: [ln_half, ln_half],
+            reverse_strand: false,
+            mapq: 0,
+            error_rate: None,
+            hp_tag: None,
+            ps_tag: None,
+        }
+    }
+}
+
+/// Creates a list of fragments from a compact text representation, which is mostly useful to
+/// set up small, deterministic examples (e.g. for testing genotyping and phasing)
+///
+/// # Arguments
+/// - ```rows```: one string per fragment, with one character per variant: the allele (```0``` to
+///   ```9```) observed at the variant, or ```-``` if the fragment does not cover the variant
+/// - ```p_miscall```: the probability that each allele call is an error
+///
+/// # Returns
+/// Returns the fragments in the same order as ```rows```, with fragment ```i``` named ```f{i}```
+///
+/// # Example
+/// ```fragments_from_strings(&["0011", "-11-"], 0.01)``` creates a fragment with calls at
+/// variants 0 to 3, and a fragment with calls for allele 1 at variants 1 and 2.
+pub fn fragments_from_strings(rows: &[&str], p_miscall: f64) -> Vec<Fragment> {
+    let mut flist: Vec<Fragment> = vec![];
+    for (f, row) in rows.iter().enumerate() {
+        let mut calls: Vec<FragCall> = vec![];
+        for (v, c) in row.chars().enumerate() {
+            if let Some(allele) = c.to_digit(10) {
+                calls.push(FragCall::new(f, v, allele as u8, p_miscall));
+            }
+        }
+        flist.push(Fragment::new(Some(format!("f{}", f)), calls));
+    }
+    flist
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum VarFilter {
-    Pass = 0,
-    Density = 1,
-    Depth = 2,
-    DensityAndDepth = 3,
-    StrandBias = 4,
-    DensityAndStrandBias = 5,
-    DepthAndStrandBias = 6,
-    DensityAndDepthAndStrandBias = 7,
+/// The FILTER flags of a variant, as a set of bits. ```VarFilter::Pass``` is the empty set.
+///
+/// Each flag is one bit, with its name in the FILTER column in ```VAR_FILTER_NAMES```. The
+/// combinations of the density, depth and strand bias flags also have constants of their own.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct VarFilter(u32);
+
+/// The FILTER names of the flags of ```VarFilter```, in the order of their bits
+pub static VAR_FILTER_NAMES: [&str; 9] = ["dn", "dp", "sb", "ldp", "ab", "hp", "sc", "tr", "rc"];
+
+#[allow(non_upper_case_globals)]
+impl VarFilter {
+    pub const Pass: VarFilter = VarFilter(0);
+    pub const Density: VarFilter = VarFilter(1);
+    pub const Depth: VarFilter = VarFilter(2);
+    pub const DensityAndDepth: VarFilter = VarFilter(3);
+    pub const StrandBias: VarFilter = VarFilter(4);
+    pub const DensityAndStrandBias: VarFilter = VarFilter(5);
+    pub const DepthAndStrandBias: VarFilter = VarFilter(6);
+    pub const DensityAndDepthAndStrandBias: VarFilter = VarFilter(7);
+    /// below the minimum depth (```--filter_min_depth```)
+    pub const LowDepth: VarFilter = VarFilter(8);
+    /// heterozygous call with an allele balance out of range (```--filter_allele_balance```)
+    pub const AlleleBalance: VarFilter = VarFilter(16);
+    /// next to a homopolymer run (```--filter_homopolymer```)
+    pub const Homopolymer: VarFilter = VarFilter(32);
+    /// SNV in a cluster of soft-clipped reads (```--soft_clip_cluster```)
+    pub const SoftClipCluster: VarFilter = VarFilter(64);
+    /// low-quality call in an annotated tandem repeat (```--filter_tandem_repeat```)
+    pub const TandemRepeat: VarFilter = VarFilter(128);
+    /// homozygous reference call, written with ```--emit-all```
+    pub const RefCall: VarFilter = VarFilter(256);
 }
 
 impl fmt::Display for VarFilter {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            VarFilter::Pass => write!(f, "PASS"),
-            VarFilter::Density => write!(f, "dn"),
-            VarFilter::Depth => write!(f, "dp"),
-            VarFilter::DensityAndDepth => write!(f, "dn;dp"),
-            VarFilter::StrandBias => write!(f, "sb"),
-            VarFilter::DensityAndStrandBias => write!(f, "dn;sb"),
-            VarFilter::DepthAndStrandBias => write!(f, "dp;sb"),
-            VarFilter::DensityAndDepthAndStrandBias => write!(f, "dn;dp;sb"),
+        if *self == VarFilter::Pass {
+            return write!(f, "PASS");
         }
+        let names: Vec<&str> = VAR_FILTER_NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(bit, _)| self.0 & (1 << bit) != 0)
+            .map(|(_, &name)| name)
+            .collect();
+        write!(f, "{}", names.join(";"))
     }
 }
 
 impl From<usize> for VarFilter {
     fn from(item: usize) -> Self {
-        match item {
-            0 => VarFilter::Pass,
-            1 => VarFilter::Density,
-            2 => VarFilter::Depth,
-            3 => VarFilter::DensityAndDepth,
-            4 => VarFilter::StrandBias,
-            5 => VarFilter::DensityAndStrandBias,
-            6 => VarFilter::DepthAndStrandBias,
-            7 => VarFilter::DensityAndDepthAndStrandBias,
-            _ => {
-                panic!("Invalid value while combining variant filters");
-            }
+        if item >> VAR_FILTER_NAMES.len() != 0 {
+            panic!("Invalid value while combining variant filters");
         }
+        VarFilter(item as u32)
+    }
+}
+
+impl From<VarFilter> for usize {
+    fn from(filter: VarFilter) -> Self {
+        filter.0 as usize
     }
 }
 
 impl VarFilter {
     // rhs is filter to add
     pub fn add_filter(&mut self, filter: VarFilter) {
-        *self = VarFilter::from(*self as usize | filter as usize)
+        self.0 |= filter.0;
     }
     pub fn has_filter(&self, filter: VarFilter) -> bool {
-        (*self as usize & filter as usize) != 0
+        (self.0 & filter.0) != 0
+    }
+    pub fn remove_filter(&mut self, filter: VarFilter) {
+        self.0 &= !filter.0;
+    }
+}
+
+/// The read depth and allele counts of a phased variant among the reads assigned to each of its
+/// haplotypes (the HDP and HAD fields)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HaplotypeCounts {
+    pub depth: [usize; 2],
+    pub allele_counts: [Vec<u16>; 2], // indices match up with those of Var.alleles
+}
+
+impl HaplotypeCounts {
+    pub fn new(n_alleles: usize) -> HaplotypeCounts {
+        HaplotypeCounts {
+            depth: [0, 0],
+            allele_counts: [vec![0; n_alleles], vec![0; n_alleles]],
+        }
     }
 }
 
@@ -108,21 +216,114 @@ pub struct Var {
     pub unphased_gq: f64,
     pub genotype_post: GenotypeProbs, // genotype posteriors[a1][a2] is log posterior of phased a1|a2 haplotype
     // e.g. genotype_posteriors[2][0] is the log posterior probability of 2|0 haplotype
+    pub genotype_likelihoods: GenotypeProbs, // normalized genotype likelihoods P(data | G), indexed like genotype_post
     pub phase_set: Option<usize>,
     pub strand_bias_pvalue: f64, // fisher's exact test strand bias Pvalue
     pub mec: usize,                // mec for variant
     pub mec_frac_variant: f64,   // mec fraction for this variant
     pub mec_frac_block: f64,     // mec fraction for this haplotype block
     pub mean_allele_qual: f64,
+    pub soft_clip_frac: f64, // fraction of overlapping reads that are soft-clipped near the variant
     pub dp_any_mq: usize,
     pub mq10_frac: f64,
     pub mq20_frac: f64,
     pub mq30_frac: f64,
     pub mq40_frac: f64,
     pub mq50_frac: f64,
+    pub mapq_median: Vec<f64>, // median MAPQ of the reads supporting each allele (indices match Var.alleles)
+    pub mapq_mad: Vec<f64>, // median absolute deviation of the MAPQ of the reads supporting each allele
+    pub mapq_mean: Vec<f64>, // mean MAPQ of the reads supporting each allele
+    pub allele_qual_mean: Vec<f64>, // mean (PHRED-scaled) allele quality of the calls of each allele
+    pub realignment_llr: Vec<f64>, // log10 realignment likelihood ratio of each allele vs the reference allele
+    pub alt_frac: f64, // alternate allele fraction, estimated from the per-read allele posteriors
+    pub alt_frac_ci: (f64, f64), // 95% credible interval of alt_frac
+    pub cluster_id: Option<usize>, // 1-based position of the first variant of the realignment cluster, for variants realigned together with others
+    pub cluster_qual: Option<f64>, // PHRED-scaled upper bound on the probability that any variant call in the cluster is wrong
+    pub cluster_split: bool, // the realignment cluster was split next to this variant in some read, because it was too large
+    pub realign_skipped: usize, // number of overlapping reads that were not realigned to the variant because of the realignment depth cap
+    pub de_novo_qual: Option<f64>, // PHRED-scaled probability that a trio child's allele is NOT de novo
+    pub somatic_qual: Option<f64>, // PHRED-scaled probability that a tumor variant is NOT somatic
+    pub somatic_vaf: Option<f64>, // most likely tumor allele fraction of a somatic variant
+    pub mosaic_qual: Option<f64>, // PHRED-scaled probability that a variant is NOT mosaic (--mosaic)
+    pub mosaic_vaf: Option<f64>, // most likely allele fraction of a mosaic variant
+    pub annotations: Vec<(String, Option<String>)>, // INFO fields (ID, value) copied from the matching record of --annotation_vcf
+    pub genotype_concordance: Option<GenotypeConcordance>, // concordance with the genotype of --annotation_vcf
+    pub phase_qual: Option<f64>, // PHRED-scaled probability that a phased variant's alleles do NOT segregate with the haplotypes
+    pub ploidy: u8, // 1 for haploid sites (genotypes are Genotype(a, a)), otherwise 2
+    pub population_af: Option<Vec<f64>>, // population allele frequencies of a known site (indices match Var.alleles)
+    pub merged_mnv: bool, // the record was merged from phased variants that are adjacent on a haplotype
+    pub mnv_id: Option<usize>, // 1-based position of the merged MNV record that this variant is part of
+    pub tandem_repeat: Option<(usize, usize)>, // 0-based first and last position of the annotated tandem repeat that the variant overlaps
+    pub hap_counts: Option<HaplotypeCounts>, // read depth and allele counts on each haplotype of a phased variant
+    pub phased_no_ps: bool, // the genotype is written phased, but without a phase set (--singleton_phase drop-ps)
 }
 
 impl Var {
+    /// Creates an ungenotyped, unphased diploid variant with the given alleles, where
+    /// ```alleles[0]``` is the reference allele. All of the other fields are set as for a new
+    /// potential variant.
+    pub fn new(tid: u32, pos0: usize, alleles: Vec<String>) -> Var {
+        let n_alleles = alleles.len();
+        Var {
+            ix: 0,
+            tid: tid,
+            pos0: pos0,
+            alleles: alleles,
+            dp: 0,
+            allele_counts: vec![0; n_alleles],
+            allele_counts_forward: vec![0; n_alleles],
+            allele_counts_reverse: vec![0; n_alleles],
+            ambiguous_count: 0,
+            qual: 0.0,
+            filter: VarFilter::Pass,
+            genotype: Genotype(0, 0),
+            gq: 0.0,
+            unphased_genotype: Genotype(0, 0),
+            unphased_gq: 0.0,
+            genotype_post: GenotypeProbs::uniform(n_alleles),
+            genotype_likelihoods: GenotypeProbs::uniform(n_alleles),
+            phase_set: None,
+            strand_bias_pvalue: 1.0,
+            mec: 0,
+            mec_frac_variant: 0.0,
+            mec_frac_block: 0.0,
+            mean_allele_qual: 0.0,
+            soft_clip_frac: 0.0,
+            dp_any_mq: 0,
+            mq10_frac: 0.0,
+            mq20_frac: 0.0,
+            mq30_frac: 0.0,
+            mq40_frac: 0.0,
+            mq50_frac: 0.0,
+            mapq_median: vec![0.0; n_alleles],
+            mapq_mad: vec![0.0; n_alleles],
+            mapq_mean: vec![0.0; n_alleles],
+            allele_qual_mean: vec![0.0; n_alleles],
+            realignment_llr: vec![0.0; n_alleles],
+            alt_frac: 0.0,
+            alt_frac_ci: (0.0, 1.0),
+            cluster_id: None,
+            cluster_qual: None,
+            cluster_split: false,
+            realign_skipped: 0,
+            de_novo_qual: None,
+            somatic_qual: None,
+            somatic_vaf: None,
+            mosaic_qual: None,
+            mosaic_vaf: None,
+            annotations: vec![],
+            genotype_concordance: None,
+            phase_qual: None,
+            ploidy: 2,
+            population_af: None,
+            merged_mnv: false,
+            mnv_id: None,
+            tandem_repeat: None,
+            hap_counts: None,
+            phased_no_ps: false,
+        }
+    }
+
     fn longest_allele_len(&self) -> Result<usize> {
         Ok(self
             .alleles
@@ -133,7 +334,7 @@ impl Var {
     }
 
     pub fn possible_genotypes(&self) -> Vec<Genotype> {
-        possible_genotypes(&self.alleles)
+        possible_genotypes_with_ploidy(&self.alleles, self.ploidy)
     }
 }
 
@@ -164,7 +365,6 @@ impl Eq for Var {}
 #[derive(Debug, Clone)]
 pub struct VarList {
     pub lst: Vec<Var>,
-    ix: HashMap<u32, Vec<usize>>, // key is TID
     pub target_names: Vec<String>,
 }
 
@@ -175,11 +375,13 @@ pub fn parse_vcf_potential_variants(
     // must assert that the VCF file is sorted correctly
     // can we just read it in and then check that it's sorted using the check_sorted function vs the bam's tlist?
 
-    let mut vcf = bcf::Reader::from_path(vcffile_name).chain_err(|| ErrorKind::BCFOpenError)?;
-    let vcfh = bcf::Reader::from_path(vcffile_name).chain_err(|| ErrorKind::BCFOpenError)?;
+    let mut vcf = bcf::Reader::from_path(vcffile_name)
+        .chain_err(|| ErrorKind::BCFOpenError(vcffile_name.clone()))?;
+    let vcfh = bcf::Reader::from_path(vcffile_name)
+        .chain_err(|| ErrorKind::BCFOpenError(vcffile_name.clone()))?;
 
     let target_names = parse_target_names(&bamfile_name)?;
-    let bam = bam::Reader::from_path(bamfile_name).chain_err(|| ErrorKind::BamOpenError)?;
+    let bam = open_bam(bamfile_name)?;
     let mut chrom2tid: HashMap<String, usize> = HashMap::new();
 
     for (t, name) in bam.header().target_names().iter().enumerate() {
@@ -198,11 +400,14 @@ pub fn parse_vcf_potential_variants(
 
         let rid = record.rid().chain_err(|| "Error accessing vcf RID")?;
 
-        let chrom_result = vcfh.header().rid2name(rid);
-        let chrom: String = u8_to_string(chrom_result.unwrap())?;
+        let chrom_name = vcfh
+            .header()
+            .rid2name(rid)
+            .chain_err(|| ErrorKind::BCFReadError)?;
+        let chrom: String = u8_to_string(chrom_name)?;
 
         if !chrom2tid.contains_key(&chrom) {
-            eprintln!(
+            warn!(
                 "WARNING: Potential variant VCF contains contig {} not found in BAM contigs.",
                 chrom
             );
@@ -213,11 +418,11 @@ pub fn parse_vcf_potential_variants(
         let mut too_big_indel = false;
 
         for a in record.alleles().iter() {
-            let s = u8_to_string(a)?;
+            let s = u8_to_string(a)?.to_ascii_uppercase();
 
             // remove non-ACGT (e.g. structural) variants
             if has_non_acgt(&s) {
-                eprintln!(
+                warn!(
                     "WARNING: Variant at {}:{} in input VCF will be ignored due to non-ACGT variant allele.",
                     &chrom,
                     record.pos()+1);
@@ -226,7 +431,7 @@ pub fn parse_vcf_potential_variants(
             }
 
             if s.len() > 50 {
-                eprintln!(
+                warn!(
                     "WARNING: Variant at {}:{} in input VCF will be ignored due to excessively large indel (>50 bp), which may cause unexpected behaviour.",
                     &chrom,
                     record.pos()+1);
@@ -239,14 +444,6 @@ pub fn parse_vcf_potential_variants(
 
         if non_acgt || too_big_indel {continue;}
 
-        if alleles.len() > 2 {
-            eprintln!(
-                "WARNING: Triallelic variant at {}:{} in input VCF will be ignored (not currently supported).",
-                &chrom,
-                record.pos()+1);
-            continue;
-        }
-
         let new_var = Var {
             ix: 0,
             tid: *chrom2tid
@@ -268,23 +465,52 @@ pub fn parse_vcf_potential_variants(
             unphased_genotype: Genotype(0, 0),
             unphased_gq: 0.0,
             genotype_post: GenotypeProbs::uniform(alleles.len()),
+            genotype_likelihoods: GenotypeProbs::uniform(alleles.len()),
             phase_set: None,
             strand_bias_pvalue: 1.0,
             mec: 0,
             mec_frac_variant: 0.0, // mec fraction for this variant
             mec_frac_block: 0.0,   // mec fraction for this haplotype block
             mean_allele_qual: 0.0,
+            soft_clip_frac: 0.0,
             dp_any_mq: 0,
             mq10_frac: 0.0,
             mq20_frac: 0.0,
             mq30_frac: 0.0,
             mq40_frac: 0.0,
-            mq50_frac: 0.0
+            mq50_frac: 0.0,
+            mapq_median: vec![0.0; alleles.len()],
+            mapq_mad: vec![0.0; alleles.len()],
+            mapq_mean: vec![0.0; alleles.len()],
+            allele_qual_mean: vec![0.0; alleles.len()],
+            realignment_llr: vec![0.0; alleles.len()],
+            alt_frac: 0.0,
+            alt_frac_ci: (0.0, 1.0),
+            cluster_id: None,
+            cluster_qual: None,
+            cluster_split: false,
+            realign_skipped: 0,
+            de_novo_qual: None,
+            somatic_qual: None,
+            somatic_vaf: None,
+            mosaic_qual: None,
+            mosaic_vaf: None,
+            annotations: vec![],
+            genotype_concordance: None,
+            phase_qual: None,
+            ploidy: 2,
+            population_af: None,
+            merged_mnv: false,
+            mnv_id: None,
+            tandem_repeat: None,
+            hap_counts: None,
+            phased_no_ps: false,
         };
         varlist.push(new_var);
     }
 
-    let vlst = VarList::new(varlist, target_names.clone())?;
+    let mut vlst = VarList::new(varlist, target_names.clone())?;
+    vlst.merge_same_position()?;
     vlst.assert_sorted();
 
     Ok(vlst)
@@ -311,7 +537,6 @@ impl VarList {
     pub fn new(lst: Vec<Var>, target_names: Vec<String>) -> Result<VarList> {
         let mut v = VarList {
             lst: lst,
-            ix: HashMap::new(),
             target_names: target_names,
         };
         v.sort()?;
@@ -322,11 +547,71 @@ impl VarList {
         self.lst.len()
     }
 
+    /// Sets the ploidy of each variant. Variants inside one of the ```regions``` get the ploidy of
+    /// the first region that contains them, and other variants get ```default_ploidy```. Variants
+    /// with ploidy 0 (e.g. chrY in a female sample) are removed from the list. Any ploidy other
+    /// than 0 or 1 is genotyped as diploid.
+    pub fn assign_ploidy(
+        &mut self,
+        default_ploidy: usize,
+        regions: &Vec<(GenomicInterval, u8)>,
+    ) -> Result<()> {
+        for var in self.lst.iter_mut() {
+            let mut ploidy = default_ploidy;
+            for &(ref iv, region_ploidy) in regions {
+                if var.tid == iv.tid
+                    && var.pos0 >= iv.start_pos as usize
+                    && var.pos0 <= iv.end_pos as usize
+                {
+                    ploidy = region_ploidy as usize;
+                    break;
+                }
+            }
+            var.ploidy = ploidy.min(2) as u8;
+        }
+
+        let n_var = self.lst.len();
+        self.lst.retain(|var| var.ploidy != 0);
+        if self.lst.len() != n_var {
+            self.sort()?;
+        }
+        Ok(())
+    }
+
+    /// Sets ```tandem_repeat``` of the variants whose reference allele overlaps one of the tandem
+    /// repeats in ```repeats```, which must be sorted and non-overlapping (see
+    /// ```parse_tandem_repeat_bed```). Returns the number of variants in tandem repeats.
+    pub fn annotate_tandem_repeats(&mut self, repeats: &Vec<GenomicInterval>) -> usize {
+        let mut n_annotated = 0;
+        for var in self.lst.iter_mut() {
+            let var_end = var.pos0 + var.alleles[0].len().max(1) - 1;
+            // the number of repeats that start at or before the end of the variant. only the last
+            // of them can end after the start of the variant
+            let k = match repeats.binary_search_by(|r| {
+                if (r.tid, r.start_pos as usize) <= (var.tid, var_end) {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }) {
+                Ok(i) | Err(i) => i,
+            };
+            var.tandem_repeat = None;
+            if k > 0 {
+                let r = &repeats[k - 1];
+                if r.tid == var.tid && r.end_pos as usize >= var.pos0 {
+                    var.tandem_repeat = Some((r.start_pos as usize, r.end_pos as usize));
+                    n_annotated += 1;
+                }
+            }
+        }
+        n_annotated
+    }
+
     pub fn sort(&mut self) -> Result<()> {
         self.lst.sort();
         self.add_ix();
-        self.ix.clear();
-        self.index_lst()?;
+        self.assert_sorted();
         Ok(())
     }
 
@@ -351,111 +636,31 @@ impl VarList {
         }
     }
 
-    fn index_lst(&mut self) -> Result<()> {
-        // need to throw error if list isn't sorted
-        self.assert_sorted();
-        // for every chromosome, get the position of the last variant
-        // and the varlist index of the first variant on that chromosome
-        let mut max_positions: HashMap<u32, usize> = HashMap::new();
-        let mut first_ix: HashMap<u32, usize> = HashMap::new();
-        for (i, var) in self.lst.iter().enumerate() {
-            let mpos: usize = *(max_positions.entry(var.tid).or_insert(0));
-            if var.pos0 > mpos {
-                max_positions.insert(var.tid, var.pos0);
-            }
-            if !first_ix.contains_key(&var.tid) {
-                first_ix.insert(var.tid, i); // insert varlist index of first variant on chrom
-            }
-        }
-
-        // for every chrom, iterate up to its last potential variant and create an index that
-        // returns the lst index of the next SNV for some position mod 1000
-        for (tid, max_pos) in &max_positions {
-            let mut v: Vec<usize> = vec![];
-            // the first variant after position 0 is the first variant on the chromosome
-            let e = match first_ix.get(tid) {
-                Some(x) => *x,
-                None => {
-                    bail!("This dictionary is missing a chromosome!");
-                }
-            };
-            v.push(e);
-            // for every subsequent position (1000,2000,3000...) we start at the last position
-            // and step forward until we get the first variant at or after that position
-            for i in 1..(max_pos / INDEX_FREQ + 1) {
-                let index_pos = INDEX_FREQ * i;
-                let start = v[i - 1];
-
-                let mut found_var = false;
-                for j in start..self.lst.len() {
-                    if self.lst[j].pos0 >= index_pos {
-                        v.push(j);
-                        found_var = true;
-                        break;
-                    }
-                }
-                if !found_var {
-                    break;
-                }
+    /// The variants that start in ```interval``` (at or after its start), up to the first
+    /// variant with an allele that extends past its end. The variants are found with a binary
+    /// search in the sorted list, and are returned as a slice of the list rather than copied.
+    pub fn variants_in_range(&self, interval: &GenomicInterval) -> Result<&[Var]> {
+        let start = match self.lst.binary_search_by(|var| {
+            if (var.tid, var.pos0) < (interval.tid, interval.start_pos as usize) {
+                Ordering::Less
+            } else {
+                Ordering::Greater
             }
-            self.ix.insert(*tid, v); // insert the index vector into the ix dictionary
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        let mut end = start;
+        while end < self.lst.len()
+            && self.lst[end].tid == interval.tid
+            && self.lst[end].pos0 + self.lst[end].longest_allele_len()? <= interval.end_pos as usize
+        {
+            end += 1;
         }
-        Ok(())
+        Ok(&self.lst[start..end])
     }
 
     pub fn get_variants_range(&self, interval: GenomicInterval) -> Result<Vec<Var>> {
-        // vector of variants to fill and return
-        let mut vlst: Vec<Var> = vec![];
-        // get the varlist index of a nearby position on the left
-
-        if !self
-                .ix
-                .contains_key(&interval.tid)
-                && self.target_names.contains(&interval.chrom) {
-
-                return Ok(vlst)
-                }
-        let index_pos = (interval.start_pos as usize) / INDEX_FREQ;
-
-        if index_pos
-            >= self
-                .ix
-                .get(&interval.tid)
-                .chain_err(|| {
-                    format!(
-                        "Error accessing chromosome {} from variant list index.",
-                        &interval.chrom
-                    )
-                })?
-                .len()
-        {
-            return Ok(vlst);
-        }
-
-        let mut i = self.ix.get(&interval.tid).chain_err(|| {
-            format!(
-                "Error accessing chromosome {} from variant list index.",
-                &interval.chrom
-            )
-        })?[index_pos];
-
-        while i < self.lst.len()
-            && self.lst[i].tid == interval.tid
-            && self.lst[i].pos0 + self.lst[i].longest_allele_len()? <= interval.end_pos as usize
-        {
-            if self.lst[i].pos0 >= interval.start_pos as usize {
-                vlst.push(self.lst[i].clone());
-            }
-            i += 1;
-        }
-
-        for var in &vlst {
-            assert!(var.tid == interval.tid);
-            assert!(var.pos0 >= interval.start_pos as usize);
-            assert!(var.pos0 <= interval.end_pos as usize);
-        }
-
-        Ok(vlst)
+        Ok(self.variants_in_range(&interval)?.to_vec())
     }
 
     fn combine_variant_group(var_group: &mut Vec<Var>) -> Var {
@@ -597,15 +802,54 @@ impl VarList {
 
         let mut new_v = var_group[0].clone();
         new_v.allele_counts = vec![0; new_allele_lst.len()];
+        new_v.allele_counts_forward = vec![0; new_allele_lst.len()];
+        new_v.allele_counts_reverse = vec![0; new_allele_lst.len()];
+        new_v.mapq_mean = vec![0.0; new_allele_lst.len()];
+        new_v.allele_qual_mean = vec![0.0; new_allele_lst.len()];
+        new_v.realignment_llr = vec![0.0; new_allele_lst.len()];
         new_v.alleles = new_allele_lst.clone();
         new_v.genotype = Genotype(0, 0);
         new_v.gq = 0.0;
         new_v.genotype_post = GenotypeProbs::uniform(new_v.alleles.len());
+        new_v.genotype_likelihoods = GenotypeProbs::uniform(new_v.alleles.len());
         new_v.phase_set = None;
+        new_v.population_af = None;
 
         new_v
     }
 
+    /// Merges variants that start at the same position into a single multiallelic variant, e.g.
+    /// an SNV and an insertion after the same base. Otherwise the two variants would overlap and
+    /// the haplotypes of a variant cluster could not include both of them.
+    pub fn merge_same_position(&mut self) -> Result<()> {
+        let mut new_vlst: Vec<Var> = Vec::with_capacity(self.lst.len());
+        let mut var_group: Vec<Var> = vec![];
+
+        for var in self.lst.drain(..) {
+            if var_group.len() > 0
+                && (var.tid != var_group[0].tid || var.pos0 != var_group[0].pos0)
+            {
+                if var_group.len() == 1 {
+                    new_vlst.append(&mut var_group);
+                } else {
+                    new_vlst.push(VarList::combine_variant_group(&mut var_group));
+                    var_group.clear();
+                }
+            }
+            var_group.push(var);
+        }
+
+        if var_group.len() == 1 {
+            new_vlst.append(&mut var_group);
+        } else if var_group.len() > 1 {
+            new_vlst.push(VarList::combine_variant_group(&mut var_group));
+        }
+
+        self.lst = new_vlst;
+        self.sort()?;
+        Ok(())
+    }
+
     pub fn combine(&mut self, other: &mut VarList) -> Result<()> {
         if self.target_names != other.target_names {
             bail!("Target names of variant lists that are being combined are not the same.");
@@ -656,29 +900,96 @@ impl VarList {
 
         // clear out the other VarList since we've mutated it beyond saving
         other.lst.clear();
-        other.ix.clear();
         Ok(())
     }
 }
 
+/// The probability that a window has more variants than the count threshold of the variant density
+/// filter at the density of the variant calls, for ```--density_count auto```
+pub static DENSITY_AUTO_P: f64 = 1e-6;
+
+/// The smallest count ```n``` (but at least ```min_n```) such that a window of ```len``` bp has
+/// more than ```n``` variants with probability at most ```DENSITY_AUTO_P```, if the variants occur
+/// at ```rate``` per bp and their number in the window is Poisson distributed
+pub fn auto_density_count(rate: f64, len: usize, min_n: usize) -> usize {
+    let lambda = rate * len as f64;
+    if !(lambda > 0.0) {
+        return min_n;
+    }
+    // log P(X = n) and log P(X <= n) for the Poisson distributed number of variants X
+    let mut ln_p_n = -lambda;
+    let mut ln_p_le = LogProb(ln_p_n);
+    let mut n = 0;
+    while n < len && 1.0 - (*ln_p_le).exp() > DENSITY_AUTO_P {
+        n += 1;
+        ln_p_n += lambda.ln() - (n as f64).ln();
+        ln_p_le = LogProb::ln_add_exp(ln_p_le, LogProb(ln_p_n));
+    }
+    n.max(min_n)
+}
+
+/// Adds the variant density (dn) and maximum depth (dp) filter flags to the variants
+///
+/// A variant is flagged as part of a dense cluster if more than ```density_params.n``` other
+/// variants follow it within ```density_params.len``` bp on the same contig (and those variants
+/// are flagged as well). Only the variants whose QUAL or GQ (```density_params.score```) is at
+/// least ```density_params.gq``` are counted. With ```density_params.auto_n```, the count
+/// threshold is raised for divergent samples (see ```auto_density_count```), with the rate of the
+/// counted variants per bp of the spans of the variants on each contig.
+///
+/// # Returns
+/// Returns ```(n, flagged)```: the count threshold that was used, and the number of variants with
+/// the dn flag
 pub fn var_filter(
     varlist: &mut VarList,
-    density_qual: f64,
-    density_dist: usize,
-    density_count: usize,
+    density_params: &DensityParameters,
     max_depth: u32,
-) {
+    contig_params: &Vec<ContigParams>,
+) -> (usize, usize) {
+    let counted = |var: &Var| {
+        let score = match density_params.score {
+            DensityScore::Qual => var.qual,
+            DensityScore::Gq => var.gq,
+        };
+        score >= density_params.gq
+    };
+
+    let density_count = if density_params.auto_n {
+        // the spans of the variants on each contig
+        let mut spans: HashMap<u32, (usize, usize)> = HashMap::new();
+        let mut n_counted = 0;
+        for var in varlist.lst.iter() {
+            let span = spans.entry(var.tid).or_insert((var.pos0, var.pos0));
+            span.0 = span.0.min(var.pos0);
+            span.1 = span.1.max(var.pos0);
+            if counted(var) {
+                n_counted += 1;
+            }
+        }
+        let total_span: usize = spans.values().map(|&(start, end)| end - start + 1).sum();
+        let rate = if total_span > 0 {
+            n_counted as f64 / total_span as f64
+        } else {
+            0.0
+        };
+        auto_density_count(rate, density_params.len, density_params.n)
+    } else {
+        density_params.n
+    };
+
     for i in 0..varlist.lst.len() {
-        if varlist.lst[i].qual < density_qual {
+        if !counted(&varlist.lst[i]) {
             continue;
         }
 
         let mut count = 0;
         for j in i + 1..varlist.lst.len() {
-            if varlist.lst[j].pos0 - varlist.lst[i].pos0 > density_dist {
+            if varlist.lst[j].tid != varlist.lst[i].tid
+                || varlist.lst[j].pos0 - varlist.lst[i].pos0 > density_params.len
+            {
                 break;
             }
-            if varlist.lst[j].qual < density_qual {
+            if !counted(&varlist.lst[j]) {
                 continue;
             }
             count += 1;
@@ -691,10 +1002,32 @@ pub fn var_filter(
     }
 
     for i in 0..varlist.lst.len() {
+        let max_depth = contig_max_cov(contig_params, varlist.lst[i].tid, max_depth);
         if varlist.lst[i].dp > max_depth as usize {
             varlist.lst[i].filter.add_filter(VarFilter::Depth);
         }
     }
+
+    let flagged = varlist
+        .lst
+        .iter()
+        .filter(|var| var.filter.has_filter(VarFilter::Density))
+        .count();
+    (density_count, flagged)
+}
+
+/// Flags the variants with a homozygous reference genotype with ```VarFilter::RefCall```, so that
+/// the evaluated sites that are not calls can be written to the VCF (```--emit-all```) without
+/// passing as calls. Returns the number of flagged variants.
+pub fn flag_reference_calls(varlist: &mut VarList) -> usize {
+    let mut n_flagged = 0;
+    for var in varlist.lst.iter_mut() {
+        if var.genotype == Genotype(0, 0) {
+            var.filter.add_filter(VarFilter::RefCall);
+            n_flagged += 1;
+        }
+    }
+    n_flagged
 }
 
 #[cfg(test)]
@@ -703,11 +1036,12 @@ mod tests {
 
     #[test]
     fn test_varfilter_cast() {
-        assert_eq!(VarFilter::Pass as usize, 0);
-        assert_eq!(VarFilter::Density as usize, 1);
-        assert_eq!(VarFilter::Depth as usize, 2);
-        assert_eq!(VarFilter::DensityAndDepth as usize, 3);
-        assert_eq!(VarFilter::StrandBias as usize, 4);
+        assert_eq!(usize::from(VarFilter::Pass), 0);
+        assert_eq!(usize::from(VarFilter::Density), 1);
+        assert_eq!(usize::from(VarFilter::Depth), 2);
+        assert_eq!(usize::from(VarFilter::DensityAndDepth), 3);
+        assert_eq!(usize::from(VarFilter::StrandBias), 4);
+        assert_eq!(VarFilter::from(7), VarFilter::DensityAndDepthAndStrandBias);
     }
 
     #[test]
@@ -735,6 +1069,128 @@ mod tests {
         assert!(f1.has_filter(dp));
         assert!(f1.has_filter(dn));
         assert!(f1.has_filter(sb));
+        assert_eq!(f1.to_string(), "dn;dp;sb");
+
+        let mut f2 = VarFilter::Pass;
+        assert_eq!(f2.to_string(), "PASS");
+        f2.add_filter(VarFilter::Homopolymer);
+        f2.add_filter(VarFilter::Depth);
+        assert!(!f2.has_filter(VarFilter::LowDepth));
+        assert_eq!(f2.to_string(), "dp;hp");
+        f2.add_filter(VarFilter::SoftClipCluster);
+        assert_eq!(f2.to_string(), "dp;hp;sc");
+        f2.add_filter(VarFilter::TandemRepeat);
+        assert_eq!(f2.to_string(), "dp;hp;sc;tr");
+        f2.add_filter(VarFilter::RefCall);
+        assert_eq!(f2.to_string(), "dp;hp;sc;tr;rc");
+        f2.remove_filter(VarFilter::Homopolymer);
+        f2.remove_filter(VarFilter::LowDepth);
+        assert_eq!(f2.to_string(), "dp;sc;tr;rc");
+    }
+
+    #[test]
+    fn test_flag_reference_calls() {
+        let mut lst: Vec<Var> = [100, 200, 300]
+            .iter()
+            .map(|&pos0| Var::new(0, pos0, vec!["A".to_string(), "G".to_string()]))
+            .collect();
+        lst[0].genotype = Genotype(0, 1);
+        lst[2].genotype = Genotype(1, 1);
+        lst[1].filter.add_filter(VarFilter::Depth);
+        let mut varlist = VarList::new(lst, vec!["chr1".to_string()]).unwrap();
+
+        assert_eq!(flag_reference_calls(&mut varlist), 1);
+        let filters: Vec<String> = varlist.lst.iter().map(|var| var.filter.to_string()).collect();
+        assert_eq!(filters, vec!["PASS", "dp;rc", "PASS"]);
+    }
+
+    #[test]
+    fn test_auto_density_count() {
+        // the minimum count is kept at a human-like variant density
+        assert_eq!(auto_density_count(0.001, 500, 10), 10);
+        assert_eq!(auto_density_count(0.0, 500, 10), 10);
+        // a window of 500 bp has more than 19 variants with probability 3e-7 at a mean of 5
+        assert_eq!(auto_density_count(0.01, 500, 10), 19);
+        // the count is finite at any density
+        assert_eq!(auto_density_count(10.0, 500, 10), 500);
+    }
+
+    #[test]
+    fn test_var_filter() {
+        let params = |n: usize, score: DensityScore, auto_n: bool| DensityParameters {
+            n: n,
+            len: 100,
+            gq: 50.0,
+            score: score,
+            auto_n: auto_n,
+            neighbor_window: 100,
+        };
+        // three variants within 100 bp, then another contig
+        let varlist = || {
+            let mut lst: Vec<Var> = [(0, 100), (0, 150), (0, 190), (1, 10), (1, 400)]
+                .iter()
+                .map(|&(tid, pos0)| {
+                    let mut var = Var::new(tid, pos0, vec!["A".to_string(), "G".to_string()]);
+                    var.qual = 100.0;
+                    var.gq = 100.0;
+                    var
+                })
+                .collect();
+            lst[2].gq = 10.0;
+            VarList::new(lst, vec!["chr1".to_string(), "chr2".to_string()]).unwrap()
+        };
+        let dense = |varlist: &VarList| -> Vec<bool> {
+            varlist
+                .lst
+                .iter()
+                .map(|var| var.filter.has_filter(VarFilter::Density))
+                .collect()
+        };
+
+        let mut vl = varlist();
+        assert_eq!(
+            var_filter(&mut vl, &params(1, DensityScore::Qual, false), 100, &vec![]),
+            (1, 3)
+        );
+        assert_eq!(dense(&vl), vec![true, true, true, false, false]);
+
+        // the third variant is not counted by its GQ
+        let mut vl = varlist();
+        assert_eq!(
+            var_filter(&mut vl, &params(1, DensityScore::Gq, false), 100, &vec![]),
+            (1, 0)
+        );
+
+        // the variants span 91 + 391 bp, so a window has 0.83 (GQ) or 1.04 (QUAL) on average
+        let mut vl = varlist();
+        assert_eq!(
+            var_filter(&mut vl, &params(1, DensityScore::Gq, true), 100, &vec![]).0,
+            8
+        );
+        let mut vl = varlist();
+        assert_eq!(
+            var_filter(&mut vl, &params(1, DensityScore::Qual, true), 100, &vec![]),
+            (9, 0)
+        );
+    }
+
+    #[test]
+    fn test_fragments_from_strings() {
+        let flist = fragments_from_strings(&["01-1", "--2"], 0.01);
+        assert_eq!(flist.len(), 2);
+        assert_eq!(flist[0].id, Some("f0".to_string()));
+        assert_eq!(flist[1].id, Some("f1".to_string()));
+
+        let calls: Vec<(usize, usize, u8)> = flist
+            .iter()
+            .flat_map(|f| f.calls.iter().map(|c| (c.frag_ix, c.var_ix, c.allele)))
+            .collect();
+        assert_eq!(calls, vec![(0, 0, 0), (0, 1, 1), (0, 3, 1), (1, 2, 2)]);
+
+        for call in &flist[0].calls {
+            assert!((*Prob::from(call.qual) - 0.01).abs() < 1e-12);
+            assert!((*Prob::from(call.one_minus_qual) - 0.99).abs() < 1e-12);
+        }
     }
 
     /**********************************************************************************************/
@@ -770,6 +1226,7 @@ mod tests {
             genotype: Genotype(0, 1),
             gq: 0.0,
             mean_allele_qual: 0.0,
+            soft_clip_frac: 0.0,
             mec: 0,
             strand_bias_pvalue: 0.0,
             mec_frac_block: 0.0,
@@ -780,9 +1237,36 @@ mod tests {
             mq30_frac: 1.0,
             mq40_frac: 1.0,
             mq50_frac: 1.0,
+            mapq_median: vec![60.0, 60.0],
+            mapq_mad: vec![0.0, 0.0],
+            mapq_mean: vec![0.0, 0.0],
+            allele_qual_mean: vec![0.0, 0.0],
+            realignment_llr: vec![0.0, 0.0],
+            alt_frac: 0.0,
+            alt_frac_ci: (0.0, 1.0),
+            cluster_id: None,
+            cluster_qual: None,
+            cluster_split: false,
+            realign_skipped: 0,
+            de_novo_qual: None,
+            somatic_qual: None,
+            somatic_vaf: None,
+            mosaic_qual: None,
+            mosaic_vaf: None,
+            annotations: vec![],
+            genotype_concordance: None,
+            phase_qual: None,
+            ploidy: 2,
+            population_af: None,
+            merged_mnv: false,
+            mnv_id: None,
+            tandem_repeat: None,
+            hap_counts: None,
+            phased_no_ps: false,
             unphased_genotype: Genotype(0, 1),
             unphased_gq: 0.0,
             genotype_post: GenotypeProbs::uniform(2),
+            genotype_likelihoods: GenotypeProbs::uniform(2),
             phase_set: None,
         }
     }
@@ -1019,6 +1503,83 @@ mod tests {
         assert!(pos_alleles_eq(vars, exp));
     }
 
+    #[test]
+    fn test_varlist_variants_in_range() {
+        let mut vlst = generate_test_lst1();
+        let target_names = vlst.target_names.clone();
+        let interval = |tid: u32, start_pos: u32, end_pos: u32| GenomicInterval {
+            tid: tid,
+            chrom: target_names[tid as usize].clone(),
+            start_pos: start_pos,
+            end_pos: end_pos,
+        };
+        let ixs = |vars: &[Var]| -> Vec<usize> { vars.iter().map(|v| v.ix).collect() };
+
+        // the lookup stops at the end of the contig, or past the end of the list
+        let iv = interval(1, 0, 3000);
+        assert_eq!(ixs(vlst.variants_in_range(&iv).unwrap()), vec![6, 7, 8, 9]);
+        let iv = interval(0, 6000, 20000);
+        assert_eq!(ixs(vlst.variants_in_range(&iv).unwrap()), vec![4, 5]);
+        let iv = interval(0, 6001, 9999);
+        assert!(vlst.variants_in_range(&iv).unwrap().is_empty());
+        let iv = interval(2, 30401, 40000);
+        assert!(vlst.variants_in_range(&iv).unwrap().is_empty());
+
+        // a variant that starts before the interval doesn't end the lookup, even if its alleles
+        // extend past the end of the interval
+        vlst.lst[3].alleles[0] = "C".repeat(5000);
+        let iv = interval(0, 2901, 7000);
+        assert_eq!(ixs(vlst.variants_in_range(&iv).unwrap()), vec![4]);
+        // but a variant in the interval that extends past its end does
+        let iv = interval(0, 2000, 7000);
+        assert_eq!(ixs(vlst.variants_in_range(&iv).unwrap()), vec![2]);
+    }
+
+    /// Benchmark of the variant lookup for the reads of a deep dataset with dense candidates:
+    /// ```cargo test --release bench_variants_in_range -- --ignored --nocapture```
+    #[test]
+    #[ignore]
+    fn bench_variants_in_range() {
+        use std::time::Instant;
+        let n_var = 200000;
+        let lst: Vec<Var> = (0..n_var)
+            .map(|i| generate_var1(i, 0, i * 50, "A".to_string(), "G".to_string()))
+            .collect();
+        let vlst = VarList::new(lst, vec!["chr1".to_string()]).unwrap();
+        // 20 kb reads starting every 50 bp, i.e. 400x coverage
+        let reads: Vec<GenomicInterval> = (0..n_var)
+            .map(|i| GenomicInterval {
+                tid: 0,
+                chrom: "chr1".to_string(),
+                start_pos: (i * 50) as u32,
+                end_pos: (i * 50 + 20000) as u32,
+            })
+            .collect();
+
+        let start = Instant::now();
+        let mut n_slice = 0;
+        for iv in &reads {
+            n_slice += vlst.variants_in_range(iv).unwrap().len();
+        }
+        let slice_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut n_copied = 0;
+        for iv in &reads {
+            n_copied += vlst.get_variants_range(iv.clone()).unwrap().len();
+        }
+        let copy_time = start.elapsed();
+
+        assert_eq!(n_slice, n_copied);
+        info!(
+            "{} read lookups of {} variant calls: {:?} as slices, {:?} copied",
+            reads.len(),
+            n_slice,
+            slice_time,
+            copy_time
+        );
+    }
+
     /**********************************************************************************************/
     // TEST VARIANT SORTING
     /**********************************************************************************************/
@@ -1137,6 +1698,39 @@ mod tests {
         .unwrap()
     }
 
+    #[test]
+    fn test_annotate_tandem_repeats() {
+        let snv = |tid: u32, pos0: usize| Var::new(tid, pos0, vec!["A".to_string(), "G".to_string()]);
+        let mut varlist = VarList::new(
+            vec![
+                snv(0, 5),
+                snv(0, 10),
+                // a deletion that starts before the repeat and ends in it
+                Var::new(0, 17, vec!["ACGT".to_string(), "A".to_string()]),
+                snv(0, 30),
+                snv(0, 45),
+                snv(1, 12),
+            ],
+            vec!["chr1".to_string(), "chr2".to_string()],
+        )
+        .unwrap();
+        let repeat = |tid: u32, start_pos: u32, end_pos: u32| GenomicInterval {
+            tid: tid,
+            chrom: format!("chr{}", tid + 1),
+            start_pos: start_pos,
+            end_pos: end_pos,
+        };
+        let repeats = vec![repeat(0, 10, 12), repeat(0, 20, 30), repeat(1, 0, 5)];
+
+        assert_eq!(varlist.annotate_tandem_repeats(&repeats), 3);
+        let annotated: Vec<Option<(usize, usize)>> =
+            varlist.lst.iter().map(|v| v.tandem_repeat).collect();
+        assert_eq!(
+            annotated,
+            vec![None, Some((10, 12)), Some((20, 30)), Some((20, 30)), None, None]
+        );
+    }
+
     #[test]
     fn test_varlist_sort1() {
         let mut vlst_unsorted = generate_test_lst1_unsorted1();
@@ -1173,6 +1767,7 @@ mod tests {
             genotype: Genotype(0, 1),
             gq: 0.0,
             mean_allele_qual: 0.0,
+            soft_clip_frac: 0.0,
             strand_bias_pvalue: 0.0,
             mec: 0,
             mec_frac_block: 0.0,
@@ -1183,9 +1778,36 @@ mod tests {
             mq30_frac: 1.0,
             mq40_frac: 1.0,
             mq50_frac: 1.0,
+            mapq_median: vec![60.0, 60.0],
+            mapq_mad: vec![0.0, 0.0],
+            mapq_mean: vec![0.0, 0.0],
+            allele_qual_mean: vec![0.0, 0.0],
+            realignment_llr: vec![0.0, 0.0],
+            alt_frac: 0.0,
+            alt_frac_ci: (0.0, 1.0),
+            cluster_id: None,
+            cluster_qual: None,
+            cluster_split: false,
+            realign_skipped: 0,
+            de_novo_qual: None,
+            somatic_qual: None,
+            somatic_vaf: None,
+            mosaic_qual: None,
+            mosaic_vaf: None,
+            annotations: vec![],
+            genotype_concordance: None,
+            phase_qual: None,
+            ploidy: 2,
+            population_af: None,
+            merged_mnv: false,
+            mnv_id: None,
+            tandem_repeat: None,
+            hap_counts: None,
+            phased_no_ps: false,
             unphased_genotype: Genotype(0, 1),
             unphased_gq: 0.0,
             genotype_post: GenotypeProbs::uniform(2),
+            genotype_likelihoods: GenotypeProbs::uniform(2),
             phase_set: None,
         }
     }
@@ -2056,4 +2678,33 @@ mod tests {
         assert_eq!(vlst1.lst[0].alleles, exp.lst[0].alleles);
         assert!(varlist_pos_alleles_eq(vlst1, exp));
     }
+
+    #[test]
+    fn test_varlist_merge_same_position() {
+        // an SNV and an insertion after the same base become one triallelic variant,
+        // while the neighboring variants are left as they are
+        let mut lst1: Vec<Var> = vec![];
+        lst1.push(generate_var1(0, 0, 100, "A".to_string(), "G".to_string()));
+        lst1.push(generate_var1(1, 0, 200, "C".to_string(), "T".to_string()));
+        lst1.push(generate_var1(2, 0, 200, "C".to_string(), "CAA".to_string()));
+        lst1.push(generate_var1(3, 1, 200, "G".to_string(), "A".to_string()));
+        let mut vlst1 = VarList::new(lst1, vec!["chr1".to_string(), "chr2".to_string()]).unwrap();
+
+        vlst1.merge_same_position().unwrap();
+
+        let mut lst2: Vec<Var> = vec![];
+        lst2.push(generate_var1(0, 0, 100, "A".to_string(), "G".to_string()));
+        lst2.push(generate_var2(
+            1,
+            0,
+            200,
+            vec!["C".to_string(), "CAA".to_string(), "T".to_string()],
+        ));
+        lst2.push(generate_var1(2, 1, 200, "G".to_string(), "A".to_string()));
+        let exp = VarList::new(lst2, vec!["chr1".to_string(), "chr2".to_string()]).unwrap();
+
+        assert_eq!(vlst1.lst.len(), 3);
+        assert_eq!(vlst1.lst[1].allele_counts.len(), 3);
+        assert!(varlist_pos_alleles_eq(vlst1, exp));
+    }
 }
@@ -0,0 +1,684 @@
+//! Transfer of annotations from an annotated VCF to the output records (```--annotation_vcf```).
+//!
+//! The records of an indexed VCF (e.g. ClinVar or a previous callset) are read for the variants
+//! of each region, and a variant matches a record at the same position if one of its alternate
+//! alleles is an alternate allele of the record (compared as in ```population_af```, so that
+//! alleles padded differently at a multiallelic site match). The selected INFO fields of the
+//! matching record are copied to the output record, with the per-allele values of ```Number=A```
+//! and ```Number=R``` fields reordered to the alleles of the variant.
+//!
+//! If the annotation VCF has genotypes for a sample, the genotype of every variant of the sample
+//! is compared to them (a site that isn't in the VCF is homozygous reference there) and marked
+//! with ```GTCONC```:
+//! - concordant: the same alleles, and for a variant that is phased in both, the same phase
+//!   relative to the previous compared variant of its phase block
+//! - discordant: different alleles
+//! - phase_discordant: the same alleles, but the phase is switched relative to the previous
+//!   compared variant of the phase block
+
+use errors::*;
+use hashbrown::HashMap;
+use population_af::trim_common_suffix;
+use rust_htslib::bcf;
+use rust_htslib::bcf::header::{HeaderRecord, TagLength, TagType};
+use rust_htslib::bcf::record::GenotypeAllele;
+use rust_htslib::bcf::Read as bcfread;
+use util::*;
+use variants_and_fragments::{Var, VarList};
+
+/// The INFO field with the genotype concordance of each sample
+pub static CONCORDANCE_INFO_ID: &str = "GTCONC";
+
+/// The genotype concordance of a variant with the genotype of the annotation VCF
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GenotypeConcordance {
+    Concordant,
+    Discordant,
+    PhaseDiscordant,
+}
+
+impl GenotypeConcordance {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            GenotypeConcordance::Concordant => "concordant",
+            GenotypeConcordance::Discordant => "discordant",
+            GenotypeConcordance::PhaseDiscordant => "phase_discordant",
+        }
+    }
+}
+
+/// An INFO field of the annotation VCF that is copied to the output records
+#[derive(Debug, Clone)]
+pub struct AnnotationField {
+    /// the ID of the field in the annotation VCF
+    pub source: String,
+    /// the ID of the field in the output VCF
+    pub dest: String,
+    pub tag_type: TagType,
+    pub tag_length: TagLength,
+    /// the INFO header line of the field in the output VCF, without the leading ```##```
+    pub header_line: String,
+}
+
+/// An annotation VCF and the fields to copy from it
+pub struct AnnotationVcf {
+    pub path: String,
+    pub fields: Vec<AnnotationField>,
+    /// the sample names of the VCF, whose genotypes are compared to the calls
+    pub sample_names: Vec<String>,
+}
+
+// the parts of an INFO header record, or None if one is missing
+fn info_header_parts(values: &Vec<(String, String)>) -> Option<(String, String, String, String)> {
+    let get = |key: &str| {
+        values
+            .iter()
+            .find(|&&(ref k, _)| k == key)
+            .map(|&(_, ref v)| v.clone())
+    };
+    Some((
+        get("ID")?,
+        get("Number")?,
+        get("Type")?,
+        get("Description")?,
+    ))
+}
+
+impl AnnotationVcf {
+    /// Opens an annotation VCF and selects the INFO fields to copy.
+    ///
+    /// # Arguments
+    /// - ```path```: the annotation VCF (bgzipped and indexed)
+    /// - ```field_list```: the fields to copy, as a comma separated list of INFO IDs, each
+    ///   optionally renamed in the output with ```ID:NEW_ID```. If ```None```, every INFO field is
+    ///   copied, except for those that have the ID of a field of the output VCF.
+    /// - ```reserved_ids```: the INFO IDs of the output VCF
+    ///
+    /// # Errors
+    /// - ```BCFOpenError```: the VCF can't be opened, e.g. because it is not indexed
+    /// - Error if a listed field isn't an INFO field of the VCF, or if an output ID is used twice
+    pub fn open(
+        path: &String,
+        field_list: Option<&str>,
+        reserved_ids: &[String],
+    ) -> Result<AnnotationVcf> {
+        let vcf = bcf::IndexedReader::from_path(path)
+            .chain_err(|| ErrorKind::BCFOpenError(path.clone()))?;
+        let header = vcf.header();
+
+        let mut info_records: Vec<(String, String, String, String)> = vec![];
+        for record in header.header_records() {
+            if let HeaderRecord::Info { values, .. } = record {
+                let values: Vec<(String, String)> = values.into_iter().collect();
+                if let Some(parts) = info_header_parts(&values) {
+                    info_records.push(parts);
+                }
+            }
+        }
+
+        let mut renames: Vec<(String, String)> = vec![];
+        match field_list {
+            Some(list) => {
+                for field in list.split(',').filter(|f| !f.is_empty()) {
+                    let mut split = field.splitn(2, ':');
+                    let source = split.next().unwrap_or("").to_string();
+                    let dest = split.next().unwrap_or(source.as_str()).to_string();
+                    ensure!(
+                        info_records.iter().any(|r| r.0 == source),
+                        "The annotation VCF {} has no INFO field {}.",
+                        path,
+                        source
+                    );
+                    ensure!(
+                        !reserved_ids.contains(&dest) && dest != CONCORDANCE_INFO_ID,
+                        "The annotation field {} has the ID of an INFO field of the output VCF. Rename it with {}:NEW_ID.",
+                        dest,
+                        source
+                    );
+                    renames.push((source, dest));
+                }
+            }
+            None => {
+                for r in &info_records {
+                    if reserved_ids.contains(&r.0) || r.0 == CONCORDANCE_INFO_ID {
+                        info!(
+                            "{} The INFO field {} of the annotation VCF is not copied, since the output VCF has a field with its ID (rename it with --annotation_fields).",
+                            print_time(),
+                            r.0
+                        );
+                        continue;
+                    }
+                    renames.push((r.0.clone(), r.0.clone()));
+                }
+            }
+        }
+
+        let mut fields: Vec<AnnotationField> = vec![];
+        for (source, dest) in renames {
+            ensure!(
+                !fields.iter().any(|f| f.dest == dest),
+                "The annotation field {} is used more than once.",
+                dest
+            );
+            let &(_, ref number, ref type_name, ref description) =
+                info_records.iter().find(|r| r.0 == source).unwrap();
+            let (tag_type, tag_length) = header
+                .info_type(source.as_bytes())
+                .chain_err(|| ErrorKind::BCFReadError)?;
+            let description = if description.starts_with('"') {
+                description.clone()
+            } else {
+                format!("\"{}\"", description)
+            };
+            fields.push(AnnotationField {
+                header_line: format!(
+                    "INFO=<ID={},Number={},Type={},Description={}>",
+                    dest, number, type_name, description
+                ),
+                source: source,
+                dest: dest,
+                tag_type: tag_type,
+                tag_length: tag_length,
+            });
+        }
+
+        let sample_names: Vec<String> = header
+            .samples()
+            .iter()
+            .map(|s| u8_to_string(s))
+            .collect::<Result<Vec<String>>>()?;
+
+        Ok(AnnotationVcf {
+            path: path.clone(),
+            fields: fields,
+            sample_names: sample_names,
+        })
+    }
+
+    /// The header lines of the copied fields and of the genotype concordance, without the
+    /// leading ```##```
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.fields.iter().map(|f| f.header_line.clone()).collect();
+        if !self.sample_names.is_empty() {
+            lines.push(format!("INFO=<ID={},Number=.,Type=String,Description=\"Genotype concordance of each sample with the annotation VCF (--annotation_vcf): concordant, discordant or phase_discordant (switched phase relative to the previous compared variant of the phase block).\">", CONCORDANCE_INFO_ID));
+        }
+        lines
+    }
+
+    /// Returns the index of the sample in the VCF whose genotypes are compared to those of
+    /// ```sample_name```. If the VCF has a single sample, it is used regardless of its name.
+    pub fn sample_index(&self, sample_name: &String) -> Option<usize> {
+        if self.sample_names.len() == 1 {
+            Some(0)
+        } else {
+            self.sample_names.iter().position(|s| s == sample_name)
+        }
+    }
+}
+
+/// The genotype of a sample in an annotation record
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotationGenotype {
+    /// the alleles of the genotype, as allele indices of the variant. An allele that the variant
+    /// doesn't have is ```None```.
+    pub alleles: Vec<Option<usize>>,
+    pub phased: bool,
+    /// the phase set (PS) of the genotype, if the VCF has one
+    pub phase_set: Option<i32>,
+}
+
+/// The annotations of an annotation VCF record
+#[derive(Debug, Clone)]
+struct AnnotationRecord {
+    alleles: Vec<String>,
+    /// the values of the copied fields, by output ID, in the order of the record alleles for the
+    /// per-allele fields. A flag has no value.
+    values: Vec<(String, Option<Vec<String>>)>,
+    /// the genotype of the compared sample (with the allele indices of the record)
+    genotype: Option<(Vec<Option<usize>>, bool, Option<i32>)>,
+}
+
+// formats the value of an INFO field of a record, or returns None if the record doesn't have it
+fn info_values(record: &mut bcf::Record, field: &AnnotationField) -> Result<Option<Vec<String>>> {
+    let tag = field.source.as_bytes();
+    let values: Option<Vec<String>> = match field.tag_type {
+        TagType::Flag => {
+            if record
+                .info(tag)
+                .flag()
+                .chain_err(|| ErrorKind::BCFReadError)?
+            {
+                Some(vec![])
+            } else {
+                None
+            }
+        }
+        TagType::Integer => record
+            .info(tag)
+            .integer()
+            .chain_err(|| ErrorKind::BCFReadError)?
+            .map(|v| {
+                v.iter()
+                    .map(|&x| {
+                        if x == i32::MIN {
+                            ".".to_string()
+                        } else {
+                            x.to_string()
+                        }
+                    })
+                    .collect()
+            }),
+        TagType::Float => record
+            .info(tag)
+            .float()
+            .chain_err(|| ErrorKind::BCFReadError)?
+            .map(|v| {
+                v.iter()
+                    .map(|&x| {
+                        if x.is_nan() {
+                            ".".to_string()
+                        } else {
+                            x.to_string()
+                        }
+                    })
+                    .collect()
+            }),
+        TagType::String => match record
+            .info(tag)
+            .string()
+            .chain_err(|| ErrorKind::BCFReadError)?
+        {
+            Some(v) => Some(
+                v.iter()
+                    .map(|s| u8_to_string(s))
+                    .collect::<Result<Vec<String>>>()?,
+            ),
+            None => None,
+        },
+    };
+    Ok(values)
+}
+
+// reads the annotation records of the VCF on a contig between two positions (0-based,
+// inclusive), by position
+fn read_annotation_records(
+    vcf: &mut bcf::IndexedReader,
+    annotation: &AnnotationVcf,
+    sample_ix: Option<usize>,
+    chrom: &String,
+    start_pos: u32,
+    end_pos: u32,
+) -> Result<HashMap<usize, Vec<AnnotationRecord>>> {
+    let mut records: HashMap<usize, Vec<AnnotationRecord>> = HashMap::new();
+    let rid = match vcf.header().name2rid(chrom.as_bytes()) {
+        Ok(rid) => rid,
+        Err(_) => {
+            return Ok(records);
+        }
+    };
+    vcf.fetch(rid, start_pos, end_pos)
+        .chain_err(|| ErrorKind::BCFReadError)?;
+
+    for r in vcf.records() {
+        let mut record = r.chain_err(|| ErrorKind::BCFReadError)?;
+        let alleles: Vec<String> = record
+            .alleles()
+            .iter()
+            .map(|a| u8_to_string(a).map(|s| s.to_ascii_uppercase()))
+            .collect::<Result<Vec<String>>>()?;
+
+        let mut values: Vec<(String, Option<Vec<String>>)> = vec![];
+        for field in &annotation.fields {
+            if let Some(v) = info_values(&mut record, field)? {
+                values.push((
+                    field.dest.clone(),
+                    if field.tag_type == TagType::Flag {
+                        None
+                    } else {
+                        Some(v)
+                    },
+                ));
+            }
+        }
+
+        let genotype = match sample_ix {
+            Some(ix) => {
+                let gt = record
+                    .genotypes()
+                    .chain_err(|| ErrorKind::BCFReadError)?
+                    .get(ix);
+                let mut gt_alleles: Vec<Option<usize>> = vec![];
+                // the first allele of a genotype is always marked unphased, so the phasing of
+                // the genotype is the phasing of the other alleles
+                let mut phased = gt.len() > 1;
+                for (i, a) in gt.iter().enumerate() {
+                    match *a {
+                        GenotypeAllele::Unphased(a) => {
+                            phased &= i == 0;
+                            gt_alleles.push(Some(a as usize));
+                        }
+                        GenotypeAllele::Phased(a) => gt_alleles.push(Some(a as usize)),
+                        _ => gt_alleles.push(None),
+                    }
+                }
+                let phase_set: Option<i32> = match record.format(b"PS").integer() {
+                    Ok(ps) => Some(ps[ix][0]),
+                    Err(_) => None,
+                };
+                // a missing genotype isn't compared
+                if gt_alleles.is_empty() || gt_alleles.contains(&None) {
+                    None
+                } else {
+                    Some((gt_alleles, phased, phase_set))
+                }
+            }
+            None => None,
+        };
+
+        records
+            .entry(record.pos() as usize)
+            .or_insert(vec![])
+            .push(AnnotationRecord {
+                alleles: alleles,
+                values: values,
+                genotype: genotype,
+            });
+    }
+
+    Ok(records)
+}
+
+// returns, for each allele of the record, the index of the same allele of the variant
+fn match_alleles(var_alleles: &Vec<String>, record_alleles: &Vec<String>) -> Vec<Option<usize>> {
+    let mut allele_map: Vec<Option<usize>> = vec![Some(0)];
+    for alt in record_alleles.iter().skip(1) {
+        let key = trim_common_suffix(&record_alleles[0], alt);
+        allele_map.push(
+            var_alleles
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|&(_, a)| trim_common_suffix(&var_alleles[0], a) == key)
+                .map(|(i, _)| i),
+        );
+    }
+    allele_map
+}
+
+// reorders the per-allele values of a field from the alleles of the record to those of the
+// variant, with a missing value for the alleles of the variant that the record doesn't have
+fn reorder_values(
+    values: &Vec<String>,
+    tag_length: TagLength,
+    allele_map: &Vec<Option<usize>>,
+    n_var_alleles: usize,
+) -> Vec<String> {
+    let skip = match tag_length {
+        TagLength::AltAlleles => 1,
+        TagLength::Alleles => 0,
+        _ => {
+            return values.clone();
+        }
+    };
+    let mut reordered: Vec<String> = vec![".".to_string(); n_var_alleles - skip];
+    for (record_ix, value) in values.iter().enumerate() {
+        if let Some(&Some(var_ix)) = allele_map.get(record_ix + skip) {
+            reordered[var_ix - skip] = value.clone();
+        }
+    }
+    reordered
+}
+
+/// Compares the genotype of a variant to the genotype of the annotation VCF
+///
+/// # Arguments
+/// - ```var```: the variant
+/// - ```genotype```: the genotype of the annotation VCF
+/// - ```last_orientations```: the phase orientation of the last compared phased variant of each
+///   phase block, by (contig, phase set of the variant, phase set of the annotation VCF). The
+///   entry of the phase block of a phased variant is updated.
+pub fn compare_genotype(
+    var: &Var,
+    genotype: &AnnotationGenotype,
+    last_orientations: &mut HashMap<(u32, usize, Option<i32>), bool>,
+) -> GenotypeConcordance {
+    let mut var_alleles: Vec<Option<usize>> =
+        vec![Some(var.genotype.0 as usize), Some(var.genotype.1 as usize)];
+    // a haploid genotype is compared as a homozygous one
+    let mut gt_alleles = genotype.alleles.clone();
+    if gt_alleles.len() == 1 {
+        gt_alleles.push(gt_alleles[0]);
+    }
+    var_alleles.sort();
+    gt_alleles.sort();
+    if var_alleles != gt_alleles {
+        return GenotypeConcordance::Discordant;
+    }
+
+    let ps = match var.phase_set {
+        Some(ps) if genotype.phased && var.genotype.0 != var.genotype.1 => ps,
+        _ => {
+            return GenotypeConcordance::Concordant;
+        }
+    };
+    // whether the first haplotype of the variant has the allele of the first haplotype of the
+    // annotation VCF
+    let orientation = Some(var.genotype.0 as usize) == genotype.alleles[0];
+    let last = last_orientations.insert((var.tid, ps, genotype.phase_set), orientation);
+    match last {
+        Some(o) if o != orientation => GenotypeConcordance::PhaseDiscordant,
+        _ => GenotypeConcordance::Concordant,
+    }
+}
+
+/// Copies the annotations of the matching records of the annotation VCF to the variants of a
+/// sample (```var.annotations```) and compares their genotypes (```var.genotype_concordance```).
+/// Only the records spanned by the variants of each contig are read.
+///
+/// # Arguments
+/// - ```varlist```: the variants of the sample, sorted by position
+/// - ```annotation```: the annotation VCF
+/// - ```sample_name```: the name of the sample, to find its genotypes in the annotation VCF
+///
+/// # Returns
+/// Returns the number of variants that match a record of the annotation VCF
+///
+/// # Errors
+/// - ```BCFOpenError```: error opening the VCF
+/// - ```BCFReadError```: error reading the records of the VCF
+pub fn annotate_variants(
+    varlist: &mut VarList,
+    annotation: &AnnotationVcf,
+    sample_name: &String,
+) -> Result<usize> {
+    let mut vcf = bcf::IndexedReader::from_path(&annotation.path)
+        .chain_err(|| ErrorKind::BCFOpenError(annotation.path.clone()))?;
+    let sample_ix = annotation.sample_index(sample_name);
+    let mut last_orientations: HashMap<(u32, usize, Option<i32>), bool> = HashMap::new();
+    let mut n_matched = 0;
+    let mut i = 0;
+
+    while i < varlist.lst.len() {
+        // the variants of the same contig are consecutive
+        let tid = varlist.lst[i].tid;
+        let mut j = i;
+        while j < varlist.lst.len() && varlist.lst[j].tid == tid {
+            j += 1;
+        }
+
+        let records = read_annotation_records(
+            &mut vcf,
+            annotation,
+            sample_ix,
+            &varlist.target_names[tid as usize],
+            varlist.lst[i].pos0 as u32,
+            varlist.lst[j - 1].pos0 as u32,
+        )?;
+        for var in varlist.lst[i..j].iter_mut() {
+            var.annotations = vec![];
+            // a site that isn't in the VCF is homozygous reference there
+            let mut genotype: Option<AnnotationGenotype> = sample_ix.map(|_| AnnotationGenotype {
+                alleles: vec![Some(0), Some(0)],
+                phased: false,
+                phase_set: None,
+            });
+
+            let matched = records.get(&var.pos0).and_then(|recs| {
+                recs.iter()
+                    .map(|r| (r, match_alleles(&var.alleles, &r.alleles)))
+                    .find(|&(_, ref allele_map)| allele_map[1..].iter().any(|a| a.is_some()))
+            });
+            if let Some((record, allele_map)) = matched {
+                n_matched += 1;
+                for &(ref dest, ref values) in &record.values {
+                    let field = annotation.fields.iter().find(|f| f.dest == *dest).unwrap();
+                    var.annotations.push((
+                        dest.clone(),
+                        values.as_ref().map(|v| {
+                            reorder_values(v, field.tag_length, &allele_map, var.alleles.len())
+                                .join(",")
+                        }),
+                    ));
+                }
+                genotype =
+                    record
+                        .genotype
+                        .as_ref()
+                        .map(|&(ref alleles, phased, ps)| AnnotationGenotype {
+                            alleles: alleles
+                                .iter()
+                                .map(|a| a.and_then(|a| allele_map.get(a).cloned().unwrap_or(None)))
+                                .collect(),
+                            phased: phased,
+                            phase_set: ps,
+                        });
+            }
+
+            var.genotype_concordance = genotype
+                .as_ref()
+                .map(|gt| compare_genotype(var, gt, &mut last_orientations));
+        }
+
+        i = j;
+    }
+
+    Ok(n_matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genotype_probs::Genotype;
+
+    fn var(pos0: usize, alleles: &[&str], genotype: Genotype, phase_set: Option<usize>) -> Var {
+        let mut var = Var::new(0, pos0, alleles.iter().map(|a| a.to_string()).collect());
+        var.genotype = genotype;
+        var.phase_set = phase_set;
+        var
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_match_alleles() {
+        // the record has the alleles in another order, padded for a deletion, and one that the
+        // variant doesn't have
+        let allele_map = match_alleles(
+            &strings(&["A", "G", "T"]),
+            &strings(&["AC", "C", "TC", "GC"]),
+        );
+        assert_eq!(allele_map, vec![Some(0), None, Some(2), Some(1)]);
+
+        // Number=A values follow the alleles of the variant
+        let values = strings(&["0.1", "0.2", "0.3"]);
+        assert_eq!(
+            reorder_values(&values, TagLength::AltAlleles, &allele_map, 3),
+            strings(&["0.3", "0.2"])
+        );
+        assert_eq!(
+            reorder_values(
+                &strings(&["5", "1", "2", "3"]),
+                TagLength::Alleles,
+                &allele_map,
+                3
+            ),
+            strings(&["5", "3", "2"])
+        );
+        assert_eq!(
+            reorder_values(
+                &strings(&["Pathogenic"]),
+                TagLength::Fixed(1),
+                &allele_map,
+                3
+            ),
+            strings(&["Pathogenic"])
+        );
+        let allele_map = match_alleles(&strings(&["A", "G", "T"]), &strings(&["A", "G"]));
+        assert_eq!(
+            reorder_values(&strings(&["0.1"]), TagLength::AltAlleles, &allele_map, 3),
+            strings(&["0.1", "."])
+        );
+    }
+
+    #[test]
+    fn test_compare_genotype() {
+        let gt = |a0: usize, a1: usize, phased: bool| AnnotationGenotype {
+            alleles: vec![Some(a0), Some(a1)],
+            phased: phased,
+            phase_set: Some(1),
+        };
+        let mut last: HashMap<(u32, usize, Option<i32>), bool> = HashMap::new();
+
+        // unphased genotypes are compared as sets of alleles
+        let v = var(10, &["A", "G"], Genotype(1, 0), None);
+        assert_eq!(
+            compare_genotype(&v, &gt(0, 1, false), &mut last),
+            GenotypeConcordance::Concordant
+        );
+        assert_eq!(
+            compare_genotype(&v, &gt(1, 1, false), &mut last),
+            GenotypeConcordance::Discordant
+        );
+        let haploid = AnnotationGenotype {
+            alleles: vec![Some(1)],
+            phased: false,
+            phase_set: None,
+        };
+        let v = var(10, &["A", "G"], Genotype(1, 1), None);
+        assert_eq!(
+            compare_genotype(&v, &haploid, &mut last),
+            GenotypeConcordance::Concordant
+        );
+
+        // the phase of the first variant of a block sets the orientation, which is flipped
+        // relative to the annotation VCF, and the third variant switches it
+        let v = var(20, &["A", "G"], Genotype(1, 0), Some(20));
+        assert_eq!(
+            compare_genotype(&v, &gt(0, 1, true), &mut last),
+            GenotypeConcordance::Concordant
+        );
+        let v = var(30, &["C", "T"], Genotype(0, 1), Some(20));
+        assert_eq!(
+            compare_genotype(&v, &gt(1, 0, true), &mut last),
+            GenotypeConcordance::Concordant
+        );
+        let v = var(40, &["C", "T"], Genotype(0, 1), Some(20));
+        assert_eq!(
+            compare_genotype(&v, &gt(0, 1, true), &mut last),
+            GenotypeConcordance::PhaseDiscordant
+        );
+        let v = var(50, &["C", "T"], Genotype(1, 0), Some(20));
+        assert_eq!(
+            compare_genotype(&v, &gt(1, 0, true), &mut last),
+            GenotypeConcordance::Concordant
+        );
+        // another phase block
+        let v = var(60, &["C", "T"], Genotype(1, 0), Some(60));
+        assert_eq!(
+            compare_genotype(&v, &gt(0, 1, true), &mut last),
+            GenotypeConcordance::Concordant
+        );
+    }
+}
@@ -0,0 +1,501 @@
+//! Merging of VCF files that were called separately, e.g. one per contig by the processes of
+//! ```--threads``` or by the jobs of a cluster (```longshot merge```).
+//!
+//! The records of the files are merged in the order of the contigs of the header and their
+//! position, under a header that has the meta-information lines of all of them, and written as a
+//! VCF, a bgzip-compressed VCF with a tabix index, or a BCF with a CSI index, depending on the
+//! extension of the output file. The phase sets are only unique within a file, so a phase set that another file already used on
+//! the same contig is renumbered to the position of its first variant.
+
+use errors::*;
+use hashbrown::HashMap;
+use rust_htslib::bcf;
+use rust_htslib::bcf::Read as BcfRead;
+use rust_htslib::htslib;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+use util::*;
+
+/// The phase sets of the merged files, by contig, and those that were renumbered
+#[derive(Default)]
+struct PhaseSets {
+    /// the file that each phase set of a contig belongs to
+    used: HashMap<String, HashMap<u64, usize>>,
+    /// the new phase set of a phase set of a file and contig
+    renumbered: HashMap<(usize, String, u64), u64>,
+}
+
+impl PhaseSets {
+    /// The phase set in the merged file of phase set ```ps``` of file ```file```, on a record at
+    /// position ```pos``` of contig ```chrom```
+    fn get(&mut self, file: usize, chrom: &str, ps: u64, pos: u64) -> u64 {
+        if let Some(&new_ps) = self.renumbered.get(&(file, chrom.to_string(), ps)) {
+            return new_ps;
+        }
+        let used = self
+            .used
+            .entry(chrom.to_string())
+            .or_insert_with(HashMap::new);
+        let mut new_ps = ps;
+        if used.get(&ps).map_or(false, |&f| f != file) {
+            new_ps = pos;
+            while used.contains_key(&new_ps) {
+                new_ps += 1;
+            }
+        }
+        used.insert(new_ps, file);
+        self.renumbered
+            .insert((file, chrom.to_string(), ps), new_ps);
+        new_ps
+    }
+
+    fn n_renumbered(&self) -> usize {
+        self.renumbered
+            .iter()
+            .filter(|&(&(_, _, ps), &new_ps)| ps != new_ps)
+            .count()
+    }
+}
+
+/// Replaces the PS of each sample of a VCF record of file ```file``` with its phase set in the
+/// merged file
+fn renumber_phase_sets(line: &str, file: usize, phase_sets: &mut PhaseSets) -> String {
+    let mut cols: Vec<String> = line.split('\t').map(|c| c.to_string()).collect();
+    if cols.len() < 10 {
+        return line.to_string();
+    }
+    let ps_ix = match cols[8].split(':').position(|f| f == "PS") {
+        Some(ix) => ix,
+        None => return line.to_string(),
+    };
+    let (chrom, pos) = (cols[0].clone(), cols[1].parse::<u64>().unwrap_or(0));
+    for col in cols.iter_mut().skip(9) {
+        let mut fields: Vec<String> = col.split(':').map(|f| f.to_string()).collect();
+        let new_ps = match fields.get(ps_ix).and_then(|ps| ps.parse::<u64>().ok()) {
+            Some(ps) => phase_sets.get(file, &chrom, ps, pos),
+            None => continue,
+        };
+        fields[ps_ix] = new_ps.to_string();
+        *col = fields.join(":");
+    }
+    cols.join("\t")
+}
+
+/// The ID of a structured meta-information line, e.g. ```INFO/DP``` for ```##INFO=<ID=DP,...>```
+fn meta_line_id(line: &str) -> Option<String> {
+    if !line.starts_with("##") {
+        return None;
+    }
+    let start = line.find("=<ID=")?;
+    let key = &line[2..start];
+    if key.contains('=') {
+        return None;
+    }
+    let id = line[start + 5..].split(|c| c == ',' || c == '>').next()?;
+    Some(format!("{}/{}", key, id))
+}
+
+/// Merges the headers of the VCF files: the header of the first one, without its phasing metrics,
+/// plus the structured meta-information lines (```INFO```, ```FORMAT```, ```FILTER```,
+/// ```contig```...) of the others that it doesn't have.
+///
+/// # Errors
+/// Fails if a file doesn't have a ```#CHROM``` line, or has other samples than the first one.
+fn merge_headers(vcf_files: &[String], headers: &[Vec<String>]) -> Result<Vec<String>> {
+    let mut merged: Vec<String> = vec![];
+    let mut ids: Vec<String> = vec![];
+    for (i, header) in headers.iter().enumerate() {
+        ensure!(
+            header.last().map_or(false, |l| l.starts_with("#CHROM")),
+            "The VCF {} doesn't have a #CHROM header line.",
+            vcf_files[i]
+        );
+        ensure!(
+            header.last() == headers[0].last(),
+            "The samples of the VCF {} differ from those of {}.",
+            vcf_files[i],
+            vcf_files[0]
+        );
+        for line in &header[..header.len() - 1] {
+            // the phasing metrics of the header are those of a single file
+            if line.starts_with("##longshot_") {
+                continue;
+            }
+            match meta_line_id(line) {
+                Some(id) => {
+                    if ids.contains(&id) {
+                        continue;
+                    }
+                    ids.push(id);
+                }
+                None if i > 0 => continue,
+                None => {}
+            }
+            merged.push(line.clone());
+        }
+    }
+    merged.push(headers[0].last().unwrap().clone());
+    Ok(merged)
+}
+
+/// The contig and the position of a VCF record
+fn record_locus(line: &str) -> (&str, u64) {
+    let mut cols = line.split('\t');
+    let chrom = cols.next().unwrap_or("");
+    let pos = cols.next().and_then(|p| p.parse::<u64>().ok()).unwrap_or(0);
+    (chrom, pos)
+}
+
+/// A VCF file opened for merging: its header, its first record and the lines after it
+struct VcfLines {
+    header: Vec<String>,
+    next: Option<String>,
+    lines: Lines<BufReader<BgzfFile>>,
+}
+
+fn open_vcf(vcf_file: &str) -> Result<VcfLines> {
+    ensure!(
+        !vcf_file.ends_with(".bcf"),
+        "The VCF {} is a BCF file, which can't be merged. Convert it to a VCF with bcftools view.",
+        vcf_file
+    );
+    let mut lines = BufReader::new(BgzfFile::open_stream(vcf_file)?).lines();
+    let mut header: Vec<String> = vec![];
+    let mut next: Option<String> = None;
+    while let Some(line) = lines.next() {
+        let line = line.chain_err(|| format!("Could not read the VCF {}.", vcf_file))?;
+        if !line.starts_with('#') {
+            next = Some(line);
+            break;
+        }
+        header.push(line);
+    }
+    Ok(VcfLines {
+        header: header,
+        next: next,
+        lines: lines,
+    })
+}
+
+/// Writes the merged VCF, compressed with bgzip if ```output_vcf_file``` ends with ```.gz```
+fn create_vcf_writer(output_vcf_file: &str) -> Result<Box<dyn Write>> {
+    if output_vcf_file.ends_with(".gz") {
+        Ok(Box::new(BufWriter::new(BgzfWriter::create(
+            output_vcf_file,
+        )?)))
+    } else {
+        let f = File::create(output_vcf_file)
+            .chain_err(|| ErrorKind::CreateFileError(output_vcf_file.to_string()))?;
+        Ok(Box::new(BufWriter::new(f)))
+    }
+}
+
+/// Indexes a bgzip-compressed VCF (```.tbi```) or a BCF (```.csi```)
+pub fn index_vcf(vcf_file: &str) -> Result<()> {
+    let c_path = CString::new(vcf_file).chain_err(|| format!("Invalid file name {}.", vcf_file))?;
+    // tabix indices can't be written for BCF files
+    let min_shift = if vcf_file.ends_with(".bcf") { 14 } else { 0 };
+    let ret = unsafe { htslib::bcf_index_build(c_path.as_ptr(), min_shift) };
+    ensure!(ret == 0, "Could not index the VCF {}.", vcf_file);
+    Ok(())
+}
+
+/// Converts a bgzip-compressed VCF to a BCF
+fn convert_to_bcf(vcf_gz_file: &str, bcf_file: &str) -> Result<()> {
+    let mut reader = bcf::Reader::from_path(vcf_gz_file)
+        .chain_err(|| format!("Could not open the VCF {}.", vcf_gz_file))?;
+    let header = bcf::Header::from_template(reader.header());
+    let mut writer = bcf::Writer::from_path(bcf_file, &header, false, bcf::Format::BCF)
+        .chain_err(|| ErrorKind::CreateFileError(bcf_file.to_string()))?;
+    for record in reader.records() {
+        let mut record = record.chain_err(|| format!("Could not read the VCF {}.", vcf_gz_file))?;
+        writer.translate(&mut record);
+        writer
+            .write(&record)
+            .chain_err(|| ErrorKind::FileWriteError(bcf_file.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Compresses a VCF with bgzip to ```vcf_gz_file``` and indexes it, and removes the uncompressed
+/// VCF
+pub fn compress_and_index_vcf(vcf_file: &Path, vcf_gz_file: &Path) -> Result<()> {
+    let vcf_gz_str = vcf_gz_file.to_string_lossy().to_string();
+    {
+        let f = File::open(vcf_file)
+            .chain_err(|| format!("Could not open the VCF {}.", vcf_file.display()))?;
+        let mut out = BufWriter::new(BgzfWriter::create(&vcf_gz_str)?);
+        for line in BufReader::new(f).lines() {
+            let line =
+                line.chain_err(|| format!("Could not read the VCF {}.", vcf_file.display()))?;
+            writeln!(out, "{}", line)
+                .chain_err(|| ErrorKind::FileWriteError(vcf_gz_str.clone()))?;
+        }
+        out.flush()
+            .chain_err(|| ErrorKind::FileWriteError(vcf_gz_str.clone()))?;
+    }
+    index_vcf(&vcf_gz_str)?;
+    fs::remove_file(vcf_file)
+        .chain_err(|| format!("Could not remove the VCF {}.", vcf_file.display()))?;
+    Ok(())
+}
+
+/// The number of records and of renumbered phase sets of a merged VCF
+pub struct VcfMergeSummary {
+    pub n_records: usize,
+    pub n_renumbered_phase_sets: usize,
+}
+
+/// Merges VCF files (plain or compressed with gzip or bgzip) into ```output_vcf_file```.
+///
+/// The records of the files are merged in the order of the ```##contig``` lines of the merged
+/// header (contigs without one come after them, in the order of the files) and their position, so
+/// the regions of the files can also alternate, like the batches of ```--scheduler windows```. The
+/// output is a bgzip-compressed VCF with a tabix index if its name ends with ```.gz```, a BCF with
+/// a CSI index if it ends with ```.bcf```, and a VCF otherwise.
+///
+/// # Errors
+/// - A file can't be read, or has other samples than the first one
+/// - Two files have records at the same position, or a file isn't sorted
+pub fn merge_vcfs(vcf_files: &[String], output_vcf_file: &str) -> Result<VcfMergeSummary> {
+    ensure!(!vcf_files.is_empty(), "No VCF files to merge.");
+    let mut files: Vec<VcfLines> = vec![];
+    for vcf_file in vcf_files {
+        files.push(open_vcf(vcf_file)?);
+    }
+    let headers: Vec<Vec<String>> = files.iter().map(|f| f.header.clone()).collect();
+    let header = merge_headers(vcf_files, &headers)?;
+
+    let mut contigs: Vec<String> = header
+        .iter()
+        .filter_map(|line| meta_line_id(line))
+        .filter(|id| id.starts_with("contig/"))
+        .map(|id| id["contig/".len()..].to_string())
+        .collect();
+    for f in &files {
+        if let Some(ref line) = f.next {
+            let chrom = record_locus(line).0.to_string();
+            if !contigs.contains(&chrom) {
+                contigs.push(chrom);
+            }
+        }
+    }
+    let rank = |line: &str| -> (usize, u64) {
+        let (chrom, pos) = record_locus(line);
+        let contig = contigs
+            .iter()
+            .position(|c| c == chrom)
+            .unwrap_or(contigs.len());
+        (contig, pos)
+    };
+
+    let (bcf_file, written_file) = if output_vcf_file.ends_with(".bcf") {
+        let tmp = format!("{}.tmp.vcf.gz", output_vcf_file);
+        (Some(output_vcf_file.to_string()), tmp)
+    } else {
+        (None, output_vcf_file.to_string())
+    };
+    let mut phase_sets = PhaseSets::default();
+    let mut n_records = 0;
+    {
+        let mut out = create_vcf_writer(&written_file)?;
+        for line in &header {
+            writeln!(out, "{}", line)
+                .chain_err(|| ErrorKind::FileWriteError(written_file.clone()))?;
+        }
+        // the last record written, and the file it came from
+        let mut last: Option<((usize, u64), usize)> = None;
+        loop {
+            // the file with the first of the next records (the first file if several have a
+            // record at the same position, which is an error)
+            let first = files
+                .iter()
+                .enumerate()
+                .filter_map(|(i, f)| f.next.as_ref().map(|line| (rank(line), i)))
+                .min();
+            let (r, i) = match first {
+                Some(first) => first,
+                None => break,
+            };
+            if let Some((last_rank, last_file)) = last {
+                ensure!(
+                    r != last_rank || last_file == i,
+                    "The VCFs {} and {} both have records at the same position. Only VCF files of separate regions can be merged.",
+                    vcf_files[last_file],
+                    vcf_files[i]
+                );
+            }
+            last = Some((r, i));
+            let f = &mut files[i];
+            if let Some(line) = f.next.take() {
+                writeln!(out, "{}", renumber_phase_sets(&line, i, &mut phase_sets))
+                    .chain_err(|| ErrorKind::FileWriteError(written_file.clone()))?;
+                n_records += 1;
+            }
+            f.next = match f.lines.next() {
+                Some(l) => {
+                    let l = l.chain_err(|| format!("Could not read the VCF {}.", vcf_files[i]))?;
+                    ensure!(
+                        rank(&l) >= r,
+                        "The records of the VCF {} are not sorted.",
+                        vcf_files[i]
+                    );
+                    Some(l)
+                }
+                None => None,
+            };
+        }
+        out.flush()
+            .chain_err(|| ErrorKind::FileWriteError(written_file.clone()))?;
+    }
+
+    if let Some(bcf_file) = bcf_file {
+        convert_to_bcf(&written_file, &bcf_file)?;
+        fs::remove_file(&written_file)
+            .chain_err(|| format!("Could not remove the temporary VCF {}.", written_file))?;
+        index_vcf(&bcf_file)?;
+    } else if written_file.ends_with(".gz") {
+        index_vcf(&written_file)?;
+    }
+    Ok(VcfMergeSummary {
+        n_records: n_records,
+        n_renumbered_phase_sets: phase_sets.n_renumbered(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_renumber_phase_sets() {
+        let mut phase_sets = PhaseSets::default();
+        let rec =
+            |pos: u64, ps: &str| format!("chr1\t{}\t.\tA\tG\t50\tPASS\t.\tGT:PS\t0|1:{}", pos, ps);
+        // the phase sets of the first file are kept
+        assert_eq!(
+            renumber_phase_sets(&rec(100, "100"), 0, &mut phase_sets),
+            rec(100, "100")
+        );
+        assert_eq!(
+            renumber_phase_sets(&rec(150, "100"), 0, &mut phase_sets),
+            rec(150, "100")
+        );
+        assert_eq!(
+            renumber_phase_sets(&rec(180, "."), 0, &mut phase_sets),
+            rec(180, ".")
+        );
+        // a phase set of another file that was already used is renumbered to its first position
+        assert_eq!(
+            renumber_phase_sets(&rec(500, "100"), 1, &mut phase_sets),
+            rec(500, "500")
+        );
+        assert_eq!(
+            renumber_phase_sets(&rec(600, "100"), 1, &mut phase_sets),
+            rec(600, "500")
+        );
+        // and the phase sets of the other file that collide with the renumbered one, too
+        assert_eq!(
+            renumber_phase_sets(&rec(700, "500"), 2, &mut phase_sets),
+            rec(700, "700")
+        );
+        assert_eq!(phase_sets.n_renumbered(), 2);
+        // records without PS are left as they are
+        let unphased = "chr1\t800\t.\tA\tG\t50\tPASS\t.\tGT\t0/1";
+        assert_eq!(renumber_phase_sets(unphased, 1, &mut phase_sets), unphased);
+    }
+
+    #[test]
+    fn test_merge_vcfs() {
+        let dir = env::temp_dir().join(format!("longshot_test_vcf_merge_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let header = |extra: &str| {
+            format!(
+                "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n##contig=<ID=chr2,length=1000>\n{}##longshot_phase_blocks=1\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS\n",
+                extra
+            )
+        };
+        let rec = |chrom: &str, pos: u64, ps: u64| {
+            format!(
+                "{}\t{}\t.\tA\tG\t50\tPASS\t.\tGT:PS\t0|1:{}\n",
+                chrom, pos, ps
+            )
+        };
+        let vcf_files: Vec<String> = ["chr2.vcf", "chr1.vcf"]
+            .iter()
+            .map(|f| dir.join(f).to_string_lossy().to_string())
+            .collect();
+        fs::write(
+            &vcf_files[0],
+            format!(
+                "{}{}{}",
+                header("##INFO=<ID=X,Number=1,Type=Integer,Description=\"x\">\n"),
+                rec("chr2", 10, 1),
+                rec("chr2", 20, 1)
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &vcf_files[1],
+            format!("{}{}", header(""), rec("chr1", 5, 1)),
+        )
+        .unwrap();
+
+        let out = dir.join("out.vcf").to_string_lossy().to_string();
+        let summary = merge_vcfs(&vcf_files, &out).unwrap();
+        assert_eq!(summary.n_records, 3);
+        // the phase sets are on different contigs, so they don't collide
+        assert_eq!(summary.n_renumbered_phase_sets, 0);
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            format!(
+                "{}{}{}{}",
+                header("##INFO=<ID=X,Number=1,Type=Integer,Description=\"x\">\n")
+                    .replace("##longshot_phase_blocks=1\n", ""),
+                rec("chr1", 5, 1),
+                rec("chr2", 10, 1),
+                rec("chr2", 20, 1)
+            )
+        );
+
+        // the records of files whose regions alternate are merged by their position, like those
+        // of the batches of --scheduler windows, with the same header as the files of contigs
+        fs::write(
+            &vcf_files[1],
+            format!("{}{}{}", header(""), rec("chr1", 5, 1), rec("chr2", 15, 15)),
+        )
+        .unwrap();
+        let summary = merge_vcfs(&vcf_files, &out).unwrap();
+        assert_eq!(summary.n_records, 4);
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            format!(
+                "{}{}{}{}{}",
+                header("##INFO=<ID=X,Number=1,Type=Integer,Description=\"x\">\n")
+                    .replace("##longshot_phase_blocks=1\n", ""),
+                rec("chr1", 5, 1),
+                rec("chr2", 10, 1),
+                rec("chr2", 15, 15),
+                rec("chr2", 20, 1)
+            )
+        );
+
+        // but not the records of two files at the same position, or of a file that isn't sorted
+        fs::write(
+            &vcf_files[1],
+            format!("{}{}", header(""), rec("chr2", 20, 20)),
+        )
+        .unwrap();
+        assert!(merge_vcfs(&vcf_files, &out).is_err());
+        fs::write(
+            &vcf_files[1],
+            format!("{}{}{}", header(""), rec("chr2", 15, 15), rec("chr1", 5, 1)),
+        )
+        .unwrap();
+        assert!(merge_vcfs(&vcf_files, &out).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
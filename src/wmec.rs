@@ -0,0 +1,325 @@
+//! Haplotype assembly by weighted minimum error correction (wMEC), an alternative to the max-cut
+//! heuristic of HapCUT2 (```--phaser wmec```).
+//!
+//! The fragments are phased exactly with a dynamic program over the variants, as in WhatsHap: at
+//! each variant, every bipartition of the fragments that span it is scored with the total weight
+//! of the allele calls that must be corrected for the two haplotypes to be heterozygous there, plus
+//! the best score of the compatible bipartitions at the previous variant. The weight of a call is
+//! its log-likelihood ratio ln((1 - p) / p), where p is the probability that the call is wrong, so
+//! that the optimal bipartition also gives the most likely pair of heterozygous haplotypes.
+//!
+//! The runtime and memory of each variant grow with 2^(number of fragments that span it), so the
+//! fragments are first selected such that at most ```max_cov``` of them span each variant,
+//! preferring the fragments with the most allele calls.
+
+use bio::stats::{LogProb, Prob};
+use std::f64;
+use variants_and_fragments::Fragment;
+
+/// The allele calls of a fragment at the phased variants, as (variant index, allele, weight),
+/// sorted by variant index
+type WmecCalls = Vec<(usize, u8, f64)>;
+
+/// Selects the fragments to phase with, such that at most ```max_cov``` selected fragments span
+/// each phased variant (from their first to their last call). The fragments with the most calls
+/// are selected first, and ties are broken by the order of the fragments.
+fn select_fragments(
+    frags: &Vec<WmecCalls>,
+    phase_variant: &Vec<bool>,
+    max_cov: usize,
+) -> Vec<bool> {
+    let mut order: Vec<usize> = (0..frags.len()).collect();
+    order.sort_by_key(|&f| (usize::max_value() - frags[f].len(), f));
+
+    let mut coverage: Vec<usize> = vec![0; phase_variant.len()];
+    let mut selected: Vec<bool> = vec![false; frags.len()];
+    for f in order {
+        let (first, last) = (frags[f][0].0, frags[f][frags[f].len() - 1].0);
+        if (first..last + 1).any(|v| phase_variant[v] && coverage[v] >= max_cov) {
+            continue;
+        }
+        for v in first..last + 1 {
+            if phase_variant[v] {
+                coverage[v] += 1;
+            }
+        }
+        selected[f] = true;
+    }
+    selected
+}
+
+fn find_root(parent: &mut Vec<usize>, mut v: usize) -> usize {
+    while parent[v] != v {
+        parent[v] = parent[parent[v]];
+        v = parent[v];
+    }
+    v
+}
+
+/// The dynamic program of one column (phased variant) of a component
+struct WmecColumn {
+    /// the variant index
+    var_ix: usize,
+    /// the fragments that span the column, in the order of the bits of the bipartitions
+    active: Vec<usize>,
+    /// (bit in this column, bit in the previous column) of the fragments active in both
+    shared: Vec<(usize, usize)>,
+    /// for each bipartition of the shared fragments, the best compatible bipartition of the
+    /// previous column
+    back: Vec<u32>,
+}
+
+/// Projects bipartition ```mask``` onto the shared fragments, using the first (```prev``` false)
+/// or the second (```prev``` true) bit of each pair in ```shared```
+fn project(mask: usize, shared: &Vec<(usize, usize)>, prev: bool) -> usize {
+    let mut proj = 0;
+    for (m, &(cur_bit, prev_bit)) in shared.iter().enumerate() {
+        let bit = if prev { prev_bit } else { cur_bit };
+        if mask & (1 << bit) != 0 {
+            proj |= 1 << m;
+        }
+    }
+    proj
+}
+
+/// The weight of the calls that disagree with haplotype 1 having allele 0 (and haplotype 2 allele
+/// 1) at a column, for each bipartition of its active fragments, and the total weight of its calls
+fn column_disagreement(
+    active: &Vec<usize>,
+    calls: &Vec<(usize, u8, f64)>,
+    frag_bit: &Vec<usize>,
+) -> (Vec<f64>, f64) {
+    let mut delta: Vec<f64> = vec![0.0; active.len()];
+    let mut base = 0.0;
+    let mut total = 0.0;
+    for &(f, allele, weight) in calls {
+        // a call on haplotype 1 (bit unset) disagrees if it is allele 1, and a call on haplotype
+        // 2 if it is allele 0
+        if allele == 1 {
+            base += weight;
+            delta[frag_bit[f]] = -weight;
+        } else {
+            delta[frag_bit[f]] = weight;
+        }
+        total += weight;
+    }
+    let mut disagreement: Vec<f64> = vec![base; 1 << active.len()];
+    for mask in 1..disagreement.len() {
+        disagreement[mask] =
+            disagreement[mask & (mask - 1)] + delta[mask.trailing_zeros() as usize];
+    }
+    (disagreement, total)
+}
+
+/// Phases the fragments with the wMEC dynamic program, with the same inputs and outputs as
+/// ```call_hapcut2```
+///
+/// # Arguments
+/// - ```flist```: the fragments, including any pseudo-fragments of a known phase
+/// - ```phase_variant```: whether each variant is a heterozygous variant to phase
+/// - ```max_p_miscall```: the maximum probability of an allele miscall for a call to be used
+/// - ```max_cov```: the maximum number of fragments that span a variant (at most 20)
+/// - ```hap1```: the first haplotype (```'0'``` or ```'1'``` for each phased variant), which is
+///   updated for the variants that the fragments link to another variant
+/// - ```phase_sets```: set to the index of the first variant of the phase block of each variant
+///   that the fragments link to another variant
+pub fn wmec_phase(
+    flist: &[&Fragment],
+    phase_variant: &Vec<bool>,
+    max_p_miscall: f64,
+    max_cov: usize,
+    hap1: &mut Vec<u8>,
+    phase_sets: &mut Vec<i32>,
+) {
+    assert!(max_cov >= 1 && max_cov <= 20);
+    let ln_max_p_miscall = LogProb::from(Prob(max_p_miscall));
+
+    // the fragments with at least two calls at the phased variants
+    let frags: Vec<WmecCalls> = flist
+        .iter()
+        .filter_map(|f| {
+            let mut calls: WmecCalls = f
+                .calls
+                .iter()
+                .filter(|c| phase_variant[c.var_ix] && c.qual < ln_max_p_miscall && c.allele <= 1)
+                .map(|c| (c.var_ix, c.allele, *c.one_minus_qual - *c.qual))
+                .collect();
+            calls.sort_by_key(|c| c.0);
+            calls.dedup_by_key(|c| c.0);
+            if calls.len() >= 2 {
+                Some(calls)
+            } else {
+                None
+            }
+        })
+        .collect();
+    let selected = select_fragments(&frags, phase_variant, max_cov);
+
+    // the phase blocks are the connected components of the variants linked by the fragments
+    let n_var = phase_variant.len();
+    let mut parent: Vec<usize> = (0..n_var).collect();
+    let mut col_calls: Vec<Vec<(usize, u8, f64)>> = vec![vec![]; n_var];
+    for (f, calls) in frags.iter().enumerate() {
+        if !selected[f] {
+            continue;
+        }
+        for &(v, allele, weight) in calls {
+            col_calls[v].push((f, allele, weight));
+            let (a, b) = (
+                find_root(&mut parent, v),
+                find_root(&mut parent, calls[0].0),
+            );
+            parent[a] = b;
+        }
+    }
+    let mut components: Vec<Vec<usize>> = vec![vec![]; n_var];
+    for v in 0..n_var {
+        if !col_calls[v].is_empty() {
+            let root = find_root(&mut parent, v);
+            components[root].push(v);
+        }
+    }
+
+    // the last variant spanned by each fragment
+    let last_col: Vec<usize> = frags.iter().map(|calls| calls[calls.len() - 1].0).collect();
+
+    for columns in components.iter().filter(|c| !c.is_empty()) {
+        // forward pass
+        let mut dp_columns: Vec<WmecColumn> = vec![];
+        let mut active: Vec<usize> = vec![];
+        let mut frag_bit: Vec<usize> = vec![0; frags.len()];
+        let mut cost: Vec<f64> = vec![0.0];
+        for &v in columns {
+            let prev_active = active.clone();
+            let prev_bit: Vec<(usize, usize)> =
+                prev_active.iter().map(|&f| (f, frag_bit[f])).collect();
+            active.retain(|&f| last_col[f] >= v);
+            for &(f, _, _) in &col_calls[v] {
+                if frags[f][0].0 == v {
+                    active.push(f);
+                }
+            }
+            for (bit, &f) in active.iter().enumerate() {
+                frag_bit[f] = bit;
+            }
+            let shared: Vec<(usize, usize)> = prev_bit
+                .iter()
+                .filter(|&&(f, _)| last_col[f] >= v)
+                .map(|&(f, bit)| (frag_bit[f], bit))
+                .collect();
+
+            // the best bipartition of the previous column for each bipartition of the shared
+            // fragments
+            let mut best_prev: Vec<f64> = vec![f64::INFINITY; 1 << shared.len()];
+            let mut back: Vec<u32> = vec![0; 1 << shared.len()];
+            for (mask, &c) in cost.iter().enumerate() {
+                let proj = project(mask, &shared, true);
+                if c < best_prev[proj] {
+                    best_prev[proj] = c;
+                    back[proj] = mask as u32;
+                }
+            }
+
+            let (disagreement, total) = column_disagreement(&active, &col_calls[v], &frag_bit);
+            cost = disagreement
+                .iter()
+                .enumerate()
+                .map(|(mask, &d)| d.min(total - d) + best_prev[project(mask, &shared, false)])
+                .collect();
+
+            dp_columns.push(WmecColumn {
+                var_ix: v,
+                active: active.clone(),
+                shared: shared,
+                back: back,
+            });
+        }
+
+        // backtrace from the best bipartition of the last column
+        let mut mask: usize = 0;
+        for (m, &c) in cost.iter().enumerate() {
+            if c < cost[mask] {
+                mask = m;
+            }
+        }
+        for col in dp_columns.iter().rev() {
+            for (bit, &f) in col.active.iter().enumerate() {
+                frag_bit[f] = bit;
+            }
+            let (disagreement, total) =
+                column_disagreement(&col.active, &col_calls[col.var_ix], &frag_bit);
+            hap1[col.var_ix] = if disagreement[mask] <= total - disagreement[mask] {
+                '0' as u8
+            } else {
+                '1' as u8
+            };
+            phase_sets[col.var_ix] = columns[0] as i32;
+            mask = col.back[project(mask, &col.shared, false)] as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use variants_and_fragments::fragments_from_strings;
+
+    fn phase(rows: &[&str], phase_variant: &Vec<bool>, max_cov: usize) -> (String, Vec<i32>) {
+        let flist = fragments_from_strings(rows, 0.01);
+        let frefs: Vec<&Fragment> = flist.iter().collect();
+        let mut hap1: Vec<u8> = vec!['-' as u8; phase_variant.len()];
+        let mut phase_sets: Vec<i32> = vec![-1; phase_variant.len()];
+        wmec_phase(
+            &frefs,
+            phase_variant,
+            0.1,
+            max_cov,
+            &mut hap1,
+            &mut phase_sets,
+        );
+        (String::from_utf8(hap1).unwrap(), phase_sets)
+    }
+
+    #[test]
+    fn test_wmec_phase() {
+        // haplotypes 0101-0 and 1010-1, with an error in the 3rd read. Variant 4 is not phased,
+        // and variant 5 is only linked to the others by a read with a single call there.
+        let phase_variant = vec![true, true, true, true, false, true];
+        let rows = ["0101--", "1010--", "-111--", "-10---", "---1-0", "-----1"];
+        let (hap1, phase_sets) = phase(&rows, &phase_variant, 15);
+        assert!(hap1 == "0101-0" || hap1 == "1010-1");
+        assert_eq!(phase_sets, vec![0, 0, 0, 0, -1, 0]);
+
+        // the variants 0-1 and 2-3 are separate blocks
+        let (hap1, phase_sets) = phase(&["01--", "10--", "--00", "--11"], &vec![true; 4], 15);
+        assert!(&hap1[..2] == "01" || &hap1[..2] == "10");
+        assert!(&hap1[2..] == "00" || &hap1[2..] == "11");
+        assert_eq!(phase_sets, vec![0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn test_select_fragments() {
+        let phase_variant = vec![true, true, false, true];
+        let frags: Vec<WmecCalls> = vec![
+            vec![(0, 0, 1.0), (1, 0, 1.0)],
+            vec![(0, 0, 1.0), (1, 0, 1.0), (3, 0, 1.0)],
+            vec![(1, 0, 1.0), (3, 0, 1.0)],
+            vec![(2, 0, 1.0), (3, 0, 1.0)],
+        ];
+        // fragment 1 has the most calls and is selected first, then fragment 2 would make the
+        // coverage of variant 1 too deep. Variant 2 is not phased, so it doesn't count for
+        // fragment 3.
+        assert_eq!(
+            select_fragments(&frags, &phase_variant, 2),
+            vec![true, true, false, true]
+        );
+        assert_eq!(
+            select_fragments(&frags, &phase_variant, 3),
+            vec![true, true, true, true]
+        );
+        assert_eq!(
+            select_fragments(&frags, &phase_variant, 1),
+            vec![false, true, false, false]
+        );
+    }
+}
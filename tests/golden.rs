@@ -0,0 +1,138 @@
+//! End-to-end tests that run Longshot on the example dataset and compare the output with the
+//! golden files in ```tests/golden```.
+//!
+//! These tests take a few minutes, so they only run with the ```golden_tests``` feature:
+//! ```cargo test --features golden_tests --test golden```
+//!
+//! After a deliberate change to the output, regenerate the golden files by running the tests with
+//! ```LONGSHOT_UPDATE_GOLDEN=1``` set, and review the differences before committing them.
+#![cfg(feature = "golden_tests")]
+
+extern crate rust_htslib;
+
+use rust_htslib::bam;
+use rust_htslib::bam::Read;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+static BAM: &str = "example_data/pacbio_reads_30x.bam";
+static REF: &str = "example_data/genome.fa";
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name)
+}
+
+/// a unique path in the temporary directory for an output file of this test run
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("longshot_golden_{}_{}", std::process::id(), name))
+}
+
+/// runs Longshot on the example data with the given extra arguments, writing the VCF to
+/// ```out_vcf```
+fn run_longshot(args: &[&str], out_vcf: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_longshot"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(&["-F", "--bam", BAM, "--ref", REF, "--out"])
+        .arg(out_vcf)
+        .args(args)
+        .status()
+        .expect("failed to run longshot");
+    assert!(status.success(), "longshot exited with {}", status);
+}
+
+/// compares ```output``` with the golden file ```name```, or overwrites the golden file with it
+/// if ```LONGSHOT_UPDATE_GOLDEN``` is set
+fn check_golden(name: &str, output: &str) {
+    let path = golden_path(name);
+    if env::var_os("LONGSHOT_UPDATE_GOLDEN").is_some() {
+        fs::write(&path, output).expect("failed to write golden file");
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "golden file {} is missing; generate it with LONGSHOT_UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+
+    let golden_lines: Vec<&str> = golden.lines().collect();
+    let output_lines: Vec<&str> = output.lines().collect();
+    for (i, (g, o)) in golden_lines.iter().zip(output_lines.iter()).enumerate() {
+        assert_eq!(
+            g,
+            o,
+            "line {} of the output differs from golden file {}",
+            i + 1,
+            path.display()
+        );
+    }
+    assert_eq!(
+        golden_lines.len(),
+        output_lines.len(),
+        "the output has a different number of lines than golden file {}",
+        path.display()
+    );
+}
+
+fn check_vcf_golden(name: &str, args: &[&str]) {
+    let out_vcf = temp_path(name);
+    run_longshot(args, &out_vcf);
+    let output = fs::read_to_string(&out_vcf).expect("failed to read output VCF");
+    fs::remove_file(&out_vcf).ok();
+    check_golden(name, &output);
+}
+
+#[test]
+fn test_golden_default() {
+    check_vcf_golden("default.vcf", &["-r", "contig1"]);
+}
+
+#[test]
+fn test_golden_no_haps() {
+    check_vcf_golden("no_haps.vcf", &["-r", "contig2", "-n"]);
+}
+
+#[test]
+fn test_golden_local_assembly() {
+    check_vcf_golden(
+        "local_assembly.vcf",
+        &["-r", "contig3:1-100000", "--local_assembly", "--max_indel_len", "10"],
+    );
+}
+
+/// the haplotype-tagged BAM is compared by its tags (read name, flag, HP, PS), since the
+/// compressed BAM bytes and the @PG header line are not stable
+#[test]
+fn test_golden_haplotag_bam() {
+    let out_vcf = temp_path("haplotag.vcf");
+    let out_bam = temp_path("haplotag.bam");
+    run_longshot(
+        &["-r", "contig1", "-y", "30", "-O", out_bam.to_str().unwrap()],
+        &out_vcf,
+    );
+
+    let mut reader = bam::Reader::from_path(&out_bam).expect("failed to open output BAM");
+    let mut output = String::new();
+    for r in reader.records() {
+        let record = r.expect("failed to read output BAM record");
+        let tag = |name: &[u8]| match record.aux(name) {
+            Some(bam::record::Aux::Integer(x)) => x.to_string(),
+            _ => ".".to_string(),
+        };
+        output.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            String::from_utf8_lossy(record.qname()),
+            record.flags(),
+            tag(b"HP"),
+            tag(b"PS")
+        ));
+    }
+    fs::remove_file(&out_vcf).ok();
+    fs::remove_file(&out_bam).ok();
+    check_golden("haplotag.tsv", &output);
+}
@@ -0,0 +1,77 @@
+//! End-to-end test that simulates a small dataset with ```longshot simulate```, calls variants on
+//! it and compares the SNV calls with the planted variants.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// a unique directory in the temporary directory for the files of this test run
+fn temp_dir(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("longshot_simulation_{}_{}", std::process::id(), name))
+}
+
+fn run(args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_longshot"))
+        .args(args)
+        .status()
+        .expect("failed to run longshot");
+    assert!(status.success(), "longshot exited with {}", status);
+}
+
+/// the (position, REF, ALT) of the SNVs of a VCF with a non-reference genotype and a PASS filter
+fn snvs(vcf: &Path) -> HashSet<(usize, String, String)> {
+    let contents = fs::read_to_string(vcf).expect("failed to read VCF");
+    let mut snvs = HashSet::new();
+    for line in contents.lines().filter(|l| !l.starts_with('#')) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let gt = fields[9].split(':').next().unwrap();
+        if fields[3].len() != 1
+            || fields[4].len() != 1
+            || fields[6] != "PASS"
+            || !gt.contains('1')
+        {
+            continue;
+        }
+        snvs.insert((
+            fields[1].parse().unwrap(),
+            fields[3].to_string(),
+            fields[4].to_string(),
+        ));
+    }
+    snvs
+}
+
+#[test]
+fn test_simulated_snv_calls() {
+    let dir = temp_dir("snvs");
+    let dir_str = dir.to_str().unwrap();
+    run(&[
+        "simulate", "-F", "--out_dir", dir_str, "--ref_len", "50000", "--coverage", "25",
+        "--read_len", "5000", "--preset", "pacbio-hifi", "--seed", "11",
+    ]);
+    let calls = dir.join("calls.vcf");
+    run(&[
+        "-F",
+        "--bam",
+        dir.join("reads.bam").to_str().unwrap(),
+        "--ref",
+        dir.join("ref.fa").to_str().unwrap(),
+        "--out",
+        calls.to_str().unwrap(),
+        "--preset",
+        "pacbio-hifi",
+    ]);
+
+    let truth = snvs(&dir.join("truth.vcf"));
+    let called = snvs(&calls);
+    assert!(truth.len() > 20, "only {} SNVs were planted", truth.len());
+    let n_true_positives = called.intersection(&truth).count() as f64;
+    let recall = n_true_positives / truth.len() as f64;
+    let precision = n_true_positives / called.len() as f64;
+    assert!(recall >= 0.9, "SNV recall is {}", recall);
+    assert!(precision >= 0.9, "SNV precision is {}", precision);
+
+    fs::remove_dir_all(&dir).unwrap();
+}